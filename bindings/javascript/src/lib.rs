@@ -149,6 +149,14 @@ impl limbo_core::DatabaseStorage for DatabaseFile {
     fn sync(&self, _c: limbo_core::Completion) -> limbo_core::Result<()> {
         todo!()
     }
+
+    fn size(&self) -> limbo_core::Result<u64> {
+        self.file.size()
+    }
+
+    fn truncate(&self, len: usize, c: limbo_core::Completion) -> limbo_core::Result<()> {
+        self.file.truncate(len, c)
+    }
 }
 
 struct IO {}
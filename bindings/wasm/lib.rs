@@ -263,6 +263,12 @@ impl limbo_core::File for File {
     fn size(&self) -> Result<u64> {
         Ok(self.vfs.size(self.fd))
     }
+
+    fn truncate(&self, _len: usize, c: limbo_core::Completion) -> Result<()> {
+        // TODO
+        c.complete(0);
+        Ok(())
+    }
 }
 
 pub struct PlatformIO {
@@ -374,6 +380,14 @@ impl limbo_core::DatabaseStorage for DatabaseFile {
     fn sync(&self, _c: limbo_core::Completion) -> Result<()> {
         todo!()
     }
+
+    fn size(&self) -> Result<u64> {
+        self.file.size()
+    }
+
+    fn truncate(&self, len: usize, c: limbo_core::Completion) -> Result<()> {
+        self.file.truncate(len, c)
+    }
 }
 
 #[cfg(all(feature = "web", feature = "nodejs"))]
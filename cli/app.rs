@@ -809,8 +809,6 @@ impl<'a> Limbo<'a> {
                 anyhow::bail!("We have to throw here, even if we printed error");
             }
         }
-        // for now let's cache flush always
-        self.conn.cacheflush()?;
         Ok(())
     }
 
@@ -0,0 +1,91 @@
+use std::cmp::Ordering;
+
+use limbo_ext::CollationFunction;
+
+use crate::types::{OwnedValue, RefValue};
+
+/// A collating sequence, which determines how two TEXT values are compared.
+/// Values of any other type are always compared the same way, regardless of the collating sequence.
+/// https://www.sqlite.org/datatype3.html#collating_sequences
+#[derive(Debug, Clone, Copy, Default)]
+pub enum CollationSeq {
+    /// Compares string data using memcmp(), regardless of text encoding.
+    #[default]
+    Binary,
+    /// Like BINARY, except the 26 upper case characters of ASCII are folded to their lower case equivalents.
+    NoCase,
+    /// Like BINARY, except that trailing space characters are ignored.
+    Rtrim,
+    /// A collating sequence registered by an extension.
+    External(CollationFunction),
+}
+
+impl CollationSeq {
+    /// Look up one of the built-in collating sequences by name. Names are case-insensitive.
+    pub fn builtin(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("BINARY") {
+            Some(Self::Binary)
+        } else if name.eq_ignore_ascii_case("NOCASE") {
+            Some(Self::NoCase)
+        } else if name.eq_ignore_ascii_case("RTRIM") {
+            Some(Self::Rtrim)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Self::Binary)
+    }
+
+    pub fn compare_strings(&self, lhs: &[u8], rhs: &[u8]) -> Ordering {
+        match self {
+            Self::Binary => lhs.cmp(rhs),
+            Self::NoCase => {
+                let lhs = lhs.iter().map(u8::to_ascii_lowercase);
+                let rhs = rhs.iter().map(u8::to_ascii_lowercase);
+                lhs.cmp(rhs)
+            }
+            Self::Rtrim => trim_trailing_spaces(lhs).cmp(trim_trailing_spaces(rhs)),
+            Self::External(func) => {
+                let cmp = unsafe { func(lhs.as_ptr(), lhs.len(), rhs.as_ptr(), rhs.len()) };
+                cmp.cmp(&0)
+            }
+        }
+    }
+
+    pub fn compare_values(&self, lhs: &OwnedValue, rhs: &OwnedValue) -> Ordering {
+        match (self, lhs, rhs) {
+            (Self::Binary, _, _) => lhs.cmp(rhs),
+            (_, OwnedValue::Text(lhs), OwnedValue::Text(rhs)) => {
+                self.compare_strings(&lhs.value, &rhs.value)
+            }
+            _ => lhs.cmp(rhs),
+        }
+    }
+
+    pub fn compare_ref_values(&self, lhs: &RefValue, rhs: &RefValue) -> Ordering {
+        match (self, lhs, rhs) {
+            (Self::Binary, _, _) => lhs.cmp(rhs),
+            (_, RefValue::Text(lhs), RefValue::Text(rhs)) => {
+                self.compare_strings(lhs.value.to_slice(), rhs.value.to_slice())
+            }
+            _ => lhs.cmp(rhs),
+        }
+    }
+
+    /// The name displayed for this collating sequence in EXPLAIN output.
+    pub fn explain_name(&self) -> &'static str {
+        match self {
+            Self::Binary => "BINARY",
+            Self::NoCase => "NOCASE",
+            Self::Rtrim => "RTRIM",
+            Self::External(_) => "EXTERNAL",
+        }
+    }
+}
+
+fn trim_trailing_spaces(s: &[u8]) -> &[u8] {
+    let len = s.iter().rposition(|b| *b != b' ').map_or(0, |i| i + 1);
+    &s[..len]
+}
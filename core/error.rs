@@ -81,4 +81,10 @@ impl From<limbo_ext::ResultCode> for LimboError {
 }
 
 pub const SQLITE_CONSTRAINT: usize = 19;
+pub const SQLITE_CONSTRAINT_CHECK: usize = SQLITE_CONSTRAINT | (1 << 8);
+pub const SQLITE_CONSTRAINT_NOTNULL: usize = SQLITE_CONSTRAINT | (5 << 8);
 pub const SQLITE_CONSTRAINT_PRIMARYKEY: usize = SQLITE_CONSTRAINT | (6 << 8);
+pub const SQLITE_CONSTRAINT_TRIGGER: usize = SQLITE_CONSTRAINT | (7 << 8);
+pub const SQLITE_CONSTRAINT_UNIQUE: usize = SQLITE_CONSTRAINT | (8 << 8);
+/// Returned by `RAISE(IGNORE)`: abandons the trigger program and the row that fired it.
+pub const SQLITE_IGNORE: usize = 2;
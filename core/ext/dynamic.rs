@@ -1,5 +1,8 @@
 use crate::{
-    ext::{register_aggregate_function, register_scalar_function, register_vtab_module},
+    ext::{
+        register_aggregate_function, register_collation, register_scalar_function,
+        register_vtab_module,
+    },
     Connection, LimboError,
 };
 use libloading::{Library, Symbol};
@@ -103,6 +106,7 @@ pub fn add_builtin_vfs_extensions(
             register_scalar_function,
             register_aggregate_function,
             register_vtab_module,
+            register_collation,
             vfs_interface: VfsInterface {
                 register_vfs,
                 builtin_vfs: vfslist.as_mut_ptr(),
@@ -2,11 +2,14 @@
 mod dynamic;
 #[cfg(all(target_os = "linux", feature = "io_uring"))]
 use crate::UringIO;
-use crate::{function::ExternalFunc, Connection, Database, LimboError, IO};
+use crate::{
+    collation::CollationSeq, function::ExternalFunc, Connection, Database, LimboError, IO,
+};
 #[cfg(feature = "fs")]
 pub use dynamic::{add_builtin_vfs_extensions, add_vfs_module, list_vfs_modules, VfsMod};
 use limbo_ext::{
-    ExtensionApi, InitAggFunction, ResultCode, ScalarFunction, VTabKind, VTabModuleImpl,
+    CollationFunction, ExtensionApi, InitAggFunction, ResultCode, ScalarFunction, VTabKind,
+    VTabModuleImpl,
 };
 pub use limbo_ext::{FinalizeFunction, StepFunction, Value as ExtValue, ValueType as ExtValueType};
 use std::{
@@ -81,6 +84,23 @@ pub(crate) unsafe extern "C" fn register_vtab_module(
     conn.register_vtab_module_impl(&name_str, module, kind)
 }
 
+pub(crate) unsafe extern "C" fn register_collation(
+    ctx: *mut c_void,
+    name: *const c_char,
+    func: CollationFunction,
+) -> ResultCode {
+    let c_str = unsafe { CStr::from_ptr(name) };
+    let name_str = match c_str.to_str() {
+        Ok(s) => s.to_string(),
+        Err(_) => return ResultCode::InvalidArgs,
+    };
+    if ctx.is_null() {
+        return ResultCode::Error;
+    }
+    let conn = unsafe { &*(ctx as *const Connection) };
+    conn.register_collation_impl(&name_str, func)
+}
+
 impl Database {
     #[cfg(feature = "fs")]
     #[allow(clippy::arc_with_non_send_sync, dead_code)]
@@ -152,12 +172,25 @@ impl Connection {
         ResultCode::OK
     }
 
+    fn register_collation_impl(&self, name: &str, func: CollationFunction) -> ResultCode {
+        // The built-in collating sequences cannot be overridden.
+        if CollationSeq::builtin(name).is_some() {
+            return ResultCode::InvalidArgs;
+        }
+        self.syms
+            .borrow_mut()
+            .collations
+            .insert(name.to_lowercase(), func);
+        ResultCode::OK
+    }
+
     pub fn build_limbo_ext(&self) -> ExtensionApi {
         ExtensionApi {
             ctx: self as *const _ as *mut c_void,
             register_scalar_function,
             register_aggregate_function,
             register_vtab_module,
+            register_collation,
             #[cfg(feature = "fs")]
             vfs_interface: limbo_ext::VfsInterface {
                 register_vfs: dynamic::register_vfs,
@@ -158,6 +158,32 @@ impl Display for VectorFunc {
     }
 }
 
+/// Internal functions that rewrite the SQL of the schema entries of a table for ALTER TABLE.
+/// The first argument of each is the SQL to rewrite. They cannot be called from SQL.
+#[derive(Debug, Clone)]
+pub enum AlterTableFunc {
+    /// `(sql, old table name, new table name)`
+    RenameTable,
+    /// `(sql, table name, old column name, new column name)`
+    RenameColumn,
+    /// `(sql, column definition)`
+    AddColumn,
+    /// `(sql, column name)`
+    DropColumn,
+}
+
+impl Display for AlterTableFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let str = match self {
+            Self::RenameTable => "sqlite_rename_table",
+            Self::RenameColumn => "sqlite_rename_column",
+            Self::AddColumn => "sqlite_add_column",
+            Self::DropColumn => "sqlite_drop_column",
+        };
+        write!(f, "{}", str)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum AggFunc {
     Avg,
@@ -469,6 +495,67 @@ impl Display for MathFunc {
     }
 }
 
+/// Built-in window functions, i.e. functions that can only be called with an OVER clause.
+/// Aggregate functions can be called with an OVER clause too, but they are not listed here.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowFunc {
+    RowNumber,
+    Rank,
+    DenseRank,
+    PercentRank,
+    CumeDist,
+    Ntile,
+    Lag,
+    Lead,
+    FirstValue,
+    LastValue,
+    NthValue,
+}
+
+impl Display for WindowFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let str = match self {
+            Self::RowNumber => "row_number",
+            Self::Rank => "rank",
+            Self::DenseRank => "dense_rank",
+            Self::PercentRank => "percent_rank",
+            Self::CumeDist => "cume_dist",
+            Self::Ntile => "ntile",
+            Self::Lag => "lag",
+            Self::Lead => "lead",
+            Self::FirstValue => "first_value",
+            Self::LastValue => "last_value",
+            Self::NthValue => "nth_value",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl WindowFunc {
+    /// Resolves a built-in window function by name.
+    /// Returns `Ok(None)` if `name` is not a built-in window function.
+    pub fn resolve_function(name: &str, arg_count: usize) -> Result<Option<Self>, LimboError> {
+        let (func, min_args, max_args) = match name {
+            "row_number" => (Self::RowNumber, 0, 0),
+            "rank" => (Self::Rank, 0, 0),
+            "dense_rank" => (Self::DenseRank, 0, 0),
+            "percent_rank" => (Self::PercentRank, 0, 0),
+            "cume_dist" => (Self::CumeDist, 0, 0),
+            "ntile" => (Self::Ntile, 1, 1),
+            "lag" => (Self::Lag, 1, 3),
+            "lead" => (Self::Lead, 1, 3),
+            "first_value" => (Self::FirstValue, 1, 1),
+            "last_value" => (Self::LastValue, 1, 1),
+            "nth_value" => (Self::NthValue, 2, 2),
+            _ => return Ok(None),
+        };
+        if arg_count < min_args || arg_count > max_args {
+            crate::bail_parse_error!("wrong number of arguments to function {}()", name)
+        }
+        Ok(Some(func))
+    }
+}
+
 #[derive(Debug)]
 pub enum Func {
     Agg(AggFunc),
@@ -478,6 +565,7 @@ pub enum Func {
     #[cfg(feature = "json")]
     Json(JsonFunc),
     External(Rc<ExternalFunc>),
+    AlterTable(AlterTableFunc),
 }
 
 impl Display for Func {
@@ -490,6 +578,7 @@ impl Display for Func {
             #[cfg(feature = "json")]
             Self::Json(json_func) => write!(f, "{}", json_func),
             Self::External(generic_func) => write!(f, "{}", generic_func),
+            Self::AlterTable(alter_func) => write!(f, "{}", alter_func),
         }
     }
 }
@@ -501,6 +590,23 @@ pub struct FuncCtx {
 }
 
 impl Func {
+    /// Whether the function always returns the same result for the same arguments. Like in
+    /// SQLite, only deterministic functions are allowed in the expressions of an index.
+    pub fn is_deterministic(&self) -> bool {
+        match self {
+            Self::Scalar(
+                ScalarFunc::Random
+                | ScalarFunc::RandomBlob
+                | ScalarFunc::Changes
+                | ScalarFunc::TotalChanges
+                | ScalarFunc::LastInsertRowid,
+            ) => false,
+            #[cfg(feature = "fs")]
+            Self::Scalar(ScalarFunc::LoadExtension) => false,
+            _ => true,
+        }
+    }
+
     pub fn resolve_function(name: &str, arg_count: usize) -> Result<Self, LimboError> {
         match name {
             "avg" => {
@@ -315,6 +315,14 @@ impl File for UringFile {
     fn size(&self) -> Result<u64> {
         Ok(self.file.metadata()?.len())
     }
+
+    fn truncate(&self, len: usize, c: Completion) -> Result<()> {
+        // Older kernels have no io_uring ftruncate, so this is done synchronously.
+        self.file.set_len(len as u64)?;
+        trace!("truncate({})", len);
+        c.complete(0);
+        Ok(())
+    }
 }
 
 impl Drop for UringFile {
@@ -165,6 +165,19 @@ impl File for MemoryFile {
     fn size(&self) -> Result<u64> {
         Ok(self.size.get() as u64)
     }
+
+    fn truncate(&self, len: usize, c: Completion) -> Result<()> {
+        if len < self.size.get() {
+            let pages = unsafe { &mut *self.pages.get() };
+            pages.retain(|page_no, _| page_no * PAGE_SIZE < len);
+            if let Some(page) = pages.get_mut(&(len / PAGE_SIZE)) {
+                page[len % PAGE_SIZE..].fill(0);
+            }
+        }
+        self.size.set(len);
+        c.complete(0);
+        Ok(())
+    }
 }
 
 impl Drop for MemoryFile {
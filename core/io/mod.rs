@@ -17,6 +17,8 @@ pub trait File: Send + Sync {
     fn pwrite(&self, pos: usize, buffer: Arc<RefCell<Buffer>>, c: Completion) -> Result<()>;
     fn sync(&self, c: Completion) -> Result<()>;
     fn size(&self) -> Result<u64>;
+    /// Shrinks (or extends) the file to `len` bytes.
+    fn truncate(&self, len: usize, c: Completion) -> Result<()>;
 }
 
 #[derive(Copy, Clone)]
@@ -3,6 +3,7 @@ use crate::io::common;
 use crate::Result;
 
 use super::{Completion, File, MemoryIO, OpenFlags, IO};
+use crate::io::clock::{Clock, Instant};
 use polling::{Event, Events, Poller};
 use rustix::{
     fd::{AsFd, AsRawFd},
@@ -18,7 +19,6 @@ use std::{
     sync::Arc,
 };
 use tracing::{debug, trace};
-use crate::io::clock::{Clock, Instant};
 
 struct OwnedCallbacks(UnsafeCell<Callbacks>);
 // We assume we locking on IO level is done by user.
@@ -258,7 +258,7 @@ impl IO for UnixIO {
         getrandom::getrandom(&mut buf).unwrap();
         i64::from_ne_bytes(buf)
     }
-    
+
     fn get_memory_io(&self) -> Arc<MemoryIO> {
         Arc::new(MemoryIO::new())
     }
@@ -401,6 +401,14 @@ impl File for UnixFile<'_> {
         let file = self.file.borrow();
         Ok(file.metadata()?.len())
     }
+
+    fn truncate(&self, len: usize, c: Completion) -> Result<()> {
+        let file = self.file.borrow();
+        fs::ftruncate(file.as_fd(), len as u64)?;
+        trace!("ftruncate({})", len);
+        c.complete(0);
+        Ok(())
+    }
 }
 
 impl Drop for UnixFile<'_> {
@@ -1,3 +1,4 @@
+mod collation;
 mod error;
 mod ext;
 mod fast_lock;
@@ -17,6 +18,7 @@ mod translate;
 pub mod types;
 #[allow(dead_code)]
 mod util;
+mod vacuum;
 mod vdbe;
 mod vector;
 
@@ -44,14 +46,13 @@ pub use io::{
 use limbo_ext::{ConstraintInfo, IndexInfo, OrderByInfo, ResultCode, VTabKind, VTabModuleImpl};
 use limbo_sqlite3_parser::{ast, ast::Cmd, lexer::sql::Parser};
 use parking_lot::RwLock;
-use schema::{Column, Schema};
+use schema::{AttachedSchema, Column, Schema};
 use std::{
     borrow::Cow,
     cell::{Cell, RefCell, UnsafeCell},
     collections::HashMap,
     io::Write,
     num::NonZero,
-    ops::Deref,
     rc::Rc,
     sync::{Arc, OnceLock},
 };
@@ -73,7 +74,10 @@ use storage::{
 use translate::select::prepare_select_plan;
 pub use types::OwnedValue;
 pub use types::RefValue;
-use util::{columns_from_create_table_body, parse_schema_rows};
+use util::{
+    columns_from_create_table_body, normalize_ident, parse_schema_rows, parse_stat1_rows,
+    STAT1_TABLE,
+};
 use vdbe::{builder::QueryMode, VTabOpaqueCursor};
 pub type Result<T, E = LimboError> = std::result::Result<T, E>;
 pub static DATABASE_VERSION: OnceLock<String> = OnceLock::new();
@@ -85,11 +89,29 @@ enum TransactionState {
     None,
 }
 
+/// A database attached to a connection with ATTACH, or the temp database of a connection.
+struct AttachedDatabase {
+    name: String,
+    db: Arc<Database>,
+    pager: Rc<Pager>,
+}
+
+/// A savepoint opened by SAVEPOINT.
+struct Savepoint {
+    name: String,
+    /// Whether opening the savepoint began the transaction, which releasing it then commits.
+    begins_tx: bool,
+    /// The number of deferred foreign key violations when the savepoint was opened.
+    deferred_fk_violations: i64,
+}
+
 pub(crate) type MvStore = crate::mvcc::MvStore<crate::mvcc::LocalClock>;
 
 pub(crate) type MvCursor = crate::mvcc::cursor::ScanCursor<crate::mvcc::LocalClock>;
 
 pub struct Database {
+    /// The absolute path of the database file, empty for an in-memory database.
+    path: String,
     mv_store: Option<Rc<MvStore>>,
     schema: Arc<RwLock<Schema>>,
     // TODO: make header work without lock
@@ -119,15 +141,32 @@ impl Database {
         io.run_once()?;
         let page_size = db_header.lock().page_size;
         let wal_shared = WalFileShared::open_shared(&io, wal_path.as_str(), page_size)?;
-        Self::open(io, db_file, wal_shared, enable_mvcc)
+        let path = match path {
+            ":memory:" => String::new(),
+            path => std::path::absolute(path).map_or_else(
+                |_| path.to_string(),
+                |path| path.to_string_lossy().into_owned(),
+            ),
+        };
+        Self::open_with_path(io, &path, db_file, wal_shared, enable_mvcc)
     }
 
-    #[allow(clippy::arc_with_non_send_sync)]
     pub fn open(
         io: Arc<dyn IO>,
         db_file: Arc<dyn DatabaseStorage>,
         shared_wal: Arc<UnsafeCell<WalFileShared>>,
         enable_mvcc: bool,
+    ) -> Result<Arc<Database>> {
+        Self::open_with_path(io, "", db_file, shared_wal, enable_mvcc)
+    }
+
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn open_with_path(
+        io: Arc<dyn IO>,
+        path: &str,
+        db_file: Arc<dyn DatabaseStorage>,
+        shared_wal: Arc<UnsafeCell<WalFileShared>>,
+        enable_mvcc: bool,
     ) -> Result<Arc<Database>> {
         let db_header = Pager::begin_open(db_file.clone())?;
         io.run_once()?;
@@ -148,6 +187,7 @@ impl Database {
         let header = db_header;
         let schema = Arc::new(RwLock::new(Schema::new()));
         let db = Database {
+            path: path.to_string(),
             mv_store,
             schema: schema.clone(),
             header: header.clone(),
@@ -173,27 +213,14 @@ impl Database {
                 // a warning to the user to load the module
                 eprintln!("Warning: {}", e);
             }
+            drop(schema);
+            conn.load_analysis()?;
         }
         Ok(db)
     }
 
     pub fn connect(self: &Arc<Database>) -> Result<Rc<Connection>> {
-        let buffer_pool = Rc::new(BufferPool::new(self.page_size as usize));
-
-        let wal = Rc::new(RefCell::new(WalFile::new(
-            self.io.clone(),
-            self.page_size as usize,
-            self.shared_wal.clone(),
-            buffer_pool.clone(),
-        )));
-        let pager = Rc::new(Pager::finish_open(
-            self.header.clone(),
-            self.db_file.clone(),
-            Some(wal),
-            self.io.clone(),
-            self.shared_page_cache.clone(),
-            buffer_pool,
-        )?);
+        let pager = self.new_pager()?;
         let conn = Rc::new(Connection {
             _db: self.clone(),
             pager: pager.clone(),
@@ -203,6 +230,11 @@ impl Database {
             auto_commit: Cell::new(true),
             mv_transactions: RefCell::new(Vec::new()),
             transaction_state: Cell::new(TransactionState::None),
+            schema_changed: Cell::new(false),
+            savepoints: RefCell::new(Vec::new()),
+            deferred_fk_violations: Cell::new(0),
+            attached: RefCell::new(Vec::new()),
+            temp: RefCell::new(None),
             last_change: Cell::new(0),
             syms: RefCell::new(SymbolTable::new()),
             total_changes: Cell::new(0),
@@ -213,6 +245,26 @@ impl Database {
         Ok(conn)
     }
 
+    fn new_pager(&self) -> Result<Rc<Pager>> {
+        let buffer_pool = Rc::new(BufferPool::new(self.page_size as usize));
+
+        let wal = Rc::new(RefCell::new(WalFile::new(
+            self.io.clone(),
+            self.page_size as usize,
+            self.shared_wal.clone(),
+            buffer_pool.clone(),
+        )));
+        let pager = Rc::new(Pager::finish_open(
+            self.header.clone(),
+            self.db_file.clone(),
+            Some(wal),
+            self.io.clone(),
+            self.shared_page_cache.clone(),
+            buffer_pool,
+        )?);
+        Ok(pager)
+    }
+
     /// Open a new database file with a specified VFS without an existing database
     /// connection and symbol table to register extensions.
     #[cfg(feature = "fs")]
@@ -241,52 +293,73 @@ impl Database {
 
 pub fn maybe_init_database_file(file: &Arc<dyn File>, io: &Arc<dyn IO>) -> Result<()> {
     if file.size()? == 0 {
-        // init db
-        let db_header = DatabaseHeader::default();
-        let page1 = allocate_page(
-            1,
-            &Rc::new(BufferPool::new(db_header.page_size as usize)),
+        init_database_file(file, io, &DatabaseHeader::default())?;
+    }
+    Ok(())
+}
+
+/// Writes the first page of an empty database with the header `db_header` to `file`.
+fn init_database_file(
+    file: &Arc<dyn File>,
+    io: &Arc<dyn IO>,
+    db_header: &DatabaseHeader,
+) -> Result<()> {
+    let page1 = allocate_page(
+        1,
+        &Rc::new(BufferPool::new(db_header.page_size as usize)),
+        DATABASE_HEADER_SIZE,
+    );
+    {
+        // Create the sqlite_schema table, for this we just need to create the btree page
+        // for the first page of the database which is basically like any other btree page
+        // but with a 100 byte offset, so we just init the page so that sqlite understands
+        // this is a correct page.
+        btree_init_page(
+            &page1,
+            storage::sqlite3_ondisk::PageType::TableLeaf,
             DATABASE_HEADER_SIZE,
+            db_header.page_size - db_header.reserved_space as u16,
         );
+
+        let contents = page1.get().contents.as_mut().unwrap();
+        contents.write_database_header(db_header);
+        // write the first page to disk synchronously
+        let flag_complete = Rc::new(RefCell::new(false));
         {
-            // Create the sqlite_schema table, for this we just need to create the btree page
-            // for the first page of the database which is basically like any other btree page
-            // but with a 100 byte offset, so we just init the page so that sqlite understands
-            // this is a correct page.
-            btree_init_page(
-                &page1,
-                storage::sqlite3_ondisk::PageType::TableLeaf,
-                DATABASE_HEADER_SIZE,
-                db_header.page_size - db_header.reserved_space as u16,
-            );
-
-            let contents = page1.get().contents.as_mut().unwrap();
-            contents.write_database_header(&db_header);
-            // write the first page to disk synchronously
-            let flag_complete = Rc::new(RefCell::new(false));
-            {
-                let flag_complete = flag_complete.clone();
-                let completion = Completion::Write(WriteCompletion::new(Box::new(move |_| {
-                    *flag_complete.borrow_mut() = true;
-                })));
-                file.pwrite(0, contents.buffer.clone(), completion)?;
+            let flag_complete = flag_complete.clone();
+            let completion = Completion::Write(WriteCompletion::new(Box::new(move |_| {
+                *flag_complete.borrow_mut() = true;
+            })));
+            file.pwrite(0, contents.buffer.clone(), completion)?;
+        }
+        let mut limit = 100;
+        loop {
+            io.run_once()?;
+            if *flag_complete.borrow() {
+                break;
             }
-            let mut limit = 100;
-            loop {
-                io.run_once()?;
-                if *flag_complete.borrow() {
-                    break;
-                }
-                limit -= 1;
-                if limit == 0 {
-                    panic!("Database file couldn't be initialized, io loop run for {} iterations and write didn't finish", limit);
-                }
+            limit -= 1;
+            if limit == 0 {
+                panic!("Database file couldn't be initialized, io loop run for {} iterations and write didn't finish", limit);
             }
         }
-    };
+    }
     Ok(())
 }
 
+/// Checkpoints the WAL of `pager` into the database file.
+fn checkpoint_pager(pager: &Pager) -> Result<()> {
+    loop {
+        // TODO: make this async?
+        match pager.checkpoint()? {
+            CheckpointStatus::Done(_) => return Ok(()),
+            CheckpointStatus::IO => {
+                pager.io.run_once()?;
+            }
+        };
+    }
+}
+
 pub struct Connection {
     _db: Arc<Database>,
     pager: Rc<Pager>,
@@ -295,6 +368,18 @@ pub struct Connection {
     auto_commit: Cell<bool>,
     mv_transactions: RefCell<Vec<crate::mvcc::database::TxID>>,
     transaction_state: Cell<TransactionState>,
+    /// Whether the transaction in progress changed the schema, which then has to be loaded again
+    /// if the changes are rolled back.
+    schema_changed: Cell<bool>,
+    /// The open savepoints, oldest first.
+    savepoints: RefCell<Vec<Savepoint>>,
+    /// The number of violations of DEFERRABLE INITIALLY DEFERRED foreign keys in the
+    /// transaction, which cannot commit until they are fixed.
+    deferred_fk_violations: Cell<i64>,
+    /// The databases attached with ATTACH, in the order they were attached.
+    attached: RefCell<Vec<AttachedDatabase>>,
+    /// The temp database, which is opened the first time a statement uses it.
+    temp: RefCell<Option<AttachedDatabase>>,
     last_insert_rowid: Cell<u64>,
     last_change: Cell<i64>,
     total_changes: Cell<i64>,
@@ -307,22 +392,23 @@ impl Connection {
         tracing::trace!("Preparing: {}", sql);
         let mut parser = Parser::new(sql.as_bytes());
         let cmd = parser.next()?;
+        let input = &sql[..parser.offset()];
         let syms = self.syms.borrow();
         if let Some(cmd) = cmd {
             match cmd {
                 Cmd::Stmt(stmt) => {
-                    let program = Rc::new(translate::translate(
-                        self.schema
-                            .try_read()
-                            .ok_or(LimboError::SchemaLocked)?
-                            .deref(),
-                        stmt,
-                        self.header.clone(),
-                        self.pager.clone(),
-                        Rc::downgrade(self),
-                        &syms,
-                        QueryMode::Normal,
-                    )?);
+                    let program = Rc::new(self.with_schema(|schema| {
+                        translate::translate(
+                            schema,
+                            stmt,
+                            input,
+                            self.header.clone(),
+                            self.pager.clone(),
+                            Rc::downgrade(self),
+                            &syms,
+                            QueryMode::Normal,
+                        )
+                    })?);
                     Ok(Statement::new(
                         program,
                         self._db.mv_store.clone(),
@@ -343,27 +429,34 @@ impl Connection {
         let mut parser = Parser::new(sql.as_bytes());
         let cmd = parser.next()?;
         match cmd {
-            Some(cmd) => self.run_cmd(cmd),
+            Some(cmd) => self.run_cmd(cmd, &sql[..parser.offset()]),
             None => Ok(None),
         }
     }
 
-    pub(crate) fn run_cmd(self: &Rc<Connection>, cmd: Cmd) -> Result<Option<Statement>> {
+    /// Runs `cmd`, which was parsed from `input`.
+    pub(crate) fn run_cmd(
+        self: &Rc<Connection>,
+        cmd: Cmd,
+        input: &str,
+    ) -> Result<Option<Statement>> {
         let syms = self.syms.borrow();
         match cmd {
             Cmd::Stmt(ref stmt) | Cmd::Explain(ref stmt) => {
-                let program = translate::translate(
-                    self.schema
-                        .try_read()
-                        .ok_or(LimboError::SchemaLocked)?
-                        .deref(),
-                    stmt.clone(),
-                    self.header.clone(),
-                    self.pager.clone(),
-                    Rc::downgrade(self),
-                    &syms,
-                    cmd.into(),
-                )?;
+                let stmt = stmt.clone();
+                let query_mode = cmd.into();
+                let program = self.with_schema(|schema| {
+                    translate::translate(
+                        schema,
+                        stmt,
+                        input,
+                        self.header.clone(),
+                        self.pager.clone(),
+                        Rc::downgrade(self),
+                        &syms,
+                        query_mode,
+                    )
+                })?;
                 let stmt = Statement::new(
                     program.into(),
                     self._db.mv_store.clone(),
@@ -374,22 +467,11 @@ impl Connection {
             Cmd::ExplainQueryPlan(stmt) => {
                 match stmt {
                     ast::Stmt::Select(select) => {
-                        let mut plan = prepare_select_plan(
-                            self.schema
-                                .try_read()
-                                .ok_or(LimboError::SchemaLocked)?
-                                .deref(),
-                            *select,
-                            &syms,
-                            None,
-                        )?;
-                        optimize_plan(
-                            &mut plan,
-                            self.schema
-                                .try_read()
-                                .ok_or(LimboError::SchemaLocked)?
-                                .deref(),
-                        )?;
+                        let plan = self.with_schema(|schema| {
+                            let mut plan = prepare_select_plan(schema, *select, &syms, None)?;
+                            optimize_plan(&mut plan, schema)?;
+                            Ok(plan)
+                        })?;
                         let _ = std::io::stdout().write_all(plan.to_string().as_bytes());
                     }
                     _ => todo!(),
@@ -409,38 +491,39 @@ impl Connection {
         let sql = sql.as_ref();
         let mut parser = Parser::new(sql.as_bytes());
         let cmd = parser.next()?;
+        let input = &sql[..parser.offset()];
         let syms = self.syms.borrow();
         if let Some(cmd) = cmd {
             match cmd {
                 Cmd::Explain(stmt) => {
-                    let program = translate::translate(
-                        self.schema
-                            .try_read()
-                            .ok_or(LimboError::SchemaLocked)?
-                            .deref(),
-                        stmt,
-                        self.header.clone(),
-                        self.pager.clone(),
-                        Rc::downgrade(self),
-                        &syms,
-                        QueryMode::Explain,
-                    )?;
+                    let program = self.with_schema(|schema| {
+                        translate::translate(
+                            schema,
+                            stmt,
+                            input,
+                            self.header.clone(),
+                            self.pager.clone(),
+                            Rc::downgrade(self),
+                            &syms,
+                            QueryMode::Explain,
+                        )
+                    })?;
                     let _ = std::io::stdout().write_all(program.explain().as_bytes());
                 }
                 Cmd::ExplainQueryPlan(_stmt) => todo!(),
                 Cmd::Stmt(stmt) => {
-                    let program = translate::translate(
-                        self.schema
-                            .try_read()
-                            .ok_or(LimboError::SchemaLocked)?
-                            .deref(),
-                        stmt,
-                        self.header.clone(),
-                        self.pager.clone(),
-                        Rc::downgrade(self),
-                        &syms,
-                        QueryMode::Normal,
-                    )?;
+                    let program = self.with_schema(|schema| {
+                        translate::translate(
+                            schema,
+                            stmt,
+                            input,
+                            self.header.clone(),
+                            self.pager.clone(),
+                            Rc::downgrade(self),
+                            &syms,
+                            QueryMode::Normal,
+                        )
+                    })?;
 
                     let mut state =
                         vdbe::ProgramState::new(program.max_registers, program.cursor_ref.len());
@@ -477,17 +560,10 @@ impl Connection {
 
     /// Close a connection and checkpoint.
     pub fn close(&self) -> Result<()> {
-        loop {
-            // TODO: make this async?
-            match self.pager.checkpoint()? {
-                CheckpointStatus::Done(_) => {
-                    return Ok(());
-                }
-                CheckpointStatus::IO => {
-                    self.pager.io.run_once()?;
-                }
-            };
+        for pager in std::iter::once(self.pager.clone()).chain(self.attached_pagers()) {
+            checkpoint_pager(&pager)?;
         }
+        Ok(())
     }
 
     pub fn last_insert_rowid(&self) -> u64 {
@@ -534,6 +610,361 @@ impl Connection {
         self.auto_commit.get()
     }
 
+    /// Rolls back the transaction in progress, if any, and goes back to autocommit mode.
+    pub(crate) fn rollback(self: &Rc<Connection>) -> Result<()> {
+        self.auto_commit.set(true);
+        self.clear_savepoints();
+        self.deferred_fk_violations.set(0);
+        if let Some(mv_store) = &self._db.mv_store {
+            for tx_id in self.mv_transactions.borrow_mut().drain(..) {
+                mv_store.rollback_tx(tx_id);
+            }
+            return Ok(());
+        }
+        let state = self.transaction_state.replace(TransactionState::None);
+        for pager in std::iter::once(self.pager.clone()).chain(self.attached_pagers()) {
+            match state {
+                TransactionState::Write => pager.rollback_tx()?,
+                TransactionState::Read => pager.end_read_tx()?,
+                TransactionState::None => {}
+            }
+        }
+        if self.schema_changed.replace(false) {
+            self.reload_schema()?;
+        }
+        Ok(())
+    }
+
+    /// Rolls back the changes of the running statement, keeping the rest of the transaction.
+    pub(crate) fn rollback_statement(self: &Rc<Connection>) -> Result<()> {
+        self.pager.rollback_statement();
+        for pager in self.attached_pagers() {
+            pager.rollback_statement();
+        }
+        if self.schema_changed.get() {
+            self.reload_schema()?;
+        }
+        Ok(())
+    }
+
+    /// Opens the savepoint `name`, beginning a transaction if none is running.
+    pub(crate) fn open_savepoint(&self, name: &str) {
+        let begins_tx = self.auto_commit.replace(false);
+        self.pager.open_savepoint();
+        for pager in self.attached_pagers() {
+            pager.open_savepoint();
+        }
+        self.savepoints.borrow_mut().push(Savepoint {
+            name: name.to_string(),
+            begins_tx,
+            deferred_fk_violations: self.deferred_fk_violations.get(),
+        });
+    }
+
+    /// Releases the savepoint `name` and the ones opened after it. If the savepoint began the
+    /// transaction, the transaction is committed when the statement halts.
+    pub(crate) fn release_savepoint(&self, name: &str) -> Result<()> {
+        let index = self.find_savepoint(name)?;
+        if self.savepoints.borrow()[index].begins_tx {
+            self.check_deferred_foreign_keys()?;
+            self.auto_commit.set(true);
+        }
+        self.savepoints.borrow_mut().truncate(index);
+        self.pager.release_savepoint(index);
+        for pager in self.attached_pagers() {
+            pager.release_savepoint(index);
+        }
+        Ok(())
+    }
+
+    /// Rolls back the changes made since the savepoint `name` was opened, without ending the
+    /// transaction. The savepoint stays open, and the ones opened after it are released.
+    pub(crate) fn rollback_to_savepoint(self: &Rc<Connection>, name: &str) -> Result<()> {
+        let index = self.find_savepoint(name)?;
+        let mut savepoints = self.savepoints.borrow_mut();
+        savepoints.truncate(index + 1);
+        self.deferred_fk_violations
+            .set(savepoints[index].deferred_fk_violations);
+        drop(savepoints);
+        self.pager.rollback_to_savepoint(index);
+        for pager in self.attached_pagers() {
+            pager.rollback_to_savepoint(index);
+        }
+        if self.schema_changed.get() {
+            self.reload_schema()?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the open savepoints once the transaction ends.
+    pub(crate) fn clear_savepoints(&self) {
+        self.savepoints.borrow_mut().clear();
+        self.pager.release_savepoint(0);
+        for pager in self.attached_pagers() {
+            pager.release_savepoint(0);
+        }
+    }
+
+    /// Fails if the transaction has violations of deferred foreign keys, and so cannot commit.
+    pub(crate) fn check_deferred_foreign_keys(&self) -> Result<()> {
+        if self.deferred_fk_violations.get() > 0 {
+            return Err(LimboError::Constraint(
+                "FOREIGN KEY constraint failed (19)".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Finds the most recent savepoint named `name`.
+    fn find_savepoint(&self, name: &str) -> Result<usize> {
+        self.savepoints
+            .borrow()
+            .iter()
+            .rposition(|savepoint| savepoint.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| LimboError::TxError(format!("no such savepoint: {}", name)))
+    }
+
+    /// Runs `f` with the schema to translate a statement with, which includes the schemas of the
+    /// temp database and of the attached databases.
+    fn with_schema<T>(&self, f: impl FnOnce(&Schema) -> Result<T>) -> Result<T> {
+        let schema = self.schema.try_read().ok_or(LimboError::SchemaLocked)?;
+        // The databases are not borrowed while `f` runs, which may open the temp database
+        let temp = self
+            .temp
+            .borrow()
+            .as_ref()
+            .map(|temp| temp.db.schema.clone());
+        let attached = self
+            .attached
+            .borrow()
+            .iter()
+            .map(|db| AttachedSchema {
+                name: db.name.clone(),
+                schema: db.db.schema.clone(),
+            })
+            .collect::<Vec<_>>();
+        if attached.is_empty() && temp.is_none() {
+            return f(&schema);
+        }
+        let mut schema = schema.clone();
+        schema.temp = temp;
+        schema.attached = attached;
+        f(&schema)
+    }
+
+    /// Attaches the database file at `path` as `name`. An empty path or `:memory:` attaches a
+    /// new in-memory database.
+    pub(crate) fn attach(&self, path: &str, name: &str) -> Result<()> {
+        let name = normalize_ident(name);
+        if !self.auto_commit.get() || self.transaction_state.get() != TransactionState::None {
+            return Err(LimboError::TxError(
+                "cannot ATTACH database within transaction".to_string(),
+            ));
+        }
+        if name == "main"
+            || name == "temp"
+            || self.attached.borrow().iter().any(|db| db.name == name)
+        {
+            return Err(LimboError::ParseError(format!(
+                "database {} is already in use",
+                name
+            )));
+        }
+        #[cfg(feature = "fs")]
+        let db = if path.is_empty() || path == ":memory:" {
+            Database::open_file(Arc::new(MemoryIO::new()), ":memory:", false)?
+        } else {
+            Database::open_file(Arc::new(PlatformIO::new()?), path, false)?
+        };
+        #[cfg(not(feature = "fs"))]
+        let db: Arc<Database> = {
+            let _ = path;
+            return Err(LimboError::InvalidArgument(
+                "ATTACH is not supported without the fs feature".to_string(),
+            ));
+        };
+        let pager = db.new_pager()?;
+        let mut attached = self.attached.borrow_mut();
+        db.schema.write().database = attached.len() + 2;
+        attached.push(AttachedDatabase { name, db, pager });
+        Ok(())
+    }
+
+    /// Detaches the database attached as `name`.
+    pub(crate) fn detach(&self, name: &str) -> Result<()> {
+        let name = normalize_ident(name);
+        if !self.auto_commit.get() || self.transaction_state.get() != TransactionState::None {
+            return Err(LimboError::TxError(
+                "cannot DETACH database within transaction".to_string(),
+            ));
+        }
+        if name == "main" || name == "temp" {
+            return Err(LimboError::ParseError(format!(
+                "cannot detach database {}",
+                name
+            )));
+        }
+        let mut attached = self.attached.borrow_mut();
+        let Some(position) = attached.iter().position(|db| db.name == name) else {
+            return Err(LimboError::ParseError(format!(
+                "no such database: {}",
+                name
+            )));
+        };
+        let db = attached.remove(position);
+        for (i, db) in attached.iter().enumerate().skip(position) {
+            db.db.schema.write().database = i + 2;
+        }
+        // Like closing the connection, so that the database attached again reads the changes
+        checkpoint_pager(&db.pager)
+    }
+
+    /// The pager of the temp database or of the attached database at `database`, see
+    /// [Schema::database].
+    pub(crate) fn attached_pager(&self, database: usize) -> Result<Rc<Pager>> {
+        if database == 1 {
+            return self.temp_pager();
+        }
+        database
+            .checked_sub(2)
+            .and_then(|i| self.attached.borrow().get(i).map(|db| db.pager.clone()))
+            .ok_or_else(|| LimboError::InternalError(format!("no database {}", database)))
+    }
+
+    /// The pagers of the temp database, once it is open, and of the attached databases, which
+    /// take part in the transactions of the connection along with the main database.
+    pub(crate) fn attached_pagers(&self) -> Vec<Rc<Pager>> {
+        let temp = self.temp.borrow();
+        temp.iter()
+            .chain(self.attached.borrow().iter())
+            .map(|db| db.pager.clone())
+            .collect()
+    }
+
+    /// The pager of the temp database, which is opened the first time a statement uses it. The
+    /// transaction in progress and its savepoints span it from then on. A statement that does not
+    /// write never rolls back to the statement journal begun here.
+    fn temp_pager(&self) -> Result<Rc<Pager>> {
+        if let Some(temp) = self.temp.borrow().as_ref() {
+            return Ok(temp.pager.clone());
+        }
+        #[cfg(feature = "fs")]
+        let db = Database::open_file(Arc::new(MemoryIO::new()), ":memory:", false)?;
+        #[cfg(not(feature = "fs"))]
+        let db: Arc<Database> = {
+            return Err(LimboError::InvalidArgument(
+                "temp databases are not supported without the fs feature".to_string(),
+            ));
+        };
+        let pager = db.new_pager()?;
+        db.schema.write().database = 1;
+        match self.transaction_state.get() {
+            TransactionState::Write => {
+                pager.begin_read_tx()?;
+                pager.begin_write_tx()?;
+                pager.begin_statement();
+            }
+            TransactionState::Read => {
+                pager.begin_read_tx()?;
+            }
+            TransactionState::None => {}
+        }
+        for _ in self.savepoints.borrow().iter() {
+            pager.open_savepoint();
+        }
+        self.temp.replace(Some(AttachedDatabase {
+            name: "temp".to_string(),
+            db,
+            pager: pager.clone(),
+        }));
+        Ok(pager)
+    }
+
+    /// The schema of the database at `database`, see [Schema::database].
+    pub(crate) fn database_schema(&self, database: usize) -> Result<Arc<RwLock<Schema>>> {
+        if database == 0 {
+            return Ok(self.schema.clone());
+        }
+        let temp = self.temp.borrow();
+        let attached = self.attached.borrow();
+        let db = match database {
+            1 => temp.as_ref(),
+            _ => attached.get(database - 2),
+        };
+        db.map(|db| db.db.schema.clone())
+            .ok_or_else(|| LimboError::InternalError(format!("no database {}", database)))
+    }
+
+    /// The schema table of the database at `database`, qualified with the name of the database.
+    pub(crate) fn schema_table_name(&self, database: usize) -> String {
+        let name = match database {
+            0 => "main".to_string(),
+            1 => "temp".to_string(),
+            _ => self.attached.borrow()[database - 2].name.clone(),
+        };
+        format!("\"{}\".sqlite_schema", name.replace('"', "\"\""))
+    }
+
+    /// The index, name and file path of each database of the connection, for `PRAGMA
+    /// database_list`. The temp database is only listed once it is open.
+    pub(crate) fn database_list(&self) -> Vec<(usize, String, String)> {
+        let temp = self.temp.borrow();
+        let attached = self.attached.borrow();
+        let databases = temp
+            .iter()
+            .map(|db| (1, db))
+            .chain(attached.iter().enumerate().map(|(i, db)| (i + 2, db)))
+            .map(|(database, db)| (database, db.name.clone(), db.db.path.clone()));
+        std::iter::once((0, "main".to_string(), self._db.path.clone()))
+            .chain(databases)
+            .collect()
+    }
+
+    /// Commits the transaction in the temp database and in the attached databases, before the
+    /// main database commits. Each database commits atomically, but a transaction that writes to
+    /// several databases is not committed atomically as a whole.
+    pub(crate) fn commit_attached(&self) -> Result<()> {
+        for pager in self.attached_pagers() {
+            loop {
+                match pager.end_tx()? {
+                    CheckpointStatus::Done(_) => break,
+                    CheckpointStatus::IO => pager.io.run_once()?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn reload_schema(self: &Rc<Connection>) -> Result<()> {
+        *self
+            .schema
+            .try_write()
+            .expect("lock on schema should succeed first try") = Schema::new();
+        self.parse_schema_rows()?;
+        // The statistics of the other databases are kept, as ANALYZE does not change them.
+        let databases = (1..self.attached.borrow().len() + 2)
+            .filter(|database| *database != 1 || self.temp.borrow().is_some());
+        for database in databases {
+            let rows = self.query(format!(
+                "SELECT * FROM {}",
+                self.schema_table_name(database)
+            ))?;
+            let mut schema = Schema::new();
+            schema.database = database;
+            parse_schema_rows(
+                rows,
+                &mut schema,
+                self.pager.io.clone(),
+                &self.syms.borrow(),
+                None,
+            )?;
+            let database_schema = self.database_schema(database)?;
+            schema.table_stats = database_schema.read().table_stats.clone();
+            *database_schema.write() = schema;
+        }
+        Ok(())
+    }
+
     pub fn parse_schema_rows(self: &Rc<Connection>) -> Result<()> {
         let rows = self.query("SELECT * FROM sqlite_schema")?;
         let mut schema = self
@@ -550,7 +981,19 @@ impl Connection {
                 eprintln!("Warning: {}", e);
             }
         }
-        Ok(())
+        drop(schema);
+        self.load_analysis()
+    }
+
+    /// Loads the statistics that ANALYZE stored in sqlite_stat1 into the schema.
+    pub(crate) fn load_analysis(self: &Rc<Connection>) -> Result<()> {
+        let has_stat1 = self.schema.read().get_btree_table(STAT1_TABLE).is_some();
+        let rows = if has_stat1 {
+            self.query(format!("SELECT tbl, idx, stat FROM {}", STAT1_TABLE))?
+        } else {
+            None
+        };
+        parse_stat1_rows(rows, &mut self.schema.write(), self.pager.io.clone())
     }
 }
 
@@ -803,6 +1246,17 @@ pub(crate) struct SymbolTable {
     pub functions: HashMap<String, Rc<function::ExternalFunc>>,
     pub vtabs: HashMap<String, Rc<VirtualTable>>,
     pub vtab_modules: HashMap<String, Rc<crate::ext::VTabImpl>>,
+    /// Collating sequences registered by extensions, keyed by lowercase name.
+    pub collations: HashMap<String, limbo_ext::CollationFunction>,
+    /// Whether PRAGMA foreign_keys is on, in which case the statements compiled for the
+    /// connection enforce the FOREIGN KEY constraints.
+    pub foreign_keys: Cell<bool>,
+    /// Whether PRAGMA ignore_check_constraints is on, in which case the statements compiled for
+    /// the connection do not check the CHECK constraints.
+    pub ignore_check_constraints: Cell<bool>,
+    /// PRAGMA analysis_limit: when positive, about how many entries of each index ANALYZE
+    /// examines.
+    pub analysis_limit: Cell<i64>,
 }
 
 impl std::fmt::Debug for SymbolTable {
@@ -846,6 +1300,10 @@ impl SymbolTable {
             functions: HashMap::new(),
             vtabs: HashMap::new(),
             vtab_modules: HashMap::new(),
+            collations: HashMap::new(),
+            foreign_keys: Cell::new(false),
+            ignore_check_constraints: Cell::new(false),
+            analysis_limit: Cell::new(0),
         }
     }
 
@@ -856,9 +1314,19 @@ impl SymbolTable {
     ) -> Option<Rc<function::ExternalFunc>> {
         self.functions.get(name).cloned()
     }
+
+    /// Resolve a collating sequence by name, looking at the built-in ones first.
+    pub fn resolve_collation(&self, name: &str) -> Option<collation::CollationSeq> {
+        collation::CollationSeq::builtin(name).or_else(|| {
+            self.collations
+                .get(&name.to_lowercase())
+                .map(|func| collation::CollationSeq::External(*func))
+        })
+    }
 }
 
 pub struct QueryRunner<'a> {
+    statements: &'a [u8],
     parser: Parser<'a>,
     conn: &'a Rc<Connection>,
 }
@@ -866,6 +1334,7 @@ pub struct QueryRunner<'a> {
 impl<'a> QueryRunner<'a> {
     pub(crate) fn new(conn: &'a Rc<Connection>, statements: &'a [u8]) -> Self {
         Self {
+            statements,
             parser: Parser::new(statements),
             conn,
         }
@@ -876,8 +1345,12 @@ impl Iterator for QueryRunner<'_> {
     type Item = Result<Option<Statement>>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let start = self.parser.offset();
         match self.parser.next() {
-            Ok(Some(cmd)) => Some(self.conn.run_cmd(cmd)),
+            Ok(Some(cmd)) => {
+                let input = String::from_utf8_lossy(&self.statements[start..self.parser.offset()]);
+                Some(self.conn.run_cmd(cmd, &input))
+            }
             Ok(None) => None,
             Err(err) => {
                 self.parser.finalize();
@@ -1,21 +1,96 @@
-use crate::{util::normalize_ident, Result};
+use crate::translate::expr::sanitize_string;
+use crate::translate::schema::SQLITE_TABLEID;
+use crate::translate::window::child_exprs;
+use crate::types::OwnedValue;
+use crate::util::{
+    normalize_ident, statement_tokens, PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX, STAT1_TABLE,
+};
+use crate::Result;
 use crate::{LimboError, VirtualTable};
 use core::fmt;
 use fallible_iterator::FallibleIterator;
-use limbo_sqlite3_parser::ast::{Expr, Literal, SortOrder, TableOptions};
+use limbo_sqlite3_parser::ast::{
+    DeferSubclause, Expr, ForeignKeyClause, InitDeferredPred, Literal, RefAct, RefArg, ResolveType,
+    SortOrder, TableConstraint, TableOptions, UnaryOperator,
+};
 use limbo_sqlite3_parser::{
-    ast::{Cmd, CreateTableBody, QualifiedName, ResultColumn, Stmt},
+    ast::{
+        Cmd, CreateTableBody, IndexedColumn, QualifiedName, ResultColumn, Select, Stmt, TriggerCmd,
+        TriggerEvent, TriggerTime,
+    },
+    dialect::TokenType,
     lexer::sql::Parser,
 };
+use parking_lot::{RwLock, RwLockReadGuard};
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::ops::Deref;
 use std::rc::Rc;
 use std::sync::Arc;
 use tracing::trace;
 
+#[derive(Clone)]
 pub struct Schema {
     pub tables: HashMap<String, Arc<Table>>,
     // table_name to list of indexes for the table
     pub indexes: HashMap<String, Vec<Arc<Index>>>,
+    // table_name to list of triggers on the table, most recently created first
+    pub triggers: HashMap<String, Vec<Arc<Trigger>>>,
+    /// While the commands of a trigger are compiled, the foreign key actions whose programs are
+    /// being compiled around them. An action that fires itself again, like a cascading delete in
+    /// a tree, runs the same program recursively instead of compiling a new one.
+    pub(crate) fk_actions_in_progress: Vec<FkActionInProgress>,
+    /// The index of this database in its connection: 0 is the main database, 1 the temp
+    /// database and the databases attached with ATTACH follow in the order they were attached.
+    pub database: usize,
+    /// The databases attached to the connection when a statement is translated. Only the schema
+    /// of the main database a statement is translated with has them.
+    pub attached: Vec<AttachedSchema>,
+    /// The schema of the temp database of the connection when a statement is translated, once
+    /// the temp database is open. Like [Schema::attached], only the schema of the main database
+    /// has it.
+    pub temp: Option<Arc<RwLock<Schema>>>,
+    /// The statistics that ANALYZE stored in sqlite_stat1, by table name.
+    pub table_stats: HashMap<String, TableStats>,
+}
+
+/// The schema of a database attached to a connection, see [Schema::attached].
+#[derive(Clone)]
+pub struct AttachedSchema {
+    pub name: String,
+    pub schema: Arc<RwLock<Schema>>,
+}
+
+/// The schema of one of the databases of a connection, see [Schema::database_schema].
+pub enum DatabaseSchema<'a> {
+    Main(&'a Schema),
+    Attached(RwLockReadGuard<'a, Schema>),
+    /// The temp database before it is opened, which is empty.
+    Empty(Box<Schema>),
+}
+
+impl Deref for DatabaseSchema<'_> {
+    type Target = Schema;
+
+    fn deref(&self) -> &Schema {
+        match self {
+            DatabaseSchema::Main(schema) => schema,
+            DatabaseSchema::Attached(schema) => schema,
+            DatabaseSchema::Empty(schema) => schema,
+        }
+    }
+}
+
+/// A foreign key action whose program is being compiled, see
+/// [Schema::fk_actions_in_progress].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct FkActionInProgress {
+    pub child_table: String,
+    /// The index of the constraint in the foreign keys of the child table.
+    pub fk_index: usize,
+    pub on_delete: bool,
+    /// How many frames up from the command being compiled the program of the action runs.
+    pub frames_up: usize,
 }
 
 impl Schema {
@@ -27,7 +102,104 @@ impl Schema {
             "sqlite_schema".to_string(),
             Arc::new(Table::BTree(sqlite_schema_table().into())),
         );
-        Self { tables, indexes }
+        Self {
+            tables,
+            indexes,
+            triggers: HashMap::new(),
+            fk_actions_in_progress: Vec::new(),
+            database: 0,
+            attached: Vec::new(),
+            temp: None,
+            table_stats: HashMap::new(),
+        }
+    }
+
+    /// The index of the database called `name`, which is `main`, `temp` or the name of an
+    /// attached database.
+    pub fn database_index(&self, name: &str) -> Result<usize> {
+        let name = normalize_ident(name);
+        match name.as_str() {
+            "main" => Ok(0),
+            "temp" => Ok(1),
+            _ => match self.attached.iter().position(|db| db.name == name) {
+                Some(position) => Ok(position + 2),
+                None => Err(LimboError::ParseError(format!("unknown database {}", name))),
+            },
+        }
+    }
+
+    /// The name of the database at `database`, or an empty string for an attached database
+    /// whose schema a statement is translated with directly, like the commands of its triggers.
+    pub fn database_name(&self, database: usize) -> &str {
+        match database {
+            0 => "main",
+            1 => "temp",
+            _ => self
+                .attached
+                .get(database - 2)
+                .map_or("", |db| db.name.as_str()),
+        }
+    }
+
+    /// The schema of the database at `database`, see [Schema::database].
+    pub fn database_schema(&self, database: usize) -> Result<DatabaseSchema<'_>> {
+        if database == self.database {
+            return Ok(DatabaseSchema::Main(self));
+        }
+        if database == 1 {
+            return Ok(match &self.temp {
+                Some(temp) => DatabaseSchema::Attached(temp.read()),
+                None => {
+                    let mut schema = Schema::new();
+                    schema.database = 1;
+                    DatabaseSchema::Empty(Box::new(schema))
+                }
+            });
+        }
+        match database.checked_sub(2).and_then(|i| self.attached.get(i)) {
+            Some(db) => Ok(DatabaseSchema::Attached(db.schema.read())),
+            None => Err(LimboError::ParseError(
+                "no such database in this context".to_string(),
+            )),
+        }
+    }
+
+    /// The databases an unqualified name is looked up in, in order: the temp database once it is
+    /// open, the main database and the attached databases in the order they were attached.
+    pub fn search_order(&self) -> impl Iterator<Item = usize> {
+        let temp = self.temp.as_ref().map(|_| 1);
+        temp.into_iter()
+            .chain(std::iter::once(self.database))
+            .chain(2..self.attached.len() + 2)
+    }
+
+    /// Looks up the table `name` in the database `db_name`, or when there is no qualifier in the
+    /// databases of [Schema::search_order]. Returns the index of the database the table was found
+    /// in.
+    pub fn find_table(
+        &self,
+        db_name: Option<&str>,
+        name: &str,
+    ) -> Result<Option<(usize, Arc<Table>)>> {
+        let database = match db_name {
+            Some(db_name) => Some(self.database_index(db_name)?),
+            None => match normalize_ident(name).as_str() {
+                // The schema table of the main database hides the one of the temp database
+                SQLITE_TABLEID | "sqlite_master" => Some(self.database),
+                "sqlite_temp_schema" | "sqlite_temp_master" => Some(1),
+                _ => None,
+            },
+        };
+        if let Some(database) = database {
+            let table = self.database_schema(database)?.get_table(name);
+            return Ok(table.map(|table| (database, table)));
+        }
+        for database in self.search_order() {
+            if let Some(table) = self.database_schema(database)?.get_table(name) {
+                return Ok(Some((database, table)));
+            }
+        }
+        Ok(None)
     }
 
     pub fn is_unique_idx_name(&self, name: &str) -> bool {
@@ -39,6 +211,9 @@ impl Schema {
 
     pub fn add_btree_table(&mut self, table: Rc<BTreeTable>) {
         let name = normalize_ident(&table.name);
+        if let Some(index) = table.primary_key_index() {
+            self.add_index(Arc::new(index));
+        }
         self.tables.insert(name, Table::BTree(table).into());
     }
 
@@ -47,14 +222,29 @@ impl Schema {
         self.tables.insert(name, Table::Virtual(table).into());
     }
 
+    pub fn add_view(&mut self, view: Rc<View>) {
+        let name = normalize_ident(&view.name);
+        self.tables.insert(name, Table::View(view).into());
+    }
+
     pub fn get_table(&self, name: &str) -> Option<Arc<Table>> {
         let name = normalize_ident(name);
-        self.tables.get(&name).cloned()
+        let name = match name.as_str() {
+            "sqlite_master" => SQLITE_TABLEID,
+            "sqlite_temp_schema" | "sqlite_temp_master" if self.database == 1 => SQLITE_TABLEID,
+            name => name,
+        };
+        self.tables.get(name).cloned()
     }
 
     pub fn remove_table(&mut self, table_name: &str) {
         let name = normalize_ident(table_name);
         self.tables.remove(&name);
+        if name == STAT1_TABLE {
+            self.table_stats.clear();
+        } else {
+            self.table_stats.remove(&name);
+        }
     }
 
     pub fn get_btree_table(&self, name: &str) -> Option<Rc<BTreeTable>> {
@@ -81,10 +271,175 @@ impl Schema {
             .map_or_else(|| &[] as &[Arc<Index>], |v| v.as_slice())
     }
 
+    pub fn get_index(&self, name: &str) -> Option<Arc<Index>> {
+        let name = normalize_ident(name);
+        self.indexes
+            .values()
+            .flatten()
+            .find(|index| index.name == name)
+            .cloned()
+    }
+
+    pub fn remove_index(&mut self, name: &str) {
+        let name = normalize_ident(name);
+        for indexes in self.indexes.values_mut() {
+            indexes.retain(|index| index.name != name);
+        }
+        for stats in self.table_stats.values_mut() {
+            stats.indexes.remove(&name);
+        }
+    }
+
     pub fn remove_indices_for_table(&mut self, table_name: &str) {
         let name = normalize_ident(table_name);
         self.indexes.remove(&name);
     }
+
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn add_trigger(&mut self, trigger: Trigger) {
+        let table_name = normalize_ident(&trigger.table_name);
+        self.triggers
+            .entry(table_name)
+            .or_default()
+            .insert(0, Arc::new(trigger));
+    }
+
+    pub fn get_trigger(&self, name: &str) -> Option<Arc<Trigger>> {
+        let name = normalize_ident(name);
+        self.triggers
+            .values()
+            .flatten()
+            .find(|trigger| trigger.name == name)
+            .cloned()
+    }
+
+    pub fn get_triggers(&self, table_name: &str) -> &[Arc<Trigger>] {
+        let name = normalize_ident(table_name);
+        self.triggers
+            .get(&name)
+            .map_or_else(|| &[] as &[Arc<Trigger>], |v| v.as_slice())
+    }
+
+    pub fn remove_trigger(&mut self, name: &str) {
+        let name = normalize_ident(name);
+        for triggers in self.triggers.values_mut() {
+            triggers.retain(|trigger| trigger.name != name);
+        }
+    }
+
+    pub fn remove_triggers_for_table(&mut self, table_name: &str) {
+        let name = normalize_ident(table_name);
+        self.triggers.remove(&name);
+    }
+
+    /// Returns the tables with a foreign key that refers to `table_name`, along with the index of
+    /// each such foreign key, ordered by the name of the table.
+    pub fn get_referencing_foreign_keys(&self, table_name: &str) -> Vec<(Rc<BTreeTable>, usize)> {
+        let name = normalize_ident(table_name);
+        let mut referencing = self
+            .tables
+            .values()
+            .filter_map(|table| table.btree())
+            .flat_map(|table| {
+                (0..table.foreign_keys.len())
+                    .filter(|i| table.foreign_keys[*i].parent_table == name)
+                    .map(|i| (table.clone(), i))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        referencing.sort_by(|(a, i), (b, j)| a.name.cmp(&b.name).then(i.cmp(j)));
+        referencing
+    }
+
+    pub fn get_table_stats(&self, table_name: &str) -> Option<&TableStats> {
+        self.table_stats.get(&normalize_ident(table_name))
+    }
+
+    /// Records a row of sqlite_stat1: the statistics of the index `idx` of the table `tbl`, or
+    /// the number of rows of the table when there is no index. Rows that cannot be parsed are
+    /// ignored, like SQLite does.
+    pub fn add_stat1_row(&mut self, tbl: &str, idx: Option<&str>, stat: &str) {
+        let mut numbers = stat
+            .split(' ')
+            .map_while(|n| n.parse::<u64>().ok())
+            .collect::<Vec<_>>();
+        if numbers.is_empty() {
+            return;
+        }
+        let row_count = numbers.remove(0);
+        // The index of the PRIMARY KEY of a WITHOUT ROWID table has the name of the table
+        let idx = idx.map(|idx| {
+            let idx = normalize_ident(idx);
+            match self.get_btree_table(&idx) {
+                Some(table) if idx == normalize_ident(tbl) => table
+                    .primary_key_index()
+                    .map_or(idx, |index| normalize_ident(&index.name)),
+                _ => idx,
+            }
+        });
+        let is_partial = idx.as_ref().is_some_and(|idx| {
+            self.get_index(idx)
+                .is_some_and(|index| index.where_clause.is_some())
+        });
+        let stats = self.table_stats.entry(normalize_ident(tbl)).or_default();
+        match idx {
+            Some(idx) => {
+                // A partial index only has some of the rows of the table
+                if !is_partial {
+                    stats.row_count = Some(row_count);
+                }
+                stats.indexes.insert(
+                    idx,
+                    IndexStats {
+                        row_count,
+                        rows_per_key: numbers,
+                    },
+                );
+            }
+            None => stats.row_count = Some(row_count),
+        }
+    }
+
+    /// Updates the b-trees whose root page was moved from page `from` to page `to`, which an
+    /// auto-vacuum database does when a b-tree is dropped.
+    pub fn root_page_moved(&mut self, from: usize, to: usize) {
+        for table in self.tables.values_mut() {
+            if let Table::BTree(btree) = table.as_ref() {
+                if btree.root_page == from {
+                    let mut btree = btree.as_ref().clone();
+                    btree.root_page = to;
+                    *table = Table::BTree(Rc::new(btree)).into();
+                }
+            }
+        }
+        for index in self.indexes.values_mut().flatten() {
+            if index.root_page == from {
+                let mut moved = index.as_ref().clone();
+                moved.root_page = to;
+                *index = Arc::new(moved);
+            }
+        }
+    }
+}
+
+/// The statistics of a table and of its indexes, which ANALYZE collects. The query planner
+/// uses them to estimate how many rows each way of looking up the table reads.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TableStats {
+    /// The number of rows of the table.
+    pub row_count: Option<u64>,
+    /// The statistics of the indexes of the table, by index name.
+    pub indexes: HashMap<String, IndexStats>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexStats {
+    /// The number of entries of the index.
+    pub row_count: u64,
+    /// For each column of the index, the average number of entries that have the same values
+    /// in the columns up to it. E.g. for an index on (a, b), the first number is how many
+    /// entries an `a = ?` lookup finds, and the second one how many `a = ? AND b = ?` finds.
+    pub rows_per_key: Vec<u64>,
 }
 
 #[derive(Clone, Debug)]
@@ -92,6 +447,7 @@ pub enum Table {
     BTree(Rc<BTreeTable>),
     Pseudo(Rc<PseudoTable>),
     Virtual(Rc<VirtualTable>),
+    View(Rc<View>),
 }
 
 impl Table {
@@ -100,6 +456,7 @@ impl Table {
             Table::BTree(table) => table.root_page,
             Table::Pseudo(_) => unimplemented!(),
             Table::Virtual(_) => unimplemented!(),
+            Table::View(_) => 0,
         }
     }
 
@@ -108,6 +465,7 @@ impl Table {
             Self::BTree(table) => &table.name,
             Self::Pseudo(_) => "",
             Self::Virtual(table) => &table.name,
+            Self::View(view) => &view.name,
         }
     }
 
@@ -116,6 +474,7 @@ impl Table {
             Self::BTree(table) => table.columns.get(index),
             Self::Pseudo(table) => table.columns.get(index),
             Self::Virtual(table) => table.columns.get(index),
+            Self::View(view) => view.columns.get(index),
         }
     }
 
@@ -124,6 +483,7 @@ impl Table {
             Self::BTree(table) => &table.columns,
             Self::Pseudo(table) => &table.columns,
             Self::Virtual(table) => &table.columns,
+            Self::View(view) => &view.columns,
         }
     }
 
@@ -132,6 +492,7 @@ impl Table {
             Self::BTree(table) => Some(table.clone()),
             Self::Pseudo(_) => None,
             Self::Virtual(_) => None,
+            Self::View(_) => None,
         }
     }
 
@@ -149,12 +510,13 @@ impl PartialEq for Table {
             (Self::BTree(a), Self::BTree(b)) => Rc::ptr_eq(a, b),
             (Self::Pseudo(a), Self::Pseudo(b)) => Rc::ptr_eq(a, b),
             (Self::Virtual(a), Self::Virtual(b)) => Rc::ptr_eq(a, b),
+            (Self::View(a), Self::View(b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BTreeTable {
     pub root_page: usize,
     pub name: String,
@@ -162,9 +524,41 @@ pub struct BTreeTable {
     pub columns: Vec<Column>,
     pub has_rowid: bool,
     pub is_strict: bool,
+    /// The PRIMARY KEY and UNIQUE constraints enforced by automatic indexes, in the order they
+    /// are numbered: `sqlite_autoindex_<table>_<n>` enforces `unique_sets[n - 1]`.
+    pub unique_sets: Vec<UniqueSet>,
+    /// The conflict resolution algorithm of the ON CONFLICT clause of the PRIMARY KEY, if any.
+    pub primary_key_on_conflict: Option<ResolveType>,
+    /// The conflict resolution algorithms of the ON CONFLICT clauses of the NOT NULL
+    /// constraints, with the positions of their columns.
+    pub not_null_on_conflict: Vec<(usize, ResolveType)>,
+    /// The FOREIGN KEY constraints of the table, in declaration order.
+    pub foreign_keys: Vec<ForeignKey>,
+    /// The CHECK constraints of the table, in declaration order.
+    pub checks: Vec<CheckConstraint>,
+}
+
+/// A PRIMARY KEY or UNIQUE constraint enforced by an automatic index.
+#[derive(Debug, Clone)]
+pub struct UniqueSet {
+    pub columns: Vec<(String, SortOrder)>,
+    /// The conflict resolution algorithm of the ON CONFLICT clause of the constraint, if any.
+    pub on_conflict: Option<ResolveType>,
 }
 
 impl BTreeTable {
+    /// Returns the constraint enforced by the automatic index named `index_name`.
+    pub fn unique_set_of_index(&self, index_name: &str) -> Option<&UniqueSet> {
+        if !index_name.starts_with(PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX) {
+            return None;
+        }
+        index_name
+            .rsplit('_')
+            .next()
+            .and_then(|n| n.parse::<usize>().ok())
+            .and_then(|n| self.unique_sets.get(n.wrapping_sub(1)))
+    }
+
     pub fn get_rowid_alias_column(&self) -> Option<(usize, &Column)> {
         if self.primary_key_columns.len() == 1 {
             let (idx, col) = self.get_column(&self.primary_key_columns[0].0).unwrap();
@@ -179,6 +573,77 @@ impl BTreeTable {
         col.is_rowid_alias
     }
 
+    /// Returns the index whose b-tree is the b-tree of a WITHOUT ROWID table, keyed by the
+    /// PRIMARY KEY columns. It has the name of the automatic index of the PRIMARY KEY, although
+    /// it has no entry in sqlite_schema.
+    pub fn primary_key_index(&self) -> Option<Index> {
+        if self.has_rowid {
+            return None;
+        }
+        let n = self
+            .unique_sets
+            .iter()
+            .position(|unique_set| {
+                unique_set.columns.len() == self.primary_key_columns.len()
+                    && unique_set
+                        .columns
+                        .iter()
+                        .zip(self.primary_key_columns.iter())
+                        .all(|((a, _), (b, _))| a == b)
+            })
+            .map_or(1, |i| i + 1);
+        let name = format!(
+            "{}{}_{}",
+            PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX, self.name, n
+        );
+        Index::automatic_from_unique(self, &name, self.root_page, &self.primary_key_columns).ok()
+    }
+
+    /// Returns true if `index` is the [BTreeTable::primary_key_index] of this table.
+    pub fn is_primary_key_index(&self, index: &Index) -> bool {
+        !self.has_rowid && !index.ephemeral && index.root_page == self.root_page
+    }
+
+    /// Returns the position in the records of the table of the column at `pos`. The records of
+    /// a WITHOUT ROWID table start with the PRIMARY KEY columns, followed by the other columns in
+    /// the order of the table.
+    pub fn column_storage_position(&self, pos: usize) -> usize {
+        if self.has_rowid {
+            return pos;
+        }
+        let column = &self.columns[pos];
+        if column.primary_key {
+            if let Some(i) = self
+                .primary_key_columns
+                .iter()
+                .position(|(name, _)| column.name.as_ref() == Some(name))
+            {
+                return i;
+            }
+        }
+        self.primary_key_columns.len()
+            + self.columns[..pos]
+                .iter()
+                .filter(|column| !column.primary_key)
+                .count()
+    }
+
+    /// Returns the positions of the PRIMARY KEY columns in the table, in the order of the key.
+    pub fn primary_key_column_positions(&self) -> Vec<usize> {
+        self.primary_key_columns
+            .iter()
+            .filter_map(|(name, _)| self.get_column(name).map(|(pos, _)| pos))
+            .collect()
+    }
+
+    /// Returns the positions of the columns of the table in the order they are stored in its
+    /// records, see [BTreeTable::column_storage_position].
+    pub fn columns_in_storage_order(&self) -> Vec<usize> {
+        let mut positions = (0..self.columns.len()).collect::<Vec<_>>();
+        positions.sort_by_key(|pos| self.column_storage_position(*pos));
+        positions
+    }
+
     /// Returns the column position and column for a given column name.
     /// Returns None if the column name is not found.
     /// E.g. if table is CREATE TABLE t(a, b, c)
@@ -198,7 +663,14 @@ impl BTreeTable {
         let cmd = parser.next()?;
         match cmd {
             Some(Cmd::Stmt(Stmt::CreateTable { tbl_name, body, .. })) => {
-                create_table(tbl_name, *body, root_page)
+                let mut table = create_table(tbl_name, *body, root_page)?;
+                let texts = check_constraint_texts(sql);
+                if texts.len() == table.checks.len() {
+                    for (check, text) in table.checks.iter_mut().zip(texts) {
+                        check.text = text.to_string();
+                    }
+                }
+                Ok(table)
             }
             _ => todo!("Expected CREATE TABLE statement"),
         }
@@ -221,6 +693,219 @@ impl BTreeTable {
     }
 }
 
+/// A view, i.e. a named SELECT statement that is expanded as a subquery wherever it is used.
+#[derive(Debug)]
+pub struct View {
+    pub name: String,
+    pub select: Select,
+    /// The column names in `CREATE VIEW name(col1, col2, ...)`, if any.
+    pub column_names: Option<Vec<IndexedColumn>>,
+    /// The explicitly named columns of the view. Empty if the view has no column name list.
+    pub columns: Vec<Column>,
+    /// Set while the view is being expanded, to detect views that refer to themselves.
+    pub expanding: Cell<bool>,
+}
+
+impl View {
+    pub fn new(name: &str, select: Select, column_names: Option<Vec<IndexedColumn>>) -> Self {
+        let columns = column_names
+            .iter()
+            .flatten()
+            .map(|column| Column {
+                name: Some(normalize_ident(&column.col_name.0)),
+                ty: Type::Null,
+                ty_str: String::new(),
+                primary_key: false,
+                is_rowid_alias: false,
+                notnull: false,
+                default: None,
+                collation: None,
+            })
+            .collect();
+        Self {
+            name: normalize_ident(name),
+            select,
+            column_names,
+            columns,
+            expanding: Cell::new(false),
+        }
+    }
+
+    pub fn from_sql(sql: &str) -> Result<View> {
+        let mut parser = Parser::new(sql.as_bytes());
+        let cmd = parser.next()?;
+        match cmd {
+            Some(Cmd::Stmt(Stmt::CreateView {
+                view_name,
+                columns,
+                select,
+                ..
+            })) => Ok(Self::new(&view_name.name.0, *select, columns)),
+            _ => Err(LimboError::ParseError(format!(
+                "expected CREATE VIEW statement: {}",
+                sql
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Trigger {
+    pub name: String,
+    pub table_name: String,
+    pub time: TriggerTime,
+    pub event: TriggerEvent,
+    pub when_clause: Option<Expr>,
+    pub commands: Vec<TriggerCmd>,
+}
+
+impl Trigger {
+    pub fn new(
+        name: &str,
+        table_name: &str,
+        time: Option<TriggerTime>,
+        event: TriggerEvent,
+        when_clause: Option<Expr>,
+        commands: Vec<TriggerCmd>,
+    ) -> Self {
+        Self {
+            name: normalize_ident(name),
+            table_name: normalize_ident(table_name),
+            time: time.unwrap_or(TriggerTime::Before),
+            event,
+            when_clause,
+            commands,
+        }
+    }
+
+    pub fn from_sql(sql: &str) -> Result<Trigger> {
+        let mut parser = Parser::new(sql.as_bytes());
+        let cmd = parser.next()?;
+        match cmd {
+            Some(Cmd::Stmt(Stmt::CreateTrigger(create))) => {
+                let create = *create;
+                Ok(Self::new(
+                    &create.trigger_name.name.0,
+                    &create.tbl_name.name.0,
+                    create.time,
+                    create.event,
+                    create.when_clause,
+                    create.commands,
+                ))
+            }
+            _ => Err(LimboError::ParseError(format!(
+                "expected CREATE TRIGGER statement: {}",
+                sql
+            ))),
+        }
+    }
+}
+
+/// A CHECK constraint, which a row of the table satisfies unless its expression is false.
+#[derive(Debug, Clone)]
+pub struct CheckConstraint {
+    pub name: Option<String>,
+    pub expr: Expr,
+    /// The text of the expression in the CREATE TABLE statement.
+    pub text: String,
+}
+
+impl CheckConstraint {
+    /// The name of the constraint in the errors, which is the text of its expression if it has
+    /// no name.
+    pub fn description(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.text.clone(),
+        }
+    }
+}
+
+/// The texts of the expressions of the CHECK constraints in the CREATE TABLE statement `sql`, in
+/// declaration order.
+fn check_constraint_texts(sql: &str) -> Vec<&str> {
+    let tokens = statement_tokens(sql);
+    let mut texts = vec![];
+    for (i, (token_type, _)) in tokens.iter().enumerate() {
+        if *token_type != TokenType::TK_CHECK {
+            continue;
+        }
+        let Some((TokenType::TK_LP, open)) = tokens.get(i + 1) else {
+            continue;
+        };
+        let mut depth = 0;
+        for (token_type, range) in &tokens[i + 1..] {
+            match token_type {
+                TokenType::TK_LP => depth += 1,
+                TokenType::TK_RP => {
+                    depth -= 1;
+                    if depth == 0 {
+                        texts.push(sql[open.end..range.start].trim());
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    texts
+}
+
+/// A FOREIGN KEY constraint, by which the child key, i.e. some columns of the table with the
+/// constraint, refers to the parent key of a row of the parent table.
+#[derive(Debug, Clone)]
+pub struct ForeignKey {
+    pub child_columns: Vec<String>,
+    pub parent_table: String,
+    /// The parent key, or empty if the constraint refers to the primary key of the parent table.
+    pub parent_columns: Vec<String>,
+    pub on_delete: RefAct,
+    pub on_update: RefAct,
+    /// Whether the constraint is DEFERRABLE INITIALLY DEFERRED, so that it is only checked when
+    /// the transaction commits.
+    pub deferred: bool,
+}
+
+impl ForeignKey {
+    fn new(
+        child_columns: Vec<String>,
+        clause: &ForeignKeyClause,
+        defer_clause: Option<&DeferSubclause>,
+    ) -> Self {
+        let mut on_delete = RefAct::NoAction;
+        let mut on_update = RefAct::NoAction;
+        for arg in &clause.args {
+            match arg {
+                RefArg::OnDelete(action) => on_delete = *action,
+                RefArg::OnUpdate(action) => on_update = *action,
+                RefArg::OnInsert(_) | RefArg::Match(_) => {}
+            }
+        }
+        let mut foreign_key = Self {
+            child_columns,
+            parent_table: normalize_ident(&clause.tbl_name.0),
+            parent_columns: clause
+                .columns
+                .iter()
+                .flatten()
+                .map(|column| normalize_ident(&column.col_name.0))
+                .collect(),
+            on_delete,
+            on_update,
+            deferred: false,
+        };
+        if let Some(defer_clause) = defer_clause {
+            foreign_key.set_deferred(defer_clause);
+        }
+        foreign_key
+    }
+
+    fn set_deferred(&mut self, defer_clause: &DeferSubclause) {
+        self.deferred = defer_clause.deferrable
+            && defer_clause.init_deferred == Some(InitDeferredPred::InitiallyDeferred);
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct PseudoTable {
     pub columns: Vec<Column>,
@@ -244,6 +929,7 @@ impl PseudoTable {
             is_rowid_alias: false,
             notnull: false,
             default: None,
+            collation: None,
         });
     }
     pub fn get_column(&self, name: &str) -> Option<(usize, &Column)> {
@@ -267,6 +953,16 @@ fn create_table(
     let mut has_rowid = true;
     let mut primary_key_columns = vec![];
     let mut cols = vec![];
+    // (constraint, is the primary key) of each PRIMARY KEY or UNIQUE constraint, in declaration
+    // order
+    let mut unique_sets = vec![];
+    let mut primary_key_on_conflict = None;
+    let mut not_null_on_conflict = vec![];
+    let mut table_unique_sets = vec![];
+    let mut foreign_keys = vec![];
+    let mut table_foreign_keys = vec![];
+    let mut checks = vec![];
+    let mut table_checks = vec![];
     let is_strict: bool;
     match body {
         CreateTableBody::ColumnsAndConstraints {
@@ -277,24 +973,63 @@ fn create_table(
             is_strict = options.contains(TableOptions::STRICT);
             if let Some(constraints) = constraints {
                 for c in constraints {
-                    if let limbo_sqlite3_parser::ast::TableConstraint::PrimaryKey {
-                        columns, ..
-                    } = c.constraint
-                    {
-                        for column in columns {
-                            let col_name = match column.expr {
-                                Expr::Id(id) => normalize_ident(&id.0),
-                                Expr::Literal(Literal::String(value)) => {
-                                    value.trim_matches('\'').to_owned()
-                                }
-                                _ => {
-                                    todo!("Unsupported primary key expression");
-                                }
-                            };
-                            primary_key_columns
-                                .push((col_name, column.order.unwrap_or(SortOrder::Asc)));
+                    let (columns, on_conflict, is_primary_key) = match c.constraint {
+                        TableConstraint::PrimaryKey {
+                            columns,
+                            conflict_clause,
+                            ..
+                        } => (columns, conflict_clause, true),
+                        TableConstraint::Unique {
+                            columns,
+                            conflict_clause,
+                        } => (columns, conflict_clause, false),
+                        TableConstraint::ForeignKey {
+                            columns,
+                            clause,
+                            deref_clause,
+                        } => {
+                            let child_columns = columns
+                                .iter()
+                                .map(|column| normalize_ident(&column.col_name.0))
+                                .collect();
+                            table_foreign_keys.push(ForeignKey::new(
+                                child_columns,
+                                &clause,
+                                deref_clause.as_ref(),
+                            ));
+                            continue;
                         }
+                        TableConstraint::Check(expr) => {
+                            table_checks.push(CheckConstraint {
+                                name: c.name.map(|name| normalize_ident(&name.0)),
+                                text: expr.to_string(),
+                                expr,
+                            });
+                            continue;
+                        }
+                    };
+                    let mut unique_set = Vec::with_capacity(columns.len());
+                    for column in columns {
+                        let col_name = match column.expr {
+                            Expr::Id(id) => normalize_ident(&id.0),
+                            Expr::Literal(Literal::String(value)) => {
+                                value.trim_matches('\'').to_owned()
+                            }
+                            _ => {
+                                todo!("Unsupported primary key expression");
+                            }
+                        };
+                        unique_set.push((col_name, column.order.unwrap_or(SortOrder::Asc)));
                     }
+                    if is_primary_key {
+                        primary_key_columns.extend(unique_set.iter().cloned());
+                        primary_key_on_conflict = on_conflict;
+                    }
+                    let unique_set = UniqueSet {
+                        columns: unique_set,
+                        on_conflict,
+                    };
+                    table_unique_sets.push((unique_set, is_primary_key));
                 }
             }
             for (col_name, col_def) in columns {
@@ -349,30 +1084,80 @@ fn create_table(
                 let mut default = None;
                 let mut primary_key = false;
                 let mut notnull = false;
+                let mut collation = None;
                 let mut order = SortOrder::Asc;
+                let mut column_foreign_keys = vec![];
                 for c_def in &col_def.constraints {
                     match &c_def.constraint {
                         limbo_sqlite3_parser::ast::ColumnConstraint::PrimaryKey {
                             order: o,
+                            conflict_clause,
                             ..
                         } => {
                             primary_key = true;
                             if let Some(o) = o {
                                 order = o.clone();
                             }
+                            primary_key_on_conflict = *conflict_clause;
+                            let unique_set = UniqueSet {
+                                columns: vec![(normalize_ident(&name), order)],
+                                on_conflict: *conflict_clause,
+                            };
+                            unique_sets.push((unique_set, true));
+                        }
+                        limbo_sqlite3_parser::ast::ColumnConstraint::Unique(conflict_clause) => {
+                            let unique_set = UniqueSet {
+                                columns: vec![(normalize_ident(&name), SortOrder::Asc)],
+                                on_conflict: *conflict_clause,
+                            };
+                            unique_sets.push((unique_set, false));
                         }
-                        limbo_sqlite3_parser::ast::ColumnConstraint::NotNull { .. } => {
+                        limbo_sqlite3_parser::ast::ColumnConstraint::NotNull {
+                            conflict_clause,
+                            ..
+                        } => {
                             notnull = true;
+                            if let Some(on_conflict) = conflict_clause {
+                                not_null_on_conflict.push((cols.len(), *on_conflict));
+                            }
                         }
                         limbo_sqlite3_parser::ast::ColumnConstraint::Default(expr) => {
                             default = Some(expr.clone())
                         }
+                        limbo_sqlite3_parser::ast::ColumnConstraint::Collate { collation_name } => {
+                            collation = Some(normalize_ident(&collation_name.0));
+                        }
+                        limbo_sqlite3_parser::ast::ColumnConstraint::ForeignKey {
+                            clause,
+                            deref_clause,
+                        } => {
+                            column_foreign_keys.push(ForeignKey::new(
+                                vec![normalize_ident(&name)],
+                                clause,
+                                deref_clause.as_ref(),
+                            ));
+                        }
+                        limbo_sqlite3_parser::ast::ColumnConstraint::Check(expr) => {
+                            checks.push(CheckConstraint {
+                                name: c_def.name.as_ref().map(|name| normalize_ident(&name.0)),
+                                expr: expr.clone(),
+                                text: expr.to_string(),
+                            });
+                        }
+                        // Like in SQLite, a DEFERRABLE clause applies to the REFERENCES clause
+                        // before it.
+                        limbo_sqlite3_parser::ast::ColumnConstraint::Defer(defer_clause) => {
+                            if let Some(foreign_key) = column_foreign_keys.last_mut() {
+                                foreign_key.set_deferred(defer_clause);
+                            }
+                        }
                         _ => {}
                     }
                 }
 
+                foreign_keys.extend(column_foreign_keys);
                 if primary_key {
-                    primary_key_columns.push((name.clone(), order));
+                    primary_key_columns.push((normalize_ident(&name), order));
                 } else if primary_key_columns
                     .iter()
                     .any(|(col_name, _)| col_name == &name)
@@ -388,6 +1173,7 @@ fn create_table(
                     is_rowid_alias: typename_exactly_integer && primary_key,
                     notnull,
                     default,
+                    collation,
                 });
             }
             if options.contains(TableOptions::WITHOUT_ROWID) {
@@ -403,6 +1189,40 @@ fn create_table(
             col.is_rowid_alias = false;
         }
     }
+    // The PRIMARY KEY columns of a WITHOUT ROWID table are NOT NULL, like in SQLite.
+    if !has_rowid {
+        for col in cols.iter_mut().filter(|col| col.primary_key) {
+            col.notnull = true;
+        }
+    }
+    foreign_keys.extend(table_foreign_keys);
+    checks.extend(table_checks);
+    // The rowid enforces a rowid alias primary key, and a constraint on the same columns
+    // as an earlier one shares its index.
+    let has_rowid_alias = cols.iter().any(|col| col.is_rowid_alias);
+    unique_sets.extend(table_unique_sets);
+    let mut automatic_index_sets: Vec<UniqueSet> = vec![];
+    for (unique_set, is_primary_key) in unique_sets {
+        if is_primary_key && has_rowid_alias {
+            continue;
+        }
+        let existing = automatic_index_sets.iter_mut().find(|existing| {
+            existing.columns.len() == unique_set.columns.len()
+                && existing
+                    .columns
+                    .iter()
+                    .zip(unique_set.columns.iter())
+                    .all(|((a, _), (b, _))| a == b)
+        });
+        match existing {
+            // Like in SQLite, the shared index takes the ON CONFLICT clause of the later
+            // constraint if the earlier one has none.
+            Some(existing) => {
+                existing.on_conflict = existing.on_conflict.or(unique_set.on_conflict);
+            }
+            None => automatic_index_sets.push(unique_set),
+        }
+    }
     Ok(BTreeTable {
         root_page,
         name: table_name,
@@ -410,6 +1230,11 @@ fn create_table(
         primary_key_columns,
         columns: cols,
         is_strict,
+        unique_sets: automatic_index_sets,
+        primary_key_on_conflict,
+        not_null_on_conflict,
+        foreign_keys,
+        checks,
     })
 }
 
@@ -441,6 +1266,8 @@ pub struct Column {
     pub is_rowid_alias: bool,
     pub notnull: bool,
     pub default: Option<Expr>,
+    /// The collating sequence declared with a COLLATE clause, if any.
+    pub collation: Option<String>,
 }
 
 impl Column {
@@ -449,6 +1276,52 @@ impl Column {
     }
 }
 
+/// Returns the value of a literal, possibly signed or parenthesized, or None if `expr` is not
+/// one. The rows stored before a column was added by ALTER TABLE read such a DEFAULT.
+pub fn literal_value(expr: &Expr) -> Option<OwnedValue> {
+    match expr {
+        Expr::Literal(Literal::Numeric(value)) => {
+            if let Some(hex) = value
+                .strip_prefix("0x")
+                .or_else(|| value.strip_prefix("0X"))
+            {
+                i64::from_str_radix(hex, 16).ok().map(OwnedValue::Integer)
+            } else if let Ok(value) = value.parse::<i64>() {
+                Some(OwnedValue::Integer(value))
+            } else {
+                value.parse::<f64>().ok().map(OwnedValue::Float)
+            }
+        }
+        Expr::Literal(Literal::String(value)) => {
+            Some(OwnedValue::build_text(&sanitize_string(value)))
+        }
+        Expr::Literal(Literal::Blob(hex)) => {
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .ok()?;
+            Some(OwnedValue::Blob(bytes))
+        }
+        Expr::Literal(Literal::Null) => Some(OwnedValue::Null),
+        Expr::Unary(UnaryOperator::Negative, expr) => match literal_value(expr)? {
+            OwnedValue::Integer(value) => Some(
+                value
+                    .checked_neg()
+                    .map_or(OwnedValue::Float(-(value as f64)), OwnedValue::Integer),
+            ),
+            OwnedValue::Float(value) => Some(OwnedValue::Float(-value)),
+            _ => None,
+        },
+        Expr::Unary(UnaryOperator::Positive, expr) => match literal_value(expr)? {
+            value @ (OwnedValue::Integer(_) | OwnedValue::Float(_)) => Some(value),
+            _ => None,
+        },
+        Expr::Parenthesized(exprs) if exprs.len() == 1 => literal_value(&exprs[0]),
+        _ => None,
+    }
+}
+
 /// 3.1. Determination Of Column Affinity
 /// For tables not declared as STRICT, the affinity of a column is determined by the declared type of the column, according to the following rules in the order shown:
 ///
@@ -598,6 +1471,10 @@ impl Affinity {
         }
     }
 
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Affinity::Integer | Affinity::Real | Affinity::Numeric)
+    }
+
     pub fn from_char(char: char) -> Result<Self> {
         match char {
             SQLITE_AFF_INTEGER => Ok(Affinity::Integer),
@@ -634,6 +1511,11 @@ pub fn sqlite_schema_table() -> BTreeTable {
         has_rowid: true,
         is_strict: false,
         primary_key_columns: vec![],
+        unique_sets: vec![],
+        primary_key_on_conflict: None,
+        not_null_on_conflict: vec![],
+        foreign_keys: vec![],
+        checks: vec![],
         columns: vec![
             Column {
                 name: Some("type".to_string()),
@@ -643,6 +1525,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 is_rowid_alias: false,
                 notnull: false,
                 default: None,
+                collation: None,
             },
             Column {
                 name: Some("name".to_string()),
@@ -652,6 +1535,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 is_rowid_alias: false,
                 notnull: false,
                 default: None,
+                collation: None,
             },
             Column {
                 name: Some("tbl_name".to_string()),
@@ -661,6 +1545,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 is_rowid_alias: false,
                 notnull: false,
                 default: None,
+                collation: None,
             },
             Column {
                 name: Some("rootpage".to_string()),
@@ -670,6 +1555,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 is_rowid_alias: false,
                 notnull: false,
                 default: None,
+                collation: None,
             },
             Column {
                 name: Some("sql".to_string()),
@@ -679,13 +1565,14 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 is_rowid_alias: false,
                 notnull: false,
                 default: None,
+                collation: None,
             },
         ],
     }
 }
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Index {
     pub name: String,
     pub table_name: String,
@@ -693,6 +1580,13 @@ pub struct Index {
     pub columns: Vec<IndexColumn>,
     pub unique: bool,
     pub ephemeral: bool,
+    /// The WHERE clause of a partial index. Only the rows for which it is true have entries in
+    /// the index.
+    pub where_clause: Option<Box<Expr>>,
+    /// The PRIMARY KEY columns that follow the indexed columns in the entries of an index on a
+    /// WITHOUT ROWID table, see [Index::primary_key_suffix]. Like in SQLite, they keep the sort
+    /// order and the collating sequence that they have in the PRIMARY KEY.
+    pub key_suffix: Vec<IndexColumn>,
 }
 
 #[allow(dead_code)]
@@ -706,6 +1600,12 @@ pub struct IndexColumn {
     /// CREATE INDEX idx ON t(b)
     /// b.pos_in_table == 1
     pub pos_in_table: usize,
+    /// The collating sequence used to compare keys in this column.
+    /// Defaults to the collating sequence of the table column.
+    pub collation: Option<String>,
+    /// The expression of a column of an index on an expression, whose value is the key instead
+    /// of a column of the table. `pos_in_table` is then [usize::MAX].
+    pub expr: Option<Box<Expr>>,
 }
 
 impl Index {
@@ -718,13 +1618,30 @@ impl Index {
                 tbl_name,
                 columns,
                 unique,
+                where_clause,
                 ..
             })) => {
                 let index_name = normalize_ident(&idx_name.name.0);
                 let mut index_columns = Vec::with_capacity(columns.len());
                 for col in columns.into_iter() {
-                    let name = normalize_ident(&col.expr.to_string());
-                    let Some((pos_in_table, _)) = table.get_column(&name) else {
+                    let (expr, collation) = match col.expr {
+                        Expr::Collate(expr, collation) => {
+                            (*expr, Some(normalize_ident(&collation)))
+                        }
+                        expr => (expr, None),
+                    };
+                    if !matches!(expr, Expr::Id(_) | Expr::Name(_)) {
+                        index_columns.push(IndexColumn {
+                            name: expr.to_string(),
+                            order: col.order.unwrap_or(SortOrder::Asc),
+                            pos_in_table: usize::MAX,
+                            collation,
+                            expr: Some(Box::new(expr)),
+                        });
+                        continue;
+                    }
+                    let name = normalize_ident(&expr.to_string());
+                    let Some((pos_in_table, column)) = table.get_column(&name) else {
                         return Err(crate::LimboError::InternalError(format!(
                             "Column {} is in index {} but not found in table {}",
                             name, index_name, table.name
@@ -734,15 +1651,19 @@ impl Index {
                         name,
                         order: col.order.unwrap_or(SortOrder::Asc),
                         pos_in_table,
+                        collation: collation.or_else(|| column.collation.clone()),
+                        expr: None,
                     });
                 }
                 Ok(Index {
                     name: index_name,
                     table_name: normalize_ident(&tbl_name.0),
                     root_page,
+                    key_suffix: Self::key_suffix_of(table, &index_columns),
                     columns: index_columns,
                     unique,
                     ephemeral: false,
+                    where_clause,
                 })
             }
             _ => todo!("Expected create index statement"),
@@ -759,13 +1680,21 @@ impl Index {
                 "Cannot create automatic index for table without primary key".to_string(),
             ));
         }
+        Self::automatic_from_unique(table, index_name, root_page, &table.primary_key_columns)
+    }
 
-        let index_columns = table
-            .primary_key_columns
+    /// Builds the automatic index that enforces a PRIMARY KEY or UNIQUE constraint on `columns`.
+    pub fn automatic_from_unique(
+        table: &BTreeTable,
+        index_name: &str,
+        root_page: usize,
+        columns: &[(String, SortOrder)],
+    ) -> Result<Index> {
+        let index_columns = columns
             .iter()
             .map(|(col_name, order)| {
                 // Verify that each primary key column exists in the table
-                let Some((pos_in_table, _)) = table.get_column(col_name) else {
+                let Some((pos_in_table, column)) = table.get_column(col_name) else {
                     return Err(crate::LimboError::InternalError(format!(
                         "Column {} is in index {} but not found in table {}",
                         col_name, index_name, table.name
@@ -775,6 +1704,8 @@ impl Index {
                     name: normalize_ident(col_name),
                     order: order.clone(),
                     pos_in_table,
+                    collation: column.collation.clone(),
+                    expr: None,
                 })
             })
             .collect::<Result<Vec<_>>>()?;
@@ -783,12 +1714,70 @@ impl Index {
             name: normalize_ident(index_name),
             table_name: table.name.clone(),
             root_page,
+            key_suffix: Self::key_suffix_of(table, &index_columns),
             columns: index_columns,
-            unique: true, // Primary key indexes are always unique
+            unique: true,
             ephemeral: false,
+            where_clause: None,
         })
     }
 
+    /// Returns the [Index::key_suffix] of an index on `table` with the given columns.
+    pub fn key_suffix_of(table: &BTreeTable, columns: &[IndexColumn]) -> Vec<IndexColumn> {
+        if table.has_rowid {
+            return Vec::new();
+        }
+        table
+            .primary_key_columns
+            .iter()
+            .filter_map(|(name, order)| {
+                let (pos_in_table, column) = table.get_column(name)?;
+                let indexed = columns
+                    .iter()
+                    .any(|c| c.expr.is_none() && c.pos_in_table == pos_in_table);
+                (!indexed).then(|| IndexColumn {
+                    name: normalize_ident(name),
+                    order: *order,
+                    pos_in_table,
+                    collation: column.collation.clone(),
+                    expr: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the columns that the keys of the entries are compared on: the indexed columns,
+    /// followed by the [Index::key_suffix].
+    pub fn key_columns(&self) -> impl Iterator<Item = &IndexColumn> {
+        self.columns.iter().chain(self.key_suffix.iter())
+    }
+
+    /// Returns the positions in `table`, a WITHOUT ROWID table, of the PRIMARY KEY columns that
+    /// are not in the index. They follow the indexed columns in the entries of the index, in the
+    /// place of the rowid.
+    pub fn primary_key_suffix(&self, table: &BTreeTable) -> Vec<usize> {
+        table
+            .primary_key_column_positions()
+            .into_iter()
+            .filter(|pos| self.column_table_pos_to_index_pos(*pos).is_none())
+            .collect()
+    }
+
+    /// Returns the positions of the PRIMARY KEY columns of `table`, a WITHOUT ROWID table, in the
+    /// entries of the index, see [Index::primary_key_suffix].
+    pub fn primary_key_entry_positions(&self, table: &BTreeTable) -> Vec<usize> {
+        let suffix = self.primary_key_suffix(table);
+        table
+            .primary_key_column_positions()
+            .into_iter()
+            .map(|pos| {
+                self.column_table_pos_to_index_pos(pos).unwrap_or_else(|| {
+                    self.columns.len() + suffix.iter().position(|p| *p == pos).unwrap()
+                })
+            })
+            .collect()
+    }
+
     /// Given a column position in the table, return the position in the index.
     /// Returns None if the column is not found in the index.
     /// For example, given:
@@ -798,7 +1787,39 @@ impl Index {
     pub fn column_table_pos_to_index_pos(&self, table_pos: usize) -> Option<usize> {
         self.columns
             .iter()
-            .position(|c| c.pos_in_table == table_pos)
+            .position(|c| c.expr.is_none() && c.pos_in_table == table_pos)
+    }
+
+    /// Returns true if some of the columns of the index are expressions.
+    pub fn has_expressions(&self) -> bool {
+        self.columns.iter().any(|c| c.expr.is_some())
+    }
+
+    /// Returns true if the entry of a row in the index, or whether the row has one, depends on
+    /// the column at `pos` in `table`.
+    pub fn depends_on_column(&self, table: &BTreeTable, pos: usize) -> bool {
+        let refers_to_column = |expr: &Expr| {
+            table.columns[pos]
+                .name
+                .as_deref()
+                .is_some_and(|name| expr_refers_to_column(expr, name))
+        };
+        self.key_columns().any(|c| match &c.expr {
+            Some(expr) => refers_to_column(expr),
+            None => c.pos_in_table == pos,
+        }) || self.where_clause.as_deref().is_some_and(refers_to_column)
+    }
+}
+
+/// Returns true if the expression, which belongs to the schema of a single table, refers to the
+/// column `name` of the table.
+fn expr_refers_to_column(expr: &Expr, name: &str) -> bool {
+    match expr {
+        Expr::Id(id) => normalize_ident(&id.0) == normalize_ident(name),
+        Expr::Name(id) | Expr::Qualified(_, id) => normalize_ident(&id.0) == normalize_ident(name),
+        expr => child_exprs(expr)
+            .into_iter()
+            .any(|child| expr_refers_to_column(child, name)),
     }
 }
 
@@ -1165,6 +2186,11 @@ mod tests {
             has_rowid: true,
             is_strict: false,
             primary_key_columns: vec![("nonexistent".to_string(), SortOrder::Asc)],
+            unique_sets: vec![],
+            primary_key_on_conflict: None,
+            not_null_on_conflict: vec![],
+            foreign_keys: vec![],
+            checks: vec![],
             columns: vec![Column {
                 name: Some("a".to_string()),
                 ty: Type::Integer,
@@ -1173,6 +2199,7 @@ mod tests {
                 is_rowid_alias: false,
                 notnull: false,
                 default: None,
+                collation: None,
             }],
         };
 
@@ -0,0 +1,602 @@
+//! Auto-vacuum support.
+//!
+//! An auto-vacuum database keeps track of the page that points to each of its pages in pointer
+//! map pages, so that a page can be moved to another location by updating the pointer to it.
+//! This is what allows it to give back its free pages to the file system: the pages at the end
+//! of the file are moved into the free pages before them, and the file is truncated. In FULL
+//! mode this happens when a transaction commits, in INCREMENTAL mode when
+//! `PRAGMA incremental_vacuum` runs.
+//!
+//! The first pointer map page is page 2, and it has an entry of 5 bytes for each of the pages
+//! that follow it, up to the next pointer map page: a 1 byte type and the 4 byte number of the
+//! parent page. See https://www.sqlite.org/fileformat.html#pointer_map_or_ptrmap_pages.
+//!
+//! The root pages of the b-trees are kept at the start of the file, right after page 1 and the
+//! first pointer map page, so that they are never moved when the file is truncated. The header
+//! field that holds the largest of them is also what tells that auto-vacuum is enabled.
+
+use crate::storage::btree::{payload_overflow_threshold_max, payload_overflow_threshold_min};
+use crate::storage::pager::{PageRef, Pager};
+use crate::storage::sqlite3_ondisk::{read_u32, BTreeCell, DatabaseHeader};
+use crate::{LimboError, Result};
+use tracing::trace;
+
+/// Size of an entry of a pointer map page.
+const PTRMAP_ENTRY_SIZE: usize = 5;
+
+/// The kind of page an entry of the pointer map is for, which tells what its parent is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PtrmapType {
+    /// The root page of a b-tree. It has no parent.
+    RootPage = 1,
+    /// A page of the freelist. It has no parent.
+    FreePage = 2,
+    /// The first overflow page of a cell, whose parent is the b-tree page of the cell.
+    Overflow1 = 3,
+    /// An overflow page after the first one, whose parent is the previous overflow page.
+    Overflow2 = 4,
+    /// A b-tree page other than a root page, whose parent is its parent b-tree page.
+    BTree = 5,
+}
+
+impl TryFrom<u8> for PtrmapType {
+    type Error = LimboError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(Self::RootPage),
+            2 => Ok(Self::FreePage),
+            3 => Ok(Self::Overflow1),
+            4 => Ok(Self::Overflow2),
+            5 => Ok(Self::BTree),
+            _ => Err(LimboError::Corrupt(format!(
+                "Invalid pointer map entry type {}",
+                value
+            ))),
+        }
+    }
+}
+
+/// The auto-vacuum mode of a database, as set with `PRAGMA auto_vacuum`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoVacuumMode {
+    None = 0,
+    Full = 1,
+    Incremental = 2,
+}
+
+impl AutoVacuumMode {
+    pub fn from_header(header: &DatabaseHeader) -> Self {
+        if header.vacuum_mode_largest_root_page == 0 {
+            Self::None
+        } else if header.incremental_vacuum_enabled != 0 {
+            Self::Incremental
+        } else {
+            Self::Full
+        }
+    }
+
+    /// Parses the value of `PRAGMA auto_vacuum`, a name or a number. Like in SQLite, anything
+    /// else means NONE.
+    pub fn from_pragma_value(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "full" | "1" => Self::Full,
+            "incremental" | "2" => Self::Incremental,
+            _ => Self::None,
+        }
+    }
+}
+
+/// Returns the pointer map page that has the entry of `page_id`, or `page_id` itself if it is a
+/// pointer map page.
+pub fn ptrmap_page_id(page_id: usize, usable_space: usize) -> usize {
+    let pages_per_map_page = usable_space / PTRMAP_ENTRY_SIZE + 1;
+    (page_id - 2) / pages_per_map_page * pages_per_map_page + 2
+}
+
+pub fn is_ptrmap_page(page_id: usize, usable_space: usize) -> bool {
+    page_id >= 2 && ptrmap_page_id(page_id, usable_space) == page_id
+}
+
+/// Returns the number of pages a database of `db_size` pages, `free_pages` of which are free,
+/// has once its free pages, and the pointer map pages that are no longer needed, are gone.
+fn final_db_size(db_size: usize, free_pages: usize, usable_space: usize) -> usize {
+    let entries = (usable_space / PTRMAP_ENTRY_SIZE) as i64;
+    let ptrmap_pages = (free_pages as i64 - db_size as i64
+        + ptrmap_page_id(db_size, usable_space) as i64
+        + entries)
+        / entries;
+    let mut final_size = db_size - ptrmap_pages as usize - free_pages;
+    while is_ptrmap_page(final_size, usable_space) {
+        final_size -= 1;
+    }
+    final_size
+}
+
+impl Pager {
+    pub fn auto_vacuum_mode(&self) -> AutoVacuumMode {
+        AutoVacuumMode::from_header(&self.db_header.lock())
+    }
+
+    /// Returns the pointer map page and the offset in it of the entry of `page_id`.
+    fn ptrmap_entry_location(&self, page_id: usize) -> Result<(usize, usize)> {
+        let usable_space = self.usable_space();
+        let ptrmap_page = ptrmap_page_id(page_id, usable_space);
+        if page_id < 3 || ptrmap_page == page_id {
+            return Err(LimboError::Corrupt(format!(
+                "Page {} has no pointer map entry",
+                page_id
+            )));
+        }
+        Ok((ptrmap_page, PTRMAP_ENTRY_SIZE * (page_id - ptrmap_page - 1)))
+    }
+
+    /// Reads the pointer map entry of `page_id`: its type and its parent page.
+    pub fn ptrmap_get(&self, page_id: usize) -> Result<(PtrmapType, usize)> {
+        let (ptrmap_page, offset) = self.ptrmap_entry_location(page_id)?;
+        let page = self.read_page_sync(ptrmap_page)?;
+        let buf = page.get_contents().as_ptr();
+        let ptrmap_type = PtrmapType::try_from(buf[offset])?;
+        Ok((ptrmap_type, read_u32(buf, offset + 1) as usize))
+    }
+
+    /// Sets the pointer map entry of `page_id`. Does nothing if auto-vacuum is disabled.
+    pub fn ptrmap_put(&self, page_id: usize, ptrmap_type: PtrmapType, parent: usize) -> Result<()> {
+        if self.auto_vacuum_mode() == AutoVacuumMode::None {
+            return Ok(());
+        }
+        trace!(
+            "ptrmap_put(page={}, type={:?}, parent={})",
+            page_id,
+            ptrmap_type,
+            parent
+        );
+        let (ptrmap_page, offset) = self.ptrmap_entry_location(page_id)?;
+        let page = self.read_page_sync(ptrmap_page)?;
+        let buf = page.get_contents().as_ptr();
+        if buf[offset] == ptrmap_type as u8 && read_u32(buf, offset + 1) == parent as u32 {
+            return Ok(());
+        }
+        page.set_dirty();
+        self.add_dirty(ptrmap_page);
+        buf[offset] = ptrmap_type as u8;
+        buf[offset + 1..offset + PTRMAP_ENTRY_SIZE].copy_from_slice(&(parent as u32).to_be_bytes());
+        Ok(())
+    }
+
+    /// Points the pointer map entries of the children and of the first overflow pages of the
+    /// cells of a b-tree page to it.
+    pub fn ptrmap_put_children(&self, page: &PageRef) -> Result<()> {
+        if self.auto_vacuum_mode() == AutoVacuumMode::None {
+            return Ok(());
+        }
+        let page_id = page.get().id;
+        let contents = page.get_contents();
+        let usable_space = self.usable_space() as u16;
+        let page_type = contents.page_type();
+        for cell_idx in 0..contents.cell_count() {
+            let cell = contents.cell_get(
+                cell_idx,
+                payload_overflow_threshold_max(page_type, usable_space),
+                payload_overflow_threshold_min(page_type, usable_space),
+                usable_space as usize,
+            )?;
+            self.ptrmap_put_cell(page_id, &cell)?;
+        }
+        if let Some(rightmost_pointer) = contents.rightmost_pointer() {
+            self.ptrmap_put(rightmost_pointer as usize, PtrmapType::BTree, page_id)?;
+        }
+        Ok(())
+    }
+
+    /// Points the pointer map entries of the child and of the first overflow page of `cell`,
+    /// a cell of page `page_id`, to it.
+    pub fn ptrmap_put_cell(&self, page_id: usize, cell: &BTreeCell) -> Result<()> {
+        let (left_child_page, first_overflow_page) = match cell {
+            BTreeCell::TableInteriorCell(cell) => (Some(cell._left_child_page), None),
+            BTreeCell::IndexInteriorCell(cell) => {
+                (Some(cell.left_child_page), cell.first_overflow_page)
+            }
+            BTreeCell::TableLeafCell(cell) => (None, cell.first_overflow_page),
+            BTreeCell::IndexLeafCell(cell) => (None, cell.first_overflow_page),
+        };
+        if let Some(left_child_page) = left_child_page {
+            self.ptrmap_put(left_child_page as usize, PtrmapType::BTree, page_id)?;
+        }
+        if let Some(first_overflow_page) = first_overflow_page {
+            self.ptrmap_put(first_overflow_page as usize, PtrmapType::Overflow1, page_id)?;
+        }
+        Ok(())
+    }
+
+    /// Moves page `from`, whose pointer map entry is `ptrmap_type` and `parent`, to page `to`,
+    /// and updates the pointers to it and the pointer map entries of the pages it points to.
+    /// Page `to` must be unused, and page `from` is left zeroed.
+    fn relocate_page(
+        &self,
+        from: usize,
+        ptrmap_type: PtrmapType,
+        parent: usize,
+        to: usize,
+    ) -> Result<()> {
+        trace!(
+            "relocate_page(from={}, type={:?}, parent={}, to={})",
+            from,
+            ptrmap_type,
+            parent,
+            to
+        );
+        // The page keeps its contents, and whoever holds it, and only changes its number.
+        let page = self.read_page_sync(from)?;
+        page.get().id = to;
+        page.set_dirty();
+        self.add_dirty(to);
+        self.put_loaded_page(to, page.clone());
+        let empty_page = self.allocate_empty_page(from);
+        self.put_loaded_page(from, empty_page);
+
+        match ptrmap_type {
+            PtrmapType::BTree | PtrmapType::RootPage => self.ptrmap_put_children(&page)?,
+            PtrmapType::Overflow1 | PtrmapType::Overflow2 => {
+                let next_overflow_page = read_u32(page.get_contents().as_ptr(), 0);
+                if next_overflow_page != 0 {
+                    self.ptrmap_put(next_overflow_page as usize, PtrmapType::Overflow2, to)?;
+                }
+            }
+            PtrmapType::FreePage => {
+                return Err(LimboError::Corrupt(format!(
+                    "Free page {} can't be moved",
+                    from
+                )))
+            }
+        }
+        if ptrmap_type != PtrmapType::RootPage {
+            self.modify_page_pointer(parent, from, to, ptrmap_type)?;
+        }
+        self.ptrmap_put(to, ptrmap_type, parent)
+    }
+
+    /// Replaces the pointer to page `from` in page `page_id` with a pointer to page `to`.
+    fn modify_page_pointer(
+        &self,
+        page_id: usize,
+        from: usize,
+        to: usize,
+        ptrmap_type: PtrmapType,
+    ) -> Result<()> {
+        let page = self.read_page_sync(page_id)?;
+        page.set_dirty();
+        self.add_dirty(page_id);
+        let contents = page.get_contents();
+        let buf = contents.as_ptr();
+        if ptrmap_type == PtrmapType::Overflow2 {
+            if read_u32(buf, 0) != from as u32 {
+                return Err(LimboError::Corrupt(format!(
+                    "Overflow page {} does not point to page {}",
+                    page_id, from
+                )));
+            }
+            buf[0..4].copy_from_slice(&(to as u32).to_be_bytes());
+            return Ok(());
+        }
+        let usable_space = self.usable_space() as u16;
+        let page_type = contents.page_type();
+        for cell_idx in 0..contents.cell_count() {
+            let (cell_start, cell_len) = contents.cell_get_raw_region(
+                cell_idx,
+                payload_overflow_threshold_max(page_type, usable_space),
+                payload_overflow_threshold_min(page_type, usable_space),
+                usable_space as usize,
+            );
+            let pointer_offset = match ptrmap_type {
+                PtrmapType::Overflow1 => {
+                    let cell = contents.cell_get(
+                        cell_idx,
+                        payload_overflow_threshold_max(page_type, usable_space),
+                        payload_overflow_threshold_min(page_type, usable_space),
+                        usable_space as usize,
+                    )?;
+                    let has_overflow = match cell {
+                        BTreeCell::TableInteriorCell(_) => false,
+                        BTreeCell::IndexInteriorCell(cell) => cell.first_overflow_page.is_some(),
+                        BTreeCell::TableLeafCell(cell) => cell.first_overflow_page.is_some(),
+                        BTreeCell::IndexLeafCell(cell) => cell.first_overflow_page.is_some(),
+                    };
+                    if !has_overflow {
+                        continue;
+                    }
+                    cell_start + cell_len - 4
+                }
+                _ => cell_start,
+            };
+            if read_u32(buf, pointer_offset) == from as u32 {
+                buf[pointer_offset..pointer_offset + 4].copy_from_slice(&(to as u32).to_be_bytes());
+                return Ok(());
+            }
+        }
+        match contents.rightmost_pointer() {
+            Some(rightmost_pointer)
+                if ptrmap_type == PtrmapType::BTree && rightmost_pointer == from as u32 =>
+            {
+                contents.write_u32(8, to as u32);
+                Ok(())
+            }
+            _ => Err(LimboError::Corrupt(format!(
+                "Page {} does not point to page {}",
+                page_id, from
+            ))),
+        }
+    }
+
+    /// Removes page `page_id` from the freelist. Returns false if it is not free.
+    fn freelist_remove(&self, page_id: usize) -> Result<bool> {
+        let mut previous_trunk: Option<PageRef> = None;
+        let mut trunk_id = self.db_header.lock().freelist_trunk_page as usize;
+        while trunk_id != 0 {
+            let trunk = self.read_page_sync(trunk_id)?;
+            let buf = trunk.get_contents().as_ptr();
+            let next_trunk = read_u32(buf, 0);
+            let leaf_count = read_u32(buf, 4) as usize;
+            if trunk_id == page_id {
+                if leaf_count > 0 {
+                    // The last leaf takes the place of the trunk.
+                    let new_trunk_id = read_u32(buf, 8 + 4 * (leaf_count - 1)) as usize;
+                    let new_trunk = self.read_page_sync(new_trunk_id)?;
+                    new_trunk.set_dirty();
+                    self.add_dirty(new_trunk_id);
+                    let new_buf = new_trunk.get_contents().as_ptr();
+                    new_buf[0..4].copy_from_slice(&next_trunk.to_be_bytes());
+                    new_buf[4..8].copy_from_slice(&(leaf_count as u32 - 1).to_be_bytes());
+                    new_buf[8..8 + 4 * (leaf_count - 1)]
+                        .copy_from_slice(&buf[8..8 + 4 * (leaf_count - 1)]);
+                    self.freelist_link(previous_trunk, new_trunk_id as u32)?;
+                } else {
+                    self.freelist_link(previous_trunk, next_trunk)?;
+                }
+                self.db_header.lock().freelist_pages -= 1;
+                return Ok(true);
+            }
+            for leaf_idx in 0..leaf_count {
+                if read_u32(buf, 8 + 4 * leaf_idx) as usize != page_id {
+                    continue;
+                }
+                trunk.set_dirty();
+                self.add_dirty(trunk_id);
+                let last_leaf = 8 + 4 * (leaf_count - 1);
+                buf.copy_within(last_leaf..last_leaf + 4, 8 + 4 * leaf_idx);
+                buf[4..8].copy_from_slice(&(leaf_count as u32 - 1).to_be_bytes());
+                self.db_header.lock().freelist_pages -= 1;
+                return Ok(true);
+            }
+            previous_trunk = Some(trunk);
+            trunk_id = next_trunk as usize;
+        }
+        Ok(false)
+    }
+
+    /// Makes the freelist trunk `previous_trunk`, or the header if it is the first one, point to
+    /// trunk `trunk_id`.
+    fn freelist_link(&self, previous_trunk: Option<PageRef>, trunk_id: u32) -> Result<()> {
+        match previous_trunk {
+            Some(previous_trunk) => {
+                previous_trunk.set_dirty();
+                self.add_dirty(previous_trunk.get().id);
+                previous_trunk.get_contents().as_ptr()[0..4]
+                    .copy_from_slice(&trunk_id.to_be_bytes());
+            }
+            None => self.db_header.lock().freelist_trunk_page = trunk_id,
+        }
+        Ok(())
+    }
+
+    /// Takes a free page numbered `max_page_id` or less off the freelist.
+    fn freelist_take(&self, max_page_id: usize) -> Result<Option<usize>> {
+        let mut trunk_id = self.db_header.lock().freelist_trunk_page as usize;
+        let mut found = None;
+        while trunk_id != 0 && found.is_none() {
+            let trunk = self.read_page_sync(trunk_id)?;
+            let buf = trunk.get_contents().as_ptr();
+            let leaf_count = read_u32(buf, 4) as usize;
+            found = (0..leaf_count)
+                .map(|leaf_idx| read_u32(buf, 8 + 4 * leaf_idx) as usize)
+                .find(|leaf_id| *leaf_id <= max_page_id)
+                .or((trunk_id <= max_page_id).then_some(trunk_id));
+            trunk_id = read_u32(buf, 0) as usize;
+        }
+        match found {
+            Some(page_id) => {
+                self.freelist_remove(page_id)?;
+                Ok(Some(page_id))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Makes a page available to be the root page of a new b-tree, right after the current
+    /// largest root page, and returns its number.
+    pub(crate) fn auto_vacuum_create_root(&self) -> Result<usize> {
+        let usable_space = self.usable_space();
+        let (largest_root, db_size) = {
+            let header = self.db_header.lock();
+            (
+                header.vacuum_mode_largest_root_page as usize,
+                header.database_size as usize,
+            )
+        };
+        let mut root = largest_root + 1;
+        while is_ptrmap_page(root, usable_space) {
+            root += 1;
+        }
+        if root > db_size {
+            let page = self.allocate_page()?;
+            assert_eq!(
+                page.get().id,
+                root,
+                "the new root page is not the next page"
+            );
+        } else if !self.freelist_remove(root)? {
+            let (ptrmap_type, parent) = self.ptrmap_get(root)?;
+            if matches!(ptrmap_type, PtrmapType::RootPage | PtrmapType::FreePage) {
+                return Err(LimboError::Corrupt(format!(
+                    "Unexpected pointer map entry {:?} of page {}",
+                    ptrmap_type, root
+                )));
+            }
+            let to = self.allocate_page()?.get().id;
+            self.relocate_page(root, ptrmap_type, parent, to)?;
+        }
+        self.ptrmap_put(root, PtrmapType::RootPage, 0)?;
+        let mut header = self.db_header.lock();
+        header.vacuum_mode_largest_root_page = root as u32;
+        self.write_header_page(&header)?;
+        Ok(root)
+    }
+
+    /// Frees root page `root` of a b-tree that has no other pages left. To keep the root pages
+    /// together at the start of the file, the largest root page is moved into it. Returns the
+    /// page the root page was moved from, or 0 if none was.
+    pub(crate) fn drop_root_page(&self, root: usize) -> Result<usize> {
+        let usable_space = self.usable_space();
+        let largest_root = self.db_header.lock().vacuum_mode_largest_root_page as usize;
+        let moved_from = if root == largest_root {
+            self.free_page(None, root)?;
+            0
+        } else {
+            self.relocate_page(largest_root, PtrmapType::RootPage, 0, root)?;
+            self.free_page(None, largest_root)?;
+            largest_root
+        };
+        let mut new_largest_root = largest_root - 1;
+        while is_ptrmap_page(new_largest_root, usable_space) {
+            new_largest_root -= 1;
+        }
+        let mut header = self.db_header.lock();
+        header.vacuum_mode_largest_root_page = new_largest_root as u32;
+        self.write_header_page(&header)?;
+        Ok(moved_from)
+    }
+
+    /// Gives back the last page of the database, moving it into a free page if it is in use.
+    /// Returns false if there is no free page left to give back.
+    pub(crate) fn incremental_vacuum_step(&self) -> Result<bool> {
+        let usable_space = self.usable_space();
+        let (db_size, free_pages) = {
+            let header = self.db_header.lock();
+            (
+                header.database_size as usize,
+                header.freelist_pages as usize,
+            )
+        };
+        if free_pages == 0 {
+            return Ok(false);
+        }
+        if free_pages >= db_size {
+            return Err(LimboError::Corrupt(format!(
+                "{} free pages in a database of {} pages",
+                free_pages, db_size
+            )));
+        }
+        let final_size = final_db_size(db_size, free_pages, usable_space);
+        self.vacuum_last_page(db_size, final_size, false)?;
+        let mut new_size = db_size - 1;
+        while is_ptrmap_page(new_size, usable_space) {
+            new_size -= 1;
+        }
+        let mut header = self.db_header.lock();
+        header.database_size = new_size as u32;
+        self.write_header_page(&header)?;
+        drop(header);
+        self.discard_pages_after(new_size);
+        Ok(true)
+    }
+
+    /// Gives back all the free pages of a FULL auto-vacuum database at the end of the write
+    /// transaction.
+    pub(crate) fn auto_vacuum_commit(&self) -> Result<()> {
+        let usable_space = self.usable_space();
+        let (db_size, free_pages) = {
+            let header = self.db_header.lock();
+            (
+                header.database_size as usize,
+                header.freelist_pages as usize,
+            )
+        };
+        if free_pages == 0 {
+            return Ok(());
+        }
+        if is_ptrmap_page(db_size, usable_space) || free_pages >= db_size {
+            return Err(LimboError::Corrupt(format!(
+                "{} free pages in a database of {} pages",
+                free_pages, db_size
+            )));
+        }
+        let final_size = final_db_size(db_size, free_pages, usable_space);
+        trace!(
+            "auto_vacuum_commit(size={}, final_size={})",
+            db_size,
+            final_size
+        );
+        for last_page in (final_size + 1..=db_size).rev() {
+            self.vacuum_last_page(last_page, final_size, true)?;
+        }
+        // The free pages that are left are all past the end of the database.
+        let mut header = self.db_header.lock();
+        header.database_size = final_size as u32;
+        header.freelist_trunk_page = 0;
+        header.freelist_pages = 0;
+        self.write_header_page(&header)?;
+        drop(header);
+        self.discard_pages_after(final_size);
+        Ok(())
+    }
+
+    /// Frees up page `last_page` by moving it into a free page numbered `final_size` or less.
+    /// When the whole freelist is dropped at commit there is nothing to do for a free page,
+    /// otherwise it is taken off the freelist.
+    fn vacuum_last_page(&self, last_page: usize, final_size: usize, commit: bool) -> Result<()> {
+        if is_ptrmap_page(last_page, self.usable_space()) {
+            return Ok(());
+        }
+        let (ptrmap_type, parent) = self.ptrmap_get(last_page)?;
+        match ptrmap_type {
+            PtrmapType::RootPage => Err(LimboError::Corrupt(format!(
+                "Root page {} is past the end of the vacuumed database",
+                last_page
+            ))),
+            PtrmapType::FreePage if commit => Ok(()),
+            PtrmapType::FreePage => {
+                if !self.freelist_remove(last_page)? {
+                    return Err(LimboError::Corrupt(format!(
+                        "Page {} is not in the freelist",
+                        last_page
+                    )));
+                }
+                Ok(())
+            }
+            _ => {
+                let Some(free_page) = self.freelist_take(final_size)? else {
+                    return Err(LimboError::Corrupt(
+                        "No free page to move a page into".to_string(),
+                    ));
+                };
+                self.relocate_page(last_page, ptrmap_type, parent, free_page)
+            }
+        }
+    }
+
+    /// Returns a zeroed page that is not in the page cache yet.
+    fn allocate_empty_page(&self, page_id: usize) -> PageRef {
+        let page = self.allocate_page_buffer(page_id);
+        page.get_contents().as_ptr().fill(0);
+        page.set_dirty();
+        self.add_dirty(page_id);
+        page
+    }
+
+    /// Zeroes a new pointer map page and adds it to the page cache.
+    pub(crate) fn init_ptrmap_page(&self, page_id: usize) {
+        let page = self.allocate_empty_page(page_id);
+        self.put_loaded_page(page_id, page);
+    }
+}
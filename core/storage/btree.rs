@@ -1,6 +1,8 @@
 use crate::{
+    collation::CollationSeq,
     schema::Index,
     storage::{
+        auto_vacuum::{AutoVacuumMode, PtrmapType},
         pager::Pager,
         sqlite3_ondisk::{
             read_u32, read_varint, BTreeCell, PageContent, PageType, TableInteriorCell,
@@ -9,6 +11,7 @@ use crate::{
     },
     translate::plan::IterationDirection,
     types::IndexKeySortOrder,
+    vdbe::Register,
     MvCursor,
 };
 
@@ -143,6 +146,8 @@ enum DestroyState {
 
 struct DestroyInfo {
     state: DestroyState,
+    /// Whether the root page is kept as an empty leaf instead of being freed.
+    keep_root: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -163,6 +168,8 @@ enum DeleteState {
     },
     InteriorNodeReplacement {
         cell_idx: usize,
+        /// The depth in the page stack of the interior page holding the cell.
+        cell_depth: usize,
         original_child_pointer: Option<u32>,
     },
     DropCell {
@@ -186,6 +193,9 @@ enum DeleteState {
 struct DeleteInfo {
     state: DeleteState,
     balance_write_info: Option<WriteInfo>,
+    /// The depth of the interior page whose cell was replaced by a cell of a leaf, which is
+    /// balanced once the leaf is.
+    interior_cell_depth: Option<usize>,
 }
 
 /// State machine of a write operation.
@@ -373,6 +383,8 @@ pub struct BTreeCursor {
     reusable_immutable_record: RefCell<Option<ImmutableRecord>>,
     empty_record: Cell<bool>,
     pub index_key_sort_order: IndexKeySortOrder,
+    /// The collating sequences used to compare the columns of index keys.
+    pub index_key_collations: Vec<CollationSeq>,
 }
 
 impl BTreeCursor {
@@ -398,6 +410,7 @@ impl BTreeCursor {
             reusable_immutable_record: RefCell::new(None),
             empty_record: Cell::new(true),
             index_key_sort_order: IndexKeySortOrder::default(),
+            index_key_collations: Vec::new(),
         }
     }
 
@@ -406,13 +419,28 @@ impl BTreeCursor {
         pager: Rc<Pager>,
         root_page: usize,
         index: &Index,
+        collations: Vec<CollationSeq>,
     ) -> Self {
         let index_key_sort_order = IndexKeySortOrder::from_index(index);
         let mut cursor = Self::new(mv_cursor, pager, root_page);
         cursor.index_key_sort_order = index_key_sort_order;
+        cursor.index_key_collations = collations;
         cursor
     }
 
+    /// Compares the values of an index entry with a key, which may have fewer columns. The
+    /// entries of a WITHOUT ROWID table may have fewer columns than the key too, when they were
+    /// written before ALTER TABLE ADD COLUMN.
+    fn compare_index_entry(&self, entry: &[RefValue], key: &[RefValue]) -> Ordering {
+        let len = entry.len().min(key.len());
+        compare_immutable(
+            &entry[..len],
+            &key[..len],
+            self.index_key_sort_order,
+            &self.index_key_collations,
+        )
+    }
+
     /// Check if the table is empty.
     /// This is done by checking if the root page has no cells.
     fn is_empty_table(&self) -> Result<CursorResult<bool>> {
@@ -551,11 +579,8 @@ impl BTreeCursor {
                     // We then mark going_upwards=false so that we go back down the tree on the next invocation.
                     self.going_upwards = false;
                     if predicate.is_none() {
-                        let rowid = match self.get_immutable_record().as_ref().unwrap().last_value()
-                        {
-                            Some(RefValue::Integer(rowid)) => *rowid as u64,
-                            _ => unreachable!("index cells should have an integer rowid"),
-                        };
+                        let rowid =
+                            index_entry_rowid(self.get_immutable_record().as_ref().unwrap());
                         return Ok(CursorResult::Ok(Some(rowid)));
                     }
 
@@ -566,15 +591,7 @@ impl BTreeCursor {
                     let order = {
                         let record = self.get_immutable_record();
                         let record = record.as_ref().unwrap();
-                        let record_values = record.get_values();
-                        let record_slice_same_num_cols =
-                            &record_values[..index_key.get_values().len()];
-                        let order = compare_immutable(
-                            record_slice_same_num_cols,
-                            index_key.get_values(),
-                            self.index_key_sort_order,
-                        );
-                        order
+                        self.compare_index_entry(record.get_values(), index_key.get_values())
                     };
 
                     let found = match op {
@@ -584,11 +601,8 @@ impl BTreeCursor {
                         _ => unreachable!("Seek GT/GE should not happen in get_prev_record() because we are iterating backwards"),
                     };
                     if found {
-                        let rowid = match self.get_immutable_record().as_ref().unwrap().last_value()
-                        {
-                            Some(RefValue::Integer(rowid)) => *rowid as u64,
-                            _ => unreachable!("index cells should have an integer rowid"),
-                        };
+                        let rowid =
+                            index_entry_rowid(self.get_immutable_record().as_ref().unwrap());
                         return Ok(CursorResult::Ok(Some(rowid)));
                     } else {
                         continue;
@@ -610,11 +624,8 @@ impl BTreeCursor {
 
                     self.stack.retreat();
                     if predicate.is_none() {
-                        let rowid = match self.get_immutable_record().as_ref().unwrap().last_value()
-                        {
-                            Some(RefValue::Integer(rowid)) => *rowid as u64,
-                            _ => unreachable!("index cells should have an integer rowid"),
-                        };
+                        let rowid =
+                            index_entry_rowid(self.get_immutable_record().as_ref().unwrap());
                         return Ok(CursorResult::Ok(Some(rowid)));
                     }
                     let (key, op) = predicate.as_ref().unwrap();
@@ -624,15 +635,7 @@ impl BTreeCursor {
                     let order = {
                         let record = self.get_immutable_record();
                         let record = record.as_ref().unwrap();
-                        let record_values = record.get_values();
-                        let record_slice_same_num_cols =
-                            &record_values[..index_key.get_values().len()];
-                        let order = compare_immutable(
-                            record_slice_same_num_cols,
-                            index_key.get_values(),
-                            self.index_key_sort_order,
-                        );
-                        order
+                        self.compare_index_entry(record.get_values(), index_key.get_values())
                     };
                     let found = match op {
                         SeekOp::EQ => order.is_eq(),
@@ -641,11 +644,8 @@ impl BTreeCursor {
                         _ => unreachable!("Seek GT/GE should not happen in get_prev_record() because we are iterating backwards"),
                     };
                     if found {
-                        let rowid = match self.get_immutable_record().as_ref().unwrap().last_value()
-                        {
-                            Some(RefValue::Integer(rowid)) => *rowid as u64,
-                            _ => unreachable!("index cells should have an integer rowid"),
-                        };
+                        let rowid =
+                            index_entry_rowid(self.get_immutable_record().as_ref().unwrap());
                         return Ok(CursorResult::Ok(Some(rowid)));
                     } else {
                         continue;
@@ -865,11 +865,8 @@ impl BTreeCursor {
                     self.going_upwards = false;
                     self.stack.advance();
                     if predicate.is_none() {
-                        let rowid = match self.get_immutable_record().as_ref().unwrap().last_value()
-                        {
-                            Some(RefValue::Integer(rowid)) => *rowid as u64,
-                            _ => unreachable!("index cells should have an integer rowid"),
-                        };
+                        let rowid =
+                            index_entry_rowid(self.get_immutable_record().as_ref().unwrap());
                         return Ok(CursorResult::Ok(Some(rowid)));
                     }
 
@@ -880,14 +877,7 @@ impl BTreeCursor {
                     let order = {
                         let record = self.get_immutable_record();
                         let record = record.as_ref().unwrap();
-                        let record_slice_same_num_cols =
-                            &record.get_values()[..index_key.get_values().len()];
-                        let order = compare_immutable(
-                            record_slice_same_num_cols,
-                            index_key.get_values(),
-                            self.index_key_sort_order,
-                        );
-                        order
+                        self.compare_index_entry(record.get_values(), index_key.get_values())
                     };
                     let found = match op {
                         SeekOp::GT => order.is_gt(),
@@ -896,12 +886,12 @@ impl BTreeCursor {
                         _ => unreachable!("Seek LE/LT should not happen in get_next_record() because we are iterating forwards"),
                     };
                     if found {
-                        let rowid = match self.get_immutable_record().as_ref().unwrap().last_value()
-                        {
-                            Some(RefValue::Integer(rowid)) => *rowid as u64,
-                            _ => unreachable!("index cells should have an integer rowid"),
-                        };
+                        let rowid =
+                            index_entry_rowid(self.get_immutable_record().as_ref().unwrap());
                         return Ok(CursorResult::Ok(Some(rowid)));
+                    } else if *op == SeekOp::EQ && order.is_gt() {
+                        // The entries that follow are greater too
+                        return Ok(CursorResult::Ok(None));
                     } else {
                         continue;
                     }
@@ -926,11 +916,8 @@ impl BTreeCursor {
 
                     self.stack.advance();
                     if predicate.is_none() {
-                        let rowid = match self.get_immutable_record().as_ref().unwrap().last_value()
-                        {
-                            Some(RefValue::Integer(rowid)) => *rowid as u64,
-                            _ => unreachable!("index cells should have an integer rowid"),
-                        };
+                        let rowid =
+                            index_entry_rowid(self.get_immutable_record().as_ref().unwrap());
                         return Ok(CursorResult::Ok(Some(rowid)));
                     }
                     let (key, op) = predicate.as_ref().unwrap();
@@ -940,14 +927,7 @@ impl BTreeCursor {
                     let order = {
                         let record = self.get_immutable_record();
                         let record = record.as_ref().unwrap();
-                        let record_slice_same_num_cols =
-                            &record.get_values()[..index_key.get_values().len()];
-                        let order = compare_immutable(
-                            record_slice_same_num_cols,
-                            index_key.get_values(),
-                            self.index_key_sort_order,
-                        );
-                        order
+                        self.compare_index_entry(record.get_values(), index_key.get_values())
                     };
                     let found = match op {
                         SeekOp::GT => order.is_lt(),
@@ -956,12 +936,12 @@ impl BTreeCursor {
                         _ => todo!("not implemented: {:?}", op),
                     };
                     if found {
-                        let rowid = match self.get_immutable_record().as_ref().unwrap().last_value()
-                        {
-                            Some(RefValue::Integer(rowid)) => *rowid as u64,
-                            _ => unreachable!("index cells should have an integer rowid"),
-                        };
+                        let rowid =
+                            index_entry_rowid(self.get_immutable_record().as_ref().unwrap());
                         return Ok(CursorResult::Ok(Some(rowid)));
+                    } else if *op == SeekOp::EQ {
+                        // The entries that follow are greater too
+                        return Ok(CursorResult::Ok(None));
                     } else {
                         continue;
                     }
@@ -1076,13 +1056,8 @@ impl BTreeCursor {
                         };
                         let record = self.get_immutable_record();
                         let record = record.as_ref().unwrap();
-                        let record_slice_equal_number_of_cols =
-                            &record.get_values().as_slice()[..index_key.get_values().len()];
-                        let order = compare_immutable(
-                            record_slice_equal_number_of_cols,
-                            index_key.get_values(),
-                            self.index_key_sort_order,
-                        );
+                        let order =
+                            self.compare_index_entry(record.get_values(), index_key.get_values());
                         let found = match op {
                             SeekOp::GT => order.is_gt(),
                             SeekOp::GE => order.is_ge(),
@@ -1092,10 +1067,7 @@ impl BTreeCursor {
                         };
                         self.stack.next_cell_in_direction(cell_iter_dir);
                         if found {
-                            let rowid = match record.last_value() {
-                                Some(RefValue::Integer(rowid)) => *rowid as u64,
-                                _ => unreachable!("index cells should have an integer rowid"),
-                            };
+                            let rowid = index_entry_rowid(record);
                             return Ok(CursorResult::Ok(Some(rowid)));
                         }
                     }
@@ -1342,13 +1314,8 @@ impl BTreeCursor {
                 };
                 let record = self.get_immutable_record();
                 let record = record.as_ref().unwrap();
-                let record_slice_equal_number_of_cols =
-                    &record.get_values().as_slice()[..index_key.get_values().len()];
-                let interior_cell_vs_index_key = compare_immutable(
-                    record_slice_equal_number_of_cols,
-                    index_key.get_values(),
-                    self.index_key_sort_order,
-                );
+                let interior_cell_vs_index_key =
+                    self.compare_index_entry(record.get_values(), index_key.get_values());
                 // in sqlite btrees left child pages have <= keys.
                 // in general, in forwards iteration we want to find the first key that matches the seek condition.
                 // in backwards iteration we want to find the last key that matches the seek condition.
@@ -1686,13 +1653,12 @@ impl BTreeCursor {
                             self.get_immutable_record_or_create().as_mut().unwrap(),
                         )
                         .expect("failed to read record");
-                    if compare_immutable(
-                                record.get_values(),
+                    if self.compare_index_entry(
                                 self.get_immutable_record()
                                     .as_ref()
                                     .unwrap()
                                     .get_values(),
-                        self.index_key_sort_order,
+                                record.get_values(),
                         ) == Ordering::Equal {
 
                         tracing::debug!("insert_into_page: found exact match with cell_idx={cell_idx}, overwriting");
@@ -1734,6 +1700,10 @@ impl BTreeCursor {
                         )?;
                         contents.overflow_cells.len()
                     };
+                    if overflow == 0 {
+                        // Otherwise the entries of the cell are set once the page is balanced.
+                        self.ptrmap_put_cell(&page, cell_idx)?;
+                    }
                     let write_info = self
                         .state
                         .mut_write_info()
@@ -1864,12 +1834,18 @@ impl BTreeCursor {
                 let number_of_cells_in_parent =
                     parent_contents.cell_count() + parent_contents.overflow_cells.len();
 
+                // The parent may only overflow with the divider cell of the page to balance,
+                // which happens when deleting from an interior page of an index, see
+                // [DeleteState::InteriorNodeReplacement]. That cell is one of the dividers
+                // taken out of the parent below.
                 assert!(
-                    parent_contents.overflow_cells.is_empty(),
+                    parent_contents.overflow_cells.is_empty()
+                        || (parent_contents.overflow_cells.len() == 1
+                            && parent_contents.overflow_cells[0].index == page_to_balance_idx),
                     "balancing child page with overflowed parent not yet implemented"
                 );
                 assert!(
-                    page_to_balance_idx <= parent_contents.cell_count(),
+                    page_to_balance_idx <= number_of_cells_in_parent,
                     "page_to_balance_idx={} is out of bounds for parent cell count {}",
                     page_to_balance_idx,
                     number_of_cells_in_parent
@@ -1907,7 +1883,7 @@ impl BTreeCursor {
                     parent_contents.rightmost_pointer_raw().unwrap()
                 } else {
                     let (start_of_cell, _) = parent_contents.cell_get_raw_region(
-                        first_cell_divider + sibling_pointer,
+                        first_cell_divider + sibling_pointer - parent_contents.overflow_cells.len(),
                         payload_overflow_threshold_max(
                             parent_contents.page_type(),
                             self.usable_space() as u16,
@@ -1930,15 +1906,25 @@ impl BTreeCursor {
                     let page = self.pager.read_page(pgno as usize)?;
                     debug_validate_cells!(&page.get_contents(), self.usable_space() as u16);
                     pages_to_balance.push(page);
-                    assert_eq!(
-                        parent_contents.overflow_cells.len(),
-                        0,
-                        "overflow in parent is not yet implented while balancing it"
-                    );
                     if i == 0 {
                         break;
                     }
                     let next_cell_divider = i + first_cell_divider - 1;
+                    if let Some(overflow_cell) = parent_contents
+                        .overflow_cells
+                        .iter()
+                        .find(|cell| cell.index == next_cell_divider)
+                    {
+                        pgno = read_u32(&overflow_cell.payload, 0);
+                        continue;
+                    }
+                    let next_cell_divider = if parent_contents.overflow_cells.is_empty()
+                        || next_cell_divider < parent_contents.overflow_cells[0].index
+                    {
+                        next_cell_divider
+                    } else {
+                        next_cell_divider - 1
+                    };
                     pgno = match parent_contents.cell_get(
                         next_cell_divider,
                         payload_overflow_threshold_max(
@@ -2006,11 +1992,6 @@ impl BTreeCursor {
                 let parent_contents = parent_page.get_contents();
                 let parent_is_root = !self.stack.has_parent();
 
-                assert!(
-                    parent_contents.overflow_cells.is_empty(),
-                    "overflow parent not yet implemented"
-                );
-
                 /* 1. Get divider cells and max_cells */
                 let mut max_cells = 0;
                 let mut pages_to_balance_new = Vec::new();
@@ -2029,6 +2010,21 @@ impl BTreeCursor {
                     }
                     // Since we know we have a left sibling, take the divider that points to left sibling of this page
                     let cell_idx = balance_info.first_divider_cell + i;
+                    max_cells += 1;
+                    if let Some(overflow_idx) = parent_contents
+                        .overflow_cells
+                        .iter()
+                        .position(|cell| cell.index == cell_idx)
+                    {
+                        let overflow_cell = parent_contents.overflow_cells.remove(overflow_idx);
+                        balance_info
+                            .divider_cells
+                            .push(Pin::into_inner(overflow_cell.payload));
+                        continue;
+                    }
+                    // The dividers are taken from right to left, so the overflow cell, if any, is
+                    // still in the parent only when this divider is to its right.
+                    let cell_idx = cell_idx - parent_contents.overflow_cells.len();
                     let (cell_start, cell_len) = parent_contents.cell_get_raw_region(
                         cell_idx,
                         payload_overflow_threshold_max(
@@ -2043,7 +2039,6 @@ impl BTreeCursor {
                     );
                     let buf = parent_contents.as_ptr();
                     let cell_buf = &buf[cell_start..cell_start + cell_len];
-                    max_cells += 1;
 
                     tracing::debug!(
                         "balance_non_root(drop_divider_cell, first_divider_cell={}, divider_cell={}, left_pointer={})",
@@ -2413,7 +2408,6 @@ impl BTreeCursor {
                         .get_contents()
                         .write_u32(offset::BTREE_RIGHTMOST_PTR, right_pointer);
                 }
-                // TODO: pointer map update (vacuum support)
                 // Update divider cells in parent
                 for (i, page) in pages_to_balance_new
                     .iter()
@@ -2476,13 +2470,22 @@ impl BTreeCursor {
                     );
                     // FIXME: defragment shouldn't be needed
                     // defragment_page(parent_contents, self.usable_space() as u16);
-                    insert_into_cell(
-                        parent_contents,
-                        &new_divider_cell,
-                        balance_info.first_divider_cell + i,
-                        self.usable_space() as u16,
-                    )
-                    .unwrap();
+                    if parent_contents.overflow_cells.is_empty() {
+                        insert_into_cell(
+                            parent_contents,
+                            &new_divider_cell,
+                            balance_info.first_divider_cell + i,
+                            self.usable_space() as u16,
+                        )
+                        .unwrap();
+                    } else {
+                        // Once a divider overflowed, the next ones must overflow too, otherwise
+                        // they would not be in order with it.
+                        parent_contents.overflow_cells.push(OverflowCell {
+                            index: balance_info.first_divider_cell + i,
+                            payload: Pin::new(new_divider_cell),
+                        });
+                    }
                     #[cfg(debug_assertions)]
                     self.validate_balance_non_root_divider_cell_insertion(
                         balance_info,
@@ -2639,6 +2642,13 @@ impl BTreeCursor {
                     assert!(sibling_count_new < balance_info.sibling_count);
                 }
 
+                // The cells moved between pages, so their children and overflow pages have new
+                // parents in the pointer map.
+                self.pager.ptrmap_put_children(&parent_page)?;
+                for page in pages_to_balance_new.iter().take(sibling_count_new) {
+                    self.pager.ptrmap_put_children(page)?;
+                }
+
                 #[cfg(debug_assertions)]
                 self.post_balance_non_root_validation(
                     &parent_page,
@@ -2970,7 +2980,9 @@ impl BTreeCursor {
                 // We will only validate rightmost pointer of parent page, we will not validate rightmost if it's a cell and not the last pointer because,
                 // insert cell could've defragmented the page and invalidated the pointer.
                 // right pointer, we just check right pointer points to this page.
-                if cell_divider_idx == parent_contents.cell_count() {
+                if cell_divider_idx
+                    == parent_contents.cell_count() + parent_contents.overflow_cells.len()
+                {
                     let rightmost = read_u32(rightmost_pointer, 0);
                     if rightmost != page.get().id as u32 {
                         tracing::error!("balance_non_root(cell_divider_right_pointer, should point to {}, but points to {})",
@@ -3284,11 +3296,12 @@ impl BTreeCursor {
                         self.get_immutable_record_or_create().as_mut().unwrap(),
                     )
                     .expect("failed to read record");
-                    let order = compare_immutable(
-                        key.to_index_key_values(),
-                        self.get_immutable_record().as_ref().unwrap().get_values(),
-                        self.index_key_sort_order,
-                    );
+                    let order = self
+                        .compare_index_entry(
+                            self.get_immutable_record().as_ref().unwrap().get_values(),
+                            key.to_index_key_values(),
+                        )
+                        .reverse();
                     match order {
                         Ordering::Less | Ordering::Equal => {
                             break;
@@ -3475,6 +3488,7 @@ impl BTreeCursor {
             self.state = CursorState::Delete(DeleteInfo {
                 state: DeleteState::Start,
                 balance_write_info: None,
+                interior_cell_depth: None,
             })
         }
 
@@ -3487,6 +3501,7 @@ impl BTreeCursor {
             match delete_state {
                 DeleteState::Start => {
                     let page = self.stack.top();
+                    return_if_locked_maybe_load!(self.pager, page);
                     if matches!(
                         page.get_contents().page_type(),
                         PageType::TableLeaf | PageType::TableInterior
@@ -3572,6 +3587,7 @@ impl BTreeCursor {
                     if !contents.is_leaf() {
                         delete_info.state = DeleteState::InteriorNodeReplacement {
                             cell_idx,
+                            cell_depth: self.stack.current(),
                             original_child_pointer,
                         };
                     } else {
@@ -3581,34 +3597,41 @@ impl BTreeCursor {
 
                 DeleteState::InteriorNodeReplacement {
                     cell_idx,
+                    cell_depth,
                     original_child_pointer,
                 } => {
-                    // This is an interior node, we need to handle deletion differently
-                    // For interior nodes:
-                    // 1. Move cursor to largest entry in left subtree
-                    // 2. Copy that entry to replace the one being deleted
-                    // 3. Delete the leaf entry
-
-                    // Move to the largest key in the left subtree
-                    return_if_io!(self.prev());
+                    // Like in SQLite, the deleted entry of an interior page is replaced by its
+                    // predecessor, the last entry of the rightmost leaf of its left subtree.
+                    loop {
+                        let page = self.stack.top();
+                        return_if_locked_maybe_load!(self.pager, page);
+                        let contents = page.get_contents();
+                        if contents.is_leaf() {
+                            break;
+                        }
+                        let child_page = if self.stack.current() == cell_depth {
+                            self.stack.set_cell_index(cell_idx as i32);
+                            original_child_pointer.expect("interior cell without a child page")
+                        } else {
+                            self.stack.set_cell_index(contents.cell_count() as i32);
+                            contents.rightmost_pointer().unwrap()
+                        };
+                        self.stack.push(self.pager.read_page(child_page as usize)?);
+                    }
 
+                    // The pages read on the way down may have evicted the interior page from
+                    // the page cache.
+                    let interior_page = self.stack.stack.borrow()[cell_depth]
+                        .as_ref()
+                        .unwrap()
+                        .clone();
+                    return_if_locked_maybe_load!(self.pager, interior_page);
                     let leaf_page = self.stack.top();
                     return_if_locked_maybe_load!(self.pager, leaf_page);
-                    assert!(
-                        matches!(
-                            leaf_page.get_contents().page_type(),
-                            PageType::TableLeaf | PageType::IndexLeaf
-                        ),
-                        "self.prev should have returned a leaf page"
-                    );
-
-                    let parent_page = self.stack.parent_page().unwrap();
-                    assert!(parent_page.is_loaded(), "parent page");
 
-                    let leaf_contents = leaf_page.get().contents.as_ref().unwrap();
-                    // The index of the cell to removed must be the last one.
+                    let leaf_contents = leaf_page.get_contents();
                     let leaf_cell_idx = leaf_contents.cell_count() - 1;
-                    let predecessor_cell = leaf_contents.cell_get(
+                    let (cell_start, cell_len) = leaf_contents.cell_get_raw_region(
                         leaf_cell_idx,
                         payload_overflow_threshold_max(
                             leaf_contents.page_type(),
@@ -3619,38 +3642,53 @@ impl BTreeCursor {
                             self.usable_space() as u16,
                         ),
                         self.usable_space(),
-                    )?;
-
-                    parent_page.set_dirty();
-                    self.pager.add_dirty(parent_page.get().id);
-
-                    let parent_contents = parent_page.get().contents.as_mut().unwrap();
-
-                    // Create an interior cell from a predecessor
-                    let mut cell_payload: Vec<u8> = Vec::new();
-                    let child_pointer = original_child_pointer.expect("there should be a pointer");
-                    match predecessor_cell {
-                        BTreeCell::TableLeafCell(leaf_cell) => {
-                            cell_payload.extend_from_slice(&child_pointer.to_be_bytes());
-                            write_varint_to_vec(leaf_cell._rowid, &mut cell_payload);
-                        }
-                        BTreeCell::IndexLeafCell(leaf_cell) => {
-                            cell_payload.extend_from_slice(&child_pointer.to_be_bytes());
-                            cell_payload.extend_from_slice(leaf_cell.payload);
-                        }
-                        _ => unreachable!("Expected table leaf cell"),
-                    }
-
+                    );
+                    // The leaf cell is moved as is, along with its overflow pages.
+                    let child_pointer =
+                        original_child_pointer.expect("interior cell without a child page");
+                    let mut cell_payload = Vec::with_capacity(4 + cell_len);
+                    cell_payload.extend_from_slice(&child_pointer.to_be_bytes());
+                    cell_payload.extend_from_slice(
+                        &leaf_contents.as_ptr()[cell_start..cell_start + cell_len],
+                    );
+                    leaf_page.set_dirty();
+                    self.pager.add_dirty(leaf_page.get().id);
+                    drop_cell(leaf_contents, leaf_cell_idx, self.usable_space() as u16)?;
+                    self.stack.set_cell_index(leaf_cell_idx as i32);
+
+                    interior_page.set_dirty();
+                    self.pager.add_dirty(interior_page.get().id);
+                    let interior_contents = interior_page.get_contents();
+                    drop_cell(interior_contents, cell_idx, self.usable_space() as u16)?;
+                    // The interior page overflows if the new cell is larger than the deleted
+                    // one, in which case the cell stays out of it until it is balanced.
                     insert_into_cell(
-                        parent_contents,
+                        interior_contents,
                         &cell_payload,
                         cell_idx,
                         self.usable_space() as u16,
                     )?;
-                    drop_cell(parent_contents, cell_idx, self.usable_space() as u16)?;
+                    if interior_contents.overflow_cells.is_empty() {
+                        let cell = interior_contents.cell_get(
+                            cell_idx,
+                            payload_overflow_threshold_max(
+                                interior_contents.page_type(),
+                                self.usable_space() as u16,
+                            ),
+                            payload_overflow_threshold_min(
+                                interior_contents.page_type(),
+                                self.usable_space() as u16,
+                            ),
+                            self.usable_space(),
+                        )?;
+                        self.pager.ptrmap_put_cell(interior_page.get().id, &cell)?;
+                    }
 
+                    let target_key =
+                        DeleteSavepoint::Payload(self.record().as_ref().unwrap().clone());
                     let delete_info = self.state.mut_delete_info().unwrap();
-                    delete_info.state = DeleteState::CheckNeedsBalancing;
+                    delete_info.interior_cell_depth = Some(cell_depth);
+                    delete_info.state = DeleteState::StartBalancing { target_key };
                 }
 
                 DeleteState::DropCell { cell_idx } => {
@@ -3713,24 +3751,44 @@ impl BTreeCursor {
 
                 DeleteState::WaitForBalancingToComplete { target_key } => {
                     let delete_info = self.state.mut_delete_info().unwrap();
+                    let interior_cell_depth = delete_info.interior_cell_depth;
 
                     // Switch the CursorState to Write state for balancing
                     let write_info = delete_info.balance_write_info.take().unwrap();
                     self.state = CursorState::Write(write_info);
 
                     match self.balance()? {
-                        // TODO(Krishna): Add second balance in the case where deletion causes cursor to end up
-                        // a level deeper.
                         CursorResult::Ok(()) => {
                             let write_info = match &self.state {
                                 CursorState::Write(wi) => wi.clone(),
                                 _ => unreachable!("Balance operation changed cursor state"),
                             };
 
+                            // If balancing the leaf a cell was moved from stopped below the
+                            // interior page it was moved to, that page is balanced too.
+                            if let Some(depth) = interior_cell_depth {
+                                if self.stack.current() > depth {
+                                    while self.stack.current() > depth {
+                                        self.stack.pop();
+                                    }
+                                    let mut write_info = WriteInfo::new();
+                                    write_info.state = WriteState::BalanceStart;
+                                    self.state = CursorState::Delete(DeleteInfo {
+                                        state: DeleteState::WaitForBalancingToComplete {
+                                            target_key,
+                                        },
+                                        balance_write_info: Some(write_info),
+                                        interior_cell_depth: None,
+                                    });
+                                    continue;
+                                }
+                            }
+
                             // Move to seek state
                             self.state = CursorState::Delete(DeleteInfo {
                                 state: DeleteState::SeekAfterBalancing { target_key },
                                 balance_write_info: Some(write_info),
+                                interior_cell_depth: None,
                             });
                         }
 
@@ -3744,6 +3802,7 @@ impl BTreeCursor {
                             self.state = CursorState::Delete(DeleteInfo {
                                 state: DeleteState::WaitForBalancingToComplete { target_key },
                                 balance_write_info: Some(write_info),
+                                interior_cell_depth,
                             });
                             return Ok(CursorResult::IO);
                         }
@@ -3751,13 +3810,39 @@ impl BTreeCursor {
                 }
 
                 DeleteState::SeekAfterBalancing { target_key } => {
-                    let key = match &target_key {
-                        DeleteSavepoint::Rowid(rowid) => SeekKey::TableRowId(*rowid),
+                    match &target_key {
+                        DeleteSavepoint::Rowid(rowid) => {
+                            // Move to the leaf the deleted row was in, and point at the row that
+                            // followed it, so that the next call to next() returns it. When the
+                            // leaf has no such row, the next one is at the start of the next
+                            // leaf, so point at the end of this one. The row is not read, as
+                            // reading its overflow pages would need the cursor state.
+                            return_if_io!(self.move_to(SeekKey::TableRowId(*rowid), SeekOp::GE));
+                            let page = self.stack.top();
+                            return_if_locked_maybe_load!(self.pager, page);
+                            let contents = page.get_contents();
+                            let mut cell_idx = 0;
+                            while cell_idx < contents.cell_count()
+                                && contents.cell_table_leaf_read_rowid(cell_idx)? < *rowid
+                            {
+                                cell_idx += 1;
+                            }
+                            self.stack.set_cell_index(cell_idx as i32);
+                        }
                         DeleteSavepoint::Payload(immutable_record) => {
-                            SeekKey::IndexKey(immutable_record)
+                            // The deleted entry is gone, so move to the entry that followed it
+                            // and step back, so that the next call to next() returns it.
+                            let found = return_if_io!(
+                                self.seek(SeekKey::IndexKey(immutable_record), SeekOp::GE)
+                            );
+                            if found {
+                                self.stack.retreat();
+                                let page = self.stack.top();
+                                return_if_locked!(page);
+                                self.going_upwards = !page.get_contents().is_leaf();
+                            }
                         }
-                    };
-                    return_if_io!(self.seek(key, SeekOp::EQ));
+                    }
 
                     let delete_info = self.state.mut_delete_info().unwrap();
                     delete_info.state = DeleteState::Finish;
@@ -3793,19 +3878,41 @@ impl BTreeCursor {
     }
 
     /// Search for a key in an Index Btree. Looking up indexes that need to be unique, we cannot compare the rowid
-    pub fn key_exists_in_index(&mut self, key: &ImmutableRecord) -> Result<CursorResult<bool>> {
-        return_if_io!(self.seek(SeekKey::IndexKey(key), SeekOp::GE));
+    pub fn key_exists_in_index(
+        &mut self,
+        key: &ImmutableRecord,
+        num_key_columns: usize,
+    ) -> Result<CursorResult<bool>> {
+        // The first `num_key_columns` values make up the unique key, which never conflicts when it
+        // contains a NULL. They are followed by the rowid, or by the rest of the PRIMARY KEY or
+        // of the row in a WITHOUT ROWID table.
+        let inserted_key_vals = &key.get_values()[..num_key_columns.min(key.count())];
+        if inserted_key_vals
+            .iter()
+            .any(|value| matches!(value, RefValue::Null))
+        {
+            return Ok(CursorResult::Ok(false));
+        }
+        let unique_key = ImmutableRecord::from_registers(
+            &inserted_key_vals
+                .iter()
+                .map(|value| Register::OwnedValue(value.to_owned()))
+                .collect::<Vec<_>>(),
+        );
+        // The record of the cursor is stale when no entry is found, e.g. after the last one was
+        // deleted
+        if !return_if_io!(self.seek(SeekKey::IndexKey(&unique_key), SeekOp::GE)) {
+            return Ok(CursorResult::Ok(false));
+        }
 
         let record_opt = self.record();
         match record_opt.as_ref() {
             Some(record) => {
                 // Existing record found — compare prefix
-                let existing_key = &record.get_values()[..record.count().saturating_sub(1)];
-                let inserted_key_vals = &key.get_values();
-                if existing_key
-                    .iter()
-                    .zip(inserted_key_vals.iter())
-                    .all(|(a, b)| a == b)
+                let existing_key = &record.get_values()[..num_key_columns.min(record.count())];
+                if self
+                    .compare_index_entry(existing_key, inserted_key_vals)
+                    .is_eq()
                 {
                     return Ok(CursorResult::Ok(true)); // duplicate
                 }
@@ -3924,10 +4031,21 @@ impl BTreeCursor {
     ///
     /// The destruction order would be: [4',4,5,2,6,7,3,1]
     pub fn btree_destroy(&mut self) -> Result<CursorResult<()>> {
+        self.free_pages(false)
+    }
+
+    /// Deletes all the entries of a B-tree. Like [Self::btree_destroy], but the root page is
+    /// kept as an empty leaf page, so the B-tree can still be used.
+    pub fn btree_clear(&mut self) -> Result<CursorResult<()>> {
+        self.free_pages(true)
+    }
+
+    fn free_pages(&mut self, keep_root: bool) -> Result<CursorResult<()>> {
         if let CursorState::None = &self.state {
             self.move_to_root();
             self.state = CursorState::Destroy(DestroyInfo {
                 state: DestroyState::Start,
+                keep_root,
             });
         }
 
@@ -4093,8 +4211,21 @@ impl BTreeCursor {
                 DestroyState::FreePage => {
                     let page = self.stack.top();
                     let page_id = page.get().id;
+                    let keep_root = self.state.destroy_info().unwrap().keep_root;
 
-                    self.pager.free_page(Some(page), page_id)?;
+                    if keep_root && !self.stack.has_parent() {
+                        let contents = page.get_contents();
+                        let page_type = match contents.page_type() {
+                            PageType::TableLeaf | PageType::TableInterior => PageType::TableLeaf,
+                            PageType::IndexLeaf | PageType::IndexInterior => PageType::IndexLeaf,
+                        };
+                        let offset = contents.offset;
+                        btree_init_page(&page, page_type, offset, self.usable_space() as u16);
+                        page.set_dirty();
+                        self.pager.add_dirty(page_id);
+                    } else {
+                        self.pager.free_page(Some(page), page_id)?;
+                    }
 
                     if self.stack.has_parent() {
                         self.stack.pop();
@@ -4102,7 +4233,8 @@ impl BTreeCursor {
                             .state
                             .mut_destroy_info()
                             .expect("unable to get a mut reference to destroy state in cursor");
-                        destroy_info.state = DestroyState::ProcessPage;
+                        // The parent may have been evicted while its children were freed
+                        destroy_info.state = DestroyState::LoadPage;
                     } else {
                         self.state = CursorState::None;
                         return Ok(CursorResult::Ok(()));
@@ -4148,7 +4280,6 @@ impl BTreeCursor {
         // if it all fits in local space and old_local_size is enough, do an in-place overwrite
         if new_payload.len() == old_local_size {
             self.overwrite_content(page_ref.clone(), old_offset, &new_payload)?;
-            Ok(CursorResult::Ok(()))
         } else {
             // doesn't fit, drop it and insert a new one
             drop_cell(
@@ -4162,8 +4293,27 @@ impl BTreeCursor {
                 cell_idx,
                 self.usable_space() as u16,
             )?;
-            Ok(CursorResult::Ok(()))
         }
+        if page_ref.get_contents().overflow_cells.is_empty() {
+            self.ptrmap_put_cell(&page_ref, cell_idx)?;
+        }
+        Ok(CursorResult::Ok(()))
+    }
+
+    /// Points the pointer map entries of the child and of the first overflow page of cell
+    /// `cell_idx` of `page` to the page.
+    fn ptrmap_put_cell(&self, page: &PageRef, cell_idx: usize) -> Result<()> {
+        if self.pager.auto_vacuum_mode() == AutoVacuumMode::None {
+            return Ok(());
+        }
+        let contents = page.get_contents();
+        let cell = contents.cell_get(
+            cell_idx,
+            payload_overflow_threshold_max(contents.page_type(), self.usable_space() as u16),
+            payload_overflow_threshold_min(contents.page_type(), self.usable_space() as u16),
+            self.usable_space(),
+        )?;
+        self.pager.ptrmap_put_cell(page.get().id, &cell)
     }
 
     pub fn overwrite_content(
@@ -4197,6 +4347,19 @@ impl BTreeCursor {
             _ => false,
         }
     }
+
+    pub fn is_delete_in_progress(&self) -> bool {
+        matches!(self.state, CursorState::Delete(_))
+    }
+}
+
+/// Returns the rowid of an index entry, which is its last column. The entries of a WITHOUT ROWID
+/// table have no rowid, and only whether the cursor points to one matters for them.
+fn index_entry_rowid(record: &ImmutableRecord) -> u64 {
+    match record.last_value() {
+        Some(RefValue::Integer(rowid)) => *rowid as u64,
+        _ => 0,
+    }
 }
 
 #[cfg(debug_assertions)]
@@ -4347,18 +4510,6 @@ impl PageStack {
     fn clear(&self) {
         self.current_page.set(-1);
     }
-    pub fn parent_page(&self) -> Option<PageRef> {
-        if self.current_page.get() > 0 {
-            Some(
-                self.stack.borrow()[self.current() - 1]
-                    .as_ref()
-                    .unwrap()
-                    .clone(),
-            )
-        } else {
-            None
-        }
-    }
 }
 
 /// Used for redistributing cells during a balance operation.
@@ -5039,7 +5190,7 @@ fn fill_cell_payload(
     cell_payload.resize(prev_size + space_left + 4, 0);
     let mut pointer = unsafe { cell_payload.as_mut_ptr().add(prev_size) };
     let mut pointer_to_next = unsafe { cell_payload.as_mut_ptr().add(prev_size + space_left) };
-    let mut overflow_pages = Vec::new();
+    let mut overflow_pages: Vec<PageRef> = Vec::new();
 
     loop {
         let to_copy = space_left.min(to_copy_buffer.len());
@@ -5052,6 +5203,16 @@ fn fill_cell_payload(
 
         // we still have bytes to add, we will need to allocate new overflow page
         let overflow_page = pager.allocate_overflow_page();
+        if let Some(previous_page) = overflow_pages.last() {
+            // The first overflow page is the child of the cell, once the cell is on a page.
+            pager
+                .ptrmap_put(
+                    overflow_page.get().id,
+                    PtrmapType::Overflow2,
+                    previous_page.get().id,
+                )
+                .unwrap();
+        }
         overflow_pages.push(overflow_page.clone());
         {
             let id = overflow_page.get().id as u32;
@@ -5084,7 +5245,7 @@ fn fill_cell_payload(
 /// - Give a minimum fanout of 4 for index b-trees
 /// - Ensure enough payload is on the b-tree page that the record header can usually be accessed
 ///   without consulting an overflow page
-fn payload_overflow_threshold_max(page_type: PageType, usable_space: u16) -> usize {
+pub(crate) fn payload_overflow_threshold_max(page_type: PageType, usable_space: u16) -> usize {
     match page_type {
         PageType::IndexInterior | PageType::IndexLeaf => {
             ((usable_space as usize - 12) * 64 / 255) - 23 // Index page formula
@@ -5104,7 +5265,7 @@ fn payload_overflow_threshold_max(page_type: PageType, usable_space: u16) -> usi
 /// - Otherwise: store M bytes on page
 ///
 /// The remaining bytes are stored on overflow pages in both cases.
-fn payload_overflow_threshold_min(_page_type: PageType, usable_space: u16) -> usize {
+pub(crate) fn payload_overflow_threshold_min(_page_type: PageType, usable_space: u16) -> usize {
     // Same formula for all page types
     ((usable_space as usize - 12) * 32 / 255) - 23
 }
@@ -16,6 +16,9 @@ pub trait DatabaseStorage: Send + Sync {
         c: Completion,
     ) -> Result<()>;
     fn sync(&self, c: Completion) -> Result<()>;
+    fn size(&self) -> Result<u64>;
+    /// Truncates the storage to `len` bytes, dropping the pages past it.
+    fn truncate(&self, len: usize, c: Completion) -> Result<()>;
 }
 
 #[cfg(feature = "fs")]
@@ -61,6 +64,14 @@ impl DatabaseStorage for DatabaseFile {
     fn sync(&self, c: Completion) -> Result<()> {
         self.file.sync(c)
     }
+
+    fn size(&self) -> Result<u64> {
+        self.file.size()
+    }
+
+    fn truncate(&self, len: usize, c: Completion) -> Result<()> {
+        self.file.truncate(len, c)
+    }
 }
 
 #[cfg(feature = "fs")]
@@ -111,6 +122,14 @@ impl DatabaseStorage for FileMemoryStorage {
     fn sync(&self, c: Completion) -> Result<()> {
         self.file.sync(c)
     }
+
+    fn size(&self) -> Result<u64> {
+        self.file.size()
+    }
+
+    fn truncate(&self, len: usize, c: Completion) -> Result<()> {
+        self.file.truncate(len, c)
+    }
 }
 
 impl FileMemoryStorage {
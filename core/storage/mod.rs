@@ -10,6 +10,7 @@
 //! for reading and writing pages to the database file, either local or
 //! remote. The `Wal` struct is responsible for managing the write-ahead log
 //! for the database, also either local or remote.
+pub(crate) mod auto_vacuum;
 pub(crate) mod btree;
 pub(crate) mod buffer_pool;
 pub(crate) mod database;
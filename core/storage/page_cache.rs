@@ -116,8 +116,15 @@ impl DumbLruPageCache {
     }
 
     pub fn resize(&mut self, capacity: usize) {
-        let _ = capacity;
-        todo!();
+        self.capacity = capacity;
+        while self.len() > self.capacity {
+            let len = self.len();
+            self.pop_if_not_dirty();
+            // The least recently used page is dirty, it stays until it is written.
+            if self.len() == len {
+                break;
+            }
+        }
     }
 
     fn detach(&mut self, mut entry: NonNull<PageCacheEntry>, clean_page: bool) {
@@ -233,6 +240,19 @@ mod tests {
         assert!(cache.get(&key1).is_none());
     }
 
+    #[test]
+    fn test_page_cache_resize() {
+        let mut cache = DumbLruPageCache::new(3);
+        let key1 = insert_page(&mut cache, 1);
+        let key2 = insert_page(&mut cache, 2);
+        let key3 = insert_page(&mut cache, 3);
+        cache.resize(2);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&key1).is_none());
+        assert_eq!(cache.get(&key2).unwrap().get().id, 2);
+        assert_eq!(cache.get(&key3).unwrap().get().id, 3);
+    }
+
     #[test]
     fn test_page_cache_fuzz() {
         let seed = std::time::SystemTime::now()
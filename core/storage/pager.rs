@@ -1,13 +1,14 @@
 use crate::fast_lock::SpinLock;
 use crate::result::LimboResult;
+use crate::storage::auto_vacuum::{is_ptrmap_page, AutoVacuumMode, PtrmapType};
 use crate::storage::buffer_pool::BufferPool;
 use crate::storage::database::DatabaseStorage;
 use crate::storage::sqlite3_ondisk::{self, DatabaseHeader, PageContent, PageType};
 use crate::storage::wal::{CheckpointResult, Wal};
 use crate::{Buffer, LimboError, Result};
 use parking_lot::RwLock;
-use std::cell::{RefCell, UnsafeCell};
-use std::collections::HashSet;
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
@@ -157,6 +158,13 @@ struct FlushInfo {
     in_flight_writes: Rc<RefCell<usize>>,
 }
 
+/// The pages changed by the write transaction up to some point, as they were then, so that the
+/// changes made after that point can be rolled back without rolling back the whole transaction.
+struct Snapshot {
+    pages: HashMap<usize, PageContent>,
+    db_header: DatabaseHeader,
+}
+
 /// The pager interface implements the persistence layer by providing access
 /// to pages of the database file, including caching, concurrency control, and
 /// transaction management.
@@ -178,6 +186,15 @@ pub struct Pager {
     checkpoint_state: RefCell<CheckpointState>,
     checkpoint_inflight: Rc<RefCell<usize>>,
     syncing: Rc<RefCell<bool>>,
+    /// The database header when the write transaction began.
+    tx_db_header: RefCell<Option<DatabaseHeader>>,
+    /// The snapshot taken when the running statement began.
+    statement_journal: RefCell<Option<Snapshot>>,
+    /// The snapshots taken when the open savepoints were opened, oldest first.
+    savepoints: RefCell<Vec<Snapshot>>,
+    /// The auto-vacuum mode set with `PRAGMA auto_vacuum` that the database can't switch to
+    /// until it is rebuilt by VACUUM.
+    pub auto_vacuum_request: Cell<Option<AutoVacuumMode>>,
 }
 
 impl Pager {
@@ -210,18 +227,30 @@ impl Pager {
             checkpoint_state: RefCell::new(CheckpointState::Checkpoint),
             checkpoint_inflight: Rc::new(RefCell::new(0)),
             buffer_pool,
+            tx_db_header: RefCell::new(None),
+            statement_journal: RefCell::new(None),
+            savepoints: RefCell::new(Vec::new()),
+            auto_vacuum_request: Cell::new(None),
         })
     }
 
-    pub fn btree_create(&self, flags: &CreateBTreeFlags) -> u32 {
+    pub fn btree_create(&self, flags: &CreateBTreeFlags) -> Result<u32> {
         let page_type = match flags {
             _ if flags.is_table() => PageType::TableLeaf,
             _ if flags.is_index() => PageType::IndexLeaf,
             _ => unreachable!("Invalid flags state"),
         };
+        if self.auto_vacuum_mode() != AutoVacuumMode::None {
+            let root = self.auto_vacuum_create_root()?;
+            let page = self.read_page_sync(root)?;
+            page.set_dirty();
+            self.add_dirty(root);
+            crate::btree_init_page(&page, page_type, 0, self.usable_space() as u16);
+            return Ok(root as u32);
+        }
         let page = self.do_allocate_page(page_type, 0);
         let id = page.get().id;
-        id as u32
+        Ok(id as u32)
     }
 
     /// Allocate a new overflow page.
@@ -272,12 +301,127 @@ impl Pager {
     #[inline(always)]
     pub fn begin_write_tx(&self) -> Result<LimboResult> {
         if let Some(wal) = &self.wal {
-            return wal.borrow_mut().begin_write_tx();
+            if let LimboResult::Busy = wal.borrow_mut().begin_write_tx()? {
+                return Ok(LimboResult::Busy);
+            }
         }
-
+        self.tx_db_header
+            .replace(Some(self.db_header.lock().clone()));
         Ok(LimboResult::Ok)
     }
 
+    /// Rolls back the write transaction, discarding all of its changes, and ends it.
+    pub fn rollback_tx(&self) -> Result<()> {
+        self.statement_journal.take();
+        if let Some(db_header) = self.tx_db_header.take() {
+            self.restore(&Snapshot {
+                pages: HashMap::new(),
+                db_header,
+            });
+        }
+        self.flush_info.borrow_mut().state = FlushState::Start;
+        if let Some(wal) = &self.wal {
+            let mut wal = wal.borrow_mut();
+            wal.rollback()?;
+            wal.end_write_tx()?;
+            wal.end_read_tx()?;
+        }
+        Ok(())
+    }
+
+    /// Begins a statement of the write transaction. Until it ends, the statement can be rolled
+    /// back on its own.
+    pub fn begin_statement(&self) {
+        self.statement_journal.replace(Some(self.snapshot()));
+    }
+
+    /// Ends the running statement, keeping its changes.
+    pub fn end_statement(&self) {
+        self.statement_journal.take();
+    }
+
+    /// Rolls back the changes of the running statement, if it made any.
+    pub fn rollback_statement(&self) {
+        if let Some(journal) = self.statement_journal.take() {
+            self.restore(&journal);
+        }
+    }
+
+    /// Opens a savepoint, which the changes made from now on can be rolled back to.
+    pub fn open_savepoint(&self) {
+        let snapshot = self.snapshot();
+        self.savepoints.borrow_mut().push(snapshot);
+    }
+
+    /// Releases the savepoint at `index` and the ones opened after it, keeping their changes.
+    pub fn release_savepoint(&self, index: usize) {
+        self.savepoints.borrow_mut().truncate(index);
+    }
+
+    /// Rolls back the changes made since the savepoint at `index` was opened. The savepoint stays
+    /// open, and the ones opened after it are released.
+    pub fn rollback_to_savepoint(&self, index: usize) {
+        let mut savepoints = self.savepoints.borrow_mut();
+        savepoints.truncate(index + 1);
+        if let Some(snapshot) = savepoints.last() {
+            self.restore(snapshot);
+        }
+    }
+
+    /// Takes a snapshot of the dirty pages and of the database header.
+    fn snapshot(&self) -> Snapshot {
+        let max_frame = match &self.wal {
+            Some(wal) => wal.borrow().get_max_frame(),
+            None => 0,
+        };
+        let mut cache = self.page_cache.write();
+        let pages = self
+            .dirty_pages
+            .borrow()
+            .iter()
+            .map(|page_id| {
+                let page_key = PageCacheKey::new(*page_id, Some(max_frame));
+                let page = cache
+                    .peek(&page_key, false)
+                    .expect("dirty page not in cache");
+                (*page_id, page.get_contents().clone())
+            })
+            .collect();
+        Snapshot {
+            pages,
+            db_header: self.db_header.lock().clone(),
+        }
+    }
+
+    /// Puts the dirty pages back as they were in `snapshot`, dropping the pages that are not in
+    /// it from the cache so that they are read again from the WAL or the database file.
+    fn restore(&self, snapshot: &Snapshot) {
+        let max_frame = match &self.wal {
+            Some(wal) => wal.borrow().get_max_frame(),
+            None => 0,
+        };
+        let mut cache = self.page_cache.write();
+        let mut dirty_pages = self.dirty_pages.borrow_mut();
+        for page_id in std::mem::take(&mut *dirty_pages) {
+            let page_key = PageCacheKey::new(page_id, Some(max_frame));
+            let Some(page) = cache.peek(&page_key, false) else {
+                continue;
+            };
+            match snapshot.pages.get(&page_id) {
+                Some(contents) => {
+                    page.get().contents = Some(contents.clone());
+                    dirty_pages.insert(page_id);
+                }
+                None => {
+                    trace!("restore(drop page={})", page_id);
+                    page.clear_dirty();
+                    cache.delete(page_key);
+                }
+            }
+        }
+        *self.db_header.lock() = snapshot.db_header.clone();
+    }
+
     pub fn end_tx(&self) -> Result<CheckpointStatus> {
         if let Some(wal) = &self.wal {
             let checkpoint_status = self.cacheflush()?;
@@ -341,6 +485,18 @@ impl Pager {
         Ok(page)
     }
 
+    /// Reads a page from the database, waiting for it to be loaded.
+    pub fn read_page_sync(&self, page_idx: usize) -> Result<PageRef> {
+        let page = self.read_page(page_idx)?;
+        if !page.is_loaded() && !page.is_dirty() {
+            self.load_page(page.clone())?;
+        }
+        while page.is_locked() {
+            self.io.run_once()?;
+        }
+        Ok(page)
+    }
+
     /// Loads pages if not loaded
     pub fn load_page(&self, page: PageRef) -> Result<()> {
         let id = page.get().id;
@@ -381,6 +537,15 @@ impl Pager {
         Ok(())
     }
 
+    /// Writes the database header to page 1, as part of the write transaction.
+    pub fn write_header_page(&self, header: &DatabaseHeader) -> Result<()> {
+        let page = self.read_page_sync(1)?;
+        page.set_dirty();
+        self.add_dirty(1);
+        page.get_contents().write_database_header(header);
+        Ok(())
+    }
+
     /// Writes the database header.
     pub fn write_database_header(&self, header: &DatabaseHeader) {
         sqlite3_ondisk::begin_write_database_header(header, self).expect("failed to write header");
@@ -405,6 +570,11 @@ impl Pager {
             trace!("cacheflush {:?}", state);
             match state {
                 FlushState::Start => {
+                    if !self.dirty_pages.borrow().is_empty()
+                        && self.auto_vacuum_mode() == AutoVacuumMode::Full
+                    {
+                        self.auto_vacuum_commit()?;
+                    }
                     let db_size = self.db_header.lock().database_size;
                     let max_frame = match &self.wal {
                         Some(wal) => wal.borrow().get_max_frame(),
@@ -581,6 +751,7 @@ impl Pager {
         };
 
         self.db_header.lock().freelist_pages += 1;
+        self.ptrmap_put(page_id, PtrmapType::FreePage, 0)?;
 
         let trunk_page_id = self.db_header.lock().freelist_trunk_page;
 
@@ -605,6 +776,7 @@ impl Pager {
                 );
                 page.clear_uptodate();
                 page.clear_loaded();
+                self.write_header_page(&self.db_header.lock())?;
 
                 return Ok(());
             }
@@ -624,6 +796,7 @@ impl Pager {
         // Clear flags
         page.clear_uptodate();
         page.clear_loaded();
+        self.write_header_page(&self.db_header.lock())?;
         Ok(())
     }
 
@@ -636,23 +809,16 @@ impl Pager {
         let header = &self.db_header;
         let mut header = header.lock();
         header.database_size += 1;
+        let usable_space = (header.page_size - header.reserved_space as u16) as usize;
+        if AutoVacuumMode::from_header(&header) != AutoVacuumMode::None
+            && is_ptrmap_page(header.database_size as usize, usable_space)
         {
-            // update database size
-            // read sync for now
-            loop {
-                let first_page_ref = self.read_page(1)?;
-                if first_page_ref.is_locked() {
-                    self.io.run_once()?;
-                    continue;
-                }
-                first_page_ref.set_dirty();
-                self.add_dirty(1);
-
-                let contents = first_page_ref.get().contents.as_ref().unwrap();
-                contents.write_database_header(&header);
-                break;
-            }
+            // The pointer map page of the pages that follow comes first.
+            self.init_ptrmap_page(header.database_size as usize);
+            header.database_size += 1;
         }
+        // update database size
+        self.write_header_page(&header)?;
 
         let page = allocate_page(header.database_size as usize, &self.buffer_pool, 0);
         {
@@ -671,6 +837,31 @@ impl Pager {
         Ok(page)
     }
 
+    /// Returns a page with a buffer of its own that is not in the page cache.
+    pub fn allocate_page_buffer(&self, page_id: usize) -> PageRef {
+        allocate_page(page_id, &self.buffer_pool, 0)
+    }
+
+    /// Drops the changes to the pages past the end of a database that shrank to `db_size` pages.
+    pub fn discard_pages_after(&self, db_size: usize) {
+        let max_frame = match &self.wal {
+            Some(wal) => wal.borrow().get_max_frame(),
+            None => 0,
+        };
+        let mut cache = self.page_cache.write();
+        self.dirty_pages.borrow_mut().retain(|page_id| {
+            if *page_id <= db_size {
+                return true;
+            }
+            let page_key = PageCacheKey::new(*page_id, Some(max_frame));
+            if let Some(page) = cache.peek(&page_key, false) {
+                page.clear_dirty();
+            }
+            cache.delete(page_key);
+            false
+        });
+    }
+
     pub fn put_loaded_page(&self, id: usize, page: PageRef) {
         let mut cache = self.page_cache.write();
         // cache insert invalidates previous page
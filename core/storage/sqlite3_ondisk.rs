@@ -123,7 +123,7 @@ pub struct DatabaseHeader {
 
     /// The page number of the largest root b-tree page when in auto-vacuum or
     /// incremental-vacuum modes, or zero otherwise.
-    vacuum_mode_largest_root_page: u32,
+    pub vacuum_mode_largest_root_page: u32,
 
     /// The database text encoding. 1=UTF-8, 2=UTF-16le, 3=UTF-16be.
     text_encoding: u32,
@@ -132,7 +132,7 @@ pub struct DatabaseHeader {
     pub user_version: u32,
 
     /// True (non-zero) for incremental-vacuum mode. False (zero) otherwise.
-    incremental_vacuum_enabled: u32,
+    pub incremental_vacuum_enabled: u32,
 
     /// The "Application ID" set by PRAGMA application_id.
     application_id: u32,
@@ -186,6 +186,9 @@ pub trait Wal {
     fn get_max_frame_in_wal(&self) -> u64;
     fn get_max_frame(&self) -> u64;
     fn get_min_frame(&self) -> u64;
+
+    /// Discard the frames of the write transaction that were not committed.
+    fn rollback(&mut self) -> Result<()>;
 }
 
 // Syncing requires a state machine because we need to schedule a sync and then wait until it is
@@ -259,6 +262,10 @@ pub struct WalFile {
     max_frame: u64,
     /// Start of range to look for frames range=(minframe..max_frame)
     min_frame: u64,
+    /// Last frame committed to the WAL, and its checksum. Frames after it belong to the write
+    /// transaction in progress and are discarded if it rolls back.
+    committed_frame: u64,
+    committed_checksum: (u32, u32),
 }
 
 impl fmt::Debug for WalFile {
@@ -273,6 +280,7 @@ impl fmt::Debug for WalFile {
             .field("max_frame_read_lock_index", &self.max_frame_read_lock_index)
             .field("max_frame", &self.max_frame)
             .field("min_frame", &self.min_frame)
+            .field("committed_frame", &self.committed_frame)
             // Excluding other fields
             .finish()
     }
@@ -396,6 +404,7 @@ impl Wal for WalFile {
         if busy {
             return Ok(LimboResult::Busy);
         }
+        self.mark_committed();
         Ok(LimboResult::Ok)
     }
 
@@ -624,6 +633,17 @@ impl Wal for WalFile {
                         shared.max_frame.store(0, Ordering::SeqCst);
                         shared.nbackfills.store(0, Ordering::SeqCst);
                         // TODO(pere): truncate wal file here.
+
+                        // The database may have shrunk, e.g. with VACUUM, and no reader can
+                        // see the pages past its end anymore, so they can be dropped.
+                        let db_size = pager.db_header.lock().database_size as usize;
+                        let db_len = db_size * self.page_size;
+                        if db_size > 0 && pager.db_file.size()? > db_len as u64 {
+                            let completion = Completion::Sync(SyncCompletion {
+                                complete: Box::new(|_| {}),
+                            });
+                            pager.db_file.truncate(db_len, completion)?;
+                        }
                     } else {
                         shared
                             .nbackfills
@@ -661,6 +681,8 @@ impl Wal for WalFile {
                     Ok(CheckpointStatus::IO)
                 } else {
                     self.sync_state.replace(SyncState::NotSyncing);
+                    // The frames are durable now, so the write transaction is committed.
+                    self.mark_committed();
                     let checkpoint_result = CheckpointResult {
                         num_wal_frames: self.max_frame,
                         num_checkpointed_frames: self.ongoing_checkpoint.max_frame,
@@ -682,6 +704,33 @@ impl Wal for WalFile {
     fn get_min_frame(&self) -> u64 {
         self.min_frame
     }
+
+    fn rollback(&mut self) -> Result<()> {
+        let shared = self.get_shared();
+        let max_frame = shared.max_frame.load(Ordering::SeqCst);
+        if max_frame <= self.committed_frame {
+            return Ok(());
+        }
+        debug!(
+            "wal_rollback(committed_frame={}, max_frame={})",
+            self.committed_frame, max_frame
+        );
+        let committed_frame = self.committed_frame;
+        {
+            let mut frame_cache = shared.frame_cache.lock();
+            let mut pages_in_frames = shared.pages_in_frames.lock();
+            frame_cache.retain(|page_id, frames| {
+                frames.retain(|frame| *frame <= committed_frame);
+                if frames.is_empty() {
+                    pages_in_frames.retain(|page| page != page_id);
+                }
+                !frames.is_empty()
+            });
+        }
+        shared.max_frame.store(committed_frame, Ordering::SeqCst);
+        shared.last_checksum = self.committed_checksum;
+        Ok(())
+    }
 }
 
 impl WalFile {
@@ -721,6 +770,8 @@ impl WalFile {
             max_frame: 0,
             min_frame: 0,
             max_frame_read_lock_index: 0,
+            committed_frame: 0,
+            committed_checksum: (0, 0),
         }
     }
 
@@ -732,6 +783,17 @@ impl WalFile {
         offset as usize
     }
 
+    /// Marks all the frames in the WAL as committed.
+    fn mark_committed(&mut self) {
+        let shared = self.get_shared();
+        let (max_frame, checksum) = (
+            shared.max_frame.load(Ordering::SeqCst),
+            shared.last_checksum,
+        );
+        self.committed_frame = max_frame;
+        self.committed_checksum = checksum;
+    }
+
     #[allow(clippy::mut_from_ref)]
     fn get_shared(&self) -> &mut WalFileShared {
         unsafe { self.shared.get().as_mut().unwrap() }
@@ -7,6 +7,7 @@ use crate::{
 };
 
 use super::{
+    collate::expr_collation,
     emitter::{Resolver, TranslateCtx},
     expr::translate_expr,
     plan::{Aggregate, SelectPlan, TableReference},
@@ -145,6 +146,8 @@ pub fn translate_aggregation_step(
             let expr = &agg.args[0];
             let expr_reg = program.alloc_register();
             let _ = translate_expr(program, Some(referenced_tables), expr, expr_reg, resolver)?;
+            let collation = expr_collation(expr, Some(referenced_tables), resolver.symbol_table)?;
+            program.emit_insn(Insn::CollSeq { collation });
             program.emit_insn(Insn::AggStep {
                 acc_reg: target_register,
                 col: expr_reg,
@@ -160,6 +163,8 @@ pub fn translate_aggregation_step(
             let expr = &agg.args[0];
             let expr_reg = program.alloc_register();
             let _ = translate_expr(program, Some(referenced_tables), expr, expr_reg, resolver)?;
+            let collation = expr_collation(expr, Some(referenced_tables), resolver.symbol_table)?;
+            program.emit_insn(Insn::CollSeq { collation });
             program.emit_insn(Insn::AggStep {
                 acc_reg: target_register,
                 col: expr_reg,
@@ -0,0 +1,1139 @@
+//! ALTER TABLE.
+//!
+//! Like in SQLite, ALTER TABLE edits the SQL of the schema entries of the table, i.e. of the table
+//! itself, its indexes and its triggers, with internal functions ([AlterTableFunc]) that the
+//! program calls on each entry as it scans sqlite_schema. A rename also edits the views, the
+//! triggers and the foreign keys of other tables that refer to the table. The table is then
+//! reloaded into the in-memory schema from the rewritten entries, along with the other entries
+//! that a rename may have changed.
+
+use fallible_iterator::FallibleIterator;
+use limbo_sqlite3_parser::ast::{
+    self, fmt::ToTokens, AlterTableBody, ColumnConstraint, CreateTableBody, DistinctNames, Expr,
+    ForeignKeyClause, TableConstraint, TriggerCmd, TriggerEvent,
+};
+use limbo_sqlite3_parser::lexer::sql::Parser;
+
+use crate::collation::CollationSeq;
+use crate::function::{AlterTableFunc, Func, FuncCtx};
+use crate::schema::{literal_value, BTreeTable, Schema, Table, Trigger};
+use crate::translate::collate::resolve_collation;
+use crate::translate::schema::SQLITE_TABLEID;
+use crate::types::OwnedValue;
+use crate::util::{normalize_ident, PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX};
+use crate::vdbe::builder::{CursorType, ProgramBuilder, ProgramBuilderOpts, QueryMode};
+use crate::vdbe::insn::{CmpInsFlags, IdxInsertFlags, Insn};
+use crate::{bail_parse_error, LimboError, Result, SymbolTable};
+
+use super::trigger::TriggerExprRewriter;
+use super::window::{child_exprs, child_exprs_mut};
+
+pub fn translate_alter_table(
+    query_mode: QueryMode,
+    tbl_name: ast::QualifiedName,
+    body: AlterTableBody,
+    schema: &Schema,
+    syms: &SymbolTable,
+) -> Result<ProgramBuilder> {
+    let table_name = normalize_ident(&tbl_name.name.0);
+    let Some(table) = schema.get_table(&table_name) else {
+        bail_parse_error!("no such table: {}", tbl_name.name.0);
+    };
+    if table_name.starts_with("sqlite_") {
+        bail_parse_error!("table {} may not be altered", table_name);
+    }
+    let btree = match table.as_ref() {
+        Table::BTree(btree) => btree.clone(),
+        Table::View(_) => match body {
+            AlterTableBody::AddColumn(_) => bail_parse_error!("Cannot add a column to a view"),
+            AlterTableBody::RenameColumn { .. } => {
+                bail_parse_error!("cannot rename columns of view \"{}\"", table_name)
+            }
+            AlterTableBody::DropColumn(_) => {
+                bail_parse_error!("cannot drop column from view \"{}\"", table_name)
+            }
+            AlterTableBody::RenameTo(_) => bail_parse_error!("renaming views is not supported"),
+        },
+        Table::Virtual(_) => bail_parse_error!("virtual tables may not be altered"),
+        Table::Pseudo(_) => unreachable!(),
+    };
+
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 2,
+        approx_num_insns: 40,
+        approx_num_labels: 4,
+    });
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+
+    // The name of the table after the statement, as it is stored in sqlite_schema.
+    let mut stored_table_name = btree.name.clone();
+    // The other tables and views whose entries a rename may rewrite, which are reloaded too.
+    let mut dependents = vec![];
+    match body {
+        AlterTableBody::RenameTo(new_name) => {
+            let normalized_new_name = normalize_ident(&new_name.0);
+            if schema.get_table(&normalized_new_name).is_some()
+                || !schema.is_unique_idx_name(&normalized_new_name)
+            {
+                bail_parse_error!(
+                    "there is already another table or index with this name: {}",
+                    new_name.0
+                );
+            }
+            if normalized_new_name.starts_with("sqlite_") {
+                bail_parse_error!("object name reserved for internal use: {}", new_name.0);
+            }
+            // The table keeps its automatic indexes, whose names are derived from its own.
+            let old_prefix = format!("{}{}_", PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX, table_name);
+            let mut renames = vec![(table_name.clone(), new_name.0.clone())];
+            for index in schema.get_indices(&table_name) {
+                if let Some(suffix) = index.name.strip_prefix(&old_prefix) {
+                    renames.push((
+                        index.name.clone(),
+                        format!(
+                            "{}{}_{}",
+                            PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX, new_name.0, suffix
+                        ),
+                    ));
+                }
+            }
+            emit_schema_rewrite(
+                &mut program,
+                schema,
+                &table_name,
+                true,
+                AlterTableFunc::RenameTable,
+                vec![table_name.clone(), new_name.0.clone()],
+                &renames,
+                Some(&new_name.0),
+            );
+            stored_table_name = new_name.0;
+            dependents = rename_dependents(schema, &table_name);
+        }
+        AlterTableBody::RenameColumn { old, new } => {
+            let old_name = normalize_ident(&old.0);
+            if btree.get_column(&old_name).is_none() {
+                bail_parse_error!("no such column: \"{}\"", old.0);
+            }
+            if btree.get_column(&normalize_ident(&new.0)).is_some() {
+                bail_parse_error!("duplicate column name: {}", new.0);
+            }
+            emit_schema_rewrite(
+                &mut program,
+                schema,
+                &table_name,
+                true,
+                AlterTableFunc::RenameColumn,
+                vec![table_name.clone(), old_name, new.0],
+                &[],
+                None,
+            );
+            dependents = rename_dependents(schema, &table_name);
+        }
+        AlterTableBody::AddColumn(column_def) => {
+            validate_new_column(&btree, &column_def, syms)?;
+            emit_schema_rewrite(
+                &mut program,
+                schema,
+                &table_name,
+                false,
+                AlterTableFunc::AddColumn,
+                vec![format_ast(&column_def)],
+                &[],
+                None,
+            );
+        }
+        AlterTableBody::DropColumn(column_name) => {
+            let column_name = normalize_ident(&column_name.0);
+            let Some((dropped, column)) = btree.get_column(&column_name) else {
+                bail_parse_error!("no such column: \"{}\"", column_name);
+            };
+            if column.primary_key {
+                bail_parse_error!("cannot drop PRIMARY KEY column: \"{}\"", column_name);
+            }
+            if btree.columns.len() == 1 {
+                bail_parse_error!(
+                    "cannot drop column \"{}\": no other columns exist",
+                    column_name
+                );
+            }
+            for index in schema.get_indices(&table_name) {
+                if index.depends_on_column(&btree, dropped) {
+                    if index
+                        .name
+                        .starts_with(PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX)
+                    {
+                        bail_parse_error!("cannot drop UNIQUE column: \"{}\"", column_name);
+                    }
+                    bail_parse_error!(
+                        "error in index {} after drop column: no such column: {}",
+                        index.name,
+                        column_name
+                    );
+                }
+            }
+            for trigger in schema.get_triggers(&table_name) {
+                if let Some(reference) = trigger_column_reference(trigger, &column_name)? {
+                    bail_parse_error!(
+                        "error in trigger {} after drop column: no such column: {}",
+                        trigger.name,
+                        reference
+                    );
+                }
+            }
+            emit_schema_rewrite(
+                &mut program,
+                schema,
+                &table_name,
+                false,
+                AlterTableFunc::DropColumn,
+                vec![column_name],
+                &[],
+                None,
+            );
+            emit_drop_column_data(&mut program, &btree, dropped);
+        }
+    }
+
+    // Reload the table, its indexes and its triggers, and the entries that depend on it.
+    let reloads = std::iter::once((table_name, stored_table_name))
+        .chain(dependents.into_iter().map(|name| (name.clone(), name)));
+    for (name, stored_name) in reloads {
+        program.emit_insn(Insn::DropTable {
+            db: 0,
+            _p2: 0,
+            _p3: 0,
+            table_name: name,
+        });
+        program.emit_insn(Insn::ParseSchema {
+            db: 0,
+            where_clause: format!(
+                "tbl_name = '{}' COLLATE NOCASE",
+                stored_name.replace('\'', "''")
+            ),
+        });
+    }
+
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_transaction(true);
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+    Ok(program)
+}
+
+/// Returns the tables and views other than `table_name` whose entries in sqlite_schema may refer
+/// to it: the views, the tables and views with triggers, and the tables with foreign keys to it.
+fn rename_dependents(schema: &Schema, table_name: &str) -> Vec<String> {
+    let mut dependents = schema
+        .tables
+        .iter()
+        .filter(|(name, table)| {
+            matches!(table.as_ref(), Table::View(_)) || !schema.get_triggers(name).is_empty()
+        })
+        .map(|(name, _)| name.clone())
+        .chain(
+            schema
+                .get_referencing_foreign_keys(table_name)
+                .into_iter()
+                .map(|(child, _)| child.name.clone()),
+        )
+        .filter(|name| name != table_name)
+        .collect::<Vec<_>>();
+    dependents.sort();
+    dependents.dedup();
+    dependents
+}
+
+/// Checks the restrictions of SQLite on the columns added by ALTER TABLE ADD COLUMN. Notably,
+/// the rows that are already in the table are not rewritten, and read the default of the column
+/// instead, so it must be a literal.
+fn validate_new_column(
+    btree: &BTreeTable,
+    column_def: &ast::ColumnDefinition,
+    syms: &SymbolTable,
+) -> Result<()> {
+    if btree
+        .get_column(&normalize_ident(&column_def.col_name.0))
+        .is_some()
+    {
+        bail_parse_error!("duplicate column name: {}", column_def.col_name.0);
+    }
+    let mut not_null = false;
+    let mut default = None;
+    for constraint in &column_def.constraints {
+        match &constraint.constraint {
+            ColumnConstraint::PrimaryKey { .. } => {
+                bail_parse_error!("Cannot add a PRIMARY KEY column")
+            }
+            ColumnConstraint::Unique(_) => bail_parse_error!("Cannot add a UNIQUE column"),
+            ColumnConstraint::NotNull { nullable, .. } => not_null = !nullable,
+            ColumnConstraint::Default(expr) => default = Some(expr),
+            ColumnConstraint::Collate { collation_name } => {
+                resolve_collation(Some(&collation_name.0), syms)?;
+            }
+            ColumnConstraint::Generated { .. } => {
+                bail_parse_error!("generated columns are not supported")
+            }
+            _ => {}
+        }
+    }
+    let default_value = match &default {
+        Some(expr) => match literal_value(expr) {
+            Some(value) => value,
+            None => bail_parse_error!("Cannot add a column with non-constant default"),
+        },
+        None => OwnedValue::Null,
+    };
+    if not_null && matches!(default_value, OwnedValue::Null) {
+        bail_parse_error!("Cannot add a NOT NULL column with default value NULL");
+    }
+    Ok(())
+}
+
+/// Emits a loop over sqlite_schema that rewrites the SQL of the entries of `table_name` with
+/// `func`, which is called with the SQL followed by `args`, or of all the entries if
+/// `with_dependents`, for the views, triggers and foreign keys that refer to the table. `renames`
+/// maps the old names of entries of the table to their new names, and `new_table_name` replaces
+/// the table name of the entries of the table.
+#[allow(clippy::too_many_arguments)]
+fn emit_schema_rewrite(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    table_name: &str,
+    with_dependents: bool,
+    func: AlterTableFunc,
+    args: Vec<String>,
+    renames: &[(String, String)],
+    new_table_name: Option<&str>,
+) {
+    let schema_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let cursor_id = program.alloc_cursor_id(
+        Some(SQLITE_TABLEID.to_owned()),
+        CursorType::BTreeTable(schema_table),
+    );
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id,
+        root_page: 1usize.into(),
+        db: 0,
+    });
+    let table_name_reg = program.emit_string8_new_reg(table_name.to_string());
+    program.mark_last_insn_constant();
+    let rename_regs = renames
+        .iter()
+        .map(|(old, new)| {
+            let old_reg = program.emit_string8_new_reg(old.clone());
+            program.mark_last_insn_constant();
+            (old_reg, new.clone())
+        })
+        .collect::<Vec<_>>();
+    // The first argument, the SQL, is copied in for each entry.
+    let arg_count = args.len() + 1;
+    let args_start = program.alloc_registers(arg_count);
+    for (i, arg) in args.into_iter().enumerate() {
+        program.emit_string8(arg, args_start + 1 + i);
+        program.mark_last_insn_constant();
+    }
+    let name_cmp_flags = CmpInsFlags::default().with_collation(CollationSeq::NoCase);
+
+    let loop_end = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: loop_end,
+    });
+    let loop_start = program.offset();
+    let next_label = program.allocate_label();
+    // type, name, tbl_name, rootpage, sql
+    let row_start = program.alloc_registers(5);
+    program.emit_insn(Insn::Column {
+        cursor_id,
+        column: 2,
+        dest: row_start + 2,
+    });
+    if !with_dependents {
+        program.emit_insn(Insn::Ne {
+            lhs: row_start + 2,
+            rhs: table_name_reg,
+            target_pc: next_label,
+            flags: name_cmp_flags,
+        });
+    }
+    for column in [0, 1, 3, 4] {
+        program.emit_insn(Insn::Column {
+            cursor_id,
+            column,
+            dest: row_start + column,
+        });
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: row_start + 4,
+        dst_reg: args_start,
+        amount: 0,
+    });
+    program.emit_insn(Insn::Function {
+        constant_mask: 0,
+        start_reg: args_start,
+        dest: row_start + 4,
+        func: FuncCtx {
+            func: Func::AlterTable(func),
+            arg_count,
+        },
+    });
+    // Only the entries of the table itself are renamed
+    let renamed_label = program.allocate_label();
+    program.emit_insn(Insn::Ne {
+        lhs: row_start + 2,
+        rhs: table_name_reg,
+        target_pc: renamed_label,
+        flags: name_cmp_flags,
+    });
+    for (old_reg, new) in rename_regs {
+        let not_renamed_label = program.allocate_label();
+        program.emit_insn(Insn::Ne {
+            lhs: row_start + 1,
+            rhs: old_reg,
+            target_pc: not_renamed_label,
+            flags: name_cmp_flags,
+        });
+        program.emit_string8(new, row_start + 1);
+        program.resolve_label(not_renamed_label, program.offset());
+    }
+    if let Some(new_table_name) = new_table_name {
+        program.emit_string8(new_table_name.to_string(), row_start + 2);
+    }
+    program.resolve_label(renamed_label, program.offset());
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::RowId {
+        cursor_id,
+        dest: rowid_reg,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: row_start,
+        count: 5,
+        dest_reg: record_reg,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: 0,
+    });
+    program.resolve_label(next_label, program.offset());
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(loop_end, program.offset());
+}
+
+/// Emits a loop that rewrites each row of `btree` without its column at index `dropped`.
+fn emit_drop_column_data(
+    program: &mut ProgramBuilder,
+    btree: &std::rc::Rc<BTreeTable>,
+    dropped: usize,
+) {
+    let cursor_id = program.alloc_cursor_id(
+        Some(btree.name.clone()),
+        CursorType::BTreeTable(btree.clone()),
+    );
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id,
+        root_page: btree.root_page.into(),
+        db: 0,
+    });
+    let loop_end = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: loop_end,
+    });
+    let loop_start = program.offset();
+    let rowid_reg = program.alloc_register();
+    if btree.has_rowid {
+        program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: rowid_reg,
+        });
+    }
+    let num_columns = btree.columns.len() - 1;
+    let columns_start = program.alloc_registers(num_columns);
+    let kept_columns = btree
+        .columns_in_storage_order()
+        .into_iter()
+        .filter(|&i| i != dropped);
+    for (i, column) in kept_columns.enumerate() {
+        program.emit_insn(Insn::Column {
+            cursor_id,
+            column,
+            dest: columns_start + i,
+        });
+    }
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: columns_start,
+        count: num_columns,
+        dest_reg: record_reg,
+    });
+    if btree.has_rowid {
+        program.emit_insn(Insn::Insert {
+            cursor: cursor_id,
+            key_reg: rowid_reg,
+            record_reg,
+            flag: 0,
+        });
+    } else {
+        // The entry is replaced, and the loop continues from the new one, whose PRIMARY KEY
+        // columns come first
+        program.emit_insn(Insn::Delete {
+            cursor_id,
+            count_change: false,
+        });
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id,
+            record_reg,
+            unpacked_start: Some(columns_start),
+            unpacked_count: Some(num_columns as u16),
+            flags: IdxInsertFlags::new(),
+        });
+        let found_label = program.allocate_label();
+        program.emit_insn(Insn::NotFound {
+            cursor_id,
+            target_pc: found_label,
+            record_reg: columns_start,
+            num_regs: btree.primary_key_columns.len(),
+        });
+        program.resolve_label(found_label, program.offset());
+    }
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(loop_end, program.offset());
+}
+
+struct AstFormatter<'a, T: ToTokens>(&'a T);
+
+impl<T: ToTokens> std::fmt::Display for AstFormatter<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.to_fmt(f)
+    }
+}
+
+fn format_ast<T: ToTokens>(node: &T) -> String {
+    AstFormatter(node).to_string()
+}
+
+/// Evaluates `func` on the SQL of a schema entry, with the arguments that follow the SQL.
+/// Returns the SQL unchanged if the entry does not need to be rewritten.
+pub fn exec_alter_table_func(func: &AlterTableFunc, sql: &str, args: &[String]) -> Result<String> {
+    let mut stmt = parse_stmt(sql)?;
+    let original = stmt.clone();
+    match func {
+        AlterTableFunc::RenameTable => rename_table(&mut stmt, &args[0], &args[1])?,
+        AlterTableFunc::RenameColumn => rename_column(&mut stmt, &args[0], &args[1], &args[2])?,
+        AlterTableFunc::AddColumn => add_column(&mut stmt, &args[0])?,
+        AlterTableFunc::DropColumn => drop_column(&mut stmt, &args[0])?,
+    }
+    if stmt == original {
+        return Ok(sql.to_string());
+    }
+    Ok(format_ast(&stmt))
+}
+
+fn parse_stmt(sql: &str) -> Result<ast::Stmt> {
+    let mut parser = Parser::new(sql.as_bytes());
+    match parser.next()? {
+        Some(ast::Cmd::Stmt(stmt)) => Ok(stmt),
+        _ => Err(LimboError::ParseError(format!(
+            "malformed schema entry: {}",
+            sql
+        ))),
+    }
+}
+
+fn name_eq(name: &str, normalized: &str) -> bool {
+    normalize_ident(name) == normalized
+}
+
+fn rename_if_eq(name: &mut ast::Name, old: &str, new: &str) {
+    if name_eq(&name.0, old) {
+        *name = ast::Name(new.to_string());
+    }
+}
+
+/// Returns the foreign key clauses of the columns and constraints of a table.
+fn foreign_key_clauses(body: &mut CreateTableBody) -> Vec<&mut ForeignKeyClause> {
+    let CreateTableBody::ColumnsAndConstraints {
+        columns,
+        constraints,
+        ..
+    } = body
+    else {
+        return vec![];
+    };
+    let column_clauses = columns
+        .values_mut()
+        .flat_map(|column| column.constraints.iter_mut())
+        .filter_map(|constraint| match &mut constraint.constraint {
+            ColumnConstraint::ForeignKey { clause, .. } => Some(clause),
+            _ => None,
+        });
+    let table_clauses = constraints.iter_mut().flatten().filter_map(|constraint| {
+        match &mut constraint.constraint {
+            TableConstraint::ForeignKey { clause, .. } => Some(clause),
+            _ => None,
+        }
+    });
+    column_clauses.chain(table_clauses).collect()
+}
+
+fn rename_table(stmt: &mut ast::Stmt, old: &str, new: &str) -> Result<()> {
+    let mut renamer = QueryRenamer::new(Rename::Table { old, new });
+    match stmt {
+        ast::Stmt::CreateTable { tbl_name, body, .. } => {
+            rename_if_eq(&mut tbl_name.name, old, new);
+            // Foreign keys that refer to the table, from it or from other tables
+            for clause in foreign_key_clauses(body) {
+                rename_if_eq(&mut clause.tbl_name, old, new);
+            }
+        }
+        ast::Stmt::CreateIndex { tbl_name, .. } => rename_if_eq(tbl_name, old, new),
+        ast::Stmt::CreateView { select, .. } => renamer.rename_select(select)?,
+        ast::Stmt::CreateTrigger(trigger) => {
+            rename_if_eq(&mut trigger.tbl_name.name, old, new);
+            renamer.rename_trigger(trigger)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Renames the references to the column `old` in an expression on the table.
+fn rename_column_refs(expr: &mut Expr, old: &str, new: &str) {
+    if let Expr::Id(id) = expr {
+        if name_eq(&id.0, old) {
+            *id = ast::Id(new.to_string());
+        }
+        return;
+    }
+    for child in child_exprs_mut(expr) {
+        rename_column_refs(child, old, new);
+    }
+}
+
+fn references_column(expr: &Expr, column: &str) -> bool {
+    match expr {
+        Expr::Id(id) => name_eq(&id.0, column),
+        _ => child_exprs(expr)
+            .into_iter()
+            .any(|child| references_column(child, column)),
+    }
+}
+
+fn rename_column(stmt: &mut ast::Stmt, table: &str, old: &str, new: &str) -> Result<()> {
+    let mut renamer = QueryRenamer::new(Rename::Column { table, old, new });
+    match stmt {
+        ast::Stmt::CreateTable { tbl_name, body, .. } => {
+            // Foreign keys that refer to the table, from it or from other tables
+            for clause in foreign_key_clauses(body) {
+                if name_eq(&clause.tbl_name.0, table) {
+                    for column in clause.columns.iter_mut().flatten() {
+                        rename_if_eq(&mut column.col_name, old, new);
+                    }
+                }
+            }
+            if !name_eq(&tbl_name.name.0, table) {
+                return Ok(());
+            }
+            let CreateTableBody::ColumnsAndConstraints {
+                columns,
+                constraints,
+                ..
+            } = body.as_mut()
+            else {
+                return Ok(());
+            };
+            *columns = std::mem::take(columns)
+                .into_iter()
+                .map(|(mut name, mut column)| {
+                    rename_if_eq(&mut name, old, new);
+                    rename_if_eq(&mut column.col_name, old, new);
+                    for constraint in column.constraints.iter_mut() {
+                        match &mut constraint.constraint {
+                            ColumnConstraint::Check(expr)
+                            | ColumnConstraint::Generated { expr, .. } => {
+                                rename_column_refs(expr, old, new)
+                            }
+                            _ => {}
+                        }
+                    }
+                    (name, column)
+                })
+                .collect();
+            for constraint in constraints.iter_mut().flatten() {
+                match &mut constraint.constraint {
+                    TableConstraint::PrimaryKey { columns, .. }
+                    | TableConstraint::Unique { columns, .. } => {
+                        for column in columns.iter_mut() {
+                            rename_column_refs(&mut column.expr, old, new);
+                        }
+                    }
+                    TableConstraint::Check(expr) => rename_column_refs(expr, old, new),
+                    TableConstraint::ForeignKey { columns, .. } => {
+                        for column in columns.iter_mut() {
+                            rename_if_eq(&mut column.col_name, old, new);
+                        }
+                    }
+                }
+            }
+        }
+        ast::Stmt::CreateIndex {
+            tbl_name,
+            columns,
+            where_clause,
+            ..
+        } if name_eq(&tbl_name.0, table) => {
+            for column in columns.iter_mut() {
+                rename_column_refs(&mut column.expr, old, new);
+            }
+            if let Some(where_clause) = where_clause {
+                rename_column_refs(where_clause, old, new);
+            }
+        }
+        ast::Stmt::CreateView { select, .. } => renamer.rename_select(select)?,
+        ast::Stmt::CreateTrigger(trigger) => {
+            if name_eq(&trigger.tbl_name.name.0, table) {
+                if let TriggerEvent::UpdateOf(names) = &mut trigger.event {
+                    let mut renamed = names.iter().cloned().map(|mut name| {
+                        rename_if_eq(&mut name, old, new);
+                        name
+                    });
+                    let mut new_names = DistinctNames::new(renamed.next().unwrap());
+                    for name in renamed {
+                        new_names
+                            .insert(name)
+                            .map_err(|e| LimboError::ParseError(e.to_string()))?;
+                    }
+                    *names = new_names;
+                }
+                // The NEW and OLD rows are rows of the table
+                renamer.scopes.push(Scope {
+                    names: vec!["new".to_string(), "old".to_string()],
+                    unqualified: false,
+                });
+            }
+            renamer.rename_trigger(trigger)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The renaming of a table, or of a column of a table, that ALTER TABLE makes in the schema.
+#[derive(Clone, Copy)]
+enum Rename<'a> {
+    Table {
+        old: &'a str,
+        new: &'a str,
+    },
+    Column {
+        table: &'a str,
+        old: &'a str,
+        new: &'a str,
+    },
+}
+
+/// The names through which a query refers to the renamed table.
+struct Scope {
+    names: Vec<String>,
+    /// Whether the columns of the table may be referred to without a name.
+    unqualified: bool,
+}
+
+/// Renames the references to a table or to one of its columns in the queries of views and
+/// triggers, like SQLite does for the entries that depend on the table. A column is recognized
+/// through the names that the enclosing queries give to the table in their FROM clauses.
+struct QueryRenamer<'a> {
+    rename: Rename<'a>,
+    scopes: Vec<Scope>,
+}
+
+impl<'a> QueryRenamer<'a> {
+    fn new(rename: Rename<'a>) -> Self {
+        Self {
+            rename,
+            scopes: vec![],
+        }
+    }
+
+    /// The table that is renamed, or whose column is renamed, as it is named in the queries.
+    fn table(&self) -> &'a str {
+        match self.rename {
+            Rename::Table { old, .. } => old,
+            Rename::Column { table, .. } => table,
+        }
+    }
+
+    fn in_scope(&self, name: &str) -> bool {
+        let name = normalize_ident(name);
+        self.scopes.iter().any(|scope| scope.names.contains(&name))
+    }
+
+    /// Renames the target table of a command, or its column, and returns the scope of the
+    /// expressions of the command.
+    fn rename_target(&self, tbl_name: &mut ast::Name) -> Scope {
+        if !name_eq(&tbl_name.0, self.table()) {
+            return Scope {
+                names: vec![],
+                unqualified: false,
+            };
+        }
+        let scope = Scope {
+            names: vec![normalize_ident(&tbl_name.0)],
+            unqualified: true,
+        };
+        if let Rename::Table { old, new } = self.rename {
+            rename_if_eq(tbl_name, old, new);
+        }
+        scope
+    }
+
+    fn rename_column_names(&self, names: &mut DistinctNames) -> Result<()> {
+        let Rename::Column { old, new, .. } = self.rename else {
+            return Ok(());
+        };
+        let mut renamed = names.iter().cloned().map(|mut name| {
+            rename_if_eq(&mut name, old, new);
+            name
+        });
+        let mut new_names = DistinctNames::new(renamed.next().unwrap());
+        for name in renamed {
+            new_names
+                .insert(name)
+                .map_err(|e| LimboError::ParseError(e.to_string()))?;
+        }
+        *names = new_names;
+        Ok(())
+    }
+
+    fn rename_trigger(&mut self, trigger: &mut ast::CreateTrigger) -> Result<()> {
+        if let Some(when_clause) = &mut trigger.when_clause {
+            self.rename_expr(when_clause)?;
+        }
+        for command in trigger.commands.iter_mut() {
+            self.rename_command(command)?;
+        }
+        Ok(())
+    }
+
+    fn rename_command(&mut self, command: &mut TriggerCmd) -> Result<()> {
+        match command {
+            TriggerCmd::Insert(insert) => {
+                let scope = self.rename_target(&mut insert.tbl_name);
+                if scope.unqualified {
+                    if let Some(col_names) = &mut insert.col_names {
+                        self.rename_column_names(col_names)?;
+                    }
+                }
+                self.rename_select(&mut insert.select)
+            }
+            TriggerCmd::Update(update) => {
+                let mut scope = self.rename_target(&mut update.tbl_name);
+                if scope.unqualified {
+                    for set in update.sets.iter_mut() {
+                        self.rename_column_names(&mut set.col_names)?;
+                    }
+                }
+                if let Some(from) = &mut update.from {
+                    let from_scope = self.rename_from_clause(from)?;
+                    scope.unqualified |= from_scope.unqualified;
+                    scope.names.extend(from_scope.names);
+                }
+                self.scopes.push(scope);
+                for set in update.sets.iter_mut() {
+                    self.rename_expr(&mut set.expr)?;
+                }
+                if let Some(where_clause) = &mut update.where_clause {
+                    self.rename_expr(where_clause)?;
+                }
+                self.scopes.pop();
+                Ok(())
+            }
+            TriggerCmd::Delete(delete) => {
+                let scope = self.rename_target(&mut delete.tbl_name);
+                self.scopes.push(scope);
+                if let Some(where_clause) = &mut delete.where_clause {
+                    self.rename_expr(where_clause)?;
+                }
+                self.scopes.pop();
+                Ok(())
+            }
+            TriggerCmd::Select(select) => self.rename_select(select),
+        }
+    }
+
+    fn rename_select(&mut self, select: &mut ast::Select) -> Result<()> {
+        for cte in select.with.iter_mut().flat_map(|with| with.ctes.iter_mut()) {
+            self.rename_select(&mut cte.select)?;
+        }
+        let scope = self.rename_one_select(&mut select.body.select)?;
+        for compound in select.body.compounds.iter_mut().flatten() {
+            self.rename_one_select(&mut compound.select)?;
+        }
+        // The ORDER BY clause refers to the columns of the first query
+        self.scopes.push(scope);
+        for sorted_column in select.order_by.iter_mut().flatten() {
+            self.rename_expr(&mut sorted_column.expr)?;
+        }
+        self.scopes.pop();
+        if let Some(limit) = &mut select.limit {
+            self.rename_expr(&mut limit.expr)?;
+            if let Some(offset) = &mut limit.offset {
+                self.rename_expr(offset)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renames the references in a query and returns the scope of its FROM clause.
+    fn rename_one_select(&mut self, select: &mut ast::OneSelect) -> Result<Scope> {
+        let select = match select {
+            ast::OneSelect::Select(select) => select,
+            ast::OneSelect::Values(rows) => {
+                for expr in rows.iter_mut().flatten() {
+                    self.rename_expr(expr)?;
+                }
+                return Ok(Scope {
+                    names: vec![],
+                    unqualified: false,
+                });
+            }
+        };
+        let scope = match &mut select.from {
+            Some(from) => self.rename_from_clause(from)?,
+            None => Scope {
+                names: vec![],
+                unqualified: false,
+            },
+        };
+        self.scopes.push(scope);
+        if let Some(from) = &mut select.from {
+            for join in from.joins.iter_mut().flatten() {
+                match &mut join.constraint {
+                    Some(ast::JoinConstraint::On(expr)) => self.rename_expr(expr)?,
+                    Some(ast::JoinConstraint::Using(names)) => {
+                        if self.scopes.last().unwrap().unqualified {
+                            self.rename_column_names(names)?;
+                        }
+                    }
+                    None => {}
+                }
+            }
+        }
+        for column in select.columns.iter_mut() {
+            if let ast::ResultColumn::Expr(expr, _) = column {
+                self.rename_expr(expr)?;
+            }
+        }
+        if let Some(where_clause) = &mut select.where_clause {
+            self.rename_expr(where_clause)?;
+        }
+        if let Some(group_by) = &mut select.group_by {
+            for expr in group_by.exprs.iter_mut() {
+                self.rename_expr(expr)?;
+            }
+            if let Some(having) = &mut group_by.having {
+                self.rename_expr(having)?;
+            }
+        }
+        Ok(self.scopes.pop().unwrap())
+    }
+
+    /// Renames the table in a FROM clause and returns the names it has there.
+    fn rename_from_clause(&mut self, from: &mut ast::FromClause) -> Result<Scope> {
+        let mut scope = Scope {
+            names: vec![],
+            unqualified: false,
+        };
+        let tables = from
+            .select
+            .iter_mut()
+            .map(|table| table.as_mut())
+            .chain(from.joins.iter_mut().flatten().map(|join| &mut join.table));
+        for table in tables {
+            match table {
+                ast::SelectTable::Table(name, alias, _) => {
+                    if !name_eq(&name.name.0, self.table()) {
+                        continue;
+                    }
+                    scope.unqualified = true;
+                    scope.names.push(normalize_ident(match alias {
+                        Some(ast::As::As(alias) | ast::As::Elided(alias)) => &alias.0,
+                        None => &name.name.0,
+                    }));
+                    if let Rename::Table { old, new } = self.rename {
+                        rename_if_eq(&mut name.name, old, new);
+                    }
+                }
+                ast::SelectTable::TableCall(_, args, _) => {
+                    for arg in args.iter_mut().flatten() {
+                        self.rename_expr(arg)?;
+                    }
+                }
+                ast::SelectTable::Select(select, _) => self.rename_select(select)?,
+                ast::SelectTable::Sub(from, _) => {
+                    self.rename_from_clause(from)?;
+                }
+            }
+        }
+        Ok(scope)
+    }
+
+    fn rename_expr(&mut self, expr: &mut Expr) -> Result<()> {
+        match (expr, self.rename) {
+            (Expr::Qualified(table, column) | Expr::DoublyQualified(_, table, column), rename) => {
+                match rename {
+                    Rename::Table { old, new } => {
+                        if name_eq(&table.0, old) && self.in_scope(&table.0) {
+                            *table = ast::Name(new.to_string());
+                        }
+                    }
+                    Rename::Column { old, new, .. } => {
+                        if self.in_scope(&table.0) {
+                            rename_if_eq(column, old, new);
+                        }
+                    }
+                }
+            }
+            (Expr::Id(id), Rename::Column { old, new, .. }) => {
+                if name_eq(&id.0, old) && self.scopes.iter().any(|scope| scope.unqualified) {
+                    *id = ast::Id(new.to_string());
+                }
+            }
+            (Expr::Exists(select) | Expr::Subquery(select), _) => self.rename_select(select)?,
+            (Expr::InSelect { lhs, rhs, .. }, _) => {
+                self.rename_expr(lhs)?;
+                self.rename_select(rhs)?;
+            }
+            (Expr::InTable { lhs, rhs, args, .. }, rename) => {
+                if let Rename::Table { old, new } = rename {
+                    rename_if_eq(&mut rhs.name, old, new);
+                }
+                self.rename_expr(lhs)?;
+                for arg in args.iter_mut().flatten() {
+                    self.rename_expr(arg)?;
+                }
+            }
+            (Expr::Raise(_, Some(message)), _) => self.rename_expr(message)?,
+            (expr, _) => {
+                for child in child_exprs_mut(expr) {
+                    self.rename_expr(child)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds a reference to `column` of the trigger's table, either in its UPDATE OF list or
+/// through the NEW or OLD row.
+fn trigger_column_reference(trigger: &Trigger, column: &str) -> Result<Option<String>> {
+    if let TriggerEvent::UpdateOf(names) = &trigger.event {
+        if let Some(name) = names.iter().find(|name| name_eq(&name.0, column)) {
+            return Ok(Some(name.0.clone()));
+        }
+    }
+    let mut found = None;
+    let mut rewriter = TriggerExprRewriter::new(|expr: &mut Expr| {
+        if let Expr::Qualified(row, name) = expr {
+            if (name_eq(&row.0, "new") || name_eq(&row.0, "old")) && name_eq(&name.0, column) {
+                found = Some(format!("{}.{}", row.0, name.0));
+            }
+        }
+        Ok(())
+    });
+    if let Some(mut when_clause) = trigger.when_clause.clone() {
+        rewriter.rewrite_expr(&mut when_clause)?;
+    }
+    for mut command in trigger.commands.iter().cloned() {
+        rewriter.rewrite_command(&mut command)?;
+    }
+    Ok(found)
+}
+
+fn add_column(stmt: &mut ast::Stmt, column_def: &str) -> Result<()> {
+    let ast::Stmt::CreateTable { body, .. } = stmt else {
+        return Ok(());
+    };
+    let CreateTableBody::ColumnsAndConstraints { columns, .. } = body.as_mut() else {
+        return Ok(());
+    };
+    let ast::Stmt::CreateTable { body: new_body, .. } =
+        parse_stmt(&format!("CREATE TABLE t({})", column_def))?
+    else {
+        unreachable!();
+    };
+    let CreateTableBody::ColumnsAndConstraints {
+        columns: new_columns,
+        ..
+    } = *new_body
+    else {
+        unreachable!();
+    };
+    columns.extend(new_columns);
+    Ok(())
+}
+
+fn drop_column(stmt: &mut ast::Stmt, column_name: &str) -> Result<()> {
+    let ast::Stmt::CreateTable { tbl_name, body, .. } = stmt else {
+        return Ok(());
+    };
+    let CreateTableBody::ColumnsAndConstraints {
+        columns,
+        constraints,
+        ..
+    } = body.as_mut()
+    else {
+        return Ok(());
+    };
+    let references_column = |expr: &Expr| references_column(expr, column_name);
+    for column in columns.values() {
+        let is_dropped = name_eq(&column.col_name.0, column_name);
+        for constraint in &column.constraints {
+            match &constraint.constraint {
+                ColumnConstraint::Unique(_) if is_dropped => {
+                    bail_parse_error!("cannot drop UNIQUE column: \"{}\"", column_name)
+                }
+                ColumnConstraint::Check(expr) if references_column(expr) => bail_parse_error!(
+                    "error in table {} after drop column: no such column: {}",
+                    tbl_name.name.0,
+                    column_name
+                ),
+                _ => {}
+            }
+        }
+    }
+    for constraint in constraints.iter().flatten() {
+        let references_column = match &constraint.constraint {
+            TableConstraint::PrimaryKey { columns, .. }
+            | TableConstraint::Unique { columns, .. } => {
+                columns.iter().any(|column| references_column(&column.expr))
+            }
+            TableConstraint::Check(expr) => references_column(expr),
+            TableConstraint::ForeignKey { columns, .. } => columns
+                .iter()
+                .any(|column| name_eq(&column.col_name.0, column_name)),
+        };
+        if references_column {
+            bail_parse_error!(
+                "error in table {} after drop column: no such column: {}",
+                tbl_name.name.0,
+                column_name
+            );
+        }
+    }
+    columns.retain(|name, _| !name_eq(&name.0, column_name));
+    Ok(())
+}
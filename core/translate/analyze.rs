@@ -0,0 +1,637 @@
+//! ANALYZE gathers statistics about the tables and their indexes into sqlite_stat1, which the
+//! query planner uses to estimate how many rows each way of looking up a table reads.
+//!
+//! For each index, the row of sqlite_stat1 holds the number of entries of the index, followed
+//! by one number per column: the average number of entries that have the same values in the
+//! columns up to it. A table without an index gets a row with just its number of rows.
+//! See https://www.sqlite.org/fileformat2.html#stat1tab.
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::schema::{BTreeTable, Index, Schema};
+use crate::storage::pager::CreateBTreeFlags;
+use crate::translate::collate::resolve_collation;
+use crate::translate::schema::{emit_schema_entry, SchemaEntryType, SQLITE_TABLEID};
+use crate::translate::{ProgramBuilder, ProgramBuilderOpts, QueryMode};
+use crate::util::{normalize_ident, STAT1_TABLE};
+use crate::vdbe::builder::CursorType;
+use crate::vdbe::insn::{CmpInsFlags, Insn, RegisterOrLiteral};
+use crate::vdbe::BranchOffset;
+use crate::{bail_parse_error, Result, SymbolTable};
+use limbo_sqlite3_parser::ast;
+
+/// A table to analyze.
+pub(crate) struct AnalyzeTarget {
+    pub table: Rc<BTreeTable>,
+    /// The index to analyze when only one of the indexes of the table is.
+    pub only_index: Option<Arc<Index>>,
+    /// For PRAGMA optimize, the number of rows the table had when it was last analyzed. It is
+    /// only analyzed again if that number has changed tenfold since.
+    pub analyzed_rows: Option<u64>,
+}
+
+impl AnalyzeTarget {
+    fn new(table: Rc<BTreeTable>, only_index: Option<Arc<Index>>) -> Self {
+        Self {
+            table,
+            only_index,
+            analyzed_rows: None,
+        }
+    }
+}
+
+pub fn translate_analyze(
+    query_mode: QueryMode,
+    schema: &Schema,
+    name: Option<&ast::QualifiedName>,
+    syms: &SymbolTable,
+) -> Result<ProgramBuilder> {
+    let targets = match name {
+        None => analyzable_tables(schema),
+        Some(name) => {
+            let object_name = normalize_ident(&name.name.0);
+            if name.db_name.is_none() && object_name != "main" {
+                if let Ok(database) = schema.database_index(&object_name) {
+                    if database != 0 {
+                        bail_parse_error!("ANALYZE of an attached database is not supported yet");
+                    }
+                }
+            }
+            if name.db_name.is_none() && object_name == "main" {
+                analyzable_tables(schema)
+            } else if let Some(table) = schema.get_btree_table(&object_name) {
+                vec![AnalyzeTarget::new(table, None)]
+            } else if let Some(index) = schema.get_index(&object_name) {
+                let table = schema.get_btree_table(&index.table_name).unwrap();
+                vec![AnalyzeTarget::new(table, Some(index))]
+            } else {
+                bail_parse_error!("no such table: {}", object_name);
+            }
+        }
+    };
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 3,
+        approx_num_insns: 40 * targets.len() + 20,
+        approx_num_labels: 5 * targets.len(),
+    });
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    emit_analyze(
+        &mut program,
+        schema,
+        &targets,
+        syms,
+        syms.analysis_limit.get(),
+    )?;
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_transaction(true);
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+    Ok(program)
+}
+
+/// The tables that a plain ANALYZE analyzes: all the tables of the database except the
+/// internal ones, in name order.
+pub(crate) fn analyzable_tables(schema: &Schema) -> Vec<AnalyzeTarget> {
+    let mut tables = schema
+        .tables
+        .values()
+        .filter_map(|table| table.btree())
+        .filter(|table| !table.name.to_lowercase().starts_with("sqlite_"))
+        .map(|table| AnalyzeTarget::new(table, None))
+        .collect::<Vec<_>>();
+    tables.sort_by(|a, b| a.table.name.cmp(&b.table.name));
+    tables
+}
+
+/// Emits PRAGMA optimize, which analyzes the tables with indexes that were never analyzed
+/// or whose number of rows has changed tenfold since they were.
+pub(crate) fn emit_optimize(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    syms: &SymbolTable,
+    mask: i64,
+) -> Result<()> {
+    // Bit 0x02 of the mask is the only optimization that is not a no-op for us
+    if mask & 0x02 == 0 {
+        return Ok(());
+    }
+    let targets = analyzable_tables(schema)
+        .into_iter()
+        .filter(|target| !schema.get_indices(&target.table.name).is_empty())
+        .filter_map(|mut target| {
+            match schema.get_table_stats(&target.table.name) {
+                None => {}
+                Some(stats) => match stats.row_count {
+                    Some(row_count) => target.analyzed_rows = Some(row_count),
+                    None if stats.indexes.is_empty() => {}
+                    // Only its partial indexes were analyzed: it has no row count to compare
+                    None => return None,
+                },
+            }
+            Some(target)
+        })
+        .collect::<Vec<_>>();
+    if targets.is_empty() {
+        return Ok(());
+    }
+    // Like SQLite, limit the work of an unlimited analysis
+    let analysis_limit = match syms.analysis_limit.get() {
+        0 => 400,
+        limit => limit,
+    };
+    emit_analyze(program, schema, &targets, syms, analysis_limit)
+}
+
+/// Emits the analysis of `targets`: their rows of sqlite_stat1, which is created if it does
+/// not exist yet, are replaced by new ones, and the statistics of the schema are reloaded.
+pub(crate) fn emit_analyze(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    targets: &[AnalyzeTarget],
+    syms: &SymbolTable,
+    analysis_limit: i64,
+) -> Result<()> {
+    let stat1_table = Rc::new(BTreeTable::from_sql(
+        &format!("CREATE TABLE {}(tbl,idx,stat)", STAT1_TABLE),
+        0,
+    )?);
+    let stat1_cursor_id = program.alloc_cursor_id(
+        Some(STAT1_TABLE.to_owned()),
+        CursorType::BTreeTable(stat1_table.clone()),
+    );
+    let created = match schema.get_btree_table(STAT1_TABLE) {
+        Some(table) => {
+            program.emit_insn(Insn::OpenWrite {
+                cursor_id: stat1_cursor_id,
+                root_page: table.root_page.into(),
+                db: 0,
+            });
+            false
+        }
+        None => {
+            let root_reg = program.alloc_register();
+            program.emit_insn(Insn::CreateBtree {
+                db: 0,
+                root: root_reg,
+                flags: CreateBTreeFlags::new_table(),
+            });
+            let sqlite_schema = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+            let sqlite_schema_cursor_id = program.alloc_cursor_id(
+                Some(SQLITE_TABLEID.to_owned()),
+                CursorType::BTreeTable(sqlite_schema),
+            );
+            program.emit_insn(Insn::OpenWrite {
+                cursor_id: sqlite_schema_cursor_id,
+                root_page: 1usize.into(),
+                db: 0,
+            });
+            emit_schema_entry(
+                program,
+                sqlite_schema_cursor_id,
+                SchemaEntryType::Table,
+                STAT1_TABLE,
+                STAT1_TABLE,
+                root_reg,
+                Some(format!("CREATE TABLE {}(tbl,idx,stat)", STAT1_TABLE)),
+            );
+            program.emit_insn(Insn::OpenWrite {
+                cursor_id: stat1_cursor_id,
+                root_page: RegisterOrLiteral::Register(root_reg),
+                db: 0,
+            });
+            true
+        }
+    };
+
+    // tbl, idx and stat of the row of sqlite_stat1 being built
+    let stat_row_reg = program.alloc_registers(3);
+    for target in targets {
+        let AnalyzeTarget {
+            table, only_index, ..
+        } = target;
+        let skip_label = program.allocate_label();
+        if let Some(analyzed_rows) = target.analyzed_rows {
+            emit_skip_unless_resized(program, table, analyzed_rows, skip_label);
+        }
+        emit_stat1_delete(
+            program,
+            stat1_cursor_id,
+            &table.name,
+            only_index
+                .as_ref()
+                .map(|index| stat1_index_name(table, index)),
+        );
+        program.emit_string8(table.name.clone(), stat_row_reg);
+        let indexes = match only_index {
+            Some(index) => vec![index.clone()],
+            None => schema.get_indices(&table.name).to_vec(),
+        };
+        for index in indexes.iter() {
+            program.emit_string8(stat1_index_name(table, index).to_string(), stat_row_reg + 1);
+            emit_analyze_index(program, index, stat_row_reg, syms, analysis_limit)?;
+            emit_stat1_insert(program, stat1_cursor_id, stat_row_reg);
+        }
+        // Like in SQLite, the number of rows of the table only gets its own row when no index
+        // has an entry for every row
+        if only_index.is_none() && indexes.iter().all(|index| index.where_clause.is_some()) {
+            program.emit_null(stat_row_reg + 1, None);
+            emit_count_table_rows(program, table, stat_row_reg + 2);
+            emit_stat1_insert(program, stat1_cursor_id, stat_row_reg);
+        }
+        program.resolve_label(skip_label, program.offset());
+    }
+
+    if created {
+        program.emit_insn(Insn::ParseSchema {
+            db: 0,
+            where_clause: format!("tbl_name = '{}'", STAT1_TABLE),
+        });
+    }
+    program.emit_insn(Insn::LoadAnalysis { db: 0 });
+    Ok(())
+}
+
+/// Emits the deletion of the rows of sqlite_stat1, if it exists, for the table `tbl` or its
+/// index `idx` that is being dropped.
+pub(crate) fn emit_clear_stat1(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    tbl: &str,
+    idx: Option<&str>,
+) {
+    let Some(stat1_table) = schema.get_btree_table(STAT1_TABLE) else {
+        return;
+    };
+    if normalize_ident(tbl) == STAT1_TABLE {
+        return;
+    }
+    let stat1_cursor_id = program.alloc_cursor_id(
+        Some(STAT1_TABLE.to_owned()),
+        CursorType::BTreeTable(stat1_table.clone()),
+    );
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: stat1_cursor_id,
+        root_page: stat1_table.root_page.into(),
+        db: schema.database,
+    });
+    emit_stat1_delete(program, stat1_cursor_id, tbl, idx);
+    program.emit_insn(Insn::Close {
+        cursor_id: stat1_cursor_id,
+    });
+}
+
+/// Emits the deletion of the rows of sqlite_stat1 for the table `tbl`, or only for its index
+/// `idx`.
+fn emit_stat1_delete(
+    program: &mut ProgramBuilder,
+    stat1_cursor_id: usize,
+    tbl: &str,
+    idx: Option<&str>,
+) {
+    let column_reg = program.alloc_register();
+    let tbl_reg = program.emit_string8_new_reg(tbl.to_string());
+    let idx_reg = idx.map(|idx| program.emit_string8_new_reg(idx.to_string()));
+    let end_loop_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: stat1_cursor_id,
+        pc_if_empty: end_loop_label,
+    });
+    let loop_start = program.offset();
+    let next_label = program.allocate_label();
+    let names = std::iter::once((0, tbl_reg)).chain(idx_reg.map(|idx_reg| (1, idx_reg)));
+    for (column, name_reg) in names {
+        program.emit_insn(Insn::Column {
+            cursor_id: stat1_cursor_id,
+            column,
+            dest: column_reg,
+        });
+        program.emit_insn(Insn::Ne {
+            lhs: column_reg,
+            rhs: name_reg,
+            target_pc: next_label,
+            flags: CmpInsFlags::default().jump_if_null(),
+        });
+    }
+    program.emit_insn(Insn::Delete {
+        cursor_id: stat1_cursor_id,
+        count_change: false,
+    });
+    program.resolve_label(next_label, program.offset());
+    program.emit_insn(Insn::Next {
+        cursor_id: stat1_cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(end_loop_label, program.offset());
+}
+
+/// The name of `index` in the idx column of sqlite_stat1. Like in SQLite, the index of the
+/// PRIMARY KEY of a WITHOUT ROWID table, which is the table itself, has the name of the table.
+fn stat1_index_name<'a>(table: &'a BTreeTable, index: &'a Index) -> &'a str {
+    if table.is_primary_key_index(index) {
+        &table.name
+    } else {
+        &index.name
+    }
+}
+
+/// Emits a scan of `index` that leaves its statistics in `stat_row_reg + 2`, or NULL there if
+/// the index is empty.
+///
+/// The scan counts the entries, and for each column how many times the values of the columns
+/// up to it change from one entry to the next, which is the number of distinct keys of that
+/// prefix. With PRAGMA analysis_limit, only that many entries are compared, and the averages
+/// are estimated from them.
+fn emit_analyze_index(
+    program: &mut ProgramBuilder,
+    index: &Arc<Index>,
+    stat_row_reg: usize,
+    syms: &SymbolTable,
+    analysis_limit: i64,
+) -> Result<()> {
+    let column_count = index.columns.len();
+    let collations = index
+        .columns
+        .iter()
+        .map(|column| resolve_collation(column.collation.as_deref(), syms))
+        .collect::<Result<Vec<_>>>()?;
+
+    let cursor_id = program.alloc_cursor_id(
+        Some(index.name.clone()),
+        CursorType::BTreeIndex(index.clone()),
+    );
+    program.emit_insn(Insn::OpenRead {
+        cursor_id,
+        root_page: index.root_page,
+        db: 0,
+    });
+    program.emit_null(stat_row_reg + 2, None);
+
+    let row_count_reg = program.alloc_register();
+    let distinct_regs = program.alloc_registers(column_count);
+    let prev_regs = program.alloc_registers(column_count);
+    let column_reg = program.alloc_register();
+    program.emit_int(0, row_count_reg);
+    for i in 0..column_count {
+        program.emit_int(0, distinct_regs + i);
+    }
+    // Without a limit, every entry is compared
+    let (compared_reg, left_reg) = if analysis_limit > 0 {
+        let compared_reg = program.alloc_register();
+        let left_reg = program.alloc_register();
+        program.emit_int(0, compared_reg);
+        program.emit_int(analysis_limit, left_reg);
+        (compared_reg, Some(left_reg))
+    } else {
+        (row_count_reg, None)
+    };
+
+    let end_label = program.allocate_label();
+    let count_row_label = program.allocate_label();
+    let changed_labels = (0..column_count)
+        .map(|_| program.allocate_label())
+        .collect::<Vec<_>>();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: end_label,
+    });
+    // The first entry starts a new key in every column
+    emit_count_compared(program, compared_reg, left_reg);
+    program.emit_insn(Insn::Goto {
+        target_pc: changed_labels[0],
+    });
+    let loop_start = program.offset();
+    if let Some(left_reg) = left_reg {
+        program.emit_insn(Insn::IfNot {
+            reg: left_reg,
+            target_pc: count_row_label,
+            jump_if_null: true,
+        });
+    }
+    emit_count_compared(program, compared_reg, left_reg);
+    for (i, collation) in collations.iter().enumerate() {
+        program.emit_insn(Insn::Column {
+            cursor_id,
+            column: i,
+            dest: column_reg,
+        });
+        program.emit_insn(Insn::Ne {
+            lhs: column_reg,
+            rhs: prev_regs + i,
+            target_pc: changed_labels[i],
+            flags: CmpInsFlags::default().null_eq().with_collation(*collation),
+        });
+    }
+    program.emit_insn(Insn::Goto {
+        target_pc: count_row_label,
+    });
+    // A change in a column is a new key for it and for all the columns after it
+    for (i, changed_label) in changed_labels.into_iter().enumerate() {
+        program.resolve_label(changed_label, program.offset());
+        program.emit_insn(Insn::AddImm {
+            register: distinct_regs + i,
+            value: 1,
+        });
+        program.emit_insn(Insn::Column {
+            cursor_id,
+            column: i,
+            dest: prev_regs + i,
+        });
+    }
+    program.resolve_label(count_row_label, program.offset());
+    program.emit_insn(Insn::AddImm {
+        register: row_count_reg,
+        value: 1,
+    });
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_start,
+    });
+
+    // "<rows> <rows per key of column 1> ...", rounding the averages up
+    let stat_reg = stat_row_reg + 2;
+    let space_reg = program.emit_string8_new_reg(" ".to_string());
+    let average_reg = program.alloc_register();
+    let two_reg = program.alloc_register();
+    let ten_reg = program.alloc_register();
+    let eleven_reg = program.alloc_register();
+    let scaled_rows_reg = program.alloc_register();
+    let scaled_distinct_reg = program.alloc_register();
+    program.emit_int(2, two_reg);
+    program.emit_int(10, ten_reg);
+    program.emit_int(11, eleven_reg);
+    program.emit_insn(Insn::Copy {
+        src_reg: row_count_reg,
+        dst_reg: stat_reg,
+        amount: 0,
+    });
+    for i in 0..column_count {
+        program.emit_insn(Insn::Add {
+            lhs: compared_reg,
+            rhs: distinct_regs + i,
+            dest: average_reg,
+        });
+        program.emit_insn(Insn::AddImm {
+            register: average_reg,
+            value: -1,
+        });
+        program.emit_insn(Insn::Divide {
+            lhs: average_reg,
+            rhs: distinct_regs + i,
+            dest: average_reg,
+        });
+        // Like in SQLite, an average of 2 is rounded down to 1 when the keys are almost
+        // unique, i.e. when at most 10% of the entries share a key with another one
+        let rounded_label = program.allocate_label();
+        program.emit_insn(Insn::Ne {
+            lhs: average_reg,
+            rhs: two_reg,
+            target_pc: rounded_label,
+            flags: CmpInsFlags::default(),
+        });
+        program.emit_insn(Insn::Multiply {
+            lhs: compared_reg,
+            rhs: ten_reg,
+            dest: scaled_rows_reg,
+        });
+        program.emit_insn(Insn::Multiply {
+            lhs: distinct_regs + i,
+            rhs: eleven_reg,
+            dest: scaled_distinct_reg,
+        });
+        program.emit_insn(Insn::Gt {
+            lhs: scaled_rows_reg,
+            rhs: scaled_distinct_reg,
+            target_pc: rounded_label,
+            flags: CmpInsFlags::default(),
+        });
+        program.emit_int(1, average_reg);
+        program.resolve_label(rounded_label, program.offset());
+        program.emit_insn(Insn::Concat {
+            lhs: stat_reg,
+            rhs: space_reg,
+            dest: stat_reg,
+        });
+        program.emit_insn(Insn::Concat {
+            lhs: stat_reg,
+            rhs: average_reg,
+            dest: stat_reg,
+        });
+    }
+    program.resolve_label(end_label, program.offset());
+    program.emit_insn(Insn::Close { cursor_id });
+    Ok(())
+}
+
+/// With PRAGMA analysis_limit, emits the counting of an entry that is compared in
+/// `compared_reg`, and in `left_reg` of how many more can be.
+fn emit_count_compared(program: &mut ProgramBuilder, compared_reg: usize, left_reg: Option<usize>) {
+    if let Some(left_reg) = left_reg {
+        program.emit_insn(Insn::AddImm {
+            register: left_reg,
+            value: -1,
+        });
+        program.emit_insn(Insn::AddImm {
+            register: compared_reg,
+            value: 1,
+        });
+    }
+}
+
+/// Emits a scan of `table` that counts its rows into `dest`.
+fn emit_count_table_rows(program: &mut ProgramBuilder, table: &Rc<BTreeTable>, dest: usize) {
+    let cursor_id = program.alloc_cursor_id(
+        Some(table.name.clone()),
+        CursorType::BTreeTable(table.clone()),
+    );
+    program.emit_insn(Insn::OpenRead {
+        cursor_id,
+        root_page: table.root_page,
+        db: 0,
+    });
+    program.emit_int(0, dest);
+    let end_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: end_label,
+    });
+    let loop_start = program.offset();
+    program.emit_insn(Insn::AddImm {
+        register: dest,
+        value: 1,
+    });
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(end_label, program.offset());
+    program.emit_insn(Insn::Close { cursor_id });
+}
+
+/// Emits a jump to `skip_label` unless the number of rows of `table` is at least ten times
+/// larger or smaller than `analyzed_rows`.
+fn emit_skip_unless_resized(
+    program: &mut ProgramBuilder,
+    table: &Rc<BTreeTable>,
+    analyzed_rows: u64,
+    skip_label: BranchOffset,
+) {
+    let count_reg = program.alloc_register();
+    emit_count_table_rows(program, table, count_reg);
+    let bounds_reg = program.alloc_registers(2);
+    program.emit_int((analyzed_rows / 10) as i64, bounds_reg);
+    program.emit_int(analyzed_rows.saturating_mul(10) as i64, bounds_reg + 1);
+    let analyze_label = program.allocate_label();
+    program.emit_insn(Insn::Le {
+        lhs: count_reg,
+        rhs: bounds_reg,
+        target_pc: analyze_label,
+        flags: CmpInsFlags::default(),
+    });
+    program.emit_insn(Insn::Lt {
+        lhs: count_reg,
+        rhs: bounds_reg + 1,
+        target_pc: skip_label,
+        flags: CmpInsFlags::default(),
+    });
+    program.resolve_label(analyze_label, program.offset());
+}
+
+/// Emits the insertion of the row of sqlite_stat1 in the 3 registers at `stat_row_reg`,
+/// unless its stat is NULL or 0, which it is for an empty index or table.
+fn emit_stat1_insert(program: &mut ProgramBuilder, stat1_cursor_id: usize, stat_row_reg: usize) {
+    let skip_label = program.allocate_label();
+    program.emit_insn(Insn::IfNot {
+        reg: stat_row_reg + 2,
+        target_pc: skip_label,
+        jump_if_null: true,
+    });
+    // Like in SQLite, the stat column holds text even for a single number
+    program.emit_insn(Insn::Affinity {
+        start_reg: stat_row_reg + 2,
+        count: std::num::NonZeroUsize::new(1).unwrap(),
+        affinities: "B".to_string(),
+    });
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::NewRowid {
+        cursor: stat1_cursor_id,
+        rowid_reg,
+        prev_largest_reg: 0,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: stat_row_reg,
+        count: 3,
+        dest_reg: record_reg,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: stat1_cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: 0,
+    });
+    program.resolve_label(skip_label, program.offset());
+}
@@ -0,0 +1,77 @@
+use crate::translate::emitter::Resolver;
+use crate::translate::expr::translate_expr;
+use crate::translate::{ProgramBuilder, ProgramBuilderOpts};
+use crate::vdbe::insn::Insn;
+use crate::{bail_parse_error, QueryMode, Result, SymbolTable};
+use limbo_sqlite3_parser::ast::{self, Expr};
+
+pub fn translate_attach(
+    query_mode: QueryMode,
+    expr: &Expr,
+    db_name: &Expr,
+    key: Option<&Expr>,
+    syms: &SymbolTable,
+) -> Result<ProgramBuilder> {
+    if key.is_some() {
+        bail_parse_error!("ATTACH with a KEY is not supported");
+    }
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 0,
+        approx_num_insns: 4,
+        approx_num_labels: 0,
+    });
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    let path_reg = program.alloc_register();
+    let name_reg = program.alloc_register();
+    let resolver = Resolver::new(syms);
+    translate_name_expr(&mut program, expr, path_reg, &resolver)?;
+    translate_name_expr(&mut program, db_name, name_reg, &resolver)?;
+    program.emit_insn(Insn::Attach { path_reg, name_reg });
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_goto(start_offset);
+    Ok(program)
+}
+
+pub fn translate_detach(
+    query_mode: QueryMode,
+    db_name: &Expr,
+    syms: &SymbolTable,
+) -> Result<ProgramBuilder> {
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 0,
+        approx_num_insns: 3,
+        approx_num_labels: 0,
+    });
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    let name_reg = program.alloc_register();
+    translate_name_expr(&mut program, db_name, name_reg, &Resolver::new(syms))?;
+    program.emit_insn(Insn::Detach { name_reg });
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_goto(start_offset);
+    Ok(program)
+}
+
+/// Evaluates the file name or the schema name of ATTACH and DETACH. Like in SQLite, a bare
+/// identifier stands for its own name rather than for a column.
+fn translate_name_expr(
+    program: &mut ProgramBuilder,
+    expr: &Expr,
+    target_register: usize,
+    resolver: &Resolver,
+) -> Result<()> {
+    match expr {
+        Expr::Id(ast::Id(name)) | Expr::Name(ast::Name(name)) => {
+            program.emit_string8(name.clone(), target_register);
+        }
+        _ => {
+            translate_expr(program, None, expr, target_register, resolver)?;
+        }
+    }
+    Ok(())
+}
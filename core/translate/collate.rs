@@ -0,0 +1,135 @@
+//! Assigning collating sequences to expressions.
+//! https://www.sqlite.org/datatype3.html#assigning_collating_sequences_from_sql
+
+use limbo_sqlite3_parser::ast;
+
+use crate::{collation::CollationSeq, util::normalize_ident, Result, SymbolTable};
+
+use super::plan::TableReference;
+
+/// The collating sequence of an expression, and whether it was assigned explicitly
+/// with the COLLATE operator or implicitly by a column definition.
+#[derive(Debug, Clone, Copy)]
+struct ExprCollation<'a> {
+    name: &'a str,
+    explicit: bool,
+}
+
+fn collation_of<'a>(
+    expr: &'a ast::Expr,
+    tables: Option<&'a [TableReference]>,
+) -> Option<ExprCollation<'a>> {
+    match expr {
+        ast::Expr::Column { table, column, .. } => {
+            let column = tables?.get(*table)?.columns().get(*column)?;
+            // A column without a declared collating sequence implicitly uses BINARY.
+            Some(ExprCollation {
+                name: column.collation.as_deref().unwrap_or("BINARY"),
+                explicit: false,
+            })
+        }
+        ast::Expr::Parenthesized(exprs) if exprs.len() == 1 => collation_of(&exprs[0], tables),
+        ast::Expr::Cast { expr, .. } | ast::Expr::Unary(ast::UnaryOperator::Positive, expr) => {
+            collation_of(expr, tables)
+        }
+        // Only an explicit collating sequence propagates from the operands of other expressions.
+        _ => explicit_collation(expr).map(|name| ExprCollation {
+            name,
+            explicit: true,
+        }),
+    }
+}
+
+/// Find the left-most collating sequence assigned with the COLLATE operator within `expr`.
+fn explicit_collation(expr: &ast::Expr) -> Option<&str> {
+    match expr {
+        ast::Expr::Collate(_, name) => Some(name),
+        ast::Expr::Binary(lhs, _, rhs) => {
+            explicit_collation(lhs).or_else(|| explicit_collation(rhs))
+        }
+        ast::Expr::Parenthesized(exprs) => exprs.iter().find_map(explicit_collation),
+        ast::Expr::FunctionCall {
+            args: Some(args), ..
+        } => args.iter().find_map(explicit_collation),
+        ast::Expr::Cast { expr, .. } | ast::Expr::Unary(_, expr) => explicit_collation(expr),
+        _ => None,
+    }
+}
+
+/// The name of the collating sequence of `expr`, if it has one.
+/// An expression without a collating sequence uses BINARY.
+pub fn expr_collation_name<'a>(
+    expr: &'a ast::Expr,
+    tables: Option<&'a [TableReference]>,
+) -> Option<&'a str> {
+    collation_of(expr, tables).map(|c| c.name)
+}
+
+/// The name of the collating sequence used to compare `lhs` with `rhs`, if any.
+/// An explicit collating sequence takes precedence over one of a column, and the left operand
+/// takes precedence over the right one.
+pub fn binary_compare_collation_name<'a>(
+    lhs: &'a ast::Expr,
+    rhs: &'a ast::Expr,
+    tables: Option<&'a [TableReference]>,
+) -> Option<&'a str> {
+    compare_collation(collation_of(lhs, tables), collation_of(rhs, tables))
+}
+
+/// Like [binary_compare_collation_name], for operands that refer to different tables, e.g. the
+/// left-hand side of `x IN (SELECT y FROM ...)` and the column of the subquery.
+pub fn subquery_compare_collation_name<'a>(
+    lhs: &'a ast::Expr,
+    lhs_tables: Option<&'a [TableReference]>,
+    rhs: &'a ast::Expr,
+    rhs_tables: Option<&'a [TableReference]>,
+) -> Option<&'a str> {
+    compare_collation(collation_of(lhs, lhs_tables), collation_of(rhs, rhs_tables))
+}
+
+fn compare_collation<'a>(
+    lhs: Option<ExprCollation<'a>>,
+    rhs: Option<ExprCollation<'a>>,
+) -> Option<&'a str> {
+    match (lhs, rhs) {
+        (Some(lhs), _) if lhs.explicit => Some(lhs.name),
+        (_, Some(rhs)) if rhs.explicit => Some(rhs.name),
+        (lhs, rhs) => lhs.or(rhs).map(|c| c.name),
+    }
+}
+
+/// Look up a collating sequence by name, failing if it does not exist.
+/// No name means BINARY.
+pub fn resolve_collation(name: Option<&str>, syms: &SymbolTable) -> Result<CollationSeq> {
+    let Some(name) = name else {
+        return Ok(CollationSeq::Binary);
+    };
+    match syms.resolve_collation(&normalize_ident(name)) {
+        Some(collation) => Ok(collation),
+        None => crate::bail_parse_error!("no such collation sequence: {}", name),
+    }
+}
+
+pub fn expr_collation(
+    expr: &ast::Expr,
+    tables: Option<&[TableReference]>,
+    syms: &SymbolTable,
+) -> Result<CollationSeq> {
+    resolve_collation(expr_collation_name(expr, tables), syms)
+}
+
+pub fn binary_compare_collation(
+    lhs: &ast::Expr,
+    rhs: &ast::Expr,
+    tables: Option<&[TableReference]>,
+    syms: &SymbolTable,
+) -> Result<CollationSeq> {
+    resolve_collation(binary_compare_collation_name(lhs, rhs, tables), syms)
+}
+
+/// Whether two collating sequence names refer to the same collating sequence.
+/// No name means BINARY.
+pub fn same_collation(lhs: Option<&str>, rhs: Option<&str>) -> bool {
+    let normalize = |name: Option<&str>| name.map_or("binary".to_string(), normalize_ident);
+    normalize(lhs) == normalize(rhs)
+}
@@ -0,0 +1,327 @@
+use limbo_sqlite3_parser::ast;
+
+use crate::{
+    collation::CollationSeq,
+    util::normalize_ident,
+    vdbe::{builder::ProgramBuilder, insn::Insn, CursorID},
+    Result,
+};
+
+use super::{
+    collate::{expr_collation_name, resolve_collation},
+    distinct::{emit_open_distinct_index, DistinctMetadata},
+    emitter::{emit_query, Resolver, TranslateCtx},
+    main_loop::LoopLabels,
+    plan::{SelectPlan, SelectQueryType},
+    result_row::emit_result_row,
+};
+
+/// Emit a compound SELECT, i.e. a SELECT whose plan has one or more compound members.
+/// Returns the start register of the result columns.
+///
+/// All members write their result columns into the same registers, so that a parent query reading
+/// the compound as a subquery does not need to know which member produced a given row.
+/// Compound operators are left-associative, so the members are emitted from left to right:
+/// - UNION ALL sends the rows of both sides directly to the destination of the compound.
+/// - UNION inserts the rows of both sides into an ephemeral index, and then sends the distinct
+///   rows of the index to the destination.
+/// - EXCEPT and INTERSECT insert the rows of each side into an ephemeral index of its own, and then
+///   send the distinct rows of the left index that are absent from (EXCEPT) or present in
+///   (INTERSECT) the right index to the destination.
+pub fn emit_compound_select(
+    program: &mut ProgramBuilder,
+    plan: &mut SelectPlan,
+    t_ctx: &mut TranslateCtx,
+) -> Result<usize> {
+    let reg_result_cols_start = match t_ctx.reg_result_cols_start {
+        Some(reg) => reg,
+        None => program.alloc_registers(plan.result_columns.len()),
+    };
+    t_ctx.reg_result_cols_start = Some(reg_result_cols_start);
+
+    // The left-most member is the plan itself; detach the other members while emitting so that
+    // the left-most member can be emitted like any other single SELECT.
+    let mut compounds = std::mem::take(&mut plan.compounds);
+    let destination = plan.query_type.clone();
+    let result = emit_compound_members(
+        program,
+        &t_ctx.resolver,
+        plan,
+        &mut compounds,
+        &destination,
+        reg_result_cols_start,
+    );
+    plan.query_type = destination;
+    plan.compounds = compounds;
+    result?;
+
+    Ok(reg_result_cols_start)
+}
+
+/// Which rows of an ephemeral index are sent to the destination when the index is scanned.
+enum ScanFilter {
+    /// Every distinct row.
+    None,
+    /// Distinct rows that are not in the index opened on the given cursor.
+    NotIn(CursorID),
+    /// Distinct rows that are also in the index opened on the given cursor.
+    In(CursorID),
+}
+
+/// Emit `first` combined with all of `rest`, sending the resulting rows to `destination`.
+fn emit_compound_members(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    first: &mut SelectPlan,
+    rest: &mut [(ast::CompoundOperator, SelectPlan)],
+    destination: &SelectQueryType,
+    reg_result_cols_start: usize,
+) -> Result<()> {
+    let Some(((operator, last), rest)) = rest.split_last_mut() else {
+        return emit_compound_member(program, resolver, first, destination, reg_result_cols_start);
+    };
+    // The rows are compared with the collating sequences of the result columns of the left-most
+    // member.
+    let collation_names: Vec<Option<String>> = first
+        .result_columns
+        .iter()
+        .map(|rc| expr_collation_name(&rc.expr, Some(&first.table_references)).map(normalize_ident))
+        .collect();
+    let collations = collation_names
+        .iter()
+        .map(|name| resolve_collation(name.as_deref(), resolver.symbol_table))
+        .collect::<Result<Vec<_>>>()?;
+    match operator {
+        ast::CompoundOperator::UnionAll => {
+            emit_compound_members(
+                program,
+                resolver,
+                first,
+                rest,
+                destination,
+                reg_result_cols_start,
+            )?;
+            emit_compound_member(program, resolver, last, destination, reg_result_cols_start)?;
+        }
+        ast::CompoundOperator::Union => {
+            // If the destination is already an index, e.g. in `a UNION b UNION c`, the rows can be
+            // inserted into it directly, since duplicates are skipped when it is scanned anyway.
+            let (cursor_id, affinity, owns_index) = match destination {
+                SelectQueryType::IndexInsert {
+                    cursor_id,
+                    affinity,
+                } => (*cursor_id, *affinity, false),
+                _ => (
+                    emit_open_distinct_index(program, &collation_names),
+                    None,
+                    true,
+                ),
+            };
+            let index_destination = SelectQueryType::IndexInsert {
+                cursor_id,
+                affinity,
+            };
+            emit_compound_members(
+                program,
+                resolver,
+                first,
+                rest,
+                &index_destination,
+                reg_result_cols_start,
+            )?;
+            emit_compound_member(
+                program,
+                resolver,
+                last,
+                &index_destination,
+                reg_result_cols_start,
+            )?;
+            if owns_index {
+                emit_index_scan(
+                    program,
+                    cursor_id,
+                    ScanFilter::None,
+                    destination,
+                    reg_result_cols_start,
+                    &collations,
+                );
+            }
+        }
+        ast::CompoundOperator::Except | ast::CompoundOperator::Intersect => {
+            let left_cursor_id = emit_open_distinct_index(program, &collation_names);
+            emit_compound_members(
+                program,
+                resolver,
+                first,
+                rest,
+                &SelectQueryType::IndexInsert {
+                    cursor_id: left_cursor_id,
+                    affinity: None,
+                },
+                reg_result_cols_start,
+            )?;
+            let right_cursor_id = emit_open_distinct_index(program, &collation_names);
+            emit_compound_member(
+                program,
+                resolver,
+                last,
+                &SelectQueryType::IndexInsert {
+                    cursor_id: right_cursor_id,
+                    affinity: None,
+                },
+                reg_result_cols_start,
+            )?;
+            let filter = if *operator == ast::CompoundOperator::Except {
+                ScanFilter::NotIn(right_cursor_id)
+            } else {
+                ScanFilter::In(right_cursor_id)
+            };
+            emit_index_scan(
+                program,
+                left_cursor_id,
+                filter,
+                destination,
+                reg_result_cols_start,
+                &collations,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Emit a single member of a compound SELECT, sending its rows to `destination`.
+/// Each member is a regular SELECT with its own loops, so it gets its own [TranslateCtx].
+fn emit_compound_member(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    plan: &mut SelectPlan,
+    destination: &SelectQueryType,
+    reg_result_cols_start: usize,
+) -> Result<()> {
+    plan.query_type = destination.clone();
+    let mut t_ctx = TranslateCtx {
+        labels_main_loop: (0..plan.table_references.len())
+            .map(|_| LoopLabels::new(program))
+            .collect(),
+        label_main_loop_end: None,
+        meta_group_by: None,
+        meta_left_joins: (0..plan.table_references.len()).map(|_| None).collect(),
+        meta_right_joins: (0..plan.table_references.len()).map(|_| None).collect(),
+        meta_sort: None,
+        meta_windows: vec![],
+        meta_distinct: DistinctMetadata::default(),
+        reg_agg_start: None,
+        reg_nonagg_emit_once_flag: None,
+        reg_result_cols_start: Some(reg_result_cols_start),
+        result_column_indexes_in_orderby_sorter: (0..plan.result_columns.len()).collect(),
+        result_columns_to_skip_in_orderby_sorter: None,
+        reg_limit: None,
+        reg_offset: None,
+        reg_limit_offset_sum: None,
+        resolver: resolver.nested(),
+        omit_predicates: Vec::new(),
+    };
+    emit_query(program, plan, &mut t_ctx)?;
+    Ok(())
+}
+
+/// Send the distinct rows of an ephemeral index that pass `filter` to `destination`.
+/// The index is sorted, so duplicates are adjacent and are skipped by comparing each row with the
+/// previous one.
+fn emit_index_scan(
+    program: &mut ProgramBuilder,
+    cursor_id: CursorID,
+    filter: ScanFilter,
+    destination: &SelectQueryType,
+    reg_result_cols_start: usize,
+    collations: &[CollationSeq],
+) {
+    let num_cols = collations.len();
+    // The key of an index entry is the row followed by a constant 0, see emit_index_insert()
+    let key_len = num_cols + 1;
+    let reg_key_start = program.alloc_registers(key_len);
+    let reg_prev_row_start = program.alloc_registers(num_cols);
+    let reg_has_prev_row = program.alloc_register();
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: reg_has_prev_row,
+    });
+
+    let label_scan_end = program.allocate_label();
+    let label_next = program.allocate_label();
+    let label_distinct = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: label_scan_end,
+    });
+    let loop_start = program.offset();
+    for i in 0..key_len {
+        program.emit_insn(Insn::Column {
+            cursor_id,
+            column: i,
+            dest: reg_key_start + i,
+        });
+    }
+    program.emit_insn(Insn::IfNot {
+        reg: reg_has_prev_row,
+        target_pc: label_distinct,
+        jump_if_null: true,
+    });
+    program.emit_insn(Insn::Compare {
+        start_reg_a: reg_key_start,
+        start_reg_b: reg_prev_row_start,
+        count: num_cols,
+        collations: collations.to_vec(),
+    });
+    program.emit_insn(Insn::Jump {
+        target_pc_lt: label_distinct,
+        target_pc_eq: label_next,
+        target_pc_gt: label_distinct,
+    });
+    program.resolve_label(label_distinct, program.offset());
+    program.emit_insn(Insn::Integer {
+        value: 1,
+        dest: reg_has_prev_row,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_key_start,
+        dst_reg: reg_prev_row_start,
+        amount: num_cols - 1,
+    });
+    match filter {
+        ScanFilter::None => {}
+        ScanFilter::NotIn(filter_cursor_id) => {
+            let label_keep = program.allocate_label();
+            program.emit_insn(Insn::NotFound {
+                cursor_id: filter_cursor_id,
+                target_pc: label_keep,
+                record_reg: reg_key_start,
+                num_regs: key_len,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: label_next,
+            });
+            program.resolve_label(label_keep, program.offset());
+        }
+        ScanFilter::In(filter_cursor_id) => {
+            program.emit_insn(Insn::NotFound {
+                cursor_id: filter_cursor_id,
+                target_pc: label_next,
+                record_reg: reg_key_start,
+                num_regs: key_len,
+            });
+        }
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_key_start,
+        dst_reg: reg_result_cols_start,
+        amount: num_cols - 1,
+    });
+    emit_result_row(program, destination, reg_result_cols_start, num_cols);
+    program.resolve_label(label_next, program.offset());
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(label_scan_end, program.offset());
+}
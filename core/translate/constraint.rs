@@ -0,0 +1,1121 @@
+//! Enforcement of the NOT NULL, CHECK, PRIMARY KEY and UNIQUE constraints of a table when a row is
+//! written, and maintenance of the indexes of the table.
+//!
+//! The row being written is a block of registers that holds its rowid followed by the columns
+//! of the table, as built by INSERT and UPDATE. The register of a rowid alias column holds NULL.
+
+use std::num::NonZeroUsize;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use limbo_sqlite3_parser::ast::{Expr, ResolveType, TriggerEvent, TriggerTime};
+
+use crate::error::{
+    SQLITE_CONSTRAINT_CHECK, SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY,
+    SQLITE_CONSTRAINT_UNIQUE,
+};
+use crate::schema::{BTreeTable, Index, Schema};
+use crate::util::normalize_ident;
+use crate::vdbe::builder::{CursorType, ProgramBuilder};
+use crate::vdbe::insn::{CmpInsFlags, IdxInsertFlags, Insn, RegisterOrLiteral};
+use crate::vdbe::{BranchOffset, CursorID};
+use crate::{Result, SymbolTable};
+
+use super::emitter::Resolver;
+use super::expr::translate_expr;
+use super::foreign_key::ForeignKeyChecks;
+use super::optimizer::rewrite_expr;
+use super::trigger::RowTriggers;
+use super::window::child_exprs_mut;
+
+/// A write cursor on an index of the table being written.
+pub struct IndexCursor {
+    pub index: Arc<Index>,
+    pub cursor_id: CursorID,
+    /// Whether the statement may change the key of a row in the index. Only those indexes are
+    /// checked and rewritten when a row is written, but all of them lose the entries of a
+    /// deleted row.
+    pub changes: bool,
+}
+
+/// The cursors through which a statement writes a table and its indexes.
+pub struct WriteCursors {
+    pub table: CursorID,
+    pub indexes: Vec<IndexCursor>,
+}
+
+impl WriteCursors {
+    /// Allocates and opens a write cursor on each of the indexes of `table` in `database`, along
+    /// with whether the statement changes the keys in it. The PRIMARY KEY index of a WITHOUT
+    /// ROWID table is the table itself, and is written through the table cursor.
+    pub fn open(
+        program: &mut ProgramBuilder,
+        table: &BTreeTable,
+        table_cursor_id: CursorID,
+        database: usize,
+        indexes: impl IntoIterator<Item = (Arc<Index>, bool)>,
+    ) -> Self {
+        let indexes = indexes
+            .into_iter()
+            .filter(|(index, _)| !table.is_primary_key_index(index))
+            .map(|(index, changes)| {
+                let cursor_id =
+                    program.alloc_cursor_id(None, CursorType::BTreeIndex(index.clone()));
+                program.emit_insn(Insn::OpenWrite {
+                    cursor_id,
+                    root_page: RegisterOrLiteral::Literal(index.root_page),
+                    db: database,
+                });
+                IndexCursor {
+                    index,
+                    cursor_id,
+                    changes,
+                }
+            })
+            .collect();
+        Self {
+            table: table_cursor_id,
+            indexes,
+        }
+    }
+
+    /// The same cursors, for a statement that changes the keys of the indexes on
+    /// `updated_columns` of `table`.
+    pub fn for_update(&self, table: &BTreeTable, updated_columns: &[usize]) -> Self {
+        let indexes = self
+            .indexes
+            .iter()
+            .map(|index_cursor| IndexCursor {
+                index: index_cursor.index.clone(),
+                cursor_id: index_cursor.cursor_id,
+                changes: updated_columns
+                    .iter()
+                    .any(|pos| index_cursor.index.depends_on_column(table, *pos)),
+            })
+            .collect();
+        Self {
+            table: self.table,
+            indexes,
+        }
+    }
+}
+
+/// How to resolve a row that has the same rowid or UNIQUE key as an existing row.
+#[derive(Debug, Clone, Copy)]
+pub enum OnConflict {
+    /// Resolve the conflict with the algorithm.
+    Resolve(ResolveType),
+    /// Go to `target` with the rowid of the existing row in `rowid_reg`, for the DO UPDATE
+    /// clause of an upsert. In a WITHOUT ROWID table, the registers from `rowid_reg` get the
+    /// PRIMARY KEY of the existing row instead.
+    Goto {
+        target: BranchOffset,
+        rowid_reg: usize,
+    },
+}
+
+/// What else happens to a row that REPLACE deletes. Like in SQLite, with PRAGMA foreign_keys on,
+/// the foreign keys of the deleted row are checked and the ON DELETE actions of the ones that refer
+/// to it run, but the DELETE triggers of the table do not fire.
+#[derive(Debug, Clone, Default)]
+pub struct ReplaceDeletes {
+    foreign_keys: ForeignKeyChecks,
+    fk_actions: RowTriggers,
+}
+
+impl ReplaceDeletes {
+    pub fn compile(
+        program: &mut ProgramBuilder,
+        schema: &Schema,
+        table: &Rc<BTreeTable>,
+        syms: &SymbolTable,
+    ) -> Result<Self> {
+        Ok(Self {
+            foreign_keys: ForeignKeyChecks::compile(schema, table, None, syms)?,
+            fk_actions: RowTriggers::compile_foreign_key_actions(
+                program,
+                schema,
+                &table.name,
+                &table.columns,
+                TriggerEvent::Delete,
+                &[],
+                syms,
+            )?,
+        })
+    }
+
+    /// Deletes the row at the table cursor.
+    fn emit(
+        &self,
+        program: &mut ProgramBuilder,
+        table: &BTreeTable,
+        cursors: &WriteCursors,
+        skip_row: BranchOffset,
+        resolver: &Resolver,
+    ) -> Result<()> {
+        let old_row = (!self.foreign_keys.is_empty() || self.fk_actions.has(TriggerTime::After))
+            .then(|| emit_load_row(program, table, cursors.table));
+        self.foreign_keys.emit(program, old_row, None);
+        emit_delete_index_entries(program, table, cursors, true, resolver)?;
+        program.emit_insn(Insn::Delete {
+            cursor_id: cursors.table,
+            count_change: false,
+        });
+        self.fk_actions
+            .emit(program, TriggerTime::After, old_row, None, skip_row);
+        Ok(())
+    }
+}
+
+/// Where the rows checked by [emit_constraint_checks] come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowSource {
+    /// A new row whose rowid was generated, which cannot conflict with another one.
+    NewRowid,
+    /// A new row whose rowid was given by the statement.
+    GivenRowid,
+    /// A new version of the row at the table cursor, with the same rowid, which is not in
+    /// conflict with its old version.
+    Update,
+    /// A new version of the row at the table cursor, with the rowid given to its INTEGER PRIMARY
+    /// KEY by the statement, which is not in conflict with its old version.
+    UpdateRowid,
+}
+
+/// Emits the checks of the constraints of `table` for the row at `row`.
+///
+/// Like in SQLite, a violated constraint is resolved with the algorithm of the OR clause of the
+/// statement, `or_conflict`, or else with the one of the ON CONFLICT clause of the constraint, or
+/// else with ABORT. A CHECK constraint has no ON CONFLICT clause, and the CHECK constraints are
+/// ignored with PRAGMA ignore_check_constraints. `conflict_for(None)` and
+/// `conflict_for(Some(index))` may override how a conflict with an existing row through its rowid
+/// or PRIMARY KEY or through a UNIQUE index is resolved, for an upsert. IGNORE goes to `skip_row`,
+/// and REPLACE deletes the existing row, along with `replace_deletes`, and leaves the table cursor
+/// where the row is to be written. The constraints resolved with REPLACE are checked last, so that no row is deleted for
+/// a row that fails another constraint.
+#[allow(clippy::too_many_arguments)]
+pub fn emit_constraint_checks(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursors: &WriteCursors,
+    row: usize,
+    source: RowSource,
+    or_conflict: Option<ResolveType>,
+    conflict_for: &dyn Fn(Option<&Index>) -> Option<OnConflict>,
+    replace_deletes: &ReplaceDeletes,
+    skip_row: BranchOffset,
+    resolver: &Resolver,
+) -> Result<()> {
+    for (i, column) in table.columns.iter().enumerate() {
+        if !column.notnull || column.is_rowid_alias {
+            continue;
+        }
+        let reg = row + 1 + i;
+        let not_null_label = program.allocate_label();
+        program.emit_insn(Insn::NotNull {
+            reg,
+            target_pc: not_null_label,
+        });
+        let on_conflict = or_conflict
+            .or_else(|| {
+                table
+                    .not_null_on_conflict
+                    .iter()
+                    .find(|(position, _)| *position == i)
+                    .map(|(_, on_conflict)| *on_conflict)
+            })
+            .unwrap_or(ResolveType::Abort);
+        match (on_conflict, &column.default) {
+            (ResolveType::Ignore, _) => program.emit_insn(Insn::Goto {
+                target_pc: skip_row,
+            }),
+            (ResolveType::Replace, Some(default)) => {
+                translate_expr(program, None, default, reg, resolver)?;
+            }
+            (on_error, _) => program.emit_halt_err(
+                SQLITE_CONSTRAINT_NOTNULL,
+                // Like in SQLite, REPLACE without a default value fails like ABORT.
+                match on_error {
+                    ResolveType::Replace => ResolveType::Abort,
+                    on_error => on_error,
+                },
+                format!(
+                    "{}.{}",
+                    table.name,
+                    column.name.as_deref().unwrap_or_default()
+                ),
+            ),
+        }
+        program.resolve_label(not_null_label, program.offset());
+    }
+
+    if !table.checks.is_empty() && !resolver.symbol_table.ignore_check_constraints.get() {
+        let on_conflict = or_conflict.unwrap_or(ResolveType::Abort);
+        emit_check_constraints(program, table, row, on_conflict, skip_row, resolver)?;
+    }
+
+    let resolve = |index: Option<&Index>, constraint_on_conflict: Option<ResolveType>| {
+        conflict_for(index).unwrap_or_else(|| {
+            OnConflict::Resolve(
+                or_conflict
+                    .or(constraint_on_conflict)
+                    .unwrap_or(ResolveType::Abort),
+            )
+        })
+    };
+    // The upserts handle their conflict targets before the other constraints are checked, and
+    // the REPLACE constraints come last
+    let order = |on_conflict: &OnConflict| match on_conflict {
+        OnConflict::Goto { .. } => 0,
+        OnConflict::Resolve(ResolveType::Replace) => 2,
+        OnConflict::Resolve(_) => 1,
+    };
+    let primary_key_conflict = (!table.has_rowid
+        || matches!(source, RowSource::GivenRowid | RowSource::UpdateRowid))
+    .then(|| resolve(None, table.primary_key_on_conflict));
+    let mut unique_indexes = cursors
+        .indexes
+        .iter()
+        .filter(|index_cursor| index_cursor.changes && index_cursor.index.unique)
+        .map(|index_cursor| {
+            let constraint_on_conflict = table
+                .unique_set_of_index(&index_cursor.index.name)
+                .and_then(|unique_set| unique_set.on_conflict);
+            (
+                index_cursor,
+                resolve(Some(&index_cursor.index), constraint_on_conflict),
+            )
+        })
+        .collect::<Vec<_>>();
+    unique_indexes.sort_by_key(|(_, on_conflict)| order(on_conflict));
+    let primary_key_position = unique_indexes
+        .iter()
+        .position(|(_, on_conflict)| {
+            primary_key_conflict.is_some_and(|pk| order(on_conflict) > order(&pk))
+        })
+        .unwrap_or(unique_indexes.len());
+
+    // An updated row of a WITHOUT ROWID table is recognized by the PRIMARY KEY it had, and one
+    // whose rowid changes by the rowid it had
+    let old_key = match source {
+        RowSource::Update if !table.has_rowid => Some(emit_primary_key_of_row_at_cursor(
+            program,
+            table,
+            cursors.table,
+        )),
+        RowSource::UpdateRowid => {
+            let old_rowid_reg = program.alloc_register();
+            program.emit_insn(Insn::RowId {
+                cursor_id: cursors.table,
+                dest: old_rowid_reg,
+            });
+            Some(old_rowid_reg)
+        }
+        _ => None,
+    };
+    for i in 0..=unique_indexes.len() {
+        if i == primary_key_position {
+            if let Some(conflict) = primary_key_conflict {
+                if table.has_rowid {
+                    emit_rowid_check(
+                        program,
+                        table,
+                        cursors,
+                        row,
+                        old_key,
+                        conflict,
+                        replace_deletes,
+                        skip_row,
+                        resolver,
+                    )?;
+                } else {
+                    emit_primary_key_check(
+                        program,
+                        table,
+                        cursors,
+                        row,
+                        old_key,
+                        conflict,
+                        replace_deletes,
+                        skip_row,
+                        resolver,
+                    )?;
+                }
+            }
+        }
+        if let Some((index_cursor, conflict)) = unique_indexes.get(i) {
+            emit_unique_index_check(
+                program,
+                table,
+                cursors,
+                index_cursor,
+                row,
+                source,
+                old_key,
+                *conflict,
+                replace_deletes,
+                skip_row,
+                resolver,
+            )?;
+        }
+    }
+    // Put the table cursor back on the updated row
+    if let Some(old_key) = old_key {
+        if table.has_rowid {
+            program.emit_insn(Insn::SeekRowid {
+                cursor_id: cursors.table,
+                src_reg: old_key,
+                target_pc: skip_row,
+            });
+        } else {
+            program.emit_insn(Insn::NotFound {
+                cursor_id: cursors.table,
+                target_pc: skip_row,
+                record_reg: old_key,
+                num_regs: table.primary_key_columns.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Emits the check of the rowid given to the new row at `row`. An updated row whose rowid was
+/// `old_rowid` is not in conflict with itself.
+#[allow(clippy::too_many_arguments)]
+fn emit_rowid_check(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursors: &WriteCursors,
+    row: usize,
+    old_rowid: Option<usize>,
+    conflict: OnConflict,
+    replace_deletes: &ReplaceDeletes,
+    skip_row: BranchOffset,
+    resolver: &Resolver,
+) -> Result<()> {
+    let no_conflict_label = program.allocate_label();
+    if let Some(old_rowid) = old_rowid {
+        program.emit_insn(Insn::Eq {
+            lhs: row,
+            rhs: old_rowid,
+            target_pc: no_conflict_label,
+            flags: CmpInsFlags::default(),
+        });
+    }
+    program.emit_insn(Insn::NotExists {
+        cursor: cursors.table,
+        rowid_reg: row,
+        target_pc: no_conflict_label,
+    });
+    let rowid_name = table
+        .get_rowid_alias_column()
+        .and_then(|(_, column)| column.name.as_deref())
+        .unwrap_or("rowid");
+    emit_conflict_resolution(
+        program,
+        table,
+        cursors,
+        row,
+        None,
+        conflict,
+        SQLITE_CONSTRAINT_PRIMARYKEY,
+        format!("{}.{}", table.name, rowid_name),
+        replace_deletes,
+        skip_row,
+        resolver,
+    )?;
+    program.resolve_label(no_conflict_label, program.offset());
+    Ok(())
+}
+
+/// Emits the check of the UNIQUE index at `index_cursor` for the row at `row`. An updated row is
+/// not in conflict with its old version, whose PRIMARY KEY is `old_key` in a WITHOUT ROWID table,
+/// and whose rowid is `old_key` if the update changes it.
+#[allow(clippy::too_many_arguments)]
+fn emit_unique_index_check(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursors: &WriteCursors,
+    index_cursor: &IndexCursor,
+    row: usize,
+    source: RowSource,
+    old_key: Option<usize>,
+    conflict: OnConflict,
+    replace_deletes: &ReplaceDeletes,
+    skip_row: BranchOffset,
+    resolver: &Resolver,
+) -> Result<()> {
+    let index = &index_cursor.index;
+    let no_conflict_label = program.allocate_label();
+    emit_partial_index_check(program, table, index, row, no_conflict_label, resolver)?;
+    let key_reg = emit_index_key(program, table, index, row, resolver)?;
+    program.emit_insn(Insn::NoConflict {
+        cursor_id: index_cursor.cursor_id,
+        target_pc: no_conflict_label,
+        record_reg: key_reg,
+        num_regs: index.columns.len(),
+    });
+    let conflict_rowid_reg = if table.has_rowid {
+        let conflict_rowid_reg = program.alloc_register();
+        program.emit_insn(Insn::IdxRowId {
+            cursor_id: index_cursor.cursor_id,
+            dest: conflict_rowid_reg,
+        });
+        let old_rowid = match source {
+            RowSource::Update => Some(row),
+            RowSource::UpdateRowid => old_key,
+            RowSource::NewRowid | RowSource::GivenRowid => None,
+        };
+        if let Some(old_rowid) = old_rowid {
+            program.emit_insn(Insn::Eq {
+                lhs: conflict_rowid_reg,
+                rhs: old_rowid,
+                target_pc: no_conflict_label,
+                flags: CmpInsFlags::default(),
+            });
+        }
+        conflict_rowid_reg
+    } else {
+        let positions = index.primary_key_entry_positions(table);
+        let conflict_key_reg = program.alloc_registers(positions.len());
+        for (i, position) in positions.iter().enumerate() {
+            program.emit_insn(Insn::Column {
+                cursor_id: index_cursor.cursor_id,
+                column: *position,
+                dest: conflict_key_reg + i,
+            });
+        }
+        if let Some(old_key) = old_key {
+            emit_jump_if_keys_equal(
+                program,
+                conflict_key_reg,
+                old_key,
+                positions.len(),
+                no_conflict_label,
+            );
+        }
+        conflict_key_reg
+    };
+    let key_names = if index.has_expressions() {
+        format!("index '{}'", index.name)
+    } else {
+        index
+            .columns
+            .iter()
+            .map(|column| format!("{}.{}", table.name, column.name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    emit_conflict_resolution(
+        program,
+        table,
+        cursors,
+        row,
+        Some(conflict_rowid_reg),
+        conflict,
+        SQLITE_CONSTRAINT_UNIQUE,
+        key_names,
+        replace_deletes,
+        skip_row,
+        resolver,
+    )?;
+    program.resolve_label(no_conflict_label, program.offset());
+    Ok(())
+}
+
+/// Copies the PRIMARY KEY of the row at the cursor on a WITHOUT ROWID table to new registers and
+/// returns the first one.
+fn emit_primary_key_of_row_at_cursor(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursor_id: CursorID,
+) -> usize {
+    let positions = table.primary_key_column_positions();
+    let key_reg = program.alloc_registers(positions.len());
+    for (i, position) in positions.iter().enumerate() {
+        program.emit_insn(Insn::Column {
+            cursor_id,
+            column: *position,
+            dest: key_reg + i,
+        });
+    }
+    key_reg
+}
+
+/// Goes to `target` if the `len` registers at `lhs` are equal to the ones at `rhs`.
+fn emit_jump_if_keys_equal(
+    program: &mut ProgramBuilder,
+    lhs: usize,
+    rhs: usize,
+    len: usize,
+    target: BranchOffset,
+) {
+    let different_label = program.allocate_label();
+    for i in 0..len {
+        program.emit_insn(Insn::Ne {
+            lhs: lhs + i,
+            rhs: rhs + i,
+            target_pc: different_label,
+            flags: CmpInsFlags::default(),
+        });
+    }
+    program.emit_insn(Insn::Goto { target_pc: target });
+    program.resolve_label(different_label, program.offset());
+}
+
+/// Emits the check of the PRIMARY KEY of a WITHOUT ROWID table, which is the key of the table
+/// b-tree, for the row at `row`. An updated row whose PRIMARY KEY was `old_key` is not in conflict
+/// with itself. The conflicts are resolved like the ones of the rowid, and the table cursor points
+/// at the conflicting row if there is one.
+#[allow(clippy::too_many_arguments)]
+fn emit_primary_key_check(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursors: &WriteCursors,
+    row: usize,
+    old_key: Option<usize>,
+    conflict: OnConflict,
+    replace_deletes: &ReplaceDeletes,
+    skip_row: BranchOffset,
+    resolver: &Resolver,
+) -> Result<()> {
+    let positions = table.primary_key_column_positions();
+    let key_reg = program.alloc_registers(positions.len());
+    for (i, position) in positions.iter().enumerate() {
+        program.emit_insn(Insn::Copy {
+            src_reg: row + 1 + position,
+            dst_reg: key_reg + i,
+            amount: 0,
+        });
+    }
+    let no_conflict_label = program.allocate_label();
+    if let Some(old_key) = old_key {
+        emit_jump_if_keys_equal(
+            program,
+            key_reg,
+            old_key,
+            positions.len(),
+            no_conflict_label,
+        );
+    }
+    program.emit_insn(Insn::NoConflict {
+        cursor_id: cursors.table,
+        target_pc: no_conflict_label,
+        record_reg: key_reg,
+        num_regs: positions.len(),
+    });
+    let key_names = positions
+        .iter()
+        .map(|position| {
+            format!(
+                "{}.{}",
+                table.name,
+                table.columns[*position].name.as_deref().unwrap_or_default()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    emit_conflict_resolution(
+        program,
+        table,
+        cursors,
+        row,
+        None,
+        conflict,
+        SQLITE_CONSTRAINT_PRIMARYKEY,
+        key_names,
+        replace_deletes,
+        skip_row,
+        resolver,
+    )?;
+    program.resolve_label(no_conflict_label, program.offset());
+    Ok(())
+}
+
+/// Emits the checks of the CHECK constraints of `table` for the row at `row`. Like in SQLite, the
+/// affinities of the columns are applied to the row first, and a constraint whose expression is
+/// NULL is satisfied.
+fn emit_check_constraints(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    row: usize,
+    on_conflict: ResolveType,
+    skip_row: BranchOffset,
+    resolver: &Resolver,
+) -> Result<()> {
+    program.emit_insn(Insn::Affinity {
+        start_reg: row + 1,
+        count: NonZeroUsize::new(table.columns.len()).unwrap(),
+        affinities: table
+            .columns
+            .iter()
+            .map(|column| column.affinity().aff_mask())
+            .collect(),
+    });
+    for check in &table.checks {
+        let mut expr = check.expr.clone();
+        rewrite_row_expr(&mut expr, table, row, "CHECK constraints")?;
+        let reg = program.alloc_register();
+        translate_expr(program, None, &expr, reg, resolver)?;
+        let check_ok_label = program.allocate_label();
+        program.emit_insn(Insn::If {
+            reg,
+            target_pc: check_ok_label,
+            jump_if_null: true,
+        });
+        match on_conflict {
+            ResolveType::Ignore => program.emit_insn(Insn::Goto {
+                target_pc: skip_row,
+            }),
+            on_error => program.emit_halt_err(
+                SQLITE_CONSTRAINT_CHECK,
+                match on_error {
+                    ResolveType::Replace => ResolveType::Abort,
+                    on_error => on_error,
+                },
+                check.description(),
+            ),
+        }
+        program.resolve_label(check_ok_label, program.offset());
+    }
+    Ok(())
+}
+
+/// Checks that the expressions of the CHECK constraints of a new table only refer to its columns.
+pub fn validate_check_constraints(table: &BTreeTable) -> Result<()> {
+    for check in &table.checks {
+        rewrite_row_expr(&mut check.expr.clone(), table, 0, "CHECK constraints")?;
+    }
+    Ok(())
+}
+
+/// Rewrites the columns of the table in an expression of its schema, like the one of a CHECK
+/// constraint, as the registers of the row at `row`, and the parts of the expression that are
+/// not translated directly like in a WHERE clause. `context` names the kind of expression in
+/// the errors.
+pub fn rewrite_row_expr(
+    expr: &mut Expr,
+    table: &BTreeTable,
+    row: usize,
+    context: &str,
+) -> Result<()> {
+    rewrite_expr(expr)?;
+    rewrite_row_columns(expr, table, row, context)
+}
+
+fn rewrite_row_columns(
+    expr: &mut Expr,
+    table: &BTreeTable,
+    row: usize,
+    context: &str,
+) -> Result<()> {
+    let name = match expr {
+        Expr::Id(name) => name.0.clone(),
+        Expr::Qualified(qualifier, name) if normalize_ident(&qualifier.0) == table.name => {
+            name.0.clone()
+        }
+        Expr::Qualified(qualifier, name) => {
+            crate::bail_parse_error!("no such column: {}.{}", qualifier.0, name.0)
+        }
+        Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. } => {
+            crate::bail_parse_error!("subqueries prohibited in {}", context)
+        }
+        Expr::Variable(_) => crate::bail_parse_error!("parameters prohibited in {}", context),
+        _ => {
+            for child in child_exprs_mut(expr) {
+                rewrite_row_columns(child, table, row, context)?;
+            }
+            return Ok(());
+        }
+    };
+    let offset = match table.get_column(&name) {
+        Some((_, column)) if column.is_rowid_alias => 0,
+        Some((position, _)) => position + 1,
+        None if ["rowid", "oid", "_rowid_"].contains(&normalize_ident(&name).as_str()) => 0,
+        None => crate::bail_parse_error!("no such column: {}", name),
+    };
+    *expr = Expr::Register(row + offset);
+    Ok(())
+}
+
+/// Resolves a conflict of the row at `row` with the row whose rowid is in `conflict_rowid_reg`,
+/// or with the row that has the same rowid if it is None. In a WITHOUT ROWID table,
+/// `conflict_rowid_reg` is the first of the registers of the PRIMARY KEY of the conflicting row,
+/// which is the row at the table cursor if it is None.
+#[allow(clippy::too_many_arguments)]
+fn emit_conflict_resolution(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursors: &WriteCursors,
+    row: usize,
+    conflict_rowid_reg: Option<usize>,
+    conflict: OnConflict,
+    err_code: usize,
+    description: String,
+    replace_deletes: &ReplaceDeletes,
+    skip_row: BranchOffset,
+    resolver: &Resolver,
+) -> Result<()> {
+    match conflict {
+        OnConflict::Resolve(ResolveType::Ignore) => {
+            program.emit_insn(Insn::Goto {
+                target_pc: skip_row,
+            });
+        }
+        OnConflict::Resolve(ResolveType::Replace) if !table.has_rowid => {
+            let done_label = program.allocate_label();
+            if let Some(conflict_key_reg) = conflict_rowid_reg {
+                program.emit_insn(Insn::NotFound {
+                    cursor_id: cursors.table,
+                    target_pc: done_label,
+                    record_reg: conflict_key_reg,
+                    num_regs: table.primary_key_columns.len(),
+                });
+            }
+            replace_deletes.emit(program, table, cursors, skip_row, resolver)?;
+            program.resolve_label(done_label, program.offset());
+        }
+        OnConflict::Resolve(ResolveType::Replace) => {
+            let done_label = program.allocate_label();
+            program.emit_insn(Insn::SeekRowid {
+                cursor_id: cursors.table,
+                src_reg: conflict_rowid_reg.unwrap_or(row),
+                target_pc: done_label,
+            });
+            replace_deletes.emit(program, table, cursors, skip_row, resolver)?;
+            program.resolve_label(done_label, program.offset());
+            // Put the table cursor back where the row goes
+            let positioned_label = program.allocate_label();
+            program.emit_insn(Insn::SeekRowid {
+                cursor_id: cursors.table,
+                src_reg: row,
+                target_pc: positioned_label,
+            });
+            program.resolve_label(positioned_label, program.offset());
+        }
+        OnConflict::Resolve(
+            on_error @ (ResolveType::Abort | ResolveType::Fail | ResolveType::Rollback),
+        ) => {
+            program.emit_halt_err(err_code, on_error, description);
+        }
+        OnConflict::Goto { target, rowid_reg } if !table.has_rowid => {
+            let positions = table.primary_key_column_positions();
+            match conflict_rowid_reg {
+                Some(conflict_key_reg) => program.emit_insn(Insn::Copy {
+                    src_reg: conflict_key_reg,
+                    dst_reg: rowid_reg,
+                    amount: positions.len() - 1,
+                }),
+                None => {
+                    for (i, position) in positions.iter().enumerate() {
+                        program.emit_insn(Insn::Copy {
+                            src_reg: row + 1 + position,
+                            dst_reg: rowid_reg + i,
+                            amount: 0,
+                        });
+                    }
+                }
+            }
+            program.emit_insn(Insn::Goto { target_pc: target });
+        }
+        OnConflict::Goto { target, rowid_reg } => {
+            program.emit_insn(match conflict_rowid_reg {
+                Some(conflict_rowid_reg) => Insn::Copy {
+                    src_reg: conflict_rowid_reg,
+                    dst_reg: rowid_reg,
+                    amount: 0,
+                },
+                None => Insn::Copy {
+                    src_reg: row,
+                    dst_reg: rowid_reg,
+                    amount: 0,
+                },
+            });
+            program.emit_insn(Insn::Goto { target_pc: target });
+        }
+    }
+    Ok(())
+}
+
+/// Returns the number of columns in the entries of the index, which are the indexed columns
+/// followed by the rowid, or by [Index::primary_key_suffix] in a WITHOUT ROWID table.
+fn index_entry_len(table: &BTreeTable, index: &Index) -> usize {
+    if table.has_rowid {
+        index.columns.len() + 1
+    } else {
+        index.columns.len() + index.primary_key_suffix(table).len()
+    }
+}
+
+/// Copies the entry of the row at `row` in the index to new registers and returns the first one.
+/// The columns of an index on expressions are evaluated on the row.
+pub fn emit_index_key(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    index: &Index,
+    row: usize,
+    resolver: &Resolver,
+) -> Result<usize> {
+    let key_reg = program.alloc_registers(index_entry_len(table, index));
+    for (i, column) in index.columns.iter().enumerate() {
+        if let Some(expr) = &column.expr {
+            let mut expr = expr.as_ref().clone();
+            rewrite_row_expr(&mut expr, table, row, "index expressions")?;
+            translate_expr(program, None, &expr, key_reg + i, resolver)?;
+            continue;
+        }
+        let src_reg = if table.columns[column.pos_in_table].is_rowid_alias {
+            row
+        } else {
+            row + 1 + column.pos_in_table
+        };
+        program.emit_insn(Insn::Copy {
+            src_reg,
+            dst_reg: key_reg + i,
+            amount: 0,
+        });
+    }
+    if table.has_rowid {
+        program.emit_insn(Insn::Copy {
+            src_reg: row,
+            dst_reg: key_reg + index.columns.len(),
+            amount: 0,
+        });
+    } else {
+        for (i, position) in index.primary_key_suffix(table).iter().enumerate() {
+            program.emit_insn(Insn::Copy {
+                src_reg: row + 1 + position,
+                dst_reg: key_reg + index.columns.len() + i,
+                amount: 0,
+            });
+        }
+    }
+    Ok(key_reg)
+}
+
+/// Jumps to `skip` if the row at `row` is not in the index, because it does not satisfy the
+/// WHERE clause of a partial index.
+pub fn emit_partial_index_check(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    index: &Index,
+    row: usize,
+    skip: BranchOffset,
+    resolver: &Resolver,
+) -> Result<()> {
+    let Some(where_clause) = &index.where_clause else {
+        return Ok(());
+    };
+    let mut expr = where_clause.as_ref().clone();
+    rewrite_row_expr(&mut expr, table, row, "partial index WHERE clauses")?;
+    let reg = program.alloc_register();
+    translate_expr(program, None, &expr, reg, resolver)?;
+    program.emit_insn(Insn::IfNot {
+        reg,
+        target_pc: skip,
+        jump_if_null: true,
+    });
+    Ok(())
+}
+
+/// Reads the row at the table cursor into a new block of registers, laid out like the row being
+/// written, and returns its first register.
+pub fn emit_load_row(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursor_id: CursorID,
+) -> usize {
+    let row = program.alloc_registers(table.columns.len() + 1);
+    if table.has_rowid {
+        program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: row,
+        });
+    } else {
+        program.emit_insn(Insn::Null {
+            dest: row,
+            dest_end: None,
+        });
+    }
+    for (i, column) in table.columns.iter().enumerate() {
+        if column.is_rowid_alias {
+            program.emit_insn(Insn::Null {
+                dest: row + 1 + i,
+                dest_end: None,
+            });
+        } else {
+            program.emit_insn(Insn::Column {
+                cursor_id,
+                column: i,
+                dest: row + 1 + i,
+            });
+        }
+    }
+    row
+}
+
+/// Writes the row at `row`, which is its rowid followed by its columns, to the table. The row of
+/// a WITHOUT ROWID table is a record of its columns in storage order, keyed by its PRIMARY KEY.
+/// With `in_place`, the row replaces the one at the cursor, and the cursor is left on it for a
+/// loop over the table to continue.
+pub fn emit_insert_row(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursor_id: CursorID,
+    row: usize,
+    in_place: bool,
+) {
+    let record_reg = program.alloc_register();
+    if table.has_rowid {
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: row + 1,
+            count: table.columns.len(),
+            dest_reg: record_reg,
+        });
+        program.emit_insn(Insn::Insert {
+            cursor: cursor_id,
+            key_reg: row,
+            record_reg,
+            flag: 0,
+        });
+        return;
+    }
+    if in_place {
+        program.emit_insn(Insn::Delete {
+            cursor_id,
+            count_change: false,
+        });
+    }
+    let positions = table.columns_in_storage_order();
+    let start_reg = program.alloc_registers(positions.len());
+    for (i, position) in positions.iter().enumerate() {
+        program.emit_insn(Insn::Copy {
+            src_reg: row + 1 + position,
+            dst_reg: start_reg + i,
+            amount: 0,
+        });
+    }
+    program.emit_insn(Insn::MakeRecord {
+        start_reg,
+        count: positions.len(),
+        dest_reg: record_reg,
+    });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id,
+        record_reg,
+        unpacked_start: Some(start_reg),
+        unpacked_count: Some(positions.len() as u16),
+        flags: IdxInsertFlags::new().nchange(true),
+    });
+    if in_place {
+        // The PRIMARY KEY columns come first in the record
+        let found_label = program.allocate_label();
+        program.emit_insn(Insn::NotFound {
+            cursor_id,
+            target_pc: found_label,
+            record_reg: start_reg,
+            num_regs: table.primary_key_columns.len(),
+        });
+        program.resolve_label(found_label, program.offset());
+    }
+}
+
+/// Adds the row at `row` to the indexes whose keys the statement changes.
+pub fn emit_insert_index_entries(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursors: &WriteCursors,
+    row: usize,
+    resolver: &Resolver,
+) -> Result<()> {
+    for index_cursor in cursors.indexes.iter().filter(|index| index.changes) {
+        let index = &index_cursor.index;
+        let skip_label = program.allocate_label();
+        emit_partial_index_check(program, table, index, row, skip_label, resolver)?;
+        let num_regs = index_entry_len(table, index);
+        let key_reg = emit_index_key(program, table, index, row, resolver)?;
+        let record_reg = program.alloc_register();
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: key_reg,
+            count: num_regs,
+            dest_reg: record_reg,
+        });
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id: index_cursor.cursor_id,
+            record_reg,
+            unpacked_start: Some(key_reg),
+            unpacked_count: Some(num_regs as u16),
+            flags: IdxInsertFlags::new(),
+        });
+        program.resolve_label(skip_label, program.offset());
+    }
+    Ok(())
+}
+
+/// Deletes the entries of the row at the table cursor from all the indexes, or only from the
+/// ones whose keys the statement changes.
+pub fn emit_delete_index_entries(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursors: &WriteCursors,
+    all_indexes: bool,
+    resolver: &Resolver,
+) -> Result<()> {
+    let mut row = None;
+    for index_cursor in cursors
+        .indexes
+        .iter()
+        .filter(|index| all_indexes || index.changes)
+    {
+        let index = &index_cursor.index;
+        let num_regs = index_entry_len(table, index);
+        if index.has_expressions() || index.where_clause.is_some() {
+            let row = *row.get_or_insert_with(|| emit_load_row(program, table, cursors.table));
+            let skip_label = program.allocate_label();
+            emit_partial_index_check(program, table, index, row, skip_label, resolver)?;
+            let key_reg = emit_index_key(program, table, index, row, resolver)?;
+            program.emit_insn(Insn::IdxDelete {
+                cursor_id: index_cursor.cursor_id,
+                start_reg: key_reg,
+                num_regs,
+            });
+            program.resolve_label(skip_label, program.offset());
+            continue;
+        }
+        let key_reg = program.alloc_registers(num_regs);
+        for (i, column) in index.columns.iter().enumerate() {
+            if table.columns[column.pos_in_table].is_rowid_alias {
+                program.emit_insn(Insn::RowId {
+                    cursor_id: cursors.table,
+                    dest: key_reg + i,
+                });
+            } else {
+                program.emit_insn(Insn::Column {
+                    cursor_id: cursors.table,
+                    column: column.pos_in_table,
+                    dest: key_reg + i,
+                });
+            }
+        }
+        if table.has_rowid {
+            program.emit_insn(Insn::RowId {
+                cursor_id: cursors.table,
+                dest: key_reg + index.columns.len(),
+            });
+        } else {
+            for (i, position) in index.primary_key_suffix(table).iter().enumerate() {
+                program.emit_insn(Insn::Column {
+                    cursor_id: cursors.table,
+                    column: *position,
+                    dest: key_reg + index.columns.len() + i,
+                });
+            }
+        }
+        program.emit_insn(Insn::IdxDelete {
+            cursor_id: index_cursor.cursor_id,
+            start_reg: key_reg,
+            num_regs,
+        });
+    }
+    Ok(())
+}
@@ -1,13 +1,16 @@
 use crate::schema::Table;
 use crate::translate::emitter::emit_program;
+use crate::translate::foreign_key::ForeignKeyChecks;
 use crate::translate::optimizer::optimize_plan;
 use crate::translate::plan::{DeletePlan, Operation, Plan};
-use crate::translate::planner::{parse_limit, parse_where};
+use crate::translate::planner::{parse_limit, parse_where, plan_dml_subqueries};
+use crate::translate::trigger::{translate_view_update_or_delete, RowTriggers};
 use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, QueryMode};
 use crate::{schema::Schema, Result, SymbolTable};
-use limbo_sqlite3_parser::ast::{Expr, Limit, QualifiedName};
+use limbo_sqlite3_parser::ast::{Expr, Limit, QualifiedName, ResultColumn, TriggerEvent};
 
 use super::plan::{ColumnUsedMask, IterationDirection, TableReference};
+use super::returning::prepare_returning;
 
 pub fn translate_delete(
     query_mode: QueryMode,
@@ -15,11 +18,26 @@ pub fn translate_delete(
     tbl_name: &QualifiedName,
     where_clause: Option<Box<Expr>>,
     limit: Option<Box<Limit>>,
+    returning: &Option<Vec<ResultColumn>>,
     syms: &SymbolTable,
 ) -> Result<ProgramBuilder> {
-    let mut delete_plan = prepare_delete_plan(schema, tbl_name, where_clause, limit)?;
+    let db_name = tbl_name.db_name.as_ref().map(|name| name.0.as_str());
+    if let Some((_, table)) = schema.find_table(db_name, &tbl_name.name.0)? {
+        if let Table::View(view) = table.as_ref() {
+            return translate_view_update_or_delete(
+                query_mode,
+                schema,
+                view,
+                &[],
+                where_clause,
+                syms,
+            );
+        }
+    }
+    let mut delete_plan =
+        prepare_delete_plan(schema, tbl_name, where_clause, limit, returning, syms)?;
     optimize_plan(&mut delete_plan, schema)?;
-    let Plan::Delete(ref delete) = delete_plan else {
+    let Plan::Delete(ref mut delete) = delete_plan else {
         panic!("delete_plan is not a DeletePlan");
     };
     let mut program = ProgramBuilder::new(ProgramBuilderOpts {
@@ -28,6 +46,19 @@ pub fn translate_delete(
         approx_num_insns: estimate_num_instructions(delete),
         approx_num_labels: 0,
     });
+    if let Some(btree) = delete.table_references[0].btree() {
+        let schema = &schema.database_schema(delete.table_references[0].database)?;
+        delete.triggers = RowTriggers::compile(
+            &mut program,
+            schema,
+            &btree.name,
+            &btree.columns,
+            TriggerEvent::Delete,
+            &[],
+            syms,
+        )?;
+        delete.foreign_keys = ForeignKeyChecks::compile(schema, &btree, None, syms)?;
+    }
     emit_program(&mut program, delete_plan, syms)?;
     Ok(program)
 }
@@ -37,11 +68,17 @@ pub fn prepare_delete_plan(
     tbl_name: &QualifiedName,
     where_clause: Option<Box<Expr>>,
     limit: Option<Box<Limit>>,
+    returning: &Option<Vec<ResultColumn>>,
+    syms: &SymbolTable,
 ) -> Result<Plan> {
-    let table = match schema.get_table(tbl_name.name.0.as_str()) {
+    let db_name = tbl_name.db_name.as_ref().map(|name| name.0.as_str());
+    let (database, table) = match schema.find_table(db_name, tbl_name.name.0.as_str())? {
         Some(table) => table,
         None => crate::bail_corrupt_error!("Parse error: no such table: {}", tbl_name),
     };
+    if let Table::View(view) = table.as_ref() {
+        crate::bail_parse_error!("cannot modify {} because it is a view", view.name);
+    }
     let table = if let Some(table) = table.virtual_table() {
         Table::Virtual(table.clone())
     } else if let Some(table) = table.btree() {
@@ -59,13 +96,27 @@ pub fn prepare_delete_plan(
         },
         join_info: None,
         col_used_mask: ColumnUsedMask::new(),
+        database,
+        database_name: schema.database_name(database).to_string(),
     }];
 
+    let result_columns = match returning {
+        Some(_) if table_references[0].virtual_table().is_some() => {
+            crate::bail_parse_error!("RETURNING is not supported for virtual tables")
+        }
+        Some(returning) => prepare_returning(returning, &mut table_references)?,
+        None => vec![],
+    };
     let mut where_predicates = vec![];
+    let mut expr_subqueries = vec![];
+    let mut where_clause = where_clause.map(|e| *e);
+    if let Some(expr) = where_clause.as_mut() {
+        plan_dml_subqueries(expr, schema, syms, &table_references, &mut expr_subqueries)?;
+    }
 
     // Parse the WHERE clause
     parse_where(
-        where_clause.map(|e| *e),
+        where_clause,
         &mut table_references,
         None,
         &mut where_predicates,
@@ -76,12 +127,19 @@ pub fn prepare_delete_plan(
 
     let plan = DeletePlan {
         table_references,
-        result_columns: vec![],
+        result_columns,
         where_clause: where_predicates,
         order_by: None,
         limit: resolved_limit,
         offset: resolved_offset,
         contains_constant_false_condition: false,
+        triggers: RowTriggers::default(),
+        foreign_keys: ForeignKeyChecks::default(),
+        indexes: schema
+            .database_schema(database)?
+            .get_indices(&tbl_name.name.0)
+            .to_vec(),
+        expr_subqueries,
     };
 
     Ok(Plan::Delete(plan))
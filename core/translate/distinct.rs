@@ -0,0 +1,139 @@
+//! SELECT DISTINCT and aggregates called with DISTINCT, e.g. count(DISTINCT x).
+//!
+//! The rows or values that were already seen are kept in an ephemeral index. Its columns are
+//! compared with the collating sequences of the values, so that e.g. 'a' and 'A' are the same
+//! value of a NOCASE column.
+
+use std::sync::Arc;
+
+use limbo_sqlite3_parser::ast::SortOrder;
+
+use crate::{
+    schema::{Index, IndexColumn},
+    util::normalize_ident,
+    vdbe::{
+        builder::{CursorType, ProgramBuilder},
+        insn::{IdxInsertFlags, Insn},
+        BranchOffset, CursorID,
+    },
+};
+
+use super::{collate::expr_collation_name, emitter::TranslateCtx, plan::SelectPlan};
+
+/// The ephemeral indexes of SELECT DISTINCT and of the aggregates called with DISTINCT.
+#[derive(Debug, Default)]
+pub struct DistinctMetadata {
+    /// The index of the result rows of SELECT DISTINCT.
+    pub cursor_id: Option<CursorID>,
+    /// The index of the values of the argument of each aggregate, by the position of the aggregate
+    /// in the plan.
+    pub agg_cursor_ids: Vec<Option<CursorID>>,
+}
+
+/// Open the ephemeral indexes of SELECT DISTINCT and of the aggregates called with DISTINCT.
+/// Opening an ephemeral index again empties it, so the indexes start out empty every time the
+/// query runs, e.g. as a correlated subquery.
+pub fn init_distinct(program: &mut ProgramBuilder, t_ctx: &mut TranslateCtx, plan: &SelectPlan) {
+    if plan.distinct {
+        let collations: Vec<Option<String>> = plan
+            .result_columns
+            .iter()
+            .map(|rc| {
+                expr_collation_name(&rc.expr, Some(&plan.table_references)).map(normalize_ident)
+            })
+            .collect();
+        t_ctx.meta_distinct.cursor_id = Some(emit_open_distinct_index(program, &collations));
+    }
+    t_ctx.meta_distinct.agg_cursor_ids = plan
+        .aggregates
+        .iter()
+        .map(|agg| {
+            agg.distinct.then(|| {
+                let collation = expr_collation_name(&agg.args[0], Some(&plan.table_references))
+                    .map(normalize_ident);
+                emit_open_distinct_index(program, &[collation])
+            })
+        })
+        .collect();
+}
+
+/// Open an ephemeral index that holds distinct rows, whose columns are compared with `collations`.
+pub fn emit_open_distinct_index(
+    program: &mut ProgramBuilder,
+    collations: &[Option<String>],
+) -> CursorID {
+    let index = Arc::new(Index {
+        name: "ephemeral_distinct".to_string(),
+        table_name: String::new(),
+        root_page: 0,
+        columns: collations
+            .iter()
+            .enumerate()
+            .map(|(i, collation)| IndexColumn {
+                name: format!("c{}", i),
+                order: SortOrder::Asc,
+                pos_in_table: i,
+                collation: collation.clone(),
+                expr: None,
+            })
+            .collect(),
+        unique: false,
+        ephemeral: true,
+        where_clause: None,
+        key_suffix: Vec::new(),
+    });
+    let cursor_id = program.alloc_cursor_id(None, CursorType::BTreeIndex(index));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id,
+        is_table: false,
+    });
+    cursor_id
+}
+
+/// Jump to `label_duplicate` if the row in the registers `regs` is already in the distinct index
+/// `cursor_id`, and otherwise add it to the index.
+/// Index cursors expect every key to end in an integer rowid, so the key ends in a constant 0.
+pub fn emit_distinct_check(
+    program: &mut ProgramBuilder,
+    cursor_id: CursorID,
+    regs: &[usize],
+    label_duplicate: BranchOffset,
+) {
+    let count = regs.len();
+    let key_start_reg = program.alloc_registers(count + 1);
+    for (i, reg) in regs.iter().enumerate() {
+        program.emit_insn(Insn::Copy {
+            src_reg: *reg,
+            dst_reg: key_start_reg + i,
+            amount: 0,
+        });
+    }
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: key_start_reg + count,
+    });
+    let label_new_row = program.allocate_label();
+    program.emit_insn(Insn::NotFound {
+        cursor_id,
+        target_pc: label_new_row,
+        record_reg: key_start_reg,
+        num_regs: count + 1,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_duplicate,
+    });
+    program.resolve_label(label_new_row, program.offset());
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: key_start_reg,
+        count: count + 1,
+        dest_reg: record_reg,
+    });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id,
+        record_reg,
+        unpacked_start: Some(key_start_reg),
+        unpacked_count: Some((count + 1) as u16),
+        flags: IdxInsertFlags::new().use_seek(false),
+    });
+}
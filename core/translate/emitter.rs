@@ -2,28 +2,48 @@
 // It handles translating high-level SQL operations into low-level bytecode that can be executed by the virtual machine.
 
 use std::rc::Rc;
+use std::sync::Arc;
 
-use limbo_sqlite3_parser::ast::{self};
+use limbo_sqlite3_parser::ast::{self, TriggerTime};
 
 use crate::function::Func;
+use crate::schema::{BTreeTable, Index, IndexColumn};
 use crate::translate::plan::{DeletePlan, Plan, Search};
 use crate::util::exprs_are_equivalent;
-use crate::vdbe::builder::ProgramBuilder;
-use crate::vdbe::{insn::Insn, BranchOffset};
+use crate::vdbe::builder::{CursorType, ProgramBuilder};
+use crate::vdbe::insn::{IdxInsertFlags, Insn};
+use crate::vdbe::{BranchOffset, CursorID};
 use crate::{Result, SymbolTable};
 
 use super::aggregation::emit_ungrouped_aggregation;
+use super::compound_select::emit_compound_select;
+use super::constraint::{
+    emit_constraint_checks, emit_delete_index_entries, emit_insert_index_entries, emit_insert_row,
+    RowSource, WriteCursors,
+};
+use super::distinct::{init_distinct, DistinctMetadata};
 use super::expr::{translate_condition_expr, translate_expr, ConditionMetadata};
 use super::group_by::{emit_group_by, init_group_by, GroupByMetadata};
-use super::main_loop::{close_loop, emit_loop, init_loop, open_loop, LeftJoinMetadata, LoopLabels};
+use super::main_loop::{
+    close_loop, emit_loop, emit_unmatched_rows, init_loop, open_loop, LeftJoinMetadata, LoopLabels,
+    RightJoinMetadata,
+};
 use super::order_by::{emit_order_by, init_order_by, SortMetadata};
-use super::plan::{Operation, SelectPlan, TableReference, UpdatePlan};
-use super::subquery::emit_subqueries;
+use super::plan::{Operation, SelectPlan, SelectQueryType, UpdatePlan};
+use super::recursive_cte::emit_recursive_cte;
+use super::returning::Returning;
+use super::subquery::{emit_expr_subqueries, emit_subqueries, emit_subquery, ExprSubqueryMetadata};
+use super::window::{emit_windows, init_window, WindowMetadata};
 
 #[derive(Debug)]
 pub struct Resolver<'a> {
     pub symbol_table: &'a SymbolTable,
     pub expr_to_reg_cache: Vec<(&'a ast::Expr, usize)>,
+    /// If the query is a subquery in an expression, the first of the registers that hold the values
+    /// of the outer queries that it refers to, see [ast::Expr::OuterRef].
+    pub reg_outer_refs_start: Option<usize>,
+    /// The subqueries in the expressions of the query, see [ast::Expr::SubqueryResult].
+    pub expr_subqueries: Vec<ExprSubqueryMetadata>,
 }
 
 impl<'a> Resolver<'a> {
@@ -31,6 +51,18 @@ impl<'a> Resolver<'a> {
         Self {
             symbol_table,
             expr_to_reg_cache: Vec::new(),
+            reg_outer_refs_start: None,
+            expr_subqueries: Vec::new(),
+        }
+    }
+
+    /// Creates the resolver of a query that is part of the query of this resolver, e.g. a subquery
+    /// in the FROM clause or a member of a compound SELECT, which can refer to the same values of
+    /// outer queries.
+    pub fn nested(&self) -> Self {
+        Self {
+            reg_outer_refs_start: self.reg_outer_refs_start,
+            ..Self::new(self.symbol_table)
         }
     }
 
@@ -80,9 +112,16 @@ pub struct TranslateCtx<'a> {
     pub meta_group_by: Option<GroupByMetadata>,
     // metadata for the order by operator
     pub meta_sort: Option<SortMetadata>,
+    // metadata for the window functions, one per window
+    pub meta_windows: Vec<WindowMetadata>,
+    // metadata for SELECT DISTINCT and the aggregates called with DISTINCT
+    pub meta_distinct: DistinctMetadata,
     /// mapping between table loop index and associated metadata (for left joins only)
     /// this metadata exists for the right table in a given left join
     pub meta_left_joins: Vec<Option<LeftJoinMetadata>>,
+    /// mapping between table loop index and associated metadata (for right and full outer joins only)
+    /// this metadata exists for the right table in a given right join
+    pub meta_right_joins: Vec<Option<RightJoinMetadata>>,
     // We need to emit result columns in the order they are present in the SELECT, but they may not be in the same order in the ORDER BY sorter.
     // This vector holds the indexes of the result columns in the ORDER BY sorter.
     pub result_column_indexes_in_orderby_sorter: Vec<usize>,
@@ -118,27 +157,57 @@ fn prologue<'a>(
 
     let start_offset = program.offset();
 
-    let t_ctx = TranslateCtx {
-        labels_main_loop: (0..table_count).map(|_| LoopLabels::new(program)).collect(),
-        label_main_loop_end: None,
-        reg_agg_start: None,
-        reg_nonagg_emit_once_flag: None,
-        reg_limit: None,
-        reg_offset: None,
-        reg_limit_offset_sum: None,
-        reg_result_cols_start: None,
-        meta_group_by: None,
-        meta_left_joins: (0..table_count).map(|_| None).collect(),
-        meta_sort: None,
-        result_column_indexes_in_orderby_sorter: (0..result_column_count).collect(),
-        result_columns_to_skip_in_orderby_sorter: None,
-        resolver: Resolver::new(syms),
-        omit_predicates: Vec::new(),
-    };
+    let t_ctx = TranslateCtx::new(program, syms, table_count, result_column_count);
 
     Ok((t_ctx, init_label, start_offset))
 }
 
+impl<'a> TranslateCtx<'a> {
+    fn new(
+        program: &mut ProgramBuilder,
+        syms: &'a SymbolTable,
+        table_count: usize,
+        result_column_count: usize,
+    ) -> Self {
+        Self {
+            labels_main_loop: (0..table_count).map(|_| LoopLabels::new(program)).collect(),
+            label_main_loop_end: None,
+            reg_agg_start: None,
+            reg_nonagg_emit_once_flag: None,
+            reg_limit: None,
+            reg_offset: None,
+            reg_limit_offset_sum: None,
+            reg_result_cols_start: None,
+            meta_group_by: None,
+            meta_left_joins: (0..table_count).map(|_| None).collect(),
+            meta_right_joins: (0..table_count).map(|_| None).collect(),
+            meta_sort: None,
+            meta_windows: vec![],
+            meta_distinct: DistinctMetadata::default(),
+            result_column_indexes_in_orderby_sorter: (0..result_column_count).collect(),
+            result_columns_to_skip_in_orderby_sorter: None,
+            resolver: Resolver::new(syms),
+            omit_predicates: Vec::new(),
+        }
+    }
+}
+
+/// Emits a SELECT as a coroutine whose rows are consumed one by one by the code that follows,
+/// e.g. the rows of a view that an INSTEAD OF trigger fires for.
+/// Returns the yield register of the coroutine and the first register of its result columns.
+pub fn emit_coroutine(
+    program: &mut ProgramBuilder,
+    plan: &mut SelectPlan,
+    syms: &SymbolTable,
+) -> Result<(usize, usize)> {
+    let mut t_ctx = TranslateCtx::new(program, syms, 0, 0);
+    let result_columns_start = emit_subquery(program, plan, &mut t_ctx)?;
+    let SelectQueryType::Subquery { yield_reg, .. } = plan.query_type else {
+        unreachable!("emit_subquery sets the query type to Subquery");
+    };
+    Ok((yield_reg, result_columns_start))
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum TransactionMode {
     None,
@@ -155,10 +224,7 @@ fn epilogue(
     start_offset: BranchOffset,
     txn_mode: TransactionMode,
 ) -> Result<()> {
-    program.emit_insn(Insn::Halt {
-        err_code: 0,
-        description: String::new(),
-    });
+    program.emit_halt();
 
     program.resolve_label(init_label, program.offset());
 
@@ -211,7 +277,7 @@ fn emit_program_for_select(
     emit_query(program, &mut plan, &mut t_ctx)?;
 
     // Finalize program
-    if plan.table_references.is_empty() {
+    if !plan.reads_tables() {
         epilogue(program, init_label, start_offset, TransactionMode::None)?;
     } else {
         epilogue(program, init_label, start_offset, TransactionMode::Read)?;
@@ -227,8 +293,17 @@ pub fn emit_query<'a>(
     plan: &'a mut SelectPlan,
     t_ctx: &'a mut TranslateCtx<'a>,
 ) -> Result<usize> {
+    if plan.recursive.is_some() {
+        return emit_recursive_cte(program, plan, t_ctx);
+    }
+
+    if !plan.compounds.is_empty() {
+        return emit_compound_select(program, plan, t_ctx);
+    }
+
     // Emit subqueries first so the results can be read in the main query loop.
     emit_subqueries(program, t_ctx, &mut plan.table_references)?;
+    emit_expr_subqueries(program, t_ctx, &mut plan.expr_subqueries)?;
 
     if t_ctx.reg_limit.is_none() {
         t_ctx.reg_limit = plan.limit.map(|_| program.alloc_register());
@@ -265,16 +340,33 @@ pub fn emit_query<'a>(
         t_ctx.reg_nonagg_emit_once_flag = Some(flag);
     }
 
-    // Allocate registers for result columns
-    t_ctx.reg_result_cols_start = Some(program.alloc_registers(plan.result_columns.len()));
+    // Allocate registers for result columns, unless they are shared with other members of a compound SELECT
+    if t_ctx.reg_result_cols_start.is_none() {
+        t_ctx.reg_result_cols_start = Some(program.alloc_registers(plan.result_columns.len()));
+    }
 
     // Initialize cursors and other resources needed for query execution
-    if let Some(ref mut order_by) = plan.order_by {
-        init_order_by(program, t_ctx, order_by)?;
+    init_distinct(program, t_ctx, plan);
+
+    if let Some(ref order_by) = plan.order_by {
+        init_order_by(program, t_ctx, order_by, &plan.table_references)?;
+    }
+
+    if !plan.windows.is_empty() {
+        init_window(program, t_ctx, &plan.windows, &plan.table_references)?;
     }
 
     if let Some(ref group_by) = plan.group_by {
         init_group_by(program, t_ctx, group_by, &plan)?;
+    } else if !plan.aggregates.is_empty() {
+        // The accumulators must start out as NULL every time the query runs, e.g. as a correlated subquery.
+        let num_aggs = plan.aggregates.len();
+        let reg_agg_start = program.alloc_registers(num_aggs);
+        program.emit_insn(Insn::Null {
+            dest: reg_agg_start,
+            dest_end: (num_aggs > 1).then_some(reg_agg_start + num_aggs - 1),
+        });
+        t_ctx.reg_agg_start = Some(reg_agg_start);
     }
     init_loop(
         program,
@@ -309,12 +401,30 @@ pub fn emit_query<'a>(
     // Clean up and close the main execution loop
     close_loop(program, t_ctx, &plan.table_references)?;
 
+    // Emit the rows of the right tables of RIGHT and FULL OUTER JOINs that had no match
+    let right_join_tables = plan
+        .table_references
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| {
+            t.join_info
+                .as_ref()
+                .is_some_and(|join_info| join_info.right)
+        })
+        .map(|(table_index, _)| table_index)
+        .collect::<Vec<_>>();
+    for table_index in right_join_tables {
+        emit_unmatched_rows(program, t_ctx, plan, table_index)?;
+    }
+
     program.resolve_label(after_main_loop_label, program.offset());
 
     let mut order_by_necessary = plan.order_by.is_some() && !plan.contains_constant_false_condition;
     let order_by = plan.order_by.as_ref();
-    // Handle GROUP BY and aggregation processing
-    if plan.group_by.is_some() {
+    // Handle window functions, GROUP BY and aggregation processing
+    if !plan.windows.is_empty() {
+        emit_windows(program, t_ctx, plan)?;
+    } else if plan.group_by.is_some() {
         emit_group_by(program, t_ctx, plan)?;
     } else if !plan.aggregates.is_empty() {
         // Handle aggregation without GROUP BY
@@ -333,7 +443,7 @@ pub fn emit_query<'a>(
 
 fn emit_program_for_delete(
     program: &mut ProgramBuilder,
-    plan: DeletePlan,
+    mut plan: DeletePlan,
     syms: &SymbolTable,
 ) -> Result<()> {
     let (mut t_ctx, init_label, start_offset) = prologue(
@@ -351,6 +461,10 @@ fn emit_program_for_delete(
         return Ok(());
     }
 
+    let returning =
+        (!plan.result_columns.is_empty()).then(|| Returning::open(program, &plan.result_columns));
+    emit_expr_subqueries(program, &mut t_ctx, &mut plan.expr_subqueries)?;
+
     // No rows will be read from source table loops if there is a constant false condition eg. WHERE 0
     let after_main_loop_label = program.allocate_label();
     t_ctx.label_main_loop_end = Some(after_main_loop_label);
@@ -368,6 +482,17 @@ fn emit_program_for_delete(
         OperationMode::DELETE,
     )?;
 
+    let cursors = plan.table_references[0].btree().map(|table| {
+        let table_cursor_id = program.resolve_cursor_id(&plan.table_references[0].cursor_key());
+        WriteCursors::open(
+            program,
+            &table,
+            table_cursor_id,
+            plan.table_references[0].database,
+            plan.indexes.iter().map(|index| (index.clone(), true)),
+        )
+    });
+
     // Set up main query execution loop
     open_loop(
         program,
@@ -375,12 +500,21 @@ fn emit_program_for_delete(
         &plan.table_references,
         &plan.where_clause,
     )?;
-    emit_delete_insns(program, &mut t_ctx, &plan.table_references, &plan.limit)?;
+    emit_delete_insns(
+        program,
+        &mut t_ctx,
+        &plan,
+        cursors.as_ref(),
+        returning.as_ref(),
+    )?;
 
     // Clean up and close the main execution loop
     close_loop(program, &mut t_ctx, &plan.table_references)?;
 
     program.resolve_label(after_main_loop_label, program.offset());
+    if let Some(returning) = &returning {
+        returning.emit_results(program);
+    }
 
     // Finalize program
     epilogue(program, init_label, start_offset, TransactionMode::Write)?;
@@ -392,29 +526,49 @@ fn emit_program_for_delete(
 fn emit_delete_insns(
     program: &mut ProgramBuilder,
     t_ctx: &mut TranslateCtx,
-    table_references: &[TableReference],
-    limit: &Option<isize>,
+    plan: &DeletePlan,
+    cursors: Option<&WriteCursors>,
+    returning: Option<&Returning>,
 ) -> Result<()> {
+    let DeletePlan {
+        table_references,
+        limit,
+        triggers,
+        foreign_keys,
+        ..
+    } = plan;
     let table_reference = table_references.first().unwrap();
     let cursor_id = match &table_reference.op {
-        Operation::Scan { .. } => program.resolve_cursor_id(&table_reference.identifier),
+        Operation::Scan { .. } => program.resolve_cursor_id(&table_reference.cursor_key()),
         Operation::Search(search) => match search {
             Search::RowidEq { .. } | Search::Seek { index: None, .. } => {
-                program.resolve_cursor_id(&table_reference.identifier)
+                program.resolve_cursor_id(&table_reference.cursor_key())
             }
             Search::Seek {
                 index: Some(index), ..
-            } => program.resolve_cursor_id(&index.name),
+            } if table_reference.is_primary_key_index(index) => {
+                program.resolve_cursor_id(&table_reference.cursor_key())
+            }
+            Search::Seek {
+                index: Some(index), ..
+            } => program.resolve_cursor_id(&table_reference.index_cursor_key(index)),
+            Search::Hash { .. } => {
+                unreachable!("the first table is not searched with a hash table")
+            }
         },
         _ => return Ok(()),
     };
 
     // Emit the instructions to delete the row
     let key_reg = program.alloc_register();
-    program.emit_insn(Insn::RowId {
-        cursor_id,
-        dest: key_reg,
-    });
+    if table_reference.btree().is_none_or(|table| table.has_rowid) {
+        program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: key_reg,
+        });
+    } else {
+        program.emit_null(key_reg, None);
+    }
 
     if let Some(vtab) = table_reference.virtual_table() {
         let conflict_action = 0u16;
@@ -432,8 +586,50 @@ fn emit_delete_insns(
             vtab_ptr: vtab.implementation.as_ref().ctx as usize,
             conflict_action,
         });
+    } else if triggers.has(TriggerTime::Before)
+        || triggers.has(TriggerTime::After)
+        || !foreign_keys.is_empty()
+        || returning.is_some()
+    {
+        // Load OLD.* for the triggers, the foreign keys and RETURNING from the table, whichever
+        // cursor drives the loop
+        let table_cursor_id = program.resolve_cursor_id(&table_reference.cursor_key());
+        let columns = table_reference.columns();
+        let old_row = program.alloc_registers(columns.len() + 1);
+        program.emit_insn(Insn::Copy {
+            src_reg: key_reg,
+            dst_reg: old_row,
+            amount: 0,
+        });
+        for i in 0..columns.len() {
+            program.emit_insn(Insn::Column {
+                cursor_id: table_cursor_id,
+                column: i,
+                dest: old_row + 1 + i,
+            });
+        }
+        let next = t_ctx.labels_main_loop[0].next;
+        triggers.emit(program, TriggerTime::Before, Some(old_row), None, next);
+        foreign_keys.emit(program, Some(old_row), None);
+        if let Some(returning) = returning {
+            returning.emit_row(program, old_row, &t_ctx.resolver)?;
+        }
+        if let (Some(cursors), Some(btree)) = (cursors, table_reference.btree()) {
+            emit_delete_index_entries(program, &btree, cursors, true, &t_ctx.resolver)?;
+        }
+        program.emit_insn(Insn::Delete {
+            cursor_id,
+            count_change: true,
+        });
+        triggers.emit(program, TriggerTime::After, Some(old_row), None, next);
     } else {
-        program.emit_insn(Insn::Delete { cursor_id });
+        if let (Some(cursors), Some(btree)) = (cursors, table_reference.btree()) {
+            emit_delete_index_entries(program, &btree, cursors, true, &t_ctx.resolver)?;
+        }
+        program.emit_insn(Insn::Delete {
+            cursor_id,
+            count_change: true,
+        });
     }
     if let Some(limit) = limit {
         let limit_reg = program.alloc_register();
@@ -453,7 +649,7 @@ fn emit_delete_insns(
 
 fn emit_program_for_update(
     program: &mut ProgramBuilder,
-    plan: UpdatePlan,
+    mut plan: UpdatePlan,
     syms: &SymbolTable,
 ) -> Result<()> {
     let (mut t_ctx, init_label, start_offset) = prologue(
@@ -495,6 +691,15 @@ fn emit_program_for_update(
             });
         }
     }
+    let returning = plan
+        .returning
+        .as_ref()
+        .map(|columns| Returning::open(program, columns));
+    emit_expr_subqueries(program, &mut t_ctx, &mut plan.expr_subqueries)?;
+    let moved_rows = plan.moves_rows().then(|| {
+        let table = plan.table_references[0].btree().unwrap();
+        open_moved_rows(program, &table)
+    });
     let after_main_loop_label = program.allocate_label();
     t_ctx.label_main_loop_end = Some(after_main_loop_label);
     if plan.contains_constant_false_condition {
@@ -508,16 +713,55 @@ fn emit_program_for_update(
         &plan.table_references,
         OperationMode::UPDATE,
     )?;
+    let cursors = plan.table_references[0].btree().map(|table| {
+        let table_cursor_id = program.resolve_cursor_id(&plan.table_references[0].cursor_key());
+        // The entries of all the indexes hold the rowid
+        let moves_rowid = table.has_rowid && plan.moves_rows();
+        WriteCursors::open(
+            program,
+            &table,
+            table_cursor_id,
+            plan.table_references[0].database,
+            plan.indexes.iter().map(|index| {
+                let changes = moves_rowid
+                    || plan
+                        .set_clauses
+                        .iter()
+                        .any(|(position, _)| index.depends_on_column(&table, *position));
+                (index.clone(), changes)
+            }),
+        )
+    });
     open_loop(
         program,
         &mut t_ctx,
         &plan.table_references,
         &plan.where_clause,
     )?;
-    emit_update_insns(&plan, &t_ctx, program)?;
+    emit_update_insns(
+        &plan,
+        &t_ctx,
+        program,
+        cursors.as_ref(),
+        returning.as_ref(),
+        moved_rows,
+    )?;
     close_loop(program, &mut t_ctx, &plan.table_references)?;
 
     program.resolve_label(after_main_loop_label, program.offset());
+    if let (Some(moved_rows), Some(cursors)) = (moved_rows, &cursors) {
+        emit_moved_rows_update(
+            &plan,
+            &t_ctx,
+            program,
+            cursors,
+            returning.as_ref(),
+            moved_rows,
+        )?;
+    }
+    if let Some(returning) = &returning {
+        returning.emit_results(program);
+    }
 
     // Finalize program
     epilogue(program, init_label, start_offset, TransactionMode::Write)?;
@@ -526,32 +770,51 @@ fn emit_program_for_update(
     Ok(())
 }
 
+/// Emits the update of the row of the loop over the table. When `moved_rows` is the cursor of the
+/// ephemeral index opened by [open_moved_rows], the updated row is only added to it, and the table
+/// is written by [emit_moved_rows_update] after the loop.
 fn emit_update_insns(
     plan: &UpdatePlan,
     t_ctx: &TranslateCtx,
     program: &mut ProgramBuilder,
+    cursors: Option<&WriteCursors>,
+    returning: Option<&Returning>,
+    moved_rows: Option<CursorID>,
 ) -> crate::Result<()> {
     let table_ref = &plan.table_references.first().unwrap();
     let loop_labels = t_ctx.labels_main_loop.first().unwrap();
     let (cursor_id, index, is_virtual) = match &table_ref.op {
         Operation::Scan { .. } => (
-            program.resolve_cursor_id(&table_ref.identifier),
+            program.resolve_cursor_id(&table_ref.cursor_key()),
             None,
             table_ref.virtual_table().is_some(),
         ),
         Operation::Search(search) => match search {
             &Search::RowidEq { .. } | Search::Seek { index: None, .. } => (
-                program.resolve_cursor_id(&table_ref.identifier),
+                program.resolve_cursor_id(&table_ref.cursor_key()),
+                None,
+                false,
+            ),
+            Search::Seek {
+                index: Some(index), ..
+            } if table_ref.is_primary_key_index(index) => (
+                program.resolve_cursor_id(&table_ref.cursor_key()),
                 None,
                 false,
             ),
             Search::Seek {
                 index: Some(index), ..
             } => (
-                program.resolve_cursor_id(&table_ref.identifier),
-                Some((index.clone(), program.resolve_cursor_id(&index.name))),
+                program.resolve_cursor_id(&table_ref.cursor_key()),
+                Some((
+                    index.clone(),
+                    program.resolve_cursor_id(&table_ref.index_cursor_key(index)),
+                )),
                 false,
             ),
+            Search::Hash { .. } => {
+                unreachable!("the first table is not searched with a hash table")
+            }
         },
         _ => return Ok(()),
     };
@@ -580,15 +843,19 @@ fn emit_update_insns(
                 1 // rowid reg
             },
     );
-    program.emit_insn(Insn::RowId {
-        cursor_id,
-        dest: beg,
-    });
-    // if no rowid, we're done
-    program.emit_insn(Insn::IsNull {
-        reg: beg,
-        target_pc: t_ctx.label_main_loop_end.unwrap(),
-    });
+    if table_ref.btree().is_none_or(|table| table.has_rowid) {
+        program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: beg,
+        });
+        // if no rowid, we're done
+        program.emit_insn(Insn::IsNull {
+            reg: beg,
+            target_pc: t_ctx.label_main_loop_end.unwrap(),
+        });
+    } else {
+        program.emit_null(beg, None);
+    }
     if is_virtual {
         program.emit_insn(Insn::Copy {
             src_reg: beg,
@@ -623,6 +890,11 @@ fn emit_update_insns(
     // we scan a column at a time, loading either the column's values, or the new value
     // from the Set expression, into registers so we can emit a MakeRecord and update the row.
     let start = if is_virtual { beg + 2 } else { beg + 1 };
+    let old_row = if moved_rows.is_none() {
+        emit_old_row(plan, program, cursor_id, beg)
+    } else {
+        None
+    };
     for idx in 0..table_ref.columns().len() {
         let target_reg = start + idx;
         if let Some((_, expr)) = plan.set_clauses.iter().find(|(i, _)| *i == idx) {
@@ -638,12 +910,12 @@ fn emit_update_insns(
             let column_idx_in_index = index.as_ref().and_then(|(idx, _)| {
                 idx.columns
                     .iter()
-                    .position(|c| Some(&c.name) == table_column.name.as_ref())
+                    .position(|c| c.expr.is_none() && Some(&c.name) == table_column.name.as_ref())
             });
 
             // don't emit null for pkey of virtual tables. they require first two args
             // before the 'record' to be explicitly non-null
-            if table_column.primary_key && !is_virtual {
+            if table_column.is_rowid_alias && !is_virtual {
                 program.emit_null(target_reg, None);
             } else if is_virtual {
                 program.emit_insn(Insn::VColumn {
@@ -678,18 +950,22 @@ fn emit_update_insns(
                 table_reference: Rc::clone(&btree_table),
             });
         }
-        let record_reg = program.alloc_register();
-        program.emit_insn(Insn::MakeRecord {
-            start_reg: start,
-            count: table_ref.columns().len(),
-            dest_reg: record_reg,
-        });
-        program.emit_insn(Insn::Insert {
-            cursor: cursor_id,
-            key_reg: beg,
-            record_reg,
-            flag: 0,
-        });
+        match moved_rows {
+            Some(moved_rows) => {
+                emit_collect_moved_row(program, &btree_table, cursor_id, beg, moved_rows)
+            }
+            None => emit_update_row(
+                plan,
+                t_ctx,
+                program,
+                cursors,
+                returning,
+                cursor_id,
+                beg,
+                old_row,
+                loop_labels.next,
+            )?,
+        }
     } else if let Some(vtab) = table_ref.virtual_table() {
         let arg_count = table_ref.columns().len() + 2;
         program.emit_insn(Insn::VUpdate {
@@ -707,6 +983,271 @@ fn emit_update_insns(
             target_pc: t_ctx.label_main_loop_end.unwrap(),
         })
     }
-    // TODO(pthorpe): handle RETURNING clause
+    Ok(())
+}
+
+/// Copies the row at the table cursor to new registers, the rowid first, if the triggers or the
+/// foreign keys of the update need it, and returns the first one.
+fn emit_old_row(
+    plan: &UpdatePlan,
+    program: &mut ProgramBuilder,
+    cursor_id: CursorID,
+    rowid_reg: usize,
+) -> Option<usize> {
+    let needs_old_row = plan.triggers.has(TriggerTime::Before)
+        || plan.triggers.has(TriggerTime::After)
+        || !plan.foreign_keys.is_empty();
+    if !needs_old_row {
+        return None;
+    }
+    let num_columns = plan.table_references[0].columns().len();
+    let old_row = program.alloc_registers(num_columns + 1);
+    program.emit_insn(Insn::Copy {
+        src_reg: rowid_reg,
+        dst_reg: old_row,
+        amount: 0,
+    });
+    for i in 0..num_columns {
+        program.emit_insn(Insn::Column {
+            cursor_id,
+            column: i,
+            dest: old_row + 1 + i,
+        });
+    }
+    Some(old_row)
+}
+
+/// Replaces the row at the table cursor with the updated row at `row`, which is its rowid followed
+/// by its columns. If the update changes the rowid, the row is moved to its new rowid.
+#[allow(clippy::too_many_arguments)]
+fn emit_update_row(
+    plan: &UpdatePlan,
+    t_ctx: &TranslateCtx,
+    program: &mut ProgramBuilder,
+    cursors: Option<&WriteCursors>,
+    returning: Option<&Returning>,
+    cursor_id: CursorID,
+    row: usize,
+    old_row: Option<usize>,
+    skip_row: BranchOffset,
+) -> crate::Result<()> {
+    let btree_table = plan.table_references[0].btree().unwrap();
+    let moves_rowid = btree_table.has_rowid && plan.moves_rows();
+    plan.triggers
+        .emit(program, TriggerTime::Before, old_row, Some(row), skip_row);
+    if let Some(cursors) = cursors {
+        emit_constraint_checks(
+            program,
+            &btree_table,
+            cursors,
+            row,
+            if moves_rowid {
+                RowSource::UpdateRowid
+            } else {
+                RowSource::Update
+            },
+            plan.or_conflict,
+            &|_| None,
+            &plan.replace_deletes,
+            skip_row,
+            &t_ctx.resolver,
+        )?;
+        plan.foreign_keys.emit(program, old_row, Some(row));
+        emit_delete_index_entries(program, &btree_table, cursors, false, &t_ctx.resolver)?;
+    }
+    if moves_rowid {
+        program.emit_insn(Insn::Delete {
+            cursor_id,
+            count_change: false,
+        });
+        let positioned_label = program.allocate_label();
+        program.emit_insn(Insn::NotExists {
+            cursor: cursor_id,
+            rowid_reg: row,
+            target_pc: positioned_label,
+        });
+        program.resolve_label(positioned_label, program.offset());
+    }
+    emit_insert_row(program, &btree_table, cursor_id, row, true);
+    if let Some(cursors) = cursors {
+        emit_insert_index_entries(program, &btree_table, cursors, row, &t_ctx.resolver)?;
+    }
+    if let Some(returning) = returning {
+        returning.emit_row(program, row, &t_ctx.resolver)?;
+    }
+    plan.triggers
+        .emit(program, TriggerTime::After, old_row, Some(row), skip_row);
+    Ok(())
+}
+
+/// Opens the ephemeral index in which an update of the PRIMARY KEY of a WITHOUT ROWID table, or of
+/// the INTEGER PRIMARY KEY of a rowid table, collects the rows to update. Like in SQLite, the table
+/// is only written after the loop over it, which would otherwise meet the rows that moved ahead of
+/// it again. An entry is the PRIMARY KEY or the rowid that a row has, followed by the updated row.
+fn open_moved_rows(program: &mut ProgramBuilder, table: &BTreeTable) -> CursorID {
+    let entry_len = moved_row_key_len(table) + table.columns.len();
+    let index = Arc::new(Index {
+        name: "ephemeral_update".to_string(),
+        table_name: String::new(),
+        root_page: 0,
+        columns: (0..entry_len)
+            .map(|i| IndexColumn {
+                name: format!("c{}", i),
+                order: ast::SortOrder::Asc,
+                pos_in_table: i,
+                collation: None,
+                expr: None,
+            })
+            .collect(),
+        unique: false,
+        ephemeral: true,
+        where_clause: None,
+        key_suffix: Vec::new(),
+    });
+    let cursor_id = program.alloc_cursor_id(None, CursorType::BTreeIndex(index));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id,
+        is_table: false,
+    });
+    cursor_id
+}
+
+/// Adds the row at the table cursor, updated to the row at `row`, to the rows collected by
+/// [open_moved_rows].
+fn emit_collect_moved_row(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursor_id: CursorID,
+    row: usize,
+    moved_rows: CursorID,
+) {
+    let key_len = moved_row_key_len(table);
+    let entry_len = key_len + table.columns.len();
+    let entry_reg = program.alloc_registers(entry_len);
+    if table.has_rowid {
+        program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: entry_reg,
+        });
+    } else {
+        for (i, position) in table.primary_key_column_positions().iter().enumerate() {
+            program.emit_insn(Insn::Column {
+                cursor_id,
+                column: *position,
+                dest: entry_reg + i,
+            });
+        }
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: row + 1,
+        dst_reg: entry_reg + key_len,
+        amount: table.columns.len() - 1,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: entry_reg,
+        count: entry_len,
+        dest_reg: record_reg,
+    });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id: moved_rows,
+        record_reg,
+        unpacked_start: Some(entry_reg),
+        unpacked_count: Some(entry_len as u16),
+        flags: IdxInsertFlags::new(),
+    });
+}
+
+/// Returns the length of the key of a row collected by [open_moved_rows].
+fn moved_row_key_len(table: &BTreeTable) -> usize {
+    if table.has_rowid {
+        1
+    } else {
+        table.primary_key_columns.len()
+    }
+}
+
+/// Emits the loop that writes the rows collected by [open_moved_rows] to the table.
+fn emit_moved_rows_update(
+    plan: &UpdatePlan,
+    t_ctx: &TranslateCtx,
+    program: &mut ProgramBuilder,
+    cursors: &WriteCursors,
+    returning: Option<&Returning>,
+    moved_rows: CursorID,
+) -> crate::Result<()> {
+    let table = plan.table_references[0].btree().unwrap();
+    let key_len = moved_row_key_len(&table);
+    let num_columns = table.columns.len();
+    let done_label = program.allocate_label();
+    let next_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: moved_rows,
+        pc_if_empty: done_label,
+    });
+    let loop_start = program.offset();
+    let key_reg = program.alloc_registers(key_len);
+    for i in 0..key_len {
+        program.emit_insn(Insn::Column {
+            cursor_id: moved_rows,
+            column: i,
+            dest: key_reg + i,
+        });
+    }
+    // The row is NULL for its rowid, followed by its columns
+    let row = program.alloc_registers(num_columns + 1);
+    program.emit_null(row, None);
+    for i in 0..num_columns {
+        program.emit_insn(Insn::Column {
+            cursor_id: moved_rows,
+            column: key_len + i,
+            dest: row + 1 + i,
+        });
+    }
+    // A row that an earlier one replaced is gone
+    let old_row = if let Some((position, _)) = table.get_rowid_alias_column() {
+        // The new rowid is the value given to the INTEGER PRIMARY KEY
+        program.emit_insn(Insn::Copy {
+            src_reg: row + 1 + position,
+            dst_reg: row,
+            amount: 0,
+        });
+        program.emit_insn(Insn::MustBeInt {
+            reg: row,
+            target_pc: None,
+        });
+        program.emit_null(row + 1 + position, None);
+        program.emit_insn(Insn::SeekRowid {
+            cursor_id: cursors.table,
+            src_reg: key_reg,
+            target_pc: next_label,
+        });
+        emit_old_row(plan, program, cursors.table, key_reg)
+    } else {
+        program.emit_insn(Insn::NotFound {
+            cursor_id: cursors.table,
+            target_pc: next_label,
+            record_reg: key_reg,
+            num_regs: key_len,
+        });
+        emit_old_row(plan, program, cursors.table, row)
+    };
+    emit_update_row(
+        plan,
+        t_ctx,
+        program,
+        Some(cursors),
+        returning,
+        cursors.table,
+        row,
+        old_row,
+        next_label,
+    )?;
+    program.resolve_label(next_label, program.offset());
+    program.emit_insn(Insn::Next {
+        cursor_id: moved_rows,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(done_label, program.offset());
     Ok(())
 }
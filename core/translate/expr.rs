@@ -1,9 +1,13 @@
+use std::num::NonZeroUsize;
+
 use limbo_sqlite3_parser::ast::{self, UnaryOperator};
 
+use crate::collation::CollationSeq;
+use crate::error::{SQLITE_CONSTRAINT_TRIGGER, SQLITE_IGNORE};
 #[cfg(feature = "json")]
 use crate::function::JsonFunc;
 use crate::function::{Func, FuncCtx, MathFuncArity, ScalarFunc, VectorFunc};
-use crate::schema::{Table, Type};
+use crate::schema::{affinity, Affinity, Table, Type};
 use crate::util::{exprs_are_equivalent, normalize_ident};
 use crate::vdbe::{
     builder::ProgramBuilder,
@@ -12,6 +16,7 @@ use crate::vdbe::{
 };
 use crate::Result;
 
+use super::collate::{binary_compare_collation, expr_collation, expr_collation_name};
 use super::emitter::Resolver;
 use super::plan::{Operation, TableReference};
 
@@ -44,21 +49,24 @@ macro_rules! emit_cmp_insn {
         $op_true:ident,
         $op_false:ident,
         $lhs:expr,
-        $rhs:expr
+        $rhs:expr,
+        $collation:expr
     ) => {{
         if $cond.jump_if_condition_is_true {
             $program.emit_insn(Insn::$op_true {
                 lhs: $lhs,
                 rhs: $rhs,
                 target_pc: $cond.jump_target_when_true,
-                flags: CmpInsFlags::default(),
+                flags: CmpInsFlags::default().with_collation($collation),
             });
         } else {
             $program.emit_insn(Insn::$op_false {
                 lhs: $lhs,
                 rhs: $rhs,
                 target_pc: $cond.jump_target_when_false,
-                flags: CmpInsFlags::default().jump_if_null(),
+                flags: CmpInsFlags::default()
+                    .with_collation($collation)
+                    .jump_if_null(),
             });
         }
     }};
@@ -71,21 +79,22 @@ macro_rules! emit_cmp_null_insn {
         $op_true:ident,
         $op_false:ident,
         $lhs:expr,
-        $rhs:expr
+        $rhs:expr,
+        $collation:expr
     ) => {{
         if $cond.jump_if_condition_is_true {
             $program.emit_insn(Insn::$op_true {
                 lhs: $lhs,
                 rhs: $rhs,
                 target_pc: $cond.jump_target_when_true,
-                flags: CmpInsFlags::default().null_eq(),
+                flags: CmpInsFlags::default().with_collation($collation).null_eq(),
             });
         } else {
             $program.emit_insn(Insn::$op_false {
                 lhs: $lhs,
                 rhs: $rhs,
                 target_pc: $cond.jump_target_when_false,
-                flags: CmpInsFlags::default().null_eq(),
+                flags: CmpInsFlags::default().with_collation($collation).null_eq(),
             });
         }
     }};
@@ -256,30 +265,96 @@ pub fn translate_condition_expr(
             let rhs_reg = program.alloc_register();
             translate_and_mark(program, Some(referenced_tables), lhs, lhs_reg, resolver)?;
             translate_and_mark(program, Some(referenced_tables), rhs, rhs_reg, resolver)?;
+            let collation =
+                binary_compare_collation(lhs, rhs, Some(referenced_tables), resolver.symbol_table)?;
             match op {
                 ast::Operator::Greater => {
-                    emit_cmp_insn!(program, condition_metadata, Gt, Le, lhs_reg, rhs_reg)
+                    emit_cmp_insn!(
+                        program,
+                        condition_metadata,
+                        Gt,
+                        Le,
+                        lhs_reg,
+                        rhs_reg,
+                        collation
+                    )
                 }
                 ast::Operator::GreaterEquals => {
-                    emit_cmp_insn!(program, condition_metadata, Ge, Lt, lhs_reg, rhs_reg)
+                    emit_cmp_insn!(
+                        program,
+                        condition_metadata,
+                        Ge,
+                        Lt,
+                        lhs_reg,
+                        rhs_reg,
+                        collation
+                    )
                 }
                 ast::Operator::Less => {
-                    emit_cmp_insn!(program, condition_metadata, Lt, Ge, lhs_reg, rhs_reg)
+                    emit_cmp_insn!(
+                        program,
+                        condition_metadata,
+                        Lt,
+                        Ge,
+                        lhs_reg,
+                        rhs_reg,
+                        collation
+                    )
                 }
                 ast::Operator::LessEquals => {
-                    emit_cmp_insn!(program, condition_metadata, Le, Gt, lhs_reg, rhs_reg)
+                    emit_cmp_insn!(
+                        program,
+                        condition_metadata,
+                        Le,
+                        Gt,
+                        lhs_reg,
+                        rhs_reg,
+                        collation
+                    )
                 }
                 ast::Operator::Equals => {
-                    emit_cmp_insn!(program, condition_metadata, Eq, Ne, lhs_reg, rhs_reg)
+                    emit_cmp_insn!(
+                        program,
+                        condition_metadata,
+                        Eq,
+                        Ne,
+                        lhs_reg,
+                        rhs_reg,
+                        collation
+                    )
                 }
                 ast::Operator::NotEquals => {
-                    emit_cmp_insn!(program, condition_metadata, Ne, Eq, lhs_reg, rhs_reg)
+                    emit_cmp_insn!(
+                        program,
+                        condition_metadata,
+                        Ne,
+                        Eq,
+                        lhs_reg,
+                        rhs_reg,
+                        collation
+                    )
                 }
                 ast::Operator::Is => {
-                    emit_cmp_null_insn!(program, condition_metadata, Eq, Ne, lhs_reg, rhs_reg)
+                    emit_cmp_null_insn!(
+                        program,
+                        condition_metadata,
+                        Eq,
+                        Ne,
+                        lhs_reg,
+                        rhs_reg,
+                        collation
+                    )
                 }
                 ast::Operator::IsNot => {
-                    emit_cmp_null_insn!(program, condition_metadata, Ne, Eq, lhs_reg, rhs_reg)
+                    emit_cmp_null_insn!(
+                        program,
+                        condition_metadata,
+                        Ne,
+                        Eq,
+                        lhs_reg,
+                        rhs_reg,
+                        collation
+                    )
                 }
                 _ => unreachable!(),
             }
@@ -294,7 +369,12 @@ pub fn translate_condition_expr(
         | ast::Expr::FunctionCall { .. }
         | ast::Expr::Column { .. }
         | ast::Expr::RowId { .. }
-        | ast::Expr::Case { .. } => {
+        | ast::Expr::Case { .. }
+        | ast::Expr::Collate(..)
+        | ast::Expr::SubqueryResult { .. }
+        | ast::Expr::OuterRef(_)
+        | ast::Expr::TriggerParam(_)
+        | ast::Expr::Register(_) => {
             let reg = program.alloc_register();
             translate_expr(program, Some(referenced_tables), expr, reg, resolver)?;
             emit_cond_jump(program, condition_metadata, reg);
@@ -338,6 +418,8 @@ pub fn translate_condition_expr(
             // The left hand side only needs to be evaluated once we have a list of values to compare against.
             let lhs_reg = program.alloc_register();
             let _ = translate_expr(program, Some(referenced_tables), lhs, lhs_reg, resolver)?;
+            // The values are compared using the collating sequence of the left hand side.
+            let collation = expr_collation(lhs, Some(referenced_tables), resolver.symbol_table)?;
 
             let rhs = rhs.as_ref().unwrap();
 
@@ -368,7 +450,7 @@ pub fn translate_condition_expr(
                             lhs: lhs_reg,
                             rhs: rhs_reg,
                             target_pc: jump_target_when_true,
-                            flags: CmpInsFlags::default(),
+                            flags: CmpInsFlags::default().with_collation(collation),
                         });
                     } else {
                         // If this is the last condition, we need to jump to the 'jump_target_when_false' label if there is no match.
@@ -376,7 +458,9 @@ pub fn translate_condition_expr(
                             lhs: lhs_reg,
                             rhs: rhs_reg,
                             target_pc: condition_metadata.jump_target_when_false,
-                            flags: CmpInsFlags::default().jump_if_null(),
+                            flags: CmpInsFlags::default()
+                                .jump_if_null()
+                                .with_collation(collation),
                         });
                     }
                 }
@@ -397,7 +481,9 @@ pub fn translate_condition_expr(
                         lhs: lhs_reg,
                         rhs: rhs_reg,
                         target_pc: condition_metadata.jump_target_when_false,
-                        flags: CmpInsFlags::default().jump_if_null(),
+                        flags: CmpInsFlags::default()
+                            .jump_if_null()
+                            .with_collation(collation),
                     });
                 }
                 // If we got here, then none of the conditions were a match, so we jump to the 'jump_target_when_true' label if 'jump_if_condition_is_true'.
@@ -498,12 +584,21 @@ pub fn translate_expr(
             unreachable!("expression should have been rewritten in optmizer")
         }
         ast::Expr::Binary(e1, op, e2) => {
+            let collation =
+                binary_compare_collation(e1, e2, referenced_tables, resolver.symbol_table)?;
             // Check if both sides of the expression are equivalent and reuse the same register if so
             if exprs_are_equivalent(e1, e2) {
                 let shared_reg = program.alloc_register();
                 translate_expr(program, referenced_tables, e1, shared_reg, resolver)?;
 
-                emit_binary_insn(program, op, shared_reg, shared_reg, target_register)?;
+                emit_binary_insn(
+                    program,
+                    op,
+                    shared_reg,
+                    shared_reg,
+                    target_register,
+                    collation,
+                )?;
                 return Ok(target_register);
             }
 
@@ -513,7 +608,7 @@ pub fn translate_expr(
             translate_expr(program, referenced_tables, e1, e1_reg, resolver)?;
             translate_expr(program, referenced_tables, e2, e2_reg, resolver)?;
 
-            emit_binary_insn(program, op, e1_reg, e2_reg, target_register)?;
+            emit_binary_insn(program, op, e1_reg, e2_reg, target_register, collation)?;
             Ok(target_register)
         }
         ast::Expr::Case {
@@ -546,17 +641,24 @@ pub fn translate_expr(
             };
             for (when_expr, then_expr) in when_then_pairs {
                 translate_expr(program, referenced_tables, when_expr, expr_reg, resolver)?;
-                match base_reg {
+                match (base_reg, base) {
                     // CASE 1 WHEN 0 THEN 0 ELSE 1 becomes 1==0, Ne branch to next clause
-                    Some(base_reg) => program.emit_insn(Insn::Ne {
+                    (Some(base_reg), Some(base_expr)) => program.emit_insn(Insn::Ne {
                         lhs: base_reg,
                         rhs: expr_reg,
                         target_pc: next_case_label,
                         // A NULL result is considered untrue when evaluating WHEN terms.
-                        flags: CmpInsFlags::default().jump_if_null(),
+                        flags: CmpInsFlags::default().jump_if_null().with_collation(
+                            binary_compare_collation(
+                                base_expr,
+                                when_expr,
+                                referenced_tables,
+                                resolver.symbol_table,
+                            )?,
+                        ),
                     }),
                     // CASE WHEN 0 THEN 0 ELSE 1 becomes ifnot 0 branch to next clause
-                    None => program.emit_insn(Insn::IfNot {
+                    _ => program.emit_insn(Insn::IfNot {
                         reg: expr_reg,
                         target_pc: next_case_label,
                         jump_if_null: true,
@@ -616,14 +718,17 @@ pub fn translate_expr(
             });
             Ok(target_register)
         }
-        ast::Expr::Collate(_, _) => todo!(),
+        ast::Expr::Collate(expr, _) => {
+            // The collating sequence only affects the comparisons the expression is an operand of,
+            // see [binary_compare_collation]; the value itself is unchanged.
+            translate_expr(program, referenced_tables, expr, target_register, resolver)
+        }
         ast::Expr::DoublyQualified(_, _, _) => todo!(),
-        ast::Expr::Exists(_) => todo!(),
         ast::Expr::FunctionCall {
             name,
             distinctness: _,
             args,
-            filter_over: _,
+            filter_over,
             order_by: _,
         } => {
             let args_count = if let Some(args) = args { args.len() } else { 0 };
@@ -633,6 +738,13 @@ pub fn translate_expr(
             if func_type.is_none() {
                 crate::bail_parse_error!("unknown function {}", name.0);
             }
+            if filter_over
+                .as_ref()
+                .is_some_and(|tail| tail.filter_clause.is_some())
+                && !matches!(func_type, Some(Func::Agg(_)))
+            {
+                crate::bail_parse_error!("FILTER may not be used with non-aggregate {}()", name.0);
+            }
 
             let func_ctx = FuncCtx {
                 func: func_type.unwrap(),
@@ -641,7 +753,10 @@ pub fn translate_expr(
 
             match &func_ctx.func {
                 Func::Agg(_) => {
-                    crate::bail_parse_error!("aggregation function in non-aggregation context")
+                    crate::bail_parse_error!("misuse of aggregate function {}()", name.0)
+                }
+                Func::AlterTable(_) => {
+                    unreachable!("ALTER TABLE functions are not resolved by name")
                 }
                 Func::External(_) => {
                     let regs = program.alloc_registers(args_count);
@@ -1402,7 +1517,7 @@ pub fn translate_expr(
                                     resolver,
                                 )?;
                             }
-
+                            emit_function_collation(program, args, referenced_tables, resolver)?;
                             program.emit_insn(Insn::Function {
                                 constant_mask: 0,
                                 start_reg,
@@ -1432,7 +1547,7 @@ pub fn translate_expr(
                                     resolver,
                                 )?;
                             }
-
+                            emit_function_collation(program, args, referenced_tables, resolver)?;
                             program.emit_insn(Insn::Function {
                                 constant_mask: 0,
                                 start_reg,
@@ -1762,7 +1877,19 @@ pub fn translate_expr(
                 },
             }
         }
-        ast::Expr::FunctionCallStar { .. } => todo!(),
+        ast::Expr::FunctionCallStar { name, .. } => {
+            // Only count(*) takes `*`, and its calls are aggregated before the expressions that
+            // contain them are translated, so any call here is misplaced.
+            match resolver.resolve_function(&normalize_ident(name.0.as_str()), 0) {
+                Some(Func::Agg(_)) => {
+                    crate::bail_parse_error!("misuse of aggregate function {}()", name.0)
+                }
+                Some(_) => {
+                    crate::bail_parse_error!("wrong number of arguments to function {}()", name.0)
+                }
+                None => crate::bail_parse_error!("unknown function {}", name.0),
+            }
+        }
         ast::Expr::Id(id) => crate::bail_parse_error!(
             "no such column: {} - should this be a string literal in single-quotes?",
             id.0
@@ -1783,15 +1910,14 @@ pub fn translate_expr(
                 Operation::Scan { .. } | Operation::Search(_) => {
                     match &table_reference.table {
                         Table::BTree(_) => {
+                            let (table_cursor_id, index_cursor_id) =
+                                table_reference.resolve_cursors(program)?;
                             let table_cursor_id = if use_covering_index {
                                 None
                             } else {
-                                Some(program.resolve_cursor_id(&table_reference.identifier))
-                            };
-                            let index_cursor_id = if let Some(index) = index {
-                                Some(program.resolve_cursor_id(&index.name))
-                            } else {
-                                None
+                                Some(table_cursor_id.unwrap_or_else(|| {
+                                    panic!("Cursor not found: {}", table_reference.identifier)
+                                }))
                             };
                             if *is_rowid_alias {
                                 if let Some(index_cursor_id) = index_cursor_id {
@@ -1836,7 +1962,8 @@ pub fn translate_expr(
                             Ok(target_register)
                         }
                         Table::Virtual(_) => {
-                            let cursor_id = program.resolve_cursor_id(&table_reference.identifier);
+                            let cursor_id =
+                                program.resolve_cursor_id(&table_reference.cursor_key());
                             program.emit_insn(Insn::VColumn {
                                 cursor_id,
                                 column: *column,
@@ -1869,13 +1996,13 @@ pub fn translate_expr(
             if use_covering_index {
                 let index =
                     index.expect("index cursor should be opened when use_covering_index=true");
-                let cursor_id = program.resolve_cursor_id(&index.name);
+                let cursor_id = program.resolve_cursor_id(&table_reference.index_cursor_key(index));
                 program.emit_insn(Insn::IdxRowId {
                     cursor_id,
                     dest: target_register,
                 });
             } else {
-                let cursor_id = program.resolve_cursor_id(&table_reference.identifier);
+                let cursor_id = program.resolve_cursor_id(&table_reference.cursor_key());
                 program.emit_insn(Insn::RowId {
                     cursor_id,
                     dest: target_register,
@@ -1883,10 +2010,93 @@ pub fn translate_expr(
             }
             Ok(target_register)
         }
-        ast::Expr::InList { .. } => todo!(),
-        ast::Expr::InSelect { .. } => todo!(),
+        ast::Expr::InList { lhs, not, rhs } => {
+            // Like in SQLite, the result is NULL if the left hand side is NULL, or if it is equal
+            // to none of the values and one of them is NULL.
+            let Some(rhs) = rhs.as_ref().filter(|rhs| !rhs.is_empty()) else {
+                program.emit_insn(Insn::Integer {
+                    value: *not as i64,
+                    dest: target_register,
+                });
+                return Ok(target_register);
+            };
+            let lhs_reg = program.alloc_register();
+            translate_expr(program, referenced_tables, lhs, lhs_reg, resolver)?;
+            let null_label = program.allocate_label();
+            let found_label = program.allocate_label();
+            let done_label = program.allocate_label();
+            program.emit_insn(Insn::IsNull {
+                reg: lhs_reg,
+                target_pc: null_label,
+            });
+            let rhs_regs = program.alloc_registers(rhs.len());
+            for (i, expr) in rhs.iter().enumerate() {
+                translate_expr(program, referenced_tables, expr, rhs_regs + i, resolver)?;
+                let collation =
+                    binary_compare_collation(lhs, expr, referenced_tables, resolver.symbol_table)?;
+                program.emit_insn(Insn::Eq {
+                    lhs: lhs_reg,
+                    rhs: rhs_regs + i,
+                    target_pc: found_label,
+                    flags: CmpInsFlags::default().with_collation(collation),
+                });
+            }
+            for i in 0..rhs.len() {
+                program.emit_insn(Insn::IsNull {
+                    reg: rhs_regs + i,
+                    target_pc: null_label,
+                });
+            }
+            program.emit_insn(Insn::Integer {
+                value: *not as i64,
+                dest: target_register,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: done_label,
+            });
+            program.resolve_label(found_label, program.offset());
+            program.emit_insn(Insn::Integer {
+                value: !*not as i64,
+                dest: target_register,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: done_label,
+            });
+            program.resolve_label(null_label, program.offset());
+            program.emit_insn(Insn::Null {
+                dest: target_register,
+                dest_end: None,
+            });
+            program.resolve_label(done_label, program.offset());
+            Ok(target_register)
+        }
         ast::Expr::InTable { .. } => todo!(),
-        ast::Expr::IsNull(_) => todo!(),
+        ast::Expr::IsNull(operand) | ast::Expr::NotNull(operand) => {
+            let reg = program.alloc_register();
+            translate_expr(program, referenced_tables, operand, reg, resolver)?;
+            let done_label = program.allocate_label();
+            program.emit_insn(Insn::Integer {
+                value: 1,
+                dest: target_register,
+            });
+            if matches!(expr, ast::Expr::IsNull(_)) {
+                program.emit_insn(Insn::IsNull {
+                    reg,
+                    target_pc: done_label,
+                });
+            } else {
+                program.emit_insn(Insn::NotNull {
+                    reg,
+                    target_pc: done_label,
+                });
+            }
+            program.emit_insn(Insn::Integer {
+                value: 0,
+                dest: target_register,
+            });
+            program.resolve_label(done_label, program.offset());
+            Ok(target_register)
+        }
         ast::Expr::Like { not, .. } => {
             let like_reg = if *not {
                 program.alloc_register()
@@ -1962,7 +2172,6 @@ pub fn translate_expr(
             ast::Literal::CurrentTimestamp => todo!(),
         },
         ast::Expr::Name(_) => todo!(),
-        ast::Expr::NotNull(_) => todo!(),
         ast::Expr::Parenthesized(exprs) => {
             if exprs.is_empty() {
                 crate::bail_parse_error!("parenthesized expression with no arguments");
@@ -1985,8 +2194,153 @@ pub fn translate_expr(
         ast::Expr::Qualified(_, _) => {
             unreachable!("Qualified should be resolved to a Column before translation")
         }
-        ast::Expr::Raise(_, _) => todo!(),
-        ast::Expr::Subquery(_) => todo!(),
+        ast::Expr::Raise(resolve_type, message) => {
+            if *resolve_type == ast::ResolveType::Ignore {
+                program.emit_halt_err(SQLITE_IGNORE, *resolve_type, String::new());
+                return Ok(target_register);
+            }
+            let description = match message.as_deref() {
+                Some(ast::Expr::Literal(ast::Literal::String(s))) => sanitize_string(s),
+                _ => crate::bail_parse_error!("RAISE() error message must be a string literal"),
+            };
+            program.emit_halt_err(SQLITE_CONSTRAINT_TRIGGER, *resolve_type, description);
+            Ok(target_register)
+        }
+        ast::Expr::Exists(_) | ast::Expr::InSelect { .. } | ast::Expr::Subquery(_) => {
+            // Subqueries are planned before translation, see [super::planner::plan_subqueries].
+            crate::bail_parse_error!("subqueries are only supported in SELECT, and in the WHERE and SET clauses of DELETE and UPDATE")
+        }
+        ast::Expr::SubqueryResult {
+            subquery_id,
+            lhs,
+            not,
+            outer_refs,
+        } => {
+            let subquery = &resolver.expr_subqueries[*subquery_id];
+            // A subquery that does not refer to the outer queries has the same result every time,
+            // so it only runs the first time it is needed.
+            let label_after_run = program.allocate_label();
+            if outer_refs.is_empty() {
+                program.emit_insn(Insn::Once {
+                    target_pc_when_reentered: label_after_run,
+                });
+            }
+            for (i, outer_ref) in outer_refs.iter().enumerate() {
+                translate_expr(
+                    program,
+                    referenced_tables,
+                    outer_ref,
+                    subquery.reg_outer_refs_start + i,
+                    resolver,
+                )?;
+            }
+            program.emit_insn(Insn::Gosub {
+                target_pc: subquery.label_start,
+                return_reg: subquery.reg_return,
+            });
+            program.resolve_label(label_after_run, program.offset());
+
+            let Some(cursor_id) = subquery.cursor_id else {
+                program.emit_insn(Insn::Copy {
+                    src_reg: subquery.reg_result.unwrap(),
+                    dst_reg: target_register,
+                    amount: 0,
+                });
+                return Ok(target_register);
+            };
+            // x IN (SELECT ...) is true if x is one of the rows, NULL if it is not but x is NULL or
+            // one of the rows is NULL, and false otherwise. It is false for no rows at all, even
+            // if x is NULL.
+            let lhs = lhs.as_ref().expect("IN subquery without a left-hand side");
+            let reg_lhs = program.alloc_register();
+            let reg_first = program.alloc_register();
+            let label_null = program.allocate_label();
+            let label_not_found = program.allocate_label();
+            let label_done = program.allocate_label();
+            translate_expr(program, referenced_tables, lhs, reg_lhs, resolver)?;
+            if subquery.affinity != Affinity::Blob {
+                program.emit_insn(Insn::Affinity {
+                    start_reg: reg_lhs,
+                    count: NonZeroUsize::new(1).unwrap(),
+                    affinities: subquery.affinity.aff_mask().to_string(),
+                });
+            }
+            program.emit_insn(Insn::Integer {
+                value: 0,
+                dest: target_register,
+            });
+            program.emit_insn(Insn::Rewind {
+                cursor_id,
+                pc_if_empty: label_done,
+            });
+            // NULLs sort first, so the set contains a NULL if the first row is NULL.
+            program.emit_insn(Insn::Column {
+                cursor_id,
+                column: 0,
+                dest: reg_first,
+            });
+            program.emit_insn(Insn::IsNull {
+                reg: reg_lhs,
+                target_pc: label_null,
+            });
+            program.emit_insn(Insn::NotFound {
+                cursor_id,
+                target_pc: label_not_found,
+                record_reg: reg_lhs,
+                num_regs: 1,
+            });
+            program.emit_insn(Insn::Integer {
+                value: 1,
+                dest: target_register,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: label_done,
+            });
+            program.resolve_label(label_not_found, program.offset());
+            program.emit_insn(Insn::NotNull {
+                reg: reg_first,
+                target_pc: label_done,
+            });
+            program.resolve_label(label_null, program.offset());
+            program.emit_insn(Insn::Null {
+                dest: target_register,
+                dest_end: None,
+            });
+            program.resolve_label(label_done, program.offset());
+            if *not {
+                program.emit_insn(Insn::Not {
+                    reg: target_register,
+                    dest: target_register,
+                });
+            }
+            Ok(target_register)
+        }
+        ast::Expr::OuterRef(index) => {
+            let reg_outer_refs_start = resolver
+                .reg_outer_refs_start
+                .expect("outer reference outside of a subquery");
+            program.emit_insn(Insn::Copy {
+                src_reg: reg_outer_refs_start + index,
+                dst_reg: target_register,
+                amount: 0,
+            });
+            Ok(target_register)
+        }
+        ast::Expr::TriggerParam(offset) => {
+            program.emit_insn(Insn::Param {
+                offset: *offset,
+                dest: target_register,
+            });
+            Ok(target_register)
+        }
+        ast::Expr::Register(reg) => {
+            program.emit_insn(Insn::Copy {
+                src_reg: *reg,
+                dst_reg: target_register,
+                amount: 0,
+            });
+            Ok(target_register)
+        }
         ast::Expr::Unary(op, expr) => match (op, expr.as_ref()) {
             (UnaryOperator::Positive, expr) => {
                 translate_expr(program, referenced_tables, expr, target_register, resolver)
@@ -2101,12 +2455,30 @@ pub fn translate_expr(
     }
 }
 
+/// Emit the collating sequence that a function which compares its arguments, like min() and max(),
+/// uses for the comparisons: that of the first argument that has one.
+fn emit_function_collation(
+    program: &mut ProgramBuilder,
+    args: &[ast::Expr],
+    referenced_tables: Option<&[TableReference]>,
+    resolver: &Resolver,
+) -> Result<()> {
+    let arg = args
+        .iter()
+        .find(|arg| expr_collation_name(arg, referenced_tables).is_some())
+        .unwrap_or(&args[0]);
+    let collation = expr_collation(arg, referenced_tables, resolver.symbol_table)?;
+    program.emit_insn(Insn::CollSeq { collation });
+    Ok(())
+}
+
 fn emit_binary_insn(
     program: &mut ProgramBuilder,
     op: &ast::Operator,
     lhs: usize,
     rhs: usize,
     target_register: usize,
+    collation: CollationSeq,
 ) -> Result<()> {
     match op {
         ast::Operator::NotEquals => {
@@ -2117,7 +2489,7 @@ fn emit_binary_insn(
                     lhs,
                     rhs,
                     target_pc: if_true_label,
-                    flags: CmpInsFlags::default(),
+                    flags: CmpInsFlags::default().with_collation(collation),
                 },
                 target_register,
                 if_true_label,
@@ -2133,7 +2505,7 @@ fn emit_binary_insn(
                     lhs,
                     rhs,
                     target_pc: if_true_label,
-                    flags: CmpInsFlags::default(),
+                    flags: CmpInsFlags::default().with_collation(collation),
                 },
                 target_register,
                 if_true_label,
@@ -2149,7 +2521,7 @@ fn emit_binary_insn(
                     lhs,
                     rhs,
                     target_pc: if_true_label,
-                    flags: CmpInsFlags::default(),
+                    flags: CmpInsFlags::default().with_collation(collation),
                 },
                 target_register,
                 if_true_label,
@@ -2165,7 +2537,7 @@ fn emit_binary_insn(
                     lhs,
                     rhs,
                     target_pc: if_true_label,
-                    flags: CmpInsFlags::default(),
+                    flags: CmpInsFlags::default().with_collation(collation),
                 },
                 target_register,
                 if_true_label,
@@ -2181,7 +2553,7 @@ fn emit_binary_insn(
                     lhs,
                     rhs,
                     target_pc: if_true_label,
-                    flags: CmpInsFlags::default(),
+                    flags: CmpInsFlags::default().with_collation(collation),
                 },
                 target_register,
                 if_true_label,
@@ -2197,7 +2569,7 @@ fn emit_binary_insn(
                     lhs,
                     rhs,
                     target_pc: if_true_label,
-                    flags: CmpInsFlags::default(),
+                    flags: CmpInsFlags::default().with_collation(collation),
                 },
                 target_register,
                 if_true_label,
@@ -2290,7 +2662,7 @@ fn emit_binary_insn(
                     lhs,
                     rhs,
                     target_pc: if_true_label,
-                    flags: CmpInsFlags::default().null_eq(),
+                    flags: CmpInsFlags::default().with_collation(collation).null_eq(),
                 },
                 target_register,
                 if_true_label,
@@ -2304,7 +2676,7 @@ fn emit_binary_insn(
                     lhs,
                     rhs,
                     target_pc: if_true_label,
-                    flags: CmpInsFlags::default().null_eq(),
+                    flags: CmpInsFlags::default().with_collation(collation).null_eq(),
                 },
                 target_register,
                 if_true_label,
@@ -2474,6 +2846,38 @@ fn wrap_eval_jump_expr_zero_or_null(
     program.preassign_label_to_next_insn(if_true_label);
 }
 
+/// The affinity of an expression, if it has one. Only a column, possibly parenthesized or with
+/// a COLLATE operator, and a CAST have an affinity.
+/// https://www.sqlite.org/datatype3.html#affinity_of_expressions
+pub fn expr_affinity(expr: &ast::Expr, tables: Option<&[TableReference]>) -> Option<Affinity> {
+    match expr {
+        ast::Expr::Column { table, column, .. } => {
+            let column = tables?.get(*table)?.columns().get(*column)?;
+            Some(column.affinity())
+        }
+        ast::Expr::RowId { .. } => Some(Affinity::Integer),
+        ast::Expr::Cast { type_name, .. } => Some(
+            type_name
+                .as_ref()
+                .map_or(Affinity::Blob, |ty| affinity(&ty.name.to_uppercase())),
+        ),
+        ast::Expr::Parenthesized(exprs) if exprs.len() == 1 => expr_affinity(&exprs[0], tables),
+        ast::Expr::Collate(expr, _) => expr_affinity(expr, tables),
+        _ => None,
+    }
+}
+
+/// The affinity applied to both operands of a comparison before comparing them, given the
+/// affinities of the operands, see [expr_affinity]. BLOB affinity leaves the values as they are.
+/// https://www.sqlite.org/datatype3.html#type_conversions_prior_to_comparison
+pub fn comparison_affinity(lhs: Option<Affinity>, rhs: Option<Affinity>) -> Affinity {
+    match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) if lhs.is_numeric() || rhs.is_numeric() => Affinity::Numeric,
+        (Some(_), Some(_)) | (None, None) => Affinity::Blob,
+        (Some(affinity), None) | (None, Some(affinity)) => affinity,
+    }
+}
+
 pub fn maybe_apply_affinity(col_type: Type, target_register: usize, program: &mut ProgramBuilder) {
     if col_type == Type::Real {
         program.emit_insn(Insn::RealAffinity {
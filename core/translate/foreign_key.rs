@@ -0,0 +1,387 @@
+//! Enforcement of the FOREIGN KEY constraints, when PRAGMA foreign_keys is on.
+//!
+//! Like in SQLite, a statement does not check that each constraint holds after it changes a row,
+//! but counts the violations that it makes and the ones that it fixes with [Insn::FkCounter], and
+//! fails when it ends if it leaves violations of immediate constraints. The violations of
+//! DEFERRABLE INITIALLY DEFERRED constraints are counted for the transaction instead, which
+//! cannot commit until it has fixed them.
+//!
+//! When a row of a child table changes, the old child key no longer violates the constraint if
+//! it had no parent row, and the new one does if it has none. When a row of a parent table
+//! changes, the child rows that refer to the old parent key become violations, and the ones that
+//! refer to the new parent key no longer are. The rows, i.e. the rowid followed by the columns,
+//! are in blocks of registers like in [super::constraint], where a rowid alias column holds NULL.
+//!
+//! The ON DELETE and ON UPDATE actions are run as triggers, see [super::trigger::RowTriggers].
+
+use std::num::NonZeroUsize;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::schema::{BTreeTable, Index, Schema};
+use crate::vdbe::builder::{CursorType, ProgramBuilder};
+use crate::vdbe::insn::{CmpInsFlags, Insn};
+use crate::vdbe::BranchOffset;
+use crate::{bail_parse_error, LimboError, Result, SymbolTable};
+
+/// A FOREIGN KEY constraint, resolved against its child and parent tables.
+#[derive(Debug, Clone)]
+pub struct ResolvedForeignKey {
+    pub child: Rc<BTreeTable>,
+    /// The index of the constraint in the foreign keys of the child table.
+    pub fk_index: usize,
+    pub parent: Rc<BTreeTable>,
+    /// The position of each column of the child key in the child table.
+    pub child_columns: Vec<usize>,
+    /// The position of each column of the parent key in the parent table, in the order of the
+    /// child key.
+    pub parent_columns: Vec<usize>,
+    /// The UNIQUE index on the parent key, or None if the parent key is the rowid.
+    pub parent_index: Option<Arc<Index>>,
+    pub deferred: bool,
+    /// The database of the child and parent tables, see [Schema::database].
+    pub database: usize,
+}
+
+impl ResolvedForeignKey {
+    /// Resolves the foreign key at `fk_index` in `child`. Like in SQLite, the parent key must be
+    /// the PRIMARY KEY of the parent table, or have a UNIQUE index.
+    pub fn new(schema: &Schema, child: &Rc<BTreeTable>, fk_index: usize) -> Result<Self> {
+        let fk = &child.foreign_keys[fk_index];
+        let Some(parent) = schema.get_btree_table(&fk.parent_table) else {
+            bail_parse_error!("no such table: main.{}", fk.parent_table);
+        };
+        let positions = |table: &BTreeTable, names: &mut dyn Iterator<Item = &String>| {
+            names
+                .map(|name| table.get_column(name).map(|(position, _)| position))
+                .collect::<Option<Vec<_>>>()
+        };
+        let child_columns = positions(child, &mut fk.child_columns.iter());
+        let parent_columns = if fk.parent_columns.is_empty() {
+            positions(
+                &parent,
+                &mut parent.primary_key_columns.iter().map(|(name, _)| name),
+            )
+        } else {
+            positions(&parent, &mut fk.parent_columns.iter())
+        };
+        let mismatch = || {
+            LimboError::ParseError(format!(
+                "foreign key mismatch - \"{}\" referencing \"{}\"",
+                child.name, parent.name
+            ))
+        };
+        let (Some(child_columns), Some(parent_columns)) = (child_columns, parent_columns) else {
+            return Err(mismatch());
+        };
+        if parent_columns.is_empty() || child_columns.len() != parent_columns.len() {
+            return Err(mismatch());
+        }
+        let parent_index =
+            if parent_columns.len() == 1 && parent.columns[parent_columns[0]].is_rowid_alias {
+                None
+            } else {
+                let index = schema.get_indices(&parent.name).iter().find(|index| {
+                    index.unique
+                        && index.where_clause.is_none()
+                        && !index.has_expressions()
+                        && index.columns.len() == parent_columns.len()
+                        && index
+                            .columns
+                            .iter()
+                            .all(|column| parent_columns.contains(&column.pos_in_table))
+                });
+                Some(index.cloned().ok_or_else(mismatch)?)
+            };
+        Ok(Self {
+            child: child.clone(),
+            fk_index,
+            parent,
+            child_columns,
+            parent_columns,
+            parent_index,
+            deferred: fk.deferred,
+            database: schema.database,
+        })
+    }
+
+    fn is_self_reference(&self) -> bool {
+        self.child.name == self.parent.name
+    }
+
+    /// Jumps to `found` if the child row at `row` has a parent row, or a NULL in its child key,
+    /// and falls through otherwise.
+    pub fn emit_parent_lookup(
+        &self,
+        program: &mut ProgramBuilder,
+        row: usize,
+        found: BranchOffset,
+    ) {
+        for &column in &self.child_columns {
+            program.emit_insn(Insn::IsNull {
+                reg: column_reg(&self.child, row, column),
+                target_pc: found,
+            });
+        }
+        let not_found = program.allocate_label();
+        match &self.parent_index {
+            None => {
+                let key_reg = program.alloc_register();
+                program.emit_insn(Insn::Copy {
+                    src_reg: column_reg(&self.child, row, self.child_columns[0]),
+                    dst_reg: key_reg,
+                    amount: 0,
+                });
+                program.emit_insn(Insn::MustBeInt {
+                    reg: key_reg,
+                    target_pc: Some(not_found),
+                });
+                let cursor_id =
+                    program.alloc_cursor_id(None, CursorType::BTreeTable(self.parent.clone()));
+                program.emit_insn(Insn::OpenRead {
+                    cursor_id,
+                    root_page: self.parent.root_page,
+                    db: self.database,
+                });
+                program.emit_insn(Insn::NotExists {
+                    cursor: cursor_id,
+                    rowid_reg: key_reg,
+                    target_pc: not_found,
+                });
+            }
+            Some(index) => {
+                let num_regs = index.columns.len();
+                let key_reg = program.alloc_registers(num_regs);
+                let mut affinities = String::with_capacity(num_regs);
+                for (i, index_column) in index.columns.iter().enumerate() {
+                    let key_position = self
+                        .parent_columns
+                        .iter()
+                        .position(|&position| position == index_column.pos_in_table)
+                        .unwrap();
+                    program.emit_insn(Insn::Copy {
+                        src_reg: column_reg(&self.child, row, self.child_columns[key_position]),
+                        dst_reg: key_reg + i,
+                        amount: 0,
+                    });
+                    affinities.push(
+                        self.parent.columns[index_column.pos_in_table]
+                            .affinity()
+                            .aff_mask(),
+                    );
+                }
+                program.emit_insn(Insn::Affinity {
+                    start_reg: key_reg,
+                    count: NonZeroUsize::new(num_regs).unwrap(),
+                    affinities,
+                });
+                let cursor_id =
+                    program.alloc_cursor_id(None, CursorType::BTreeIndex(index.clone()));
+                program.emit_insn(Insn::OpenRead {
+                    cursor_id,
+                    root_page: index.root_page,
+                    db: self.database,
+                });
+                program.emit_insn(Insn::NotFound {
+                    cursor_id,
+                    target_pc: not_found,
+                    record_reg: key_reg,
+                    num_regs,
+                });
+            }
+        }
+        program.emit_insn(Insn::Goto { target_pc: found });
+        program.resolve_label(not_found, program.offset());
+    }
+
+    /// Adds `increment` to the count of violations if the child row at `row` has no parent row.
+    fn emit_child_check(&self, program: &mut ProgramBuilder, row: usize, increment: i64) {
+        let done_label = program.allocate_label();
+        if increment < 0 {
+            // The row cannot fix a violation if there is none.
+            program.emit_insn(Insn::FkIfZero {
+                deferred: self.deferred,
+                target_pc: done_label,
+            });
+        }
+        if self.is_self_reference() && increment > 0 {
+            // A new row may be its own parent row.
+            let not_self_label = program.allocate_label();
+            for (&child_column, &parent_column) in
+                self.child_columns.iter().zip(&self.parent_columns)
+            {
+                program.emit_insn(Insn::Ne {
+                    lhs: column_reg(&self.child, row, child_column),
+                    rhs: column_reg(&self.parent, row, parent_column),
+                    target_pc: not_self_label,
+                    flags: CmpInsFlags::default().jump_if_null(),
+                });
+            }
+            program.emit_insn(Insn::Goto {
+                target_pc: done_label,
+            });
+            program.resolve_label(not_self_label, program.offset());
+        }
+        self.emit_parent_lookup(program, row, done_label);
+        program.emit_insn(Insn::FkCounter {
+            deferred: self.deferred,
+            increment,
+        });
+        program.resolve_label(done_label, program.offset());
+    }
+
+    /// Adds `increment` to the count of violations for each child row of the parent row at `row`.
+    fn emit_parent_check(&self, program: &mut ProgramBuilder, row: usize, increment: i64) {
+        let done_label = program.allocate_label();
+        if increment < 0 {
+            program.emit_insn(Insn::FkIfZero {
+                deferred: self.deferred,
+                target_pc: done_label,
+            });
+        }
+        for &column in &self.parent_columns {
+            program.emit_insn(Insn::IsNull {
+                reg: column_reg(&self.parent, row, column),
+                target_pc: done_label,
+            });
+        }
+        let cursor_id = program.alloc_cursor_id(None, CursorType::BTreeTable(self.child.clone()));
+        program.emit_insn(Insn::OpenRead {
+            cursor_id,
+            root_page: self.child.root_page,
+            db: self.database,
+        });
+        program.emit_insn(Insn::Rewind {
+            cursor_id,
+            pc_if_empty: done_label,
+        });
+        let loop_start = program.offset();
+        let next_label = program.allocate_label();
+        let value_reg = program.alloc_register();
+        for (&child_column, &parent_column) in self.child_columns.iter().zip(&self.parent_columns) {
+            if self.child.columns[child_column].is_rowid_alias {
+                program.emit_insn(Insn::RowId {
+                    cursor_id,
+                    dest: value_reg,
+                });
+            } else {
+                program.emit_insn(Insn::Column {
+                    cursor_id,
+                    column: child_column,
+                    dest: value_reg,
+                });
+            }
+            program.emit_insn(Insn::Ne {
+                lhs: value_reg,
+                rhs: column_reg(&self.parent, row, parent_column),
+                target_pc: next_label,
+                flags: CmpInsFlags::default().jump_if_null(),
+            });
+        }
+        if self.is_self_reference() && increment > 0 {
+            // A deleted row that is its own parent row does not leave itself without one.
+            program.emit_insn(Insn::RowId {
+                cursor_id,
+                dest: value_reg,
+            });
+            program.emit_insn(Insn::Eq {
+                lhs: value_reg,
+                rhs: row,
+                target_pc: next_label,
+                flags: CmpInsFlags::default(),
+            });
+        }
+        program.emit_insn(Insn::FkCounter {
+            deferred: self.deferred,
+            increment,
+        });
+        program.resolve_label(next_label, program.offset());
+        program.emit_insn(Insn::Next {
+            cursor_id,
+            pc_if_next: loop_start,
+        });
+        program.resolve_label(done_label, program.offset());
+    }
+}
+
+/// The foreign key constraints that a statement checks for each row that it changes in a table.
+#[derive(Debug, Clone, Default)]
+pub struct ForeignKeyChecks {
+    /// The constraints of the table, whose child key the statement changes.
+    child_keys: Vec<ResolvedForeignKey>,
+    /// The constraints that refer to the table, whose parent key the statement changes.
+    parent_keys: Vec<ResolvedForeignKey>,
+}
+
+impl ForeignKeyChecks {
+    /// Resolves the constraints that a statement checks when it changes rows of `table`.
+    /// `updated_columns` are the indexes of the columns assigned by an UPDATE, or None for an
+    /// INSERT or a DELETE, which change all of them.
+    pub fn compile(
+        schema: &Schema,
+        table: &Rc<BTreeTable>,
+        updated_columns: Option<&[usize]>,
+        syms: &SymbolTable,
+    ) -> Result<Self> {
+        if !syms.foreign_keys.get() {
+            return Ok(Self::default());
+        }
+        let changes = |columns: &[usize]| {
+            updated_columns.map_or(true, |updated| {
+                columns.iter().any(|column| updated.contains(column))
+            })
+        };
+        let mut child_keys = Vec::new();
+        for fk_index in 0..table.foreign_keys.len() {
+            let fk = ResolvedForeignKey::new(schema, table, fk_index)?;
+            if changes(&fk.child_columns) {
+                child_keys.push(fk);
+            }
+        }
+        let mut parent_keys = Vec::new();
+        for (child, fk_index) in schema.get_referencing_foreign_keys(&table.name) {
+            let fk = ResolvedForeignKey::new(schema, &child, fk_index)?;
+            if changes(&fk.parent_columns) {
+                parent_keys.push(fk);
+            }
+        }
+        Ok(Self {
+            child_keys,
+            parent_keys,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.child_keys.is_empty() && self.parent_keys.is_empty()
+    }
+
+    /// Counts the violations that the statement makes and fixes by replacing the row at `old`
+    /// with the row at `new`, either of which is None for an INSERT or a DELETE.
+    pub fn emit(&self, program: &mut ProgramBuilder, old: Option<usize>, new: Option<usize>) {
+        for fk in &self.child_keys {
+            if let Some(old) = old {
+                fk.emit_child_check(program, old, -1);
+            }
+            if let Some(new) = new {
+                fk.emit_child_check(program, new, 1);
+            }
+        }
+        for fk in &self.parent_keys {
+            if let Some(old) = old {
+                fk.emit_parent_check(program, old, 1);
+            }
+            if let Some(new) = new {
+                fk.emit_parent_check(program, new, -1);
+            }
+        }
+    }
+}
+
+/// The register of the column at `position` of `table` in the row at `row`.
+fn column_reg(table: &BTreeTable, row: usize, position: usize) -> usize {
+    if table.columns[position].is_rowid_alias {
+        row
+    } else {
+        row + 1 + position
+    }
+}
@@ -3,6 +3,7 @@ use std::rc::Rc;
 use limbo_sqlite3_parser::ast;
 
 use crate::{
+    collation::CollationSeq,
     function::AggFunc,
     schema::{Column, PseudoTable},
     types::{OwnedValue, Record},
@@ -12,10 +13,12 @@ use crate::{
         insn::Insn,
         BranchOffset,
     },
-    Result,
+    LimboError, Result, SymbolTable,
 };
 
 use super::{
+    collate::expr_collation,
+    distinct::emit_distinct_check,
     emitter::{Resolver, TranslateCtx},
     expr::{translate_condition_expr, translate_expr, ConditionMetadata},
     order_by::order_by_sorter_insert,
@@ -83,6 +86,7 @@ pub fn init_group_by(
         cursor_id: sort_cursor,
         columns: non_aggregate_count + plan.aggregates.len(),
         order: Record::new(order),
+        collations: group_by_collations(group_by, plan, t_ctx.resolver.symbol_table)?,
     });
 
     program.add_comment(program.offset(), "clear group by abort flag");
@@ -170,7 +174,7 @@ pub fn emit_group_by<'a>(
     let agg_args_count = plan
         .aggregates
         .iter()
-        .map(|agg| agg.args.len())
+        .map(|agg| agg.num_sorter_columns())
         .sum::<usize>();
     let group_by_count = group_by.exprs.len();
     let non_group_by_non_agg_column_count = non_group_by_non_agg_column_count.unwrap();
@@ -218,6 +222,7 @@ pub fn emit_group_by<'a>(
             is_rowid_alias: false,
             notnull: false,
             default: None,
+            collation: None,
         })
         .collect::<Vec<_>>();
 
@@ -266,6 +271,7 @@ pub fn emit_group_by<'a>(
         start_reg_a: reg_group_exprs_cmp,
         start_reg_b: groups_start_reg,
         count: group_by.exprs.len(),
+        collations: group_by_collations(group_by, plan, t_ctx.resolver.symbol_table)?,
     });
 
     let agg_step_label = program.allocate_label();
@@ -316,6 +322,31 @@ pub fn emit_group_by<'a>(
     let mut cursor_index = group_by_count + non_group_by_non_agg_column_count; // Skipping all columns in sorter that not an aggregation arguments
     for (i, agg) in plan.aggregates.iter().enumerate() {
         let agg_result_reg = start_reg + i;
+        // The value of the FILTER condition follows the arguments in the sorter; skip the row
+        // unless it is true.
+        let label_skip = program.allocate_label();
+        if agg.filter.is_some() {
+            let reg_filter = program.alloc_register();
+            program.emit_insn(Insn::Column {
+                cursor_id: pseudo_cursor,
+                column: cursor_index + agg.args.len(),
+                dest: reg_filter,
+            });
+            program.emit_insn(Insn::IfNot {
+                reg: reg_filter,
+                target_pc: label_skip,
+                jump_if_null: true,
+            });
+        }
+        if let Some(cursor_id) = t_ctx.meta_distinct.agg_cursor_ids[i] {
+            let reg_arg = program.alloc_register();
+            program.emit_insn(Insn::Column {
+                cursor_id: pseudo_cursor,
+                column: cursor_index,
+                dest: reg_arg,
+            });
+            emit_distinct_check(program, cursor_id, &[reg_arg], label_skip);
+        }
         translate_aggregation_step_groupby(
             program,
             &plan.table_references,
@@ -325,7 +356,8 @@ pub fn emit_group_by<'a>(
             agg_result_reg,
             &t_ctx.resolver,
         )?;
-        cursor_index += agg.args.len();
+        program.resolve_label(label_skip, program.offset());
+        cursor_index += agg.num_sorter_columns();
     }
 
     // We only need to store non-aggregate columns once per group
@@ -502,6 +534,13 @@ pub fn emit_group_by<'a>(
         value: 0,
         dest: reg_data_in_acc_flag,
     });
+    // Opening the ephemeral indexes of the DISTINCT aggregates again empties them for the new group.
+    for cursor_id in t_ctx.meta_distinct.agg_cursor_ids.iter().flatten() {
+        program.emit_insn(Insn::OpenEphemeral {
+            cursor_id: *cursor_id,
+            is_table: false,
+        });
+    }
     program.emit_insn(Insn::Return {
         return_reg: reg_subrtn_acc_clear_return_offset,
     });
@@ -611,6 +650,9 @@ pub fn translate_aggregation_step_groupby(
             }
             let expr_reg = program.alloc_register();
             emit_column(program, expr_reg);
+            let collation =
+                expr_collation(&agg.args[0], Some(referenced_tables), resolver.symbol_table)?;
+            program.emit_insn(Insn::CollSeq { collation });
             program.emit_insn(Insn::AggStep {
                 acc_reg: target_register,
                 col: expr_reg,
@@ -625,6 +667,9 @@ pub fn translate_aggregation_step_groupby(
             }
             let expr_reg = program.alloc_register();
             emit_column(program, expr_reg);
+            let collation =
+                expr_collation(&agg.args[0], Some(referenced_tables), resolver.symbol_table)?;
+            program.emit_insn(Insn::CollSeq { collation });
             program.emit_insn(Insn::AggStep {
                 acc_reg: target_register,
                 col: expr_reg,
@@ -737,13 +782,51 @@ pub fn translate_aggregation_step_groupby(
             });
             target_register
         }
-        AggFunc::External(_) => {
-            todo!("External aggregate functions are not yet supported in GROUP BY");
+        AggFunc::External(ref func) => {
+            let argc = func.agg_args().map_err(|_| {
+                LimboError::ExtensionError(
+                    "External aggregate function called with wrong number of arguments".to_string(),
+                )
+            })?;
+            if argc != agg.args.len() {
+                crate::bail_parse_error!(
+                    "External aggregate function called with wrong number of arguments"
+                );
+            }
+            // The arguments are in consecutive columns of the sorter.
+            let expr_reg = program.alloc_registers(argc);
+            for i in 0..argc {
+                program.emit_insn(Insn::Column {
+                    cursor_id: group_by_sorter_cursor_id,
+                    column: cursor_index + i,
+                    dest: expr_reg + i,
+                });
+            }
+            program.emit_insn(Insn::AggStep {
+                acc_reg: target_register,
+                col: expr_reg,
+                delimiter: 0,
+                func: AggFunc::External(func.clone()),
+            });
+            target_register
         }
     };
     Ok(dest)
 }
 
+/// The collating sequences used to sort and compare the GROUP BY expressions.
+fn group_by_collations(
+    group_by: &GroupBy,
+    plan: &SelectPlan,
+    syms: &SymbolTable,
+) -> Result<Vec<CollationSeq>> {
+    group_by
+        .exprs
+        .iter()
+        .map(|expr| expr_collation(expr, Some(&plan.table_references), syms))
+        .collect()
+}
+
 pub fn is_column_in_group_by(expr: &ast::Expr, group_by_exprs: &[ast::Expr]) -> bool {
     group_by_exprs
         .iter()
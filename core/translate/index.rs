@@ -1,6 +1,8 @@
+use std::rc::Rc;
 use std::sync::Arc;
 
 use crate::{
+    function::Func,
     schema::{BTreeTable, Column, Index, IndexColumn, PseudoTable, Schema},
     storage::pager::CreateBTreeFlags,
     types::Record,
@@ -9,19 +11,28 @@ use crate::{
         builder::{CursorType, ProgramBuilder, QueryMode},
         insn::{IdxInsertFlags, Insn, RegisterOrLiteral},
     },
-    OwnedValue,
+    OwnedValue, SymbolTable,
 };
 use limbo_sqlite3_parser::ast::{self, Expr, Id, SortOrder, SortedColumn};
 
+use super::collate::resolve_collation;
+use super::constraint::{
+    emit_index_key, emit_load_row, emit_partial_index_check, rewrite_row_expr,
+};
+use super::emitter::Resolver;
 use super::schema::{emit_schema_entry, SchemaEntryType, SQLITE_TABLEID};
+use super::window::child_exprs;
 
+#[allow(clippy::too_many_arguments)]
 pub fn translate_create_index(
     mode: QueryMode,
     unique_if_not_exists: (bool, bool),
     idx_name: &str,
     tbl_name: &str,
     columns: &[SortedColumn],
+    where_clause: Option<&Expr>,
     schema: &Schema,
+    syms: &SymbolTable,
 ) -> crate::Result<ProgramBuilder> {
     let idx_name = normalize_ident(idx_name);
     let tbl_name = normalize_ident(tbl_name);
@@ -44,55 +55,64 @@ pub fn translate_create_index(
         crate::bail_parse_error!("Error: table '{tbl_name}' is not a b-tree table.");
     };
     let columns = resolve_sorted_columns(&tbl, columns)?;
+    if let Some(where_clause) = where_clause {
+        validate_index_expr(where_clause, &tbl, "partial index WHERE clauses")?;
+    }
+    // Index columns use the collating sequence of the table column unless one is given explicitly.
+    let collation_names = columns
+        .iter()
+        .map(|(column, _, _, collation)| {
+            collation
+                .clone()
+                .or_else(|| column.and_then(|(_, col)| col.collation.clone()))
+        })
+        .collect::<Vec<_>>();
 
     // Prologue:
     let init_label = program.emit_init();
     let start_offset = program.offset();
 
+    let index_columns = columns
+        .iter()
+        .zip(collation_names)
+        .map(|((column, expr, order, _), collation)| match column {
+            Some((pos_in_table, col)) => IndexColumn {
+                name: col.name.as_ref().unwrap().clone(),
+                order: *order,
+                pos_in_table: *pos_in_table,
+                collation,
+                expr: None,
+            },
+            None => IndexColumn {
+                name: expr.to_string(),
+                order: *order,
+                pos_in_table: usize::MAX,
+                collation,
+                expr: Some(Box::new((*expr).clone())),
+            },
+        })
+        .collect::<Vec<_>>();
     let idx = Arc::new(Index {
         name: idx_name.clone(),
         table_name: tbl.name.clone(),
         root_page: 0, //  we dont have access till its created, after we parse the schema table
-        columns: columns
-            .iter()
-            .map(|((pos_in_table, col), order)| IndexColumn {
-                name: col.name.as_ref().unwrap().clone(),
-                order: *order,
-                pos_in_table: *pos_in_table,
-            })
-            .collect(),
+        key_suffix: Index::key_suffix_of(&tbl, &index_columns),
+        columns: index_columns,
         unique: unique_if_not_exists.0,
         ephemeral: false,
+        where_clause: where_clause.map(|expr| Box::new(expr.clone())),
     });
 
-    // Allocate the necessary cursors:
-    //
-    // 1. sqlite_schema_cursor_id - sqlite_schema table
-    // 2. btree_cursor_id         - new index btree
-    // 3. table_cursor_id         - table we are creating the index on
-    // 4. sorter_cursor_id        - sorter
-    // 5. pseudo_cursor_id        - pseudo table to store the sorted index values
     let sqlite_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
     let sqlite_schema_cursor_id = program.alloc_cursor_id(
         Some(SQLITE_TABLEID.to_owned()),
         CursorType::BTreeTable(sqlite_table.clone()),
     );
-    let btree_cursor_id = program.alloc_cursor_id(
-        Some(idx_name.to_owned()),
-        CursorType::BTreeIndex(idx.clone()),
-    );
-    let table_cursor_id = program.alloc_cursor_id(
-        Some(tbl_name.to_owned()),
-        CursorType::BTreeTable(tbl.clone()),
-    );
-    let sorter_cursor_id = program.alloc_cursor_id(None, CursorType::Sorter);
-    let pseudo_table = PseudoTable::new_with_columns(tbl.columns.clone());
-    let pseudo_cursor_id = program.alloc_cursor_id(None, CursorType::Pseudo(pseudo_table.into()));
 
     // Create a new B-Tree and store the root page index in a register
     let root_page_reg = program.alloc_register();
     program.emit_insn(Insn::CreateBtree {
-        db: 0,
+        db: schema.database,
         root: root_page_reg,
         flags: CreateBTreeFlags::new_index(),
     });
@@ -101,8 +121,15 @@ pub fn translate_create_index(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
+        db: schema.database,
     });
-    let sql = create_idx_stmt_to_sql(&tbl_name, &idx_name, unique_if_not_exists, &columns);
+    let sql = create_idx_stmt_to_sql(
+        &tbl_name,
+        &idx_name,
+        unique_if_not_exists,
+        &columns,
+        where_clause,
+    );
     emit_schema_entry(
         &mut program,
         sqlite_schema_cursor_id,
@@ -113,10 +140,150 @@ pub fn translate_create_index(
         Some(sql),
     );
 
-    // determine the order of the columns in the index for the sorter
-    let order = idx
-        .columns
-        .iter()
+    emit_index_entries(
+        &mut program,
+        schema.database,
+        &tbl,
+        &idx,
+        RegisterOrLiteral::Register(root_page_reg),
+        syms,
+    )?;
+
+    // TODO: SetCookie for schema change
+    //
+    // Parse the schema table to get the index root page and add new index to Schema
+    let parse_schema_where_clause = format!("name = '{}' AND type = 'index'", idx_name);
+    program.emit_insn(Insn::ParseSchema {
+        db: schema.database,
+        where_clause: parse_schema_where_clause,
+    });
+    // Close the final sqlite_schema cursor
+    program.emit_insn(Insn::Close {
+        cursor_id: sqlite_schema_cursor_id,
+    });
+
+    // Epilogue:
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_transaction(true);
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+
+    Ok(program)
+}
+
+/// Rebuilds indexes from the rows of their tables. `obj_name` names a collating sequence, a table
+/// or an index, in that order of precedence, and all indexes are rebuilt if it is omitted.
+pub fn translate_reindex(
+    mode: QueryMode,
+    obj_name: Option<ast::QualifiedName>,
+    schema: &Schema,
+    syms: &SymbolTable,
+) -> crate::Result<ProgramBuilder> {
+    let mut program = ProgramBuilder::new(crate::vdbe::builder::ProgramBuilderOpts {
+        query_mode: mode,
+        num_cursors: 4,
+        approx_num_insns: 40,
+        approx_num_labels: 5,
+    });
+    let all_indexes = schema.indexes.values().flatten();
+    let mut indexes = match obj_name {
+        None => all_indexes.cloned().collect::<Vec<_>>(),
+        Some(ast::QualifiedName {
+            db_name: None,
+            name,
+            ..
+        }) if resolve_collation(Some(&name.0), syms).is_ok() => {
+            let collation = normalize_ident(&name.0);
+            all_indexes
+                .filter(|index| {
+                    index.columns.iter().any(|column| {
+                        normalize_ident(column.collation.as_deref().unwrap_or("binary"))
+                            == collation
+                    })
+                })
+                .cloned()
+                .collect()
+        }
+        Some(ast::QualifiedName { name, .. }) => {
+            if schema.get_btree_table(&name.0).is_some() {
+                schema.get_indices(&name.0).to_vec()
+            } else if let Some(index) = schema.get_index(&name.0) {
+                vec![index]
+            } else {
+                crate::bail_parse_error!("unable to identify the object to be reindexed");
+            }
+        }
+    };
+    indexes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    for index in indexes {
+        let table = schema.get_btree_table(&index.table_name).unwrap();
+        // The PRIMARY KEY index of a WITHOUT ROWID table is the table itself
+        if table.is_primary_key_index(&index) {
+            continue;
+        }
+        program.emit_insn(Insn::Clear {
+            root: index.root_page,
+            db: schema.database,
+        });
+        emit_index_entries(
+            &mut program,
+            schema.database,
+            &table,
+            &index,
+            RegisterOrLiteral::Literal(index.root_page),
+            syms,
+        )?;
+    }
+
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_transaction(true);
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+
+    Ok(program)
+}
+
+/// Fills the empty b-tree at `root_page` with the entries of `index` for every row of `table`,
+/// which are in the database `db`. The entries are sorted first, so that they can be appended to
+/// the b-tree.
+fn emit_index_entries(
+    program: &mut ProgramBuilder,
+    db: usize,
+    table: &Rc<BTreeTable>,
+    index: &Arc<Index>,
+    root_page: RegisterOrLiteral<usize>,
+    syms: &SymbolTable,
+) -> crate::Result<()> {
+    // Allocate the necessary cursors:
+    //
+    // 1. btree_cursor_id         - index btree
+    // 2. table_cursor_id         - table the index is on
+    // 3. sorter_cursor_id        - sorter
+    // 4. pseudo_cursor_id        - pseudo table to store the sorted index values
+    let btree_cursor_id = program.alloc_cursor_id(
+        Some(index.name.clone()),
+        CursorType::BTreeIndex(index.clone()),
+    );
+    let table_cursor_id = program.alloc_cursor_id(
+        Some(table.name.clone()),
+        CursorType::BTreeTable(table.clone()),
+    );
+    let sorter_cursor_id = program.alloc_cursor_id(None, CursorType::Sorter);
+    let pseudo_table = PseudoTable::new_with_columns(table.columns.clone());
+    let pseudo_cursor_id = program.alloc_cursor_id(None, CursorType::Pseudo(pseudo_table.into()));
+
+    // The entries are sorted on their keys, see [Index::key_columns]
+    let collations = index
+        .key_columns()
+        .map(|column| resolve_collation(column.collation.as_deref(), syms))
+        .collect::<crate::Result<Vec<_>>>()?;
+    let order = index
+        .key_columns()
         .map(|c| {
             OwnedValue::Integer(match c.order {
                 SortOrder::Asc => 0,
@@ -127,20 +294,29 @@ pub fn translate_create_index(
     // open the sorter and the pseudo table
     program.emit_insn(Insn::SorterOpen {
         cursor_id: sorter_cursor_id,
-        columns: columns.len(),
+        columns: collations.len(),
         order: Record::new(order),
+        collations,
     });
+    // The entries end with the rowid, or with the rest of the PRIMARY KEY of a WITHOUT ROWID table
+    let primary_key_suffix = index.primary_key_suffix(table);
+    let entry_len = if table.has_rowid {
+        index.columns.len() + 1
+    } else {
+        index.columns.len() + primary_key_suffix.len()
+    };
     let content_reg = program.alloc_register();
     program.emit_insn(Insn::OpenPseudo {
         cursor_id: pseudo_cursor_id,
         content_reg,
-        num_fields: columns.len() + 1,
+        num_fields: entry_len,
     });
 
     // open the table we are creating the index on for reading
     program.emit_insn(Insn::OpenRead {
         cursor_id: table_cursor_id,
-        root_page: tbl.root_page,
+        root_page: table.root_page,
+        db,
     });
 
     let loop_start_label = program.allocate_label();
@@ -157,23 +333,43 @@ pub fn translate_create_index(
     // emit MakeRecord (index key + rowid) into record_reg.
     //
     // Then insert the record into the sorter
-    let start_reg = program.alloc_registers(columns.len() + 1);
-    for (i, (col, _)) in columns.iter().enumerate() {
-        program.emit_insn(Insn::Column {
-            cursor_id: table_cursor_id,
-            column: col.0,
-            dest: start_reg + i,
-        });
-    }
-    let rowid_reg = start_reg + columns.len();
-    program.emit_insn(Insn::RowId {
-        cursor_id: table_cursor_id,
-        dest: rowid_reg,
-    });
+    let next_label = program.allocate_label();
+    let start_reg = if index.has_expressions() || index.where_clause.is_some() {
+        // Index expressions and the WHERE clause of a partial index are evaluated on the row
+        let resolver = Resolver::new(syms);
+        let row = emit_load_row(program, table, table_cursor_id);
+        emit_partial_index_check(program, table, index, row, next_label, &resolver)?;
+        emit_index_key(program, table, index, row, &resolver)?
+    } else {
+        let start_reg = program.alloc_registers(entry_len);
+        for (i, column) in index.columns.iter().enumerate() {
+            program.emit_insn(Insn::Column {
+                cursor_id: table_cursor_id,
+                column: column.pos_in_table,
+                dest: start_reg + i,
+            });
+        }
+        let rowid_reg = start_reg + index.columns.len();
+        if table.has_rowid {
+            program.emit_insn(Insn::RowId {
+                cursor_id: table_cursor_id,
+                dest: rowid_reg,
+            });
+        } else {
+            for (i, position) in primary_key_suffix.iter().enumerate() {
+                program.emit_insn(Insn::Column {
+                    cursor_id: table_cursor_id,
+                    column: *position,
+                    dest: rowid_reg + i,
+                });
+            }
+        }
+        start_reg
+    };
     let record_reg = program.alloc_register();
     program.emit_insn(Insn::MakeRecord {
         start_reg,
-        count: columns.len() + 1,
+        count: entry_len,
         dest_reg: record_reg,
     });
     program.emit_insn(Insn::SorterInsert {
@@ -181,17 +377,18 @@ pub fn translate_create_index(
         record_reg,
     });
 
+    program.resolve_label(next_label, program.offset());
     program.emit_insn(Insn::Next {
         cursor_id: table_cursor_id,
         pc_if_next: loop_start_label,
     });
     program.resolve_label(loop_end_label, program.offset());
 
-    // Open the index btree we created for writing to insert the
-    // newly sorted index records.
+    // Open the index btree for writing to insert the newly sorted index records.
     program.emit_insn(Insn::OpenWrite {
         cursor_id: btree_cursor_id,
-        root_page: RegisterOrLiteral::Register(root_page_reg),
+        root_page,
+        db,
     });
 
     let sorted_loop_start = program.allocate_label();
@@ -228,60 +425,81 @@ pub fn translate_create_index(
     });
     program.resolve_label(sorted_loop_end, program.offset());
 
-    // End of the outer loop
-    //
-    // Keep schema table open to emit ParseSchema, close the other cursors.
     program.close_cursors(&[sorter_cursor_id, table_cursor_id, btree_cursor_id]);
-
-    // TODO: SetCookie for schema change
-    //
-    // Parse the schema table to get the index root page and add new index to Schema
-    let parse_schema_where_clause = format!("name = '{}' AND type = 'index'", idx_name);
-    program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
-        where_clause: parse_schema_where_clause,
-    });
-    // Close the final sqlite_schema cursor
-    program.emit_insn(Insn::Close {
-        cursor_id: sqlite_schema_cursor_id,
-    });
-
-    // Epilogue:
-    program.emit_halt();
-    program.resolve_label(init_label, program.offset());
-    program.emit_transaction(true);
-    program.emit_constant_insns();
-    program.emit_goto(start_offset);
-
-    Ok(program)
+    Ok(())
 }
 
+/// A column of a CREATE INDEX statement: the position in the table and the column itself if it
+/// indexes a column, the indexed expression, its sort order and its explicit collating sequence.
+type ResolvedColumn<'a> = (
+    Option<(usize, &'a Column)>,
+    &'a Expr,
+    SortOrder,
+    Option<String>,
+);
+
 fn resolve_sorted_columns<'a>(
     table: &'a BTreeTable,
-    cols: &[SortedColumn],
-) -> crate::Result<Vec<((usize, &'a Column), SortOrder)>> {
+    cols: &'a [SortedColumn],
+) -> crate::Result<Vec<ResolvedColumn<'a>>> {
     let mut resolved = Vec::with_capacity(cols.len());
     for sc in cols {
-        let ident = normalize_ident(match &sc.expr {
-            Expr::Id(Id(col_name)) | Expr::Name(ast::Name(col_name)) => col_name,
-            _ => crate::bail_parse_error!("Error: cannot use expressions in CREATE INDEX"),
-        });
+        let (expr, collation) = match &sc.expr {
+            Expr::Collate(expr, collation) => (expr.as_ref(), Some(normalize_ident(collation))),
+            expr => (expr, None),
+        };
+        let order = sc.order.unwrap_or(SortOrder::Asc);
+        let ident = match expr {
+            Expr::Id(Id(col_name)) | Expr::Name(ast::Name(col_name)) => normalize_ident(col_name),
+            _ => {
+                validate_index_expr(expr, table, "index expressions")?;
+                resolved.push((None, expr, order, collation));
+                continue;
+            }
+        };
         let Some(col) = table.get_column(&ident) else {
             crate::bail_parse_error!(
                 "Error: column '{ident}' does not exist in table '{}'",
                 table.name
             );
         };
-        resolved.push((col, sc.order.unwrap_or(SortOrder::Asc)));
+        resolved.push((Some(col), expr, order, collation));
     }
     Ok(resolved)
 }
 
+/// Checks that an expression of a new index only refers to the columns of its table and only
+/// calls deterministic functions. `context` names the kind of expression in the errors.
+fn validate_index_expr(expr: &Expr, table: &BTreeTable, context: &str) -> crate::Result<()> {
+    rewrite_row_expr(&mut expr.clone(), table, 0, context)?;
+    check_deterministic(expr, context)
+}
+
+fn check_deterministic(expr: &Expr, context: &str) -> crate::Result<()> {
+    let call = match expr {
+        Expr::FunctionCall { name, args, .. } => Some((name, args.as_ref().map_or(0, Vec::len))),
+        Expr::FunctionCallStar { name, .. } => Some((name, 0)),
+        _ => None,
+    };
+    if let Some((name, arg_count)) = call {
+        if let Ok(func) = Func::resolve_function(&normalize_ident(&name.0), arg_count) {
+            if !func.is_deterministic() {
+                crate::bail_parse_error!("non-deterministic functions prohibited in {}", context);
+            }
+        }
+    }
+    for child in child_exprs(expr) {
+        check_deterministic(child, context)?;
+    }
+    Ok(())
+}
+
 fn create_idx_stmt_to_sql(
     tbl_name: &str,
     idx_name: &str,
     unique_if_not_exists: (bool, bool),
-    cols: &[((usize, &Column), SortOrder)],
+    cols: &[ResolvedColumn],
+    where_clause: Option<&Expr>,
 ) -> String {
     let mut sql = String::with_capacity(128);
     sql.push_str("CREATE ");
@@ -296,15 +514,26 @@ fn create_idx_stmt_to_sql(
     sql.push_str(" ON ");
     sql.push_str(tbl_name);
     sql.push_str(" (");
-    for (i, (col, order)) in cols.iter().enumerate() {
+    for (i, (col, expr, order, collation)) in cols.iter().enumerate() {
         if i > 0 {
             sql.push_str(", ");
         }
-        sql.push_str(col.1.name.as_ref().unwrap());
+        match col {
+            Some((_, col)) => sql.push_str(col.name.as_ref().unwrap()),
+            None => sql.push_str(&expr.to_string()),
+        }
+        if let Some(collation) = collation {
+            sql.push_str(" COLLATE ");
+            sql.push_str(collation);
+        }
         if *order == SortOrder::Desc {
             sql.push_str(" DESC");
         }
     }
     sql.push(')');
+    if let Some(where_clause) = where_clause {
+        sql.push_str(" WHERE ");
+        sql.push_str(&where_clause.to_string());
+    }
     sql
 }
@@ -1,12 +1,14 @@
 use std::ops::Deref;
 use std::rc::Rc;
 
+use std::sync::Arc;
+
 use limbo_sqlite3_parser::ast::{
-    DistinctNames, Expr, InsertBody, OneSelect, QualifiedName, ResolveType, ResultColumn, With,
+    self, DistinctNames, Expr, InsertBody, OneSelect, QualifiedName, ResolveType, ResultColumn,
+    Set, SortOrder, TriggerEvent, TriggerTime, Upsert, UpsertDo, With,
 };
 
-use crate::error::SQLITE_CONSTRAINT_PRIMARYKEY;
-use crate::schema::{IndexColumn, Table};
+use crate::schema::{BTreeTable, Index, IndexColumn, Table};
 use crate::util::normalize_ident;
 use crate::vdbe::builder::{ProgramBuilderOpts, QueryMode};
 use crate::vdbe::insn::{IdxInsertFlags, RegisterOrLiteral};
@@ -22,7 +24,21 @@ use crate::{
 };
 use crate::{Result, VirtualTable};
 
-use super::emitter::Resolver;
+use super::constraint::{
+    emit_constraint_checks, emit_delete_index_entries, emit_insert_index_entries, emit_insert_row,
+    OnConflict, ReplaceDeletes, RowSource, WriteCursors,
+};
+use super::emitter::{emit_coroutine, Resolver};
+use super::foreign_key::ForeignKeyChecks;
+use super::optimizer::optimize_plan;
+use super::plan::{
+    ColumnUsedMask, IterationDirection, Operation, Plan, SelectPlan, SelectQueryType,
+    TableReference,
+};
+use super::returning::{prepare_returning, Returning};
+use super::select::prepare_select_plan;
+use super::trigger::{translate_view_insert, RowTriggers};
+use super::window::child_exprs_mut;
 
 #[allow(clippy::too_many_arguments)]
 pub fn translate_insert(
@@ -33,7 +49,7 @@ pub fn translate_insert(
     tbl_name: &QualifiedName,
     columns: &Option<DistinctNames>,
     body: &InsertBody,
-    _returning: &Option<Vec<ResultColumn>>,
+    returning: &Option<Vec<ResultColumn>>,
     syms: &SymbolTable,
 ) -> Result<ProgramBuilder> {
     let mut program = ProgramBuilder::new(ProgramBuilderOpts {
@@ -45,15 +61,16 @@ pub fn translate_insert(
     if with.is_some() {
         crate::bail_parse_error!("WITH clause is not supported");
     }
-    if on_conflict.is_some() {
-        crate::bail_parse_error!("ON CONFLICT clause is not supported");
-    }
 
     let table_name = &tbl_name.name;
-    let table = match schema.get_table(table_name.0.as_str()) {
+    let db_name = tbl_name.db_name.as_ref().map(|name| name.0.as_str());
+    let (database, table) = match schema.find_table(db_name, table_name.0.as_str())? {
         Some(table) => table,
         None => crate::bail_corrupt_error!("Parse error: no such table: {}", table_name),
     };
+    if let Table::View(view) = table.as_ref() {
+        return translate_view_insert(query_mode, schema, view, columns, body, syms);
+    }
     let resolver = Resolver::new(syms);
     if let Some(virtual_table) = &table.virtual_table() {
         translate_virtual_table_insert(
@@ -75,41 +92,77 @@ pub fn translate_insert(
     let Some(btree_table) = table.btree() else {
         crate::bail_corrupt_error!("Parse error: no such table: {}", table_name);
     };
-    if !btree_table.has_rowid {
-        crate::bail_parse_error!("INSERT into WITHOUT ROWID table is not supported");
-    }
 
     let cursor_id = program.alloc_cursor_id(
         Some(table_name.0.clone()),
         CursorType::BTreeTable(btree_table.clone()),
     );
-    // allocate cursor id's for each btree index cursor we'll need to populate the indexes
-    // (idx name, root_page, idx cursor id)
-    let idx_cursors = schema
-        .get_indices(&table_name.0)
-        .iter()
-        .map(|idx| {
-            (
-                &idx.name,
-                idx.root_page,
-                program.alloc_cursor_id(
-                    Some(table_name.0.clone()),
-                    CursorType::BTreeIndex(idx.clone()),
-                ),
-            )
-        })
-        .collect::<Vec<(&String, usize, usize)>>();
     let root_page = btree_table.root_page;
-    let values = match body {
-        InsertBody::Select(select, _) => match &select.body.select.deref() {
-            OneSelect::Values(values) => values,
-            _ => todo!(),
+    let mut table_references = vec![TableReference {
+        op: Operation::Scan {
+            iter_dir: IterationDirection::Forwards,
+            index: None,
+        },
+        table: Table::BTree(btree_table.clone()),
+        identifier: table_name.0.clone(),
+        join_info: None,
+        col_used_mask: ColumnUsedMask::new(),
+        database,
+        database_name: schema.database_name(database).to_string(),
+    }];
+    let returning_columns = match returning {
+        Some(returning) => prepare_returning(returning, &mut table_references)?,
+        None => vec![],
+    };
+    let returning = returning
+        .is_some()
+        .then(|| Returning::open(&mut program, &returning_columns));
+    // The indexes, triggers and foreign keys of the table are in its own database, while the
+    // SELECT of the rows to insert may read any of them.
+    let target_schema = schema.database_schema(database)?;
+    let triggers = RowTriggers::compile(
+        &mut program,
+        &target_schema,
+        &table_name.0,
+        &btree_table.columns,
+        TriggerEvent::Insert,
+        &[],
+        syms,
+    )?;
+    let foreign_keys = ForeignKeyChecks::compile(&target_schema, &btree_table, None, syms)?;
+    let replace_deletes =
+        ReplaceDeletes::compile(&mut program, &target_schema, &btree_table, syms)?;
+    let mut select_plan = None;
+    let select_values;
+    let (values, upsert) = match body {
+        InsertBody::Select(select, upsert) => match &select.body.select.deref() {
+            OneSelect::Values(values) if select.body.compounds.is_none() => {
+                (values, upsert.as_ref())
+            }
+            _ => {
+                let mut plan = prepare_select_plan(schema, select.as_ref().clone(), syms, None)?;
+                optimize_plan(&mut plan, schema)?;
+                let Plan::Select(mut plan) = plan else {
+                    unreachable!("a SELECT is planned as a SelectPlan");
+                };
+                plan.query_type = SelectQueryType::Subquery {
+                    yield_reg: usize::MAX, // will be set in emit_coroutine
+                    coroutine_implementation_start: BranchOffset::Placeholder,
+                };
+                // The values are the result columns of the SELECT, whose registers are known
+                // once it is emitted.
+                select_values = vec![vec![
+                    Expr::Literal(ast::Literal::Null);
+                    plan.result_columns.len()
+                ]];
+                select_plan = Some(plan);
+                (&select_values, upsert.as_ref())
+            }
         },
-        InsertBody::DefaultValues => &vec![vec![]],
+        InsertBody::DefaultValues => (&vec![vec![]], None),
     };
 
     let column_mappings = resolve_columns_for_insert(&table, columns, values)?;
-    let index_col_mappings = resolve_indicies_for_insert(schema, table.as_ref(), &column_mappings)?;
     // Check if rowid was provided (through INTEGER PRIMARY KEY as a rowid alias)
     let rowid_alias_index = btree_table.columns.iter().position(|c| c.is_rowid_alias);
     let has_user_provided_rowid = {
@@ -134,14 +187,40 @@ pub fn translate_insert(
         }
     };
 
-    let record_register = program.alloc_register();
     let halt_label = program.allocate_label();
     let mut loop_start_offset = BranchOffset::Offset(0);
 
-    let inserting_multiple_rows = values.len() > 1;
+    let inserting_multiple_rows = values.len() > 1 || select_plan.is_some();
+
+    if let Some(plan) = select_plan.as_mut() {
+        let reads_table = plan.reads_btree(database, root_page);
+        let (yield_reg, row_start) = emit_insert_select(&mut program, plan, reads_table, syms)?;
+        program.emit_insn(Insn::OpenWrite {
+            cursor_id,
+            root_page: RegisterOrLiteral::Literal(root_page),
+            db: database,
+        });
 
-    // Multiple rows - use coroutine for value population
-    if inserting_multiple_rows {
+        // Main loop
+        loop_start_offset = program.offset();
+        program.emit_insn(Insn::Yield {
+            yield_reg,
+            end_offset: halt_label,
+        });
+        let row = (0..values[0].len())
+            .map(|i| Expr::Register(row_start + i))
+            .collect::<Vec<_>>();
+        populate_column_registers(
+            &mut program,
+            &row,
+            &column_mappings,
+            column_registers_start,
+            true,
+            rowid_reg,
+            &resolver,
+        )?;
+    } else if inserting_multiple_rows {
+        // Multiple rows - use coroutine for value population
         let yield_reg = program.alloc_register();
         let jump_on_definition_label = program.allocate_label();
         program.emit_insn(Insn::InitCoroutine {
@@ -171,11 +250,10 @@ pub fn translate_insert(
         program.emit_insn(Insn::OpenWrite {
             cursor_id,
             root_page: RegisterOrLiteral::Literal(root_page),
+            db: database,
         });
 
         // Main loop
-        // FIXME: rollback is not implemented. E.g. if you insert 2 rows and one fails to unique constraint violation,
-        // the other row will still be inserted.
         loop_start_offset = program.offset();
         program.emit_insn(Insn::Yield {
             yield_reg,
@@ -186,6 +264,7 @@ pub fn translate_insert(
         program.emit_insn(Insn::OpenWrite {
             cursor_id,
             root_page: RegisterOrLiteral::Literal(root_page),
+            db: database,
         });
 
         populate_column_registers(
@@ -199,12 +278,16 @@ pub fn translate_insert(
         )?;
     }
     // Open all the index btrees for writing
-    for idx_cursor in idx_cursors.iter() {
-        program.emit_insn(Insn::OpenWrite {
-            cursor_id: idx_cursor.2,
-            root_page: idx_cursor.1.into(),
-        });
-    }
+    let cursors = WriteCursors::open(
+        &mut program,
+        &btree_table,
+        cursor_id,
+        database,
+        target_schema
+            .get_indices(&table_name.0)
+            .iter()
+            .map(|index| (index.clone(), true)),
+    );
     // Common record insertion logic for both single and multiple rows
     let check_rowid_is_integer_label = rowid_alias_reg.and(Some(program.allocate_label()));
     if let Some(reg) = rowid_alias_reg {
@@ -223,6 +306,42 @@ pub fn translate_insert(
             // for the row record, the rowid alias column is always set to NULL
             program.emit_insn(Insn::SoftNull { reg });
         }
+    }
+    // Where to continue when a trigger skips the row with RAISE(IGNORE)
+    let row_done_offset = if inserting_multiple_rows {
+        loop_start_offset
+    } else {
+        halt_label
+    };
+    if triggers.has(TriggerTime::Before) {
+        // The rowid is not known yet, so like in SQLite, NEW.rowid is -1 unless it was provided.
+        let new_row = program.alloc_registers(num_cols + 1);
+        program.emit_insn(Insn::Copy {
+            src_reg: rowid_reg,
+            dst_reg: new_row,
+            amount: num_cols,
+        });
+        let rowid_known_label = program.allocate_label();
+        if has_user_provided_rowid {
+            program.emit_insn(Insn::NotNull {
+                reg: new_row,
+                target_pc: rowid_known_label,
+            });
+        }
+        program.emit_insn(Insn::Integer {
+            value: -1,
+            dest: new_row,
+        });
+        program.resolve_label(rowid_known_label, program.offset());
+        triggers.emit(
+            &mut program,
+            TriggerTime::Before,
+            None,
+            Some(new_row),
+            row_done_offset,
+        );
+    }
+    if rowid_alias_reg.is_some() {
         // the user provided rowid value might itself be NULL. If it is, we create a new rowid on the next instruction.
         program.emit_insn(Insn::NotNull {
             reg: rowid_reg,
@@ -230,46 +349,23 @@ pub fn translate_insert(
         });
     }
 
-    // Create new rowid if a) not provided by user or b) provided by user but is NULL
-    program.emit_insn(Insn::NewRowid {
-        cursor: cursor_id,
-        rowid_reg,
-        prev_largest_reg: 0,
-    });
-
-    if let Some(must_be_int_label) = check_rowid_is_integer_label {
-        program.resolve_label(must_be_int_label, program.offset());
-        // If the user provided a rowid, it must be an integer.
-        program.emit_insn(Insn::MustBeInt { reg: rowid_reg });
-    }
-
-    // Check uniqueness constraint for rowid if it was provided by user.
-    // When the DB allocates it there are no need for separate uniqueness checks.
-    if has_user_provided_rowid {
-        let make_record_label = program.allocate_label();
-        program.emit_insn(Insn::NotExists {
+    // Create new rowid if a) not provided by user or b) provided by user but is NULL.
+    // A WITHOUT ROWID table has none, and its rowid register stays NULL.
+    if btree_table.has_rowid {
+        program.emit_insn(Insn::NewRowid {
             cursor: cursor_id,
             rowid_reg,
-            target_pc: make_record_label,
+            prev_largest_reg: 0,
         });
-        let rowid_column_name = if let Some(index) = rowid_alias_index {
-            btree_table
-                .columns
-                .get(index)
-                .unwrap()
-                .name
-                .as_ref()
-                .expect("column name is None")
-        } else {
-            "rowid"
-        };
+    }
 
-        program.emit_insn(Insn::Halt {
-            err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
-            description: format!("{}.{}", table_name.0, rowid_column_name),
+    if let Some(must_be_int_label) = check_rowid_is_integer_label {
+        program.resolve_label(must_be_int_label, program.offset());
+        // If the user provided a rowid, it must be an integer.
+        program.emit_insn(Insn::MustBeInt {
+            reg: rowid_reg,
+            target_pc: None,
         });
-
-        program.resolve_label(make_record_label, program.offset());
     }
 
     match table.btree() {
@@ -283,77 +379,75 @@ pub fn translate_insert(
         }
         _ => (),
     }
-    // Create and insert the record
-    program.emit_insn(Insn::MakeRecord {
-        start_reg: column_registers_start,
-        count: num_cols,
-        dest_reg: record_register,
-    });
-
-    program.emit_insn(Insn::Insert {
-        cursor: cursor_id,
-        key_reg: rowid_reg,
-        record_reg: record_register,
-        flag: 0,
+    let upserts = resolve_upserts(&mut program, &btree_table, &cursors, upsert)?;
+    let upsert_rowid_reg = program.alloc_registers(if btree_table.has_rowid {
+        1
+    } else {
+        btree_table.primary_key_columns.len()
     });
-    for index_col_mapping in index_col_mappings.iter() {
-        // find which cursor we opened earlier for this index
-        let idx_cursor_id = idx_cursors
-            .iter()
-            .find(|(name, _, _)| *name == &index_col_mapping.idx_name)
-            .map(|(_, _, c_id)| *c_id)
-            .expect("no cursor found for index");
-
-        let num_cols = index_col_mapping.columns.len();
-        // allocate scratch registers for the index columns plus rowid
-        let idx_start_reg = program.alloc_registers(num_cols + 1);
-
-        // copy each index column from the table's column registers into these scratch regs
-        for (i, col) in index_col_mapping.columns.iter().enumerate() {
-            // copy from the table's column register over to the index's scratch register
-
-            program.emit_insn(Insn::Copy {
-                src_reg: column_registers_start + col.0,
-                dst_reg: idx_start_reg + i,
-                amount: 0,
-            });
-        }
-        // last register is the rowid
-        program.emit_insn(Insn::Copy {
-            src_reg: rowid_reg,
-            dst_reg: idx_start_reg + num_cols,
-            amount: 0,
-        });
-
-        let record_reg = program.alloc_register();
-        program.emit_insn(Insn::MakeRecord {
-            start_reg: idx_start_reg,
-            count: num_cols + 1,
-            dest_reg: record_reg,
-        });
-
-        // now do the actual index insertion using the unpacked registers
-        program.emit_insn(Insn::IdxInsert {
-            cursor_id: idx_cursor_id,
-            record_reg,
-            unpacked_start: Some(idx_start_reg), // TODO: enable optimization
-            unpacked_count: Some((num_cols + 1) as u16),
-            // TODO: figure out how to determine whether or not we need to seek prior to insert.
-            flags: IdxInsertFlags::new(),
-        });
+    // When the DB allocates the rowid there is no need to check that it is unique.
+    let row_source = if has_user_provided_rowid {
+        RowSource::GivenRowid
+    } else {
+        RowSource::NewRowid
+    };
+    emit_constraint_checks(
+        &mut program,
+        &btree_table,
+        &cursors,
+        rowid_reg,
+        row_source,
+        *on_conflict,
+        &|index| {
+            upserts
+                .iter()
+                .find(|upsert| upsert.handles(index))
+                .map(|upsert| upsert.on_conflict(upsert_rowid_reg))
+        },
+        &replace_deletes,
+        row_done_offset,
+        &resolver,
+    )?;
+    foreign_keys.emit(&mut program, None, Some(rowid_reg));
+    emit_insert_row(&mut program, &btree_table, cursor_id, rowid_reg, false);
+    emit_insert_index_entries(&mut program, &btree_table, &cursors, rowid_reg, &resolver)?;
+    if let Some(returning) = &returning {
+        returning.emit_row(&mut program, rowid_reg, &resolver)?;
     }
-    if inserting_multiple_rows {
+    triggers.emit(
+        &mut program,
+        TriggerTime::After,
+        None,
+        Some(rowid_reg),
+        row_done_offset,
+    );
+    if inserting_multiple_rows || !upserts.is_empty() {
         // For multiple rows, loop back
         program.emit_insn(Insn::Goto {
-            target_pc: loop_start_offset,
+            target_pc: row_done_offset,
         });
     }
+    for upsert in upserts.iter() {
+        emit_upsert(
+            &mut program,
+            &target_schema,
+            &btree_table,
+            &cursors,
+            upsert,
+            rowid_reg,
+            upsert_rowid_reg,
+            row_done_offset,
+            returning.as_ref(),
+            syms,
+            &resolver,
+        )?;
+    }
 
     program.resolve_label(halt_label, program.offset());
-    program.emit_insn(Insn::Halt {
-        err_code: 0,
-        description: String::new(),
-    });
+    if let Some(returning) = &returning {
+        returning.emit_results(&mut program);
+    }
+    program.emit_halt();
 
     program.resolve_label(init_label, program.offset());
     program.emit_insn(Insn::Transaction { write: true });
@@ -361,10 +455,138 @@ pub fn translate_insert(
     program.emit_insn(Insn::Goto {
         target_pc: start_offset,
     });
+    program.result_columns = returning_columns;
+    program.table_references = table_references;
 
     Ok(program)
 }
 
+/// Emits the SELECT of an INSERT as a coroutine that yields the rows to insert, and returns its
+/// yield register and the first register of a row. Like in SQLite, when the SELECT reads the table
+/// that the statement inserts into, its rows are all collected in an ephemeral index first, so
+/// that the statement does not see the rows that it inserts.
+fn emit_insert_select(
+    program: &mut ProgramBuilder,
+    plan: &mut SelectPlan,
+    reads_table: bool,
+    syms: &SymbolTable,
+) -> Result<(usize, usize)> {
+    let (select_yield_reg, select_row_start) = emit_coroutine(program, plan, syms)?;
+    if !reads_table {
+        return Ok((select_yield_reg, select_row_start));
+    }
+    // Like for RETURNING, the key of an entry is the row between two copies of a counter that
+    // keeps the rows in order.
+    let num_cols = plan.result_columns.len();
+    let key_len = num_cols + 2;
+    let index = Arc::new(Index {
+        name: "ephemeral_insert_select".to_string(),
+        table_name: String::new(),
+        root_page: 0,
+        columns: (0..key_len)
+            .map(|i| IndexColumn {
+                name: format!("c{}", i),
+                order: SortOrder::Asc,
+                pos_in_table: i,
+                collation: None,
+                expr: None,
+            })
+            .collect(),
+        unique: false,
+        ephemeral: true,
+        where_clause: None,
+        key_suffix: Vec::new(),
+    });
+    let cursor_id = program.alloc_cursor_id(None, CursorType::BTreeIndex(index));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id,
+        is_table: false,
+    });
+    let reg_counter = program.alloc_register();
+    program.emit_int(0, reg_counter);
+    let reg_one = program.alloc_register();
+    program.emit_int(1, reg_one);
+    let reg_key = program.alloc_registers(key_len);
+    let reg_record = program.alloc_register();
+    let collected_label = program.allocate_label();
+    let collect_start = program.offset();
+    program.emit_insn(Insn::Yield {
+        yield_reg: select_yield_reg,
+        end_offset: collected_label,
+    });
+    program.emit_insn(Insn::Add {
+        lhs: reg_counter,
+        rhs: reg_one,
+        dest: reg_counter,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_counter,
+        dst_reg: reg_key,
+        amount: 0,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: select_row_start,
+        dst_reg: reg_key + 1,
+        amount: num_cols - 1,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_counter,
+        dst_reg: reg_key + key_len - 1,
+        amount: 0,
+    });
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: reg_key,
+        count: key_len,
+        dest_reg: reg_record,
+    });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id,
+        record_reg: reg_record,
+        unpacked_start: Some(reg_key),
+        unpacked_count: Some(key_len as u16),
+        flags: IdxInsertFlags::new().use_seek(false),
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: collect_start,
+    });
+    program.resolve_label(collected_label, program.offset());
+
+    // The collected rows, yielded by a coroutine like the ones of the SELECT.
+    let yield_reg = program.alloc_register();
+    let row_start = program.alloc_registers(num_cols);
+    let jump_on_definition_label = program.allocate_label();
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg,
+        jump_on_definition: jump_on_definition_label,
+        start_offset: program.offset().add(1u32),
+    });
+    let rows_done_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: rows_done_label,
+    });
+    let rows_start = program.offset();
+    for i in 0..num_cols {
+        program.emit_insn(Insn::Column {
+            cursor_id,
+            column: i + 1,
+            dest: row_start + i,
+        });
+    }
+    program.emit_insn(Insn::Yield {
+        yield_reg,
+        end_offset: rows_done_label,
+    });
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: rows_start,
+    });
+    program.resolve_label(rows_done_label, program.offset());
+    program.emit_insn(Insn::EndCoroutine { yield_reg });
+    program.resolve_label(jump_on_definition_label, program.offset());
+    Ok((yield_reg, row_start))
+}
+
 #[derive(Debug)]
 /// Represents how a column should be populated during an INSERT.
 /// Contains both the column definition and optionally the index into the VALUES tuple.
@@ -469,67 +691,313 @@ fn resolve_columns_for_insert<'a>(
     Ok(mappings)
 }
 
-/// Represents how a column in an index should be populated during an INSERT.
-/// Similar to ColumnMapping above but includes the index name, as well as multiple
-/// possible value indices for each.
-#[derive(Default)]
-struct IndexColMapping {
-    idx_name: String,
-    columns: Vec<(usize, IndexColumn)>,
-    value_indicies: Vec<Option<usize>>,
+/// A clause of an upsert, resolved against the table.
+struct UpsertClause<'a> {
+    /// The conflicts that the clause handles: those of the rowid if None, or those of the index.
+    /// A clause without a conflict target handles all of them.
+    target: Option<Option<Arc<Index>>>,
+    /// The assignments and the WHERE clause of DO UPDATE, or None for DO NOTHING.
+    update: Option<(&'a [Set], Option<&'a Expr>)>,
+    /// Where the DO UPDATE clause is emitted.
+    label: BranchOffset,
 }
 
-impl IndexColMapping {
-    fn new(name: String) -> Self {
-        IndexColMapping {
-            idx_name: name,
-            ..Default::default()
+impl UpsertClause<'_> {
+    fn handles(&self, index: Option<&Index>) -> bool {
+        match (&self.target, index) {
+            (None, _) => true,
+            (Some(None), None) => true,
+            (Some(Some(target)), Some(index)) => target.name == index.name,
+            _ => false,
+        }
+    }
+
+    fn on_conflict(&self, rowid_reg: usize) -> OnConflict {
+        match self.update {
+            Some(_) => OnConflict::Goto {
+                target: self.label,
+                rowid_reg,
+            },
+            None => OnConflict::Resolve(ResolveType::Ignore),
         }
     }
 }
 
-/// Example:
-/// Table 'test': (a, b, c);
-/// Index 'idx': test(a, b);
-///________________________________
-/// Insert (a, c): (2, 3)
-/// Record: (2, NULL, 3)
-/// IndexColMapping: (a, b) = (2, NULL)
-fn resolve_indicies_for_insert(
+/// Matches the conflict target of each clause of an upsert with the rowid or a UNIQUE index.
+fn resolve_upserts<'a>(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursors: &WriteCursors,
+    mut upsert: Option<&'a Upsert>,
+) -> Result<Vec<UpsertClause<'a>>> {
+    let mut clauses = Vec::new();
+    while let Some(clause) = upsert {
+        let target = match &clause.index {
+            Some(index) => {
+                let mut columns = Vec::with_capacity(index.targets.len());
+                for target in index.targets.iter() {
+                    let name = match &target.expr {
+                        Expr::Id(name) => &name.0,
+                        Expr::Name(name) => &name.0,
+                        _ => crate::bail_parse_error!(
+                            "expressions in an ON CONFLICT target are not supported"
+                        ),
+                    };
+                    let Some((position, _)) = table.get_column(name) else {
+                        crate::bail_parse_error!("no such column: {}", name);
+                    };
+                    columns.push(position);
+                }
+                // The rowid, or the PRIMARY KEY of a WITHOUT ROWID table, is checked in the table
+                let is_table_key = if table.has_rowid {
+                    matches!(columns[..], [position] if table.columns[position].is_rowid_alias)
+                } else {
+                    let primary_key = table.primary_key_column_positions();
+                    primary_key.len() == columns.len()
+                        && primary_key
+                            .iter()
+                            .all(|position| columns.contains(position))
+                };
+                if is_table_key {
+                    clauses.push(UpsertClause {
+                        target: Some(None),
+                        update: upsert_update(&clause.do_clause),
+                        label: program.allocate_label(),
+                    });
+                    upsert = clause.next.as_deref();
+                    continue;
+                }
+                let index = cursors.indexes.iter().find(|index_cursor| {
+                    let index = &index_cursor.index;
+                    index.unique
+                        && index.where_clause.is_none()
+                        && !index.has_expressions()
+                        && index.columns.len() == columns.len()
+                        && index
+                            .columns
+                            .iter()
+                            .all(|column| columns.contains(&column.pos_in_table))
+                });
+                match index {
+                    Some(index_cursor) => Some(Some(index_cursor.index.clone())),
+                    None => crate::bail_parse_error!(
+                        "ON CONFLICT clause does not match any PRIMARY KEY or UNIQUE constraint"
+                    ),
+                }
+            }
+            None => None,
+        };
+        clauses.push(UpsertClause {
+            target,
+            update: upsert_update(&clause.do_clause),
+            label: program.allocate_label(),
+        });
+        upsert = clause.next.as_deref();
+    }
+    Ok(clauses)
+}
+
+fn upsert_update(do_clause: &UpsertDo) -> Option<(&[Set], Option<&Expr>)> {
+    match do_clause {
+        UpsertDo::Set { sets, where_clause } => Some((sets, where_clause.as_ref())),
+        UpsertDo::Nothing => None,
+    }
+}
+
+/// Emits the DO UPDATE clause of an upsert, which updates the existing row whose rowid is in
+/// `conflict_rowid_reg` instead of inserting the row at `row`, which is the `excluded` row. In a
+/// WITHOUT ROWID table, the registers from `conflict_rowid_reg` hold the PRIMARY KEY of the row.
+#[allow(clippy::too_many_arguments)]
+fn emit_upsert(
+    program: &mut ProgramBuilder,
     schema: &Schema,
-    table: &Table,
-    columns: &[ColumnMapping<'_>],
-) -> Result<Vec<IndexColMapping>> {
-    let mut index_col_mappings = Vec::new();
-    // Iterate over all indices for this table
-    for index in schema.get_indices(table.get_name()) {
-        let mut idx_map = IndexColMapping::new(index.name.clone());
-        // For each column in the index (in the order defined by the index),
-        // try to find the corresponding column in the insert’s column mapping.
-        for idx_col in &index.columns {
-            let target_name = normalize_ident(idx_col.name.as_str());
-            if let Some((i, col_mapping)) = columns.iter().enumerate().find(|(_, mapping)| {
-                mapping
-                    .column
-                    .name
-                    .as_ref()
-                    .map_or(false, |name| name.eq_ignore_ascii_case(&target_name))
-            }) {
-                idx_map.columns.push((i, idx_col.clone()));
-                idx_map.value_indicies.push(col_mapping.value_index);
+    table: &Rc<BTreeTable>,
+    cursors: &WriteCursors,
+    upsert: &UpsertClause,
+    row: usize,
+    conflict_rowid_reg: usize,
+    row_done: BranchOffset,
+    returning: Option<&Returning>,
+    syms: &SymbolTable,
+    resolver: &Resolver,
+) -> Result<()> {
+    let Some((sets, where_clause)) = upsert.update else {
+        return Ok(());
+    };
+    program.resolve_label(upsert.label, program.offset());
+    let num_cols = table.columns.len();
+    let old_row = program.alloc_registers(num_cols + 1);
+    if table.has_rowid {
+        program.emit_insn(Insn::SeekRowid {
+            cursor_id: cursors.table,
+            src_reg: conflict_rowid_reg,
+            target_pc: row_done,
+        });
+        program.emit_insn(Insn::Copy {
+            src_reg: conflict_rowid_reg,
+            dst_reg: old_row,
+            amount: 0,
+        });
+    } else {
+        program.emit_insn(Insn::NotFound {
+            cursor_id: cursors.table,
+            target_pc: row_done,
+            record_reg: conflict_rowid_reg,
+            num_regs: table.primary_key_columns.len(),
+        });
+        program.emit_null(old_row, None);
+    }
+    for (i, column) in table.columns.iter().enumerate() {
+        if column.is_rowid_alias {
+            program.emit_null(old_row + 1 + i, None);
+        } else {
+            program.emit_insn(Insn::Column {
+                cursor_id: cursors.table,
+                column: i,
+                dest: old_row + 1 + i,
+            });
+        }
+    }
+    let rewrite = |expr: &Expr| -> Result<Expr> {
+        let mut expr = expr.clone();
+        rewrite_upsert_expr(&mut expr, table, old_row, row)?;
+        Ok(expr)
+    };
+    if let Some(where_clause) = where_clause {
+        let reg = program.alloc_register();
+        translate_expr(program, None, &rewrite(where_clause)?, reg, resolver)?;
+        program.emit_insn(Insn::IfNot {
+            reg,
+            target_pc: row_done,
+            jump_if_null: true,
+        });
+    }
+    let new_row = program.alloc_registers(num_cols + 1);
+    program.emit_insn(Insn::Copy {
+        src_reg: old_row,
+        dst_reg: new_row,
+        amount: num_cols,
+    });
+    let mut updated_columns = Vec::with_capacity(sets.len());
+    for set in sets {
+        let name = &set.col_names[0].0;
+        let Some((position, column)) = table.get_column(name) else {
+            crate::bail_parse_error!("no such column: {}", name);
+        };
+        if column.is_rowid_alias {
+            crate::bail_parse_error!("updating the rowid in an upsert is not supported");
+        }
+        translate_expr(
+            program,
+            None,
+            &rewrite(&set.expr)?,
+            new_row + 1 + position,
+            resolver,
+        )?;
+        updated_columns.push(position);
+    }
+    let triggers = RowTriggers::compile(
+        program,
+        schema,
+        &table.name,
+        &table.columns,
+        TriggerEvent::Update,
+        &updated_columns,
+        syms,
+    )?;
+    triggers.emit(
+        program,
+        TriggerTime::Before,
+        Some(old_row),
+        Some(new_row),
+        row_done,
+    );
+    if table.is_strict {
+        program.emit_insn(Insn::TypeCheck {
+            start_reg: new_row + 1,
+            count: num_cols,
+            check_generated: true,
+            table_reference: Rc::clone(table),
+        });
+    }
+    let update_cursors = cursors.for_update(table, &updated_columns);
+    emit_constraint_checks(
+        program,
+        table,
+        &update_cursors,
+        new_row,
+        RowSource::Update,
+        Some(ResolveType::Abort),
+        &|_| None,
+        &ReplaceDeletes::default(),
+        row_done,
+        resolver,
+    )?;
+    ForeignKeyChecks::compile(schema, table, Some(&updated_columns), syms)?.emit(
+        program,
+        Some(old_row),
+        Some(new_row),
+    );
+    emit_delete_index_entries(program, table, &update_cursors, false, resolver)?;
+    emit_insert_row(program, table, cursors.table, new_row, true);
+    emit_insert_index_entries(program, table, &update_cursors, new_row, resolver)?;
+    if let Some(returning) = returning {
+        returning.emit_row(program, new_row, resolver)?;
+    }
+    triggers.emit(
+        program,
+        TriggerTime::After,
+        Some(old_row),
+        Some(new_row),
+        row_done,
+    );
+    program.emit_insn(Insn::Goto {
+        target_pc: row_done,
+    });
+    Ok(())
+}
+
+/// Rewrites the columns of the table in an expression of an upsert as the registers of the
+/// existing row at `old_row`, and the columns of the `excluded` row as those of `excluded_row`.
+fn rewrite_upsert_expr(
+    expr: &mut Expr,
+    table: &BTreeTable,
+    old_row: usize,
+    excluded_row: usize,
+) -> Result<()> {
+    let (row, name) = match expr {
+        Expr::Id(name) => (old_row, name.0.clone()),
+        Expr::Qualified(qualifier, name) => {
+            let qualifier = normalize_ident(&qualifier.0);
+            if qualifier == "excluded" {
+                (excluded_row, name.0.clone())
+            } else if qualifier.eq_ignore_ascii_case(&table.name) {
+                (old_row, name.0.clone())
             } else {
-                return Err(crate::LimboError::ParseError(format!(
-                    "Column {} not found in index {}",
-                    target_name, index.name
-                )));
+                crate::bail_parse_error!("no such column: {}.{}", qualifier, name.0);
             }
         }
-        // Add the mapping if at least one column was found.
-        if !idx_map.columns.is_empty() {
-            index_col_mappings.push(idx_map);
+        Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. } => {
+            crate::bail_parse_error!("subqueries in an upsert are not supported")
         }
-    }
-    Ok(index_col_mappings)
+        _ => {
+            for child in child_exprs_mut(expr) {
+                rewrite_upsert_expr(child, table, old_row, excluded_row)?;
+            }
+            return Ok(());
+        }
+    };
+    let offset = match table.get_column(&name) {
+        // The value of a rowid alias column is the rowid.
+        Some((_, column)) if column.is_rowid_alias => 0,
+        Some((position, _)) => position + 1,
+        None if ["rowid", "oid", "_rowid_"].contains(&normalize_ident(&name).as_str()) => 0,
+        // Not a column, e.g. a string in double quotes
+        None if matches!(expr, Expr::Id(_)) => return Ok(()),
+        None => crate::bail_parse_error!("no such column: {}", name),
+    };
+    *expr = Expr::Register(row + offset);
+    Ok(())
 }
 
 /// Populates the column registers with values for a single row
@@ -672,10 +1140,7 @@ fn translate_virtual_table_insert(
     });
 
     let halt_label = program.allocate_label();
-    program.emit_insn(Insn::Halt {
-        err_code: 0,
-        description: String::new(),
-    });
+    program.emit_halt();
 
     program.resolve_label(halt_label, program.offset());
     program.resolve_label(init_label, program.offset());
@@ -1,14 +1,14 @@
 use limbo_ext::VTabKind;
-use limbo_sqlite3_parser::ast;
+use limbo_sqlite3_parser::ast::{self, SortOrder};
 
-use std::sync::Arc;
+use std::{num::NonZeroUsize, sync::Arc};
 
 use crate::{
-    schema::{Index, Table},
+    schema::{Affinity, Index, IndexColumn, Table},
     translate::result_row::emit_select_result,
     types::SeekOp,
     vdbe::{
-        builder::ProgramBuilder,
+        builder::{CursorType, ProgramBuilder},
         insn::{CmpInsFlags, IdxInsertFlags, Insn},
         BranchOffset, CursorID,
     },
@@ -17,15 +17,18 @@ use crate::{
 
 use super::{
     aggregation::translate_aggregation_step,
+    collate::{expr_collation_name, resolve_collation},
+    distinct::emit_distinct_check,
     emitter::{OperationMode, TranslateCtx},
     expr::{translate_condition_expr, translate_expr, ConditionMetadata},
     group_by::is_column_in_group_by,
     optimizer::Optimizable,
     order_by::{order_by_sorter_insert, sorter_insert},
     plan::{
-        convert_where_to_vtab_constraint, IterationDirection, Operation, Search, SeekDef,
-        SelectPlan, SelectQueryType, TableReference, WhereTerm,
+        convert_where_to_vtab_constraint, Aggregate, IterationDirection, Operation, Search,
+        SeekDef, SelectPlan, SelectQueryType, TableReference, WhereTerm,
     },
+    window::window_sorter_insert,
 };
 
 // Metadata for handling LEFT JOIN operations
@@ -39,6 +42,15 @@ pub struct LeftJoinMetadata {
     pub label_match_flag_check_value: BranchOffset,
 }
 
+/// Metadata for handling RIGHT and FULL OUTER JOIN operations
+#[derive(Debug)]
+pub struct RightJoinMetadata {
+    /// cursor of an ephemeral index that holds the keys of the rows of the right table that matched a row of the left tables
+    pub matched_cursor_id: CursorID,
+    /// register that holds the position of the current row, if the right table is a subquery, since subqueries have no rowid
+    pub reg_row_number: Option<usize>,
+}
+
 /// Jump labels for each loop in the query's main execution loop
 #[derive(Debug, Clone, Copy)]
 pub struct LoopLabels {
@@ -71,6 +83,10 @@ pub fn init_loop(
         t_ctx.meta_left_joins.len() == tables.len(),
         "meta_left_joins length does not match tables length"
     );
+    assert!(
+        t_ctx.meta_right_joins.len() == tables.len(),
+        "meta_right_joins length does not match tables length"
+    );
     for (table_index, table) in tables.iter().enumerate() {
         // Initialize bookkeeping for OUTER JOIN
         if let Some(join_info) = table.join_info.as_ref() {
@@ -82,6 +98,14 @@ pub fn init_loop(
                 };
                 t_ctx.meta_left_joins[table_index] = Some(lj_metadata);
             }
+            if join_info.right {
+                let rj_metadata = RightJoinMetadata {
+                    matched_cursor_id: emit_open_matched_index(program),
+                    reg_row_number: matches!(table.op, Operation::Subquery { .. })
+                        .then(|| program.alloc_register()),
+                };
+                t_ctx.meta_right_joins[table_index] = Some(rj_metadata);
+            }
         }
         let (table_cursor_id, index_cursor_id) = table.open_cursors(program, mode)?;
         match &table.op {
@@ -92,12 +116,16 @@ pub fn init_loop(
                         program.emit_insn(Insn::OpenRead {
                             cursor_id,
                             root_page,
+                            db: table.database,
                         });
                     }
-                    if let Some(index_cursor_id) = index_cursor_id {
+                    if let Some(index_cursor_id) =
+                        index_cursor_id.filter(|id| Some(*id) != table_cursor_id)
+                    {
                         program.emit_insn(Insn::OpenRead {
                             cursor_id: index_cursor_id,
                             root_page: index.as_ref().unwrap().root_page,
+                            db: table.database,
                         });
                     }
                 }
@@ -107,6 +135,7 @@ pub fn init_loop(
                         cursor_id: table_cursor_id
                             .expect("table cursor is always opened in OperationMode::DELETE"),
                         root_page: root_page.into(),
+                        db: table.database,
                     });
                 }
                 (OperationMode::UPDATE, Table::BTree(btree)) => {
@@ -115,11 +144,15 @@ pub fn init_loop(
                         cursor_id: table_cursor_id
                             .expect("table cursor is always opened in OperationMode::UPDATE"),
                         root_page: root_page.into(),
+                        db: table.database,
                     });
-                    if let Some(index_cursor_id) = index_cursor_id {
+                    if let Some(index_cursor_id) =
+                        index_cursor_id.filter(|id| Some(*id) != table_cursor_id)
+                    {
                         program.emit_insn(Insn::OpenWrite {
                             cursor_id: index_cursor_id,
                             root_page: index.as_ref().unwrap().root_page.into(),
+                            db: table.database,
                         });
                     }
                 }
@@ -139,6 +172,7 @@ pub fn init_loop(
                             program.emit_insn(Insn::OpenRead {
                                 cursor_id: table_cursor_id,
                                 root_page: table.table.get_root_page(),
+                                db: table.database,
                             });
                         }
                     }
@@ -147,6 +181,7 @@ pub fn init_loop(
                         program.emit_insn(Insn::OpenWrite {
                             cursor_id: table_cursor_id,
                             root_page: table.table.get_root_page().into(),
+                            db: table.database,
                         });
                     }
                     _ => {
@@ -158,14 +193,16 @@ pub fn init_loop(
                     index: Some(index), ..
                 } = search
                 {
-                    // Ephemeral index cursor are opened ad-hoc when needed.
-                    if !index.ephemeral {
+                    // Ephemeral index cursor are opened ad-hoc when needed, and the PRIMARY KEY index
+                    // of a WITHOUT ROWID table is the table itself.
+                    if !index.ephemeral && !table.is_primary_key_index(index) {
                         match mode {
                             OperationMode::SELECT => {
                                 program.emit_insn(Insn::OpenRead {
                                     cursor_id: index_cursor_id
                                         .expect("index cursor is always opened in Seek with index"),
                                     root_page: index.root_page,
+                                    db: table.database,
                                 });
                             }
                             OperationMode::UPDATE | OperationMode::DELETE => {
@@ -173,6 +210,7 @@ pub fn init_loop(
                                     cursor_id: index_cursor_id
                                         .expect("index cursor is always opened in Seek with index"),
                                     root_page: index.root_page.into(),
+                                    db: table.database,
                                 });
                             }
                             _ => {
@@ -198,7 +236,18 @@ pub fn open_loop(
     tables: &[TableReference],
     predicates: &[WhereTerm],
 ) -> Result<()> {
-    for (table_index, table) in tables.iter().enumerate() {
+    open_loops_from(program, t_ctx, tables, predicates, 0)
+}
+
+/// Open the loops of the tables starting at `first_table_index`, see [open_loop].
+fn open_loops_from(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    tables: &[TableReference],
+    predicates: &[WhereTerm],
+    first_table_index: usize,
+) -> Result<()> {
+    for (table_index, table) in tables.iter().enumerate().skip(first_table_index) {
         let LoopLabels {
             loop_start,
             loop_end,
@@ -225,47 +274,47 @@ pub fn open_loop(
 
         match &table.op {
             Operation::Subquery { plan, .. } => {
-                let (yield_reg, coroutine_implementation_start) = match &plan.query_type {
+                // A subquery has no rowid, so the rows of a subquery on the right side of a RIGHT or
+                // FULL OUTER JOIN are identified by their position instead.
+                let reg_row_number = t_ctx.meta_right_joins[table_index]
+                    .as_ref()
+                    .and_then(|rj_meta| rj_meta.reg_row_number);
+                if let Some(reg_row_number) = reg_row_number {
+                    program.emit_insn(Insn::Integer {
+                        value: 0,
+                        dest: reg_row_number,
+                    });
+                }
+                match &plan.query_type {
                     SelectQueryType::Subquery {
                         yield_reg,
                         coroutine_implementation_start,
-                    } => (*yield_reg, *coroutine_implementation_start),
+                    } => {
+                        // In case the subquery is an inner loop, it needs to be reinitialized on each iteration of the outer loop.
+                        program.emit_insn(Insn::InitCoroutine {
+                            yield_reg: *yield_reg,
+                            jump_on_definition: BranchOffset::Offset(0),
+                            start_offset: *coroutine_implementation_start,
+                        });
+                        program.resolve_label(loop_start, program.offset());
+                        // A subquery within the main loop of a parent query has no cursor, so instead of advancing the cursor,
+                        // it emits a Yield which jumps back to the main loop of the subquery itself to retrieve the next row.
+                        // When the subquery coroutine completes, this instruction jumps to the label at the top of the termination_label_stack,
+                        // which in this case is the end of the Yield-Goto loop in the parent query.
+                        program.emit_insn(Insn::Yield {
+                            yield_reg: *yield_reg,
+                            end_offset: loop_end,
+                        });
+                        if let Some(reg_row_number) = reg_row_number {
+                            emit_increment(program, reg_row_number);
+                        }
+                    }
+                    // The only row of a recursive CTE within one of its recursive members is already in
+                    // the result registers of the CTE, so the loop runs exactly once.
+                    SelectQueryType::RecursiveRow => {
+                        program.resolve_label(loop_start, program.offset());
+                    }
                     _ => unreachable!("Subquery operator with non-subquery query type"),
-                };
-                // In case the subquery is an inner loop, it needs to be reinitialized on each iteration of the outer loop.
-                program.emit_insn(Insn::InitCoroutine {
-                    yield_reg,
-                    jump_on_definition: BranchOffset::Offset(0),
-                    start_offset: coroutine_implementation_start,
-                });
-                program.resolve_label(loop_start, program.offset());
-                // A subquery within the main loop of a parent query has no cursor, so instead of advancing the cursor,
-                // it emits a Yield which jumps back to the main loop of the subquery itself to retrieve the next row.
-                // When the subquery coroutine completes, this instruction jumps to the label at the top of the termination_label_stack,
-                // which in this case is the end of the Yield-Goto loop in the parent query.
-                program.emit_insn(Insn::Yield {
-                    yield_reg,
-                    end_offset: loop_end,
-                });
-
-                for cond in predicates
-                    .iter()
-                    .filter(|cond| cond.should_eval_at_loop(table_index))
-                {
-                    let jump_target_when_true = program.allocate_label();
-                    let condition_metadata = ConditionMetadata {
-                        jump_if_condition_is_true: false,
-                        jump_target_when_true,
-                        jump_target_when_false: next,
-                    };
-                    translate_condition_expr(
-                        program,
-                        tables,
-                        &cond.expr,
-                        condition_metadata,
-                        &t_ctx.resolver,
-                    )?;
-                    program.resolve_label(jump_target_when_true, program.offset());
                 }
             }
             Operation::Scan { iter_dir, .. } => {
@@ -396,31 +445,9 @@ pub fn open_loop(
 
                 if let Some(table_cursor_id) = table_cursor_id {
                     if let Some(index_cursor_id) = index_cursor_id {
-                        program.emit_insn(Insn::DeferredSeek {
-                            index_cursor_id,
-                            table_cursor_id,
-                        });
+                        emit_seek_table_row(program, table, index_cursor_id, table_cursor_id, next);
                     }
                 }
-
-                for (_, cond) in predicates.iter().enumerate().filter(|(i, cond)| {
-                    cond.should_eval_at_loop(table_index) && !t_ctx.omit_predicates.contains(i)
-                }) {
-                    let jump_target_when_true = program.allocate_label();
-                    let condition_metadata = ConditionMetadata {
-                        jump_if_condition_is_true: false,
-                        jump_target_when_true,
-                        jump_target_when_false: next,
-                    };
-                    translate_condition_expr(
-                        program,
-                        tables,
-                        &cond.expr,
-                        condition_metadata,
-                        &t_ctx.resolver,
-                    )?;
-                    program.resolve_label(jump_target_when_true, program.offset());
-                }
             }
             Operation::Search(search) => {
                 // Open the loop for the index search.
@@ -440,6 +467,46 @@ pub fn open_loop(
                         src_reg,
                         target_pc: next,
                     });
+                } else if let Search::Hash {
+                    index,
+                    key,
+                    affinities,
+                } = search
+                {
+                    let table_has_rowid = table.btree().is_some_and(|btree| btree.has_rowid);
+                    let hash_cursor_id =
+                        index_cursor_id.expect("a hash join must have a hash table cursor");
+                    emit_hash_table(
+                        program,
+                        t_ctx,
+                        index,
+                        key.len(),
+                        table_cursor_id.expect("a hash join must have a source table cursor"),
+                        hash_cursor_id,
+                        table_has_rowid,
+                    )?;
+                    let key_reg = program.alloc_registers(key.len());
+                    for (i, expr) in key.iter().enumerate() {
+                        translate_expr(program, Some(tables), expr, key_reg + i, &t_ctx.resolver)?;
+                    }
+                    // The key is converted like the operands of the comparisons it stands for, so
+                    // that it hashes like the values of the table that compare equal to it.
+                    if affinities
+                        .iter()
+                        .any(|affinity| *affinity != Affinity::Blob)
+                    {
+                        program.emit_insn(Insn::Affinity {
+                            start_reg: key_reg,
+                            count: NonZeroUsize::new(key.len()).unwrap(),
+                            affinities: affinities.iter().map(|a| a.aff_mask()).collect(),
+                        });
+                    }
+                    program.emit_insn(Insn::HashProbe {
+                        cursor_id: hash_cursor_id,
+                        key_reg,
+                        target_pc: loop_end,
+                    });
+                    program.resolve_label(loop_start, program.offset());
                 } else {
                     // Otherwise, it's an index/rowid scan, i.e. first a seek is performed and then a scan until the comparison expression is not satisfied anymore.
                     if let Search::Seek {
@@ -501,37 +568,65 @@ pub fn open_loop(
 
                     if let Some(index_cursor_id) = index_cursor_id {
                         if let Some(table_cursor_id) = table_cursor_id {
-                            // Don't do a btree table seek until it's actually necessary to read from the table.
-                            program.emit_insn(Insn::DeferredSeek {
+                            emit_seek_table_row(
+                                program,
+                                table,
                                 index_cursor_id,
                                 table_cursor_id,
-                            });
+                                next,
+                            );
                         }
                     }
                 }
-
-                for cond in predicates
-                    .iter()
-                    .filter(|cond| cond.should_eval_at_loop(table_index))
-                {
-                    let jump_target_when_true = program.allocate_label();
-                    let condition_metadata = ConditionMetadata {
-                        jump_if_condition_is_true: false,
-                        jump_target_when_true,
-                        jump_target_when_false: next,
-                    };
-                    translate_condition_expr(
-                        program,
-                        tables,
-                        &cond.expr,
-                        condition_metadata,
-                        &t_ctx.resolver,
-                    )?;
-                    program.resolve_label(jump_target_when_true, program.offset());
-                }
             }
         }
 
+        // The ON conditions of an OUTER JOIN decide whether the rows match, so they are evaluated first.
+        // The other conditions, e.g. the WHERE clause, are evaluated after the match flag is set,
+        // so that they also apply to the rows that are emitted with NULLs for the right table.
+        let (join_conditions, other_conditions): (Vec<_>, Vec<_>) = predicates
+            .iter()
+            .enumerate()
+            .filter(|(i, cond)| {
+                cond.should_eval_at_loop(table_index) && !t_ctx.omit_predicates.contains(i)
+            })
+            .map(|(_, cond)| cond)
+            .partition(|cond| cond.from_join == Some(table_index));
+        emit_conditions(program, t_ctx, tables, &join_conditions, next)?;
+
+        // Remember that the current row of the right table of a RIGHT or FULL OUTER JOIN has a match,
+        // so that it is not emitted again with NULLs for the left tables after the main loop.
+        if let Some(rj_meta) = t_ctx.meta_right_joins[table_index].as_ref() {
+            let matched_cursor_id = rj_meta.matched_cursor_id;
+            let key_reg = emit_right_join_key(program, t_ctx, tables, table_index)?;
+            let label_already_matched = program.allocate_label();
+            let label_not_matched = program.allocate_label();
+            program.emit_insn(Insn::NotFound {
+                cursor_id: matched_cursor_id,
+                target_pc: label_not_matched,
+                record_reg: key_reg,
+                num_regs: 2,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: label_already_matched,
+            });
+            program.resolve_label(label_not_matched, program.offset());
+            let record_reg = program.alloc_register();
+            program.emit_insn(Insn::MakeRecord {
+                start_reg: key_reg,
+                count: 2,
+                dest_reg: record_reg,
+            });
+            program.emit_insn(Insn::IdxInsert {
+                cursor_id: matched_cursor_id,
+                record_reg,
+                unpacked_start: Some(key_reg),
+                unpacked_count: Some(2),
+                flags: IdxInsertFlags::new().use_seek(false),
+            });
+            program.resolve_label(label_already_matched, program.offset());
+        }
+
         // Set the match flag to true if this is a LEFT JOIN.
         // At this point of execution we are going to emit columns for the left table,
         // and either emit columns or NULLs for the right table, depending on whether the null_flag is set
@@ -546,6 +641,8 @@ pub fn open_loop(
                 });
             }
         }
+
+        emit_conditions(program, t_ctx, tables, &other_conditions, next)?;
     }
 
     Ok(())
@@ -553,11 +650,13 @@ pub fn open_loop(
 
 /// SQLite (and so Limbo) processes joins as a nested loop.
 /// The loop may emit rows to various destinations depending on the query:
+/// - a window sorter (the window functions are evaluated after the loop, see [super::window::emit_windows])
 /// - a GROUP BY sorter (grouping is done by sorting based on the GROUP BY keys and aggregating while the GROUP BY keys match)
 /// - an ORDER BY sorter (when there is no GROUP BY, but there is an ORDER BY)
 /// - an AggStep (the columns are collected for aggregation, which is finished later)
 /// - a QueryResult (there is none of the above, so the loop either emits a ResultRow, or if it's a subquery, yields to the parent query)
 enum LoopEmitTarget {
+    WindowSorter,
     GroupBySorter,
     OrderBySorter,
     AggStep,
@@ -571,6 +670,10 @@ pub fn emit_loop(
     t_ctx: &mut TranslateCtx,
     plan: &mut SelectPlan,
 ) -> Result<()> {
+    // if we have window functions, we emit a record into the sorter of the first window.
+    if !plan.windows.is_empty() {
+        return emit_loop_source(program, t_ctx, plan, LoopEmitTarget::WindowSorter);
+    }
     // if we have a group by, we emit a record into the group by sorter.
     if plan.group_by.is_some() {
         return emit_loop_source(program, t_ctx, plan, LoopEmitTarget::GroupBySorter);
@@ -598,6 +701,7 @@ fn emit_loop_source(
     emit_target: LoopEmitTarget,
 ) -> Result<()> {
     match emit_target {
+        LoopEmitTarget::WindowSorter => window_sorter_insert(program, t_ctx, plan, 0),
         LoopEmitTarget::GroupBySorter => {
             // This function creates a sorter for GROUP BY operations by allocating registers and
             // translating expressions for three types of columns:
@@ -623,11 +727,11 @@ fn emit_loop_source(
                 meta
             });
 
-            // Calculate the total number of arguments used across all aggregate functions
+            // Calculate the total number of arguments and filter conditions used across all aggregate functions
             let aggregate_arguments_count = plan
                 .aggregates
                 .iter()
-                .map(|agg| agg.args.len())
+                .map(|agg| agg.num_sorter_columns())
                 .sum::<usize>();
 
             // Calculate total number of registers needed for all columns in the sorter
@@ -672,7 +776,8 @@ fn emit_loop_source(
                 // For a query like: SELECT group_col, SUM(val1), AVG(val2) FROM table GROUP BY group_col
                 // we'll process val1 and val2 here, storing them in the sorter so they're available
                 // when computing the aggregates after sorting by group_col
-                for expr in agg.args.iter() {
+                // The filter condition is evaluated here too, and checked when the aggregates are computed.
+                for expr in agg.args.iter().chain(agg.filter.iter()) {
                     let agg_reg = cur_reg;
                     cur_reg += 1;
                     translate_expr(
@@ -700,8 +805,11 @@ fn emit_loop_source(
         LoopEmitTarget::OrderBySorter => order_by_sorter_insert(program, t_ctx, plan),
         LoopEmitTarget::AggStep => {
             let num_aggs = plan.aggregates.len();
-            let start_reg = program.alloc_registers(num_aggs);
-            t_ctx.reg_agg_start = Some(start_reg);
+            // The loop body is emitted again for the unmatched rows of a RIGHT JOIN, see [emit_unmatched_rows],
+            // and those rows go to the same aggregates.
+            let start_reg = *t_ctx
+                .reg_agg_start
+                .get_or_insert_with(|| program.alloc_registers(num_aggs));
 
             // In planner.rs, we have collected all aggregates from the SELECT clause, including ones where the aggregate is embedded inside
             // a more complex expression. Some examples: length(sum(x)), sum(x) + avg(y), sum(x) + 1, etc.
@@ -709,13 +817,67 @@ fn emit_loop_source(
             // Instead, we accumulate the intermediate results of all aggreagates, and evaluate any expressions that do not contain aggregates.
             for (i, agg) in plan.aggregates.iter().enumerate() {
                 let reg = start_reg + i;
-                translate_aggregation_step(
-                    program,
-                    &plan.table_references,
-                    agg,
-                    reg,
-                    &t_ctx.resolver,
-                )?;
+                // Skip the rows that do not satisfy the FILTER clause of the aggregate.
+                let label_skip = program.allocate_label();
+                if let Some(filter) = &agg.filter {
+                    let jump_target_when_true = program.allocate_label();
+                    translate_condition_expr(
+                        program,
+                        &plan.table_references,
+                        filter,
+                        ConditionMetadata {
+                            jump_if_condition_is_true: false,
+                            jump_target_when_true,
+                            jump_target_when_false: label_skip,
+                        },
+                        &t_ctx.resolver,
+                    )?;
+                    program.resolve_label(jump_target_when_true, program.offset());
+                }
+                match t_ctx.meta_distinct.agg_cursor_ids[i] {
+                    Some(cursor_id) => {
+                        // The argument is evaluated once, for both the DISTINCT check and the
+                        // aggregate, and keeps its collating sequence for min() and max().
+                        let reg_arg = program.alloc_register();
+                        translate_expr(
+                            program,
+                            Some(&plan.table_references),
+                            &agg.args[0],
+                            reg_arg,
+                            &t_ctx.resolver,
+                        )?;
+                        emit_distinct_check(program, cursor_id, &[reg_arg], label_skip);
+                        let arg =
+                            match expr_collation_name(&agg.args[0], Some(&plan.table_references)) {
+                                Some(collation) => ast::Expr::Collate(
+                                    Box::new(ast::Expr::Register(reg_arg)),
+                                    collation.to_string(),
+                                ),
+                                None => ast::Expr::Register(reg_arg),
+                            };
+                        let agg = Aggregate {
+                            args: vec![arg],
+                            ..agg.clone()
+                        };
+                        translate_aggregation_step(
+                            program,
+                            &plan.table_references,
+                            &agg,
+                            reg,
+                            &t_ctx.resolver,
+                        )?;
+                    }
+                    None => {
+                        translate_aggregation_step(
+                            program,
+                            &plan.table_references,
+                            agg,
+                            reg,
+                            &t_ctx.resolver,
+                        )?;
+                    }
+                }
+                program.resolve_label(label_skip, program.offset());
             }
 
             let label_emit_nonagg_only_once = if let Some(flag) = t_ctx.reg_nonagg_emit_once_flag {
@@ -788,6 +950,16 @@ pub fn close_loop(
     program: &mut ProgramBuilder,
     t_ctx: &mut TranslateCtx,
     tables: &[TableReference],
+) -> Result<()> {
+    close_loops_from(program, t_ctx, tables, 0)
+}
+
+/// Close the loops of the tables starting at `first_table_index`, see [close_loop].
+fn close_loops_from(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    tables: &[TableReference],
+    first_table_index: usize,
 ) -> Result<()> {
     // We close the loops for all tables in reverse order, i.e. innermost first.
     // OPEN t1
@@ -797,7 +969,7 @@ pub fn close_loop(
     //     CLOSE t3
     //   CLOSE t2
     // CLOSE t1
-    for (idx, table) in tables.iter().rev().enumerate() {
+    for (idx, table) in tables[first_table_index..].iter().rev().enumerate() {
         let table_index = tables.len() - idx - 1;
         let loop_labels = *t_ctx
             .labels_main_loop
@@ -807,14 +979,16 @@ pub fn close_loop(
         let (table_cursor_id, index_cursor_id) = table.resolve_cursors(program)?;
 
         match &table.op {
-            Operation::Subquery { .. } => {
+            Operation::Subquery { plan, .. } => {
                 program.resolve_label(loop_labels.next, program.offset());
                 // A subquery has no cursor to call Next on, so it just emits a Goto
                 // to the Yield instruction, which in turn jumps back to the main loop of the subquery,
                 // so that the next row from the subquery can be read.
-                program.emit_insn(Insn::Goto {
-                    target_pc: loop_labels.loop_start,
-                });
+                if !matches!(plan.query_type, SelectQueryType::RecursiveRow) {
+                    program.emit_insn(Insn::Goto {
+                        target_pc: loop_labels.loop_start,
+                    });
+                }
             }
             Operation::Scan { iter_dir, .. } => {
                 program.resolve_label(loop_labels.next, program.offset());
@@ -851,10 +1025,15 @@ pub fn close_loop(
                     table_cursor_id.expect("Either index or table cursor must be opened")
                 });
                 // Rowid equality point lookups are handled with a SeekRowid instruction which does not loop, so there is no need to emit a Next instruction.
-                if !matches!(search, Search::RowidEq { .. }) {
+                if let Search::Hash { .. } = search {
+                    program.emit_insn(Insn::HashNext {
+                        cursor_id: iteration_cursor_id,
+                        pc_if_next: loop_labels.loop_start,
+                    });
+                } else if !matches!(search, Search::RowidEq { .. }) {
                     let iter_dir = match search {
                         Search::Seek { seek_def, .. } => seek_def.iter_dir,
-                        Search::RowidEq { .. } => unreachable!(),
+                        Search::RowidEq { .. } | Search::Hash { .. } => unreachable!(),
                     };
 
                     if iter_dir == IterationDirection::Backwards {
@@ -896,14 +1075,7 @@ pub fn close_loop(
                 // First we set the right table cursor's "pseudo null bit" on, which means any Insn::Column will return NULL.
                 // This needs to be set for both the table and the index cursor, if present,
                 // since even if the iteration cursor is the index cursor, it might fetch values from the table cursor.
-                [table_cursor_id, index_cursor_id]
-                    .iter()
-                    .filter_map(|maybe_cursor_id| maybe_cursor_id.as_ref())
-                    .for_each(|cursor_id| {
-                        program.emit_insn(Insn::NullRow {
-                            cursor_id: *cursor_id,
-                        });
-                    });
+                emit_null_row(program, table, table_cursor_id, index_cursor_id);
                 // Then we jump to setting the left join match flag to 1 again,
                 // but this time the right table cursor will set everything to null.
                 // This leads to emitting a row with cols from the left + nulls from the right,
@@ -920,6 +1092,327 @@ pub fn close_loop(
     Ok(())
 }
 
+/// Emits the rows of the right table of a RIGHT or FULL OUTER JOIN that did not match any row of
+/// the tables to its left, with NULLs for the columns of those tables. This runs after the main loop:
+/// the right table is scanned again, the rows that were recorded as matched in the main loop are skipped,
+/// and the remaining rows are joined with the tables that come after the right table, if any.
+pub fn emit_unmatched_rows(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    plan: &mut SelectPlan,
+    table_index: usize,
+) -> Result<()> {
+    // The right table is scanned in rowid order through its table cursor, unless only an index
+    // cursor is open, so the index that is used in the main loop, if any, is set aside meanwhile.
+    // An automatic index always has a table cursor, which matters since it may not have been built.
+    let (table_cursor_id, _) = plan.table_references[table_index].resolve_cursors(program)?;
+    let table = &mut plan.table_references[table_index];
+    let original_op = if matches!(table.op, Operation::Subquery { .. }) || table_cursor_id.is_none()
+    {
+        None
+    } else {
+        Some(std::mem::replace(
+            &mut table.op,
+            Operation::Scan {
+                iter_dir: IterationDirection::Forwards,
+                index: None,
+            },
+        ))
+    };
+    let result = emit_unmatched_rows_scan(program, t_ctx, plan, table_index);
+    if let Some(original_op) = original_op {
+        plan.table_references[table_index].op = original_op;
+    }
+    result
+}
+
+fn emit_unmatched_rows_scan(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    plan: &mut SelectPlan,
+    table_index: usize,
+) -> Result<()> {
+    let tables = &plan.table_references;
+    let table = &tables[table_index];
+    let rj_meta = t_ctx.meta_right_joins[table_index]
+        .as_ref()
+        .expect("table is not the right table of a RIGHT JOIN");
+    let matched_cursor_id = rj_meta.matched_cursor_id;
+    let reg_row_number = rj_meta.reg_row_number;
+
+    // All columns of the tables to the left are NULL.
+    for left_table in &tables[..table_index] {
+        let (table_cursor_id, index_cursor_id) = left_table.resolve_cursors(program)?;
+        emit_null_row(program, left_table, table_cursor_id, index_cursor_id);
+    }
+
+    // The loops of the right table and of the tables after it are emitted a second time,
+    // so they need new labels.
+    for labels in t_ctx.labels_main_loop[table_index..].iter_mut() {
+        *labels = LoopLabels::new(program);
+    }
+    for lj_meta in t_ctx.meta_left_joins[table_index + 1..]
+        .iter_mut()
+        .flatten()
+    {
+        lj_meta.label_match_flag_set_true = program.allocate_label();
+        lj_meta.label_match_flag_check_value = program.allocate_label();
+    }
+    let LoopLabels {
+        loop_start,
+        next,
+        loop_end,
+    } = t_ctx.labels_main_loop[table_index];
+
+    // Scan all rows of the right table, regardless of how it is accessed in the main loop.
+    let (table_cursor_id, index_cursor_id) = table.resolve_cursors(program)?;
+    let mut iteration_cursor_id = None;
+    match &table.op {
+        Operation::Subquery { plan, .. } => {
+            if let Some(reg_row_number) = reg_row_number {
+                program.emit_insn(Insn::Integer {
+                    value: 0,
+                    dest: reg_row_number,
+                });
+            }
+            match &plan.query_type {
+                SelectQueryType::Subquery {
+                    yield_reg,
+                    coroutine_implementation_start,
+                } => {
+                    program.emit_insn(Insn::InitCoroutine {
+                        yield_reg: *yield_reg,
+                        jump_on_definition: BranchOffset::Offset(0),
+                        start_offset: *coroutine_implementation_start,
+                    });
+                    program.resolve_label(loop_start, program.offset());
+                    program.emit_insn(Insn::Yield {
+                        yield_reg: *yield_reg,
+                        end_offset: loop_end,
+                    });
+                    if let Some(reg_row_number) = reg_row_number {
+                        emit_increment(program, reg_row_number);
+                    }
+                }
+                SelectQueryType::RecursiveRow => {
+                    program.resolve_label(loop_start, program.offset());
+                }
+                _ => unreachable!("Subquery operator with non-subquery query type"),
+            }
+        }
+        Operation::Scan { .. } | Operation::Search(_) => {
+            let cursor_id = index_cursor_id.unwrap_or_else(|| {
+                table_cursor_id.expect("Either index or table cursor must be opened")
+            });
+            program.emit_insn(Insn::Rewind {
+                cursor_id,
+                pc_if_empty: loop_end,
+            });
+            program.resolve_label(loop_start, program.offset());
+            iteration_cursor_id = Some(cursor_id);
+        }
+    }
+
+    // Skip the rows that matched in the main loop.
+    let key_reg = emit_right_join_key(program, t_ctx, tables, table_index)?;
+    let label_not_matched = program.allocate_label();
+    program.emit_insn(Insn::NotFound {
+        cursor_id: matched_cursor_id,
+        target_pc: label_not_matched,
+        record_reg: key_reg,
+        num_regs: 2,
+    });
+    program.emit_insn(Insn::Goto { target_pc: next });
+    program.resolve_label(label_not_matched, program.offset());
+
+    // The ON conditions of the right join are not evaluated, since no row of the left tables matched,
+    // but the WHERE clause still applies.
+    let conditions = plan
+        .where_clause
+        .iter()
+        .enumerate()
+        .filter(|(i, cond)| {
+            cond.should_eval_at_loop(table_index)
+                && cond.from_join != Some(table_index)
+                && !t_ctx.omit_predicates.contains(i)
+        })
+        .map(|(_, cond)| cond)
+        .collect::<Vec<_>>();
+    emit_conditions(program, t_ctx, tables, &conditions, next)?;
+
+    open_loops_from(
+        program,
+        t_ctx,
+        &plan.table_references,
+        &plan.where_clause,
+        table_index + 1,
+    )?;
+    emit_loop(program, t_ctx, plan)?;
+    close_loops_from(program, t_ctx, &plan.table_references, table_index + 1)?;
+
+    program.resolve_label(next, program.offset());
+    match iteration_cursor_id {
+        Some(cursor_id) => {
+            program.emit_insn(Insn::Next {
+                cursor_id,
+                pc_if_next: loop_start,
+            });
+        }
+        None => {
+            if !matches!(
+                &plan.table_references[table_index].op,
+                Operation::Subquery { plan, .. } if matches!(plan.query_type, SelectQueryType::RecursiveRow)
+            ) {
+                program.emit_insn(Insn::Goto {
+                    target_pc: loop_start,
+                });
+            }
+        }
+    }
+    program.resolve_label(loop_end, program.offset());
+    Ok(())
+}
+
+/// Makes all columns of the current row of a table NULL.
+/// A subquery has no cursor, its columns are read from its result registers, so those are set to NULL instead.
+fn emit_null_row(
+    program: &mut ProgramBuilder,
+    table: &TableReference,
+    table_cursor_id: Option<CursorID>,
+    index_cursor_id: Option<CursorID>,
+) {
+    if let Operation::Subquery {
+        plan,
+        result_columns_start_reg,
+    } = &table.op
+    {
+        program.emit_insn(Insn::Null {
+            dest: *result_columns_start_reg,
+            dest_end: Some(result_columns_start_reg + plan.result_columns.len() - 1),
+        });
+        return;
+    }
+    [table_cursor_id, index_cursor_id]
+        .iter()
+        .filter_map(|maybe_cursor_id| maybe_cursor_id.as_ref())
+        .for_each(|cursor_id| {
+            program.emit_insn(Insn::NullRow {
+                cursor_id: *cursor_id,
+            });
+        });
+}
+
+/// Emits the given conditions, jumping to `jump_target_when_false` as soon as one of them is false.
+fn emit_conditions(
+    program: &mut ProgramBuilder,
+    t_ctx: &TranslateCtx,
+    tables: &[TableReference],
+    conditions: &[&WhereTerm],
+    jump_target_when_false: BranchOffset,
+) -> Result<()> {
+    for cond in conditions {
+        let jump_target_when_true = program.allocate_label();
+        let condition_metadata = ConditionMetadata {
+            jump_if_condition_is_true: false,
+            jump_target_when_true,
+            jump_target_when_false,
+        };
+        translate_condition_expr(
+            program,
+            tables,
+            &cond.expr,
+            condition_metadata,
+            &t_ctx.resolver,
+        )?;
+        program.resolve_label(jump_target_when_true, program.offset());
+    }
+    Ok(())
+}
+
+/// Emits the key of the current row of the right table of a RIGHT or FULL OUTER JOIN in the index
+/// of matched rows, i.e. the rowid (or the position of the row in a subquery) followed by a constant 0,
+/// since index cursors expect every key to end in an integer rowid.
+/// Returns the first of the two registers of the key.
+fn emit_right_join_key(
+    program: &mut ProgramBuilder,
+    t_ctx: &TranslateCtx,
+    tables: &[TableReference],
+    table_index: usize,
+) -> Result<usize> {
+    let key_reg = program.alloc_registers(2);
+    let rj_meta = t_ctx.meta_right_joins[table_index]
+        .as_ref()
+        .expect("table is not the right table of a RIGHT JOIN");
+    match rj_meta.reg_row_number {
+        Some(reg_row_number) => {
+            program.emit_insn(Insn::Copy {
+                src_reg: reg_row_number,
+                dst_reg: key_reg,
+                amount: 0,
+            });
+        }
+        None => {
+            translate_expr(
+                program,
+                Some(tables),
+                &ast::Expr::RowId {
+                    database: None,
+                    table: table_index,
+                },
+                key_reg,
+                &t_ctx.resolver,
+            )?;
+        }
+    }
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: key_reg + 1,
+    });
+    Ok(key_reg)
+}
+
+/// Adds 1 to the integer in the given register.
+fn emit_increment(program: &mut ProgramBuilder, reg: usize) {
+    let reg_one = program.alloc_register();
+    program.emit_insn(Insn::Integer {
+        value: 1,
+        dest: reg_one,
+    });
+    program.emit_insn(Insn::Add {
+        lhs: reg,
+        rhs: reg_one,
+        dest: reg,
+    });
+}
+
+/// Open the ephemeral index that holds the keys of the matched rows of the right table of a RIGHT
+/// or FULL OUTER JOIN, see [emit_right_join_key].
+/// Opening the index again empties it, so it is reset whenever the query runs again, e.g. as a subquery.
+fn emit_open_matched_index(program: &mut ProgramBuilder) -> CursorID {
+    let index = Arc::new(Index {
+        name: "ephemeral_right_join".to_string(),
+        table_name: String::new(),
+        root_page: 0,
+        columns: vec![IndexColumn {
+            name: "key".to_string(),
+            order: SortOrder::Asc,
+            pos_in_table: 0,
+            collation: None,
+            expr: None,
+        }],
+        unique: false,
+        ephemeral: true,
+        where_clause: None,
+        key_suffix: Vec::new(),
+    });
+    let cursor_id = program.alloc_cursor_id(None, CursorType::BTreeIndex(index));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id,
+        is_table: false,
+    });
+    cursor_id
+}
+
 /// Emits instructions for an index seek. See e.g. [crate::translate::plan::SeekDef]
 /// for more details about the seek definition.
 ///
@@ -1157,6 +1650,48 @@ fn emit_seek_termination(
     Ok(())
 }
 
+/// Positions the table cursor on the row of the entry at the index cursor.
+/// The seek in a table with rowids is deferred until it's actually necessary to read from the
+/// table. A WITHOUT ROWID table is searched for the PRIMARY KEY at the end of the entry instead,
+/// unless the index is its PRIMARY KEY index, whose cursor is the table cursor.
+fn emit_seek_table_row(
+    program: &mut ProgramBuilder,
+    table: &TableReference,
+    index_cursor_id: CursorID,
+    table_cursor_id: CursorID,
+    next: BranchOffset,
+) {
+    if index_cursor_id == table_cursor_id {
+        return;
+    }
+    let (Some(btree), Some(index)) = (table.btree(), table.op.index()) else {
+        return;
+    };
+    // An ephemeral index has all the columns the query reads
+    if btree.has_rowid || index.ephemeral {
+        program.emit_insn(Insn::DeferredSeek {
+            index_cursor_id,
+            table_cursor_id,
+        });
+        return;
+    }
+    let positions = index.primary_key_entry_positions(&btree);
+    let key_reg = program.alloc_registers(positions.len());
+    for (i, position) in positions.iter().enumerate() {
+        program.emit_insn(Insn::Column {
+            cursor_id: index_cursor_id,
+            column: *position,
+            dest: key_reg + i,
+        });
+    }
+    program.emit_insn(Insn::NotFound {
+        cursor_id: table_cursor_id,
+        target_pc: next,
+        record_reg: key_reg,
+        num_regs: positions.len(),
+    });
+}
+
 /// Open an ephemeral index cursor and build an automatic index on a table.
 /// This is used as a last-resort to avoid a nested full table scan
 /// Returns the cursor id of the ephemeral index cursor.
@@ -1168,14 +1703,76 @@ fn emit_autoindex(
     table_has_rowid: bool,
 ) -> Result<CursorID> {
     assert!(index.ephemeral, "Index {} is not ephemeral", index.name);
+    emit_ephemeral_build(
+        program,
+        index,
+        table_cursor_id,
+        table_has_rowid,
+        Insn::OpenAutoindex {
+            cursor_id: index_cursor_id,
+        },
+        |record_reg, start_reg, count| Insn::IdxInsert {
+            cursor_id: index_cursor_id,
+            record_reg,
+            unpacked_start: Some(start_reg),
+            unpacked_count: Some(count as u16),
+            flags: IdxInsertFlags::new().use_seek(false),
+        },
+    );
+    Ok(index_cursor_id)
+}
+
+/// Open a hash table cursor and build the hash table of a hash join from the rows of a table.
+/// Its entries are laid out like the ones of `index`, and their first `key_count` values are the
+/// key.
+fn emit_hash_table(
+    program: &mut ProgramBuilder,
+    t_ctx: &TranslateCtx,
+    index: &Arc<Index>,
+    key_count: usize,
+    table_cursor_id: CursorID,
+    hash_cursor_id: CursorID,
+    table_has_rowid: bool,
+) -> Result<()> {
+    let collations = index.columns[..key_count]
+        .iter()
+        .map(|column| resolve_collation(column.collation.as_deref(), t_ctx.resolver.symbol_table))
+        .collect::<Result<Vec<_>>>()?;
+    emit_ephemeral_build(
+        program,
+        index,
+        table_cursor_id,
+        table_has_rowid,
+        Insn::HashOpen {
+            cursor_id: hash_cursor_id,
+            key_count,
+            collations,
+        },
+        |record_reg, _, _| Insn::HashInsert {
+            cursor_id: hash_cursor_id,
+            record_reg,
+        },
+    );
+    Ok(())
+}
+
+/// Emit the instructions that open an ephemeral structure with `open_insn`, then add a record to
+/// it with the instruction built by `insert_insn` for each row of a table. The records have the
+/// columns of the ephemeral `index`, followed by the rowid if the table has one.
+/// Since this typically happens in an inner loop, it is only done once.
+fn emit_ephemeral_build(
+    program: &mut ProgramBuilder,
+    index: &Index,
+    table_cursor_id: CursorID,
+    table_has_rowid: bool,
+    open_insn: Insn,
+    insert_insn: impl FnOnce(usize, usize, usize) -> Insn,
+) {
     let label_ephemeral_build_end = program.allocate_label();
-    // Since this typically happens in an inner loop, we only build it once.
     program.emit_insn(Insn::Once {
         target_pc_when_reentered: label_ephemeral_build_end,
     });
-    program.emit_insn(Insn::OpenAutoindex {
-        cursor_id: index_cursor_id,
-    });
+    program.emit_insn(open_insn);
     // Rewind source table
     program.emit_insn(Insn::Rewind {
         cursor_id: table_cursor_id,
@@ -1206,17 +1803,14 @@ fn emit_autoindex(
         count: num_regs_to_reserve,
         dest_reg: record_reg,
     });
-    program.emit_insn(Insn::IdxInsert {
-        cursor_id: index_cursor_id,
+    program.emit_insn(insert_insn(
         record_reg,
-        unpacked_start: Some(ephemeral_cols_start_reg),
-        unpacked_count: Some(num_regs_to_reserve as u16),
-        flags: IdxInsertFlags::new().use_seek(false),
-    });
+        ephemeral_cols_start_reg,
+        num_regs_to_reserve,
+    ));
     program.emit_insn(Insn::Next {
         cursor_id: table_cursor_id,
         pc_if_next: offset_ephemeral_build_loop_start,
     });
     program.resolve_label(label_ephemeral_build_end, program.offset());
-    Ok(index_cursor_id)
 }
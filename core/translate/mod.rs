@@ -8,9 +8,17 @@
 //! will read rows from the database and filter them according to a WHERE clause.
 
 pub(crate) mod aggregation;
+pub(crate) mod alter;
+pub(crate) mod analyze;
+pub(crate) mod attach;
+pub(crate) mod collate;
+pub(crate) mod compound_select;
+pub(crate) mod constraint;
 pub(crate) mod delete;
+pub(crate) mod distinct;
 pub(crate) mod emitter;
 pub(crate) mod expr;
+pub(crate) mod foreign_key;
 pub(crate) mod group_by;
 pub(crate) mod index;
 pub(crate) mod insert;
@@ -20,35 +28,54 @@ pub(crate) mod order_by;
 pub(crate) mod plan;
 pub(crate) mod planner;
 pub(crate) mod pragma;
+pub(crate) mod recursive_cte;
 pub(crate) mod result_row;
+pub(crate) mod returning;
 pub(crate) mod schema;
 pub(crate) mod select;
 pub(crate) mod subquery;
 pub(crate) mod transaction;
+pub(crate) mod trigger;
 pub(crate) mod update;
+pub(crate) mod vacuum;
+pub(crate) mod window;
 
 use crate::fast_lock::SpinLock;
 use crate::schema::Schema;
 use crate::storage::pager::Pager;
 use crate::storage::sqlite3_ondisk::DatabaseHeader;
 use crate::translate::delete::translate_delete;
+use crate::util::normalize_ident;
 use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, QueryMode};
 use crate::vdbe::Program;
 use crate::{bail_parse_error, Connection, Result, SymbolTable};
-use index::translate_create_index;
+use alter::translate_alter_table;
+use analyze::translate_analyze;
+use attach::{translate_attach, translate_detach};
+use index::{translate_create_index, translate_reindex};
 use insert::translate_insert;
 use limbo_sqlite3_parser::ast::{self, Delete, Insert};
-use schema::{translate_create_table, translate_create_virtual_table, translate_drop_table};
+use schema::{
+    translate_create_table, translate_create_trigger, translate_create_view,
+    translate_create_virtual_table, translate_drop_index, translate_drop_table,
+    translate_drop_trigger, translate_drop_view,
+};
 use select::translate_select;
 use std::rc::{Rc, Weak};
 use std::sync::Arc;
-use transaction::{translate_tx_begin, translate_tx_commit};
+use transaction::{
+    translate_release, translate_savepoint, translate_tx_begin, translate_tx_commit,
+    translate_tx_rollback,
+};
 use update::translate_update;
+use vacuum::translate_vacuum;
 
-/// Translate SQL statement into bytecode program.
+/// Translate SQL statement into bytecode program. `input` is the text `stmt` was parsed from.
+#[allow(clippy::too_many_arguments)]
 pub fn translate(
     schema: &Schema,
     stmt: ast::Stmt,
+    input: &str,
     database_header: Arc<SpinLock<DatabaseHeader>>,
     pager: Rc<Pager>,
     connection: Weak<Connection>,
@@ -56,11 +83,17 @@ pub fn translate(
     query_mode: QueryMode,
 ) -> Result<Program> {
     let mut change_cnt_on = false;
+    check_schema_change_database(&stmt)?;
 
     let program = match stmt {
-        ast::Stmt::AlterTable(_) => bail_parse_error!("ALTER TABLE not supported yet"),
-        ast::Stmt::Analyze(_) => bail_parse_error!("ANALYZE not supported yet"),
-        ast::Stmt::Attach { .. } => bail_parse_error!("ATTACH not supported yet"),
+        ast::Stmt::AlterTable(alter) => {
+            let (tbl_name, body) = *alter;
+            translate_alter_table(query_mode, tbl_name, body, schema, syms)?
+        }
+        ast::Stmt::Analyze(name) => translate_analyze(query_mode, schema, name.as_ref(), syms)?,
+        ast::Stmt::Attach { expr, db_name, key } => {
+            translate_attach(query_mode, &expr, &db_name, key.as_deref(), syms)?
+        }
         ast::Stmt::Begin(tx_type, tx_name) => translate_tx_begin(tx_type, tx_name)?,
         ast::Stmt::Commit(tx_name) => translate_tx_commit(tx_name)?,
         ast::Stmt::CreateIndex {
@@ -69,16 +102,26 @@ pub fn translate(
             idx_name,
             tbl_name,
             columns,
-            ..
+            where_clause,
         } => {
             change_cnt_on = true;
+            // An unqualified index goes in the database of its table
+            let database = schema_change_database(
+                schema,
+                idx_name.db_name.as_ref(),
+                &tbl_name.0,
+                |schema, name| schema.get_table(name).is_some(),
+            )?;
+            let schema = &schema.database_schema(database)?;
             translate_create_index(
                 query_mode,
                 (unique, if_not_exists),
                 &idx_name.name.0,
                 &tbl_name.0,
                 &columns,
+                where_clause.as_deref(),
                 schema,
+                syms,
             )?
         }
         ast::Stmt::CreateTable {
@@ -86,16 +129,42 @@ pub fn translate(
             if_not_exists,
             tbl_name,
             body,
-        } => translate_create_table(
+        } => {
+            let database = match &tbl_name.db_name {
+                Some(db_name) if temporary && normalize_ident(&db_name.0) != "temp" => {
+                    bail_parse_error!("temporary table name must be unqualified")
+                }
+                _ if temporary => 1,
+                Some(db_name) => schema.database_index(&db_name.0)?,
+                None => schema.database,
+            };
+            let schema = &schema.database_schema(database)?;
+            translate_create_table(
+                query_mode,
+                tbl_name,
+                *body,
+                if_not_exists,
+                input,
+                schema,
+                syms,
+            )?
+        }
+        ast::Stmt::CreateTrigger(create) => translate_create_trigger(query_mode, *create, schema)?,
+        ast::Stmt::CreateView {
+            temporary,
+            if_not_exists,
+            view_name,
+            columns,
+            select,
+        } => translate_create_view(
             query_mode,
-            tbl_name,
+            view_name,
             temporary,
-            *body,
             if_not_exists,
+            columns,
+            *select,
             schema,
         )?,
-        ast::Stmt::CreateTrigger { .. } => bail_parse_error!("CREATE TRIGGER not supported yet"),
-        ast::Stmt::CreateView { .. } => bail_parse_error!("CREATE VIEW not supported yet"),
         ast::Stmt::CreateVirtualTable(vtab) => {
             translate_create_virtual_table(*vtab, schema, query_mode)?
         }
@@ -104,19 +173,55 @@ pub fn translate(
                 tbl_name,
                 where_clause,
                 limit,
+                returning,
                 ..
             } = *delete;
             change_cnt_on = true;
-            translate_delete(query_mode, schema, &tbl_name, where_clause, limit, syms)?
+            translate_delete(
+                query_mode,
+                schema,
+                &tbl_name,
+                where_clause,
+                limit,
+                &returning,
+                syms,
+            )?
+        }
+        ast::Stmt::Detach(db_name) => translate_detach(query_mode, &db_name, syms)?,
+        ast::Stmt::DropIndex {
+            if_exists,
+            idx_name,
+        } => {
+            let database = schema_change_database(
+                schema,
+                idx_name.db_name.as_ref(),
+                &idx_name.name.0,
+                |schema, name| schema.get_index(name).is_some(),
+            )?;
+            let schema = &schema.database_schema(database)?;
+            translate_drop_index(query_mode, idx_name, if_exists, schema)?
         }
-        ast::Stmt::Detach(_) => bail_parse_error!("DETACH not supported yet"),
-        ast::Stmt::DropIndex { .. } => bail_parse_error!("DROP INDEX not supported yet"),
         ast::Stmt::DropTable {
             if_exists,
             tbl_name,
-        } => translate_drop_table(query_mode, tbl_name, if_exists, schema)?,
-        ast::Stmt::DropTrigger { .. } => bail_parse_error!("DROP TRIGGER not supported yet"),
-        ast::Stmt::DropView { .. } => bail_parse_error!("DROP VIEW not supported yet"),
+        } => {
+            let database = schema_change_database(
+                schema,
+                tbl_name.db_name.as_ref(),
+                &tbl_name.name.0,
+                |schema, name| schema.get_table(name).is_some(),
+            )?;
+            let schema = &schema.database_schema(database)?;
+            translate_drop_table(query_mode, tbl_name, if_exists, schema, syms)?
+        }
+        ast::Stmt::DropTrigger {
+            if_exists,
+            trigger_name,
+        } => translate_drop_trigger(query_mode, trigger_name, if_exists, schema)?,
+        ast::Stmt::DropView {
+            if_exists,
+            view_name,
+        } => translate_drop_view(query_mode, view_name, if_exists, schema, syms)?,
         ast::Stmt::Pragma(name, body) => pragma::translate_pragma(
             query_mode,
             schema,
@@ -124,14 +229,24 @@ pub fn translate(
             body.map(|b| *b),
             database_header.clone(),
             pager,
+            &connection,
+            syms,
         )?,
-        ast::Stmt::Reindex { .. } => bail_parse_error!("REINDEX not supported yet"),
-        ast::Stmt::Release(_) => bail_parse_error!("RELEASE not supported yet"),
-        ast::Stmt::Rollback { .. } => bail_parse_error!("ROLLBACK not supported yet"),
-        ast::Stmt::Savepoint(_) => bail_parse_error!("SAVEPOINT not supported yet"),
+        ast::Stmt::Reindex { obj_name } => translate_reindex(query_mode, obj_name, schema, syms)?,
+        ast::Stmt::Release(name) => translate_release(name)?,
+        ast::Stmt::Rollback {
+            tx_name,
+            savepoint_name,
+        } => translate_tx_rollback(tx_name, savepoint_name)?,
+        ast::Stmt::Savepoint(name) => translate_savepoint(name)?,
         ast::Stmt::Select(select) => translate_select(query_mode, schema, *select, syms)?,
-        ast::Stmt::Update(mut update) => translate_update(query_mode, schema, &mut update, syms)?,
-        ast::Stmt::Vacuum(_, _) => bail_parse_error!("VACUUM not supported yet"),
+        ast::Stmt::Update(mut update) => {
+            change_cnt_on = true;
+            translate_update(query_mode, schema, &mut update, syms)?
+        }
+        ast::Stmt::Vacuum(db_name, into) => {
+            translate_vacuum(query_mode, schema, db_name.as_ref(), into.as_deref(), syms)?
+        }
         ast::Stmt::Insert(insert) => {
             let Insert {
                 with,
@@ -158,3 +273,49 @@ pub fn translate(
 
     Ok(program.build(database_header, connection, change_cnt_on))
 }
+
+/// The database a statement that changes the schema applies to: the database `db_name`, or else
+/// the first database of [Schema::search_order] that has the object `name` according to
+/// `exists`, or the main database if none has it.
+fn schema_change_database(
+    schema: &Schema,
+    db_name: Option<&ast::Name>,
+    name: &str,
+    exists: impl Fn(&Schema, &str) -> bool,
+) -> Result<usize> {
+    if let Some(db_name) = db_name {
+        return schema.database_index(&db_name.0);
+    }
+    for database in schema.search_order() {
+        if exists(&*schema.database_schema(database)?, name) {
+            return Ok(database);
+        }
+    }
+    Ok(schema.database)
+}
+
+/// Fails for a statement that changes the schema of a database other than the main database,
+/// which is only supported for tables and indexes.
+fn check_schema_change_database(stmt: &ast::Stmt) -> Result<()> {
+    let name = match stmt {
+        ast::Stmt::AlterTable(alter) => Some(&alter.0),
+        ast::Stmt::Analyze(name) => name.as_ref(),
+        ast::Stmt::CreateTrigger(create) => Some(&create.trigger_name),
+        ast::Stmt::CreateView { view_name, .. } => Some(view_name),
+        ast::Stmt::CreateVirtualTable(vtab) => Some(&vtab.tbl_name),
+        ast::Stmt::DropTrigger { trigger_name, .. } => Some(trigger_name),
+        ast::Stmt::DropView { view_name, .. } => Some(view_name),
+        ast::Stmt::Reindex { obj_name } => obj_name.as_ref(),
+        _ => None,
+    };
+    if let Some(db_name) = name.and_then(|name| name.db_name.as_ref()) {
+        let db_name = normalize_ident(&db_name.0);
+        if db_name != "main" {
+            bail_parse_error!(
+                "changing the schema of database {} is not supported yet",
+                db_name
+            );
+        }
+    }
+    Ok(())
+}
@@ -1,21 +1,32 @@
-use std::{cmp::Ordering, collections::HashMap, sync::Arc};
+use std::{cmp::Ordering, sync::Arc};
 
 use limbo_sqlite3_parser::ast::{self, Expr, SortOrder};
 
 use crate::{
-    schema::{Index, IndexColumn, Schema},
+    collation::CollationSeq,
+    schema::{Affinity, BTreeTable, Index, IndexColumn, Schema, TableStats},
     translate::plan::TerminationKey,
     types::SeekOp,
-    util::exprs_are_equivalent,
+    util::{exprs_are_equivalent, normalize_ident},
     Result,
 };
 
 use super::{
+    collate::{
+        binary_compare_collation_name, expr_collation_name, same_collation,
+        subquery_compare_collation_name,
+    },
+    expr::{comparison_affinity, expr_affinity},
     plan::{
-        DeletePlan, Direction, EvalAt, GroupBy, IterationDirection, Operation, Plan, Search,
-        SeekDef, SeekKey, SelectPlan, TableReference, UpdatePlan, WhereTerm,
+        DeletePlan, Direction, EvalAt, ExprSubquery, GroupBy, IterationDirection, JoinInfo,
+        Operation, Plan, Search, SeekDef, SeekKey, SelectPlan, TableReference, UpdatePlan,
+        WhereTerm,
+    },
+    planner::{
+        break_predicate_at_and_boundaries, defer_conditions_past_right_joins,
+        determine_where_to_eval_expr,
     },
-    planner::determine_where_to_eval_expr,
+    window::{child_exprs, child_exprs_mut},
 };
 
 pub fn optimize_plan(plan: &mut Plan, schema: &Schema) -> Result<()> {
@@ -32,8 +43,17 @@ pub fn optimize_plan(plan: &mut Plan, schema: &Schema) -> Result<()> {
  * but having them separate makes them easier to understand
  */
 fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
+    for (_, member) in plan.compounds.iter_mut() {
+        optimize_select_plan(member, schema)?;
+    }
+    if let Some(recursive) = plan.recursive.as_mut() {
+        for member in recursive.members.iter_mut() {
+            optimize_select_plan(member, schema)?;
+        }
+    }
     optimize_subqueries(plan, schema)?;
     rewrite_exprs_select(plan)?;
+    resolve_in_subquery_comparisons_select(plan);
     if let ConstantConditionEliminationResult::ImpossibleCondition =
         eliminate_constant_conditions(&mut plan.where_clause)?
     {
@@ -41,9 +61,13 @@ fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
         return Ok(());
     }
 
+    let mut available_indexes = table_indexes(&plan.table_references, schema)?;
+    let mut stats = table_stats(&plan.table_references, schema)?;
+    reorder_joins(plan, &mut available_indexes, &mut stats)?;
     use_indexes(
         &mut plan.table_references,
-        &schema.indexes,
+        &available_indexes,
+        &stats,
         &mut plan.where_clause,
         &mut plan.order_by,
         &plan.group_by,
@@ -55,7 +79,17 @@ fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
 }
 
 fn optimize_delete_plan(plan: &mut DeletePlan, schema: &Schema) -> Result<()> {
+    for subquery in plan.expr_subqueries.iter_mut() {
+        optimize_select_plan(&mut subquery.plan, schema)?;
+    }
     rewrite_exprs_delete(plan)?;
+    for cond in plan.where_clause.iter() {
+        resolve_in_subquery_comparisons(
+            &cond.expr,
+            &plan.table_references,
+            &mut plan.expr_subqueries,
+        );
+    }
     if let ConstantConditionEliminationResult::ImpossibleCondition =
         eliminate_constant_conditions(&mut plan.where_clause)?
     {
@@ -63,9 +97,19 @@ fn optimize_delete_plan(plan: &mut DeletePlan, schema: &Schema) -> Result<()> {
         return Ok(());
     }
 
+    // The indexes of the table lose the entries of the deleted rows, so the loop cannot walk one,
+    // except the PRIMARY KEY index of a WITHOUT ROWID table, which is the table itself
+    let table_reference = &plan.table_references[0];
+    let available_indexes = vec![table_indexes(&plan.table_references, schema)?[0]
+        .iter()
+        .filter(|index| table_reference.is_primary_key_index(index))
+        .cloned()
+        .collect()];
+    let stats = table_stats(&plan.table_references, schema)?;
     use_indexes(
         &mut plan.table_references,
-        &schema.indexes,
+        &available_indexes,
+        &stats,
         &mut plan.where_clause,
         &mut plan.order_by,
         &None,
@@ -75,16 +119,46 @@ fn optimize_delete_plan(plan: &mut DeletePlan, schema: &Schema) -> Result<()> {
 }
 
 fn optimize_update_plan(plan: &mut UpdatePlan, schema: &Schema) -> Result<()> {
+    for subquery in plan.expr_subqueries.iter_mut() {
+        optimize_select_plan(&mut subquery.plan, schema)?;
+    }
     rewrite_exprs_update(plan)?;
+    for expr in plan
+        .set_clauses
+        .iter()
+        .map(|(_, expr)| expr)
+        .chain(plan.where_clause.iter().map(|cond| &cond.expr))
+    {
+        resolve_in_subquery_comparisons(expr, &plan.table_references, &mut plan.expr_subqueries);
+    }
     if let ConstantConditionEliminationResult::ImpossibleCondition =
         eliminate_constant_conditions(&mut plan.where_clause)?
     {
         plan.contains_constant_false_condition = true;
         return Ok(());
     }
+    // The loop cannot walk an index whose entries the update rewrites. With REPLACE, the update
+    // may delete other rows and their entries in all the indexes, but the PRIMARY KEY index of a
+    // WITHOUT ROWID table is walked like the table itself. An update that moves rows writes them
+    // after the loop, see [UpdatePlan::moves_rows].
+    let replaces = plan.or_conflict == Some(ast::ResolveType::Replace);
+    let updated_table = plan.table_references[0].btree();
+    let mut available_indexes = table_indexes(&plan.table_references, schema)?;
+    if !plan.moves_rows() {
+        available_indexes[0].retain(|index| {
+            !((replaces && !plan.table_references[0].is_primary_key_index(index))
+                || updated_table.as_ref().is_some_and(|table| {
+                    plan.set_clauses
+                        .iter()
+                        .any(|(position, _)| index.depends_on_column(table, *position))
+                }))
+        });
+    }
+    let stats = table_stats(&plan.table_references, schema)?;
     use_indexes(
         &mut plan.table_references,
-        &schema.indexes,
+        &available_indexes,
+        &stats,
         &mut plan.where_clause,
         &mut plan.order_by,
         &None,
@@ -92,16 +166,574 @@ fn optimize_update_plan(plan: &mut UpdatePlan, schema: &Schema) -> Result<()> {
     Ok(())
 }
 
+/// The indexes of each of the tables, in the database of the table.
+fn table_indexes(
+    table_references: &[TableReference],
+    schema: &Schema,
+) -> Result<Vec<Vec<Arc<Index>>>> {
+    table_references
+        .iter()
+        .map(|table_reference| match table_reference.btree() {
+            Some(table) => Ok(schema
+                .database_schema(table_reference.database)?
+                .get_indices(&table.name)
+                .to_vec()),
+            None => Ok(vec![]),
+        })
+        .collect()
+}
+
+/// The statistics of each of the tables that ANALYZE gathered, in the database of the table.
+fn table_stats(
+    table_references: &[TableReference],
+    schema: &Schema,
+) -> Result<Vec<Option<TableStats>>> {
+    table_references
+        .iter()
+        .map(|table_reference| match table_reference.btree() {
+            Some(table) => Ok(schema
+                .database_schema(table_reference.database)?
+                .get_table_stats(&table.name)
+                .cloned()),
+            None => Ok(None),
+        })
+        .collect()
+}
+
+/// The number of tables of a join up to which the best order of them is searched for, see
+/// [search_join_order]. The tables of larger joins are ordered greedily.
+const MAX_SEARCHED_JOIN_TABLES: usize = 12;
+
+/// A comparison in the WHERE clause between a column of a table and an expression that does not
+/// refer to that table, which may search the table once the tables of the expression are in
+/// outer loops.
+struct TableConstraint {
+    table_index: usize,
+    /// The column of the table, or None for its rowid.
+    column: Option<usize>,
+    constraint: IndexConstraint,
+    /// The tables that the other side of the comparison refers to, as a bitmask of their indexes.
+    other_tables: u64,
+}
+
+/// What the costs of the orders of a join are estimated from.
+struct JoinOrderContext<'a> {
+    table_references: &'a [TableReference],
+    available_indexes: &'a [Vec<Arc<Index>>],
+    stats: &'a [Option<TableStats>],
+    constraints: Vec<TableConstraint>,
+}
+
+/// Chooses the order of the loops of a join with the lowest estimated cost, and moves the tables
+/// of the plan into that order.
+///
+/// Only the tables of inner joins move. A table that is the right side of an OUTER or CROSS
+/// JOIN, a subquery or a virtual table keeps its position, and no table moves across it.
+fn reorder_joins(
+    plan: &mut SelectPlan,
+    available_indexes: &mut Vec<Vec<Arc<Index>>>,
+    stats: &mut Vec<Option<TableStats>>,
+) -> Result<()> {
+    let table_count = plan.table_references.len();
+    if table_count < 2 || table_count > u64::BITS as usize {
+        return Ok(());
+    }
+    let ctx = JoinOrderContext {
+        table_references: &plan.table_references,
+        available_indexes,
+        stats,
+        constraints: table_constraints(&plan.where_clause),
+    };
+    let mut order = Vec::with_capacity(table_count);
+    let mut available = 0u64;
+    let mut loop_count = 1.0;
+    let mut start = 0;
+    while start < table_count {
+        if is_fixed_in_join_order(&ctx.table_references[start]) {
+            let (_, rows) = estimate_table_access(&ctx, start, available, loop_count);
+            order.push(start);
+            available |= 1 << start;
+            loop_count *= rows;
+            start += 1;
+            continue;
+        }
+        let end = (start..table_count)
+            .find(|&i| is_fixed_in_join_order(&ctx.table_references[i]))
+            .unwrap_or(table_count);
+        let tables = (start..end).collect::<Vec<_>>();
+        let (segment_order, segment_loop_count) = if tables.len() <= MAX_SEARCHED_JOIN_TABLES {
+            search_join_order(&ctx, &tables, available, loop_count)
+        } else {
+            greedy_join_order(&ctx, tables, available, loop_count)
+        };
+        for table_index in segment_order {
+            order.push(table_index);
+            available |= 1 << table_index;
+        }
+        loop_count = segment_loop_count;
+        start = end;
+    }
+    if order.iter().enumerate().all(|(position, &i)| position == i) {
+        return Ok(());
+    }
+    apply_join_order(plan, &order)?;
+    *available_indexes = order
+        .iter()
+        .map(|&i| available_indexes[i].clone())
+        .collect();
+    *stats = order.iter().map(|&i| stats[i].clone()).collect();
+    Ok(())
+}
+
+/// Whether a table keeps its position in the join order, see [reorder_joins].
+fn is_fixed_in_join_order(table_reference: &TableReference) -> bool {
+    table_reference.btree().is_none()
+        || !matches!(table_reference.op, Operation::Scan { .. })
+        || table_reference
+            .join_info
+            .as_ref()
+            .is_some_and(|join_info| join_info.outer || join_info.right || join_info.cross)
+}
+
+/// Finds the order of `tables` with the lowest estimated cost, after the tables in `available`
+/// whose loops run `loop_count` times in total. Returns the order and the number of times the
+/// loop after it runs.
+///
+/// The cheapest order of each subset of the tables is the cheapest of the orders that end with
+/// one of its tables after the cheapest order of the others, so the subsets are solved from the
+/// smallest up. The last table of a subset is tried from the one that comes last in the FROM
+/// clause, so that the FROM clause order is kept unless another order is strictly cheaper.
+fn search_join_order(
+    ctx: &JoinOrderContext,
+    tables: &[usize],
+    available: u64,
+    loop_count: f64,
+) -> (Vec<usize>, f64) {
+    // For each subset of `tables`, as a bitmask of positions in `tables`: the cost of its
+    // cheapest order, the number of times the loop after it runs and its last table
+    let mut best: Vec<Option<(f64, f64, usize)>> = vec![None; 1 << tables.len()];
+    best[0] = Some((0.0, loop_count, 0));
+    for subset in 1..best.len() {
+        for last in (0..tables.len())
+            .rev()
+            .filter(|last| subset & 1 << last != 0)
+        {
+            let rest = subset & !(1 << last);
+            let (rest_cost, rest_loop_count, _) = best[rest].unwrap();
+            let rest_tables = (0..tables.len())
+                .filter(|i| rest & 1 << i != 0)
+                .fold(available, |mask, i| mask | 1 << tables[i]);
+            let (cost, rows) =
+                estimate_table_access(ctx, tables[last], rest_tables, rest_loop_count);
+            let cost = rest_cost + cost;
+            if best[subset].is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best[subset] = Some((cost, rest_loop_count * rows, last));
+            }
+        }
+    }
+    let mut order = Vec::with_capacity(tables.len());
+    let mut subset = best.len() - 1;
+    let (_, final_loop_count, _) = best[subset].unwrap();
+    while subset != 0 {
+        let (_, _, last) = best[subset].unwrap();
+        order.push(tables[last]);
+        subset &= !(1 << last);
+    }
+    order.reverse();
+    (order, final_loop_count)
+}
+
+/// Orders `tables` by repeatedly choosing the cheapest one to loop over next, see
+/// [search_join_order].
+fn greedy_join_order(
+    ctx: &JoinOrderContext,
+    mut tables: Vec<usize>,
+    mut available: u64,
+    mut loop_count: f64,
+) -> (Vec<usize>, f64) {
+    let mut order = Vec::with_capacity(tables.len());
+    while !tables.is_empty() {
+        let (i, rows) = tables
+            .iter()
+            .enumerate()
+            .map(|(i, &table_index)| {
+                let (cost, rows) = estimate_table_access(ctx, table_index, available, loop_count);
+                (i, cost, rows)
+            })
+            .min_by(|(_, a, _), (_, b, _)| a.total_cmp(b))
+            .map(|(i, _, rows)| (i, rows))
+            .unwrap();
+        let table_index = tables.remove(i);
+        order.push(table_index);
+        available |= 1 << table_index;
+        loop_count *= rows;
+    }
+    (order, loop_count)
+}
+
+/// Estimates the cost of running the loop of a table `loop_count` times, once for each row of the
+/// tables in `available`, with the cheapest way to read it: a scan, a search of one of its
+/// indexes or of its rowid, or a search of an ephemeral index or a hash table built on it. Also
+/// returns the number of its rows that each run of the loop is estimated to find.
+fn estimate_table_access(
+    ctx: &JoinOrderContext,
+    table_index: usize,
+    available: u64,
+    loop_count: f64,
+) -> (f64, f64) {
+    let table_reference = &ctx.table_references[table_index];
+    let table_rows = match table_reference.btree() {
+        Some(_) => estimated_table_rows(ctx.stats[table_index].as_ref()),
+        None => ESTIMATED_HARDCODED_ROWS_PER_TABLE,
+    };
+    let mut cost = estimate_cost(None, &[], table_rows, loop_count, false);
+    let mut rows = table_rows;
+    if table_reference.btree().is_none() {
+        return (cost, rows);
+    }
+    let usable = ctx
+        .constraints
+        .iter()
+        .filter(|c| c.table_index == table_index && c.other_tables & !available == 0)
+        .collect::<Vec<_>>();
+    let is_equality = |c: &&TableConstraint| c.constraint.operator == ast::Operator::Equals;
+    if usable.iter().any(|c| c.column.is_none() && is_equality(c)) {
+        return (loop_count, 1.0);
+    }
+    for index in ctx.available_indexes[table_index].iter() {
+        if index.where_clause.is_some() {
+            continue;
+        }
+        let mut constraints = vec![];
+        for index_column in index.columns.iter() {
+            if index_column.expr.is_some() {
+                break;
+            }
+            let mut on_column = usable
+                .iter()
+                .filter(|c| c.column == Some(index_column.pos_in_table));
+            if let Some(equality) = on_column.clone().find(|c| is_equality(c)) {
+                constraints.push(equality.constraint.clone());
+                continue;
+            }
+            if let Some(range) = on_column.next() {
+                constraints.push(range.constraint.clone());
+            }
+            break;
+        }
+        if constraints.is_empty() {
+            continue;
+        }
+        let index_info = IndexInfo {
+            unique: index.unique,
+            column_count: index.columns.len(),
+            rows_per_key: ctx.stats[table_index]
+                .as_ref()
+                .and_then(|stats| stats.indexes.get(&normalize_ident(&index.name)))
+                .map(|stats| stats.rows_per_key.as_slice()),
+        };
+        cost = cost.min(estimate_cost(
+            Some(index_info),
+            &constraints,
+            table_rows,
+            loop_count,
+            false,
+        ));
+        rows = rows.min(estimate_cost(
+            Some(index_info),
+            &constraints,
+            table_rows,
+            1.0,
+            false,
+        ));
+    }
+    let equalities = usable
+        .iter()
+        .filter(|c| c.column.is_some() && is_equality(c))
+        .map(|c| c.constraint.clone())
+        .collect::<Vec<_>>();
+    if !equalities.is_empty() {
+        let index_info = IndexInfo {
+            unique: false,
+            column_count: equalities.len(),
+            rows_per_key: None,
+        };
+        if loop_count > 1.0 {
+            let used_column_count = (0..table_reference.columns().len())
+                .filter(|i| table_reference.column_is_used(*i))
+                .count();
+            cost = cost
+                .min(estimate_cost(
+                    Some(index_info),
+                    &equalities,
+                    table_rows,
+                    loop_count,
+                    true,
+                ))
+                .min(estimate_hash_join_cost(
+                    &equalities,
+                    used_column_count,
+                    table_rows,
+                    loop_count,
+                ));
+        }
+        rows = rows.min(estimate_cost(
+            Some(index_info),
+            &equalities,
+            table_rows,
+            1.0,
+            false,
+        ));
+    }
+    (cost, rows.max(1.0))
+}
+
+/// The comparisons in `where_clause` that may search a table, see [TableConstraint]. The
+/// conditions of OUTER JOINs are left out, since the tables that they may search do not move.
+fn table_constraints(where_clause: &[WhereTerm]) -> Vec<TableConstraint> {
+    let mut constraints = vec![];
+    for (position, term) in where_clause.iter().enumerate() {
+        if term.from_outer_join {
+            continue;
+        }
+        let Ok(ast::Expr::Binary(lhs, operator, rhs)) = unwrap_parens(&term.expr) else {
+            continue;
+        };
+        if !matches!(
+            operator,
+            ast::Operator::Equals
+                | ast::Operator::Greater
+                | ast::Operator::GreaterEquals
+                | ast::Operator::Less
+                | ast::Operator::LessEquals
+        ) {
+            continue;
+        }
+        let sides = [
+            (lhs, rhs, *operator, BinaryExprSide::Rhs),
+            (rhs, lhs, opposite_cmp_op(*operator), BinaryExprSide::Lhs),
+        ];
+        for (expr, other, operator, other_side) in sides {
+            let (table_index, column) = match unwrap_parens(expr.as_ref()) {
+                Ok(ast::Expr::Column {
+                    table,
+                    column,
+                    is_rowid_alias,
+                    ..
+                }) => (*table, (!is_rowid_alias).then_some(*column)),
+                Ok(ast::Expr::RowId { table, .. }) => (*table, None),
+                _ => continue,
+            };
+            let other_tables = referenced_tables(other);
+            if other_tables & 1 << table_index != 0 {
+                continue;
+            }
+            constraints.push(TableConstraint {
+                table_index,
+                column,
+                constraint: IndexConstraint {
+                    position_in_where_clause: (position, other_side),
+                    operator,
+                    index_column_sort_order: SortOrder::Asc,
+                },
+                other_tables,
+            });
+        }
+    }
+    constraints
+}
+
+/// The tables that an expression refers to, as a bitmask of their indexes.
+fn referenced_tables(expr: &ast::Expr) -> u64 {
+    let mut tables = child_exprs(expr)
+        .into_iter()
+        .fold(0, |tables, child| tables | referenced_tables(child));
+    match expr {
+        ast::Expr::Column { table, .. } | ast::Expr::RowId { table, .. } => tables |= 1 << table,
+        ast::Expr::SubqueryResult { outer_refs, .. } => {
+            for outer_ref in outer_refs {
+                tables |= referenced_tables(outer_ref);
+            }
+        }
+        ast::Expr::FunctionCall {
+            order_by: Some(order_by),
+            ..
+        } => {
+            for column in order_by {
+                tables |= referenced_tables(&column.expr);
+            }
+        }
+        _ => {}
+    }
+    tables
+}
+
+/// Moves the tables of a plan into `order`, where `order[i]` is the current index of the table
+/// that goes to position i, and renumbers the references to the tables accordingly.
+fn apply_join_order(plan: &mut SelectPlan, order: &[usize]) -> Result<()> {
+    let mut new_positions = vec![0; order.len()];
+    for (position, &table_index) in order.iter().enumerate() {
+        new_positions[table_index] = position;
+    }
+    let mut tables = std::mem::take(&mut plan.table_references)
+        .into_iter()
+        .map(Some)
+        .collect::<Vec<_>>();
+    plan.table_references = order.iter().map(|&i| tables[i].take().unwrap()).collect();
+    // Every table but the first is the right side of a join
+    for (position, table_reference) in plan.table_references.iter_mut().enumerate() {
+        if position == 0 {
+            table_reference.join_info = None;
+        } else if table_reference.join_info.is_none() {
+            table_reference.join_info = Some(JoinInfo {
+                outer: false,
+                right: false,
+                cross: false,
+                using: None,
+            });
+        }
+    }
+
+    for term in plan.where_clause.iter_mut() {
+        renumber_tables(&mut term.expr, &new_positions);
+        term.from_join = term.from_join.map(|j| new_positions[j]);
+        term.eval_at = match term.from_join {
+            Some(j) if term.from_outer_join => EvalAt::Loop(j),
+            _ => determine_where_to_eval_expr(&term.expr)?,
+        };
+    }
+    defer_conditions_past_right_joins(&plan.table_references, &mut plan.where_clause);
+    for column in plan.result_columns.iter_mut() {
+        renumber_tables(&mut column.expr, &new_positions);
+    }
+    if let Some(group_by) = plan.group_by.as_mut() {
+        for expr in group_by
+            .exprs
+            .iter_mut()
+            .chain(group_by.having.iter_mut().flatten())
+        {
+            renumber_tables(expr, &new_positions);
+        }
+    }
+    for (expr, _) in plan.order_by.iter_mut().flatten() {
+        renumber_tables(expr, &new_positions);
+    }
+    for aggregate in plan.aggregates.iter_mut() {
+        for expr in aggregate
+            .args
+            .iter_mut()
+            .chain(aggregate.filter.iter_mut())
+            .chain(std::iter::once(&mut aggregate.original_expr))
+        {
+            renumber_tables(expr, &new_positions);
+        }
+    }
+    Ok(())
+}
+
+/// Replaces each table index `i` in the column references of an expression with
+/// `new_positions[i]`, see [apply_join_order].
+fn renumber_tables(expr: &mut ast::Expr, new_positions: &[usize]) {
+    match expr {
+        ast::Expr::Column { table, .. } | ast::Expr::RowId { table, .. } => {
+            *table = new_positions[*table];
+        }
+        ast::Expr::SubqueryResult { outer_refs, .. } => {
+            for outer_ref in outer_refs.iter_mut() {
+                renumber_tables(outer_ref, new_positions);
+            }
+        }
+        ast::Expr::FunctionCall {
+            order_by: Some(order_by),
+            ..
+        } => {
+            for column in order_by.iter_mut() {
+                renumber_tables(&mut column.expr, new_positions);
+            }
+        }
+        _ => {}
+    }
+    for child in child_exprs_mut(expr) {
+        renumber_tables(child, new_positions);
+    }
+}
+
 fn optimize_subqueries(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
     for table in plan.table_references.iter_mut() {
         if let Operation::Subquery { plan, .. } = &mut table.op {
             optimize_select_plan(&mut *plan, schema)?;
         }
     }
+    for subquery in plan.expr_subqueries.iter_mut() {
+        optimize_select_plan(&mut subquery.plan, schema)?;
+    }
 
     Ok(())
 }
 
+fn resolve_in_subquery_comparisons_select(plan: &mut SelectPlan) {
+    let exprs = plan
+        .result_columns
+        .iter()
+        .map(|rc| &rc.expr)
+        .chain(plan.where_clause.iter().map(|cond| &cond.expr))
+        .chain(plan.group_by.iter().flat_map(|group_by| {
+            group_by
+                .exprs
+                .iter()
+                .chain(group_by.having.iter().flatten())
+        }))
+        .chain(plan.order_by.iter().flatten().map(|(expr, _)| expr))
+        .chain(plan.aggregates.iter().flat_map(|agg| {
+            agg.args
+                .iter()
+                .chain(agg.filter.iter())
+                .chain(std::iter::once(&agg.original_expr))
+        }));
+    for expr in exprs {
+        resolve_in_subquery_comparisons(expr, &plan.table_references, &mut plan.expr_subqueries);
+    }
+}
+
+/// Decides how the left-hand side of each `x IN (SELECT y ...)` in `expr` is compared with the
+/// rows of the subquery, see [ExprSubquery::affinity] and [ExprSubquery::collation]. Like for
+/// `x = y`, this depends on both x and y.
+fn resolve_in_subquery_comparisons(
+    expr: &ast::Expr,
+    tables: &[TableReference],
+    subqueries: &mut [ExprSubquery],
+) {
+    if let ast::Expr::SubqueryResult {
+        subquery_id,
+        lhs: Some(lhs),
+        ..
+    } = expr
+    {
+        if let Some(subquery) = subqueries.get_mut(*subquery_id) {
+            // Like SQLite, use the column of the last SELECT of a compound subquery.
+            let select = subquery
+                .plan
+                .compounds
+                .last()
+                .map_or(subquery.plan.as_ref(), |(_, member)| member);
+            let column = &select.result_columns[0].expr;
+            let subquery_tables = Some(select.table_references.as_slice());
+            let affinity = comparison_affinity(
+                expr_affinity(lhs, Some(tables)),
+                expr_affinity(column, subquery_tables),
+            );
+            let collation =
+                subquery_compare_collation_name(lhs, Some(tables), column, subquery_tables)
+                    .map(str::to_string);
+            subquery.affinity = affinity;
+            subquery.collation = collation;
+        }
+    }
+    for child in child_exprs(expr) {
+        resolve_in_subquery_comparisons(child, tables, subqueries);
+    }
+}
+
 fn eliminate_orderby_like_groupby(plan: &mut SelectPlan) -> Result<()> {
     if plan.order_by.is_none() | plan.group_by.is_none() {
         return Ok(());
@@ -171,13 +803,26 @@ fn eliminate_orderby_like_groupby(plan: &mut SelectPlan) -> Result<()> {
 /// Returns true if the ORDER BY clause was eliminated.
 fn eliminate_unnecessary_orderby(
     table_references: &mut [TableReference],
-    available_indexes: &HashMap<String, Vec<Arc<Index>>>,
+    available_indexes: &[Vec<Arc<Index>>],
     order_by: &mut Option<Vec<(ast::Expr, Direction)>>,
     group_by: &Option<GroupBy>,
 ) -> Result<bool> {
     let Some(order) = order_by else {
         return Ok(false);
     };
+    // Rows that are emitted after the main loop by a RIGHT or FULL OUTER JOIN are not in the order of
+    // the first table.
+    if table_references.iter().any(|t| {
+        t.join_info
+            .as_ref()
+            .is_some_and(|join_info| join_info.right)
+    }) {
+        return Ok(false);
+    }
+    let order_collations = order
+        .iter()
+        .map(|(expr, _)| expr_collation_name(expr, Some(table_references)).map(String::from))
+        .collect::<Vec<_>>();
     let Some(first_table_reference) = table_references.first_mut() else {
         return Ok(false);
     };
@@ -213,26 +858,32 @@ fn eliminate_unnecessary_orderby(
     }
 
     // Find the best matching index for the ORDER BY columns
-    let table_name = &btree_table.name;
     let mut best_index = (None, 0);
 
-    for (_, indexes) in available_indexes.iter() {
-        for index_candidate in indexes.iter().filter(|i| &i.table_name == table_name) {
-            let matching_columns = index_candidate.columns.iter().enumerate().take_while(|(i, c)| {
-                            if let Some((Expr::Column { table, column, .. }, _)) = order.get(*i) {
-                                let col_idx_in_table = btree_table
-                                    .columns
-                                    .iter()
-                                    .position(|tc| tc.name.as_ref() == Some(&c.name));
-                                matches!(col_idx_in_table, Some(col_idx) if *table == 0 && *column == col_idx)
-                            } else {
-                                false
-                            }
-                        }).count();
-
-            if matching_columns > best_index.1 {
-                best_index = (Some(index_candidate), matching_columns);
-            }
+    for index_candidate in available_indexes[0]
+        .iter()
+        .filter(|i| i.where_clause.is_none())
+    {
+        let matching_columns = index_candidate
+            .columns
+            .iter()
+            .enumerate()
+            .take_while(|(i, c)| {
+                if let Some((Expr::Column { table, column, .. }, _)) = order.get(*i) {
+                    let col_idx_in_table = btree_table
+                        .columns
+                        .iter()
+                        .position(|tc| c.expr.is_none() && tc.name.as_ref() == Some(&c.name));
+                    matches!(col_idx_in_table, Some(col_idx) if *table == 0 && *column == col_idx)
+                        && same_collation(order_collations[*i].as_deref(), c.collation.as_deref())
+                } else {
+                    false
+                }
+            })
+            .count();
+
+        if matching_columns > best_index.1 {
+            best_index = (Some(index_candidate), matching_columns);
         }
     }
 
@@ -296,7 +947,8 @@ fn eliminate_unnecessary_orderby(
  */
 fn use_indexes(
     table_references: &mut [TableReference],
-    available_indexes: &HashMap<String, Vec<Arc<Index>>>,
+    available_indexes: &[Vec<Arc<Index>>],
+    stats: &[Option<TableStats>],
     where_clause: &mut Vec<WhereTerm>,
     order_by: &mut Option<Vec<(ast::Expr, Direction)>>,
     group_by: &Option<GroupBy>,
@@ -306,44 +958,37 @@ fn use_indexes(
         eliminate_unnecessary_orderby(table_references, available_indexes, order_by, group_by)?;
 
     // Try to use indexes for WHERE conditions
-    for (table_index, table_reference) in table_references.iter_mut().enumerate() {
-        if matches!(table_reference.op, Operation::Scan { .. }) {
-            let index = if let Operation::Scan { index, .. } = &table_reference.op {
-                Option::clone(index)
-            } else {
-                None
-            };
-            match index {
+    for table_index in 0..table_references.len() {
+        if let Operation::Scan { index, .. } = &table_references[table_index].op {
+            match index.clone() {
                 // If we decided to eliminate ORDER BY using an index, let's constrain our search to only that index
                 Some(index) => {
-                    let available_indexes = available_indexes
-                        .values()
-                        .flatten()
+                    let available_indexes = available_indexes[table_index]
+                        .iter()
                         .filter(|i| i.name == index.name)
                         .cloned()
                         .collect::<Vec<_>>();
                     if let Some(search) = try_extract_index_search_from_where_clause(
                         where_clause,
                         table_index,
-                        table_reference,
+                        table_references,
                         &available_indexes,
+                        stats,
                     )? {
-                        table_reference.op = Operation::Search(search);
+                        table_references[table_index].op = Operation::Search(search);
                     }
                 }
                 None => {
-                    let table_name = table_reference.table.get_name();
-
                     // If we can utilize the rowid alias of the table, let's preferentially always use it for now.
                     let mut i = 0;
                     while i < where_clause.len() {
                         if let Some(search) = try_extract_rowid_search_expression(
                             &mut where_clause[i],
                             table_index,
-                            table_reference,
+                            &table_references[table_index],
                         )? {
                             where_clause.remove(i);
-                            table_reference.op = Operation::Search(search);
+                            table_references[table_index].op = Operation::Search(search);
                             continue;
                         } else {
                             i += 1;
@@ -355,35 +1000,30 @@ fn use_indexes(
                         // but we just don't do that yet.
                         continue;
                     }
-                    let placeholder = vec![];
-                    let mut usable_indexes_ref = &placeholder;
-                    if let Some(indexes) = available_indexes.get(table_name) {
-                        usable_indexes_ref = indexes;
-                    }
                     if let Some(search) = try_extract_index_search_from_where_clause(
                         where_clause,
                         table_index,
-                        table_reference,
-                        usable_indexes_ref,
+                        table_references,
+                        &available_indexes[table_index],
+                        stats,
                     )? {
-                        table_reference.op = Operation::Search(search);
+                        table_references[table_index].op = Operation::Search(search);
                     }
                 }
             }
         }
 
         // Finally, if there's no other reason to use an index, if an index covers the columns used in the query, let's use it.
-        if let Some(indexes) = available_indexes.get(table_reference.table.get_name()) {
-            for index_candidate in indexes.iter() {
-                let is_covering = table_reference.index_is_covering(index_candidate);
-                if let Operation::Scan { index, .. } = &mut table_reference.op {
-                    if index.is_some() {
-                        continue;
-                    }
-                    if is_covering {
-                        *index = Some(index_candidate.clone());
-                        break;
-                    }
+        let table_reference = &mut table_references[table_index];
+        for index_candidate in available_indexes[table_index].iter() {
+            let is_covering = table_reference.index_is_covering(index_candidate);
+            if let Operation::Scan { index, .. } = &mut table_reference.op {
+                if index.is_some() {
+                    continue;
+                }
+                if is_covering {
+                    *index = Some(index_candidate.clone());
+                    break;
                 }
             }
         }
@@ -433,6 +1073,12 @@ fn rewrite_exprs_select(plan: &mut SelectPlan) -> Result<()> {
     }
     for agg in plan.aggregates.iter_mut() {
         rewrite_expr(&mut agg.original_expr)?;
+        for arg in agg.args.iter_mut() {
+            rewrite_expr(arg)?;
+        }
+        if let Some(filter) = agg.filter.as_mut() {
+            rewrite_expr(filter)?;
+        }
     }
     for cond in plan.where_clause.iter_mut() {
         rewrite_expr(&mut cond.expr)?;
@@ -579,6 +1225,10 @@ impl Optimizable for ast::Expr {
             }
             Expr::Raise(..) => false,
             Expr::Subquery(..) => false,
+            Expr::SubqueryResult { .. } => false,
+            Expr::OuterRef(..) => false,
+            Expr::TriggerParam(..) => false,
+            Expr::Register(..) => false,
             Expr::Unary(_, expr) => expr.is_nonnull(),
             Expr::Variable(..) => false,
         }
@@ -713,45 +1363,45 @@ fn opposite_cmp_op(op: ast::Operator) -> ast::Operator {
 }
 
 /// Struct used for scoring index scans
-/// Currently we just estimate cost in a really dumb way,
-/// i.e. no statistics are used.
 struct IndexScore {
     index: Option<Arc<Index>>,
     cost: f64,
     constraints: Vec<IndexConstraint>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct IndexInfo {
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct IndexInfo<'a> {
     unique: bool,
     column_count: usize,
+    /// The average number of entries per key of each prefix of the index columns, from the
+    /// statistics gathered by ANALYZE.
+    rows_per_key: Option<&'a [u64]>,
 }
 
 const ESTIMATED_HARDCODED_ROWS_PER_TABLE: f64 = 1000.0;
 
-/// Unbelievably dumb cost estimate for rows scanned by an index scan.
-fn dumb_cost_estimator(
+/// The number of rows of a table, from its statistics if it has been analyzed.
+fn estimated_table_rows(stats: Option<&TableStats>) -> f64 {
+    stats
+        .and_then(|stats| stats.row_count)
+        .map_or(ESTIMATED_HARDCODED_ROWS_PER_TABLE, |rows| rows as f64)
+}
+
+/// Estimate of the number of rows read by a table scan or an index scan, repeated `loop_count`
+/// times. Without statistics for the index, the selectivity of its constraints is guessed.
+fn estimate_cost(
     index_info: Option<IndexInfo>,
     constraints: &[IndexConstraint],
-    is_inner_loop: bool,
+    table_rows: f64,
+    loop_count: f64,
     is_ephemeral: bool,
 ) -> f64 {
-    // assume that the outer table always does a full table scan :)
-    // this discourages building ephemeral indexes on the outer table
-    // (since a scan reads TABLE_ROWS rows, so an ephemeral index on the outer table would both read TABLE_ROWS rows to build the index and then seek the index)
-    // but encourages building it on the inner table because it's only built once but the inner loop is run as many times as the outer loop has iterations.
-    let loop_multiplier = if is_inner_loop {
-        ESTIMATED_HARDCODED_ROWS_PER_TABLE
-    } else {
-        1.0
-    };
-
     // If we are building an ephemeral index, we assume we will scan the entire source table to build it.
     // Non-ephemeral indexes don't need to be built.
-    let cost_to_build_index = is_ephemeral as usize as f64 * ESTIMATED_HARDCODED_ROWS_PER_TABLE;
+    let cost_to_build_index = is_ephemeral as usize as f64 * table_rows;
 
     let Some(index_info) = index_info else {
-        return cost_to_build_index + ESTIMATED_HARDCODED_ROWS_PER_TABLE * loop_multiplier;
+        return cost_to_build_index + table_rows * loop_count;
     };
 
     let final_constraint_is_range = constraints
@@ -764,27 +1414,67 @@ fn dumb_cost_estimator(
         } else {
             constraints.len()
         })
-        .count() as f64;
+        .count();
+    let range_selectivity = if final_constraint_is_range { 0.4 } else { 1.0 };
 
+    let rows_per_key = index_info
+        .rows_per_key
+        .and_then(|rows_per_key| rows_per_key.get(equalities_count.checked_sub(1)?));
     let selectivity = match (
+        rows_per_key,
         index_info.unique,
         index_info.column_count as f64,
-        equalities_count,
+        equalities_count as f64,
     ) {
+        // the statistics tell how many rows the equalities select
+        (Some(rows), _, _, _) => (*rows as f64 / table_rows.max(1.0)).min(1.0) * range_selectivity,
         // no equalities: let's assume range query selectivity is 0.4. if final constraint is not range and there are no equalities, it means full table scan incoming
-        (_, _, 0.0) => {
-            if final_constraint_is_range {
-                0.4
-            } else {
-                1.0
-            }
-        }
+        (None, _, _, 0.0) => range_selectivity,
         // on an unique index if we have equalities across all index columns, assume very high selectivity
-        (true, index_cols, eq_count) if eq_count == index_cols => 0.01 * eq_count,
+        (None, true, index_cols, eq_count) if eq_count == index_cols => 0.01 * eq_count,
         // some equalities: let's assume each equality has a selectivity of 0.1 and range query selectivity is 0.4
-        (_, _, eq_count) => (eq_count * 0.1) * if final_constraint_is_range { 0.4 } else { 1.0 },
+        (None, _, _, eq_count) => (eq_count * 0.1) * range_selectivity,
     };
-    cost_to_build_index + selectivity * ESTIMATED_HARDCODED_ROWS_PER_TABLE * loop_multiplier
+    cost_to_build_index + selectivity * table_rows * loop_count
+}
+
+/// The memory that a hash table is assumed to hold before it spills to disk: the default size of
+/// the page cache, which bounds it at runtime.
+const ESTIMATED_HASH_TABLE_MEMORY: f64 = 2000.0 * 1024.0;
+const ESTIMATED_HASH_TABLE_BYTES_PER_COLUMN: f64 = 16.0;
+/// A probe of a hash table looks up a single bucket, whereas a seek of an ephemeral index descends
+/// its b-tree. The rows read by [estimate_cost] leave the seeks out, so a probe is counted as this
+/// many rows cheaper than a seek.
+const HASH_TABLE_PROBE_SAVING: f64 = 0.5;
+/// A probe of a hash table that finds entries on disk costs as much as reading this many rows.
+const HASH_TABLE_SPILL_READ_COST: f64 = 16.0;
+
+/// Estimate of the cost of a hash join on a table of `table_rows` rows, with a hash table of
+/// `column_count` columns that is probed `loop_count` times with the values of `equalities`.
+/// Building the hash table and reading the entries that the probes find cost as much as with an
+/// ephemeral index, so the hash table wins when it is probed often and fits in memory, which also
+/// makes the smaller of two tables the better one to build it from.
+fn estimate_hash_join_cost(
+    equalities: &[IndexConstraint],
+    column_count: usize,
+    table_rows: f64,
+    loop_count: f64,
+) -> f64 {
+    let ephemeral_index_cost = estimate_cost(
+        Some(IndexInfo {
+            unique: false,
+            column_count,
+            rows_per_key: None,
+        }),
+        equalities,
+        table_rows,
+        loop_count,
+        true,
+    );
+    let table_size = table_rows * column_count as f64 * ESTIMATED_HASH_TABLE_BYTES_PER_COLUMN;
+    let spilled_fraction = (1.0 - ESTIMATED_HASH_TABLE_MEMORY / table_size).max(0.0);
+    ephemeral_index_cost
+        + loop_count * (spilled_fraction * HASH_TABLE_SPILL_READ_COST - HASH_TABLE_PROBE_SAVING)
 }
 
 /// Try to extract an index search from the WHERE clause
@@ -792,13 +1482,15 @@ fn dumb_cost_estimator(
 pub fn try_extract_index_search_from_where_clause(
     where_clause: &mut Vec<WhereTerm>,
     table_index: usize,
-    table_reference: &TableReference,
+    table_references: &[TableReference],
     table_indexes: &[Arc<Index>],
+    stats: &[Option<TableStats>],
 ) -> Result<Option<Search>> {
     // If there are no WHERE terms, we can't extract a search
     if where_clause.is_empty() {
         return Ok(None);
     }
+    let table_reference = &table_references[table_index];
 
     let iter_dir = if let Operation::Scan { iter_dir, .. } = &table_reference.op {
         *iter_dir
@@ -813,7 +1505,17 @@ pub fn try_extract_index_search_from_where_clause(
     // 3. constrain the index columns in the order that they appear in the index
     //    - e.g. if the index is on (a,b,c) then we can use all of "a = 1 AND b = 2 AND c = 3" to constrain the index scan,
     //    - but if the where clause is "a = 1 and c = 3" then we can only use "a = 1".
-    let cost_of_full_table_scan = dumb_cost_estimator(None, &[], table_index != 0, false);
+    let table_stats = stats[table_index].as_ref();
+    let table_rows = estimated_table_rows(table_stats);
+    // assume that the outer table always does a full table scan :)
+    // this discourages building ephemeral indexes on the outer table
+    // (since a scan reads all its rows, so an ephemeral index on the outer table would both read them to build the index and then seek the index)
+    // but encourages building it on the inner table because it's only built once but the inner loop is run as many times as the outer loop has iterations.
+    let loop_count = match table_index {
+        0 => 1.0,
+        _ => estimated_table_rows(stats[table_index - 1].as_ref()),
+    };
+    let cost_of_full_table_scan = estimate_cost(None, &[], table_rows, loop_count, false);
     let mut constraints_cur = vec![];
     let mut best_index = IndexScore {
         index: None,
@@ -822,17 +1524,30 @@ pub fn try_extract_index_search_from_where_clause(
     };
 
     for index in table_indexes {
+        if !partial_index_is_usable(index, where_clause, table_index, table_reference) {
+            continue;
+        }
         // Check how many terms in the where clause constrain the index in column order
-        find_index_constraints(where_clause, table_index, index, &mut constraints_cur)?;
-        // naive scoring since we don't have statistics: prefer the index where we can use the most columns
+        find_index_constraints(
+            where_clause,
+            table_index,
+            table_references,
+            index,
+            &mut constraints_cur,
+        )?;
+        // without statistics the scoring is naive: prefer the index where we can use the most columns
         // e.g. if we can use all columns of an index on (a,b), it's better than an index of (c,d,e) where we can only use c.
-        let cost = dumb_cost_estimator(
+        let cost = estimate_cost(
             Some(IndexInfo {
                 unique: index.unique,
                 column_count: index.columns.len(),
+                rows_per_key: table_stats
+                    .and_then(|stats| stats.indexes.get(&normalize_ident(&index.name)))
+                    .map(|stats| stats.rows_per_key.as_slice()),
             }),
             &constraints_cur,
-            table_index != 0,
+            table_rows,
+            loop_count,
             false,
         );
         if cost < best_index.cost {
@@ -844,17 +1559,62 @@ pub fn try_extract_index_search_from_where_clause(
     }
 
     // We haven't found a persistent btree index that is any better than a full table scan;
-    // let's see if building an ephemeral index would be better.
+    // let's see if building an ephemeral index or a hash table would be better.
+    // The affinities of the key of the hash table, if the table is searched with a hash join.
+    let mut hash_join_affinities = None;
     if best_index.index.is_none() {
         let (ephemeral_cost, constraints_with_col_idx, mut constraints_without_col_idx) =
-            ephemeral_index_estimate_cost(where_clause, table_reference, table_index);
-        if ephemeral_cost < best_index.cost {
-            // ephemeral index makes sense, so let's build it now.
-            // ephemeral columns are: columns from the table_reference, constraints first, then the rest
-            let ephemeral_index =
-                ephemeral_index_build(table_reference, table_index, &constraints_with_col_idx);
-            best_index.index = Some(Arc::new(ephemeral_index));
-            best_index.cost = ephemeral_cost;
+            ephemeral_index_estimate_cost(
+                where_clause,
+                table_references,
+                table_index,
+                table_rows,
+                loop_count,
+            );
+        // ephemeral columns are: columns from the table_reference, constraints first, then the rest
+        let ephemeral_index =
+            ephemeral_index_build(table_reference, table_index, &constraints_with_col_idx);
+        // A hash table is built from the rows of the table for the probes of the outer loops, so
+        // it is of no use to the first table. It is not used for the right table of a RIGHT JOIN,
+        // which is read again after the main loop, nor when it would not hold all the columns that
+        // the query reads, nor when its key cannot be hashed.
+        let affinities = hash_key_affinities(
+            where_clause,
+            table_references,
+            &ephemeral_index,
+            &constraints_without_col_idx,
+        );
+        let hash_join_cost = if table_index > 0
+            && !constraints_without_col_idx.is_empty()
+            && affinities.is_some()
+            && !table_reference
+                .join_info
+                .as_ref()
+                .is_some_and(|join_info| join_info.right)
+            && table_reference.index_is_covering(&ephemeral_index)
+        {
+            estimate_hash_join_cost(
+                &constraints_without_col_idx,
+                ephemeral_index.columns.len(),
+                table_rows,
+                loop_count,
+            )
+        } else {
+            f64::INFINITY
+        };
+        if ephemeral_cost.min(hash_join_cost) < best_index.cost {
+            if hash_join_cost <= ephemeral_cost {
+                hash_join_affinities = affinities;
+            }
+            best_index.index = Some(Arc::new(if hash_join_affinities.is_some() {
+                Index {
+                    name: format!("hash_{}_{}", table_reference.table.get_name(), table_index),
+                    ..ephemeral_index
+                }
+            } else {
+                ephemeral_index
+            }));
+            best_index.cost = ephemeral_cost.min(hash_join_cost);
             best_index.constraints.clear();
             best_index
                 .constraints
@@ -862,13 +1622,30 @@ pub fn try_extract_index_search_from_where_clause(
         }
     }
 
-    if best_index.index.is_none() {
+    let Some(index) = best_index.index else {
         return Ok(None);
-    }
+    };
 
-    // Build the seek definition
-    let seek_def =
-        build_seek_def_from_index_constraints(&best_index.constraints, iter_dir, where_clause)?;
+    // Build the seek definition, or the key of the probes of the hash table
+    let search = if let Some(affinities) = hash_join_affinities {
+        Search::Hash {
+            index,
+            key: constraint_operands(&best_index.constraints, where_clause)?
+                .into_iter()
+                .map(|(expr, _)| expr)
+                .collect(),
+            affinities,
+        }
+    } else {
+        Search::Seek {
+            index: Some(index),
+            seek_def: build_seek_def_from_index_constraints(
+                &best_index.constraints,
+                iter_dir,
+                where_clause,
+            )?,
+        }
+    };
 
     // Remove the used terms from the where_clause since they are now part of the seek definition
     // Sort terms by position in descending order to avoid shifting indices during removal
@@ -882,27 +1659,35 @@ pub fn try_extract_index_search_from_where_clause(
         where_clause.remove(constraint.position_in_where_clause.0);
     }
 
-    return Ok(Some(Search::Seek {
-        index: best_index.index,
-        seek_def,
-    }));
+    Ok(Some(search))
 }
 
 fn ephemeral_index_estimate_cost(
     where_clause: &mut Vec<WhereTerm>,
-    table_reference: &TableReference,
+    table_references: &[TableReference],
     table_index: usize,
+    table_rows: f64,
+    loop_count: f64,
 ) -> (f64, Vec<(usize, IndexConstraint)>, Vec<IndexConstraint>) {
+    let table_reference = &table_references[table_index];
     let mut constraints_with_col_idx: Vec<(usize, IndexConstraint)> = where_clause
         .iter()
         .enumerate()
-        .filter(|(_, term)| is_potential_index_constraint(term, table_index))
+        .filter(|(_, term)| is_potential_index_constraint(term, table_index, table_reference))
         .filter_map(|(i, term)| {
             let Ok(ast::Expr::Binary(lhs, operator, rhs)) = unwrap_parens(&term.expr) else {
                 panic!("expected binary expression");
             };
+            // The ephemeral index uses the collating sequences of the table columns.
+            let collation = binary_compare_collation_name(lhs, rhs, Some(table_references));
+            let column_has_collation = |column: usize| {
+                same_collation(
+                    collation,
+                    table_reference.columns()[column].collation.as_deref(),
+                )
+            };
             if let ast::Expr::Column { table, column, .. } = lhs.as_ref() {
-                if *table == table_index {
+                if *table == table_index && column_has_collation(*column) {
                     return Some((
                         *column,
                         IndexConstraint {
@@ -914,7 +1699,7 @@ fn ephemeral_index_estimate_cost(
                 }
             }
             if let ast::Expr::Column { table, column, .. } = rhs.as_ref() {
-                if *table == table_index {
+                if *table == table_index && column_has_collation(*column) {
                     return Some((
                         *column,
                         IndexConstraint {
@@ -956,13 +1741,15 @@ fn ephemeral_index_estimate_cost(
         .cloned()
         .map(|(_, c)| c)
         .collect::<Vec<_>>();
-    let ephemeral_cost = dumb_cost_estimator(
+    let ephemeral_cost = estimate_cost(
         Some(IndexInfo {
             unique: false,
             column_count: ephemeral_column_count,
+            rows_per_key: None,
         }),
         &constraints_without_col_idx,
-        table_index != 0,
+        table_rows,
+        loop_count,
         true,
     );
     (
@@ -972,6 +1759,54 @@ fn ephemeral_index_estimate_cost(
     )
 }
 
+/// The affinities to apply to the values that probe the hash table of a hash join on the key
+/// columns of `index`, which are compared with the other operands of `constraints`. Or None if a
+/// hash table cannot find the rows that the comparisons are true for:
+/// - The hash table holds the values of the table as they are, so each comparison must not convert
+///   them, i.e. its affinity must be BLOB or the affinity of the column, like for the seeks of an
+///   index in SQLite.
+/// - Values that a collating sequence of an extension finds equal may not hash the same.
+fn hash_key_affinities(
+    where_clause: &[WhereTerm],
+    table_references: &[TableReference],
+    index: &Index,
+    constraints: &[IndexConstraint],
+) -> Option<Vec<Affinity>> {
+    let mut affinities = Vec::with_capacity(constraints.len());
+    for (constraint, index_column) in constraints.iter().zip(index.columns.iter()) {
+        if index_column
+            .collation
+            .as_deref()
+            .is_some_and(|name| CollationSeq::builtin(name).is_none())
+        {
+            return None;
+        }
+        let (position, side) = constraint.position_in_where_clause;
+        let Ok(ast::Expr::Binary(lhs, _, rhs)) = unwrap_parens(&where_clause[position].expr) else {
+            return None;
+        };
+        let (operand, column) = match side {
+            BinaryExprSide::Lhs => (lhs, rhs),
+            BinaryExprSide::Rhs => (rhs, lhs),
+        };
+        let column_affinity = expr_affinity(column, Some(table_references))?;
+        let affinity = comparison_affinity(
+            Some(column_affinity),
+            expr_affinity(operand, Some(table_references)),
+        );
+        let converts_column = match affinity {
+            Affinity::Blob => false,
+            Affinity::Text => column_affinity != Affinity::Text,
+            _ => !column_affinity.is_numeric(),
+        };
+        if converts_column {
+            return None;
+        }
+        affinities.push(affinity);
+    }
+    Some(affinities)
+}
+
 fn ephemeral_index_build(
     table_reference: &TableReference,
     table_index: usize,
@@ -985,6 +1820,8 @@ fn ephemeral_index_build(
             name: c.name.clone().unwrap(),
             order: SortOrder::Asc,
             pos_in_table: i,
+            collation: c.collation.clone(),
+            expr: None,
         })
         // only include columns that are used in the query
         .filter(|c| table_reference.column_is_used(c.pos_in_table))
@@ -1015,8 +1852,10 @@ fn ephemeral_index_build(
         columns: ephemeral_columns,
         unique: false,
         ephemeral: true,
+        where_clause: None,
         table_name: table_reference.table.get_name().to_string(),
         root_page: 0,
+        key_suffix: Vec::new(),
     };
 
     ephemeral_index
@@ -1087,23 +1926,126 @@ impl UnwrapParens for ast::Expr {
 
 /// Get the position of a column in an index
 /// For example, if there is an index on table T(x,y) then y's position in the index is 1.
+/// In an index on expressions, an expression is at the position of the equivalent index column.
 fn get_column_position_in_index(
     expr: &ast::Expr,
     table_index: usize,
+    table_reference: &TableReference,
     index: &Arc<Index>,
 ) -> Result<Option<usize>> {
-    let ast::Expr::Column { table, column, .. } = unwrap_parens(expr)? else {
+    let expr = unwrap_parens(expr)?;
+    if let ast::Expr::Column { table, column, .. } = expr {
+        if *table != table_index {
+            return Ok(None);
+        }
+        return Ok(index.column_table_pos_to_index_pos(*column));
+    }
+    let Some(btree) = table_reference.btree() else {
         return Ok(None);
     };
-    if *table != table_index {
-        return Ok(None);
+    Ok(index.columns.iter().position(|column| {
+        column.expr.as_ref().is_some_and(|index_expr| {
+            exprs_are_equivalent(&bind_index_expr(index_expr, &btree, table_index), expr)
+        })
+    }))
+}
+
+/// Binds the columns of the table in an expression of an index, like the ones of an index on
+/// expressions, to the table at `table_index`, as they are in the conditions of a query.
+fn bind_index_expr(expr: &ast::Expr, table: &BTreeTable, table_index: usize) -> ast::Expr {
+    fn bind(expr: &mut ast::Expr, table: &BTreeTable, table_index: usize) {
+        let name = match expr {
+            ast::Expr::Id(name) => normalize_ident(&name.0),
+            ast::Expr::Qualified(_, name) => normalize_ident(&name.0),
+            _ => {
+                for child in child_exprs_mut(expr) {
+                    bind(child, table, table_index);
+                }
+                return;
+            }
+        };
+        if let Some((position, column)) = table.get_column(&name) {
+            *expr = ast::Expr::Column {
+                database: None,
+                table: table_index,
+                column: position,
+                is_rowid_alias: column.is_rowid_alias,
+            };
+        } else if ["rowid", "oid", "_rowid_"].contains(&name.as_str()) {
+            *expr = ast::Expr::RowId {
+                database: None,
+                table: table_index,
+            };
+        }
     }
-    Ok(index.column_table_pos_to_index_pos(*column))
+    let mut expr = expr.clone();
+    bind(&mut expr, table, table_index);
+    expr
+}
+
+/// Whether a partial index may be searched for the table at `table_index`, which is when the
+/// conditions that are evaluated at its loop imply the WHERE clause of the index, so that the
+/// index has all the rows of the table that the query may use.
+fn partial_index_is_usable(
+    index: &Index,
+    where_clause: &[WhereTerm],
+    table_index: usize,
+    table_reference: &TableReference,
+) -> bool {
+    let Some(index_where_clause) = &index.where_clause else {
+        return true;
+    };
+    let Some(btree) = table_reference.btree() else {
+        return false;
+    };
+    let mut index_terms = vec![];
+    break_predicate_at_and_boundaries(
+        bind_index_expr(index_where_clause, &btree, table_index),
+        &mut index_terms,
+    );
+    index_terms.iter().all(|index_term| {
+        where_clause.iter().any(|term| {
+            term.can_search_table(table_index, table_reference)
+                && condition_implies(&term.expr, index_term)
+        })
+    })
+}
+
+/// Whether `condition` being true implies that `implied` is. Besides equivalent expressions, a
+/// comparison of an expression implies that it is NOT NULL.
+fn condition_implies(condition: &ast::Expr, implied: &ast::Expr) -> bool {
+    if exprs_are_equivalent(condition, implied) {
+        return true;
+    }
+    let operand = match unwrap_parens(implied) {
+        Ok(ast::Expr::NotNull(operand)) => operand,
+        Ok(ast::Expr::Binary(operand, ast::Operator::IsNot, rhs))
+            if matches!(rhs.as_ref(), ast::Expr::Literal(ast::Literal::Null)) =>
+        {
+            operand
+        }
+        _ => return false,
+    };
+    let Ok(ast::Expr::Binary(lhs, operator, rhs)) = unwrap_parens(condition) else {
+        return false;
+    };
+    matches!(
+        operator,
+        ast::Operator::Equals
+            | ast::Operator::Greater
+            | ast::Operator::GreaterEquals
+            | ast::Operator::Less
+            | ast::Operator::LessEquals
+    ) && (exprs_are_equivalent(lhs, operand) || exprs_are_equivalent(rhs, operand))
 }
 
-fn is_potential_index_constraint(term: &WhereTerm, table_index: usize) -> bool {
+fn is_potential_index_constraint(
+    term: &WhereTerm,
+    table_index: usize,
+    table_reference: &TableReference,
+) -> bool {
     // Skip terms that cannot be evaluated at this table's loop level
-    if !term.should_eval_at_loop(table_index) {
+    if !term.can_search_table(table_index, table_reference) {
         return false;
     }
     // Skip terms that are not binary comparisons
@@ -1147,22 +2089,33 @@ fn is_potential_index_constraint(term: &WhereTerm, table_index: usize) -> bool {
 fn find_index_constraints(
     where_clause: &mut Vec<WhereTerm>,
     table_index: usize,
+    table_references: &[TableReference],
     index: &Arc<Index>,
     out_constraints: &mut Vec<IndexConstraint>,
 ) -> Result<()> {
+    let table_reference = &table_references[table_index];
     for position_in_index in 0..index.columns.len() {
         let mut found = false;
         for (position_in_where_clause, term) in where_clause.iter().enumerate() {
-            if !is_potential_index_constraint(term, table_index) {
+            if !is_potential_index_constraint(term, table_index, table_reference) {
                 continue;
             }
 
             let ast::Expr::Binary(lhs, operator, rhs) = unwrap_parens(&term.expr)? else {
                 panic!("expected binary expression");
             };
+            // The index can only be used if the comparison uses the collating sequence of the index column.
+            if !same_collation(
+                binary_compare_collation_name(lhs, rhs, Some(table_references)),
+                index.columns[position_in_index].collation.as_deref(),
+            ) {
+                continue;
+            }
 
             // Check if lhs is a column that is in the i'th position of the index
-            if Some(position_in_index) == get_column_position_in_index(lhs, table_index, index)? {
+            if Some(position_in_index)
+                == get_column_position_in_index(lhs, table_index, table_reference, index)?
+            {
                 out_constraints.push(IndexConstraint {
                     operator: *operator,
                     position_in_where_clause: (position_in_where_clause, BinaryExprSide::Rhs),
@@ -1172,7 +2125,9 @@ fn find_index_constraints(
                 break;
             }
             // Check if rhs is a column that is in the i'th position of the index
-            if Some(position_in_index) == get_column_position_in_index(rhs, table_index, index)? {
+            if Some(position_in_index)
+                == get_column_position_in_index(rhs, table_index, table_reference, index)?
+            {
                 out_constraints.push(IndexConstraint {
                     operator: opposite_cmp_op(*operator), // swap the operator since e.g. if condition is 5 >= x, we want to use x <= 5
                     position_in_where_clause: (position_in_where_clause, BinaryExprSide::Lhs),
@@ -1218,8 +2173,21 @@ pub fn build_seek_def_from_index_constraints(
         "cannot build seek def from empty list of index constraints"
     );
     // Extract the key values and operators
-    let mut key = Vec::with_capacity(constraints.len());
+    let key = constraint_operands(constraints, where_clause)?;
+
+    // We know all but potentially the last term is an equality, so we can use the operator of the last term
+    // to form the SeekOp
+    let op = constraints.last().unwrap().operator;
+
+    build_seek_def(op, iter_dir, key)
+}
 
+/// Take the expressions that `constraints` compare with the index columns out of their WHERE terms.
+fn constraint_operands(
+    constraints: &[IndexConstraint],
+    where_clause: &mut [WhereTerm],
+) -> Result<Vec<(ast::Expr, SortOrder)>> {
+    let mut key = Vec::with_capacity(constraints.len());
     for constraint in constraints {
         // Extract the other expression from the binary WhereTerm (i.e. the one being compared to the index column)
         let (idx, side) = constraint.position_in_where_clause;
@@ -1235,12 +2203,7 @@ pub fn build_seek_def_from_index_constraints(
         };
         key.push((cmp_expr, constraint.index_column_sort_order));
     }
-
-    // We know all but potentially the last term is an equality, so we can use the operator of the last term
-    // to form the SeekOp
-    let op = constraints.last().unwrap().operator;
-
-    build_seek_def(op, iter_dir, key)
+    Ok(key)
 }
 
 /// Build a [SeekDef] for a given comparison operator and index key.
@@ -1676,7 +2639,7 @@ pub fn try_extract_rowid_search_expression(
     } else {
         return Ok(None);
     };
-    if !cond.should_eval_at_loop(table_index) {
+    if !cond.can_search_table(table_index, table_reference) {
         return Ok(None);
     }
     match &mut cond.expr {
@@ -1699,6 +2662,7 @@ pub fn try_extract_rowid_search_expression(
                             cmp_expr: WhereTerm {
                                 expr: rhs_owned,
                                 from_outer_join: cond.from_outer_join,
+                                from_join: cond.from_join,
                                 eval_at: cond.eval_at,
                             },
                         }));
@@ -1727,6 +2691,7 @@ pub fn try_extract_rowid_search_expression(
                             cmp_expr: WhereTerm {
                                 expr: lhs_owned,
                                 from_outer_join: cond.from_outer_join,
+                                from_join: cond.from_join,
                                 eval_at: cond.eval_at,
                             },
                         }));
@@ -1754,7 +2719,8 @@ pub fn try_extract_rowid_search_expression(
     }
 }
 
-fn rewrite_expr(expr: &mut ast::Expr) -> Result<()> {
+/// Rewrites the expressions that are not translated directly, like BETWEEN, into equivalent ones.
+pub fn rewrite_expr(expr: &mut ast::Expr) -> Result<()> {
     match expr {
         ast::Expr::Id(id) => {
             // Convert "true" and "false" to 1 and 0
@@ -1808,33 +2774,12 @@ fn rewrite_expr(expr: &mut ast::Expr) -> Result<()> {
             }
             Ok(())
         }
-        ast::Expr::Parenthesized(ref mut exprs) => {
-            for subexpr in exprs.iter_mut() {
-                rewrite_expr(subexpr)?;
+        _ => {
+            for child in child_exprs_mut(expr) {
+                rewrite_expr(child)?;
             }
-            let exprs = std::mem::take(exprs);
-            *expr = ast::Expr::Parenthesized(exprs);
-            Ok(())
-        }
-        // Process other expressions recursively
-        ast::Expr::Binary(lhs, _, rhs) => {
-            rewrite_expr(lhs)?;
-            rewrite_expr(rhs)?;
-            Ok(())
-        }
-        ast::Expr::FunctionCall { args, .. } => {
-            if let Some(args) = args {
-                for arg in args.iter_mut() {
-                    rewrite_expr(arg)?;
-                }
-            }
-            Ok(())
-        }
-        ast::Expr::Unary(_, arg) => {
-            rewrite_expr(arg)?;
             Ok(())
         }
-        _ => Ok(()),
     }
 }
 
@@ -14,9 +14,11 @@ use crate::{
 };
 
 use super::{
+    collate::expr_collation,
+    distinct::emit_distinct_check,
     emitter::TranslateCtx,
     expr::translate_expr,
-    plan::{Direction, ResultSetColumn, SelectPlan},
+    plan::{Direction, ResultSetColumn, SelectPlan, TableReference},
     result_row::{emit_offset, emit_result_row_and_limit},
 };
 
@@ -34,6 +36,7 @@ pub fn init_order_by(
     program: &mut ProgramBuilder,
     t_ctx: &mut TranslateCtx,
     order_by: &[(ast::Expr, Direction)],
+    tables: &[TableReference],
 ) -> Result<()> {
     let sort_cursor = program.alloc_cursor_id(None, CursorType::Sorter);
     t_ctx.meta_sort = Some(SortMetadata {
@@ -41,13 +44,20 @@ pub fn init_order_by(
         reg_sorter_data: program.alloc_register(),
     });
     let mut order = Vec::new();
-    for (_, direction) in order_by.iter() {
+    let mut collations = Vec::new();
+    for (expr, direction) in order_by.iter() {
         order.push(OwnedValue::Integer(*direction as i64));
+        collations.push(expr_collation(
+            expr,
+            Some(tables),
+            t_ctx.resolver.symbol_table,
+        )?);
     }
     program.emit_insn(Insn::SorterOpen {
         cursor_id: sort_cursor,
         columns: order_by.len(),
         order: Record::new(order),
+        collations,
     });
     Ok(())
 }
@@ -77,6 +87,7 @@ pub fn emit_order_by(
             is_rowid_alias: false,
             notnull: false,
             default: None,
+            collation: None,
         });
     }
     for i in 0..result_columns.len() {
@@ -95,6 +106,7 @@ pub fn emit_order_by(
             is_rowid_alias: false,
             notnull: false,
             default: None,
+            collation: None,
         });
     }
 
@@ -217,6 +229,15 @@ pub fn order_by_sorter_insert(
         cur_reg += 1;
     }
 
+    // With DISTINCT, a row is only sorted if its result columns are not a duplicate.
+    let label_duplicate = program.allocate_label();
+    if let Some(cursor_id) = t_ctx.meta_distinct.cursor_id {
+        let regs: Vec<usize> = (0..result_columns.len())
+            .map(|i| start_reg + t_ctx.result_column_indexes_in_orderby_sorter[i])
+            .collect();
+        emit_distinct_check(program, cursor_id, &regs, label_duplicate);
+    }
+
     let SortMetadata {
         sort_cursor,
         reg_sorter_data,
@@ -229,6 +250,7 @@ pub fn order_by_sorter_insert(
         sort_cursor,
         reg_sorter_data,
     );
+    program.resolve_label(label_duplicate, program.offset());
     Ok(())
 }
 
@@ -9,8 +9,8 @@ use std::{
 };
 
 use crate::{
-    function::AggFunc,
-    schema::{BTreeTable, Column, Index, Table},
+    function::{AggFunc, WindowFunc},
+    schema::{Affinity, BTreeTable, Column, Index, IndexColumn, Table},
     vdbe::{
         builder::{CursorType, ProgramBuilder},
         BranchOffset, CursorID,
@@ -20,10 +20,13 @@ use crate::{
 use crate::{
     schema::{PseudoTable, Type},
     types::SeekOp,
-    util::can_pushdown_predicate,
+    util::{can_pushdown_predicate, normalize_ident},
 };
 
-use super::emitter::OperationMode;
+use super::{
+    collate::expr_collation_name, constraint::ReplaceDeletes, emitter::OperationMode,
+    foreign_key::ForeignKeyChecks, trigger::RowTriggers,
+};
 
 #[derive(Debug, Clone)]
 pub struct ResultSetColumn {
@@ -77,11 +80,15 @@ pub struct GroupBy {
 pub struct WhereTerm {
     /// The original condition expression.
     pub expr: ast::Expr,
-    /// Is this condition originally from an OUTER JOIN?
+    /// Is this condition originally from an OUTER JOIN (LEFT, RIGHT or FULL)?
     /// If so, we need to evaluate it at the loop of the right table in that JOIN,
     /// regardless of which tables it references.
     /// We also cannot e.g. short circuit the entire query in the optimizer if the condition is statically false.
     pub from_outer_join: bool,
+    /// The index of the table whose ON or USING clause this condition comes from, if any.
+    /// The conditions of a join decide whether a row of the right table has a match, so they are
+    /// evaluated before the match flags of OUTER JOINs are set, and all other conditions after.
+    pub from_join: Option<usize>,
     pub eval_at: EvalAt,
 }
 
@@ -93,6 +100,19 @@ impl WhereTerm {
     pub fn should_eval_at_loop(&self, loop_idx: usize) -> bool {
         self.eval_at == EvalAt::Loop(loop_idx)
     }
+
+    /// Whether the condition may be used to search the table at `table_index`, e.g. as the key
+    /// of an index seek. A condition that does not come from the join of an OUTER JOIN table must
+    /// still be evaluated for the rows of that table that are emitted with NULLs, so it cannot be
+    /// used to skip rows of that table.
+    pub fn can_search_table(&self, table_index: usize, table: &TableReference) -> bool {
+        let null_extended = table
+            .join_info
+            .as_ref()
+            .is_some_and(|join_info| join_info.outer || join_info.right);
+        self.should_eval_at_loop(table_index)
+            && (!null_extended || self.from_join == Some(table_index))
+    }
 }
 
 use crate::ast::{Expr, Operator};
@@ -253,6 +273,42 @@ pub enum SelectQueryType {
         /// The index of the first instruction in the bytecode that implements the subquery.
         coroutine_implementation_start: BranchOffset,
     },
+    /// Each result row is inserted into the ephemeral index opened on `cursor_id`.
+    /// Used to collect the intermediate results of a compound SELECT, and the rows of an IN
+    /// subquery, which get the `affinity` of the comparison with the value searched in them.
+    IndexInsert {
+        cursor_id: usize,
+        affinity: Option<Affinity>,
+    },
+    /// Each result row is added to the queue of a recursive CTE, see [RecursiveCte].
+    RecursiveQueue {
+        /// The ephemeral index that holds the queue, keyed by the position of the row in the queue.
+        queue_cursor_id: usize,
+        /// The result columns that the queue is sorted by before the position, see
+        /// [RecursiveCte::order_by].
+        order_by_columns: Vec<usize>,
+        /// The ephemeral index of the rows that were ever added to the queue, used to skip
+        /// duplicate rows when the recursive members are combined with UNION.
+        distinct_cursor_id: Option<usize>,
+        /// The register that holds the number of rows ever added to the queue.
+        reg_queue_len: usize,
+    },
+    /// A reference to a recursive CTE from one of its own recursive members.
+    /// The plan is never emitted: the current row of the CTE is already in the registers of the
+    /// subquery, so the loop over it runs exactly once.
+    RecursiveRow,
+    /// The first result row is copied to the registers starting at `dest_reg`, and the query stops.
+    /// Used for a scalar subquery in an expression, see [ExprSubquery].
+    Scalar {
+        dest_reg: usize,
+        label_done: BranchOffset,
+    },
+    /// `dest_reg` is set to 1 as soon as the query returns a row, and the query stops.
+    /// Used for an EXISTS subquery in an expression, see [ExprSubquery].
+    Exists {
+        dest_reg: usize,
+        label_done: BranchOffset,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -261,6 +317,9 @@ pub struct SelectPlan {
     pub table_references: Vec<TableReference>,
     /// the columns inside SELECT ... FROM
     pub result_columns: Vec<ResultSetColumn>,
+    /// SELECT DISTINCT: a result row is skipped if an equal one was already returned, see
+    /// [super::distinct::emit_distinct_check].
+    pub distinct: bool,
     /// where clause split into a vec at 'AND' boundaries. all join conditions also get shoved in here,
     /// and we keep track of which join they came from (mainly for OUTER JOIN processing)
     pub where_clause: Vec<WhereTerm>,
@@ -278,6 +337,75 @@ pub struct SelectPlan {
     pub contains_constant_false_condition: bool,
     /// query type (top level or subquery)
     pub query_type: SelectQueryType,
+    /// the SELECTs combined with this one using UNION, UNION ALL, INTERSECT or EXCEPT, in order.
+    /// compound operators are left-associative and have equal precedence, so
+    /// `a UNION b EXCEPT c` is evaluated as `(a UNION b) EXCEPT c`.
+    /// ORDER BY and LIMIT of a compound SELECT apply to the whole result, so the planner
+    /// never puts them on the members themselves.
+    pub compounds: Vec<(ast::CompoundOperator, SelectPlan)>,
+    /// the windows of the window functions called in the result columns and order by clause.
+    /// a plan with windows always reads from a single subquery that evaluates everything except
+    /// the window functions, see [super::window::plan_windows].
+    pub windows: Vec<Window>,
+    /// the recursive members of a recursive CTE. the plan itself, together with its compounds,
+    /// is the initial part of the CTE.
+    pub recursive: Option<Box<RecursiveCte>>,
+    /// the subqueries in the expressions of the query, e.g. in the WHERE clause or the result columns.
+    /// each of them is referred to by an [ast::Expr::SubqueryResult] with its index in this vec.
+    pub expr_subqueries: Vec<ExprSubquery>,
+}
+
+/// A subquery in an expression: `EXISTS (SELECT ...)`, `x IN (SELECT ...)` or a scalar subquery
+/// `(SELECT ...)`.
+///
+/// A subquery can refer to the tables of the queries that contain it. Those values are evaluated
+/// by the containing query every time it runs the subquery, see [ast::Expr::OuterRef].
+/// A subquery that does not refer to any outer values only runs once.
+#[derive(Debug, Clone)]
+pub struct ExprSubquery {
+    pub plan: Box<SelectPlan>,
+    pub kind: ExprSubqueryKind,
+    /// The number of values of outer queries that the subquery refers to.
+    pub num_outer_refs: usize,
+    /// For an IN subquery, the affinity applied to its rows and to the value searched in them,
+    /// see [super::expr::comparison_affinity].
+    pub affinity: Affinity,
+    /// For an IN subquery, the collating sequence used to compare the value with its rows.
+    pub collation: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExprSubqueryKind {
+    /// `(SELECT ...)`: the value of the first column of the first row, or NULL if there are no rows.
+    Scalar,
+    /// `EXISTS (SELECT ...)`: whether there is at least one row.
+    Exists,
+    /// `x IN (SELECT ...)`: the rows are collected in an ephemeral index, which is then searched for x.
+    In,
+}
+
+/// The recursive part of a recursive CTE, e.g. in
+/// WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x+1 FROM c WHERE x<10) SELECT x FROM c
+/// the recursive member is `SELECT x+1 FROM c WHERE x<10`.
+///
+/// The rows of the initial part are added to a queue. Then rows are taken from the queue one at a
+/// time, and each row is both sent to the destination of the CTE and used as the only row of the
+/// CTE while the recursive members run, which add more rows to the queue. The CTE is done when
+/// the queue is empty.
+#[derive(Debug, Clone)]
+pub struct RecursiveCte {
+    /// the SELECTs that read the CTE itself, in order.
+    pub members: Vec<SelectPlan>,
+    /// true if the members are combined with UNION, in which case a row is only added to the queue
+    /// if it was never added before. this is what guarantees termination on cyclic data.
+    pub distinct: bool,
+    /// the ORDER BY terms, as the leading columns of the queue, which is read in their order
+    /// instead of first in, first out. `pos_in_table` is the result column that a term sorts by.
+    pub order_by: Vec<IndexColumn>,
+    /// limit clause. the recursion stops as soon as the limit is reached.
+    pub limit: Option<isize>,
+    /// offset clause
+    pub offset: Option<isize>,
 }
 
 #[allow(dead_code)]
@@ -285,7 +413,7 @@ pub struct SelectPlan {
 pub struct DeletePlan {
     /// List of table references. Delete is always a single table.
     pub table_references: Vec<TableReference>,
-    /// the columns inside SELECT ... FROM
+    /// the columns of the RETURNING clause
     pub result_columns: Vec<ResultSetColumn>,
     /// where clause split into a vec at 'AND' boundaries.
     pub where_clause: Vec<WhereTerm>,
@@ -297,6 +425,14 @@ pub struct DeletePlan {
     pub offset: Option<isize>,
     /// query contains a constant condition that is always false
     pub contains_constant_false_condition: bool,
+    /// the triggers that fire for each deleted row
+    pub triggers: RowTriggers,
+    /// the foreign key constraints checked for each deleted row
+    pub foreign_keys: ForeignKeyChecks,
+    /// the indexes on the table, which lose the entries of the deleted rows
+    pub indexes: Vec<Arc<Index>>,
+    /// the subqueries in the WHERE clause, see [SelectPlan::expr_subqueries]
+    pub expr_subqueries: Vec<ExprSubquery>,
 }
 
 #[derive(Debug, Clone)]
@@ -309,10 +445,39 @@ pub struct UpdatePlan {
     pub order_by: Option<Vec<(ast::Expr, Direction)>>,
     pub limit: Option<isize>,
     pub offset: Option<isize>,
-    // TODO: optional RETURNING clause
+    // the columns of the RETURNING clause
     pub returning: Option<Vec<ResultSetColumn>>,
     // whether the WHERE clause is always false
     pub contains_constant_false_condition: bool,
+    // the triggers that fire for each updated row
+    pub triggers: RowTriggers,
+    // the foreign key constraints checked for each updated row
+    pub foreign_keys: ForeignKeyChecks,
+    // what else happens to the rows that UPDATE OR REPLACE deletes
+    pub replace_deletes: ReplaceDeletes,
+    // the conflict resolution algorithm of UPDATE OR ...
+    pub or_conflict: Option<ast::ResolveType>,
+    // the indexes on the table, whose entries the update maintains
+    pub indexes: Vec<Arc<Index>>,
+    // the subqueries in the SET and WHERE clauses, see [SelectPlan::expr_subqueries]
+    pub expr_subqueries: Vec<ExprSubquery>,
+}
+
+impl UpdatePlan {
+    /// Returns true if the update changes the PRIMARY KEY of a WITHOUT ROWID table or the INTEGER
+    /// PRIMARY KEY of a rowid table, which moves the updated rows in the table b-tree.
+    pub fn moves_rows(&self) -> bool {
+        self.table_references[0].btree().is_some_and(|table| {
+            let primary_key = table.primary_key_column_positions();
+            self.set_clauses.iter().any(|(position, _)| {
+                if table.has_rowid {
+                    table.columns[*position].is_rowid_alias
+                } else {
+                    primary_key.contains(position)
+                }
+            })
+        })
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -345,25 +510,111 @@ pub fn select_star(tables: &[TableReference], out_columns: &mut Vec<ResultSetCol
                         true
                     }
                 })
-                .map(|(i, col)| ResultSetColumn {
-                    alias: None,
-                    expr: ast::Expr::Column {
+                .map(|(i, col)| {
+                    let expr = ast::Expr::Column {
                         database: None,
                         table: current_table_index,
                         column: i,
                         is_rowid_alias: col.is_rowid_alias,
-                    },
-                    contains_aggregates: false,
+                    };
+                    // In a RIGHT or FULL OUTER JOIN with USING, the column of the left table is NULL for the
+                    // rows of the right table without a match, so the column is the first non-NULL of the two.
+                    let using_columns = col
+                        .name
+                        .as_ref()
+                        .map(|name| using_columns(tables, name))
+                        .unwrap_or_default();
+                    if using_columns.len() > 1 && using_columns[0] == (current_table_index, i) {
+                        ResultSetColumn {
+                            alias: col.name.clone(),
+                            expr: using_column_expr(tables, &using_columns),
+                            contains_aggregates: false,
+                        }
+                    } else {
+                        ResultSetColumn {
+                            alias: None,
+                            expr,
+                            contains_aggregates: false,
+                        }
+                    }
                 }),
         );
     }
 }
 
+/// Returns the columns that make up the USING column `name` of the given tables, as pairs of table and
+/// column index: the column of the first table that has it, followed by the columns of the tables that
+/// are joined with USING on that column in a RIGHT or FULL OUTER JOIN. The first column is NULL for the
+/// rows of those tables without a match, so the value of the USING column is the first non-NULL of them.
+pub fn using_columns(tables: &[TableReference], name: &str) -> Vec<(usize, usize)> {
+    let find_column = |table: &TableReference| {
+        table.columns().iter().position(|col| {
+            col.name
+                .as_ref()
+                .is_some_and(|n| n.eq_ignore_ascii_case(name))
+        })
+    };
+    let Some((first_table_index, first_column)) = tables
+        .iter()
+        .enumerate()
+        .find_map(|(table_index, table)| Some((table_index, find_column(table)?)))
+    else {
+        return vec![];
+    };
+    let mut columns = vec![(first_table_index, first_column)];
+    for (table_index, table) in tables.iter().enumerate().skip(first_table_index + 1) {
+        let Some(join_info) = table.join_info.as_ref() else {
+            continue;
+        };
+        let joined_using = join_info
+            .using
+            .as_ref()
+            .is_some_and(|using| using.iter().any(|col| col.0.eq_ignore_ascii_case(name)));
+        if join_info.right && joined_using {
+            if let Some(column) = find_column(table) {
+                columns.push((table_index, column));
+            }
+        }
+    }
+    columns
+}
+
+/// Returns the expression for a USING column, see [using_columns].
+pub fn using_column_expr(tables: &[TableReference], columns: &[(usize, usize)]) -> ast::Expr {
+    let mut args = columns
+        .iter()
+        .map(|&(table_index, column)| ast::Expr::Column {
+            database: None,
+            table: table_index,
+            column,
+            is_rowid_alias: tables[table_index].columns()[column].is_rowid_alias,
+        })
+        .collect::<Vec<_>>();
+    if args.len() == 1 {
+        return args.pop().unwrap();
+    }
+    ast::Expr::FunctionCall {
+        name: ast::Id("coalesce".to_string()),
+        distinctness: None,
+        args: Some(args),
+        order_by: None,
+        filter_over: None,
+    }
+}
+
 /// Join information for a table reference.
 #[derive(Debug, Clone)]
 pub struct JoinInfo {
-    /// Whether this is an OUTER JOIN.
+    /// Whether this is a LEFT or FULL OUTER JOIN, i.e. a row of the tables to the left without
+    /// a matching row in this table is emitted with NULLs for this table.
     pub outer: bool,
+    /// Whether this is a RIGHT or FULL OUTER JOIN, i.e. a row of this table without a matching
+    /// row in the tables to the left is emitted with NULLs for the tables to the left.
+    /// These rows are emitted after the main loop, see [super::main_loop::emit_unmatched_rows].
+    pub right: bool,
+    /// Whether this is a CROSS JOIN, which SQLite never reorders, see
+    /// [super::optimizer::reorder_joins].
+    pub cross: bool,
     /// The USING clause for the join, if any. NATURAL JOIN is transformed into USING (col1, col2, ...).
     pub using: Option<ast::DistinctNames>,
 }
@@ -387,6 +638,11 @@ pub struct TableReference {
     /// Bitmask of columns that are referenced in the query.
     /// Used to decide whether a covering index can be used.
     pub col_used_mask: ColumnUsedMask,
+    /// The index of the database of the table in the connection, see [crate::schema::Schema::database].
+    pub database: usize,
+    /// The name of the database of the table, e.g. "main", which qualifies its columns in
+    /// `database.table.column` references.
+    pub database_name: String,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -452,6 +708,7 @@ impl Operation {
             Operation::Scan { index, .. } => index.as_ref(),
             Operation::Search(Search::RowidEq { .. }) => None,
             Operation::Search(Search::Seek { index, .. }) => index.as_ref(),
+            Operation::Search(Search::Hash { index, .. }) => Some(index),
             Operation::Subquery { .. } => None,
         }
     }
@@ -485,6 +742,8 @@ impl TableReference {
                     primary_key: false,
                     notnull: false,
                     default: None,
+                    collation: expr_collation_name(&rc.expr, Some(&plan.table_references))
+                        .map(normalize_ident),
                 })
                 .collect(),
         )));
@@ -497,6 +756,8 @@ impl TableReference {
             identifier: identifier.clone(),
             join_info,
             col_used_mask: ColumnUsedMask::new(),
+            database: 0,
+            database_name: "main".to_string(),
         }
     }
 
@@ -510,6 +771,26 @@ impl TableReference {
         self.col_used_mask.set(index);
     }
 
+    /// The name the cursor of this table reference is registered under in the program. Tables of
+    /// attached databases are prefixed with the database name so that e.g. `main.t` and `aux.t`
+    /// get distinct cursors.
+    pub fn cursor_key(&self) -> String {
+        if self.database == 0 {
+            self.identifier.clone()
+        } else {
+            format!("{}.{}", self.database_name, self.identifier)
+        }
+    }
+
+    /// The name the cursor of `index`, an index of this table, is registered under.
+    pub fn index_cursor_key(&self, index: &Index) -> String {
+        if self.database == 0 {
+            index.name.clone()
+        } else {
+            format!("{}.{}", self.database_name, index.name)
+        }
+    }
+
     /// Open the necessary cursors for this table reference.
     /// Generally a table cursor is always opened unless a SELECT query can use a covering index.
     /// An index cursor is opened if an index is used in any way for reading data from the table.
@@ -529,29 +810,37 @@ impl TableReference {
                     None
                 } else {
                     Some(program.alloc_cursor_id(
-                        Some(self.identifier.clone()),
+                        Some(self.cursor_key()),
                         CursorType::BTreeTable(btree.clone()),
                     ))
                 };
-                let index_cursor_id = if let Some(index) = index {
-                    Some(program.alloc_cursor_id(
-                        Some(index.name.clone()),
+                // The PRIMARY KEY index of a WITHOUT ROWID table is searched through the table cursor.
+                let index_cursor_id = match index {
+                    Some(index) if btree.is_primary_key_index(index) => table_cursor_id,
+                    Some(index) if matches!(self.op, Operation::Search(Search::Hash { .. })) => {
+                        Some(program.alloc_cursor_id(
+                            Some(self.index_cursor_key(index)),
+                            CursorType::HashTable(index.clone()),
+                        ))
+                    }
+                    Some(index) => Some(program.alloc_cursor_id(
+                        Some(self.index_cursor_key(index)),
                         CursorType::BTreeIndex(index.clone()),
-                    ))
-                } else {
-                    None
+                    )),
+                    None => None,
                 };
                 Ok((table_cursor_id, index_cursor_id))
             }
             Table::Virtual(virtual_table) => {
                 let table_cursor_id = Some(program.alloc_cursor_id(
-                    Some(self.identifier.clone()),
+                    Some(self.cursor_key()),
                     CursorType::VirtualTable(virtual_table.clone()),
                 ));
                 let index_cursor_id = None;
                 Ok((table_cursor_id, index_cursor_id))
             }
             Table::Pseudo(_) => Ok((None, None)),
+            Table::View(_) => unreachable!("views are planned as subqueries"),
         }
     }
 
@@ -561,21 +850,35 @@ impl TableReference {
         program: &mut ProgramBuilder,
     ) -> Result<(Option<CursorID>, Option<CursorID>)> {
         let index = self.op.index();
-        let table_cursor_id = program.resolve_cursor_id_safe(&self.identifier);
-        let index_cursor_id = index.map(|index| program.resolve_cursor_id(&index.name));
+        let table_cursor_id = program.resolve_cursor_id_safe(&self.cursor_key());
+        let index_cursor_id = match index {
+            Some(index) if self.is_primary_key_index(index) => table_cursor_id,
+            Some(index) => Some(program.resolve_cursor_id(&self.index_cursor_key(index))),
+            None => None,
+        };
         Ok((table_cursor_id, index_cursor_id))
     }
 
+    /// Returns true if `index` is the PRIMARY KEY index of a WITHOUT ROWID table, i.e. the b-tree
+    /// of the table itself.
+    pub fn is_primary_key_index(&self, index: &Index) -> bool {
+        self.btree()
+            .is_some_and(|btree| btree.is_primary_key_index(index))
+    }
+
     /// Returns true if a given index is a covering index for this [TableReference].
     pub fn index_is_covering(&self, index: &Index) -> bool {
         let Table::BTree(btree) = &self.table else {
             return false;
         };
-        if self.col_used_mask.is_empty() {
+        if self.col_used_mask.is_empty()
+            || btree.is_primary_key_index(index)
+            || index.where_clause.is_some()
+        {
             return false;
         }
         let mut index_cols_mask = ColumnUsedMask::new();
-        for col in index.columns.iter() {
+        for col in index.columns.iter().filter(|col| col.expr.is_none()) {
             index_cols_mask.set(col.pos_in_table);
         }
 
@@ -676,6 +979,14 @@ pub enum Search {
         index: Option<Arc<Index>>,
         seek_def: SeekDef,
     },
+    /// A hash join: a hash table is built from the rows of the table, then probed with the values
+    /// of `key`, converted with `affinities`. Its entries are laid out like the ones of the
+    /// ephemeral `index`, whose first columns are the ones compared with `key`.
+    Hash {
+        index: Arc<Index>,
+        key: Vec<ast::Expr>,
+        affinities: Vec<Affinity>,
+    },
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -697,9 +1008,23 @@ impl Display for Direction {
 pub struct Aggregate {
     pub func: AggFunc,
     pub args: Vec<ast::Expr>,
+    /// The condition of the FILTER (WHERE ...) clause; only the rows for which it is true are
+    /// passed to the aggregate.
+    pub filter: Option<ast::Expr>,
+    /// Whether the aggregate was called with DISTINCT, in which case a value of its argument is
+    /// only passed to it once.
+    pub distinct: bool,
     pub original_expr: ast::Expr,
 }
 
+impl Aggregate {
+    /// The number of columns of the aggregate in the GROUP BY sorter: its arguments, followed by
+    /// the value of its filter condition, if any.
+    pub fn num_sorter_columns(&self) -> usize {
+        self.args.len() + self.filter.is_some() as usize
+    }
+}
+
 impl Display for Aggregate {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         let args_str = self
@@ -712,6 +1037,55 @@ impl Display for Aggregate {
     }
 }
 
+/// A window, i.e. the PARTITION BY and ORDER BY clauses shared by one or more window function calls.
+/// Window function calls that only differ in their frame share the same window.
+#[derive(Clone, Debug)]
+pub struct Window {
+    pub partition_by: Vec<ast::Expr>,
+    pub order_by: Vec<(ast::Expr, Direction)>,
+    pub functions: Vec<WindowFunction>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WindowFunction {
+    pub func: WindowFunctionKind,
+    pub args: Vec<ast::Expr>,
+    /// The condition of the FILTER (WHERE ...) clause of an aggregate, a result column of the
+    /// subquery like the arguments.
+    pub filter: Option<ast::Expr>,
+    pub frame: WindowFrame,
+    pub original_expr: ast::Expr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WindowFunctionKind {
+    /// A built-in window function, e.g. rank()
+    Builtin(WindowFunc),
+    /// An aggregate function called with an OVER clause, e.g. sum(x) OVER (...)
+    Aggregate(AggFunc),
+}
+
+/// The set of rows of a partition that a window function is computed over, relative to the current row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowFrame {
+    pub mode: ast::FrameMode,
+    pub start: ast::FrameBound,
+    pub end: ast::FrameBound,
+    pub exclude: ast::FrameExclude,
+}
+
+impl Default for WindowFrame {
+    /// RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW
+    fn default() -> Self {
+        Self {
+            mode: ast::FrameMode::Range,
+            start: ast::FrameBound::UnboundedPreceding,
+            end: ast::FrameBound::CurrentRow,
+            exclude: ast::FrameExclude::NoOthers,
+        }
+    }
+}
+
 /// For EXPLAIN QUERY PLAN
 impl Display for Plan {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -725,6 +1099,73 @@ impl Display for Plan {
 
 impl Display for SelectPlan {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.compounds.is_empty() {
+            return self.fmt_loops(f);
+        }
+        writeln!(f, "QUERY PLAN")?;
+        writeln!(f, "`--COMPOUND QUERY")?;
+        let mut first = String::new();
+        self.fmt_loops(&mut first)?;
+        let members = std::iter::once(("LEFT-MOST SUBQUERY".to_string(), first));
+        let rest = self.compounds.iter().map(|(operator, plan)| {
+            let name = match operator {
+                ast::CompoundOperator::UnionAll => operator.to_string(),
+                _ => format!("{} USING TEMP B-TREE", operator),
+            };
+            (name, plan.to_string())
+        });
+        let members: Vec<_> = members.chain(rest).collect();
+        for (i, (name, member)) in members.iter().enumerate() {
+            let is_last = i == members.len() - 1;
+            writeln!(f, "   {}{}", if is_last { "`--" } else { "|--" }, name)?;
+            // Skip the QUERY PLAN header of the member and nest its loops under the operator
+            for line in member.lines().skip(1) {
+                writeln!(f, "   {}{}", if is_last { "   " } else { "|  " }, line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SelectPlan {
+    /// Whether the query reads any table, either directly, through one of its compound members or
+    /// through a subquery in one of its expressions. Such a query needs a read transaction even
+    /// without a FROM clause, e.g. `SELECT (SELECT max(a) FROM t)`.
+    pub fn reads_tables(&self) -> bool {
+        !self.table_references.is_empty()
+            || self
+                .compounds
+                .iter()
+                .any(|(_, member)| member.reads_tables())
+            || self
+                .expr_subqueries
+                .iter()
+                .any(|subquery| subquery.plan.reads_tables())
+    }
+
+    /// Whether the query reads the b-tree at `root_page` in `database`, directly or through a
+    /// subquery.
+    pub fn reads_btree(&self, database: usize, root_page: usize) -> bool {
+        self.table_references.iter().any(|table| match &table.op {
+            Operation::Subquery { plan, .. } => plan.reads_btree(database, root_page),
+            _ => {
+                table.database == database
+                    && table
+                        .btree()
+                        .is_some_and(|btree| btree.root_page == root_page)
+            }
+        }) || self
+            .compounds
+            .iter()
+            .any(|(_, member)| member.reads_btree(database, root_page))
+            || self
+                .expr_subqueries
+                .iter()
+                .any(|subquery| subquery.plan.reads_btree(database, root_page))
+    }
+
+    /// Writes the loops of a single (non-compound) SELECT for EXPLAIN QUERY PLAN.
+    fn fmt_loops(&self, f: &mut dyn fmt::Write) -> fmt::Result {
         writeln!(f, "QUERY PLAN")?;
 
         // Print each table reference with appropriate indentation based on join depth
@@ -767,6 +1208,13 @@ impl Display for SelectPlan {
                             indent, reference.identifier, index.name
                         )?;
                     }
+                    Search::Hash { .. } => {
+                        writeln!(
+                            f,
+                            "{}SEARCH {} USING HASH TABLE",
+                            indent, reference.identifier
+                        )?;
+                    }
                 },
                 Operation::Subquery { plan, .. } => {
                     writeln!(f, "{}SUBQUERY {}", indent, reference.identifier)?;
@@ -858,6 +1306,13 @@ impl fmt::Display for UpdatePlan {
                             indent, reference.identifier, index.name
                         )?;
                     }
+                    Search::Hash { .. } => {
+                        writeln!(
+                            f,
+                            "{}SEARCH {} USING HASH TABLE",
+                            indent, reference.identifier
+                        )?;
+                    }
                 },
                 Operation::Subquery { plan, .. } => {
                     writeln!(f, "{}SUBQUERY {}", indent, reference.identifier)?;
@@ -1,14 +1,19 @@
+use std::cell::RefCell;
+
 use super::{
+    collate::expr_collation_name,
     plan::{
-        Aggregate, ColumnUsedMask, EvalAt, IterationDirection, JoinInfo, Operation, Plan,
+        using_column_expr, using_columns, Aggregate, ColumnUsedMask, EvalAt, ExprSubquery,
+        ExprSubqueryKind, IterationDirection, JoinInfo, Operation, Plan, RecursiveCte,
         ResultSetColumn, SelectPlan, SelectQueryType, TableReference, WhereTerm,
     },
     select::prepare_select_plan,
+    window::{child_exprs_mut, window_exprs},
     SymbolTable,
 };
 use crate::{
     function::Func,
-    schema::{Schema, Table},
+    schema::{Affinity, Column, IndexColumn, Schema, Table, View},
     util::{exprs_are_equivalent, normalize_ident, vtable_args},
     vdbe::BranchOffset,
     Result,
@@ -27,7 +32,51 @@ pub fn resolve_aggregates(expr: &Expr, aggs: &mut Vec<Aggregate>) -> bool {
         return true;
     }
     match expr {
-        Expr::FunctionCall { name, args, .. } => {
+        // A window function is evaluated after the aggregates, so it is not an aggregate itself,
+        // but its arguments and window definition may contain aggregates.
+        Expr::FunctionCall {
+            args,
+            filter_over:
+                Some(ast::FunctionTail {
+                    over_clause: Some(over),
+                    ..
+                }),
+            ..
+        } => {
+            let mut contains_aggregates = false;
+            for arg in args.iter().flatten() {
+                contains_aggregates |= resolve_aggregates(arg, aggs);
+            }
+            if let ast::Over::Window(window) = over.as_ref() {
+                for expr in window_exprs(window) {
+                    contains_aggregates |= resolve_aggregates(expr, aggs);
+                }
+            }
+            contains_aggregates
+        }
+        Expr::FunctionCallStar {
+            filter_over:
+                Some(ast::FunctionTail {
+                    over_clause: Some(over),
+                    ..
+                }),
+            ..
+        } => {
+            let mut contains_aggregates = false;
+            if let ast::Over::Window(window) = over.as_ref() {
+                for expr in window_exprs(window) {
+                    contains_aggregates |= resolve_aggregates(expr, aggs);
+                }
+            }
+            contains_aggregates
+        }
+        Expr::FunctionCall {
+            name,
+            distinctness,
+            args,
+            filter_over,
+            ..
+        } => {
             let args_count = if let Some(args) = &args {
                 args.len()
             } else {
@@ -38,6 +87,8 @@ pub fn resolve_aggregates(expr: &Expr, aggs: &mut Vec<Aggregate>) -> bool {
                     aggs.push(Aggregate {
                         func: f,
                         args: args.clone().unwrap_or_default(),
+                        filter: aggregate_filter(filter_over),
+                        distinct: aggregate_distinct(distinctness),
                         original_expr: expr.clone(),
                     });
                     true
@@ -53,13 +104,15 @@ pub fn resolve_aggregates(expr: &Expr, aggs: &mut Vec<Aggregate>) -> bool {
                 }
             }
         }
-        Expr::FunctionCallStar { name, .. } => {
+        Expr::FunctionCallStar { name, filter_over } => {
             if let Ok(Func::Agg(f)) =
                 Func::resolve_function(normalize_ident(name.0.as_str()).as_str(), 0)
             {
                 aggs.push(Aggregate {
                     func: f,
                     args: vec![],
+                    filter: aggregate_filter(filter_over),
+                    distinct: false,
                     original_expr: expr.clone(),
                 });
                 true
@@ -78,11 +131,25 @@ pub fn resolve_aggregates(expr: &Expr, aggs: &mut Vec<Aggregate>) -> bool {
             contains_aggregates |= resolve_aggregates(expr, aggs);
             contains_aggregates
         }
+        // The aggregates in a subquery belong to the subquery.
+        Expr::SubqueryResult { lhs: Some(lhs), .. } => resolve_aggregates(lhs, aggs),
         // TODO: handle other expressions that may contain aggregates
         _ => false,
     }
 }
 
+/// Returns the condition of the FILTER clause of an aggregate function call, if any.
+pub fn aggregate_filter(filter_over: &Option<ast::FunctionTail>) -> Option<Expr> {
+    filter_over
+        .as_ref()
+        .and_then(|tail| tail.filter_clause.as_deref().cloned())
+}
+
+/// Returns whether an aggregate function was called with DISTINCT.
+pub fn aggregate_distinct(distinctness: &Option<ast::Distinctness>) -> bool {
+    matches!(distinctness, Some(ast::Distinctness::Distinct))
+}
+
 pub fn bind_column_references(
     expr: &mut Expr,
     referenced_tables: &mut [TableReference],
@@ -95,43 +162,13 @@ pub fn bind_column_references(
             if id.0.eq_ignore_ascii_case("true") || id.0.eq_ignore_ascii_case("false") {
                 return Ok(());
             }
-            let normalized_id = normalize_ident(id.0.as_str());
-
-            if !referenced_tables.is_empty() {
-                if let Some(row_id_expr) =
-                    parse_row_id(&normalized_id, 0, || referenced_tables.len() != 1)?
-                {
-                    *expr = row_id_expr;
-
-                    return Ok(());
-                }
-            }
-            let mut match_result = None;
-            for (tbl_idx, table) in referenced_tables.iter().enumerate() {
-                let col_idx = table.columns().iter().position(|c| {
-                    c.name
-                        .as_ref()
-                        .map_or(false, |name| name.eq_ignore_ascii_case(&normalized_id))
-                });
-                if col_idx.is_some() {
-                    if match_result.is_some() {
-                        crate::bail_parse_error!("Column {} is ambiguous", id.0);
-                    }
-                    let col = table.columns().get(col_idx.unwrap()).unwrap();
-                    match_result = Some((tbl_idx, col_idx.unwrap(), col.is_rowid_alias));
-                }
-            }
-            if let Some((tbl_idx, col_idx, is_rowid_alias)) = match_result {
-                *expr = Expr::Column {
-                    database: None, // TODO: support different databases
-                    table: tbl_idx,
-                    column: col_idx,
-                    is_rowid_alias,
-                };
-                referenced_tables[tbl_idx].mark_column_used(col_idx);
+            let name = id.0.clone();
+            if let Some(resolved) = resolve_column_reference(expr, referenced_tables)? {
+                mark_columns_used(&resolved, referenced_tables);
+                *expr = resolved;
                 return Ok(());
             }
-
+            let normalized_id = normalize_ident(name.as_str());
             if let Some(result_columns) = result_columns {
                 for result_column in result_columns.iter() {
                     if result_column
@@ -143,44 +180,16 @@ pub fn bind_column_references(
                     }
                 }
             }
-            crate::bail_parse_error!("Column {} not found", id.0);
+            crate::bail_parse_error!("Column {} not found", name);
         }
-        Expr::Qualified(tbl, id) => {
+        Expr::Qualified(tbl, _) => {
             let normalized_table_name = normalize_ident(tbl.0.as_str());
-            let matching_tbl_idx = referenced_tables
-                .iter()
-                .position(|t| t.identifier.eq_ignore_ascii_case(&normalized_table_name));
-            if matching_tbl_idx.is_none() {
-                crate::bail_parse_error!("Table {} not found", normalized_table_name);
-            }
-            let tbl_idx = matching_tbl_idx.unwrap();
-            let normalized_id = normalize_ident(id.0.as_str());
-
-            if let Some(row_id_expr) = parse_row_id(&normalized_id, tbl_idx, || false)? {
-                *expr = row_id_expr;
-
+            if let Some(resolved) = resolve_column_reference(expr, referenced_tables)? {
+                mark_columns_used(&resolved, referenced_tables);
+                *expr = resolved;
                 return Ok(());
             }
-            let col_idx = referenced_tables[tbl_idx].columns().iter().position(|c| {
-                c.name
-                    .as_ref()
-                    .map_or(false, |name| name.eq_ignore_ascii_case(&normalized_id))
-            });
-            if col_idx.is_none() {
-                crate::bail_parse_error!("Column {} not found", normalized_id);
-            }
-            let col = referenced_tables[tbl_idx]
-                .columns()
-                .get(col_idx.unwrap())
-                .unwrap();
-            *expr = Expr::Column {
-                database: None, // TODO: support different databases
-                table: tbl_idx,
-                column: col_idx.unwrap(),
-                is_rowid_alias: col.is_rowid_alias,
-            };
-            referenced_tables[tbl_idx].mark_column_used(col_idx.unwrap());
-            Ok(())
+            crate::bail_parse_error!("Table {} not found", normalized_table_name);
         }
         Expr::Between {
             lhs,
@@ -226,20 +235,37 @@ pub fn bind_column_references(
             distinctness: _,
             args,
             order_by: _,
-            filter_over: _,
+            filter_over,
         } => {
             if let Some(args) = args {
                 for arg in args {
                     bind_column_references(arg, referenced_tables, result_columns)?;
                 }
             }
-            Ok(())
+            bind_function_tail_column_references(filter_over, referenced_tables, result_columns)
         }
         // Already bound earlier
         Expr::Column { .. } | Expr::RowId { .. } => Ok(()),
-        Expr::DoublyQualified(_, _, _) => todo!(),
-        Expr::Exists(_) => todo!(),
-        Expr::FunctionCallStar { .. } => Ok(()),
+        Expr::DoublyQualified(db, tbl, _) => {
+            let qualified_table_name =
+                format!("{}.{}", normalize_ident(&db.0), normalize_ident(&tbl.0));
+            if let Some(resolved) = resolve_column_reference(expr, referenced_tables)? {
+                mark_columns_used(&resolved, referenced_tables);
+                *expr = resolved;
+                return Ok(());
+            }
+            crate::bail_parse_error!("Table {} not found", qualified_table_name);
+        }
+        // Subqueries are planned before binding, see [plan_subqueries].
+        Expr::Exists(_) | Expr::InSelect { .. } | Expr::Subquery(_) => {
+            crate::bail_parse_error!(
+                "subqueries are only supported in SELECT, and in the WHERE and SET clauses of DELETE and UPDATE"
+            )
+        }
+        Expr::FunctionCallStar {
+            name: _,
+            filter_over,
+        } => bind_function_tail_column_references(filter_over, referenced_tables, result_columns),
         Expr::InList { lhs, not: _, rhs } => {
             bind_column_references(lhs, referenced_tables, result_columns)?;
             if let Some(rhs) = rhs {
@@ -249,7 +275,6 @@ pub fn bind_column_references(
             }
             Ok(())
         }
-        Expr::InSelect { .. } => todo!(),
         Expr::InTable { .. } => todo!(),
         Expr::IsNull(expr) => {
             bind_column_references(expr, referenced_tables, result_columns)?;
@@ -272,8 +297,27 @@ pub fn bind_column_references(
             }
             Ok(())
         }
-        Expr::Raise(_, _) => todo!(),
-        Expr::Subquery(_) => todo!(),
+        Expr::Raise(_, message) => {
+            if let Some(message) = message {
+                bind_column_references(message, referenced_tables, result_columns)?;
+            }
+            Ok(())
+        }
+        Expr::SubqueryResult {
+            lhs, outer_refs, ..
+        } => {
+            if let Some(lhs) = lhs {
+                bind_column_references(lhs, referenced_tables, result_columns)?;
+            }
+            // The outer references of the subquery are already bound to the tables of this query.
+            for outer_ref in outer_refs.iter() {
+                mark_columns_used(outer_ref, referenced_tables);
+            }
+            Ok(())
+        }
+        Expr::OuterRef(_) => Ok(()),
+        Expr::TriggerParam(_) => Ok(()),
+        Expr::Register(_) => Ok(()),
         Expr::Unary(_, expr) => {
             bind_column_references(expr, referenced_tables, result_columns)?;
             Ok(())
@@ -282,6 +326,344 @@ pub fn bind_column_references(
     }
 }
 
+/// Resolves a reference to a column, i.e. an [Expr::Id] or [Expr::Qualified], to the expression
+/// that reads the column from the given tables.
+/// Returns None if the column is not in any of the tables, or for a qualified reference, if none of
+/// the tables has the given name.
+fn resolve_column_reference(expr: &Expr, tables: &[TableReference]) -> Result<Option<Expr>> {
+    match expr {
+        Expr::Id(id) => {
+            let normalized_id = normalize_ident(id.0.as_str());
+            if !tables.is_empty() && has_rowid(&tables[0]) {
+                if let Some(row_id_expr) = parse_row_id(&normalized_id, 0, || tables.len() != 1)? {
+                    return Ok(Some(row_id_expr));
+                }
+            }
+            // A column that is joined with USING is a single column, see [using_columns].
+            let using_cols = using_columns(tables, &normalized_id);
+            if using_cols.len() > 1
+                || !using_cols.is_empty()
+                    && tables.iter().any(|table| {
+                        table
+                            .join_info
+                            .as_ref()
+                            .and_then(|join_info| join_info.using.as_ref())
+                            .is_some_and(|using| {
+                                using
+                                    .iter()
+                                    .any(|col| col.0.eq_ignore_ascii_case(&normalized_id))
+                            })
+                    })
+            {
+                return Ok(Some(using_column_expr(tables, &using_cols)));
+            }
+            let mut match_result = None;
+            for (tbl_idx, table) in tables.iter().enumerate() {
+                let col_idx = table.columns().iter().position(|c| {
+                    c.name
+                        .as_ref()
+                        .map_or(false, |name| name.eq_ignore_ascii_case(&normalized_id))
+                });
+                if col_idx.is_some() {
+                    if match_result.is_some() {
+                        crate::bail_parse_error!("Column {} is ambiguous", id.0);
+                    }
+                    let col = table.columns().get(col_idx.unwrap()).unwrap();
+                    match_result = Some((tbl_idx, col_idx.unwrap(), col.is_rowid_alias));
+                }
+            }
+            Ok(
+                match_result.map(|(tbl_idx, col_idx, is_rowid_alias)| Expr::Column {
+                    database: None, // TODO: support different databases
+                    table: tbl_idx,
+                    column: col_idx,
+                    is_rowid_alias,
+                }),
+            )
+        }
+        Expr::Qualified(tbl, id) => {
+            let normalized_table_name = normalize_ident(tbl.0.as_str());
+            let Some(tbl_idx) = tables
+                .iter()
+                .position(|t| t.identifier.eq_ignore_ascii_case(&normalized_table_name))
+            else {
+                return Ok(None);
+            };
+            resolve_table_column(tables, tbl_idx, id).map(Some)
+        }
+        Expr::DoublyQualified(db, tbl, id) => {
+            let normalized_db_name = normalize_ident(db.0.as_str());
+            let normalized_table_name = normalize_ident(tbl.0.as_str());
+            let Some(tbl_idx) = tables.iter().position(|t| {
+                t.database_name == normalized_db_name
+                    && t.identifier.eq_ignore_ascii_case(&normalized_table_name)
+            }) else {
+                return Ok(None);
+            };
+            resolve_table_column(tables, tbl_idx, id).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Resolves the column `id` of the table at `tbl_idx`, for a qualified column reference.
+fn resolve_table_column(tables: &[TableReference], tbl_idx: usize, id: &ast::Name) -> Result<Expr> {
+    let normalized_id = normalize_ident(id.0.as_str());
+
+    if has_rowid(&tables[tbl_idx]) {
+        if let Some(row_id_expr) = parse_row_id(&normalized_id, tbl_idx, || false)? {
+            return Ok(row_id_expr);
+        }
+    }
+    let col_idx = tables[tbl_idx].columns().iter().position(|c| {
+        c.name
+            .as_ref()
+            .map_or(false, |name| name.eq_ignore_ascii_case(&normalized_id))
+    });
+    if col_idx.is_none() {
+        crate::bail_parse_error!("Column {} not found", normalized_id);
+    }
+    let col = tables[tbl_idx].columns().get(col_idx.unwrap()).unwrap();
+    Ok(Expr::Column {
+        database: None, // TODO: support different databases
+        table: tbl_idx,
+        column: col_idx.unwrap(),
+        is_rowid_alias: col.is_rowid_alias,
+    })
+}
+
+/// Marks the columns read by a resolved column reference as used, see [resolve_column_reference].
+fn mark_columns_used(expr: &Expr, tables: &mut [TableReference]) {
+    match expr {
+        Expr::Column { table, column, .. } => tables[*table].mark_column_used(*column),
+        Expr::FunctionCall {
+            args: Some(args), ..
+        } => {
+            for arg in args {
+                mark_columns_used(arg, tables);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Binds the column references in the FILTER clause of a function call, and in the PARTITION BY
+/// and ORDER BY clauses of the window of a window function call.
+fn bind_function_tail_column_references(
+    filter_over: &mut Option<ast::FunctionTail>,
+    referenced_tables: &mut [TableReference],
+    result_columns: Option<&[ResultSetColumn]>,
+) -> Result<()> {
+    let Some(ast::FunctionTail {
+        filter_clause,
+        over_clause,
+    }) = filter_over
+    else {
+        return Ok(());
+    };
+    if let Some(filter) = filter_clause {
+        bind_column_references(filter, referenced_tables, result_columns)?;
+    }
+    let Some(over) = over_clause else {
+        return Ok(());
+    };
+    let ast::Over::Window(window) = over.as_mut() else {
+        return Ok(());
+    };
+    for expr in window.partition_by.iter_mut().flatten() {
+        bind_column_references(expr, referenced_tables, result_columns)?;
+    }
+    for sorted_column in window.order_by.iter_mut().flatten() {
+        bind_column_references(&mut sorted_column.expr, referenced_tables, result_columns)?;
+    }
+    Ok(())
+}
+
+/// Plans the subqueries in `expr`, i.e. `EXISTS (SELECT ...)`, `x IN (SELECT ...)` and scalar
+/// subqueries `(SELECT ...)`, and replaces each of them with an [Expr::SubqueryResult] that refers
+/// to its plan in `out_subqueries`. This is done before the columns of `expr` are bound, see
+/// [bind_column_references].
+///
+/// `scope` is the scope of the subqueries of the query that contains `expr`, see
+/// [Scope::for_expr_subqueries]. References in `expr` to the columns of outer queries are replaced
+/// with an [Expr::OuterRef], see [resolve_outer_reference].
+pub fn plan_subqueries(
+    expr: &mut Expr,
+    schema: &Schema,
+    syms: &SymbolTable,
+    scope: &Scope,
+    result_columns: Option<&[ResultSetColumn]>,
+    out_subqueries: &mut Vec<ExprSubquery>,
+) -> Result<()> {
+    match expr {
+        Expr::Id(_) | Expr::Qualified(_, _) | Expr::DoublyQualified(_, _, _) => {
+            if let Expr::Id(id) = expr {
+                if id.0.eq_ignore_ascii_case("true") || id.0.eq_ignore_ascii_case("false") {
+                    return Ok(());
+                }
+            }
+            if resolve_column_reference(expr, &scope.tables)?.is_some() {
+                return Ok(());
+            }
+            if let (Expr::Id(id), Some(result_columns)) = (&*expr, result_columns) {
+                let normalized_id = normalize_ident(id.0.as_str());
+                if result_columns.iter().any(|rc| {
+                    rc.name(&scope.tables)
+                        .is_some_and(|name| name.eq_ignore_ascii_case(&normalized_id))
+                }) {
+                    return Ok(());
+                }
+            }
+            if let Some(outer_ref) = resolve_outer_reference(expr, scope.parent)? {
+                *expr = outer_ref;
+            }
+            Ok(())
+        }
+        Expr::Exists(_) | Expr::InSelect { .. } | Expr::Subquery(_) => {
+            let (select, mut lhs, not, kind) =
+                match std::mem::replace(expr, Expr::Literal(ast::Literal::Null)) {
+                    Expr::Exists(select) => (select, None, false, ExprSubqueryKind::Exists),
+                    Expr::InSelect { lhs, not, rhs } => (rhs, Some(lhs), not, ExprSubqueryKind::In),
+                    Expr::Subquery(select) => (select, None, false, ExprSubqueryKind::Scalar),
+                    _ => unreachable!(),
+                };
+            if let Some(lhs) = lhs.as_mut() {
+                plan_subqueries(lhs, schema, syms, scope, result_columns, out_subqueries)?;
+            }
+            let Plan::Select(plan) = prepare_select_plan(schema, *select, syms, Some(scope))?
+            else {
+                unreachable!();
+            };
+            if let Some(name) = recursive_reference(&plan) {
+                crate::bail_parse_error!("recursive reference in a subquery: {}", name);
+            }
+            let outer_refs = scope
+                .outer_refs
+                .as_ref()
+                .expect("scope is not the scope of the subqueries of a query")
+                .take();
+            if kind != ExprSubqueryKind::Exists && plan.result_columns.len() != 1 {
+                crate::bail_parse_error!(
+                    "sub-select returns {} columns - expected 1",
+                    plan.result_columns.len()
+                );
+            }
+            out_subqueries.push(ExprSubquery {
+                plan: Box::new(plan),
+                kind,
+                num_outer_refs: outer_refs.len(),
+                affinity: Affinity::Blob,
+                collation: None,
+            });
+            *expr = Expr::SubqueryResult {
+                subquery_id: out_subqueries.len() - 1,
+                lhs,
+                not,
+                outer_refs,
+            };
+            Ok(())
+        }
+        _ => {
+            for child in child_exprs_mut(expr) {
+                plan_subqueries(child, schema, syms, scope, result_columns, out_subqueries)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Plans the subqueries in an expression of an UPDATE or DELETE on the first of
+/// `table_references`, see [plan_subqueries].
+/// The statement changes each row as soon as it finds it, so a subquery that refers to the row
+/// and reads the changed table would see the rows that were already changed. That is rejected.
+pub fn plan_dml_subqueries(
+    expr: &mut Expr,
+    schema: &Schema,
+    syms: &SymbolTable,
+    table_references: &[TableReference],
+    out_subqueries: &mut Vec<ExprSubquery>,
+) -> Result<()> {
+    let num_planned = out_subqueries.len();
+    let scope = Scope::for_expr_subqueries(table_references.to_vec(), vec![], None);
+    plan_subqueries(expr, schema, syms, &scope, None, out_subqueries)?;
+    let table = &table_references[0];
+    if let Some(btree) = table.btree() {
+        if out_subqueries[num_planned..].iter().any(|subquery| {
+            subquery.num_outer_refs > 0
+                && subquery.plan.reads_btree(table.database, btree.root_page)
+        }) {
+            crate::bail_parse_error!(
+                "a correlated subquery that reads the table being changed is not supported: {}",
+                btree.name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Resolves a reference to a column of an outer query, e.g. `u.id` in
+/// `SELECT * FROM users u WHERE EXISTS (SELECT 1 FROM orders o WHERE o.user_id = u.id)`,
+/// where `scope` is the scope of the subquery being planned.
+///
+/// The value is added to the outer references of the subquery, which are evaluated by the query
+/// that contains the subquery every time it runs the subquery, and the returned [Expr::OuterRef]
+/// reads it. If the column belongs to a query further out, it also becomes an outer reference of
+/// each query in between.
+/// Returns the name of the recursive CTE that a plan refers to from one of its recursive members,
+/// if any. Only the FROM clause of a recursive member can refer to the CTE, see [RecursiveCte].
+fn recursive_reference(plan: &SelectPlan) -> Option<&str> {
+    plan.table_references
+        .iter()
+        .find_map(|table| match &table.op {
+            Operation::Subquery { plan: subquery, .. } => {
+                if matches!(subquery.query_type, SelectQueryType::RecursiveRow) {
+                    Some(table.identifier.as_str())
+                } else {
+                    recursive_reference(subquery)
+                }
+            }
+            _ => None,
+        })
+        .or_else(|| {
+            plan.compounds
+                .iter()
+                .find_map(|(_, member)| recursive_reference(member))
+        })
+        .or_else(|| {
+            plan.expr_subqueries
+                .iter()
+                .find_map(|subquery| recursive_reference(&subquery.plan))
+        })
+}
+
+fn resolve_outer_reference(expr: &Expr, scope: Option<&Scope>) -> Result<Option<Expr>> {
+    let Some(scope) = scope else {
+        return Ok(None);
+    };
+    let Some(outer_refs) = scope.outer_refs.as_ref() else {
+        return resolve_outer_reference(expr, scope.parent);
+    };
+    let value = match resolve_column_reference(expr, &scope.tables)? {
+        Some(value) => value,
+        None => match resolve_outer_reference(expr, scope.parent)? {
+            Some(value) => value,
+            None => return Ok(None),
+        },
+    };
+    let mut outer_refs = outer_refs.borrow_mut();
+    let index = match outer_refs
+        .iter()
+        .position(|outer_ref| exprs_are_equivalent(outer_ref, &value))
+    {
+        Some(index) => index,
+        None => {
+            outer_refs.push(value);
+            outer_refs.len() - 1
+        }
+    };
+    Ok(Some(Expr::OuterRef(index)))
+}
+
 fn parse_from_clause_table<'a>(
     schema: &Schema,
     table: ast::SelectTable,
@@ -291,27 +673,42 @@ fn parse_from_clause_table<'a>(
     match table {
         ast::SelectTable::Table(qualified_name, maybe_alias, _) => {
             let normalized_qualified_name = normalize_ident(qualified_name.name.0.as_str());
+            let alias = maybe_alias
+                .map(|a| match a {
+                    ast::As::As(id) => id,
+                    ast::As::Elided(id) => id,
+                })
+                .map(|a| a.0);
+            let db_name = qualified_name.db_name.as_ref().map(|name| name.0.as_str());
             // Check if the FROM clause table is referring to a CTE in the current scope.
             if let Some(cte) = scope
                 .ctes
                 .iter()
-                .find(|cte| cte.name == normalized_qualified_name)
+                .find(|cte| db_name.is_none() && cte.name == normalized_qualified_name)
             {
                 // CTE can be rewritten as a subquery.
                 // TODO: find a way not to clone the CTE plan here.
-                let cte_table =
-                    TableReference::new_subquery(cte.name.clone(), cte.plan.clone(), None);
+                let cte_table = TableReference::new_subquery(
+                    alias.unwrap_or_else(|| cte.name.clone()),
+                    cte.plan.clone(),
+                    None,
+                );
                 scope.tables.push(cte_table);
                 return Ok(());
             };
             // Check if our top level schema has this table.
-            if let Some(table) = schema.get_table(&normalized_qualified_name) {
-                let alias = maybe_alias
-                    .map(|a| match a {
-                        ast::As::As(id) => id,
-                        ast::As::Elided(id) => id,
-                    })
-                    .map(|a| a.0);
+            if let Some((database, table)) =
+                schema.find_table(db_name, &normalized_qualified_name)?
+            {
+                if let Table::View(view) = table.as_ref() {
+                    let subplan = parse_view(schema, view, syms)?;
+                    scope.tables.push(TableReference::new_subquery(
+                        alias.unwrap_or(normalized_qualified_name),
+                        subplan,
+                        None,
+                    ));
+                    return Ok(());
+                }
                 let tbl_ref = if let Table::Virtual(tbl) = table.as_ref() {
                     Table::Virtual(tbl.clone())
                 } else if let Table::BTree(table) = table.as_ref() {
@@ -330,9 +727,18 @@ fn parse_from_clause_table<'a>(
                     identifier: alias.unwrap_or(normalized_qualified_name),
                     join_info: None,
                     col_used_mask: ColumnUsedMask::new(),
+                    database,
+                    database_name: schema.database_name(database).to_string(),
                 });
                 return Ok(());
             };
+            if let Some(db_name) = db_name {
+                crate::bail_parse_error!(
+                    "Table {}.{} not found",
+                    normalize_ident(db_name),
+                    normalized_qualified_name
+                );
+            }
 
             // Check if the outer query scope has this table.
             if let Some(outer_scope) = scope.parent {
@@ -351,8 +757,11 @@ fn parse_from_clause_table<'a>(
                     .find(|cte| cte.name == normalized_qualified_name)
                 {
                     // TODO: avoid cloning the CTE plan here.
-                    let cte_table =
-                        TableReference::new_subquery(cte.name.clone(), cte.plan.clone(), None);
+                    let cte_table = TableReference::new_subquery(
+                        alias.unwrap_or_else(|| cte.name.clone()),
+                        cte.plan.clone(),
+                        None,
+                    );
                     scope.tables.push(cte_table);
                     return Ok(());
                 }
@@ -413,6 +822,8 @@ fn parse_from_clause_table<'a>(
                 table: Table::Virtual(vtab),
                 identifier: alias,
                 col_used_mask: ColumnUsedMask::new(),
+                database: 0,
+                database_name: "main".to_string(),
             });
 
             Ok(())
@@ -451,6 +862,28 @@ pub struct Scope<'a> {
     ctes: Vec<Cte>,
     /// The parent scope, if any. For example, a second CTE has access to the first CTE via the parent scope.
     parent: Option<&'a Scope<'a>>,
+    /// For the scope of the subqueries in the expressions of a query, the values of the query that
+    /// the subquery being planned refers to, see [resolve_outer_reference].
+    /// The tables of a FROM clause are not visible to the subqueries in that FROM clause, so this is
+    /// None for the scope of a FROM clause.
+    outer_refs: Option<RefCell<Vec<Expr>>>,
+}
+
+impl<'a> Scope<'a> {
+    /// Creates the scope of the subqueries in the expressions of a query, which can refer to the
+    /// tables and CTEs of the query, as well as to everything that the query itself can refer to.
+    pub fn for_expr_subqueries(
+        tables: Vec<TableReference>,
+        ctes: Vec<Cte>,
+        parent: Option<&'a Scope<'a>>,
+    ) -> Self {
+        Self {
+            tables,
+            ctes,
+            parent,
+            outer_refs: Some(RefCell::new(vec![])),
+        }
+    }
 }
 
 pub struct Cte {
@@ -468,28 +901,23 @@ pub fn parse_from<'a>(
     with: Option<With>,
     out_where_clause: &mut Vec<WhereTerm>,
     outer_scope: Option<&'a Scope<'a>>,
-) -> Result<Vec<TableReference>> {
+) -> Result<(Vec<TableReference>, Vec<Cte>)> {
     if from.as_ref().and_then(|f| f.select.as_ref()).is_none() {
-        return Ok(vec![]);
+        return Ok((vec![], vec![]));
     }
 
     let mut scope = Scope {
         tables: vec![],
         ctes: vec![],
         parent: outer_scope,
+        outer_refs: None,
     };
 
     if let Some(with) = with {
-        if with.recursive {
-            crate::bail_parse_error!("Recursive CTEs are not yet supported");
-        }
         for cte in with.ctes {
             if cte.materialized == Materialized::Yes {
                 crate::bail_parse_error!("Materialized CTEs are not yet supported");
             }
-            if cte.columns.is_some() {
-                crate::bail_parse_error!("CTE columns are not yet supported");
-            }
 
             // Check if normalized name conflicts with catalog tables or other CTEs
             // TODO: sqlite actually allows overriding a catalog table with a CTE.
@@ -513,9 +941,23 @@ pub fn parse_from<'a>(
             }
 
             // CTE can refer to other CTEs that came before it, plus any schema tables or tables in the outer scope.
-            let cte_plan = prepare_select_plan(schema, *cte.select, syms, Some(&scope))?;
-            let Plan::Select(mut cte_plan) = cte_plan else {
-                crate::bail_parse_error!("Only SELECT queries are currently supported in CTEs");
+            // Like SQLite, a CTE that refers to itself is recursive even without the RECURSIVE keyword.
+            let mut cte_plan = if select_references_table(&cte.select, &cte_name_normalized) {
+                parse_recursive_cte(
+                    schema,
+                    *cte.select,
+                    cte.columns,
+                    &cte_name_normalized,
+                    syms,
+                    &mut scope,
+                )?
+            } else {
+                let cte_plan = prepare_select_plan(schema, *cte.select, syms, Some(&scope))?;
+                let Plan::Select(mut cte_plan) = cte_plan else {
+                    crate::bail_parse_error!("Only SELECT queries are currently supported in CTEs");
+                };
+                apply_cte_column_names(&mut cte_plan, &cte_name_normalized, cte.columns)?;
+                cte_plan
             };
             // CTE can be rewritten as a subquery.
             cte_plan.query_type = SelectQueryType::Subquery {
@@ -538,7 +980,343 @@ pub fn parse_from<'a>(
         parse_join(schema, join, syms, &mut scope, out_where_clause)?;
     }
 
-    Ok(scope.tables)
+    Ok((scope.tables, scope.ctes))
+}
+
+/// Plans the SELECT statement of a view so that the view can be used as a subquery.
+fn parse_view(schema: &Schema, view: &View, syms: &SymbolTable) -> Result<SelectPlan> {
+    if view.expanding.replace(true) {
+        crate::bail_parse_error!("view {} is circularly defined", view.name);
+    }
+    // The view is planned in its own scope, so it cannot see the tables or CTEs of the query using it.
+    let plan = prepare_select_plan(schema, view.select.clone(), syms, None);
+    view.expanding.set(false);
+    let Plan::Select(mut plan) = plan? else {
+        unreachable!();
+    };
+    if !view.columns.is_empty() && view.columns.len() != plan.result_columns.len() {
+        crate::bail_parse_error!(
+            "expected {} columns for '{}' but got {}",
+            view.columns.len(),
+            view.name,
+            plan.result_columns.len()
+        );
+    }
+    apply_cte_column_names(&mut plan, &view.name, view.column_names.clone())?;
+    plan.query_type = SelectQueryType::Subquery {
+        yield_reg: usize::MAX, // will be set later in bytecode emission
+        coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
+    };
+    Ok(plan)
+}
+
+/// Returns the columns of a view, named after its column name list or the result columns of its
+/// SELECT.
+pub fn view_columns(schema: &Schema, view: &View, syms: &SymbolTable) -> Result<Vec<Column>> {
+    let plan = parse_view(schema, view, syms)?;
+    Ok(TableReference::new_subquery(view.name.clone(), plan, None)
+        .columns()
+        .to_vec())
+}
+
+/// Renames the result columns of a CTE plan to the column names in `WITH name(col1, col2, ...)`.
+fn apply_cte_column_names(
+    plan: &mut SelectPlan,
+    cte_name: &str,
+    columns: Option<Vec<ast::IndexedColumn>>,
+) -> Result<()> {
+    let Some(columns) = columns else {
+        return Ok(());
+    };
+    if columns.len() != plan.result_columns.len() {
+        crate::bail_parse_error!(
+            "table {} has {} values for {} columns",
+            cte_name,
+            plan.result_columns.len(),
+            columns.len()
+        );
+    }
+    for (rc, column) in plan.result_columns.iter_mut().zip(columns) {
+        rc.alias = Some(column.col_name.0);
+    }
+    Ok(())
+}
+
+/// Plans a CTE that refers to itself, see [RecursiveCte].
+///
+/// The compound members of the CTE are split into the initial part, i.e. all the members before
+/// the first one that refers to the CTE, and the recursive members, i.e. that one and all the
+/// members after it. Like in SQLite, each recursive member must refer to the CTE exactly once,
+/// directly in its FROM clause, and all of them must be combined with the same operator,
+/// either UNION or UNION ALL.
+fn parse_recursive_cte(
+    schema: &Schema,
+    select: ast::Select,
+    columns: Option<Vec<ast::IndexedColumn>>,
+    cte_name: &str,
+    syms: &SymbolTable,
+    scope: &mut Scope,
+) -> Result<SelectPlan> {
+    let ast::Select {
+        with,
+        body,
+        order_by,
+        limit,
+    } = select;
+    let compounds = body.compounds.unwrap_or_default();
+    let first_recursive = compounds
+        .iter()
+        .position(|c| one_select_references_table(&c.select, cte_name));
+    let Some(first_recursive) = first_recursive else {
+        // The first SELECT refers to the CTE, so there is nothing to start the recursion with.
+        crate::bail_parse_error!("circular reference: {}", cte_name);
+    };
+    if one_select_references_table(&body.select, cte_name) {
+        crate::bail_parse_error!("circular reference: {}", cte_name);
+    }
+    let operator = compounds[first_recursive].operator;
+    let mut initial_compounds = compounds;
+    let recursive_compounds = initial_compounds.split_off(first_recursive);
+    for compound in recursive_compounds.iter() {
+        let (num_references, referenced_in_subquery) =
+            one_select_table_references(&compound.select, cte_name);
+        if compound.operator != operator
+            || !matches!(
+                operator,
+                ast::CompoundOperator::Union | ast::CompoundOperator::UnionAll
+            )
+            || num_references == 0
+            || referenced_in_subquery
+        {
+            crate::bail_parse_error!("circular reference: {}", cte_name);
+        }
+        if num_references > 1 {
+            crate::bail_parse_error!("multiple references to recursive table: {}", cte_name);
+        }
+    }
+
+    let initial_select = ast::Select {
+        with: with.clone(),
+        body: ast::SelectBody {
+            select: body.select,
+            compounds: (!initial_compounds.is_empty()).then_some(initial_compounds),
+        },
+        order_by: None,
+        limit: None,
+    };
+    let Plan::Select(mut plan) = prepare_select_plan(schema, initial_select, syms, Some(scope))?
+    else {
+        crate::bail_parse_error!("Only SELECT queries are currently supported in CTEs");
+    };
+    apply_cte_column_names(&mut plan, cte_name, columns)?;
+
+    // While the recursive members are planned, the CTE refers to a single row that is provided
+    // by the CTE itself during bytecode emission.
+    let row_plan = SelectPlan {
+        table_references: vec![],
+        result_columns: plan
+            .result_columns
+            .iter()
+            .map(|rc| ResultSetColumn {
+                expr: Expr::Literal(ast::Literal::Null),
+                alias: Some(
+                    rc.name(&plan.table_references)
+                        .map(String::from)
+                        .unwrap_or_else(|| rc.expr.to_string()),
+                ),
+                contains_aggregates: false,
+            })
+            .collect(),
+        distinct: false,
+        where_clause: vec![],
+        group_by: None,
+        order_by: None,
+        aggregates: vec![],
+        limit: None,
+        offset: None,
+        contains_constant_false_condition: false,
+        query_type: SelectQueryType::RecursiveRow,
+        compounds: vec![],
+        windows: vec![],
+        recursive: None,
+        expr_subqueries: vec![],
+    };
+    scope.ctes.push(Cte {
+        name: cte_name.to_string(),
+        plan: row_plan,
+    });
+    let members = recursive_compounds
+        .into_iter()
+        .map(|compound| {
+            let member_select = ast::Select {
+                with: with.clone(),
+                body: ast::SelectBody {
+                    select: compound.select,
+                    compounds: None,
+                },
+                order_by: None,
+                limit: None,
+            };
+            let Plan::Select(member) =
+                prepare_select_plan(schema, member_select, syms, Some(scope))?
+            else {
+                unreachable!();
+            };
+            if member.result_columns.len() != plan.result_columns.len() {
+                crate::bail_parse_error!(
+                    "SELECTs to the left and right of {} do not have the same number of result columns",
+                    operator
+                );
+            }
+            if !member.windows.is_empty() {
+                crate::bail_parse_error!("cannot use window functions in recursive queries");
+            }
+            if !member.aggregates.is_empty() || member.group_by.is_some() {
+                crate::bail_parse_error!("recursive aggregate queries not supported");
+            }
+            Ok(member)
+        })
+        .collect::<Result<Vec<_>>>();
+    scope.ctes.pop();
+
+    let members = members?;
+
+    let order_by = order_by
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .map(|(i, term)| recursive_order_by_column(term, i, &plan, &members))
+        .collect::<Result<Vec<_>>>()?;
+    let (limit, offset) = limit.map_or(Ok((None, None)), |l| parse_limit(&l))?;
+    plan.recursive = Some(Box::new(RecursiveCte {
+        members,
+        distinct: operator == ast::CompoundOperator::Union,
+        order_by,
+        limit,
+        offset,
+    }));
+    Ok(plan)
+}
+
+/// Resolves the ORDER BY term at `position` of a recursive CTE to the result column that it sorts
+/// the queue by. Like in the ORDER BY clause of a compound SELECT in SQLite, the term must be the
+/// number or the name of a result column of the initial part or of one of the recursive members,
+/// and it sorts by the collating sequence of its COLLATE clause or else of the initial part.
+fn recursive_order_by_column(
+    term: ast::SortedColumn,
+    position: usize,
+    initial: &SelectPlan,
+    members: &[SelectPlan],
+) -> Result<IndexColumn> {
+    let ordinal = match position + 1 {
+        n @ 11..=13 => format!("{}th", n),
+        n if n % 10 == 1 => format!("{}st", n),
+        n if n % 10 == 2 => format!("{}nd", n),
+        n if n % 10 == 3 => format!("{}rd", n),
+        n => format!("{}th", n),
+    };
+    let num_columns = initial.result_columns.len();
+    let (expr, collation) = match term.expr {
+        Expr::Collate(expr, collation) => (*expr, Some(collation)),
+        expr => (expr, None),
+    };
+    let column = match &expr {
+        Expr::Literal(ast::Literal::Numeric(num)) => match num.parse::<usize>() {
+            Ok(n) if (1..=num_columns).contains(&n) => Some(n - 1),
+            _ => crate::bail_parse_error!(
+                "{} ORDER BY term out of range - should be between 1 and {}",
+                ordinal,
+                num_columns
+            ),
+        },
+        Expr::Id(ast::Id(name)) | Expr::Name(ast::Name(name)) => {
+            let name = normalize_ident(name);
+            std::iter::once(initial).chain(members).find_map(|select| {
+                select.result_columns.iter().position(|rc| {
+                    rc.name(&select.table_references)
+                        .is_some_and(|column_name| normalize_ident(column_name) == name)
+                })
+            })
+        }
+        _ => None,
+    };
+    let Some(column) = column else {
+        crate::bail_parse_error!(
+            "{} ORDER BY term does not match any column in the result set",
+            ordinal
+        );
+    };
+    let collation = collation.or_else(|| {
+        expr_collation_name(
+            &initial.result_columns[column].expr,
+            Some(&initial.table_references),
+        )
+        .map(String::from)
+    });
+    Ok(IndexColumn {
+        name: format!("o{}", position),
+        order: term.order.unwrap_or(ast::SortOrder::Asc),
+        pos_in_table: column,
+        collation,
+        expr: None,
+    })
+}
+
+/// Returns true if any SELECT of `select` refers to the table called `name` in its FROM clause,
+/// either directly or from a subquery.
+fn select_references_table(select: &ast::Select, name: &str) -> bool {
+    one_select_references_table(&select.body.select, name)
+        || select
+            .body
+            .compounds
+            .iter()
+            .flatten()
+            .any(|c| one_select_references_table(&c.select, name))
+}
+
+fn one_select_references_table(select: &ast::OneSelect, name: &str) -> bool {
+    let (num_references, referenced_in_subquery) = one_select_table_references(select, name);
+    num_references > 0 || referenced_in_subquery
+}
+
+/// Counts the direct references to the table called `name` in the FROM clause of `select`,
+/// and checks whether the table is also referred to from a subquery in the FROM clause.
+fn one_select_table_references(select: &ast::OneSelect, name: &str) -> (usize, bool) {
+    match select {
+        ast::OneSelect::Select(select) => select
+            .from
+            .as_ref()
+            .map_or((0, false), |from| from_clause_table_references(from, name)),
+        ast::OneSelect::Values(_) => (0, false),
+    }
+}
+
+fn from_clause_table_references(from: &FromClause, name: &str) -> (usize, bool) {
+    let mut num_references = 0;
+    let mut referenced_in_subquery = false;
+    let tables = from
+        .select
+        .iter()
+        .map(|table| table.as_ref())
+        .chain(from.joins.iter().flatten().map(|join| &join.table));
+    for table in tables {
+        match table {
+            ast::SelectTable::Table(qualified_name, _, _) => {
+                if normalize_ident(qualified_name.name.0.as_str()) == name {
+                    num_references += 1;
+                }
+            }
+            ast::SelectTable::Select(select, _) => {
+                referenced_in_subquery |= select_references_table(select, name);
+            }
+            ast::SelectTable::Sub(from, _) => {
+                let (n, nested) = from_clause_table_references(from, name);
+                referenced_in_subquery |= n > 0 || nested;
+            }
+            ast::SelectTable::TableCall(..) => {}
+        }
+    }
+    (num_references, referenced_in_subquery)
 }
 
 pub fn parse_where(
@@ -558,6 +1336,7 @@ pub fn parse_where(
             out_where_clause.push(WhereTerm {
                 expr,
                 from_outer_join: false,
+                from_join: None,
                 eval_at,
             });
         }
@@ -567,6 +1346,33 @@ pub fn parse_where(
     }
 }
 
+/// The rows of the right table of a RIGHT or FULL OUTER JOIN that have no match are emitted after
+/// the main loop, so the loop of that table must record which of its rows have a match before any
+/// condition that is not part of the join to its left can skip a row.
+/// This moves every such condition that would be evaluated in an earlier loop to the loop of the
+/// right table, where it is evaluated after the match is recorded.
+pub fn defer_conditions_past_right_joins(
+    table_references: &[TableReference],
+    where_clause: &mut [WhereTerm],
+) {
+    for (right_table_index, table) in table_references.iter().enumerate() {
+        if !table.join_info.as_ref().is_some_and(|j| j.right) {
+            continue;
+        }
+        for term in where_clause.iter_mut() {
+            // The conditions of the joins to the left of the right table are part of its left side.
+            if term.from_join.is_some_and(|j| j < right_table_index) {
+                continue;
+            }
+            if let EvalAt::Loop(loop_idx) = term.eval_at {
+                if loop_idx < right_table_index {
+                    term.eval_at = EvalAt::Loop(right_table_index);
+                }
+            }
+        }
+    }
+}
+
 /**
   Returns the earliest point at which a WHERE term can be evaluated.
   For expressions referencing tables, this is the innermost loop that contains a row for each
@@ -670,12 +1476,25 @@ pub fn determine_where_to_eval_expr<'a>(predicate: &'a ast::Expr) -> Result<Eval
                 eval_at = eval_at.max(determine_where_to_eval_expr(expr)?);
             }
         }
-        Expr::Raise(_, _) => {
-            todo!("raise not supported yet")
+        Expr::Raise(_, message) => {
+            if let Some(message) = message {
+                eval_at = eval_at.max(determine_where_to_eval_expr(message)?);
+            }
         }
         Expr::Subquery(_) => {
             todo!("subquery not supported yet")
         }
+        Expr::SubqueryResult {
+            lhs, outer_refs, ..
+        } => {
+            for expr in lhs.iter().map(|lhs| lhs.as_ref()).chain(outer_refs.iter()) {
+                eval_at = eval_at.max(determine_where_to_eval_expr(expr)?);
+            }
+        }
+        // A value of an outer query does not change while this query runs.
+        Expr::OuterRef(_) => {}
+        Expr::TriggerParam(_) => {}
+        Expr::Register(_) => {}
         Expr::Unary(_, expr) => {
             eval_at = eval_at.max(determine_where_to_eval_expr(expr)?);
         }
@@ -700,14 +1519,24 @@ fn parse_join<'a>(
 
     parse_from_clause_table(schema, table, scope, syms)?;
 
-    let (outer, natural) = match join_operator {
+    let (outer, right, natural, cross) = match join_operator {
         ast::JoinOperator::TypedJoin(Some(join_type)) => {
-            let is_outer = join_type.contains(JoinType::OUTER);
+            let is_outer = join_type.contains(JoinType::LEFT);
+            let is_right = join_type.contains(JoinType::RIGHT);
             let is_natural = join_type.contains(JoinType::NATURAL);
-            (is_outer, is_natural)
+            let is_cross = join_type.contains(JoinType::CROSS);
+            (is_outer, is_right, is_natural, is_cross)
         }
-        _ => (false, false),
+        _ => (false, false, false, false),
     };
+    if right
+        && scope
+            .tables
+            .iter()
+            .any(|t| matches!(t.table, Table::Virtual(_)))
+    {
+        crate::bail_parse_error!("RIGHT and FULL OUTER JOIN are not supported with virtual tables");
+    }
 
     let mut using = None;
 
@@ -766,14 +1595,15 @@ fn parse_join<'a>(
                 }
                 for pred in preds {
                     let cur_table_idx = scope.tables.len() - 1;
-                    let eval_at = if outer {
+                    let eval_at = if outer || right {
                         EvalAt::Loop(cur_table_idx)
                     } else {
                         determine_where_to_eval_expr(&pred)?
                     };
                     out_where_clause.push(WhereTerm {
                         expr: pred,
-                        from_outer_join: outer,
+                        from_outer_join: outer || right,
+                        from_join: Some(cur_table_idx),
                         eval_at,
                     });
                 }
@@ -786,23 +1616,8 @@ fn parse_join<'a>(
                     let left_tables = &scope.tables[..cur_table_idx];
                     assert!(!left_tables.is_empty());
                     let right_table = scope.tables.last().unwrap();
-                    let mut left_col = None;
-                    for (left_table_idx, left_table) in left_tables.iter().enumerate() {
-                        left_col = left_table
-                            .columns()
-                            .iter()
-                            .enumerate()
-                            .find(|(_, col)| {
-                                col.name
-                                    .as_ref()
-                                    .map_or(false, |name| *name == name_normalized)
-                            })
-                            .map(|(idx, col)| (left_table_idx, idx, col));
-                        if left_col.is_some() {
-                            break;
-                        }
-                    }
-                    if left_col.is_none() {
+                    let left_cols = using_columns(left_tables, &name_normalized);
+                    if left_cols.is_empty() {
                         crate::bail_parse_error!(
                             "cannot join using column {} - column not present in all tables",
                             distinct_name.0
@@ -819,15 +1634,9 @@ fn parse_join<'a>(
                             distinct_name.0
                         );
                     }
-                    let (left_table_idx, left_col_idx, left_col) = left_col.unwrap();
                     let (right_col_idx, right_col) = right_col.unwrap();
                     let expr = Expr::Binary(
-                        Box::new(Expr::Column {
-                            database: None,
-                            table: left_table_idx,
-                            column: left_col_idx,
-                            is_rowid_alias: left_col.is_rowid_alias,
-                        }),
+                        Box::new(using_column_expr(left_tables, &left_cols)),
                         ast::Operator::Equals,
                         Box::new(Expr::Column {
                             database: None,
@@ -837,18 +1646,20 @@ fn parse_join<'a>(
                         }),
                     );
 
-                    let left_table = scope.tables.get_mut(left_table_idx).unwrap();
-                    left_table.mark_column_used(left_col_idx);
+                    for (left_table_idx, left_col_idx) in left_cols {
+                        scope.tables[left_table_idx].mark_column_used(left_col_idx);
+                    }
                     let right_table = scope.tables.get_mut(cur_table_idx).unwrap();
                     right_table.mark_column_used(right_col_idx);
-                    let eval_at = if outer {
+                    let eval_at = if outer || right {
                         EvalAt::Loop(cur_table_idx)
                     } else {
                         determine_where_to_eval_expr(&expr)?
                     };
                     out_where_clause.push(WhereTerm {
                         expr,
-                        from_outer_join: outer,
+                        from_outer_join: outer || right,
+                        from_join: Some(cur_table_idx),
                         eval_at,
                     });
                 }
@@ -860,7 +1671,12 @@ fn parse_join<'a>(
     assert!(scope.tables.len() >= 2);
     let last_idx = scope.tables.len() - 1;
     let rightmost_table = scope.tables.get_mut(last_idx).unwrap();
-    rightmost_table.join_info = Some(JoinInfo { outer, using });
+    rightmost_table.join_info = Some(JoinInfo {
+        outer,
+        right,
+        cross,
+        using,
+    });
 
     Ok(())
 }
@@ -916,6 +1732,11 @@ pub fn break_predicate_at_and_boundaries(predicate: Expr, out_predicates: &mut V
     }
 }
 
+/// Returns false for a WITHOUT ROWID table, whose rows cannot be referred to by rowid.
+fn has_rowid(table: &TableReference) -> bool {
+    table.btree().map_or(true, |btree| btree.has_rowid)
+}
+
 fn parse_row_id<F>(column_name: &str, table_id: usize, fn_check: F) -> Result<Option<Expr>>
 where
     F: FnOnce() -> bool,
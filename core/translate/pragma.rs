@@ -2,19 +2,23 @@
 //! More info: https://www.sqlite.org/pragma.html.
 
 use limbo_sqlite3_parser::ast;
-use limbo_sqlite3_parser::ast::PragmaName;
-use std::rc::Rc;
+use limbo_sqlite3_parser::ast::{PragmaName, RefAct};
+use std::rc::{Rc, Weak};
 use std::sync::Arc;
 
 use crate::fast_lock::SpinLock;
-use crate::schema::Schema;
+use crate::schema::{BTreeTable, Schema};
+use crate::storage::auto_vacuum::AutoVacuumMode;
 use crate::storage::sqlite3_ondisk::{DatabaseHeader, MIN_PAGE_CACHE_SIZE};
 use crate::storage::wal::CheckpointMode;
+use crate::translate::analyze::emit_optimize;
 use crate::util::normalize_ident;
-use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, QueryMode};
+use crate::vdbe::builder::{CursorType, ProgramBuilder, ProgramBuilderOpts, QueryMode};
 use crate::vdbe::insn::{Cookie, Insn};
 use crate::vdbe::BranchOffset;
-use crate::{bail_parse_error, Pager};
+use crate::{bail_parse_error, Connection, Pager, SymbolTable};
+
+use super::foreign_key::ResolvedForeignKey;
 use std::str::FromStr;
 use strum::IntoEnumIterator;
 
@@ -34,6 +38,7 @@ fn list_pragmas(
     program.emit_goto(start_offset);
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn translate_pragma(
     query_mode: QueryMode,
     schema: &Schema,
@@ -41,6 +46,8 @@ pub fn translate_pragma(
     body: Option<ast::PragmaBody>,
     database_header: Arc<SpinLock<DatabaseHeader>>,
     pager: Rc<Pager>,
+    connection: &Weak<Connection>,
+    syms: &SymbolTable,
 ) -> crate::Result<ProgramBuilder> {
     let mut program = ProgramBuilder::new(ProgramBuilderOpts {
         query_mode,
@@ -62,39 +69,139 @@ pub fn translate_pragma(
         Err(_) => bail_parse_error!("Not a valid pragma name"),
     };
 
-    match body {
-        None => {
-            query_pragma(pragma, schema, None, database_header.clone(), &mut program)?;
+    // A schema qualifier selects the database the pragma reads or changes. The pragmas that are
+    // settings of the connection ignore it, like in SQLite.
+    let database = match &name.db_name {
+        Some(db_name) => schema.database_index(&db_name.0)?,
+        None => 0,
+    };
+    let (pager, database_header) = if database == 0 {
+        (pager, database_header)
+    } else {
+        if matches!(pragma, PragmaName::Optimize | PragmaName::ForeignKeyCheck) {
+            bail_parse_error!(
+                "PRAGMA {} of an attached database is not supported yet",
+                pragma
+            );
         }
+        let Some(connection) = connection.upgrade() else {
+            bail_parse_error!("no such database: {}", name.db_name.as_ref().unwrap().0);
+        };
+        let pager = connection.attached_pager(database)?;
+        let database_header = pager.db_header.clone();
+        (pager, database_header)
+    };
+    let schema = &schema.database_schema(database)?;
+
+    match body {
+        // Like in SQLite, a value is ignored.
+        _ if pragma == PragmaName::DatabaseList => emit_database_list(&mut program, connection),
+        None => match pragma {
+            PragmaName::IncrementalVacuum => {
+                write = true;
+                emit_incremental_vacuum(&mut program, database, None);
+            }
+            PragmaName::Optimize => {
+                write = true;
+                emit_optimize(&mut program, schema, syms, DEFAULT_OPTIMIZE_MASK)?;
+            }
+            _ => {
+                query_pragma(
+                    pragma,
+                    schema,
+                    database,
+                    None,
+                    database_header.clone(),
+                    syms,
+                    &mut program,
+                )?;
+            }
+        },
         Some(ast::PragmaBody::Equals(value)) => match pragma {
-            PragmaName::TableInfo => {
+            PragmaName::TableInfo | PragmaName::ForeignKeyList | PragmaName::ForeignKeyCheck => {
                 query_pragma(
                     pragma,
                     schema,
+                    database,
                     Some(value),
                     database_header.clone(),
+                    syms,
                     &mut program,
                 )?;
             }
+            // Like in SQLite, the setting applies to the statements prepared after it.
+            PragmaName::ForeignKeys => syms.foreign_keys.set(parse_bool(&value)?),
+            PragmaName::IgnoreCheckConstraints => {
+                syms.ignore_check_constraints.set(parse_bool(&value)?)
+            }
+            PragmaName::AnalysisLimit => {
+                update_analysis_limit(&value, syms);
+                query_pragma(
+                    pragma,
+                    schema,
+                    database,
+                    None,
+                    database_header.clone(),
+                    syms,
+                    &mut program,
+                )?;
+            }
+            PragmaName::Optimize => {
+                write = true;
+                emit_optimize(&mut program, schema, syms, parse_optimize_mask(&value)?)?;
+            }
             _ => {
                 write = true;
                 update_pragma(
                     pragma,
                     schema,
+                    database,
                     value,
                     database_header.clone(),
                     pager,
+                    syms,
                     &mut program,
                 )?;
             }
         },
         Some(ast::PragmaBody::Call(value)) => match pragma {
-            PragmaName::TableInfo => {
+            PragmaName::TableInfo | PragmaName::ForeignKeyList | PragmaName::ForeignKeyCheck => {
                 query_pragma(
                     pragma,
                     schema,
+                    database,
                     Some(value),
                     database_header.clone(),
+                    syms,
+                    &mut program,
+                )?;
+            }
+            PragmaName::AnalysisLimit => {
+                update_analysis_limit(&value, syms);
+                query_pragma(
+                    pragma,
+                    schema,
+                    database,
+                    None,
+                    database_header.clone(),
+                    syms,
+                    &mut program,
+                )?;
+            }
+            PragmaName::Optimize => {
+                write = true;
+                emit_optimize(&mut program, schema, syms, parse_optimize_mask(&value)?)?;
+            }
+            PragmaName::AutoVacuum | PragmaName::IncrementalVacuum => {
+                write = true;
+                update_pragma(
+                    pragma,
+                    schema,
+                    database,
+                    value,
+                    database_header.clone(),
+                    pager,
+                    syms,
                     &mut program,
                 )?;
             }
@@ -112,12 +219,15 @@ pub fn translate_pragma(
     Ok(program)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn update_pragma(
     pragma: PragmaName,
     schema: &Schema,
+    database: usize,
     value: ast::Expr,
     header: Arc<SpinLock<DatabaseHeader>>,
     pager: Rc<Pager>,
+    syms: &SymbolTable,
     program: &mut ProgramBuilder,
 ) -> crate::Result<()> {
     match pragma {
@@ -138,23 +248,100 @@ fn update_pragma(
             Ok(())
         }
         PragmaName::JournalMode => {
-            query_pragma(PragmaName::JournalMode, schema, None, header, program)?;
+            query_pragma(
+                PragmaName::JournalMode,
+                schema,
+                database,
+                None,
+                header,
+                syms,
+                program,
+            )?;
             Ok(())
         }
         PragmaName::LegacyFileFormat => Ok(()),
         PragmaName::WalCheckpoint => {
-            query_pragma(PragmaName::WalCheckpoint, schema, None, header, program)?;
+            query_pragma(
+                PragmaName::WalCheckpoint,
+                schema,
+                database,
+                None,
+                header,
+                syms,
+                program,
+            )?;
             Ok(())
         }
         PragmaName::PageCount => {
-            query_pragma(PragmaName::PageCount, schema, None, header, program)?;
+            query_pragma(
+                PragmaName::PageCount,
+                schema,
+                database,
+                None,
+                header,
+                syms,
+                program,
+            )?;
             Ok(())
         }
         PragmaName::UserVersion => {
             // TODO: Implement updating user_version
             todo!("updating user_version not yet implemented")
         }
-        PragmaName::TableInfo => {
+        PragmaName::AutoVacuum => {
+            let mode = AutoVacuumMode::from_pragma_value(&pragma_value_text(&value)?);
+            // The mode a database was created with only changes when VACUUM rebuilds it, except
+            // between FULL and INCREMENTAL.
+            pager.auto_vacuum_request.set(Some(mode));
+            if header.lock().database_size <= 1 {
+                program.emit_insn(Insn::SetCookie {
+                    db: database,
+                    cookie: Cookie::LargestRootPageNumber,
+                    value: (mode != AutoVacuumMode::None) as u32,
+                });
+                program.emit_insn(Insn::SetCookie {
+                    db: database,
+                    cookie: Cookie::IncrementalVacuum,
+                    value: (mode == AutoVacuumMode::Incremental) as u32,
+                });
+            } else if mode != AutoVacuumMode::None {
+                let largest_root_reg = program.alloc_register();
+                program.emit_insn(Insn::ReadCookie {
+                    db: database,
+                    dest: largest_root_reg,
+                    cookie: Cookie::LargestRootPageNumber,
+                });
+                let done_label = program.allocate_label();
+                program.emit_insn(Insn::IfNot {
+                    reg: largest_root_reg,
+                    target_pc: done_label,
+                    jump_if_null: true,
+                });
+                program.emit_insn(Insn::SetCookie {
+                    db: database,
+                    cookie: Cookie::IncrementalVacuum,
+                    value: (mode == AutoVacuumMode::Incremental) as u32,
+                });
+                program.resolve_label(done_label, program.offset());
+            }
+            Ok(())
+        }
+        PragmaName::IncrementalVacuum => {
+            let limit = match value {
+                ast::Expr::Literal(ast::Literal::Numeric(limit)) => limit.parse::<i64>().ok(),
+                _ => None,
+            };
+            emit_incremental_vacuum(program, database, limit);
+            Ok(())
+        }
+        PragmaName::TableInfo
+        | PragmaName::DatabaseList
+        | PragmaName::AnalysisLimit
+        | PragmaName::Optimize
+        | PragmaName::ForeignKeys
+        | PragmaName::IgnoreCheckConstraints
+        | PragmaName::ForeignKeyList
+        | PragmaName::ForeignKeyCheck => {
             // because we need control over the write parameter for the transaction,
             // this should be unreachable. We have to force-call query_pragma before
             // getting here
@@ -166,12 +353,21 @@ fn update_pragma(
 fn query_pragma(
     pragma: PragmaName,
     schema: &Schema,
+    database: usize,
     value: Option<ast::Expr>,
     database_header: Arc<SpinLock<DatabaseHeader>>,
+    syms: &SymbolTable,
     program: &mut ProgramBuilder,
 ) -> crate::Result<()> {
     let register = program.alloc_register();
     match pragma {
+        PragmaName::AutoVacuum => {
+            let mode = AutoVacuumMode::from_header(&database_header.lock());
+            program.emit_int(mode as i64, register);
+            program.emit_result_row(register, 1);
+        }
+        // Gives back free pages rather than returning anything.
+        PragmaName::IncrementalVacuum => unreachable!(),
         PragmaName::CacheSize => {
             program.emit_int(
                 database_header.lock().default_page_cache_size.into(),
@@ -190,7 +386,7 @@ fn query_pragma(
             program.alloc_register();
             program.alloc_register();
             program.emit_insn(Insn::Checkpoint {
-                database: 0,
+                database,
                 checkpoint_mode: CheckpointMode::Passive,
                 dest: register,
             });
@@ -198,16 +394,17 @@ fn query_pragma(
         }
         PragmaName::PageCount => {
             program.emit_insn(Insn::PageCount {
-                db: 0,
+                db: database,
                 dest: register,
             });
             program.emit_result_row(register, 1);
         }
         PragmaName::TableInfo => {
             let table = match value {
+                // Like in SQLite, an unqualified table is looked up in every database
                 Some(ast::Expr::Name(name)) => {
                     let tbl = normalize_ident(&name.0);
-                    schema.get_table(&tbl)
+                    schema.find_table(None, &tbl)?.map(|(_, table)| table)
                 }
                 _ => None,
             };
@@ -248,10 +445,76 @@ fn query_pragma(
                 }
             }
         }
+        PragmaName::AnalysisLimit => {
+            program.emit_int(syms.analysis_limit.get(), register);
+            program.emit_result_row(register, 1);
+        }
+        PragmaName::Optimize | PragmaName::DatabaseList => unreachable!(),
+        PragmaName::ForeignKeys => {
+            program.emit_bool(syms.foreign_keys.get(), register);
+            program.emit_result_row(register, 1);
+        }
+        PragmaName::IgnoreCheckConstraints => {
+            program.emit_bool(syms.ignore_check_constraints.get(), register);
+            program.emit_result_row(register, 1);
+        }
+        PragmaName::ForeignKeyList => {
+            let table = match value {
+                Some(ast::Expr::Name(name)) => schema.get_btree_table(&normalize_ident(&name.0)),
+                _ => None,
+            };
+            let base_reg = register;
+            program.alloc_registers(7);
+            // Like in SQLite, the constraints are numbered from the last one declared.
+            let foreign_keys = table
+                .iter()
+                .flat_map(|table| table.foreign_keys.iter().rev());
+            for (id, fk) in foreign_keys.enumerate() {
+                for (seq, from) in fk.child_columns.iter().enumerate() {
+                    program.emit_int(id as i64, base_reg);
+                    program.emit_int(seq as i64, base_reg + 1);
+                    program.emit_string8(fk.parent_table.clone(), base_reg + 2);
+                    program.emit_string8(from.clone(), base_reg + 3);
+                    match fk.parent_columns.get(seq) {
+                        Some(to) => program.emit_string8(to.clone(), base_reg + 4),
+                        None => program.emit_null(base_reg + 4, None),
+                    }
+                    program.emit_string8(action_name(fk.on_update).to_string(), base_reg + 5);
+                    program.emit_string8(action_name(fk.on_delete).to_string(), base_reg + 6);
+                    program.emit_string8("NONE".to_string(), base_reg + 7);
+                    program.emit_result_row(base_reg, 8);
+                }
+            }
+        }
+        PragmaName::ForeignKeyCheck => {
+            let tables = match value {
+                Some(ast::Expr::Name(name)) => {
+                    let name = normalize_ident(&name.0);
+                    match schema.get_btree_table(&name) {
+                        Some(table) => vec![table],
+                        None => bail_parse_error!("no such table: {}", name),
+                    }
+                }
+                _ => {
+                    let mut tables = schema
+                        .tables
+                        .values()
+                        .filter_map(|table| table.btree())
+                        .collect::<Vec<_>>();
+                    tables.sort_by(|a, b| a.name.cmp(&b.name));
+                    tables
+                }
+            };
+            let base_reg = register;
+            program.alloc_registers(3);
+            for table in tables {
+                emit_foreign_key_check(program, schema, &table, base_reg)?;
+            }
+        }
         PragmaName::UserVersion => {
             program.emit_transaction(false);
             program.emit_insn(Insn::ReadCookie {
-                db: 0,
+                db: database,
                 dest: register,
                 cookie: Cookie::UserVersion,
             });
@@ -262,6 +525,166 @@ fn query_pragma(
     Ok(())
 }
 
+/// Emits a row of `seq`, `name` and `file` for each database of the connection.
+fn emit_database_list(program: &mut ProgramBuilder, connection: &Weak<Connection>) {
+    let Some(connection) = connection.upgrade() else {
+        return;
+    };
+    let base_reg = program.alloc_registers(3);
+    for (seq, name, file) in connection.database_list() {
+        program.emit_int(seq as i64, base_reg);
+        program.emit_string8(name, base_reg + 1);
+        program.emit_string8(file, base_reg + 2);
+        program.emit_result_row(base_reg, 3);
+    }
+}
+
+/// Emits a row of `table`, `rowid`, `parent` and `fkid` at `base_reg` for each row of `table`
+/// that violates one of its foreign key constraints.
+fn emit_foreign_key_check(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    table: &Rc<BTreeTable>,
+    base_reg: usize,
+) -> crate::Result<()> {
+    if table.foreign_keys.is_empty() {
+        return Ok(());
+    }
+    let foreign_keys = (0..table.foreign_keys.len())
+        .rev()
+        .map(|fk_index| ResolvedForeignKey::new(schema, table, fk_index))
+        .collect::<crate::Result<Vec<_>>>()?;
+    let cursor_id = program.alloc_cursor_id(
+        Some(table.name.clone()),
+        CursorType::BTreeTable(table.clone()),
+    );
+    program.emit_insn(Insn::OpenRead {
+        cursor_id,
+        root_page: table.root_page,
+        db: 0,
+    });
+    let done_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: done_label,
+    });
+    let loop_start = program.offset();
+    let row = program.alloc_registers(table.columns.len() + 1);
+    program.emit_insn(Insn::RowId {
+        cursor_id,
+        dest: row,
+    });
+    for i in 0..table.columns.len() {
+        program.emit_insn(Insn::Column {
+            cursor_id,
+            column: i,
+            dest: row + 1 + i,
+        });
+    }
+    for (fkid, fk) in foreign_keys.iter().enumerate() {
+        let found_label = program.allocate_label();
+        fk.emit_parent_lookup(program, row, found_label);
+        program.emit_string8(table.name.clone(), base_reg);
+        program.emit_insn(Insn::Copy {
+            src_reg: row,
+            dst_reg: base_reg + 1,
+            amount: 0,
+        });
+        program.emit_string8(fk.parent.name.clone(), base_reg + 2);
+        program.emit_int(fkid as i64, base_reg + 3);
+        program.emit_result_row(base_reg, 4);
+        program.resolve_label(found_label, program.offset());
+    }
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(done_label, program.offset());
+    Ok(())
+}
+
+/// The name of an ON DELETE or ON UPDATE action, as listed by PRAGMA foreign_key_list.
+fn action_name(action: RefAct) -> &'static str {
+    match action {
+        RefAct::SetNull => "SET NULL",
+        RefAct::SetDefault => "SET DEFAULT",
+        RefAct::Cascade => "CASCADE",
+        RefAct::Restrict => "RESTRICT",
+        RefAct::NoAction => "NO ACTION",
+    }
+}
+
+/// Emits the steps of `PRAGMA incremental_vacuum`, which gives back up to `limit` free pages of
+/// the database at `database`, or all of them if there is no positive limit.
+fn emit_incremental_vacuum(program: &mut ProgramBuilder, database: usize, limit: Option<i64>) {
+    let limit = limit.filter(|limit| *limit > 0).unwrap_or(i32::MAX as i64);
+    let remaining_reg = program.alloc_register();
+    program.emit_int(limit - 1, remaining_reg);
+    let loop_start = program.offset();
+    let done_label = program.allocate_label();
+    program.emit_insn(Insn::IncrVacuum {
+        db: database,
+        target_pc: done_label,
+    });
+    program.emit_insn(Insn::IfPos {
+        reg: remaining_reg,
+        target_pc: loop_start,
+        decrement_by: 1,
+    });
+    program.resolve_label(done_label, program.offset());
+}
+
+/// The text of a pragma value, like ON, 'full' or 1.
+fn pragma_value_text(value: &ast::Expr) -> crate::Result<String> {
+    match value {
+        ast::Expr::Literal(ast::Literal::Numeric(text) | ast::Literal::Keyword(text)) => {
+            Ok(text.clone())
+        }
+        ast::Expr::Literal(ast::Literal::String(text)) => Ok(text.trim_matches('\'').to_string()),
+        ast::Expr::Id(ast::Id(text)) | ast::Expr::Name(ast::Name(text)) => Ok(text.clone()),
+        _ => bail_parse_error!("Not a valid value"),
+    }
+}
+
+/// Sets PRAGMA analysis_limit, ignoring the values that are not a non-negative integer like
+/// SQLite does.
+fn update_analysis_limit(value: &ast::Expr, syms: &SymbolTable) {
+    let limit = pragma_value_text(value)
+        .ok()
+        .and_then(|text| text.parse::<i64>().ok());
+    if let Some(limit) = limit.filter(|limit| *limit >= 0) {
+        syms.analysis_limit.set(limit.min(i32::MAX as i64));
+    }
+}
+
+/// The optimizations that PRAGMA optimize runs when no mask is given.
+const DEFAULT_OPTIMIZE_MASK: i64 = 0xfffe;
+
+fn parse_optimize_mask(value: &ast::Expr) -> crate::Result<i64> {
+    let text = pragma_value_text(value)?;
+    let mask = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => text.parse::<i64>().ok(),
+    };
+    match mask {
+        Some(mask) => Ok(mask),
+        None => bail_parse_error!("Not a valid value"),
+    }
+}
+
+/// Parses the value of a boolean pragma, like ON, FALSE or 1.
+fn parse_bool(value: &ast::Expr) -> crate::Result<bool> {
+    let text = pragma_value_text(value)?;
+    match text.to_lowercase().as_str() {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        number => match number.parse::<i64>() {
+            Ok(number) => Ok(number != 0),
+            Err(_) => bail_parse_error!("Not a valid value"),
+        },
+    }
+}
+
 fn update_cache_size(value: i64, header: Arc<SpinLock<DatabaseHeader>>, pager: Rc<Pager>) {
     let mut cache_size_unformatted: i64 = value;
     let mut cache_size = if cache_size_unformatted < 0 {
@@ -0,0 +1,267 @@
+use std::sync::Arc;
+
+use limbo_sqlite3_parser::ast::SortOrder;
+
+use crate::{
+    schema::{Index, IndexColumn},
+    util::normalize_ident,
+    vdbe::{
+        builder::{CursorType, ProgramBuilder},
+        insn::Insn,
+        CursorID,
+    },
+    Result,
+};
+
+use super::{
+    collate::expr_collation_name,
+    distinct::{emit_open_distinct_index, DistinctMetadata},
+    emitter::{emit_query, Resolver, TranslateCtx},
+    main_loop::LoopLabels,
+    plan::{Operation, RecursiveCte, SelectPlan, SelectQueryType},
+    result_row::emit_result_row,
+};
+
+/// Emit a recursive CTE, i.e. a SELECT whose plan has recursive members, see [RecursiveCte].
+/// Returns the start register of the result columns.
+///
+/// The rows of the initial part and of the recursive members are added to a queue, which is an
+/// ephemeral index keyed by the ORDER BY terms of the CTE, if any, and then by the position of each
+/// row in the queue. The first row of the queue is taken out of it until it is empty: the row is
+/// copied into the result registers and deleted from the queue, sent to the destination of the
+/// CTE, and then the recursive members run with that row as the only row of the CTE. The recursive
+/// members read the row directly from the result registers, so they write their own results into
+/// separate registers before adding them to the queue.
+///
+/// With UNION, every row that was ever added to the queue is also kept in a second ephemeral index,
+/// and rows that are already in it are not added again, so the recursion terminates even if the
+/// data has cycles.
+pub fn emit_recursive_cte(
+    program: &mut ProgramBuilder,
+    plan: &mut SelectPlan,
+    t_ctx: &mut TranslateCtx,
+) -> Result<usize> {
+    let reg_result_cols_start = match t_ctx.reg_result_cols_start {
+        Some(reg) => reg,
+        None => program.alloc_registers(plan.result_columns.len()),
+    };
+    t_ctx.reg_result_cols_start = Some(reg_result_cols_start);
+
+    // Detach the recursive members while emitting, so that the initial part can be emitted like
+    // any other SELECT.
+    let mut recursive = plan.recursive.take().unwrap();
+    let destination = plan.query_type.clone();
+    let result = emit_recursion(
+        program,
+        &t_ctx.resolver,
+        plan,
+        &mut recursive,
+        &destination,
+        reg_result_cols_start,
+    );
+    plan.query_type = destination;
+    plan.recursive = Some(recursive);
+    result?;
+
+    Ok(reg_result_cols_start)
+}
+
+fn emit_recursion(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    initial: &mut SelectPlan,
+    recursive: &mut RecursiveCte,
+    destination: &SelectQueryType,
+    reg_result_cols_start: usize,
+) -> Result<()> {
+    let num_cols = initial.result_columns.len();
+    let label_done = program.allocate_label();
+
+    // The CTE may run more than once, e.g. when it is the inner loop of a join, so everything is
+    // initialized with regular instructions instead of constant ones.
+    let reg_limit = recursive.limit.map(|limit| {
+        let reg = program.alloc_register();
+        program.emit_insn(Insn::Integer {
+            value: limit as i64,
+            dest: reg,
+        });
+        reg
+    });
+    if let Some(reg_limit) = reg_limit {
+        program.emit_insn(Insn::IfNot {
+            reg: reg_limit,
+            target_pc: label_done,
+            jump_if_null: true,
+        });
+    }
+    let reg_offset = recursive.offset.map(|offset| {
+        let reg = program.alloc_register();
+        program.emit_insn(Insn::Integer {
+            value: offset as i64,
+            dest: reg,
+        });
+        reg
+    });
+
+    // Opening an ephemeral index again empties it, which resets the queue on every run.
+    // The key of a queue entry is the ORDER BY terms, the position of the row, the row and a
+    // constant 0, see emit_queue_insert().
+    let num_sort_cols = recursive.order_by.len() + 1;
+    let queue_cursor_id = emit_open_ephemeral_index(program, &recursive.order_by, num_cols + 1);
+    // Like for UNION, rows are compared with the collating sequences of the result columns of the
+    // initial part.
+    let distinct_cursor_id = recursive.distinct.then(|| {
+        let collations: Vec<Option<String>> = initial
+            .result_columns
+            .iter()
+            .map(|rc| {
+                expr_collation_name(&rc.expr, Some(&initial.table_references)).map(normalize_ident)
+            })
+            .collect();
+        emit_open_distinct_index(program, &collations)
+    });
+    let reg_queue_len = program.alloc_register();
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: reg_queue_len,
+    });
+    let queue = SelectQueryType::RecursiveQueue {
+        queue_cursor_id,
+        order_by_columns: recursive
+            .order_by
+            .iter()
+            .map(|column| column.pos_in_table)
+            .collect(),
+        distinct_cursor_id,
+        reg_queue_len,
+    };
+
+    emit_member(program, resolver, initial, &queue)?;
+
+    // Take the first row out of the queue.
+    let loop_start = program.offset();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: queue_cursor_id,
+        pc_if_empty: label_done,
+    });
+    for i in 0..num_cols {
+        program.emit_insn(Insn::Column {
+            cursor_id: queue_cursor_id,
+            column: num_sort_cols + i,
+            dest: reg_result_cols_start + i,
+        });
+    }
+    program.emit_insn(Insn::Delete {
+        cursor_id: queue_cursor_id,
+        count_change: false,
+    });
+
+    // LIMIT and OFFSET apply to the rows of the CTE, so the recursion stops as soon as enough rows
+    // were sent to the destination.
+    let label_recurse = program.allocate_label();
+    if let Some(reg_offset) = reg_offset {
+        program.emit_insn(Insn::IfPos {
+            reg: reg_offset,
+            target_pc: label_recurse,
+            decrement_by: 1,
+        });
+    }
+    emit_result_row(program, destination, reg_result_cols_start, num_cols);
+    if let Some(reg_limit) = reg_limit {
+        program.emit_insn(Insn::DecrJumpZero {
+            reg: reg_limit,
+            target_pc: label_done,
+        });
+    }
+    program.resolve_label(label_recurse, program.offset());
+
+    for member in recursive.members.iter_mut() {
+        for table in member.table_references.iter_mut() {
+            if let Operation::Subquery {
+                plan,
+                result_columns_start_reg,
+            } = &mut table.op
+            {
+                if matches!(plan.query_type, SelectQueryType::RecursiveRow) {
+                    *result_columns_start_reg = reg_result_cols_start;
+                }
+            }
+        }
+        emit_member(program, resolver, member, &queue)?;
+    }
+    program.emit_insn(Insn::Goto {
+        target_pc: loop_start,
+    });
+    program.resolve_label(label_done, program.offset());
+    Ok(())
+}
+
+/// Emit the initial part or a recursive member of a recursive CTE, adding its rows to the queue.
+/// Each member is a regular SELECT with its own loops, so it gets its own [TranslateCtx].
+fn emit_member(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    plan: &mut SelectPlan,
+    queue: &SelectQueryType,
+) -> Result<()> {
+    plan.query_type = queue.clone();
+    let mut t_ctx = TranslateCtx {
+        labels_main_loop: (0..plan.table_references.len())
+            .map(|_| LoopLabels::new(program))
+            .collect(),
+        label_main_loop_end: None,
+        meta_group_by: None,
+        meta_left_joins: (0..plan.table_references.len()).map(|_| None).collect(),
+        meta_right_joins: (0..plan.table_references.len()).map(|_| None).collect(),
+        meta_sort: None,
+        meta_windows: vec![],
+        meta_distinct: DistinctMetadata::default(),
+        reg_agg_start: None,
+        reg_nonagg_emit_once_flag: None,
+        reg_result_cols_start: None,
+        result_column_indexes_in_orderby_sorter: (0..plan.result_columns.len()).collect(),
+        result_columns_to_skip_in_orderby_sorter: None,
+        reg_limit: None,
+        reg_offset: None,
+        reg_limit_offset_sum: None,
+        resolver: resolver.nested(),
+        omit_predicates: Vec::new(),
+    };
+    emit_query(program, plan, &mut t_ctx)?;
+    Ok(())
+}
+
+/// Open an ephemeral index whose keys are the `leading` columns and `num_cols` more columns,
+/// followed by a constant 0.
+fn emit_open_ephemeral_index(
+    program: &mut ProgramBuilder,
+    leading: &[IndexColumn],
+    num_cols: usize,
+) -> CursorID {
+    let index = Arc::new(Index {
+        name: "ephemeral_recursive".to_string(),
+        table_name: String::new(),
+        root_page: 0,
+        columns: leading
+            .iter()
+            .cloned()
+            .chain((0..num_cols).map(|i| IndexColumn {
+                name: format!("c{}", i),
+                order: SortOrder::Asc,
+                pos_in_table: i,
+                collation: None,
+                expr: None,
+            }))
+            .collect(),
+        unique: false,
+        ephemeral: true,
+        where_clause: None,
+        key_suffix: Vec::new(),
+    });
+    let cursor_id = program.alloc_cursor_id(None, CursorType::BTreeIndex(index));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id,
+        is_table: false,
+    });
+    cursor_id
+}
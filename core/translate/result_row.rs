@@ -1,9 +1,17 @@
+use std::num::NonZeroUsize;
+
 use crate::{
-    vdbe::{builder::ProgramBuilder, insn::Insn, BranchOffset},
+    schema::Affinity,
+    vdbe::{
+        builder::ProgramBuilder,
+        insn::{IdxInsertFlags, Insn},
+        BranchOffset,
+    },
     Result,
 };
 
 use super::{
+    distinct::emit_distinct_check,
     emitter::TranslateCtx,
     expr::translate_expr,
     plan::{SelectPlan, SelectQueryType},
@@ -20,7 +28,11 @@ pub fn emit_select_result(
     label_on_limit_reached: Option<BranchOffset>,
     offset_jump_to: Option<BranchOffset>,
 ) -> Result<()> {
-    if let (Some(jump_to), Some(_)) = (offset_jump_to, label_on_limit_reached) {
+    // With DISTINCT, OFFSET only counts the rows that are not skipped as duplicates.
+    let distinct_cursor_id = t_ctx.meta_distinct.cursor_id;
+    if let (Some(jump_to), Some(_), None) =
+        (offset_jump_to, label_on_limit_reached, distinct_cursor_id)
+    {
         emit_offset(program, t_ctx, plan, jump_to)?;
     }
 
@@ -44,7 +56,16 @@ pub fn emit_select_result(
             &t_ctx.resolver,
         )?;
     }
+    let label_duplicate = program.allocate_label();
+    if let Some(cursor_id) = distinct_cursor_id {
+        let regs: Vec<usize> = (start_reg..start_reg + plan.result_columns.len()).collect();
+        emit_distinct_check(program, cursor_id, &regs, label_duplicate);
+        if let (Some(jump_to), Some(_)) = (offset_jump_to, label_on_limit_reached) {
+            emit_offset(program, t_ctx, plan, jump_to)?;
+        }
+    }
     emit_result_row_and_limit(program, t_ctx, plan, start_reg, label_on_limit_reached)?;
+    program.resolve_label(label_duplicate, program.offset());
     Ok(())
 }
 
@@ -58,20 +79,12 @@ pub fn emit_result_row_and_limit(
     result_columns_start_reg: usize,
     label_on_limit_reached: Option<BranchOffset>,
 ) -> Result<()> {
-    match &plan.query_type {
-        SelectQueryType::TopLevel => {
-            program.emit_insn(Insn::ResultRow {
-                start_reg: result_columns_start_reg,
-                count: plan.result_columns.len(),
-            });
-        }
-        SelectQueryType::Subquery { yield_reg, .. } => {
-            program.emit_insn(Insn::Yield {
-                yield_reg: *yield_reg,
-                end_offset: BranchOffset::Offset(0),
-            });
-        }
-    }
+    emit_result_row(
+        program,
+        &plan.query_type,
+        result_columns_start_reg,
+        plan.result_columns.len(),
+    );
 
     if let Some(limit) = plan.limit {
         if label_on_limit_reached.is_none() {
@@ -109,6 +122,194 @@ pub fn emit_result_row_and_limit(
     Ok(())
 }
 
+/// Emits the bytecode for sending a single result row, stored in `count` registers starting at
+/// `start_reg`, to its destination:
+/// - a top level query returns the row to the caller
+/// - a subquery yields to the parent query
+/// - a member of a compound SELECT inserts the row into an ephemeral index
+/// - a member of a recursive CTE adds the row to the queue of the CTE
+/// - a scalar or EXISTS subquery in an expression stores its result and stops
+pub fn emit_result_row(
+    program: &mut ProgramBuilder,
+    query_type: &SelectQueryType,
+    start_reg: usize,
+    count: usize,
+) {
+    match query_type {
+        SelectQueryType::TopLevel => {
+            program.emit_insn(Insn::ResultRow { start_reg, count });
+        }
+        SelectQueryType::Subquery { yield_reg, .. } => {
+            program.emit_insn(Insn::Yield {
+                yield_reg: *yield_reg,
+                end_offset: BranchOffset::Offset(0),
+            });
+        }
+        SelectQueryType::IndexInsert {
+            cursor_id,
+            affinity,
+        } => {
+            emit_index_insert(program, *cursor_id, *affinity, start_reg, count);
+        }
+        SelectQueryType::RecursiveQueue {
+            queue_cursor_id,
+            order_by_columns,
+            distinct_cursor_id,
+            reg_queue_len,
+        } => {
+            emit_queue_insert(
+                program,
+                *queue_cursor_id,
+                order_by_columns,
+                *distinct_cursor_id,
+                *reg_queue_len,
+                start_reg,
+                count,
+            );
+        }
+        SelectQueryType::RecursiveRow => {
+            unreachable!("the row of a recursive CTE is never emitted as a query")
+        }
+        SelectQueryType::Scalar {
+            dest_reg,
+            label_done,
+        } => {
+            program.emit_insn(Insn::Copy {
+                src_reg: start_reg,
+                dst_reg: *dest_reg,
+                amount: count - 1,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: *label_done,
+            });
+        }
+        SelectQueryType::Exists {
+            dest_reg,
+            label_done,
+        } => {
+            program.emit_insn(Insn::Integer {
+                value: 1,
+                dest: *dest_reg,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: *label_done,
+            });
+        }
+    }
+}
+
+/// Adds a result row to the queue of a recursive CTE.
+/// The key of a queue entry is the values of the `order_by_columns` of the row, the position of
+/// the row in the queue, the row itself and a constant 0, so that the first entry of the queue is
+/// the first row in the order of the ORDER BY terms, or else in insertion order.
+/// If the CTE uses UNION, rows that were already added to the queue once are skipped.
+fn emit_queue_insert(
+    program: &mut ProgramBuilder,
+    queue_cursor_id: usize,
+    order_by_columns: &[usize],
+    distinct_cursor_id: Option<usize>,
+    reg_queue_len: usize,
+    start_reg: usize,
+    count: usize,
+) {
+    let label_skip = program.allocate_label();
+    if let Some(distinct_cursor_id) = distinct_cursor_id {
+        let regs: Vec<usize> = (start_reg..start_reg + count).collect();
+        emit_distinct_check(program, distinct_cursor_id, &regs, label_skip);
+    }
+
+    let key_len = order_by_columns.len() + count + 2;
+    let key_start_reg = program.alloc_registers(key_len);
+    for (i, column) in order_by_columns.iter().enumerate() {
+        program.emit_insn(Insn::Copy {
+            src_reg: start_reg + column,
+            dst_reg: key_start_reg + i,
+            amount: 0,
+        });
+    }
+    let reg_pos = key_start_reg + order_by_columns.len();
+    program.emit_insn(Insn::Integer {
+        value: 1,
+        dest: reg_pos,
+    });
+    program.emit_insn(Insn::Add {
+        lhs: reg_queue_len,
+        rhs: reg_pos,
+        dest: reg_queue_len,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_queue_len,
+        dst_reg: reg_pos,
+        amount: 0,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: start_reg,
+        dst_reg: reg_pos + 1,
+        amount: count - 1,
+    });
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: reg_pos + count + 1,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: key_start_reg,
+        count: key_len,
+        dest_reg: record_reg,
+    });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id: queue_cursor_id,
+        record_reg,
+        unpacked_start: Some(key_start_reg),
+        unpacked_count: Some(key_len as u16),
+        flags: IdxInsertFlags::new().use_seek(false),
+    });
+    program.resolve_label(label_skip, program.offset());
+}
+
+/// Inserts a result row into an ephemeral index of a compound SELECT.
+/// Index cursors expect every key to end in an integer rowid, so the row is copied into a key
+/// that ends in a constant 0. Duplicate keys may end up in the index; they are skipped when the
+/// index is scanned.
+fn emit_index_insert(
+    program: &mut ProgramBuilder,
+    cursor_id: usize,
+    affinity: Option<Affinity>,
+    start_reg: usize,
+    count: usize,
+) {
+    let key_start_reg = program.alloc_registers(count + 1);
+    program.emit_insn(Insn::Copy {
+        src_reg: start_reg,
+        dst_reg: key_start_reg,
+        amount: count - 1,
+    });
+    if let Some(affinity) = affinity {
+        program.emit_insn(Insn::Affinity {
+            start_reg: key_start_reg,
+            count: NonZeroUsize::new(count).unwrap(),
+            affinities: affinity.aff_mask().to_string().repeat(count),
+        });
+    }
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: key_start_reg + count,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: key_start_reg,
+        count: count + 1,
+        dest_reg: record_reg,
+    });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id,
+        record_reg,
+        unpacked_start: Some(key_start_reg),
+        unpacked_count: Some((count + 1) as u16),
+        flags: IdxInsertFlags::new().use_seek(false),
+    });
+}
+
 pub fn emit_offset(
     program: &mut ProgramBuilder,
     t_ctx: &mut TranslateCtx,
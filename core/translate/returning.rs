@@ -0,0 +1,211 @@
+//! The RETURNING clause of INSERT, UPDATE and DELETE.
+//!
+//! Like in SQLite, the statement makes all of its changes before it returns the first row: the
+//! values of the RETURNING clause for each changed row are collected in an ephemeral index, keyed
+//! by a counter that keeps them in order, and are sent as result rows once the statement is done.
+//! Like the key of any index entry, the key of a row ends with an integer, which is the counter
+//! again.
+
+use std::sync::Arc;
+
+use limbo_sqlite3_parser::ast::{self, Expr, ResultColumn, SortOrder};
+
+use crate::schema::{Index, IndexColumn};
+use crate::vdbe::builder::{CursorType, ProgramBuilder};
+use crate::vdbe::insn::{IdxInsertFlags, Insn};
+use crate::vdbe::CursorID;
+use crate::{bail_parse_error, Result};
+
+use super::emitter::Resolver;
+use super::expr::translate_expr;
+use super::plan::{ResultSetColumn, TableReference};
+use super::planner::bind_column_references;
+use super::window::child_exprs_mut;
+
+/// Binds the expressions of a RETURNING clause to the changed table, which is the first of
+/// `table_references`, and expands `*`.
+pub fn prepare_returning(
+    returning: &[ResultColumn],
+    table_references: &mut [TableReference],
+) -> Result<Vec<ResultSetColumn>> {
+    let mut result_columns = Vec::with_capacity(returning.len());
+    for result_column in returning {
+        match result_column {
+            ResultColumn::Expr(expr, alias) => {
+                let mut bound_expr = expr.clone();
+                bind_column_references(&mut bound_expr, table_references, None)?;
+                // Like in SQLite, the name of a column that is not a column of the table is the
+                // text of its expression.
+                let alias = match alias {
+                    Some(ast::As::As(name) | ast::As::Elided(name)) => Some(name.0.clone()),
+                    None if matches!(bound_expr, Expr::Column { .. } | Expr::RowId { .. }) => None,
+                    None => Some(expr.to_string()),
+                };
+                result_columns.push(ResultSetColumn {
+                    expr: bound_expr,
+                    alias,
+                    contains_aggregates: false,
+                });
+            }
+            ResultColumn::Star => {
+                for (i, column) in table_references[0].columns().iter().enumerate() {
+                    result_columns.push(ResultSetColumn {
+                        expr: Expr::Column {
+                            database: None,
+                            table: 0,
+                            column: i,
+                            is_rowid_alias: column.is_rowid_alias,
+                        },
+                        alias: None,
+                        contains_aggregates: false,
+                    });
+                }
+            }
+            ResultColumn::TableStar(_) => {
+                bail_parse_error!("table.* is not allowed in RETURNING")
+            }
+        }
+    }
+    Ok(result_columns)
+}
+
+/// The rows of a RETURNING clause, collected while the statement runs.
+pub struct Returning<'a> {
+    columns: &'a [ResultSetColumn],
+    cursor_id: CursorID,
+    reg_counter: usize,
+}
+
+impl<'a> Returning<'a> {
+    /// Opens the ephemeral index that collects the rows of the RETURNING clause with `columns`.
+    pub fn open(program: &mut ProgramBuilder, columns: &'a [ResultSetColumn]) -> Self {
+        let index = Arc::new(Index {
+            name: "ephemeral_returning".to_string(),
+            table_name: String::new(),
+            root_page: 0,
+            columns: (0..columns.len() + 2)
+                .map(|i| IndexColumn {
+                    name: format!("c{}", i),
+                    order: SortOrder::Asc,
+                    pos_in_table: i,
+                    collation: None,
+                    expr: None,
+                })
+                .collect(),
+            unique: false,
+            ephemeral: true,
+            where_clause: None,
+            key_suffix: Vec::new(),
+        });
+        let cursor_id = program.alloc_cursor_id(None, CursorType::BTreeIndex(index));
+        program.emit_insn(Insn::OpenEphemeral {
+            cursor_id,
+            is_table: false,
+        });
+        let reg_counter = program.alloc_register();
+        program.emit_int(0, reg_counter);
+        Self {
+            columns,
+            cursor_id,
+            reg_counter,
+        }
+    }
+
+    /// Collects the values of the RETURNING clause for the row whose rowid and columns are in
+    /// the registers starting at `row`.
+    pub fn emit_row(
+        &self,
+        program: &mut ProgramBuilder,
+        row: usize,
+        resolver: &Resolver,
+    ) -> Result<()> {
+        let key_len = self.columns.len() + 2;
+        let reg_key = program.alloc_registers(key_len);
+        let reg_one = program.alloc_register();
+        program.emit_int(1, reg_one);
+        program.emit_insn(Insn::Add {
+            lhs: self.reg_counter,
+            rhs: reg_one,
+            dest: self.reg_counter,
+        });
+        program.emit_insn(Insn::Copy {
+            src_reg: self.reg_counter,
+            dst_reg: reg_key,
+            amount: 0,
+        });
+        for (i, column) in self.columns.iter().enumerate() {
+            let mut expr = column.expr.clone();
+            rewrite_row_columns(&mut expr, row);
+            translate_expr(program, None, &expr, reg_key + 1 + i, resolver)?;
+        }
+        program.emit_insn(Insn::Copy {
+            src_reg: self.reg_counter,
+            dst_reg: reg_key + key_len - 1,
+            amount: 0,
+        });
+        let reg_record = program.alloc_register();
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: reg_key,
+            count: key_len,
+            dest_reg: reg_record,
+        });
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id: self.cursor_id,
+            record_reg: reg_record,
+            unpacked_start: Some(reg_key),
+            unpacked_count: Some(key_len as u16),
+            flags: IdxInsertFlags::new().use_seek(false),
+        });
+        Ok(())
+    }
+
+    /// Sends the collected rows as result rows.
+    pub fn emit_results(&self, program: &mut ProgramBuilder) {
+        let num_cols = self.columns.len();
+        let reg_result = program.alloc_registers(num_cols);
+        let label_done = program.allocate_label();
+        program.emit_insn(Insn::Rewind {
+            cursor_id: self.cursor_id,
+            pc_if_empty: label_done,
+        });
+        let loop_start = program.offset();
+        for i in 0..num_cols {
+            program.emit_insn(Insn::Column {
+                cursor_id: self.cursor_id,
+                column: i + 1,
+                dest: reg_result + i,
+            });
+        }
+        program.emit_result_row(reg_result, num_cols);
+        program.emit_insn(Insn::Next {
+            cursor_id: self.cursor_id,
+            pc_if_next: loop_start,
+        });
+        program.resolve_label(label_done, program.offset());
+    }
+}
+
+/// Rewrites the references to the columns of the changed table as the registers of the row at
+/// `row`, where the value of a rowid alias column is the rowid.
+fn rewrite_row_columns(expr: &mut Expr, row: usize) {
+    match expr {
+        Expr::Column {
+            table: 0,
+            column,
+            is_rowid_alias,
+            ..
+        } => {
+            *expr = Expr::Register(if *is_rowid_alias {
+                row
+            } else {
+                row + 1 + *column
+            });
+        }
+        Expr::RowId { table: 0, .. } => *expr = Expr::Register(row),
+        _ => {
+            for child in child_exprs_mut(expr) {
+                rewrite_row_columns(child, row);
+            }
+        }
+    }
+}
@@ -1,30 +1,42 @@
 use std::fmt::Display;
 
 use crate::ast;
-use crate::schema::Schema;
 use crate::schema::Table;
+use crate::schema::{BTreeTable, Schema};
 use crate::storage::pager::CreateBTreeFlags;
+use crate::translate::analyze::emit_clear_stat1;
+use crate::translate::collate::resolve_collation;
+use crate::translate::constraint::validate_check_constraints;
+use crate::translate::delete::translate_delete;
 use crate::translate::ProgramBuilder;
 use crate::translate::ProgramBuilderOpts;
 use crate::translate::QueryMode;
-use crate::util::PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX;
+use crate::util::{normalize_ident, statement_tokens, PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX};
 use crate::vdbe::builder::CursorType;
-use crate::vdbe::insn::{CmpInsFlags, Insn};
-use crate::LimboError;
-use crate::{bail_parse_error, Result};
+use crate::vdbe::insn::{CmpInsFlags, Insn, Subprogram};
+use crate::{bail_parse_error, Result, SymbolTable};
 
 use limbo_sqlite3_parser::ast::{fmt::ToTokens, CreateVirtualTable};
+use limbo_sqlite3_parser::dialect::TokenType;
 
+#[allow(clippy::too_many_arguments)]
 pub fn translate_create_table(
     query_mode: QueryMode,
     tbl_name: ast::QualifiedName,
-    temporary: bool,
     body: ast::CreateTableBody,
     if_not_exists: bool,
+    input: &str,
     schema: &Schema,
+    syms: &SymbolTable,
 ) -> Result<ProgramBuilder> {
-    if temporary {
-        bail_parse_error!("TEMPORARY table not supported yet");
+    if let ast::CreateTableBody::ColumnsAndConstraints { columns, .. } = &body {
+        for column in columns.values() {
+            for constraint in &column.constraints {
+                if let ast::ColumnConstraint::Collate { collation_name } = &constraint.constraint {
+                    resolve_collation(Some(&collation_name.0), syms)?;
+                }
+            }
+        }
     }
     let mut program = ProgramBuilder::new(ProgramBuilderOpts {
         query_mode,
@@ -47,7 +59,10 @@ pub fn translate_create_table(
         bail_parse_error!("Table {} already exists", tbl_name);
     }
 
-    let sql = create_table_body_to_str(&tbl_name, &body);
+    check_unique_constraints(&body, &tbl_name.name.0)?;
+    let sql = create_table_sql(input).unwrap_or_else(|| create_table_body_to_str(&tbl_name, &body));
+    let new_table = BTreeTable::from_sql(&sql, 0)?;
+    validate_check_constraints(&new_table)?;
 
     let parse_schema_label = program.allocate_label();
     let init_label = program.emit_init();
@@ -57,15 +72,20 @@ pub fn translate_create_table(
     // TODO: SetCookie
     // TODO: SetCookie
 
-    // Create the table B-tree
+    // Create the table B-tree. A WITHOUT ROWID table is stored in an index B-tree keyed by its
+    // PRIMARY KEY.
     let table_root_reg = program.alloc_register();
     program.emit_insn(Insn::CreateBtree {
-        db: 0,
+        db: schema.database,
         root: table_root_reg,
-        flags: CreateBTreeFlags::new_table(),
+        flags: if new_table.has_rowid {
+            CreateBTreeFlags::new_table()
+        } else {
+            CreateBTreeFlags::new_index()
+        },
     });
 
-    // Create an automatic index B-tree if needed
+    // Create the automatic index B-trees that enforce the PRIMARY KEY and UNIQUE constraints
     //
     // NOTE: we are deviating from SQLite bytecode here. For some reason, SQLite first creates a placeholder entry
     // for the table in sqlite_schema, then writes the index to sqlite_schema, then UPDATEs the table placeholder entry
@@ -73,9 +93,9 @@ pub fn translate_create_table(
     //
     // What we do instead is:
     // 1. Create the table B-tree
-    // 2. Create the index B-tree
+    // 2. Create the index B-trees
     // 3. Add the table entry to sqlite_schema
-    // 4. Add the index entry to sqlite_schema
+    // 4. Add the index entries to sqlite_schema
     //
     // I.e. we skip the weird song and dance with the placeholder entry. Unclear why sqlite does this.
     // The sqlite code has this comment:
@@ -89,11 +109,24 @@ pub fn translate_create_table(
     // https://github.com/sqlite/sqlite/blob/95f6df5b8d55e67d1e34d2bff217305a2f21b1fb/src/build.c#L2856-L2871
     // https://github.com/sqlite/sqlite/blob/95f6df5b8d55e67d1e34d2bff217305a2f21b1fb/src/build.c#L1334C5-L1336C65
 
-    let index_root_reg = check_automatic_pk_index_required(&body, &mut program, &tbl_name.name.0)?;
-    if let Some(index_root_reg) = index_root_reg {
+    //
+    // The PRIMARY KEY of a WITHOUT ROWID table is enforced by the table B-tree itself, but it keeps
+    // its number among the automatic indexes.
+    let primary_key_index_name = new_table.primary_key_index().map(|index| index.name);
+    let automatic_index_names = (1..=new_table.unique_sets.len())
+        .map(|n| {
+            format!(
+                "{}{}_{}",
+                PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX, tbl_name.name.0, n
+            )
+        })
+        .filter(|name| Some(normalize_ident(name)) != primary_key_index_name)
+        .collect::<Vec<_>>();
+    let index_root_regs = program.alloc_registers(automatic_index_names.len());
+    for i in 0..automatic_index_names.len() {
         program.emit_insn(Insn::CreateBtree {
-            db: 0,
-            root: index_root_reg,
+            db: schema.database,
+            root: index_root_regs + i,
             flags: CreateBTreeFlags::new_index(),
         });
     }
@@ -106,6 +139,7 @@ pub fn translate_create_table(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: 1usize.into(),
+        db: schema.database,
     });
 
     // Add the table entry to sqlite_schema
@@ -119,19 +153,15 @@ pub fn translate_create_table(
         Some(sql),
     );
 
-    // If we need an automatic index, add its entry to sqlite_schema
-    if let Some(index_root_reg) = index_root_reg {
-        let index_name = format!(
-            "{}{}_1",
-            PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX, tbl_name.name.0
-        );
+    // Add the entries of the automatic indexes to sqlite_schema
+    for (i, index_name) in automatic_index_names.iter().enumerate() {
         emit_schema_entry(
             &mut program,
             sqlite_schema_cursor_id,
             SchemaEntryType::Index,
-            &index_name,
+            index_name,
             &tbl_name.name.0,
-            index_root_reg,
+            index_root_regs + i,
             None,
         );
     }
@@ -140,9 +170,10 @@ pub fn translate_create_table(
     // TODO: SetCookie
     //
     // TODO: remove format, it sucks for performance but is convenient
-    let parse_schema_where_clause = format!("tbl_name = '{}' AND type != 'trigger'", tbl_name);
+    let parse_schema_where_clause =
+        format!("tbl_name = '{}' AND type != 'trigger'", tbl_name.name.0);
     program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
+        db: schema.database,
         where_clause: parse_schema_where_clause,
     });
 
@@ -160,6 +191,8 @@ pub fn translate_create_table(
 pub enum SchemaEntryType {
     Table,
     Index,
+    View,
+    Trigger,
 }
 
 impl SchemaEntryType {
@@ -167,6 +200,8 @@ impl SchemaEntryType {
         match self {
             SchemaEntryType::Table => "table",
             SchemaEntryType::Index => "index",
+            SchemaEntryType::View => "view",
+            SchemaEntryType::Trigger => "trigger",
         }
     }
 }
@@ -228,85 +263,38 @@ pub fn emit_schema_entry(
     });
 }
 
-struct PrimaryKeyColumnInfo<'a> {
-    name: &'a String,
-    is_descending: bool,
-}
-
-/// Check if an automatic PRIMARY KEY index is required for the table.
-/// If so, create a register for the index root page and return it.
-///
-/// An automatic PRIMARY KEY index is not required if:
-/// - The table has no PRIMARY KEY
-/// - The table has a single-column PRIMARY KEY whose typename is _exactly_ "INTEGER" e.g. not "INT".
-///   In this case, the PRIMARY KEY column becomes an alias for the rowid.
-///
-/// Otherwise, an automatic PRIMARY KEY index is required.
-fn check_automatic_pk_index_required(
-    body: &ast::CreateTableBody,
-    program: &mut ProgramBuilder,
-    tbl_name: &str,
-) -> Result<Option<usize>> {
+/// Checks the PRIMARY KEY and UNIQUE constraints of a new table.
+fn check_unique_constraints(body: &ast::CreateTableBody, tbl_name: &str) -> Result<()> {
     match body {
         ast::CreateTableBody::ColumnsAndConstraints {
             columns,
             constraints,
             options,
         } => {
-            let mut primary_key_definition = None;
-
-            // Check table constraints for PRIMARY KEY
-            if let Some(constraints) = constraints {
-                for constraint in constraints {
-                    if let ast::TableConstraint::PrimaryKey {
-                        columns: pk_cols, ..
-                    } = &constraint.constraint
-                    {
-                        let primary_key_column_results: Vec<Result<PrimaryKeyColumnInfo>> = pk_cols
-                            .iter()
-                            .map(|col| match &col.expr {
-                                ast::Expr::Id(name) => Ok(PrimaryKeyColumnInfo {
-                                    name: &name.0,
-                                    is_descending: matches!(col.order, Some(ast::SortOrder::Desc)),
-                                }),
-                                _ => Err(LimboError::ParseError(
-                                    "expressions prohibited in PRIMARY KEY and UNIQUE constraints"
-                                        .to_string(),
-                                )),
-                            })
-                            .collect();
-
-                        for result in primary_key_column_results {
-                            if let Err(e) = result {
-                                bail_parse_error!("{}", e);
-                            }
-                            let pk_info = result?;
-
-                            let column_name = pk_info.name;
-                            let column_def = columns.get(&ast::Name(column_name.clone()));
-                            if column_def.is_none() {
-                                bail_parse_error!("No such column: {}", column_name);
-                            }
-
-                            if matches!(
-                                primary_key_definition,
-                                Some(PrimaryKeyDefinitionType::Simple { .. })
-                            ) {
-                                primary_key_definition = Some(PrimaryKeyDefinitionType::Composite);
-                                continue;
-                            }
-                            if primary_key_definition.is_none() {
-                                let column_def = column_def.unwrap();
-                                let typename =
-                                    column_def.col_type.as_ref().map(|t| t.name.as_str());
-                                let is_descending = pk_info.is_descending;
-                                primary_key_definition = Some(PrimaryKeyDefinitionType::Simple {
-                                    typename,
-                                    is_descending,
-                                });
-                            }
-                        }
+            let mut has_primary_key = false;
+
+            // Check table constraints for PRIMARY KEY and UNIQUE
+            for constraint in constraints.iter().flatten() {
+                let (key_columns, is_primary_key) = match &constraint.constraint {
+                    ast::TableConstraint::PrimaryKey { columns, .. } => (columns, true),
+                    ast::TableConstraint::Unique { columns, .. } => (columns, false),
+                    _ => continue,
+                };
+                for key_column in key_columns {
+                    let ast::Expr::Id(name) = &key_column.expr else {
+                        bail_parse_error!(
+                            "expressions prohibited in PRIMARY KEY and UNIQUE constraints"
+                        );
+                    };
+                    if columns.get(&ast::Name(name.0.clone())).is_none() {
+                        bail_parse_error!("No such column: {}", name.0);
+                    }
+                }
+                if is_primary_key {
+                    if has_primary_key {
+                        bail_parse_error!("table {} has more than one primary key", tbl_name);
                     }
+                    has_primary_key = true;
                 }
             }
 
@@ -317,46 +305,18 @@ fn check_automatic_pk_index_required(
                         constraint.constraint,
                         ast::ColumnConstraint::PrimaryKey { .. }
                     ) {
-                        if primary_key_definition.is_some() {
+                        if has_primary_key {
                             bail_parse_error!("table {} has more than one primary key", tbl_name);
                         }
-                        let typename = col_def.col_type.as_ref().map(|t| t.name.as_str());
-                        primary_key_definition = Some(PrimaryKeyDefinitionType::Simple {
-                            typename,
-                            is_descending: false,
-                        });
+                        has_primary_key = true;
                     }
                 }
             }
 
-            // Check if table has rowid
-            if options.contains(ast::TableOptions::WITHOUT_ROWID) {
-                bail_parse_error!("WITHOUT ROWID tables are not supported yet");
-            }
-
-            // Check if we need an automatic index
-            let needs_auto_index = if let Some(primary_key_definition) = &primary_key_definition {
-                match primary_key_definition {
-                    PrimaryKeyDefinitionType::Simple {
-                        typename,
-                        is_descending,
-                    } => {
-                        let is_integer =
-                            typename.is_some() && typename.unwrap().to_uppercase() == "INTEGER";
-                        !is_integer || *is_descending
-                    }
-                    PrimaryKeyDefinitionType::Composite => true,
-                }
-            } else {
-                false
-            };
-
-            if needs_auto_index {
-                let index_root_reg = program.alloc_register();
-                Ok(Some(index_root_reg))
-            } else {
-                Ok(None)
+            if options.contains(ast::TableOptions::WITHOUT_ROWID) && !has_primary_key {
+                bail_parse_error!("PRIMARY KEY missing on table {}", tbl_name);
             }
+            Ok(())
         }
         ast::CreateTableBody::AsSelect(_) => {
             bail_parse_error!("CREATE TABLE AS SELECT not supported yet")
@@ -364,14 +324,6 @@ fn check_automatic_pk_index_required(
     }
 }
 
-enum PrimaryKeyDefinitionType<'a> {
-    Simple {
-        typename: Option<&'a str>,
-        is_descending: bool,
-    },
-    Composite,
-}
-
 struct TableFormatter<'a> {
     body: &'a ast::CreateTableBody,
 }
@@ -381,6 +333,32 @@ impl Display for TableFormatter<'_> {
     }
 }
 
+/// The SQL that SQLite stores for the CREATE TABLE statement `input`, which is its text from the
+/// name of the table, without the name of its database, to its end.
+fn create_table_sql(input: &str) -> Option<String> {
+    let tokens = statement_tokens(input);
+    let mut name = tokens
+        .iter()
+        .position(|(token_type, _)| *token_type == TokenType::TK_TABLE)?
+        + 1;
+    if matches!(
+        tokens.get(name..name + 3),
+        Some([
+            (TokenType::TK_IF, _),
+            (TokenType::TK_NOT, _),
+            (TokenType::TK_EXISTS, _)
+        ])
+    ) {
+        name += 3;
+    }
+    if matches!(tokens.get(name + 1), Some((TokenType::TK_DOT, _))) {
+        name += 2;
+    }
+    let (_, start) = tokens.get(name)?;
+    let (_, end) = tokens.last()?;
+    Some(format!("CREATE TABLE {}", &input[start.start..end.end]))
+}
+
 fn create_table_body_to_str(tbl_name: &ast::QualifiedName, body: &ast::CreateTableBody) -> String {
     let mut sql = String::new();
     let formatter = TableFormatter { body };
@@ -499,6 +477,7 @@ pub fn translate_create_virtual_table(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: 1usize.into(),
+        db: schema.database,
     });
 
     let sql = create_vtable_body_to_str(&vtab);
@@ -514,7 +493,7 @@ pub fn translate_create_virtual_table(
 
     let parse_schema_where_clause = format!("tbl_name = '{}' AND type != 'trigger'", table_name);
     program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
+        db: schema.database,
         where_clause: parse_schema_where_clause,
     });
 
@@ -527,11 +506,518 @@ pub fn translate_create_virtual_table(
     Ok(program)
 }
 
+struct ViewFormatter<'a> {
+    view_name: &'a ast::QualifiedName,
+    columns: Option<&'a [ast::IndexedColumn]>,
+    select: &'a ast::Select,
+}
+
+impl Display for ViewFormatter<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CREATE VIEW {}", self.view_name.name.0)?;
+        if let Some(columns) = self.columns {
+            let names = columns
+                .iter()
+                .map(|column| column.col_name.0.as_str())
+                .collect::<Vec<_>>();
+            write!(f, "({})", names.join(", "))?;
+        }
+        write!(f, " AS ")?;
+        self.select.to_fmt(f)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn translate_create_view(
+    query_mode: QueryMode,
+    view_name: ast::QualifiedName,
+    temporary: bool,
+    if_not_exists: bool,
+    columns: Option<Vec<ast::IndexedColumn>>,
+    select: ast::Select,
+    schema: &Schema,
+) -> Result<ProgramBuilder> {
+    if temporary {
+        bail_parse_error!("TEMPORARY view not supported yet");
+    }
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 1,
+    });
+    if let Some(table) = schema.get_table(view_name.name.0.as_str()) {
+        if if_not_exists {
+            let init_label = program.emit_init();
+            let start_offset = program.offset();
+            program.emit_halt();
+            program.resolve_label(init_label, program.offset());
+            program.emit_transaction(true);
+            program.emit_constant_insns();
+            program.emit_goto(start_offset);
+
+            return Ok(program);
+        }
+        let kind = if matches!(table.as_ref(), Table::View(_)) {
+            "view"
+        } else {
+            "table"
+        };
+        bail_parse_error!("{} {} already exists", kind, view_name.name.0);
+    }
+
+    let sql = ViewFormatter {
+        view_name: &view_name,
+        columns: columns.as_deref(),
+        select: &select,
+    }
+    .to_string();
+    let view_name = &view_name.name.0;
+
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+
+    let table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id = program.alloc_cursor_id(
+        Some(SQLITE_TABLEID.to_owned()),
+        CursorType::BTreeTable(table.clone()),
+    );
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: 1usize.into(),
+        db: schema.database,
+    });
+    emit_schema_entry(
+        &mut program,
+        sqlite_schema_cursor_id,
+        SchemaEntryType::View,
+        view_name,
+        view_name,
+        0, // views dont have a root page
+        Some(sql),
+    );
+    program.emit_insn(Insn::ParseSchema {
+        db: schema.database,
+        where_clause: format!("name = '{}' AND type = 'view'", view_name),
+    });
+
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_transaction(true);
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+
+    Ok(program)
+}
+
+/// Formats the CREATE TRIGGER statement that is stored in the schema table.
+struct TriggerFormatter<'a>(&'a ast::Stmt);
+
+impl Display for TriggerFormatter<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.to_fmt(f)
+    }
+}
+
+pub fn translate_create_trigger(
+    query_mode: QueryMode,
+    create: ast::CreateTrigger,
+    schema: &Schema,
+) -> Result<ProgramBuilder> {
+    if create.temporary {
+        bail_parse_error!("TEMPORARY trigger not supported yet");
+    }
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 1,
+    });
+    let trigger_name = normalize_ident(&create.trigger_name.name.0);
+    if schema.get_trigger(&trigger_name).is_some() {
+        if create.if_not_exists {
+            let init_label = program.emit_init();
+            let start_offset = program.offset();
+            program.emit_halt();
+            program.resolve_label(init_label, program.offset());
+            program.emit_transaction(true);
+            program.emit_constant_insns();
+            program.emit_goto(start_offset);
+
+            return Ok(program);
+        }
+        bail_parse_error!("trigger {} already exists", trigger_name);
+    }
+    let table_name = normalize_ident(&create.tbl_name.name.0);
+    let Some(table) = schema.get_table(&table_name) else {
+        bail_parse_error!("no such table: {}", table_name);
+    };
+    if table_name.starts_with("sqlite_") {
+        bail_parse_error!("cannot create trigger on system table");
+    }
+    match (table.as_ref(), create.time) {
+        (Table::View(_), Some(ast::TriggerTime::InsteadOf)) => {}
+        (Table::View(view), time) => {
+            let time = match time {
+                Some(ast::TriggerTime::After) => "AFTER",
+                _ => "BEFORE",
+            };
+            bail_parse_error!("cannot create {} trigger on view: {}", time, view.name);
+        }
+        (Table::Virtual(_), _) => bail_parse_error!("cannot create triggers on virtual tables"),
+        (table, Some(ast::TriggerTime::InsteadOf)) => {
+            bail_parse_error!(
+                "cannot create INSTEAD OF trigger on table: {}",
+                table.get_name()
+            );
+        }
+        _ => {}
+    }
+
+    let stmt = ast::Stmt::CreateTrigger(Box::new(ast::CreateTrigger {
+        if_not_exists: false,
+        ..create
+    }));
+    let sql = TriggerFormatter(&stmt).to_string();
+
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+
+    let table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id = program.alloc_cursor_id(
+        Some(SQLITE_TABLEID.to_owned()),
+        CursorType::BTreeTable(table.clone()),
+    );
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: 1usize.into(),
+        db: schema.database,
+    });
+    emit_schema_entry(
+        &mut program,
+        sqlite_schema_cursor_id,
+        SchemaEntryType::Trigger,
+        &trigger_name,
+        &table_name,
+        0, // triggers dont have a root page
+        Some(sql),
+    );
+    program.emit_insn(Insn::ParseSchema {
+        db: schema.database,
+        where_clause: format!("name = '{}' AND type = 'trigger'", trigger_name),
+    });
+
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_transaction(true);
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+
+    Ok(program)
+}
+
+pub fn translate_drop_trigger(
+    query_mode: QueryMode,
+    trigger_name: ast::QualifiedName,
+    if_exists: bool,
+    schema: &Schema,
+) -> Result<ProgramBuilder> {
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 2,
+    });
+    let trigger_name = normalize_ident(&trigger_name.name.0);
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    if schema.get_trigger(&trigger_name).is_none() {
+        if !if_exists {
+            bail_parse_error!("no such trigger: {}", trigger_name);
+        }
+    } else {
+        let name_reg = program.alloc_register();
+        let trigger_name_reg = program.emit_string8_new_reg(trigger_name.clone());
+        program.mark_last_insn_constant();
+        let trigger_type_reg = program.emit_string8_new_reg("trigger".to_string());
+        program.mark_last_insn_constant();
+
+        let schema_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+        let sqlite_schema_cursor_id = program.alloc_cursor_id(
+            Some(SQLITE_TABLEID.to_owned()),
+            CursorType::BTreeTable(schema_table.clone()),
+        );
+        program.emit_insn(Insn::OpenWrite {
+            cursor_id: sqlite_schema_cursor_id,
+            root_page: 1usize.into(),
+            db: schema.database,
+        });
+
+        //  Remove the entry of the trigger from the schema table
+        let end_loop_label = program.allocate_label();
+        program.emit_insn(Insn::Rewind {
+            cursor_id: sqlite_schema_cursor_id,
+            pc_if_empty: end_loop_label,
+        });
+        let loop_start = program.offset();
+        let next_label = program.allocate_label();
+        program.emit_insn(Insn::Column {
+            cursor_id: sqlite_schema_cursor_id,
+            column: 1,
+            dest: name_reg,
+        });
+        program.emit_insn(Insn::Ne {
+            lhs: name_reg,
+            rhs: trigger_name_reg,
+            target_pc: next_label,
+            flags: CmpInsFlags::default(),
+        });
+        program.emit_insn(Insn::Column {
+            cursor_id: sqlite_schema_cursor_id,
+            column: 0,
+            dest: name_reg,
+        });
+        program.emit_insn(Insn::Ne {
+            lhs: name_reg,
+            rhs: trigger_type_reg,
+            target_pc: next_label,
+            flags: CmpInsFlags::default(),
+        });
+        program.emit_insn(Insn::Delete {
+            cursor_id: sqlite_schema_cursor_id,
+            count_change: false,
+        });
+        program.resolve_label(next_label, program.offset());
+        program.emit_insn(Insn::Next {
+            cursor_id: sqlite_schema_cursor_id,
+            pc_if_next: loop_start,
+        });
+        program.resolve_label(end_loop_label, program.offset());
+
+        program.emit_insn(Insn::DropTrigger {
+            db: schema.database,
+            trigger_name,
+        });
+    }
+
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_transaction(true);
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+
+    Ok(program)
+}
+
+pub fn translate_drop_index(
+    query_mode: QueryMode,
+    idx_name: ast::QualifiedName,
+    if_exists: bool,
+    schema: &Schema,
+) -> Result<ProgramBuilder> {
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 2,
+    });
+    let idx_name = normalize_ident(&idx_name.name.0);
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    match schema.get_index(&idx_name) {
+        None if !if_exists => bail_parse_error!("no such index: {}", idx_name),
+        None => {}
+        Some(index) => {
+            if idx_name.starts_with("sqlite_autoindex_") {
+                bail_parse_error!(
+                    "index associated with UNIQUE or PRIMARY KEY constraint cannot be dropped"
+                );
+            }
+            let name_reg = program.alloc_register();
+            let index_name_reg = program.emit_string8_new_reg(idx_name.clone());
+            program.mark_last_insn_constant();
+            let index_type_reg = program.emit_string8_new_reg("index".to_string());
+            program.mark_last_insn_constant();
+
+            let schema_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+            let sqlite_schema_cursor_id = program.alloc_cursor_id(
+                Some(SQLITE_TABLEID.to_owned()),
+                CursorType::BTreeTable(schema_table.clone()),
+            );
+            program.emit_insn(Insn::OpenWrite {
+                cursor_id: sqlite_schema_cursor_id,
+                root_page: 1usize.into(),
+                db: schema.database,
+            });
+
+            //  Remove the entry of the index from the schema table
+            let end_loop_label = program.allocate_label();
+            program.emit_insn(Insn::Rewind {
+                cursor_id: sqlite_schema_cursor_id,
+                pc_if_empty: end_loop_label,
+            });
+            let loop_start = program.offset();
+            let next_label = program.allocate_label();
+            program.emit_insn(Insn::Column {
+                cursor_id: sqlite_schema_cursor_id,
+                column: 1,
+                dest: name_reg,
+            });
+            program.emit_insn(Insn::Ne {
+                lhs: name_reg,
+                rhs: index_name_reg,
+                target_pc: next_label,
+                flags: CmpInsFlags::default(),
+            });
+            program.emit_insn(Insn::Column {
+                cursor_id: sqlite_schema_cursor_id,
+                column: 0,
+                dest: name_reg,
+            });
+            program.emit_insn(Insn::Ne {
+                lhs: name_reg,
+                rhs: index_type_reg,
+                target_pc: next_label,
+                flags: CmpInsFlags::default(),
+            });
+            program.emit_insn(Insn::Delete {
+                cursor_id: sqlite_schema_cursor_id,
+                count_change: false,
+            });
+            program.resolve_label(next_label, program.offset());
+            program.emit_insn(Insn::Next {
+                cursor_id: sqlite_schema_cursor_id,
+                pc_if_next: loop_start,
+            });
+            program.resolve_label(end_loop_label, program.offset());
+
+            emit_clear_stat1(&mut program, schema, &index.table_name, Some(&index.name));
+
+            //  Free the pages of the index
+            emit_destroy(
+                &mut program,
+                schema.database,
+                index.root_page,
+                sqlite_schema_cursor_id,
+            );
+            program.emit_insn(Insn::DropIndex {
+                db: schema.database,
+                index_name: idx_name,
+            });
+        }
+    }
+
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_transaction(true);
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+
+    Ok(program)
+}
+
 pub fn translate_drop_table(
     query_mode: QueryMode,
     tbl_name: ast::QualifiedName,
     if_exists: bool,
     schema: &Schema,
+    syms: &SymbolTable,
+) -> Result<ProgramBuilder> {
+    translate_drop(query_mode, tbl_name, if_exists, schema, false, syms)
+}
+
+pub fn translate_drop_view(
+    query_mode: QueryMode,
+    view_name: ast::QualifiedName,
+    if_exists: bool,
+    schema: &Schema,
+    syms: &SymbolTable,
+) -> Result<ProgramBuilder> {
+    translate_drop(query_mode, view_name, if_exists, schema, true, syms)
+}
+
+/// Emits the Destroy of the b-tree at `root_page`. If the largest root page of an auto-vacuum
+/// database is moved into its place, the row of sqlite_schema with the moved page is updated.
+fn emit_destroy(
+    program: &mut ProgramBuilder,
+    db: usize,
+    root_page: usize,
+    sqlite_schema_cursor_id: usize,
+) {
+    let former_root_reg = program.alloc_register();
+    program.emit_insn(Insn::Destroy {
+        root: root_page,
+        former_root_reg,
+        db,
+    });
+    let done_label = program.allocate_label();
+    program.emit_insn(Insn::IfNot {
+        reg: former_root_reg,
+        target_pc: done_label,
+        jump_if_null: true,
+    });
+    program.emit_insn(Insn::Rewind {
+        cursor_id: sqlite_schema_cursor_id,
+        pc_if_empty: done_label,
+    });
+    let loop_start = program.offset();
+    let next_label = program.allocate_label();
+    let rowid_reg = program.alloc_register();
+    let columns_reg = program.alloc_registers(5);
+    program.emit_insn(Insn::Column {
+        cursor_id: sqlite_schema_cursor_id,
+        column: 3,
+        dest: columns_reg + 3,
+    });
+    program.emit_insn(Insn::Ne {
+        lhs: columns_reg + 3,
+        rhs: former_root_reg,
+        target_pc: next_label,
+        flags: CmpInsFlags::default(),
+    });
+    program.emit_insn(Insn::RowId {
+        cursor_id: sqlite_schema_cursor_id,
+        dest: rowid_reg,
+    });
+    for column in [0, 1, 2, 4] {
+        program.emit_insn(Insn::Column {
+            cursor_id: sqlite_schema_cursor_id,
+            column,
+            dest: columns_reg + column,
+        });
+    }
+    program.emit_insn(Insn::Integer {
+        value: root_page as i64,
+        dest: columns_reg + 3,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: columns_reg,
+        count: 5,
+        dest_reg: record_reg,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: sqlite_schema_cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: 0,
+    });
+    program.resolve_label(next_label, program.offset());
+    program.emit_insn(Insn::Next {
+        cursor_id: sqlite_schema_cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.resolve_label(done_label, program.offset());
+}
+
+/// Drops a table or a view. Dropping a view only removes its entry from the schema table.
+fn translate_drop(
+    query_mode: QueryMode,
+    tbl_name: ast::QualifiedName,
+    if_exists: bool,
+    schema: &Schema,
+    is_view: bool,
+    syms: &SymbolTable,
 ) -> Result<ProgramBuilder> {
     let mut program = ProgramBuilder::new(ProgramBuilderOpts {
         query_mode,
@@ -552,22 +1038,67 @@ pub fn translate_drop_table(
 
             return Ok(program);
         }
+        if is_view {
+            bail_parse_error!("No such view: {}", tbl_name.name.0.as_str());
+        }
         bail_parse_error!("No such table: {}", tbl_name.name.0.as_str());
     }
 
     let table = table.unwrap(); // safe since we just checked for None
+    match (table.as_ref(), is_view) {
+        (Table::View(view), false) => {
+            bail_parse_error!("use DROP VIEW to delete view {}", view.name)
+        }
+        (table, true) if !matches!(table, Table::View(_)) => {
+            bail_parse_error!("use DROP TABLE to delete table {}", table.get_name())
+        }
+        _ => {}
+    }
 
     let init_label = program.emit_init();
     let start_offset = program.offset();
 
+    // Like SQLite, with foreign keys on, a table that other tables refer to is emptied first,
+    // which runs the ON DELETE actions but not the triggers of the table. The table is not
+    // dropped if that violates an immediate constraint.
+    if let Some(btree) = table.btree() {
+        if syms.foreign_keys.get() && !schema.get_referencing_foreign_keys(&btree.name).is_empty() {
+            let mut delete_schema = schema.clone();
+            delete_schema.remove_triggers_for_table(&btree.name);
+            // The schema is the one of the database of the table, so the name is unqualified
+            let delete = translate_delete(
+                QueryMode::Normal,
+                &delete_schema,
+                &ast::QualifiedName::single(tbl_name.name.clone()),
+                None,
+                None,
+                &None,
+                syms,
+            )?;
+            let subprogram = program.add_subprogram(delete);
+            let deleted_label = program.allocate_label();
+            program.emit_insn(Insn::Program {
+                subprogram: Subprogram::Index(subprogram),
+                params_start: 0,
+                ignore_target: deleted_label,
+            });
+            program.resolve_label(deleted_label, program.offset());
+            let drop_label = program.allocate_label();
+            program.emit_insn(Insn::FkIfZero {
+                deferred: false,
+                target_pc: drop_label,
+            });
+            // Halt fails the statement while there are violations.
+            program.emit_halt();
+            program.resolve_label(drop_label, program.offset());
+        }
+    }
+
     let null_reg = program.alloc_register(); //  r1
     program.emit_null(null_reg, None);
     let tbl_name_reg = program.alloc_register(); //  r2
     let table_reg = program.emit_string8_new_reg(tbl_name.name.0.clone()); //  r3
     program.mark_last_insn_constant();
-    let table_type = program.emit_string8_new_reg("trigger".to_string()); //  r4
-    program.mark_last_insn_constant();
-    let row_id_reg = program.alloc_register(); //  r5
 
     let table_name = "sqlite_schema";
     let schema_table = schema.get_btree_table(table_name).unwrap();
@@ -578,9 +1109,11 @@ pub fn translate_drop_table(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: 1usize.into(),
+        db: schema.database,
     });
 
-    //  1. Remove all entries from the schema table related to the table we are dropping, except for triggers
+    //  1. Remove all entries from the schema table related to the table we are dropping,
+    //  including its indexes and triggers
     //  loop to beginning of schema table
     let end_metadata_label = program.allocate_label();
     program.emit_insn(Insn::Rewind {
@@ -603,23 +1136,9 @@ pub fn translate_drop_table(
         target_pc: next_label,
         flags: CmpInsFlags::default(),
     });
-    program.emit_insn(Insn::Column {
-        cursor_id: sqlite_schema_cursor_id,
-        column: 0,
-        dest: tbl_name_reg,
-    });
-    program.emit_insn(Insn::Eq {
-        lhs: tbl_name_reg,
-        rhs: table_type,
-        target_pc: next_label,
-        flags: CmpInsFlags::default(),
-    });
-    program.emit_insn(Insn::RowId {
-        cursor_id: sqlite_schema_cursor_id,
-        dest: row_id_reg,
-    });
     program.emit_insn(Insn::Delete {
         cursor_id: sqlite_schema_cursor_id,
+        count_change: false,
     });
 
     program.resolve_label(next_label, program.offset());
@@ -630,14 +1149,34 @@ pub fn translate_drop_table(
     program.resolve_label(end_metadata_label, program.offset());
     //  end of loop on schema table
 
-    //  2. Destroy the indices within a loop
-    let indices = schema.get_indices(&tbl_name.name.0);
-    for index in indices {
-        program.emit_insn(Insn::Destroy {
-            root: index.root_page,
-            former_root_reg: 0, //  no autovacuum (https://www.sqlite.org/opcode.html#Destroy)
-            is_temp: 0,
-        });
+    if let Some(btree) = table.btree() {
+        emit_clear_stat1(&mut program, schema, &btree.name, None);
+    }
+
+    //  2. Destroy the indices and the table, except the PRIMARY KEY index of a WITHOUT ROWID
+    //  table, which is the table itself. The largest root page goes first: in an auto-vacuum
+    //  database, destroying a b-tree moves the largest root page into its place.
+    let btree = table.btree();
+    let indices = schema.get_indices(&tbl_name.name.0).iter().filter(|index| {
+        !btree
+            .as_ref()
+            .is_some_and(|t| t.is_primary_key_index(index))
+    });
+    let mut root_pages = btree
+        .iter()
+        .map(|table| table.root_page)
+        .chain(indices.clone().map(|index| index.root_page))
+        .collect::<Vec<_>>();
+    root_pages.sort_unstable_by(|a, b| b.cmp(a));
+    for root_page in root_pages {
+        emit_destroy(
+            &mut program,
+            schema.database,
+            root_page,
+            sqlite_schema_cursor_id,
+        );
+    }
+    for _ in indices {
         let null_reg_1 = program.alloc_register();
         let null_reg_2 = program.alloc_register();
         program.emit_null(null_reg_1, Some(null_reg_2));
@@ -651,13 +1190,7 @@ pub fn translate_drop_table(
 
     //  3. Destroy the table structure
     match table.as_ref() {
-        Table::BTree(table) => {
-            program.emit_insn(Insn::Destroy {
-                root: table.root_page,
-                former_root_reg: 0, //  no autovacuum (https://www.sqlite.org/opcode.html#Destroy)
-                is_temp: 0,
-            });
-        }
+        Table::BTree(_) => {}
         Table::Virtual(vtab) => {
             // From what I see, TableValuedFunction is not stored in the schema as a table.
             // But this line here below is a safeguard in case this behavior changes in the future
@@ -670,10 +1203,12 @@ pub fn translate_drop_table(
             }
             program.emit_insn(Insn::VDestroy {
                 table_name: vtab.name.clone(),
-                db: 0, // TODO change this for multiple databases
+                db: schema.database,
             });
         }
         Table::Pseudo(..) => unimplemented!(),
+        // A view has no storage of its own.
+        Table::View(_) => {}
     };
 
     let r6 = program.alloc_register();
@@ -688,7 +1223,7 @@ pub fn translate_drop_table(
 
     //  Drop the in-memory structures for the table
     program.emit_insn(Insn::DropTable {
-        db: 0,
+        db: schema.database,
         _p2: 0,
         _p3: 0,
         table_name: tbl_name.name.0,
@@ -1,15 +1,20 @@
 use super::emitter::emit_program;
-use super::plan::{select_star, Operation, Search, SelectQueryType};
+use super::plan::{
+    select_star, ExprSubqueryKind, Operation, Search, SelectQueryType, TableReference,
+};
 use super::planner::Scope;
+use super::window;
 use crate::function::{AggFunc, ExtFunc, Func};
 use crate::translate::optimizer::optimize_plan;
 use crate::translate::plan::{Aggregate, Direction, GroupBy, Plan, ResultSetColumn, SelectPlan};
 use crate::translate::planner::{
-    bind_column_references, break_predicate_at_and_boundaries, parse_from, parse_limit,
-    parse_where, resolve_aggregates,
+    aggregate_distinct, aggregate_filter, bind_column_references,
+    break_predicate_at_and_boundaries, defer_conditions_past_right_joins, parse_from, parse_limit,
+    parse_where, plan_subqueries, resolve_aggregates,
 };
 use crate::util::normalize_ident;
 use crate::vdbe::builder::{ProgramBuilderOpts, QueryMode};
+use crate::vdbe::BranchOffset;
 use crate::SymbolTable;
 use crate::{schema::Schema, vdbe::builder::ProgramBuilder, Result};
 use limbo_sqlite3_parser::ast::{self};
@@ -43,27 +48,141 @@ pub fn prepare_select_plan<'a>(
     syms: &SymbolTable,
     outer_scope: Option<&'a Scope<'a>>,
 ) -> Result<Plan> {
-    match *select.body.select {
+    let ast::Select {
+        with,
+        body,
+        order_by,
+        limit,
+    } = select;
+    let compounds = body.compounds.unwrap_or_default();
+    if compounds.is_empty() {
+        let plan = prepare_one_select_plan(
+            schema,
+            *body.select,
+            with,
+            order_by,
+            limit,
+            syms,
+            outer_scope,
+        )?;
+        return Ok(Plan::Select(plan));
+    }
+
+    // Every member of a compound SELECT can refer to the CTEs of the WITH clause.
+    let mut plan = prepare_one_select_plan(
+        schema,
+        *body.select,
+        with.clone(),
+        None,
+        None,
+        syms,
+        outer_scope,
+    )?;
+    for ast::CompoundSelect { operator, select } in compounds {
+        let member =
+            prepare_one_select_plan(schema, *select, with.clone(), None, None, syms, outer_scope)?;
+        if member.result_columns.len() != plan.result_columns.len() {
+            crate::bail_parse_error!(
+                "SELECTs to the left and right of {} do not have the same number of result columns",
+                operator
+            );
+        }
+        plan.compounds.push((operator, member));
+    }
+
+    if order_by.is_none() && limit.is_none() {
+        return Ok(Plan::Select(plan));
+    }
+
+    // ORDER BY and LIMIT apply to the result of the whole compound SELECT, so we turn the compound
+    // into a subquery and sort/limit the rows it yields, i.e.
+    // SELECT a FROM t1 UNION SELECT b FROM t2 ORDER BY 1 LIMIT 5
+    // becomes
+    // SELECT * FROM (SELECT a FROM t1 UNION SELECT b FROM t2) ORDER BY 1 LIMIT 5
+    let result_columns = plan
+        .result_columns
+        .iter()
+        .enumerate()
+        .map(|(i, rc)| ResultSetColumn {
+            expr: ast::Expr::Column {
+                database: None,
+                table: 0,
+                column: i,
+                is_rowid_alias: false,
+            },
+            alias: Some(
+                rc.name(&plan.table_references)
+                    .map(String::from)
+                    .unwrap_or_else(|| rc.expr.to_string()),
+            ),
+            contains_aggregates: false,
+        })
+        .collect::<Vec<_>>();
+    plan.query_type = SelectQueryType::Subquery {
+        yield_reg: usize::MAX, // will be set later in bytecode emission
+        coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
+    };
+    let mut table_reference =
+        TableReference::new_subquery("compound_subquery".to_string(), plan, None);
+    for i in 0..result_columns.len() {
+        table_reference.mark_column_used(i);
+    }
+    let mut plan = SelectPlan {
+        table_references: vec![table_reference],
+        result_columns,
+        distinct: false,
+        where_clause: vec![],
+        group_by: None,
+        order_by: None,
+        aggregates: vec![],
+        limit: None,
+        offset: None,
+        contains_constant_false_condition: false,
+        query_type: SelectQueryType::TopLevel,
+        compounds: vec![],
+        windows: vec![],
+        recursive: None,
+        expr_subqueries: vec![],
+    };
+    parse_order_by_and_limit(&mut plan, order_by, limit)?;
+    Ok(Plan::Select(plan))
+}
+
+/// Prepares the plan for a single SELECT, i.e. one that is not a compound SELECT.
+fn prepare_one_select_plan<'a>(
+    schema: &Schema,
+    select: ast::OneSelect,
+    with: Option<ast::With>,
+    mut order_by: Option<Vec<ast::SortedColumn>>,
+    limit: Option<Box<ast::Limit>>,
+    syms: &SymbolTable,
+    outer_scope: Option<&'a Scope<'a>>,
+) -> Result<SelectPlan> {
+    match select {
         ast::OneSelect::Select(select_inner) => {
             let SelectInner {
+                distinctness,
                 mut columns,
                 from,
                 where_clause,
                 group_by,
-                ..
+                window_clause,
             } = *select_inner;
             let col_count = columns.len();
             if col_count == 0 {
                 crate::bail_parse_error!("SELECT without columns is not allowed");
             }
 
-            let mut where_predicates = vec![];
+            window::resolve_named_windows(&mut columns, order_by.as_mut(), window_clause)?;
 
-            let with = select.with;
+            let mut where_predicates = vec![];
 
             // Parse the FROM clause into a vec of TableReferences. Fold all the join conditions expressions into the WHERE clause.
-            let table_references =
+            let (table_references, ctes) =
                 parse_from(schema, from, syms, with, &mut where_predicates, outer_scope)?;
+            // The subqueries in the expressions of this query can refer to its tables and CTEs.
+            let subquery_scope =
+                Scope::for_expr_subqueries(table_references.clone(), ctes, outer_scope);
 
             // Preallocate space for the result columns
             let result_columns = Vec::with_capacity(
@@ -89,6 +208,7 @@ pub fn prepare_select_plan<'a>(
             let mut plan = SelectPlan {
                 table_references,
                 result_columns,
+                distinct: matches!(distinctness, Some(ast::Distinctness::Distinct)),
                 where_clause: where_predicates,
                 group_by: None,
                 order_by: None,
@@ -97,6 +217,10 @@ pub fn prepare_select_plan<'a>(
                 offset: None,
                 contains_constant_false_condition: false,
                 query_type: SelectQueryType::TopLevel,
+                compounds: vec![],
+                windows: vec![],
+                recursive: None,
+                expr_subqueries: vec![],
             };
 
             let mut aggregate_expressions = Vec::new();
@@ -142,19 +266,30 @@ pub fn prepare_select_plan<'a>(
                         }
                     }
                     ResultColumn::Expr(ref mut expr, maybe_alias) => {
+                        window::check_window_function_usage(expr)?;
+                        plan_subqueries(
+                            expr,
+                            schema,
+                            syms,
+                            &subquery_scope,
+                            Some(&plan.result_columns),
+                            &mut plan.expr_subqueries,
+                        )?;
                         bind_column_references(
                             expr,
                             &mut plan.table_references,
                             Some(&plan.result_columns),
                         )?;
+                        // window function calls are evaluated after the aggregates, see [window::plan_windows]
+                        let is_window_function_call = window::is_window_function_call(expr);
                         match expr {
                             ast::Expr::FunctionCall {
                                 name,
-                                distinctness: _,
+                                distinctness,
                                 args,
-                                filter_over: _,
+                                filter_over,
                                 order_by: _,
-                            } => {
+                            } if !is_window_function_call => {
                                 let args_count = if let Some(args) = &args {
                                     args.len()
                                 } else {
@@ -182,6 +317,8 @@ pub fn prepare_select_plan<'a>(
                                         let agg = Aggregate {
                                             func: f,
                                             args: agg_args.clone(),
+                                            filter: aggregate_filter(filter_over),
+                                            distinct: aggregate_distinct(distinctness),
                                             original_expr: expr.clone(),
                                         };
                                         aggregate_expressions.push(agg.clone());
@@ -230,6 +367,8 @@ pub fn prepare_select_plan<'a>(
                                                 let agg = Aggregate {
                                                     func: AggFunc::External(f.func.clone().into()),
                                                     args: args.as_ref().unwrap().clone(),
+                                                    filter: aggregate_filter(filter_over),
+                                                    distinct: aggregate_distinct(distinctness),
                                                     original_expr: expr.clone(),
                                                 };
                                                 aggregate_expressions.push(agg.clone());
@@ -253,10 +392,9 @@ pub fn prepare_select_plan<'a>(
                                     }
                                 }
                             }
-                            ast::Expr::FunctionCallStar {
-                                name,
-                                filter_over: _,
-                            } => {
+                            ast::Expr::FunctionCallStar { name, filter_over }
+                                if !is_window_function_call =>
+                            {
                                 if let Ok(Func::Agg(f)) = Func::resolve_function(
                                     normalize_ident(name.0.as_str()).as_str(),
                                     0,
@@ -266,6 +404,8 @@ pub fn prepare_select_plan<'a>(
                                         args: vec![ast::Expr::Literal(ast::Literal::Numeric(
                                             "1".to_string(),
                                         ))],
+                                        filter: aggregate_filter(filter_over),
+                                        distinct: false,
                                         original_expr: expr.clone(),
                                     };
                                     aggregate_expressions.push(agg.clone());
@@ -301,6 +441,29 @@ pub fn prepare_select_plan<'a>(
                 }
             }
 
+            // Window functions are evaluated after WHERE, GROUP BY and HAVING
+            for expr in where_clause.iter().chain(
+                group_by
+                    .iter()
+                    .flat_map(|g| g.exprs.iter().chain(g.having.as_deref())),
+            ) {
+                window::check_no_window_functions(expr)?;
+            }
+            for o in order_by.iter().flatten() {
+                window::check_window_function_usage(&o.expr)?;
+            }
+
+            let mut where_clause = where_clause;
+            if let Some(where_expr) = where_clause.as_mut() {
+                plan_subqueries(
+                    where_expr,
+                    schema,
+                    syms,
+                    &subquery_scope,
+                    Some(&plan.result_columns),
+                    &mut plan.expr_subqueries,
+                )?;
+            }
             // Parse the actual WHERE clause and add its conditions to the plan WHERE clause that already contains the join conditions.
             parse_where(
                 where_clause,
@@ -308,10 +471,19 @@ pub fn prepare_select_plan<'a>(
                 Some(&plan.result_columns),
                 &mut plan.where_clause,
             )?;
+            defer_conditions_past_right_joins(&plan.table_references, &mut plan.where_clause);
 
             if let Some(mut group_by) = group_by {
                 for expr in group_by.exprs.iter_mut() {
                     replace_column_number_with_copy_of_column_expr(expr, &plan.result_columns)?;
+                    plan_subqueries(
+                        expr,
+                        schema,
+                        syms,
+                        &subquery_scope,
+                        Some(&plan.result_columns),
+                        &mut plan.expr_subqueries,
+                    )?;
                     bind_column_references(
                         expr,
                         &mut plan.table_references,
@@ -325,6 +497,14 @@ pub fn prepare_select_plan<'a>(
                         let mut predicates = vec![];
                         break_predicate_at_and_boundaries(*having, &mut predicates);
                         for expr in predicates.iter_mut() {
+                            plan_subqueries(
+                                expr,
+                                schema,
+                                syms,
+                                &subquery_scope,
+                                Some(&plan.result_columns),
+                                &mut plan.expr_subqueries,
+                            )?;
                             bind_column_references(
                                 expr,
                                 &mut plan.table_references,
@@ -351,45 +531,79 @@ pub fn prepare_select_plan<'a>(
 
             plan.aggregates = aggregate_expressions;
 
-            // Parse the ORDER BY clause
-            if let Some(order_by) = select.order_by {
-                let mut key = Vec::new();
-
-                for mut o in order_by {
-                    replace_column_number_with_copy_of_column_expr(
-                        &mut o.expr,
-                        &plan.result_columns,
-                    )?;
-
-                    bind_column_references(
-                        &mut o.expr,
-                        &mut plan.table_references,
-                        Some(&plan.result_columns),
-                    )?;
-                    resolve_aggregates(&o.expr, &mut plan.aggregates);
-
-                    key.push((
-                        o.expr,
-                        o.order.map_or(Direction::Ascending, |o| match o {
-                            ast::SortOrder::Asc => Direction::Ascending,
-                            ast::SortOrder::Desc => Direction::Descending,
-                        }),
-                    ));
-                }
-                plan.order_by = Some(key);
+            for o in order_by.iter_mut().flatten() {
+                plan_subqueries(
+                    &mut o.expr,
+                    schema,
+                    syms,
+                    &subquery_scope,
+                    Some(&plan.result_columns),
+                    &mut plan.expr_subqueries,
+                )?;
+            }
+            parse_order_by_and_limit(&mut plan, order_by, limit)?;
+
+            if plan
+                .aggregates
+                .iter()
+                .any(|agg| agg.distinct && agg.args.len() != 1)
+            {
+                crate::bail_parse_error!("DISTINCT aggregates must have exactly one argument");
             }
 
-            // Parse the LIMIT/OFFSET clause
-            (plan.limit, plan.offset) =
-                select.limit.map_or(Ok((None, None)), |l| parse_limit(&l))?;
+            if plan
+                .result_columns
+                .iter()
+                .map(|rc| &rc.expr)
+                .chain(plan.order_by.iter().flatten().map(|(expr, _)| expr))
+                .any(window::contains_window_function)
+            {
+                plan = window::plan_windows(plan, syms)?;
+            }
 
             // Return the unoptimized query plan
-            Ok(Plan::Select(plan))
+            Ok(plan)
         }
         _ => todo!(),
     }
 }
 
+/// Parses the ORDER BY and LIMIT/OFFSET clauses of a SELECT into the plan.
+fn parse_order_by_and_limit(
+    plan: &mut SelectPlan,
+    order_by: Option<Vec<ast::SortedColumn>>,
+    limit: Option<Box<ast::Limit>>,
+) -> Result<()> {
+    // Parse the ORDER BY clause
+    if let Some(order_by) = order_by {
+        let mut key = Vec::new();
+
+        for mut o in order_by {
+            replace_column_number_with_copy_of_column_expr(&mut o.expr, &plan.result_columns)?;
+
+            bind_column_references(
+                &mut o.expr,
+                &mut plan.table_references,
+                Some(&plan.result_columns),
+            )?;
+            resolve_aggregates(&o.expr, &mut plan.aggregates);
+
+            key.push((
+                o.expr,
+                o.order.map_or(Direction::Ascending, |o| match o {
+                    ast::SortOrder::Asc => Direction::Ascending,
+                    ast::SortOrder::Desc => Direction::Descending,
+                }),
+            ));
+        }
+        plan.order_by = Some(key);
+    }
+
+    // Parse the LIMIT/OFFSET clause
+    (plan.limit, plan.offset) = limit.map_or(Ok((None, None)), |l| parse_limit(&l))?;
+    Ok(())
+}
+
 /// Replaces a column number in an ORDER BY or GROUP BY expression with a copy of the column expression.
 /// For example, in SELECT u.first_name, count(1) FROM users u GROUP BY 1 ORDER BY 2,
 /// the column number 1 is replaced with u.first_name and the column number 2 is replaced with count(1).
@@ -424,14 +638,50 @@ fn count_plan_required_cursors(plan: &SelectPlan) -> usize {
             Operation::Search(search) => match search {
                 Search::RowidEq { .. } => 1,
                 Search::Seek { index, .. } => 1 + index.is_some() as usize,
+                Search::Hash { .. } => 2,
             },
             Operation::Subquery { plan, .. } => count_plan_required_cursors(plan),
         })
         .sum();
     let num_sorter_cursors = plan.group_by.is_some() as usize + plan.order_by.is_some() as usize;
     let num_pseudo_cursors = plan.group_by.is_some() as usize + plan.order_by.is_some() as usize;
+    // a sorter, a pseudo cursor and an ephemeral index for the current partition
+    let num_window_cursors = plan.windows.len() * 3;
+    // INTERSECT needs two ephemeral indexes, the other compound operators need at most one
+    let num_compound_cursors: usize = plan
+        .compounds
+        .iter()
+        .map(|(_, member)| 2 + count_plan_required_cursors(member))
+        .sum();
+    // the queue of a recursive CTE and the ephemeral index that keeps its rows distinct
+    let num_recursive_cursors: usize = plan.recursive.as_ref().map_or(0, |recursive| {
+        2 + recursive
+            .members
+            .iter()
+            .map(count_plan_required_cursors)
+            .sum::<usize>()
+    });
+    // SELECT DISTINCT and each aggregate called with DISTINCT need an ephemeral index
+    let num_distinct_cursors =
+        plan.distinct as usize + plan.aggregates.iter().filter(|agg| agg.distinct).count();
+    // an IN subquery also needs an ephemeral index for its rows
+    let num_expr_subquery_cursors: usize = plan
+        .expr_subqueries
+        .iter()
+        .map(|subquery| {
+            (subquery.kind == ExprSubqueryKind::In) as usize
+                + count_plan_required_cursors(&subquery.plan)
+        })
+        .sum();
 
-    num_table_cursors + num_sorter_cursors + num_pseudo_cursors
+    num_table_cursors
+        + num_sorter_cursors
+        + num_pseudo_cursors
+        + num_compound_cursors
+        + num_recursive_cursors
+        + num_window_cursors
+        + num_distinct_cursors
+        + num_expr_subquery_cursors
 }
 
 fn estimate_num_instructions(select: &SelectPlan) -> usize {
@@ -448,12 +698,38 @@ fn estimate_num_instructions(select: &SelectPlan) -> usize {
     let group_by_instructions = select.group_by.is_some() as usize * 10;
     let order_by_instructions = select.order_by.is_some() as usize * 10;
     let condition_instructions = select.where_clause.len() * 3;
+    let window_instructions: usize = select
+        .windows
+        .iter()
+        .map(|w| 50 + w.functions.len() * 30)
+        .sum();
+    let compound_instructions: usize = select
+        .compounds
+        .iter()
+        .map(|(_, member)| 10 + estimate_num_instructions(member))
+        .sum();
+    let recursive_instructions: usize = select.recursive.as_ref().map_or(0, |recursive| {
+        30 + recursive
+            .members
+            .iter()
+            .map(|member| 20 + estimate_num_instructions(member))
+            .sum::<usize>()
+    });
+    let expr_subquery_instructions: usize = select
+        .expr_subqueries
+        .iter()
+        .map(|subquery| 20 + estimate_num_instructions(&subquery.plan))
+        .sum();
 
     let num_instructions = 20
         + table_instructions
         + group_by_instructions
         + order_by_instructions
-        + condition_instructions;
+        + condition_instructions
+        + compound_instructions
+        + recursive_instructions
+        + window_instructions
+        + expr_subquery_instructions;
 
     num_instructions
 }
@@ -475,9 +751,38 @@ fn estimate_num_labels(select: &SelectPlan) -> usize {
     let group_by_labels = select.group_by.is_some() as usize * 10;
     let order_by_labels = select.order_by.is_some() as usize * 10;
     let condition_labels = select.where_clause.len() * 2;
+    let window_labels: usize = select
+        .windows
+        .iter()
+        .map(|w| 10 + w.functions.len() * 10)
+        .sum();
+    let compound_labels: usize = select
+        .compounds
+        .iter()
+        .map(|(_, member)| 2 + estimate_num_labels(member))
+        .sum();
+    let recursive_labels: usize = select.recursive.as_ref().map_or(0, |recursive| {
+        5 + recursive
+            .members
+            .iter()
+            .map(|member| 2 + estimate_num_labels(member))
+            .sum::<usize>()
+    });
+    let expr_subquery_labels: usize = select
+        .expr_subqueries
+        .iter()
+        .map(|subquery| 8 + estimate_num_labels(&subquery.plan))
+        .sum();
 
-    let num_labels =
-        init_halt_labels + table_labels + group_by_labels + order_by_labels + condition_labels;
+    let num_labels = init_halt_labels
+        + table_labels
+        + group_by_labels
+        + order_by_labels
+        + condition_labels
+        + compound_labels
+        + recursive_labels
+        + window_labels
+        + expr_subquery_labels;
 
     num_labels
 }
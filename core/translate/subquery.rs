@@ -1,12 +1,24 @@
+use std::sync::Arc;
+
+use limbo_sqlite3_parser::ast::SortOrder;
+
 use crate::{
-    vdbe::{builder::ProgramBuilder, insn::Insn},
+    schema::{Affinity, Index, IndexColumn},
+    vdbe::{
+        builder::{CursorType, ProgramBuilder},
+        insn::Insn,
+        BranchOffset, CursorID,
+    },
     Result,
 };
 
 use super::{
-    emitter::{emit_query, Resolver, TranslateCtx},
+    distinct::DistinctMetadata,
+    emitter::{emit_query, TranslateCtx},
     main_loop::LoopLabels,
-    plan::{Operation, SelectPlan, SelectQueryType, TableReference},
+    plan::{
+        ExprSubquery, ExprSubqueryKind, Operation, SelectPlan, SelectQueryType, TableReference,
+    },
 };
 
 /// Emit the subqueries contained in the FROM clause.
@@ -22,6 +34,10 @@ pub fn emit_subqueries(
             result_columns_start_reg,
         } = &mut table.op
         {
+            // The row of a recursive CTE is provided by the CTE itself, see emit_recursive_cte().
+            if matches!(plan.query_type, SelectQueryType::RecursiveRow) {
+                continue;
+            }
             // Emit the subquery and get the start register of the result columns.
             let result_columns_start = emit_subquery(program, plan, t_ctx)?;
             // Set the start register of the subquery's result columns.
@@ -73,7 +89,10 @@ pub fn emit_subquery<'a>(
         label_main_loop_end: None,
         meta_group_by: None,
         meta_left_joins: (0..plan.table_references.len()).map(|_| None).collect(),
+        meta_right_joins: (0..plan.table_references.len()).map(|_| None).collect(),
         meta_sort: None,
+        meta_windows: vec![],
+        meta_distinct: DistinctMetadata::default(),
         reg_agg_start: None,
         reg_nonagg_emit_once_flag: None,
         reg_result_cols_start: None,
@@ -82,7 +101,7 @@ pub fn emit_subquery<'a>(
         reg_limit: plan.limit.map(|_| program.alloc_register()),
         reg_offset: plan.offset.map(|_| program.alloc_register()),
         reg_limit_offset_sum: plan.offset.map(|_| program.alloc_register()),
-        resolver: Resolver::new(t_ctx.resolver.symbol_table),
+        resolver: t_ctx.resolver.nested(),
         omit_predicates: Vec::new(),
     };
     let subquery_body_end_label = program.allocate_label();
@@ -106,3 +125,170 @@ pub fn emit_subquery<'a>(
     program.resolve_label(subquery_body_end_label, program.offset());
     Ok(result_column_start_reg)
 }
+
+/// Where to find the subroutine of a subquery in an expression, and its result.
+#[derive(Debug)]
+pub struct ExprSubqueryMetadata {
+    /// The first instruction of the subroutine, called with Gosub.
+    pub label_start: BranchOffset,
+    /// The register that holds the return address of the subroutine.
+    pub reg_return: usize,
+    /// The first of the registers that the caller fills with the values of the outer queries that
+    /// the subquery refers to, before calling the subroutine.
+    pub reg_outer_refs_start: usize,
+    /// The register that holds the result of a scalar or EXISTS subquery.
+    pub reg_result: Option<usize>,
+    /// The ephemeral index that holds the rows of an IN subquery.
+    pub cursor_id: Option<CursorID>,
+    /// The affinity applied to the value searched in the rows of an IN subquery.
+    pub affinity: Affinity,
+}
+
+/// Emit the subqueries contained in the expressions of a query, see [ExprSubquery].
+///
+/// Each subquery is a subroutine that runs the whole subquery and leaves its result in a register
+/// (scalar and EXISTS subqueries) or in an ephemeral index (IN subqueries). The subroutines are
+/// skipped over here; translate_expr() calls them with Gosub whenever the value is needed, after
+/// storing the values of the outer queries that the subquery refers to.
+///
+/// A subroutine resets its own state every time it runs: the result register or the index, and
+/// the LIMIT and OFFSET counters, which would otherwise only be initialized once.
+/// A scalar or EXISTS subquery stops as soon as it produces a row.
+pub fn emit_expr_subqueries(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    subqueries: &mut [ExprSubquery],
+) -> Result<()> {
+    if subqueries.is_empty() {
+        return Ok(());
+    }
+    let label_skip = program.allocate_label();
+    program.emit_insn(Insn::Goto {
+        target_pc: label_skip,
+    });
+    for subquery in subqueries.iter_mut() {
+        let reg_return = program.alloc_register();
+        let reg_outer_refs_start = program.alloc_registers(subquery.num_outer_refs);
+        let label_start = program.allocate_label();
+        let label_done = program.allocate_label();
+        program.resolve_label(label_start, program.offset());
+
+        let plan = &mut subquery.plan;
+        let (reg_result, cursor_id) = match subquery.kind {
+            ExprSubqueryKind::Scalar => {
+                let dest_reg = program.alloc_register();
+                program.emit_insn(Insn::Null {
+                    dest: dest_reg,
+                    dest_end: None,
+                });
+                plan.query_type = SelectQueryType::Scalar {
+                    dest_reg,
+                    label_done,
+                };
+                (Some(dest_reg), None)
+            }
+            ExprSubqueryKind::Exists => {
+                let dest_reg = program.alloc_register();
+                program.emit_insn(Insn::Integer {
+                    value: 0,
+                    dest: dest_reg,
+                });
+                plan.query_type = SelectQueryType::Exists {
+                    dest_reg,
+                    label_done,
+                };
+                (Some(dest_reg), None)
+            }
+            ExprSubqueryKind::In => {
+                let cursor_id = emit_open_in_index(program, subquery.collation.clone());
+                plan.query_type = SelectQueryType::IndexInsert {
+                    cursor_id,
+                    affinity: Some(subquery.affinity),
+                };
+                (None, Some(cursor_id))
+            }
+        };
+
+        if plan.limit != Some(0) {
+            let mut metadata = TranslateCtx {
+                labels_main_loop: (0..plan.table_references.len())
+                    .map(|_| LoopLabels::new(program))
+                    .collect(),
+                label_main_loop_end: None,
+                meta_group_by: None,
+                meta_left_joins: (0..plan.table_references.len()).map(|_| None).collect(),
+                meta_right_joins: (0..plan.table_references.len()).map(|_| None).collect(),
+                meta_sort: None,
+                meta_windows: vec![],
+                meta_distinct: DistinctMetadata::default(),
+                reg_agg_start: None,
+                reg_nonagg_emit_once_flag: None,
+                reg_result_cols_start: None,
+                result_column_indexes_in_orderby_sorter: (0..plan.result_columns.len()).collect(),
+                result_columns_to_skip_in_orderby_sorter: None,
+                reg_limit: plan.limit.map(|_| program.alloc_register()),
+                reg_offset: plan.offset.map(|_| program.alloc_register()),
+                reg_limit_offset_sum: plan.offset.map(|_| program.alloc_register()),
+                resolver: t_ctx.resolver.nested(),
+                omit_predicates: Vec::new(),
+            };
+            metadata.resolver.reg_outer_refs_start = Some(reg_outer_refs_start);
+            if let Some(limit) = plan.limit {
+                program.emit_insn(Insn::Integer {
+                    value: limit as i64,
+                    dest: metadata.reg_limit.unwrap(),
+                });
+            }
+            if let Some(offset) = plan.offset {
+                program.emit_insn(Insn::Integer {
+                    value: offset as i64,
+                    dest: metadata.reg_offset.unwrap(),
+                });
+            }
+            emit_query(program, plan, &mut metadata)?;
+        }
+        program.resolve_label(label_done, program.offset());
+        program.emit_insn(Insn::Return {
+            return_reg: reg_return,
+        });
+
+        t_ctx.resolver.expr_subqueries.push(ExprSubqueryMetadata {
+            label_start,
+            reg_return,
+            reg_outer_refs_start,
+            reg_result,
+            cursor_id,
+            affinity: subquery.affinity,
+        });
+    }
+    program.resolve_label(label_skip, program.offset());
+    Ok(())
+}
+
+/// Open the ephemeral index that holds the rows of an IN subquery, ordered by the collating
+/// sequence used to compare them with the value searched in them.
+/// Opening the index again empties it, so it is reset whenever the subquery runs again.
+fn emit_open_in_index(program: &mut ProgramBuilder, collation: Option<String>) -> CursorID {
+    let index = Arc::new(Index {
+        name: "ephemeral_in".to_string(),
+        table_name: String::new(),
+        root_page: 0,
+        columns: vec![IndexColumn {
+            name: "value".to_string(),
+            order: SortOrder::Asc,
+            pos_in_table: 0,
+            collation,
+            expr: None,
+        }],
+        unique: false,
+        ephemeral: true,
+        where_clause: None,
+        key_suffix: Vec::new(),
+    });
+    let cursor_id = program.alloc_cursor_id(None, CursorType::BTreeIndex(index));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id,
+        is_table: false,
+    });
+    cursor_id
+}
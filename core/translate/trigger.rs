@@ -0,0 +1,906 @@
+//! Triggers.
+//!
+//! Like in SQLite, the body of a trigger is compiled into a sub-program of the statement that
+//! fires it, which runs it with [Insn::Program] once for each row that it changes. Each command of
+//! the body is in turn compiled into a sub-program of the trigger program, by the same code that
+//! translates the corresponding top-level statement.
+//!
+//! The `OLD` and `NEW` rows are passed to the trigger program in a block of registers of the
+//! calling program: the rowid and the columns of the `OLD` row, followed by the rowid and the
+//! columns of the `NEW` row. The references to them in the body of the trigger are rewritten as
+//! [ast::Expr::TriggerParam]s, which read that block with [Insn::Param].
+//!
+//! The ON DELETE and ON UPDATE actions of the foreign keys that refer to a table are compiled in
+//! the same way, from AFTER triggers on the parent table built by [fk_action_trigger].
+
+use limbo_sqlite3_parser::ast::{
+    self, Expr, FromClause, InsertBody, JoinConstraint, OneSelect, QualifiedName, RefAct,
+    ResultColumn, Select, SelectTable, TriggerCmd, TriggerEvent, TriggerTime,
+};
+
+use crate::error::SQLITE_IGNORE;
+use crate::schema::{BTreeTable, Column, FkActionInProgress, Schema, Trigger, View};
+use crate::util::normalize_ident;
+use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, QueryMode};
+use crate::vdbe::insn::{Insn, Subprogram};
+use crate::vdbe::BranchOffset;
+use crate::{bail_parse_error, Result, SymbolTable};
+
+use super::delete::translate_delete;
+use super::emitter::{emit_coroutine, Resolver};
+use super::expr::{translate_condition_expr, translate_expr, ConditionMetadata};
+use super::foreign_key::ResolvedForeignKey;
+use super::insert::translate_insert;
+use super::optimizer::{optimize_plan, rewrite_expr};
+use super::plan::{Plan, SelectQueryType};
+use super::planner::view_columns;
+use super::select::{prepare_select_plan, translate_select};
+use super::update::translate_update;
+use super::window::child_exprs_mut;
+
+/// The trigger programs that a statement runs for each row that it changes.
+#[derive(Debug, Clone, Default)]
+pub struct RowTriggers {
+    /// The time of each trigger and its program.
+    programs: Vec<(TriggerTime, Subprogram)>,
+    /// The number of columns of the table, i.e. of the `OLD` and `NEW` rows.
+    num_columns: usize,
+}
+
+impl RowTriggers {
+    /// Compiles the triggers on `table_name` that fire for `event` into sub-programs of `program`.
+    /// `updated_columns` are the indexes of the columns assigned by an UPDATE, which decide
+    /// whether an `UPDATE OF` trigger fires. The foreign key actions, if PRAGMA foreign_keys is
+    /// on, fire before the AFTER triggers.
+    pub fn compile(
+        program: &mut ProgramBuilder,
+        schema: &Schema,
+        table_name: &str,
+        columns: &[Column],
+        event: TriggerEvent,
+        updated_columns: &[usize],
+        syms: &SymbolTable,
+    ) -> Result<Self> {
+        let mut triggers = Self::compile_foreign_key_actions(
+            program,
+            schema,
+            table_name,
+            columns,
+            event.clone(),
+            updated_columns,
+            syms,
+        )?;
+        for trigger in schema.get_triggers(table_name) {
+            let fires = match &trigger.event {
+                TriggerEvent::UpdateOf(names) => {
+                    event == TriggerEvent::Update
+                        && names.iter().any(|name| {
+                            column_index(columns, &name.0)
+                                .is_some_and(|index| updated_columns.contains(&index))
+                        })
+                }
+                trigger_event => *trigger_event == event,
+            };
+            if !fires {
+                continue;
+            }
+            let trigger_program =
+                translate_trigger_program(schema, trigger, columns, &event, None, syms)?;
+            triggers.programs.push((
+                trigger.time,
+                Subprogram::Index(program.add_subprogram(trigger_program)),
+            ));
+        }
+        Ok(triggers)
+    }
+
+    /// Compiles only the foreign key actions of [Self::compile], which also run for the rows
+    /// that REPLACE deletes.
+    pub fn compile_foreign_key_actions(
+        program: &mut ProgramBuilder,
+        schema: &Schema,
+        table_name: &str,
+        columns: &[Column],
+        event: TriggerEvent,
+        updated_columns: &[usize],
+        syms: &SymbolTable,
+    ) -> Result<Self> {
+        let mut programs = Vec::new();
+        let on_delete = event == TriggerEvent::Delete;
+        if syms.foreign_keys.get() && (on_delete || event == TriggerEvent::Update) {
+            for (child, fk_index) in schema.get_referencing_foreign_keys(table_name) {
+                let foreign_key = &child.foreign_keys[fk_index];
+                let action = if on_delete {
+                    foreign_key.on_delete
+                } else {
+                    foreign_key.on_update
+                };
+                if action == RefAct::NoAction {
+                    continue;
+                }
+                let fk = ResolvedForeignKey::new(schema, &child, fk_index)?;
+                if !on_delete
+                    && !fk
+                        .parent_columns
+                        .iter()
+                        .any(|column| updated_columns.contains(column))
+                {
+                    continue;
+                }
+                // An action that fires itself again, like a cascading delete in a tree, runs
+                // its own program recursively.
+                if let Some(in_progress) = schema.fk_actions_in_progress.iter().find(|action| {
+                    action.child_table == child.name
+                        && action.fk_index == fk_index
+                        && action.on_delete == on_delete
+                }) {
+                    programs.push((TriggerTime::After, Subprogram::Frame(in_progress.frames_up)));
+                    continue;
+                }
+                let in_progress = FkActionInProgress {
+                    child_table: child.name.clone(),
+                    fk_index,
+                    on_delete,
+                    frames_up: 1,
+                };
+                let trigger = fk_action_trigger(&fk, action, on_delete);
+                let trigger_program = translate_trigger_program(
+                    schema,
+                    &trigger,
+                    columns,
+                    &event,
+                    Some(in_progress),
+                    syms,
+                )?;
+                programs.push((
+                    TriggerTime::After,
+                    Subprogram::Index(program.add_subprogram(trigger_program)),
+                ));
+            }
+        }
+        Ok(Self {
+            programs,
+            num_columns: columns.len(),
+        })
+    }
+
+    pub fn has(&self, time: TriggerTime) -> bool {
+        self.programs.iter().any(|(t, _)| *t == time)
+    }
+
+    /// Runs the triggers that fire at `time`. `old` and `new` are the first of the registers that
+    /// hold the rowid and the columns of the `OLD` and `NEW` rows, if the statement has them.
+    /// If a trigger runs `RAISE(IGNORE)`, the program continues at `ignore_target`.
+    pub fn emit(
+        &self,
+        program: &mut ProgramBuilder,
+        time: TriggerTime,
+        old: Option<usize>,
+        new: Option<usize>,
+        ignore_target: BranchOffset,
+    ) {
+        if !self.has(time) {
+            return;
+        }
+        let row_len = self.num_columns + 1;
+        let params_start = program.alloc_registers(2 * row_len);
+        for (row, dst_reg) in [(old, params_start), (new, params_start + row_len)] {
+            match row {
+                Some(src_reg) => program.emit_insn(Insn::Copy {
+                    src_reg,
+                    dst_reg,
+                    amount: self.num_columns,
+                }),
+                None => program.emit_null(dst_reg, Some(dst_reg + self.num_columns)),
+            }
+        }
+        for (_, subprogram) in self.programs.iter().filter(|(t, _)| *t == time) {
+            program.emit_insn(Insn::Program {
+                subprogram: *subprogram,
+                params_start,
+                ignore_target,
+            });
+        }
+    }
+}
+
+fn column_index(columns: &[Column], name: &str) -> Option<usize> {
+    let name = normalize_ident(name);
+    columns.iter().position(|column| {
+        column
+            .name
+            .as_ref()
+            .is_some_and(|column_name| column_name.eq_ignore_ascii_case(&name))
+    })
+}
+
+/// Compiles the WHEN clause and the commands of a trigger on a table with `columns`, or of the
+/// foreign key action `fk_action`.
+fn translate_trigger_program(
+    schema: &Schema,
+    trigger: &Trigger,
+    columns: &[Column],
+    event: &TriggerEvent,
+    fk_action: Option<FkActionInProgress>,
+    syms: &SymbolTable,
+) -> Result<ProgramBuilder> {
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode: QueryMode::Normal,
+        num_cursors: 0,
+        approx_num_insns: 10 + trigger.commands.len(),
+        approx_num_labels: 2,
+    });
+    let has_old = *event != TriggerEvent::Insert;
+    let has_new = *event != TriggerEvent::Delete;
+    let mut rewriter = TriggerExprRewriter::new(|expr: &mut Expr| {
+        if let Expr::Qualified(table, column) = expr {
+            if let Some(offset) = row_param_offset(columns, has_old, has_new, table, column)? {
+                *expr = Expr::TriggerParam(offset);
+            }
+        }
+        Ok(())
+    });
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    let end_label = program.allocate_label();
+    let ignore_label = program.allocate_label();
+
+    if let Some(when_clause) = &trigger.when_clause {
+        let mut when_clause = when_clause.clone();
+        rewriter.rewrite_expr(&mut when_clause)?;
+        if rewriter.has_subquery {
+            bail_parse_error!("subqueries in the WHEN clause of a trigger are not supported");
+        }
+        rewrite_expr(&mut when_clause)?;
+        let when_true_label = program.allocate_label();
+        translate_condition_expr(
+            &mut program,
+            &[],
+            &when_clause,
+            ConditionMetadata {
+                jump_if_condition_is_true: false,
+                jump_target_when_true: when_true_label,
+                jump_target_when_false: end_label,
+            },
+            &Resolver::new(syms),
+        )?;
+        program.resolve_label(when_true_label, program.offset());
+    }
+
+    // The commands read the OLD and NEW rows from this program, so copy them here.
+    let num_params = 2 * (columns.len() + 1);
+    let params_start = program.alloc_registers(num_params);
+    for offset in 0..num_params {
+        program.emit_insn(Insn::Param {
+            offset,
+            dest: params_start + offset,
+        });
+    }
+
+    // Like SQLite with recursive_triggers off, a trigger does not fire again while it runs.
+    // The commands run two frames below this program: in their own, inside the one of this one.
+    let mut body_schema = schema.clone();
+    for action in body_schema.fk_actions_in_progress.iter_mut() {
+        action.frames_up += 2;
+    }
+    match fk_action {
+        Some(fk_action) => body_schema.fk_actions_in_progress.push(fk_action),
+        None => body_schema.remove_trigger(&trigger.name),
+    }
+    for command in &trigger.commands {
+        let mut command = command.clone();
+        rewriter.rewrite_command(&mut command)?;
+        let command_program = translate_trigger_command(&body_schema, command, syms)?;
+        let subprogram = program.add_subprogram(command_program);
+        program.emit_insn(Insn::Program {
+            subprogram: Subprogram::Index(subprogram),
+            params_start,
+            ignore_target: ignore_label,
+        });
+    }
+
+    program.resolve_label(end_label, program.offset());
+    program.emit_halt();
+    program.resolve_label(ignore_label, program.offset());
+    program.emit_halt_err(SQLITE_IGNORE, ast::ResolveType::Ignore, String::new());
+    program.resolve_label(init_label, program.offset());
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+    Ok(program)
+}
+
+/// Builds the trigger that runs the ON DELETE or ON UPDATE `action` of the foreign key `fk` for
+/// each deleted or updated row of the parent table, like SQLite does.
+fn fk_action_trigger(fk: &ResolvedForeignKey, action: RefAct, on_delete: bool) -> Trigger {
+    let column_name = |table: &BTreeTable, position: usize| {
+        ast::Name(table.columns[position].name.clone().unwrap_or_default())
+    };
+    let parent_key = |row: &str, position: usize| {
+        Expr::Qualified(
+            ast::Name(row.to_string()),
+            column_name(&fk.parent, position),
+        )
+    };
+    // WHERE <child key> = OLD.<parent key>
+    let where_clause = fk
+        .child_columns
+        .iter()
+        .zip(&fk.parent_columns)
+        .map(|(&child_column, &parent_column)| {
+            Expr::Binary(
+                Box::new(Expr::Id(ast::Id(column_name(&fk.child, child_column).0))),
+                ast::Operator::Equals,
+                Box::new(parent_key("old", parent_column)),
+            )
+        })
+        .reduce(|lhs, rhs| Expr::Binary(Box::new(lhs), ast::Operator::And, Box::new(rhs)));
+    // WHEN OLD.<parent key> IS NOT NEW.<parent key>
+    let when_clause = (!on_delete).then(|| {
+        fk.parent_columns
+            .iter()
+            .map(|&column| {
+                Expr::Binary(
+                    Box::new(parent_key("old", column)),
+                    ast::Operator::IsNot,
+                    Box::new(parent_key("new", column)),
+                )
+            })
+            .reduce(|lhs, rhs| Expr::Binary(Box::new(lhs), ast::Operator::Or, Box::new(rhs)))
+            .unwrap()
+    });
+    let child_name = ast::Name(fk.child.name.clone());
+    let update = |value: &dyn Fn(usize, usize) -> Expr| {
+        TriggerCmd::Update(Box::new(ast::TriggerCmdUpdate {
+            or_conflict: None,
+            tbl_name: child_name.clone(),
+            sets: fk
+                .child_columns
+                .iter()
+                .zip(&fk.parent_columns)
+                .map(|(&child_column, &parent_column)| ast::Set {
+                    col_names: ast::DistinctNames::single(column_name(&fk.child, child_column)),
+                    expr: value(child_column, parent_column),
+                })
+                .collect(),
+            from: None,
+            where_clause: where_clause.clone(),
+        }))
+    };
+    let null = || Expr::Literal(ast::Literal::Null);
+    let command = match action {
+        RefAct::Cascade if on_delete => TriggerCmd::Delete(Box::new(ast::TriggerCmdDelete {
+            tbl_name: child_name.clone(),
+            where_clause: where_clause.clone(),
+        })),
+        RefAct::Cascade => update(&|_, parent_column| parent_key("new", parent_column)),
+        RefAct::SetNull => update(&|_, _| null()),
+        RefAct::SetDefault => update(&|child_column, _| {
+            fk.child.columns[child_column]
+                .default
+                .clone()
+                .unwrap_or_else(null)
+        }),
+        RefAct::Restrict => TriggerCmd::Select(Box::new(Select {
+            with: None,
+            body: ast::SelectBody {
+                select: Box::new(OneSelect::Select(Box::new(ast::SelectInner {
+                    distinctness: None,
+                    columns: vec![ResultColumn::Expr(
+                        Expr::Raise(
+                            ast::ResolveType::Abort,
+                            Some(Box::new(Expr::Literal(ast::Literal::String(
+                                "'FOREIGN KEY constraint failed'".to_string(),
+                            )))),
+                        ),
+                        None,
+                    )],
+                    from: Some(FromClause::new(
+                        Box::new(SelectTable::Table(
+                            QualifiedName::single(child_name.clone()),
+                            None,
+                            None,
+                        )),
+                        None,
+                    )),
+                    where_clause: where_clause.clone(),
+                    group_by: None,
+                    window_clause: None,
+                }))),
+                compounds: None,
+            },
+            order_by: None,
+            limit: None,
+        })),
+        RefAct::NoAction => unreachable!("NO ACTION is not run by a trigger"),
+    };
+    Trigger::new(
+        &format!("{} foreign key {}", fk.child.name, fk.fk_index),
+        &fk.parent.name,
+        Some(TriggerTime::After),
+        if on_delete {
+            TriggerEvent::Delete
+        } else {
+            TriggerEvent::Update
+        },
+        when_clause,
+        vec![command],
+    )
+}
+
+fn translate_trigger_command(
+    schema: &Schema,
+    command: TriggerCmd,
+    syms: &SymbolTable,
+) -> Result<ProgramBuilder> {
+    match command {
+        TriggerCmd::Insert(insert) => {
+            let ast::TriggerCmdInsert {
+                or_conflict,
+                tbl_name,
+                col_names,
+                select,
+                upsert,
+                returning,
+            } = *insert;
+            if returning.is_some() {
+                bail_parse_error!("cannot use RETURNING in a trigger");
+            }
+            translate_insert(
+                QueryMode::Normal,
+                schema,
+                &None,
+                &or_conflict,
+                &QualifiedName::single(tbl_name),
+                &col_names,
+                &InsertBody::Select(select, upsert),
+                &returning,
+                syms,
+            )
+        }
+        TriggerCmd::Update(update) => {
+            let ast::TriggerCmdUpdate {
+                or_conflict,
+                tbl_name,
+                sets,
+                from,
+                where_clause,
+            } = *update;
+            let mut update = ast::Update {
+                with: None,
+                or_conflict,
+                tbl_name: QualifiedName::single(tbl_name),
+                indexed: None,
+                sets,
+                from,
+                where_clause: where_clause.map(Box::new),
+                returning: None,
+                order_by: None,
+                limit: None,
+            };
+            translate_update(QueryMode::Normal, schema, &mut update, syms)
+        }
+        TriggerCmd::Delete(delete) => {
+            let ast::TriggerCmdDelete {
+                tbl_name,
+                where_clause,
+            } = *delete;
+            translate_delete(
+                QueryMode::Normal,
+                schema,
+                &QualifiedName::single(tbl_name),
+                where_clause.map(Box::new),
+                None,
+                &None,
+                syms,
+            )
+        }
+        TriggerCmd::Select(select) => translate_select(QueryMode::Normal, schema, *select, syms),
+    }
+}
+
+/// Returns the offset of `table.column` in the parameters of a trigger program on a table with
+/// `columns`, or None if `table` is neither `OLD` nor `NEW`.
+fn row_param_offset(
+    columns: &[Column],
+    has_old: bool,
+    has_new: bool,
+    table: &ast::Name,
+    column: &ast::Name,
+) -> Result<Option<usize>> {
+    let table = normalize_ident(&table.0);
+    let row_start = match table.as_str() {
+        "old" if has_old => 0,
+        "new" if has_new => columns.len() + 1,
+        "old" | "new" => bail_parse_error!("no such column: {}.{}", table, column),
+        _ => return Ok(None),
+    };
+    let offset = match column_index(columns, &column.0) {
+        // The value of a rowid alias column is the rowid.
+        Some(index) if columns[index].is_rowid_alias => 0,
+        Some(index) => index + 1,
+        None if ["rowid", "oid", "_rowid_"].contains(&normalize_ident(&column.0).as_str()) => 0,
+        None => bail_parse_error!("no such column: {}.{}", table, column),
+    };
+    Ok(Some(row_start + offset))
+}
+
+/// Walks the expressions in the WHEN clause and the commands of a trigger, and calls
+/// `rewrite_column` on each qualified column reference, e.g. `NEW.a`.
+pub(crate) struct TriggerExprRewriter<F> {
+    rewrite_column: F,
+    /// Whether a rewritten expression contains a subquery.
+    has_subquery: bool,
+}
+
+impl<F: FnMut(&mut Expr) -> Result<()>> TriggerExprRewriter<F> {
+    pub fn new(rewrite_column: F) -> Self {
+        Self {
+            rewrite_column,
+            has_subquery: false,
+        }
+    }
+
+    pub fn rewrite_expr(&mut self, expr: &mut Expr) -> Result<()> {
+        match expr {
+            Expr::Qualified(..) => return (self.rewrite_column)(expr),
+            Expr::Exists(select) | Expr::Subquery(select) => {
+                self.has_subquery = true;
+                self.rewrite_select(select)?;
+            }
+            Expr::InSelect { lhs, rhs, .. } => {
+                self.has_subquery = true;
+                self.rewrite_expr(lhs)?;
+                self.rewrite_select(rhs)?;
+            }
+            Expr::InTable { lhs, args, .. } => {
+                self.rewrite_expr(lhs)?;
+                for arg in args.iter_mut().flatten() {
+                    self.rewrite_expr(arg)?;
+                }
+            }
+            Expr::Raise(_, Some(message)) => self.rewrite_expr(message)?,
+            _ => {}
+        }
+        for child in child_exprs_mut(expr) {
+            self.rewrite_expr(child)?;
+        }
+        Ok(())
+    }
+
+    fn rewrite_select(&mut self, select: &mut Select) -> Result<()> {
+        for cte in select.with.iter_mut().flat_map(|with| with.ctes.iter_mut()) {
+            self.rewrite_select(&mut cte.select)?;
+        }
+        self.rewrite_one_select(&mut select.body.select)?;
+        for compound in select.body.compounds.iter_mut().flatten() {
+            self.rewrite_one_select(&mut compound.select)?;
+        }
+        for sorted_column in select.order_by.iter_mut().flatten() {
+            self.rewrite_expr(&mut sorted_column.expr)?;
+        }
+        if let Some(limit) = &mut select.limit {
+            self.rewrite_expr(&mut limit.expr)?;
+            if let Some(offset) = &mut limit.offset {
+                self.rewrite_expr(offset)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_one_select(&mut self, select: &mut OneSelect) -> Result<()> {
+        match select {
+            OneSelect::Select(select) => {
+                for column in select.columns.iter_mut() {
+                    if let ResultColumn::Expr(expr, _) = column {
+                        self.rewrite_expr(expr)?;
+                    }
+                }
+                if let Some(from) = &mut select.from {
+                    self.rewrite_from_clause(from)?;
+                }
+                if let Some(where_clause) = &mut select.where_clause {
+                    self.rewrite_expr(where_clause)?;
+                }
+                if let Some(group_by) = &mut select.group_by {
+                    for expr in group_by.exprs.iter_mut() {
+                        self.rewrite_expr(expr)?;
+                    }
+                    if let Some(having) = &mut group_by.having {
+                        self.rewrite_expr(having)?;
+                    }
+                }
+            }
+            OneSelect::Values(rows) => {
+                for expr in rows.iter_mut().flatten() {
+                    self.rewrite_expr(expr)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_from_clause(&mut self, from: &mut FromClause) -> Result<()> {
+        if let Some(table) = &mut from.select {
+            self.rewrite_select_table(table)?;
+        }
+        for join in from.joins.iter_mut().flatten() {
+            self.rewrite_select_table(&mut join.table)?;
+            if let Some(JoinConstraint::On(expr)) = &mut join.constraint {
+                self.rewrite_expr(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_select_table(&mut self, table: &mut SelectTable) -> Result<()> {
+        match table {
+            SelectTable::Table(..) => {}
+            SelectTable::TableCall(_, args, _) => {
+                for arg in args.iter_mut().flatten() {
+                    self.rewrite_expr(arg)?;
+                }
+            }
+            SelectTable::Select(select, _) => self.rewrite_select(select)?,
+            SelectTable::Sub(from, _) => self.rewrite_from_clause(from)?,
+        }
+        Ok(())
+    }
+
+    pub fn rewrite_command(&mut self, command: &mut TriggerCmd) -> Result<()> {
+        match command {
+            TriggerCmd::Insert(insert) => self.rewrite_select(&mut insert.select),
+            TriggerCmd::Update(update) => {
+                for set in update.sets.iter_mut() {
+                    self.rewrite_expr(&mut set.expr)?;
+                }
+                if let Some(from) = &mut update.from {
+                    self.rewrite_from_clause(from)?;
+                }
+                if let Some(where_clause) = &mut update.where_clause {
+                    self.rewrite_expr(where_clause)?;
+                }
+                Ok(())
+            }
+            TriggerCmd::Delete(delete) => {
+                if let Some(where_clause) = &mut delete.where_clause {
+                    self.rewrite_expr(where_clause)?;
+                }
+                Ok(())
+            }
+            TriggerCmd::Select(select) => self.rewrite_select(select),
+        }
+    }
+}
+
+/// Translates an INSERT into a view, which runs the INSTEAD OF INSERT triggers of the view
+/// for each row of VALUES instead of inserting it.
+pub fn translate_view_insert(
+    query_mode: QueryMode,
+    schema: &Schema,
+    view: &View,
+    columns: &Option<ast::DistinctNames>,
+    body: &InsertBody,
+    syms: &SymbolTable,
+) -> Result<ProgramBuilder> {
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 2,
+    });
+    let view_columns = view_columns(schema, view, syms)?;
+    let triggers = RowTriggers::compile(
+        &mut program,
+        schema,
+        &view.name,
+        &view_columns,
+        TriggerEvent::Insert,
+        &[],
+        syms,
+    )?;
+    if !triggers.has(TriggerTime::InsteadOf) {
+        bail_parse_error!("cannot modify {} because it is a view", view.name);
+    }
+    let rows = match body {
+        InsertBody::Select(select, None) => match select.body.select.as_ref() {
+            OneSelect::Values(values) if select.body.compounds.is_none() => values,
+            _ => bail_parse_error!("INSERT INTO a view only supports VALUES"),
+        },
+        InsertBody::Select(_, Some(_)) => bail_parse_error!("ON CONFLICT clause is not supported"),
+        InsertBody::DefaultValues => &vec![vec![]],
+    };
+    // The position of the value of each column of the view in a row of VALUES.
+    let value_indexes = match columns {
+        Some(names) => {
+            let mut value_indexes = vec![None; view_columns.len()];
+            for (value_index, name) in names.iter().enumerate() {
+                let Some(index) = column_index(&view_columns, &name.0) else {
+                    bail_parse_error!("table {} has no column named {}", view.name, name.0);
+                };
+                value_indexes[index] = Some(value_index);
+            }
+            value_indexes
+        }
+        None => (0..view_columns.len()).map(Some).collect(),
+    };
+    let num_values = columns
+        .as_ref()
+        .map_or(view_columns.len(), |names| names.len());
+    for row in rows.iter() {
+        if row.len() != num_values && !matches!(body, InsertBody::DefaultValues) {
+            bail_parse_error!(
+                "table {} has {} columns but {} values were supplied",
+                view.name,
+                num_values,
+                row.len()
+            );
+        }
+    }
+
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    let resolver = Resolver::new(syms);
+    let new_row = program.alloc_registers(view_columns.len() + 1);
+    for row in rows.iter() {
+        let next_row_label = program.allocate_label();
+        program.emit_null(new_row, None);
+        for (i, value_index) in value_indexes.iter().enumerate() {
+            match value_index.and_then(|value_index| row.get(value_index)) {
+                Some(value) => {
+                    translate_expr(&mut program, None, value, new_row + 1 + i, &resolver)?;
+                }
+                None => program.emit_null(new_row + 1 + i, None),
+            }
+        }
+        triggers.emit(
+            &mut program,
+            TriggerTime::InsteadOf,
+            None,
+            Some(new_row),
+            next_row_label,
+        );
+        program.resolve_label(next_row_label, program.offset());
+    }
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_transaction(true);
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+    Ok(program)
+}
+
+/// Translates an UPDATE or a DELETE of a view, which runs the INSTEAD OF triggers of the view
+/// for each row of the view that matches the WHERE clause. `sets` is empty for a DELETE.
+pub fn translate_view_update_or_delete(
+    query_mode: QueryMode,
+    schema: &Schema,
+    view: &View,
+    sets: &[ast::Set],
+    where_clause: Option<Box<Expr>>,
+    syms: &SymbolTable,
+) -> Result<ProgramBuilder> {
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 1,
+        approx_num_insns: 30,
+        approx_num_labels: 3,
+    });
+    let is_delete = sets.is_empty();
+    let view_columns = view_columns(schema, view, syms)?;
+    // The index of the column assigned by each SET clause.
+    let mut updated_columns = Vec::with_capacity(sets.len());
+    for set in sets {
+        let [name] = set.col_names.iter().collect::<Vec<_>>()[..] else {
+            bail_parse_error!("row value assignments are not supported");
+        };
+        let name = &name.0;
+        let Some(index) = column_index(&view_columns, name) else {
+            bail_parse_error!("no such column: {}", name);
+        };
+        updated_columns.push(index);
+    }
+    let triggers = RowTriggers::compile(
+        &mut program,
+        schema,
+        &view.name,
+        &view_columns,
+        if is_delete {
+            TriggerEvent::Delete
+        } else {
+            TriggerEvent::Update
+        },
+        &updated_columns,
+        syms,
+    )?;
+    if !triggers.has(TriggerTime::InsteadOf) {
+        bail_parse_error!("cannot modify {} because it is a view", view.name);
+    }
+
+    // SELECT *, <the values of the SET clauses> FROM view WHERE ...
+    let select = Select {
+        with: None,
+        body: ast::SelectBody {
+            select: Box::new(OneSelect::Select(Box::new(ast::SelectInner {
+                distinctness: None,
+                columns: std::iter::once(ResultColumn::Star)
+                    .chain(
+                        sets.iter()
+                            .map(|set| ResultColumn::Expr(set.expr.clone(), None)),
+                    )
+                    .collect(),
+                from: Some(FromClause::new(
+                    Box::new(SelectTable::Table(
+                        QualifiedName::single(ast::Name(view.name.clone())),
+                        None,
+                        None,
+                    )),
+                    None,
+                )),
+                where_clause: where_clause.map(|expr| *expr),
+                group_by: None,
+                window_clause: None,
+            }))),
+            compounds: None,
+        },
+        order_by: None,
+        limit: None,
+    };
+    let mut plan = prepare_select_plan(schema, select, syms, None)?;
+    optimize_plan(&mut plan, schema)?;
+    let Plan::Select(mut plan) = plan else {
+        unreachable!("a SELECT without compounds is planned as a SelectPlan");
+    };
+    plan.query_type = SelectQueryType::Subquery {
+        yield_reg: usize::MAX, // will be set in emit_coroutine
+        coroutine_implementation_start: BranchOffset::Placeholder,
+    };
+
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    let (yield_reg, row_start) = emit_coroutine(&mut program, &mut plan, syms)?;
+    let num_columns = view_columns.len();
+    let old_row = program.alloc_registers(num_columns + 1);
+    let new_row = (!is_delete).then(|| program.alloc_registers(num_columns + 1));
+    let loop_start = program.allocate_label();
+    let loop_end = program.allocate_label();
+    program.resolve_label(loop_start, program.offset());
+    program.emit_insn(Insn::Yield {
+        yield_reg,
+        end_offset: loop_end,
+    });
+    program.emit_null(old_row, None);
+    program.emit_insn(Insn::Copy {
+        src_reg: row_start,
+        dst_reg: old_row + 1,
+        amount: num_columns - 1,
+    });
+    if let Some(new_row) = new_row {
+        program.emit_insn(Insn::Copy {
+            src_reg: old_row,
+            dst_reg: new_row,
+            amount: num_columns,
+        });
+        for (i, index) in updated_columns.iter().enumerate() {
+            program.emit_insn(Insn::Copy {
+                src_reg: row_start + num_columns + i,
+                dst_reg: new_row + 1 + index,
+                amount: 0,
+            });
+        }
+    }
+    triggers.emit(
+        &mut program,
+        TriggerTime::InsteadOf,
+        Some(old_row),
+        new_row,
+        loop_start,
+    );
+    program.emit_goto(loop_start);
+    program.resolve_label(loop_end, program.offset());
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    program.emit_transaction(true);
+    program.emit_constant_insns();
+    program.emit_goto(start_offset);
+    Ok(program)
+}
@@ -6,16 +6,19 @@ use crate::{
     vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, QueryMode},
     SymbolTable,
 };
-use limbo_sqlite3_parser::ast::{self, Expr, ResultColumn, SortOrder, Update};
+use limbo_sqlite3_parser::ast::{Expr, SortOrder, TriggerEvent, Update};
 
+use super::constraint::ReplaceDeletes;
 use super::emitter::emit_program;
+use super::foreign_key::ForeignKeyChecks;
 use super::optimizer::optimize_plan;
 use super::plan::{
-    ColumnUsedMask, Direction, IterationDirection, Plan, ResultSetColumn, TableReference,
-    UpdatePlan,
+    ColumnUsedMask, Direction, IterationDirection, Plan, TableReference, UpdatePlan,
 };
 use super::planner::bind_column_references;
-use super::planner::{parse_limit, parse_where};
+use super::planner::{parse_limit, parse_where, plan_dml_subqueries};
+use super::returning::prepare_returning;
+use super::trigger::{translate_view_update_or_delete, RowTriggers};
 
 /*
 * Update is simple. By default we scan the table, and for each row, we check the WHERE
@@ -52,7 +55,20 @@ pub fn translate_update(
     body: &mut Update,
     syms: &SymbolTable,
 ) -> crate::Result<ProgramBuilder> {
-    let mut plan = prepare_update_plan(schema, body)?;
+    let db_name = body.tbl_name.db_name.as_ref().map(|name| name.0.clone());
+    if let Some((_, table)) = schema.find_table(db_name.as_deref(), &body.tbl_name.name.0)? {
+        if let Table::View(view) = table.as_ref() {
+            return translate_view_update_or_delete(
+                query_mode,
+                schema,
+                view,
+                &body.sets,
+                body.where_clause.take(),
+                syms,
+            );
+        }
+    }
+    let mut plan = prepare_update_plan(schema, body, syms)?;
     optimize_plan(&mut plan, schema)?;
     // TODO: freestyling these numbers
     let mut program = ProgramBuilder::new(ProgramBuilderOpts {
@@ -61,22 +77,50 @@ pub fn translate_update(
         approx_num_insns: 20,
         approx_num_labels: 4,
     });
+    if let Plan::Update(update_plan) = &mut plan {
+        if let Some(btree) = update_plan.table_references[0].btree() {
+            let updated_columns = update_plan
+                .set_clauses
+                .iter()
+                .map(|(index, _)| *index)
+                .collect::<Vec<_>>();
+            let schema = &schema.database_schema(update_plan.table_references[0].database)?;
+            update_plan.triggers = RowTriggers::compile(
+                &mut program,
+                schema,
+                &btree.name,
+                &btree.columns,
+                TriggerEvent::Update,
+                &updated_columns,
+                syms,
+            )?;
+            update_plan.foreign_keys =
+                ForeignKeyChecks::compile(schema, &btree, Some(&updated_columns), syms)?;
+            update_plan.replace_deletes =
+                ReplaceDeletes::compile(&mut program, schema, &btree, syms)?;
+        }
+    }
     emit_program(&mut program, plan, syms)?;
     Ok(program)
 }
 
-pub fn prepare_update_plan(schema: &Schema, body: &mut Update) -> crate::Result<Plan> {
+pub fn prepare_update_plan(
+    schema: &Schema,
+    body: &mut Update,
+    syms: &SymbolTable,
+) -> crate::Result<Plan> {
     if body.with.is_some() {
         bail_parse_error!("WITH clause is not supported");
     }
-    if body.or_conflict.is_some() {
-        bail_parse_error!("ON CONFLICT clause is not supported");
-    }
     let table_name = &body.tbl_name.name;
-    let table = match schema.get_table(table_name.0.as_str()) {
+    let db_name = body.tbl_name.db_name.as_ref().map(|name| name.0.as_str());
+    let (database, table) = match schema.find_table(db_name, table_name.0.as_str())? {
         Some(table) => table,
         None => bail_parse_error!("Parse error: no such table: {}", table_name),
     };
+    if let Table::View(view) = table.as_ref() {
+        bail_parse_error!("cannot modify {} because it is a view", view.name);
+    }
     let iter_dir = body
         .order_by
         .as_ref()
@@ -102,7 +146,10 @@ pub fn prepare_update_plan(schema: &Schema, body: &mut Update) -> crate::Result<
         },
         join_info: None,
         col_used_mask: ColumnUsedMask::new(),
+        database,
+        database_name: schema.database_name(database).to_string(),
     }];
+    let mut expr_subqueries = vec![];
     let set_clauses = body
         .sets
         .iter_mut()
@@ -125,33 +172,25 @@ pub fn prepare_update_plan(schema: &Schema, body: &mut Update) -> crate::Result<
                     ))
                 })?;
 
+            plan_dml_subqueries(
+                &mut set.expr,
+                schema,
+                syms,
+                &table_references,
+                &mut expr_subqueries,
+            )?;
             let _ = bind_column_references(&mut set.expr, &mut table_references, None);
             Ok((col_index, set.expr.clone()))
         })
         .collect::<Result<Vec<(usize, Expr)>, crate::LimboError>>()?;
-
-    let mut where_clause = vec![];
-    let mut result_columns = vec![];
-    if let Some(returning) = &mut body.returning {
-        for rc in returning.iter_mut() {
-            if let ResultColumn::Expr(expr, alias) = rc {
-                bind_column_references(expr, &mut table_references, None)?;
-                result_columns.push(ResultSetColumn {
-                    expr: expr.clone(),
-                    alias: alias.as_ref().and_then(|a| {
-                        if let ast::As::As(name) = a {
-                            Some(name.to_string())
-                        } else {
-                            None
-                        }
-                    }),
-                    contains_aggregates: false,
-                });
-            } else {
-                bail_parse_error!("Only expressions are allowed in RETURNING clause");
-            }
+    let returning = match &body.returning {
+        Some(_) if table.virtual_table().is_some() => {
+            bail_parse_error!("RETURNING is not supported for virtual tables")
         }
-    }
+        Some(returning) => Some(prepare_returning(returning, &mut table_references)?),
+        None => None,
+    };
+    let mut where_clause = vec![];
     let order_by = body.order_by.as_ref().map(|order| {
         order
             .iter()
@@ -168,13 +207,12 @@ pub fn prepare_update_plan(schema: &Schema, body: &mut Update) -> crate::Result<
             })
             .collect()
     });
+    let mut where_expr = body.where_clause.as_ref().map(|w| *w.clone());
+    if let Some(expr) = where_expr.as_mut() {
+        plan_dml_subqueries(expr, schema, syms, &table_references, &mut expr_subqueries)?;
+    }
     // Parse the WHERE clause
-    parse_where(
-        body.where_clause.as_ref().map(|w| *w.clone()),
-        &mut table_references,
-        Some(&result_columns),
-        &mut where_clause,
-    )?;
+    parse_where(where_expr, &mut table_references, None, &mut where_clause)?;
 
     // Parse the LIMIT/OFFSET clause
     let (limit, offset) = body
@@ -187,10 +225,19 @@ pub fn prepare_update_plan(schema: &Schema, body: &mut Update) -> crate::Result<
         table_references,
         set_clauses,
         where_clause,
-        returning: Some(result_columns),
+        returning,
         order_by,
         limit,
         offset,
         contains_constant_false_condition: false,
+        triggers: RowTriggers::default(),
+        foreign_keys: ForeignKeyChecks::default(),
+        replace_deletes: ReplaceDeletes::default(),
+        or_conflict: body.or_conflict,
+        indexes: schema
+            .database_schema(database)?
+            .get_indices(&table_name.0)
+            .to_vec(),
+        expr_subqueries,
     }))
 }
@@ -0,0 +1,46 @@
+use crate::schema::Schema;
+use crate::translate::emitter::Resolver;
+use crate::translate::expr::translate_expr;
+use crate::translate::{ProgramBuilder, ProgramBuilderOpts};
+use crate::vdbe::insn::Insn;
+use crate::{bail_parse_error, QueryMode, Result, SymbolTable};
+use limbo_sqlite3_parser::ast::{self, Expr};
+
+pub fn translate_vacuum(
+    query_mode: QueryMode,
+    schema: &Schema,
+    db_name: Option<&ast::Name>,
+    into: Option<&Expr>,
+    syms: &SymbolTable,
+) -> Result<ProgramBuilder> {
+    let db = match db_name {
+        Some(db_name) => schema.database_index(&db_name.0)?,
+        None => 0,
+    };
+    if db == 1 {
+        bail_parse_error!("VACUUM of the temp database is not supported");
+    }
+    let mut program = ProgramBuilder::new(ProgramBuilderOpts {
+        query_mode,
+        num_cursors: 0,
+        approx_num_insns: 5,
+        approx_num_labels: 0,
+    });
+    let init_label = program.emit_init();
+    let start_offset = program.offset();
+    let into_reg = match into {
+        Some(into) => {
+            let into_reg = program.alloc_register();
+            translate_expr(&mut program, None, into, into_reg, &Resolver::new(syms))?;
+            Some(into_reg)
+        }
+        None => None,
+    };
+    program.emit_insn(Insn::Vacuum { db, into_reg });
+    program.emit_halt();
+    program.resolve_label(init_label, program.offset());
+    // VACUUM INTO only reads the database, so other connections can keep writing meanwhile
+    program.emit_transaction(into.is_none());
+    program.emit_goto(start_offset);
+    Ok(program)
+}
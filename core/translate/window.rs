@@ -0,0 +1,2381 @@
+use std::rc::Rc;
+use std::sync::Arc;
+
+use limbo_sqlite3_parser::ast::{self, SortOrder};
+
+use crate::{
+    collation::CollationSeq,
+    function::{AggFunc, ExtFunc, Func, WindowFunc},
+    schema::{Column, Index, IndexColumn, PseudoTable},
+    types::{OwnedValue, Record},
+    util::{exprs_are_equivalent, normalize_ident},
+    vdbe::{
+        builder::{CursorType, ProgramBuilder},
+        insn::{CmpInsFlags, IdxInsertFlags, Insn},
+        BranchOffset, CursorID,
+    },
+    Result, SymbolTable,
+};
+
+use super::{
+    aggregation::translate_aggregation_step,
+    collate::expr_collation,
+    emitter::{Resolver, TranslateCtx},
+    expr::translate_expr,
+    order_by::{order_by_sorter_insert, sorter_insert},
+    plan::{
+        Aggregate, Direction, Operation, ResultSetColumn, SelectPlan, SelectQueryType,
+        TableReference, Window, WindowFrame, WindowFunction, WindowFunctionKind,
+    },
+    planner::resolve_aggregates,
+    result_row::emit_select_result,
+};
+
+/// Returns the OVER clause of `expr` if it is a window function call.
+fn over_clause(expr: &ast::Expr) -> Option<&ast::Over> {
+    match expr {
+        ast::Expr::FunctionCall {
+            filter_over: Some(tail),
+            ..
+        }
+        | ast::Expr::FunctionCallStar {
+            filter_over: Some(tail),
+            ..
+        } => tail.over_clause.as_deref(),
+        _ => None,
+    }
+}
+
+pub fn is_window_function_call(expr: &ast::Expr) -> bool {
+    over_clause(expr).is_some()
+}
+
+/// Returns the first window function call in `expr`, if any.
+fn find_window_function_call(expr: &ast::Expr) -> Option<&ast::Expr> {
+    if is_window_function_call(expr) {
+        return Some(expr);
+    }
+    child_exprs(expr)
+        .into_iter()
+        .find_map(find_window_function_call)
+}
+
+pub fn contains_window_function(expr: &ast::Expr) -> bool {
+    find_window_function_call(expr).is_some()
+}
+
+/// Returns the expressions of the PARTITION BY and ORDER BY clauses of a window.
+pub fn window_exprs(window: &ast::Window) -> impl Iterator<Item = &ast::Expr> {
+    window
+        .partition_by
+        .iter()
+        .flatten()
+        .chain(window.order_by.iter().flatten().map(|c| &c.expr))
+}
+
+/// Returns the subexpressions of `expr`.
+/// The window of a window function call is not included, see [window_exprs].
+pub fn child_exprs(expr: &ast::Expr) -> Vec<&ast::Expr> {
+    match expr {
+        ast::Expr::Between {
+            lhs, start, end, ..
+        } => vec![lhs, start, end],
+        ast::Expr::Binary(lhs, _, rhs) => vec![lhs, rhs],
+        ast::Expr::Case {
+            base,
+            when_then_pairs,
+            else_expr,
+        } => base
+            .iter()
+            .map(|e| e.as_ref())
+            .chain(when_then_pairs.iter().flat_map(|(w, t)| [w, t]))
+            .chain(else_expr.iter().map(|e| e.as_ref()))
+            .collect(),
+        ast::Expr::Cast { expr, .. }
+        | ast::Expr::Collate(expr, _)
+        | ast::Expr::IsNull(expr)
+        | ast::Expr::NotNull(expr)
+        | ast::Expr::Unary(_, expr) => vec![expr],
+        ast::Expr::FunctionCall {
+            args, filter_over, ..
+        } => args
+            .iter()
+            .flatten()
+            .chain(
+                filter_over
+                    .iter()
+                    .filter_map(|tail| tail.filter_clause.as_deref()),
+            )
+            .collect(),
+        ast::Expr::FunctionCallStar { filter_over, .. } => filter_over
+            .iter()
+            .filter_map(|tail| tail.filter_clause.as_deref())
+            .collect(),
+        ast::Expr::InList { lhs, rhs, .. } => std::iter::once(lhs.as_ref())
+            .chain(rhs.iter().flatten())
+            .collect(),
+        ast::Expr::Like {
+            lhs, rhs, escape, ..
+        } => [lhs, rhs]
+            .into_iter()
+            .map(|e| e.as_ref())
+            .chain(escape.iter().map(|e| e.as_ref()))
+            .collect(),
+        ast::Expr::Parenthesized(exprs) => exprs.iter().collect(),
+        ast::Expr::SubqueryResult { lhs: Some(lhs), .. } => vec![lhs],
+        _ => vec![],
+    }
+}
+
+/// Mutable version of [child_exprs].
+pub fn child_exprs_mut(expr: &mut ast::Expr) -> Vec<&mut ast::Expr> {
+    match expr {
+        ast::Expr::Between {
+            lhs, start, end, ..
+        } => vec![lhs, start, end],
+        ast::Expr::Binary(lhs, _, rhs) => vec![lhs, rhs],
+        ast::Expr::Case {
+            base,
+            when_then_pairs,
+            else_expr,
+        } => base
+            .iter_mut()
+            .map(|e| e.as_mut())
+            .chain(when_then_pairs.iter_mut().flat_map(|(w, t)| [w, t]))
+            .chain(else_expr.iter_mut().map(|e| e.as_mut()))
+            .collect(),
+        ast::Expr::Cast { expr, .. }
+        | ast::Expr::Collate(expr, _)
+        | ast::Expr::IsNull(expr)
+        | ast::Expr::NotNull(expr)
+        | ast::Expr::Unary(_, expr) => vec![expr],
+        ast::Expr::FunctionCall {
+            args, filter_over, ..
+        } => args
+            .iter_mut()
+            .flatten()
+            .chain(
+                filter_over
+                    .iter_mut()
+                    .filter_map(|tail| tail.filter_clause.as_deref_mut()),
+            )
+            .collect(),
+        ast::Expr::FunctionCallStar { filter_over, .. } => filter_over
+            .iter_mut()
+            .filter_map(|tail| tail.filter_clause.as_deref_mut())
+            .collect(),
+        ast::Expr::InList { lhs, rhs, .. } => std::iter::once(lhs.as_mut())
+            .chain(rhs.iter_mut().flatten())
+            .collect(),
+        ast::Expr::Like {
+            lhs, rhs, escape, ..
+        } => [lhs, rhs]
+            .into_iter()
+            .map(|e| e.as_mut())
+            .chain(escape.iter_mut().map(|e| e.as_mut()))
+            .collect(),
+        ast::Expr::Parenthesized(exprs) => exprs.iter_mut().collect(),
+        ast::Expr::SubqueryResult { lhs: Some(lhs), .. } => vec![lhs],
+        _ => vec![],
+    }
+}
+
+fn function_name(call: &ast::Expr) -> &str {
+    match call {
+        ast::Expr::FunctionCall { name, .. } | ast::Expr::FunctionCallStar { name, .. } => &name.0,
+        _ => unreachable!("not a function call: {:?}", call),
+    }
+}
+
+/// Returns an error if `expr` contains a window function call.
+/// Used for the clauses that are evaluated before the window functions, e.g. WHERE.
+pub fn check_no_window_functions(expr: &ast::Expr) -> Result<()> {
+    if let Some(call) = find_window_function_call(expr) {
+        crate::bail_parse_error!("misuse of window function {}()", function_name(call));
+    }
+    Ok(())
+}
+
+/// Returns an error if `expr` calls a window function where that is not allowed:
+/// - a built-in window function without an OVER clause, e.g. SELECT rank() FROM t
+/// - a window function call in the arguments or the window of another window function call
+/// - a window function call in the arguments of an aggregate function
+pub fn check_window_function_usage(expr: &ast::Expr) -> Result<()> {
+    if let Some(over) = over_clause(expr) {
+        for arg in child_exprs(expr) {
+            check_no_window_functions(arg)?;
+        }
+        if let ast::Over::Window(window) = over {
+            for window_expr in window_exprs(window) {
+                check_no_window_functions(window_expr)?;
+            }
+        }
+    } else if let ast::Expr::FunctionCall { name, args, .. } = expr {
+        let name = normalize_ident(name.0.as_str());
+        let arg_count = args.as_ref().map_or(0, |args| args.len());
+        if !matches!(WindowFunc::resolve_function(&name, arg_count), Ok(None)) {
+            crate::bail_parse_error!("misuse of window function {}()", function_name(expr));
+        }
+        if let Ok(Func::Agg(_)) = Func::resolve_function(&name, arg_count) {
+            for arg in args.iter().flatten() {
+                check_no_window_functions(arg)?;
+            }
+        }
+    } else if let ast::Expr::FunctionCallStar { name, .. } = expr {
+        if !matches!(
+            WindowFunc::resolve_function(&normalize_ident(name.0.as_str()), 0),
+            Ok(None)
+        ) {
+            crate::bail_parse_error!("misuse of window function {}()", function_name(expr));
+        }
+    }
+    for child in child_exprs(expr) {
+        check_window_function_usage(child)?;
+    }
+    Ok(())
+}
+
+/// Replaces the references to the named windows of the WINDOW clause in the window function calls
+/// of the result columns and the ORDER BY clause with the windows they refer to, e.g.
+/// SELECT rank() OVER w FROM t WINDOW w AS (ORDER BY x)
+/// becomes
+/// SELECT rank() OVER (ORDER BY x) FROM t
+pub fn resolve_named_windows(
+    columns: &mut [ast::ResultColumn],
+    order_by: Option<&mut Vec<ast::SortedColumn>>,
+    window_clause: Option<Vec<ast::WindowDef>>,
+) -> Result<()> {
+    // A window definition can only be based on the windows that are defined before it.
+    let mut named_windows: Vec<(String, ast::Window)> = vec![];
+    for def in window_clause.unwrap_or_default() {
+        let window = resolve_window_base(def.window, &named_windows)?;
+        named_windows.push((normalize_ident(def.name.0.as_str()), window));
+    }
+    let exprs = columns
+        .iter_mut()
+        .filter_map(|column| match column {
+            ast::ResultColumn::Expr(expr, _) => Some(expr),
+            _ => None,
+        })
+        .chain(order_by.into_iter().flatten().map(|c| &mut c.expr));
+    for expr in exprs {
+        resolve_named_windows_in_expr(expr, &named_windows)?;
+    }
+    Ok(())
+}
+
+fn resolve_named_windows_in_expr(
+    expr: &mut ast::Expr,
+    named_windows: &[(String, ast::Window)],
+) -> Result<()> {
+    if let ast::Expr::FunctionCall {
+        filter_over: Some(tail),
+        ..
+    }
+    | ast::Expr::FunctionCallStar {
+        filter_over: Some(tail),
+        ..
+    } = expr
+    {
+        if let Some(over) = tail.over_clause.as_mut() {
+            let window = match over.as_ref() {
+                ast::Over::Name(name) => lookup_named_window(name, named_windows)?.clone(),
+                ast::Over::Window(window) => resolve_window_base(window.clone(), named_windows)?,
+            };
+            **over = ast::Over::Window(window);
+        }
+    }
+    for child in child_exprs_mut(expr) {
+        resolve_named_windows_in_expr(child, named_windows)?;
+    }
+    Ok(())
+}
+
+fn lookup_named_window<'a>(
+    name: &ast::Name,
+    named_windows: &'a [(String, ast::Window)],
+) -> Result<&'a ast::Window> {
+    let normalized_name = normalize_ident(name.0.as_str());
+    match named_windows.iter().find(|(n, _)| *n == normalized_name) {
+        Some((_, window)) => Ok(window),
+        None => crate::bail_parse_error!("no such window: {}", name.0),
+    }
+}
+
+/// Merges a window that is based on a named window, e.g. OVER (w ORDER BY x), with that named window.
+/// The window can only add the clauses that the named window does not have.
+fn resolve_window_base(
+    window: ast::Window,
+    named_windows: &[(String, ast::Window)],
+) -> Result<ast::Window> {
+    let Some(base_name) = &window.base else {
+        return Ok(window);
+    };
+    let base = lookup_named_window(base_name, named_windows)?;
+    if window.partition_by.is_some() {
+        crate::bail_parse_error!(
+            "cannot override PARTITION clause of window: {}",
+            base_name.0
+        );
+    }
+    if window.order_by.is_some() && base.order_by.is_some() {
+        crate::bail_parse_error!("cannot override ORDER BY clause of window: {}", base_name.0);
+    }
+    if base.frame_clause.is_some() {
+        crate::bail_parse_error!(
+            "cannot override frame specification of window: {}",
+            base_name.0
+        );
+    }
+    Ok(ast::Window {
+        base: None,
+        partition_by: base.partition_by.clone(),
+        order_by: window.order_by.or_else(|| base.order_by.clone()),
+        frame_clause: window.frame_clause,
+    })
+}
+
+/// Plans a SELECT whose result columns or ORDER BY clause call window functions.
+///
+/// Window functions are evaluated after the WHERE, GROUP BY and HAVING clauses, and before the
+/// ORDER BY and LIMIT clauses. So the SELECT is split in two:
+/// - a subquery that evaluates everything but the window functions. Its result columns are the
+///   arguments, PARTITION BY terms and ORDER BY terms of the window functions, plus the parts of
+///   the result columns and ORDER BY terms that do not contain window functions.
+/// - a query that reads the rows of the subquery, evaluates the window functions, and then the
+///   result columns, ORDER BY and LIMIT of the original SELECT.
+///
+/// For example, SELECT a, sum(b) OVER (PARTITION BY c) + 1 FROM t WHERE d > 0
+/// becomes something like
+/// SELECT x0, sum(x1) OVER (PARTITION BY x2) + 1 FROM (SELECT a AS x0, b AS x1, c AS x2 FROM t WHERE d > 0)
+pub fn plan_windows(mut plan: SelectPlan, syms: &SymbolTable) -> Result<SelectPlan> {
+    let mut leaves = vec![];
+    let mut windows = vec![];
+
+    let mut result_columns = Vec::with_capacity(plan.result_columns.len());
+    for rc in plan.result_columns.iter() {
+        let mut expr = rc.expr.clone();
+        rewrite_expr(&mut expr, &mut leaves, &mut windows, syms)?;
+        result_columns.push(ResultSetColumn {
+            expr,
+            alias: Some(
+                rc.name(&plan.table_references)
+                    .map(String::from)
+                    .unwrap_or_else(|| rc.expr.to_string()),
+            ),
+            contains_aggregates: false,
+        });
+    }
+    let order_by = match plan.order_by.take() {
+        Some(order_by) => {
+            let mut key = Vec::with_capacity(order_by.len());
+            for (mut expr, direction) in order_by {
+                rewrite_expr(&mut expr, &mut leaves, &mut windows, syms)?;
+                key.push((expr, direction));
+            }
+            Some(key)
+        }
+        None => None,
+    };
+
+    // Like SQLite, the windows are evaluated in reverse order, so that without an ORDER BY clause
+    // the rows come out in the order of the first window.
+    windows.reverse();
+
+    // A subquery needs at least one result column.
+    if leaves.is_empty() {
+        leaves.push(ast::Expr::Literal(ast::Literal::Null));
+    }
+    plan.result_columns = leaves
+        .into_iter()
+        .map(|expr| ResultSetColumn {
+            contains_aggregates: resolve_aggregates(&expr, &mut plan.aggregates),
+            expr,
+            alias: None,
+        })
+        .collect();
+    let distinct = std::mem::take(&mut plan.distinct);
+    let limit = plan.limit.take();
+    let offset = plan.offset.take();
+    let query_type = std::mem::replace(
+        &mut plan.query_type,
+        SelectQueryType::Subquery {
+            yield_reg: usize::MAX, // will be set later in bytecode emission
+            coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
+        },
+    );
+    let num_leaves = plan.result_columns.len();
+    let mut table_reference =
+        TableReference::new_subquery("window_subquery".to_string(), plan, None);
+    for i in 0..num_leaves {
+        table_reference.mark_column_used(i);
+    }
+
+    Ok(SelectPlan {
+        table_references: vec![table_reference],
+        result_columns,
+        distinct,
+        where_clause: vec![],
+        group_by: None,
+        order_by,
+        aggregates: vec![],
+        limit,
+        offset,
+        contains_constant_false_condition: false,
+        query_type,
+        compounds: vec![],
+        windows,
+        recursive: None,
+        expr_subqueries: vec![],
+    })
+}
+
+/// Rewrites an expression of the original SELECT into an expression of the query that evaluates
+/// the window functions, see [plan_windows].
+fn rewrite_expr(
+    expr: &mut ast::Expr,
+    leaves: &mut Vec<ast::Expr>,
+    windows: &mut Vec<Window>,
+    syms: &SymbolTable,
+) -> Result<()> {
+    if is_window_function_call(expr) {
+        return plan_window_function(expr, leaves, windows, syms);
+    }
+    if !contains_window_function(expr) {
+        if !matches!(expr, ast::Expr::Literal(_) | ast::Expr::Variable(_)) {
+            *expr = leaf_column(expr, leaves);
+        }
+        return Ok(());
+    }
+    for child in child_exprs_mut(expr) {
+        rewrite_expr(child, leaves, windows, syms)?;
+    }
+    Ok(())
+}
+
+/// Returns a reference to the result column of the subquery that evaluates `expr`.
+fn leaf_column(expr: &ast::Expr, leaves: &mut Vec<ast::Expr>) -> ast::Expr {
+    let column = match leaves.iter().position(|e| exprs_are_equivalent(e, expr)) {
+        Some(column) => column,
+        None => {
+            leaves.push(expr.clone());
+            leaves.len() - 1
+        }
+    };
+    ast::Expr::Column {
+        database: None,
+        table: 0,
+        column,
+        is_rowid_alias: false,
+    }
+}
+
+/// Adds the window function call `expr` to the window it belongs to, creating the window if needed.
+fn plan_window_function(
+    expr: &mut ast::Expr,
+    leaves: &mut Vec<ast::Expr>,
+    windows: &mut Vec<Window>,
+    syms: &SymbolTable,
+) -> Result<()> {
+    let (name, distinctness, mut args, tail) = match expr {
+        ast::Expr::FunctionCall {
+            name,
+            distinctness,
+            args,
+            filter_over: Some(tail),
+            ..
+        } => (&name.0, distinctness.as_ref(), args.as_mut(), tail),
+        ast::Expr::FunctionCallStar {
+            name,
+            filter_over: Some(tail),
+        } => (&name.0, None, None, tail),
+        _ => unreachable!("not a window function call: {:?}", expr),
+    };
+    if matches!(distinctness, Some(ast::Distinctness::Distinct)) {
+        crate::bail_parse_error!("DISTINCT is not supported for window functions");
+    }
+    let Some(ast::Over::Window(window)) = tail.over_clause.as_deref_mut() else {
+        unreachable!("named windows are resolved before planning");
+    };
+
+    let arg_count = args.as_ref().map_or(0, |args| args.len());
+    let func = resolve_window_function(name, arg_count, syms)?;
+    let filter = match tail.filter_clause.as_deref() {
+        Some(_) if !matches!(func, WindowFunctionKind::Aggregate(_)) => {
+            crate::bail_parse_error!(
+                "FILTER clause may only be used with aggregate window functions"
+            );
+        }
+        Some(filter) => Some(leaf_column(filter, leaves)),
+        None => None,
+    };
+    if let Some(args) = args.as_mut() {
+        match func {
+            WindowFunctionKind::Builtin(WindowFunc::Ntile) => plan_ntile_argument(&mut args[0])?,
+            WindowFunctionKind::Builtin(WindowFunc::NthValue) => {
+                plan_nth_value_argument(&mut args[1])?
+            }
+            _ => {}
+        }
+        for arg in args.iter_mut() {
+            *arg = leaf_column(arg, leaves);
+        }
+    }
+    let frame = match func {
+        // These functions work on the peers of the current row, whatever the frame is.
+        WindowFunctionKind::Builtin(
+            WindowFunc::Rank | WindowFunc::PercentRank | WindowFunc::CumeDist,
+        ) => {
+            plan_frame(window)?;
+            WindowFrame {
+                mode: ast::FrameMode::Groups,
+                start: ast::FrameBound::CurrentRow,
+                end: ast::FrameBound::CurrentRow,
+                exclude: ast::FrameExclude::NoOthers,
+            }
+        }
+        _ => plan_frame(window)?,
+    };
+
+    let mut partition_by = vec![];
+    for expr in window.partition_by.iter_mut().flatten() {
+        *expr = leaf_column(expr, leaves);
+        partition_by.push(expr.clone());
+    }
+    let mut order_by = vec![];
+    for sorted_column in window.order_by.iter_mut().flatten() {
+        sorted_column.expr = leaf_column(&sorted_column.expr, leaves);
+        let direction = match sorted_column.order {
+            Some(ast::SortOrder::Desc) => Direction::Descending,
+            _ => Direction::Ascending,
+        };
+        order_by.push((sorted_column.expr.clone(), direction));
+    }
+
+    let function = WindowFunction {
+        func,
+        args: args.map(|args| args.clone()).unwrap_or_default(),
+        filter,
+        frame,
+        original_expr: expr.clone(),
+    };
+    let existing_window = windows.iter_mut().find(|w| {
+        w.partition_by.len() == partition_by.len()
+            && w.order_by.len() == order_by.len()
+            && w.partition_by
+                .iter()
+                .zip(partition_by.iter())
+                .all(|(a, b)| exprs_are_equivalent(a, b))
+            && w.order_by
+                .iter()
+                .zip(order_by.iter())
+                .all(|((a, dir_a), (b, dir_b))| dir_a == dir_b && exprs_are_equivalent(a, b))
+    });
+    match existing_window {
+        Some(window) => window.functions.push(function),
+        None => windows.push(Window {
+            partition_by,
+            order_by,
+            functions: vec![function],
+        }),
+    }
+    Ok(())
+}
+
+fn resolve_window_function(
+    name: &str,
+    arg_count: usize,
+    syms: &SymbolTable,
+) -> Result<WindowFunctionKind> {
+    let normalized_name = normalize_ident(name);
+    if let Some(func) = WindowFunc::resolve_function(&normalized_name, arg_count)? {
+        return Ok(WindowFunctionKind::Builtin(func));
+    }
+    match Func::resolve_function(&normalized_name, arg_count) {
+        Ok(Func::Agg(func)) => Ok(WindowFunctionKind::Aggregate(func)),
+        Ok(_) => crate::bail_parse_error!("{}() may not be used as a window function", name),
+        Err(e) => match syms.resolve_function(&normalized_name, arg_count) {
+            Some(f) => match f.func {
+                ExtFunc::Aggregate { .. } => Ok(WindowFunctionKind::Aggregate(AggFunc::External(
+                    f.func.clone().into(),
+                ))),
+                ExtFunc::Scalar(_) => {
+                    crate::bail_parse_error!("{}() may not be used as a window function", name)
+                }
+            },
+            None => Err(e),
+        },
+    }
+}
+
+/// Returns true if `expr` is a literal, possibly preceded by a sign.
+fn is_literal(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::Literal(_) => true,
+        ast::Expr::Unary(ast::UnaryOperator::Negative | ast::UnaryOperator::Positive, expr) => {
+            is_literal(expr)
+        }
+        _ => false,
+    }
+}
+
+/// Returns the value of a numeric literal, possibly preceded by a sign.
+fn numeric_literal(expr: &ast::Expr) -> Option<f64> {
+    match expr {
+        ast::Expr::Literal(ast::Literal::Numeric(n)) => n.parse::<f64>().ok(),
+        ast::Expr::Unary(ast::UnaryOperator::Negative, expr) => numeric_literal(expr).map(|v| -v),
+        ast::Expr::Unary(ast::UnaryOperator::Positive, expr) => numeric_literal(expr),
+        _ => None,
+    }
+}
+
+fn integer_literal(value: f64) -> ast::Expr {
+    ast::Expr::Literal(ast::Literal::Numeric((value as i64).to_string()))
+}
+
+/// The argument of ntile() must be a positive integer. A literal real argument is truncated.
+fn plan_ntile_argument(arg: &mut ast::Expr) -> Result<()> {
+    if !is_literal(arg) {
+        return Ok(());
+    }
+    match numeric_literal(arg) {
+        Some(value) if value >= 1.0 => {
+            *arg = integer_literal(value);
+            Ok(())
+        }
+        _ => crate::bail_parse_error!("argument of ntile must be a positive integer"),
+    }
+}
+
+/// The second argument of nth_value() must be a positive integer.
+fn plan_nth_value_argument(arg: &mut ast::Expr) -> Result<()> {
+    if !is_literal(arg) {
+        return Ok(());
+    }
+    match numeric_literal(arg) {
+        Some(value) if value >= 1.0 && value.fract() == 0.0 => {
+            *arg = integer_literal(value);
+            Ok(())
+        }
+        _ => crate::bail_parse_error!("second argument to nth_value must be a positive integer"),
+    }
+}
+
+fn plan_frame(window: &mut ast::Window) -> Result<WindowFrame> {
+    let Some(frame_clause) = window.frame_clause.as_mut() else {
+        return Ok(WindowFrame::default());
+    };
+    let mode = frame_clause.mode;
+    let start = frame_clause.start.clone();
+    let end = frame_clause
+        .end
+        .clone()
+        .unwrap_or(ast::FrameBound::CurrentRow);
+    match (&start, &end) {
+        (ast::FrameBound::UnboundedFollowing, _)
+        | (_, ast::FrameBound::UnboundedPreceding)
+        | (ast::FrameBound::CurrentRow, ast::FrameBound::Preceding(_))
+        | (
+            ast::FrameBound::Following(_),
+            ast::FrameBound::Preceding(_) | ast::FrameBound::CurrentRow,
+        ) => crate::bail_parse_error!("unsupported frame specification"),
+        _ => {}
+    }
+    let has_offset = |bound: &ast::FrameBound| {
+        matches!(
+            bound,
+            ast::FrameBound::Preceding(_) | ast::FrameBound::Following(_)
+        )
+    };
+    if mode == ast::FrameMode::Range
+        && (has_offset(&start) || has_offset(&end))
+        && window.order_by.as_ref().map_or(0, |o| o.len()) != 1
+    {
+        crate::bail_parse_error!(
+            "RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression"
+        );
+    }
+    Ok(WindowFrame {
+        mode,
+        start: plan_frame_offset(start, mode, "starting")?,
+        end: plan_frame_offset(end, mode, "ending")?,
+        exclude: frame_clause
+            .exclude
+            .clone()
+            .unwrap_or(ast::FrameExclude::NoOthers),
+    })
+}
+
+/// The offset of a ROWS or GROUPS frame bound must be a non-negative integer,
+/// and the offset of a RANGE frame bound must be a non-negative number.
+fn plan_frame_offset(
+    bound: ast::FrameBound,
+    mode: ast::FrameMode,
+    which: &str,
+) -> Result<ast::FrameBound> {
+    let (ast::FrameBound::Preceding(offset) | ast::FrameBound::Following(offset)) = &bound else {
+        return Ok(bound);
+    };
+    if !is_literal(offset) {
+        return Ok(bound);
+    }
+    let value = numeric_literal(offset);
+    if mode == ast::FrameMode::Range {
+        if !value.is_some_and(|v| v >= 0.0) {
+            crate::bail_parse_error!("frame {} offset must be a non-negative number", which);
+        }
+        return Ok(bound);
+    }
+    let Some(value) = value.filter(|v| *v >= 0.0 && v.fract() == 0.0) else {
+        crate::bail_parse_error!("frame {} offset must be a non-negative integer", which);
+    };
+    Ok(match bound {
+        ast::FrameBound::Preceding(_) => {
+            ast::FrameBound::Preceding(Box::new(integer_literal(value)))
+        }
+        _ => ast::FrameBound::Following(Box::new(integer_literal(value))),
+    })
+}
+
+// Metadata for evaluating the window functions of a window
+#[derive(Debug, Clone, Copy)]
+pub struct WindowMetadata {
+    // cursor id of the sorter that sorts the rows by the PARTITION BY and ORDER BY terms of the window
+    pub sort_cursor: usize,
+    // register where the sorter data is inserted and later retrieved from
+    pub reg_sorter_data: usize,
+    // first register of the results of the window functions of the window, for the current row
+    pub reg_results_start: usize,
+}
+
+/// Initialize resources needed for evaluating window functions
+pub fn init_window(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    windows: &[Window],
+    tables: &[TableReference],
+) -> Result<()> {
+    for window in windows {
+        let sort_cursor = program.alloc_cursor_id(None, CursorType::Sorter);
+        let order = window
+            .partition_by
+            .iter()
+            .map(|_| OwnedValue::Integer(Direction::Ascending as i64))
+            .chain(
+                window
+                    .order_by
+                    .iter()
+                    .map(|(_, direction)| OwnedValue::Integer(*direction as i64)),
+            )
+            .collect();
+        program.emit_insn(Insn::SorterOpen {
+            cursor_id: sort_cursor,
+            columns: window.partition_by.len() + window.order_by.len(),
+            order: Record::new(order),
+            collations: sort_key_collations(window, tables, t_ctx.resolver.symbol_table)?,
+        });
+        t_ctx.meta_windows.push(WindowMetadata {
+            sort_cursor,
+            reg_sorter_data: program.alloc_register(),
+            reg_results_start: program.alloc_registers(window.functions.len()),
+        });
+    }
+    Ok(())
+}
+
+/// The collating sequences of the PARTITION BY terms of a window, followed by those of its ORDER BY
+/// terms.
+fn sort_key_collations(
+    window: &Window,
+    tables: &[TableReference],
+    syms: &SymbolTable,
+) -> Result<Vec<CollationSeq>> {
+    window
+        .partition_by
+        .iter()
+        .chain(window.order_by.iter().map(|(expr, _)| expr))
+        .map(|expr| expr_collation(expr, Some(tables), syms))
+        .collect()
+}
+
+/// Returns the first register and the number of the result columns of the subquery that a plan
+/// with windows reads from.
+fn source_columns(plan: &SelectPlan) -> (usize, usize) {
+    let Operation::Subquery {
+        plan: subquery,
+        result_columns_start_reg,
+    } = &plan.table_references[0].op
+    else {
+        unreachable!("a plan with windows reads from a subquery");
+    };
+    (*result_columns_start_reg, subquery.result_columns.len())
+}
+
+/// Returns the registers that hold the results of the window functions of the windows that are
+/// evaluated before the window at `window_idx`.
+fn previous_results(t_ctx: &TranslateCtx, plan: &SelectPlan, window_idx: usize) -> Vec<usize> {
+    plan.windows[..window_idx]
+        .iter()
+        .zip(t_ctx.meta_windows.iter())
+        .flat_map(|(window, meta)| {
+            (0..window.functions.len()).map(move |i| meta.reg_results_start + i)
+        })
+        .collect()
+}
+
+/// Emits the bytecode for inserting the current row into the sorter of the window at `window_idx`.
+/// The sorter has the PARTITION BY and ORDER BY terms of the window first, then the result
+/// columns of the subquery, then the results of the windows that were evaluated before.
+pub fn window_sorter_insert(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    plan: &SelectPlan,
+    window_idx: usize,
+) -> Result<()> {
+    let window = &plan.windows[window_idx];
+    let (reg_source_start, num_source_columns) = source_columns(plan);
+    let previous_results = previous_results(t_ctx, plan, window_idx);
+    let num_keys = window.partition_by.len() + window.order_by.len();
+    let column_count = num_keys + num_source_columns + previous_results.len();
+
+    let start_reg = program.alloc_registers(column_count);
+    let keys = window
+        .partition_by
+        .iter()
+        .chain(window.order_by.iter().map(|(expr, _)| expr));
+    for (i, expr) in keys.enumerate() {
+        translate_expr(
+            program,
+            Some(&plan.table_references),
+            expr,
+            start_reg + i,
+            &t_ctx.resolver,
+        )?;
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_source_start,
+        dst_reg: start_reg + num_keys,
+        amount: num_source_columns - 1,
+    });
+    for (i, reg) in previous_results.iter().enumerate() {
+        program.emit_insn(Insn::Copy {
+            src_reg: *reg,
+            dst_reg: start_reg + num_keys + num_source_columns + i,
+            amount: 0,
+        });
+    }
+
+    let WindowMetadata {
+        sort_cursor,
+        reg_sorter_data,
+        ..
+    } = t_ctx.meta_windows[window_idx];
+    sorter_insert(
+        program,
+        start_reg,
+        column_count,
+        sort_cursor,
+        reg_sorter_data,
+    );
+    Ok(())
+}
+
+/// Emits the bytecode for evaluating the window functions.
+/// This is called when the main query execution loop has finished processing and all rows are
+/// in the sorter of the first window.
+///
+/// The windows are evaluated one after another. Each window sorts the rows by its PARTITION BY
+/// and ORDER BY terms, evaluates its window functions for every row, and sends the row to the
+/// sorter of the next window. After the last window, the result columns are emitted, or the row
+/// is sent to the ORDER BY sorter.
+pub fn emit_windows<'a>(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx<'a>,
+    plan: &'a SelectPlan,
+) -> Result<()> {
+    // The window function calls in the result columns and ORDER BY terms are replaced by their results.
+    for (window, meta) in plan.windows.iter().zip(t_ctx.meta_windows.clone()) {
+        for (i, function) in window.functions.iter().enumerate() {
+            t_ctx
+                .resolver
+                .expr_to_reg_cache
+                .push((&function.original_expr, meta.reg_results_start + i));
+        }
+    }
+
+    let label_end = program.allocate_label();
+    for window_idx in 0..plan.windows.len() {
+        emit_window(program, t_ctx, plan, window_idx, label_end)?;
+    }
+    program.resolve_label(label_end, program.offset());
+    Ok(())
+}
+
+/// The registers and cursors used for evaluating the window functions of a window for the rows
+/// of a partition.
+///
+/// The rows of the current partition are kept in an ephemeral index, so that the window
+/// functions can read any row of the partition. Its columns are:
+/// - the position of the row in the partition, starting at 1. This is the key used to look up rows.
+/// - the peer group of the row, starting at 1. Rows with equal ORDER BY terms are peers.
+/// - the ORDER BY terms of the window
+/// - the result columns of the subquery
+/// - the results of the windows that were evaluated before
+/// - a constant 0, because index keys must end with an integer rowid
+struct Partition {
+    cursor_id: CursorID,
+    num_order_by: usize,
+    // number of rows in the partition
+    reg_count: usize,
+    // position of the current row
+    reg_row: usize,
+    // peer group of the current row
+    reg_row_group: usize,
+    // value of the first ORDER BY term of the current row
+    reg_row_order_value: usize,
+    // constant 1
+    reg_one: usize,
+}
+
+impl Partition {
+    const GROUP_COLUMN: usize = 1;
+    const ORDER_BY_START_COLUMN: usize = 2;
+
+    fn source_column(&self, column: usize) -> usize {
+        Self::ORDER_BY_START_COLUMN + self.num_order_by + column
+    }
+
+    /// Returns the column that holds the value of `arg` (a result column of the subquery).
+    fn arg_column(&self, arg: &ast::Expr) -> usize {
+        match arg {
+            ast::Expr::Column { column, .. } => self.source_column(*column),
+            _ => unreachable!("window function arguments are subquery columns: {:?}", arg),
+        }
+    }
+
+    /// Positions the cursor on the row whose position is in `reg_position`.
+    fn emit_seek(&self, program: &mut ProgramBuilder, reg_position: usize) {
+        let label_seek_done = program.allocate_label();
+        program.emit_insn(Insn::SeekGE {
+            is_index: true,
+            cursor_id: self.cursor_id,
+            start_reg: reg_position,
+            num_regs: 1,
+            target_pc: label_seek_done,
+        });
+        program.resolve_label(label_seek_done, program.offset());
+    }
+
+    fn emit_read_column(
+        &self,
+        program: &mut ProgramBuilder,
+        reg_position: usize,
+        column: usize,
+        dest: usize,
+    ) {
+        self.emit_seek(program, reg_position);
+        program.emit_insn(Insn::Column {
+            cursor_id: self.cursor_id,
+            column,
+            dest,
+        });
+    }
+}
+
+/// Open an ephemeral index that holds the rows of a partition.
+fn emit_open_partition_index(program: &mut ProgramBuilder, num_cols: usize) -> CursorID {
+    let index = Arc::new(Index {
+        name: "ephemeral_window".to_string(),
+        table_name: String::new(),
+        root_page: 0,
+        columns: (0..num_cols)
+            .map(|i| IndexColumn {
+                name: format!("c{}", i),
+                order: SortOrder::Asc,
+                pos_in_table: i,
+                collation: None,
+                expr: None,
+            })
+            .collect(),
+        unique: false,
+        ephemeral: true,
+        where_clause: None,
+        key_suffix: Vec::new(),
+    });
+    let cursor_id = program.alloc_cursor_id(None, CursorType::BTreeIndex(index));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id,
+        is_table: false,
+    });
+    cursor_id
+}
+
+/// Emits the bytecode for evaluating the window functions of the window at `window_idx`.
+/// The rows are read from the sorter of the window and collected one partition at a time.
+/// When a partition is complete, the window functions are evaluated for each of its rows.
+fn emit_window<'a>(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx<'a>,
+    plan: &'a SelectPlan,
+    window_idx: usize,
+    label_end: BranchOffset,
+) -> Result<()> {
+    let window = &plan.windows[window_idx];
+    let (_, num_source_columns) = source_columns(plan);
+    let num_partition_by = window.partition_by.len();
+    let num_order_by = window.order_by.len();
+    let num_payload_columns = num_source_columns + previous_results(t_ctx, plan, window_idx).len();
+    let num_sorter_columns = num_partition_by + num_order_by + num_payload_columns;
+    let mut partition_by_collations =
+        sort_key_collations(window, &plan.table_references, t_ctx.resolver.symbol_table)?;
+    let order_by_collations = partition_by_collations.split_off(num_partition_by);
+    let WindowMetadata {
+        sort_cursor,
+        reg_sorter_data,
+        ..
+    } = t_ctx.meta_windows[window_idx];
+
+    let pseudo_columns = (0..num_sorter_columns)
+        .map(|_| {
+            let ty = crate::schema::Type::Null;
+            Column {
+                name: None,
+                primary_key: false,
+                ty,
+                ty_str: ty.to_string().to_uppercase(),
+                is_rowid_alias: false,
+                notnull: false,
+                default: None,
+                collation: None,
+            }
+        })
+        .collect();
+    let pseudo_cursor = program.alloc_cursor_id(
+        None,
+        CursorType::Pseudo(Rc::new(PseudoTable {
+            columns: pseudo_columns,
+        })),
+    );
+    let num_partition_columns =
+        Partition::ORDER_BY_START_COLUMN + num_order_by + num_payload_columns + 1;
+    let partition = Partition {
+        cursor_id: emit_open_partition_index(program, num_partition_columns),
+        num_order_by,
+        reg_count: program.alloc_register(),
+        reg_row: program.alloc_register(),
+        reg_row_group: program.alloc_register(),
+        reg_row_order_value: program.alloc_register(),
+        reg_one: program.alloc_register(),
+    };
+    program.emit_insn(Insn::Integer {
+        value: 1,
+        dest: partition.reg_one,
+    });
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: partition.reg_count,
+    });
+    // peer group of the last row added to the partition
+    let reg_group = program.alloc_register();
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: reg_group,
+    });
+    // The keys of the previous row are compared with the keys of the current row, so they must
+    // be in lower registers.
+    let reg_prev_partition_by = program.alloc_registers(num_partition_by);
+    let reg_partition_by = program.alloc_registers(num_partition_by);
+    let reg_prev_order_by = program.alloc_registers(num_order_by);
+    let reg_partition_row = program.alloc_registers(num_partition_columns);
+    let reg_partition_record = program.alloc_register();
+    let reg_subroutine_return = program.alloc_register();
+
+    let label_sort_loop_start = program.allocate_label();
+    let label_partition_subroutine = program.allocate_label();
+    let label_window_end = program.allocate_label();
+
+    program.emit_insn(Insn::OpenPseudo {
+        cursor_id: pseudo_cursor,
+        content_reg: reg_sorter_data,
+        num_fields: num_sorter_columns,
+    });
+    program.emit_insn(Insn::SorterSort {
+        cursor_id: sort_cursor,
+        pc_if_empty: label_window_end,
+    });
+    program.resolve_label(label_sort_loop_start, program.offset());
+    program.emit_insn(Insn::SorterData {
+        cursor_id: sort_cursor,
+        dest_reg: reg_sorter_data,
+        pseudo_cursor,
+    });
+
+    // If the PARTITION BY terms changed, the previous partition is complete.
+    if num_partition_by > 0 {
+        for i in 0..num_partition_by {
+            program.emit_insn(Insn::Column {
+                cursor_id: pseudo_cursor,
+                column: i,
+                dest: reg_partition_by + i,
+            });
+        }
+        let label_partition_complete = program.allocate_label();
+        let label_same_partition = program.allocate_label();
+        program.emit_insn(Insn::IfNot {
+            reg: partition.reg_count,
+            target_pc: label_same_partition,
+            jump_if_null: false,
+        });
+        program.emit_insn(Insn::Compare {
+            start_reg_a: reg_prev_partition_by,
+            start_reg_b: reg_partition_by,
+            count: num_partition_by,
+            collations: partition_by_collations,
+        });
+        program.emit_insn(Insn::Jump {
+            target_pc_lt: label_partition_complete,
+            target_pc_eq: label_same_partition,
+            target_pc_gt: label_partition_complete,
+        });
+        program.resolve_label(label_partition_complete, program.offset());
+        program.emit_insn(Insn::Gosub {
+            target_pc: label_partition_subroutine,
+            return_reg: reg_subroutine_return,
+        });
+        program.resolve_label(label_same_partition, program.offset());
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_partition_by,
+            dst_reg: reg_prev_partition_by,
+            amount: num_partition_by - 1,
+        });
+    }
+
+    // If the ORDER BY terms changed, the row starts a new peer group.
+    let reg_order_by = reg_partition_row + Partition::ORDER_BY_START_COLUMN;
+    if num_order_by > 0 {
+        for i in 0..num_order_by {
+            program.emit_insn(Insn::Column {
+                cursor_id: pseudo_cursor,
+                column: num_partition_by + i,
+                dest: reg_order_by + i,
+            });
+        }
+        let label_new_group = program.allocate_label();
+        let label_same_group = program.allocate_label();
+        program.emit_insn(Insn::IfNot {
+            reg: partition.reg_count,
+            target_pc: label_new_group,
+            jump_if_null: false,
+        });
+        program.emit_insn(Insn::Compare {
+            start_reg_a: reg_prev_order_by,
+            start_reg_b: reg_order_by,
+            count: num_order_by,
+            collations: order_by_collations,
+        });
+        program.emit_insn(Insn::Jump {
+            target_pc_lt: label_new_group,
+            target_pc_eq: label_same_group,
+            target_pc_gt: label_new_group,
+        });
+        program.resolve_label(label_new_group, program.offset());
+        program.emit_insn(Insn::Add {
+            lhs: reg_group,
+            rhs: partition.reg_one,
+            dest: reg_group,
+        });
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_order_by,
+            dst_reg: reg_prev_order_by,
+            amount: num_order_by - 1,
+        });
+        program.resolve_label(label_same_group, program.offset());
+    } else {
+        // Without ORDER BY, all rows of the partition are peers.
+        program.emit_insn(Insn::Integer {
+            value: 1,
+            dest: reg_group,
+        });
+    }
+
+    // Add the row to the partition.
+    program.emit_insn(Insn::Add {
+        lhs: partition.reg_count,
+        rhs: partition.reg_one,
+        dest: partition.reg_count,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: partition.reg_count,
+        dst_reg: reg_partition_row,
+        amount: 0,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_group,
+        dst_reg: reg_partition_row + Partition::GROUP_COLUMN,
+        amount: 0,
+    });
+    for i in 0..num_payload_columns {
+        program.emit_insn(Insn::Column {
+            cursor_id: pseudo_cursor,
+            column: num_partition_by + num_order_by + i,
+            dest: reg_partition_row + partition.source_column(i),
+        });
+    }
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: reg_partition_row + num_partition_columns - 1,
+    });
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: reg_partition_row,
+        count: num_partition_columns,
+        dest_reg: reg_partition_record,
+    });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id: partition.cursor_id,
+        record_reg: reg_partition_record,
+        unpacked_start: Some(reg_partition_row),
+        unpacked_count: Some(num_partition_columns as u16),
+        flags: IdxInsertFlags::new().use_seek(false),
+    });
+    program.emit_insn(Insn::SorterNext {
+        cursor_id: sort_cursor,
+        pc_if_next: label_sort_loop_start,
+    });
+
+    // The last partition is complete when the sorter is exhausted.
+    program.emit_insn(Insn::Gosub {
+        target_pc: label_partition_subroutine,
+        return_reg: reg_subroutine_return,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_window_end,
+    });
+
+    program.resolve_label(label_partition_subroutine, program.offset());
+    emit_partition(program, t_ctx, plan, window_idx, &partition, label_end)?;
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: partition.reg_count,
+    });
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: reg_group,
+    });
+    // Opening the ephemeral index again empties it for the next partition.
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id: partition.cursor_id,
+        is_table: false,
+    });
+    program.emit_insn(Insn::Return {
+        return_reg: reg_subroutine_return,
+    });
+
+    program.resolve_label(label_window_end, program.offset());
+    Ok(())
+}
+
+/// Registers that track the frame of a window function while the rows of a partition are evaluated.
+/// The first and last rows of the frame only move forward from one row to the next.
+struct FrameRegisters {
+    // position of the first row of the frame
+    reg_start: usize,
+    // position of the last row of the frame. The frame is empty if this is less than reg_start.
+    reg_end: usize,
+    reg_start_offset: usize,
+    reg_end_offset: usize,
+    // accumulator of an aggregate that is computed incrementally, i.e. whose frame only grows
+    reg_acc: usize,
+    // position of the last row added to reg_acc
+    reg_added: usize,
+}
+
+/// Emits the bytecode for evaluating the window functions of the window at `window_idx` for all
+/// rows of the current partition, and sending the rows to their destination.
+fn emit_partition<'a>(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx<'a>,
+    plan: &'a SelectPlan,
+    window_idx: usize,
+    partition: &Partition,
+    label_end: BranchOffset,
+) -> Result<()> {
+    let window = &plan.windows[window_idx];
+    let (reg_source_start, num_source_columns) = source_columns(plan);
+    let previous_results = previous_results(t_ctx, plan, window_idx);
+    let reg_results_start = t_ctx.meta_windows[window_idx].reg_results_start;
+
+    let mut frames = Vec::with_capacity(window.functions.len());
+    for function in window.functions.iter() {
+        let frame = FrameRegisters {
+            reg_start: program.alloc_register(),
+            reg_end: program.alloc_register(),
+            reg_start_offset: program.alloc_register(),
+            reg_end_offset: program.alloc_register(),
+            reg_acc: program.alloc_register(),
+            reg_added: program.alloc_register(),
+        };
+        program.emit_insn(Insn::Integer {
+            value: 1,
+            dest: frame.reg_start,
+        });
+        program.emit_insn(Insn::Integer {
+            value: 0,
+            dest: frame.reg_end,
+        });
+        program.emit_insn(Insn::Null {
+            dest: frame.reg_acc,
+            dest_end: None,
+        });
+        program.emit_insn(Insn::Integer {
+            value: 0,
+            dest: frame.reg_added,
+        });
+        for (bound, reg) in [
+            (&function.frame.start, frame.reg_start_offset),
+            (&function.frame.end, frame.reg_end_offset),
+        ] {
+            if let ast::FrameBound::Preceding(offset) | ast::FrameBound::Following(offset) = bound {
+                translate_expr(
+                    program,
+                    Some(&plan.table_references),
+                    offset,
+                    reg,
+                    &t_ctx.resolver,
+                )?;
+            }
+        }
+        frames.push(frame);
+    }
+
+    let label_next_row = program.allocate_label();
+    let label_partition_end = program.allocate_label();
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: partition.reg_row,
+    });
+    program.resolve_label(label_next_row, program.offset());
+    program.emit_insn(Insn::Add {
+        lhs: partition.reg_row,
+        rhs: partition.reg_one,
+        dest: partition.reg_row,
+    });
+    program.emit_insn(Insn::Gt {
+        lhs: partition.reg_row,
+        rhs: partition.reg_count,
+        target_pc: label_partition_end,
+        flags: CmpInsFlags::default(),
+    });
+
+    // Load the current row. The result columns of the subquery are loaded into the registers they
+    // were read from in the main loop, so that the expressions referring to them can be translated as usual.
+    partition.emit_seek(program, partition.reg_row);
+    program.emit_insn(Insn::Column {
+        cursor_id: partition.cursor_id,
+        column: Partition::GROUP_COLUMN,
+        dest: partition.reg_row_group,
+    });
+    if partition.num_order_by > 0 {
+        program.emit_insn(Insn::Column {
+            cursor_id: partition.cursor_id,
+            column: Partition::ORDER_BY_START_COLUMN,
+            dest: partition.reg_row_order_value,
+        });
+    }
+    for i in 0..num_source_columns {
+        program.emit_insn(Insn::Column {
+            cursor_id: partition.cursor_id,
+            column: partition.source_column(i),
+            dest: reg_source_start + i,
+        });
+    }
+    for (i, reg) in previous_results.iter().enumerate() {
+        program.emit_insn(Insn::Column {
+            cursor_id: partition.cursor_id,
+            column: partition.source_column(num_source_columns + i),
+            dest: *reg,
+        });
+    }
+
+    for (i, (function, frame)) in window.functions.iter().zip(frames.iter()).enumerate() {
+        let order_direction = window.order_by.first().map(|(_, direction)| *direction);
+        emit_window_function(
+            program,
+            &t_ctx.resolver,
+            plan,
+            partition,
+            function,
+            frame,
+            order_direction,
+            reg_results_start + i,
+        )?;
+    }
+
+    if window_idx + 1 < plan.windows.len() {
+        window_sorter_insert(program, t_ctx, plan, window_idx + 1)?;
+    } else if plan.order_by.is_some() {
+        order_by_sorter_insert(program, t_ctx, plan)?;
+    } else {
+        emit_select_result(program, t_ctx, plan, Some(label_end), Some(label_next_row))?;
+    }
+    program.emit_insn(Insn::Goto {
+        target_pc: label_next_row,
+    });
+    program.resolve_label(label_partition_end, program.offset());
+    Ok(())
+}
+
+/// Emits the bytecode for evaluating a window function for the current row.
+#[allow(clippy::too_many_arguments)]
+fn emit_window_function(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    plan: &SelectPlan,
+    partition: &Partition,
+    function: &WindowFunction,
+    frame: &FrameRegisters,
+    order_direction: Option<Direction>,
+    reg_result: usize,
+) -> Result<()> {
+    let func = match &function.func {
+        WindowFunctionKind::Builtin(func) => func,
+        WindowFunctionKind::Aggregate(func) => {
+            emit_frame(program, partition, &function.frame, frame, order_direction);
+            return emit_window_aggregate(
+                program, resolver, plan, partition, function, func, frame, reg_result,
+            );
+        }
+    };
+    let label_done = program.allocate_label();
+    match func {
+        WindowFunc::RowNumber => {
+            program.emit_insn(Insn::Copy {
+                src_reg: partition.reg_row,
+                dst_reg: reg_result,
+                amount: 0,
+            });
+        }
+        WindowFunc::DenseRank => {
+            program.emit_insn(Insn::Copy {
+                src_reg: partition.reg_row_group,
+                dst_reg: reg_result,
+                amount: 0,
+            });
+        }
+        // The frame of these functions is the peer group of the current row.
+        WindowFunc::Rank => {
+            emit_frame(program, partition, &function.frame, frame, order_direction);
+            program.emit_insn(Insn::Copy {
+                src_reg: frame.reg_start,
+                dst_reg: reg_result,
+                amount: 0,
+            });
+        }
+        WindowFunc::PercentRank => {
+            // (rank - 1) / (partition rows - 1), or 0.0 if the partition has a single row
+            emit_frame(program, partition, &function.frame, frame, order_direction);
+            program.emit_insn(Insn::Real {
+                value: 0.0,
+                dest: reg_result,
+            });
+            program.emit_insn(Insn::Le {
+                lhs: partition.reg_count,
+                rhs: partition.reg_one,
+                target_pc: label_done,
+                flags: CmpInsFlags::default(),
+            });
+            let reg_rank = program.alloc_register();
+            let reg_rows = program.alloc_register();
+            program.emit_insn(Insn::Subtract {
+                lhs: frame.reg_start,
+                rhs: partition.reg_one,
+                dest: reg_rank,
+            });
+            emit_to_real(program, reg_rank);
+            program.emit_insn(Insn::Subtract {
+                lhs: partition.reg_count,
+                rhs: partition.reg_one,
+                dest: reg_rows,
+            });
+            program.emit_insn(Insn::Divide {
+                lhs: reg_rank,
+                rhs: reg_rows,
+                dest: reg_result,
+            });
+        }
+        WindowFunc::CumeDist => {
+            // (last peer position) / (partition rows)
+            emit_frame(program, partition, &function.frame, frame, order_direction);
+            let reg_end = program.alloc_register();
+            program.emit_insn(Insn::Copy {
+                src_reg: frame.reg_end,
+                dst_reg: reg_end,
+                amount: 0,
+            });
+            emit_to_real(program, reg_end);
+            program.emit_insn(Insn::Divide {
+                lhs: reg_end,
+                rhs: partition.reg_count,
+                dest: reg_result,
+            });
+        }
+        WindowFunc::Ntile => {
+            // The first (rows % n) buckets have (rows / n + 1) rows, the others (rows / n) rows.
+            let reg_buckets = program.alloc_register();
+            translate_expr(
+                program,
+                Some(&plan.table_references),
+                &function.args[0],
+                reg_buckets,
+                resolver,
+            )?;
+            program.emit_insn(Insn::MustBeInt {
+                reg: reg_buckets,
+                target_pc: None,
+            });
+            let reg_index = program.alloc_register();
+            let reg_size = program.alloc_register();
+            let reg_big_size = program.alloc_register();
+            let reg_num_big = program.alloc_register();
+            let reg_big_rows = program.alloc_register();
+            program.emit_insn(Insn::Subtract {
+                lhs: partition.reg_row,
+                rhs: partition.reg_one,
+                dest: reg_index,
+            });
+            program.emit_insn(Insn::Divide {
+                lhs: partition.reg_count,
+                rhs: reg_buckets,
+                dest: reg_size,
+            });
+            program.emit_insn(Insn::Add {
+                lhs: reg_size,
+                rhs: partition.reg_one,
+                dest: reg_big_size,
+            });
+            program.emit_insn(Insn::Remainder {
+                lhs: partition.reg_count,
+                rhs: reg_buckets,
+                dest: reg_num_big,
+            });
+            program.emit_insn(Insn::Multiply {
+                lhs: reg_num_big,
+                rhs: reg_big_size,
+                dest: reg_big_rows,
+            });
+            let label_small_bucket = program.allocate_label();
+            program.emit_insn(Insn::Ge {
+                lhs: reg_index,
+                rhs: reg_big_rows,
+                target_pc: label_small_bucket,
+                flags: CmpInsFlags::default(),
+            });
+            program.emit_insn(Insn::Divide {
+                lhs: reg_index,
+                rhs: reg_big_size,
+                dest: reg_result,
+            });
+            program.emit_insn(Insn::Add {
+                lhs: reg_result,
+                rhs: partition.reg_one,
+                dest: reg_result,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: label_done,
+            });
+            program.resolve_label(label_small_bucket, program.offset());
+            program.emit_insn(Insn::Subtract {
+                lhs: reg_index,
+                rhs: reg_big_rows,
+                dest: reg_index,
+            });
+            program.emit_insn(Insn::Divide {
+                lhs: reg_index,
+                rhs: reg_size,
+                dest: reg_index,
+            });
+            program.emit_insn(Insn::Add {
+                lhs: reg_index,
+                rhs: reg_num_big,
+                dest: reg_index,
+            });
+            program.emit_insn(Insn::Add {
+                lhs: reg_index,
+                rhs: partition.reg_one,
+                dest: reg_result,
+            });
+        }
+        WindowFunc::Lag | WindowFunc::Lead => {
+            let reg_offset = program.alloc_register();
+            match function.args.get(1) {
+                Some(offset) => {
+                    translate_expr(
+                        program,
+                        Some(&plan.table_references),
+                        offset,
+                        reg_offset,
+                        resolver,
+                    )?;
+                }
+                None => {
+                    program.emit_insn(Insn::Integer {
+                        value: 1,
+                        dest: reg_offset,
+                    });
+                }
+            }
+            let reg_target = program.alloc_register();
+            if *func == WindowFunc::Lag {
+                program.emit_insn(Insn::Subtract {
+                    lhs: partition.reg_row,
+                    rhs: reg_offset,
+                    dest: reg_target,
+                });
+            } else {
+                program.emit_insn(Insn::Add {
+                    lhs: partition.reg_row,
+                    rhs: reg_offset,
+                    dest: reg_target,
+                });
+            }
+            match function.args.get(2) {
+                Some(default) => {
+                    translate_expr(
+                        program,
+                        Some(&plan.table_references),
+                        default,
+                        reg_result,
+                        resolver,
+                    )?;
+                }
+                None => {
+                    program.emit_insn(Insn::Null {
+                        dest: reg_result,
+                        dest_end: None,
+                    });
+                }
+            }
+            // The default is used if the target row is outside the partition.
+            program.emit_insn(Insn::Lt {
+                lhs: reg_target,
+                rhs: partition.reg_one,
+                target_pc: label_done,
+                flags: CmpInsFlags::default().jump_if_null(),
+            });
+            program.emit_insn(Insn::Gt {
+                lhs: reg_target,
+                rhs: partition.reg_count,
+                target_pc: label_done,
+                flags: CmpInsFlags::default().jump_if_null(),
+            });
+            partition.emit_read_column(
+                program,
+                reg_target,
+                partition.arg_column(&function.args[0]),
+                reg_result,
+            );
+        }
+        WindowFunc::FirstValue | WindowFunc::LastValue | WindowFunc::NthValue => {
+            emit_frame(program, partition, &function.frame, frame, order_direction);
+            program.emit_insn(Insn::Null {
+                dest: reg_result,
+                dest_end: None,
+            });
+            let value_column = partition.arg_column(&function.args[0]);
+            let reg_n = program.alloc_register();
+            if *func == WindowFunc::NthValue {
+                translate_expr(
+                    program,
+                    Some(&plan.table_references),
+                    &function.args[1],
+                    reg_n,
+                    resolver,
+                )?;
+            }
+            if function.frame.exclude == ast::FrameExclude::NoOthers {
+                let reg_target = match func {
+                    WindowFunc::FirstValue => frame.reg_start,
+                    WindowFunc::LastValue => frame.reg_end,
+                    _ => {
+                        let reg_target = program.alloc_register();
+                        program.emit_insn(Insn::Add {
+                            lhs: frame.reg_start,
+                            rhs: reg_n,
+                            dest: reg_target,
+                        });
+                        program.emit_insn(Insn::Subtract {
+                            lhs: reg_target,
+                            rhs: partition.reg_one,
+                            dest: reg_target,
+                        });
+                        reg_target
+                    }
+                };
+                program.emit_insn(Insn::Lt {
+                    lhs: reg_target,
+                    rhs: frame.reg_start,
+                    target_pc: label_done,
+                    flags: CmpInsFlags::default().jump_if_null(),
+                });
+                program.emit_insn(Insn::Gt {
+                    lhs: reg_target,
+                    rhs: frame.reg_end,
+                    target_pc: label_done,
+                    flags: CmpInsFlags::default().jump_if_null(),
+                });
+                partition.emit_read_column(program, reg_target, value_column, reg_result);
+            } else {
+                // Scan the frame, skipping the excluded rows.
+                let reg_position = program.alloc_register();
+                let reg_seen = program.alloc_register();
+                let label_scan_next = program.allocate_label();
+                program.emit_insn(Insn::Subtract {
+                    lhs: frame.reg_start,
+                    rhs: partition.reg_one,
+                    dest: reg_position,
+                });
+                program.emit_insn(Insn::Integer {
+                    value: 0,
+                    dest: reg_seen,
+                });
+                program.resolve_label(label_scan_next, program.offset());
+                program.emit_insn(Insn::Add {
+                    lhs: reg_position,
+                    rhs: partition.reg_one,
+                    dest: reg_position,
+                });
+                program.emit_insn(Insn::Gt {
+                    lhs: reg_position,
+                    rhs: frame.reg_end,
+                    target_pc: label_done,
+                    flags: CmpInsFlags::default(),
+                });
+                emit_skip_excluded_row(
+                    program,
+                    partition,
+                    &function.frame.exclude,
+                    reg_position,
+                    label_scan_next,
+                );
+                if *func == WindowFunc::NthValue {
+                    program.emit_insn(Insn::Add {
+                        lhs: reg_seen,
+                        rhs: partition.reg_one,
+                        dest: reg_seen,
+                    });
+                    program.emit_insn(Insn::Ne {
+                        lhs: reg_seen,
+                        rhs: reg_n,
+                        target_pc: label_scan_next,
+                        flags: CmpInsFlags::default(),
+                    });
+                }
+                partition.emit_read_column(program, reg_position, value_column, reg_result);
+                // The last value of the frame is the last one read.
+                program.emit_insn(Insn::Goto {
+                    target_pc: if *func == WindowFunc::LastValue {
+                        label_scan_next
+                    } else {
+                        label_done
+                    },
+                });
+            }
+        }
+    }
+    program.resolve_label(label_done, program.offset());
+    Ok(())
+}
+
+fn emit_to_real(program: &mut ProgramBuilder, reg: usize) {
+    let reg_one = program.alloc_register();
+    program.emit_insn(Insn::Real {
+        value: 1.0,
+        dest: reg_one,
+    });
+    program.emit_insn(Insn::Multiply {
+        lhs: reg,
+        rhs: reg_one,
+        dest: reg,
+    });
+}
+
+/// Emits the bytecode for evaluating an aggregate window function for the current row.
+#[allow(clippy::too_many_arguments)]
+fn emit_window_aggregate(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    plan: &SelectPlan,
+    partition: &Partition,
+    function: &WindowFunction,
+    func: &AggFunc,
+    frame: &FrameRegisters,
+    reg_result: usize,
+) -> Result<()> {
+    let aggregate = Aggregate {
+        func: func.clone(),
+        args: function.args.clone(),
+        // The rows that do not satisfy the filter are skipped by emit_aggregate_step.
+        filter: None,
+        distinct: false,
+        original_expr: function.original_expr.clone(),
+    };
+    let reg_position = program.alloc_register();
+    let label_step = program.allocate_label();
+    let label_done = program.allocate_label();
+
+    // If the frame starts at the start of the partition and nothing is excluded, the frame only
+    // grows from one row to the next, so the rows are added to the accumulator as they enter the
+    // frame. The state of external aggregates cannot be copied, so they are always recomputed.
+    if function.frame.start == ast::FrameBound::UnboundedPreceding
+        && function.frame.exclude == ast::FrameExclude::NoOthers
+        && !matches!(func, AggFunc::External(_))
+    {
+        program.resolve_label(label_step, program.offset());
+        program.emit_insn(Insn::Ge {
+            lhs: frame.reg_added,
+            rhs: frame.reg_end,
+            target_pc: label_done,
+            flags: CmpInsFlags::default(),
+        });
+        program.emit_insn(Insn::Add {
+            lhs: frame.reg_added,
+            rhs: partition.reg_one,
+            dest: frame.reg_added,
+        });
+        emit_aggregate_step(
+            program,
+            resolver,
+            plan,
+            partition,
+            function,
+            &aggregate,
+            frame.reg_added,
+            frame.reg_acc,
+        )?;
+        program.emit_insn(Insn::Goto {
+            target_pc: label_step,
+        });
+        program.resolve_label(label_done, program.offset());
+        program.emit_insn(Insn::Copy {
+            src_reg: frame.reg_acc,
+            dst_reg: reg_result,
+            amount: 0,
+        });
+    } else {
+        program.emit_insn(Insn::Null {
+            dest: reg_result,
+            dest_end: None,
+        });
+        program.emit_insn(Insn::Subtract {
+            lhs: frame.reg_start,
+            rhs: partition.reg_one,
+            dest: reg_position,
+        });
+        program.resolve_label(label_step, program.offset());
+        program.emit_insn(Insn::Add {
+            lhs: reg_position,
+            rhs: partition.reg_one,
+            dest: reg_position,
+        });
+        program.emit_insn(Insn::Gt {
+            lhs: reg_position,
+            rhs: frame.reg_end,
+            target_pc: label_done,
+            flags: CmpInsFlags::default(),
+        });
+        emit_skip_excluded_row(
+            program,
+            partition,
+            &function.frame.exclude,
+            reg_position,
+            label_step,
+        );
+        emit_aggregate_step(
+            program,
+            resolver,
+            plan,
+            partition,
+            function,
+            &aggregate,
+            reg_position,
+            reg_result,
+        )?;
+        program.emit_insn(Insn::Goto {
+            target_pc: label_step,
+        });
+        program.resolve_label(label_done, program.offset());
+    }
+    program.emit_insn(Insn::AggFinal {
+        register: reg_result,
+        func: func.clone(),
+    });
+    Ok(())
+}
+
+/// Emits the bytecode for adding the row at `reg_position` to the aggregate accumulator `reg_acc`,
+/// unless the FILTER clause of the function is not true for it.
+#[allow(clippy::too_many_arguments)]
+fn emit_aggregate_step(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    plan: &SelectPlan,
+    partition: &Partition,
+    function: &WindowFunction,
+    aggregate: &Aggregate,
+    reg_position: usize,
+    reg_acc: usize,
+) -> Result<()> {
+    // The arguments are read from the row, and the aggregation step finds them in the registers
+    // they were read into.
+    let mut row_resolver = Resolver::new(resolver.symbol_table);
+    partition.emit_seek(program, reg_position);
+    let label_skip = program.allocate_label();
+    if let Some(filter) = &function.filter {
+        let reg_filter = program.alloc_register();
+        program.emit_insn(Insn::Column {
+            cursor_id: partition.cursor_id,
+            column: partition.arg_column(filter),
+            dest: reg_filter,
+        });
+        program.emit_insn(Insn::IfNot {
+            reg: reg_filter,
+            target_pc: label_skip,
+            jump_if_null: true,
+        });
+    }
+    for arg in function.args.iter() {
+        let reg = program.alloc_register();
+        program.emit_insn(Insn::Column {
+            cursor_id: partition.cursor_id,
+            column: partition.arg_column(arg),
+            dest: reg,
+        });
+        row_resolver.expr_to_reg_cache.push((arg, reg));
+    }
+    translate_aggregation_step(
+        program,
+        &plan.table_references,
+        aggregate,
+        reg_acc,
+        &row_resolver,
+    )?;
+    program.resolve_label(label_skip, program.offset());
+    Ok(())
+}
+
+/// Emits the bytecode for jumping to `label_skip` if the row at `reg_position` is excluded from
+/// the frame of the current row by an EXCLUDE clause.
+fn emit_skip_excluded_row(
+    program: &mut ProgramBuilder,
+    partition: &Partition,
+    exclude: &ast::FrameExclude,
+    reg_position: usize,
+    label_skip: BranchOffset,
+) {
+    match exclude {
+        ast::FrameExclude::NoOthers => {}
+        ast::FrameExclude::CurrentRow => {
+            program.emit_insn(Insn::Eq {
+                lhs: reg_position,
+                rhs: partition.reg_row,
+                target_pc: label_skip,
+                flags: CmpInsFlags::default(),
+            });
+        }
+        ast::FrameExclude::Group | ast::FrameExclude::Ties => {
+            let reg_group = program.alloc_register();
+            partition.emit_read_column(program, reg_position, Partition::GROUP_COLUMN, reg_group);
+            if *exclude == ast::FrameExclude::Group {
+                program.emit_insn(Insn::Eq {
+                    lhs: reg_group,
+                    rhs: partition.reg_row_group,
+                    target_pc: label_skip,
+                    flags: CmpInsFlags::default(),
+                });
+            } else {
+                // The peers of the current row are excluded, but not the current row itself.
+                let label_keep = program.allocate_label();
+                program.emit_insn(Insn::Ne {
+                    lhs: reg_group,
+                    rhs: partition.reg_row_group,
+                    target_pc: label_keep,
+                    flags: CmpInsFlags::default(),
+                });
+                program.emit_insn(Insn::Ne {
+                    lhs: reg_position,
+                    rhs: partition.reg_row,
+                    target_pc: label_skip,
+                    flags: CmpInsFlags::default(),
+                });
+                program.resolve_label(label_keep, program.offset());
+            }
+        }
+    }
+}
+
+/// Emits the bytecode for moving the first and last rows of the frame to the current row.
+fn emit_frame(
+    program: &mut ProgramBuilder,
+    partition: &Partition,
+    window_frame: &WindowFrame,
+    frame: &FrameRegisters,
+    order_direction: Option<Direction>,
+) {
+    if window_frame.mode == ast::FrameMode::Rows {
+        emit_rows_frame(program, partition, window_frame, frame);
+        return;
+    }
+    if window_frame.start != ast::FrameBound::UnboundedPreceding {
+        emit_frame_bound(
+            program,
+            partition,
+            window_frame,
+            &window_frame.start,
+            frame.reg_start_offset,
+            frame.reg_start,
+            true,
+            order_direction,
+        );
+    }
+    if window_frame.end == ast::FrameBound::UnboundedFollowing {
+        program.emit_insn(Insn::Copy {
+            src_reg: partition.reg_count,
+            dst_reg: frame.reg_end,
+            amount: 0,
+        });
+    } else {
+        emit_frame_bound(
+            program,
+            partition,
+            window_frame,
+            &window_frame.end,
+            frame.reg_end_offset,
+            frame.reg_end,
+            false,
+            order_direction,
+        );
+    }
+}
+
+/// In ROWS mode, the frame bounds are offsets from the current row.
+fn emit_rows_frame(
+    program: &mut ProgramBuilder,
+    partition: &Partition,
+    window_frame: &WindowFrame,
+    frame: &FrameRegisters,
+) {
+    match &window_frame.start {
+        ast::FrameBound::Preceding(_) => {
+            let label_in_partition = program.allocate_label();
+            program.emit_insn(Insn::Subtract {
+                lhs: partition.reg_row,
+                rhs: frame.reg_start_offset,
+                dest: frame.reg_start,
+            });
+            program.emit_insn(Insn::Ge {
+                lhs: frame.reg_start,
+                rhs: partition.reg_one,
+                target_pc: label_in_partition,
+                flags: CmpInsFlags::default(),
+            });
+            program.emit_insn(Insn::Integer {
+                value: 1,
+                dest: frame.reg_start,
+            });
+            program.resolve_label(label_in_partition, program.offset());
+        }
+        ast::FrameBound::CurrentRow => {
+            program.emit_insn(Insn::Copy {
+                src_reg: partition.reg_row,
+                dst_reg: frame.reg_start,
+                amount: 0,
+            });
+        }
+        ast::FrameBound::Following(_) => {
+            program.emit_insn(Insn::Add {
+                lhs: partition.reg_row,
+                rhs: frame.reg_start_offset,
+                dest: frame.reg_start,
+            });
+        }
+        // The start of the frame stays at the first row of the partition.
+        ast::FrameBound::UnboundedPreceding => {}
+        ast::FrameBound::UnboundedFollowing => unreachable!("rejected when planning the frame"),
+    }
+    match &window_frame.end {
+        ast::FrameBound::Preceding(_) => {
+            program.emit_insn(Insn::Subtract {
+                lhs: partition.reg_row,
+                rhs: frame.reg_end_offset,
+                dest: frame.reg_end,
+            });
+        }
+        ast::FrameBound::CurrentRow => {
+            program.emit_insn(Insn::Copy {
+                src_reg: partition.reg_row,
+                dst_reg: frame.reg_end,
+                amount: 0,
+            });
+        }
+        ast::FrameBound::Following(_) => {
+            let label_in_partition = program.allocate_label();
+            program.emit_insn(Insn::Add {
+                lhs: partition.reg_row,
+                rhs: frame.reg_end_offset,
+                dest: frame.reg_end,
+            });
+            program.emit_insn(Insn::Le {
+                lhs: frame.reg_end,
+                rhs: partition.reg_count,
+                target_pc: label_in_partition,
+                flags: CmpInsFlags::default(),
+            });
+            program.emit_insn(Insn::Copy {
+                src_reg: partition.reg_count,
+                dst_reg: frame.reg_end,
+                amount: 0,
+            });
+            program.resolve_label(label_in_partition, program.offset());
+        }
+        ast::FrameBound::UnboundedFollowing => {
+            program.emit_insn(Insn::Copy {
+                src_reg: partition.reg_count,
+                dst_reg: frame.reg_end,
+                amount: 0,
+            });
+        }
+        ast::FrameBound::UnboundedPreceding => unreachable!("rejected when planning the frame"),
+    }
+}
+
+/// In GROUPS and RANGE mode, a frame bound is found by moving forward from its position for the
+/// previous row until a row of the partition is past the bound:
+/// - the start of the frame (`is_start`) is the first row that is not before the bound
+/// - the end of the frame is the last row that is not after the bound
+///
+/// In GROUPS mode, and in RANGE mode without an offset, the rows are compared by peer group.
+/// In RANGE mode with an offset, the rows are compared by the value of the ORDER BY term.
+/// NULL values are ordered before all other values, and are only peers of each other.
+#[allow(clippy::too_many_arguments)]
+fn emit_frame_bound(
+    program: &mut ProgramBuilder,
+    partition: &Partition,
+    window_frame: &WindowFrame,
+    bound: &ast::FrameBound,
+    reg_offset: usize,
+    reg_pointer: usize,
+    is_start: bool,
+    order_direction: Option<Direction>,
+) {
+    let by_value =
+        window_frame.mode == ast::FrameMode::Range && bound != &ast::FrameBound::CurrentRow;
+    if !by_value {
+        let offset = match window_frame.mode {
+            ast::FrameMode::Range => None,
+            _ => Some(reg_offset),
+        };
+        emit_move_frame_bound(
+            program,
+            partition,
+            bound,
+            offset,
+            reg_pointer,
+            is_start,
+            None,
+        );
+        return;
+    }
+    // The peers of a row with a NULL value are the other rows with a NULL value.
+    let label_by_value = program.allocate_label();
+    let label_done = program.allocate_label();
+    program.emit_insn(Insn::NotNull {
+        reg: partition.reg_row_order_value,
+        target_pc: label_by_value,
+    });
+    emit_move_frame_bound(
+        program,
+        partition,
+        &ast::FrameBound::CurrentRow,
+        None,
+        reg_pointer,
+        is_start,
+        None,
+    );
+    program.emit_insn(Insn::Goto {
+        target_pc: label_done,
+    });
+    program.resolve_label(label_by_value, program.offset());
+    emit_move_frame_bound(
+        program,
+        partition,
+        bound,
+        Some(reg_offset),
+        reg_pointer,
+        is_start,
+        Some(order_direction.unwrap_or(Direction::Ascending)),
+    );
+    program.resolve_label(label_done, program.offset());
+}
+
+/// See [emit_frame_bound]. If `by_value` is None, the rows are compared by peer group,
+/// otherwise by the value of the ORDER BY term, sorted in the given direction.
+#[allow(clippy::too_many_arguments)]
+fn emit_move_frame_bound(
+    program: &mut ProgramBuilder,
+    partition: &Partition,
+    bound: &ast::FrameBound,
+    reg_offset: Option<usize>,
+    reg_pointer: usize,
+    is_start: bool,
+    by_value: Option<Direction>,
+) {
+    let (reg_current, column) = match by_value {
+        Some(_) => (
+            partition.reg_row_order_value,
+            Partition::ORDER_BY_START_COLUMN,
+        ),
+        None => (partition.reg_row_group, Partition::GROUP_COLUMN),
+    };
+    // Compute the bound. In descending order, PRECEDING rows have larger values.
+    let reg_bound = program.alloc_register();
+    let descending = by_value == Some(Direction::Descending);
+    match (bound, reg_offset) {
+        (ast::FrameBound::Preceding(_), Some(reg_offset)) if !descending => {
+            program.emit_insn(Insn::Subtract {
+                lhs: reg_current,
+                rhs: reg_offset,
+                dest: reg_bound,
+            });
+        }
+        (ast::FrameBound::Following(_), Some(reg_offset)) if descending => {
+            program.emit_insn(Insn::Subtract {
+                lhs: reg_current,
+                rhs: reg_offset,
+                dest: reg_bound,
+            });
+        }
+        (ast::FrameBound::Preceding(_) | ast::FrameBound::Following(_), Some(reg_offset)) => {
+            program.emit_insn(Insn::Add {
+                lhs: reg_current,
+                rhs: reg_offset,
+                dest: reg_bound,
+            });
+        }
+        _ => {
+            program.emit_insn(Insn::Copy {
+                src_reg: reg_current,
+                dst_reg: reg_bound,
+                amount: 0,
+            });
+        }
+    }
+
+    let label_loop = program.allocate_label();
+    let label_advance = program.allocate_label();
+    let label_done = program.allocate_label();
+    let reg_candidate = program.alloc_register();
+    let reg_value = program.alloc_register();
+    program.resolve_label(label_loop, program.offset());
+    if is_start {
+        // The candidate for the first row of the frame is the current first row.
+        program.emit_insn(Insn::Gt {
+            lhs: reg_pointer,
+            rhs: partition.reg_count,
+            target_pc: label_done,
+            flags: CmpInsFlags::default(),
+        });
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_pointer,
+            dst_reg: reg_candidate,
+            amount: 0,
+        });
+    } else {
+        // The candidate for the last row of the frame is the row after the current last row.
+        program.emit_insn(Insn::Ge {
+            lhs: reg_pointer,
+            rhs: partition.reg_count,
+            target_pc: label_done,
+            flags: CmpInsFlags::default(),
+        });
+        program.emit_insn(Insn::Add {
+            lhs: reg_pointer,
+            rhs: partition.reg_one,
+            dest: reg_candidate,
+        });
+    }
+    partition.emit_read_column(program, reg_candidate, column, reg_value);
+
+    // Jump to label_done if the candidate is in the frame (start) or after the frame (end).
+    let flags = CmpInsFlags::default();
+    match (is_start, descending) {
+        (true, false) => {
+            if by_value.is_some() {
+                program.emit_insn(Insn::IsNull {
+                    reg: reg_value,
+                    target_pc: label_advance,
+                });
+            }
+            program.emit_insn(Insn::Ge {
+                lhs: reg_value,
+                rhs: reg_bound,
+                target_pc: label_done,
+                flags,
+            });
+        }
+        (true, true) => {
+            program.emit_insn(Insn::IsNull {
+                reg: reg_value,
+                target_pc: label_done,
+            });
+            program.emit_insn(Insn::Le {
+                lhs: reg_value,
+                rhs: reg_bound,
+                target_pc: label_done,
+                flags,
+            });
+        }
+        (false, false) => {
+            if by_value.is_some() {
+                program.emit_insn(Insn::IsNull {
+                    reg: reg_value,
+                    target_pc: label_advance,
+                });
+            }
+            program.emit_insn(Insn::Gt {
+                lhs: reg_value,
+                rhs: reg_bound,
+                target_pc: label_done,
+                flags,
+            });
+        }
+        (false, true) => {
+            program.emit_insn(Insn::IsNull {
+                reg: reg_value,
+                target_pc: label_done,
+            });
+            program.emit_insn(Insn::Lt {
+                lhs: reg_value,
+                rhs: reg_bound,
+                target_pc: label_done,
+                flags,
+            });
+        }
+    }
+    program.resolve_label(label_advance, program.offset());
+    if is_start {
+        program.emit_insn(Insn::Add {
+            lhs: reg_pointer,
+            rhs: partition.reg_one,
+            dest: reg_pointer,
+        });
+    } else {
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_candidate,
+            dst_reg: reg_pointer,
+            amount: 0,
+        });
+    }
+    program.emit_insn(Insn::Goto {
+        target_pc: label_loop,
+    });
+    program.resolve_label(label_done, program.offset());
+}
@@ -1,6 +1,7 @@
 use limbo_ext::{AggCtx, FinalizeFunction, StepFunction};
 use limbo_sqlite3_parser::ast::SortOrder;
 
+use crate::collation::CollationSeq;
 use crate::error::LimboError;
 use crate::ext::{ExtValue, ExtValueType};
 use crate::pseudo::PseudoCursor;
@@ -8,6 +9,7 @@ use crate::schema::Index;
 use crate::storage::btree::BTreeCursor;
 use crate::storage::sqlite3_ondisk::write_varint;
 use crate::translate::plan::IterationDirection;
+use crate::vdbe::hash_table::HashTable;
 use crate::vdbe::sorter::Sorter;
 use crate::vdbe::{Register, VTabOpaqueCursor};
 use crate::Result;
@@ -917,29 +919,24 @@ impl Clone for ImmutableRecord {
     fn clone(&self) -> Self {
         let mut new_values = Vec::new();
         let new_payload = self.payload.clone();
+        // Let's update the pointers into the payload. Empty texts and blobs have a null pointer.
+        let rebase = |slice: &RawSlice| {
+            if slice.data.is_null() {
+                return RawSlice::new(std::ptr::null(), 0);
+            }
+            let offset = slice.data as usize - self.payload.as_ptr() as usize;
+            RawSlice::new(unsafe { new_payload.as_ptr().add(offset) }, slice.len)
+        };
         for value in &self.values {
             let value = match value {
                 RefValue::Null => RefValue::Null,
                 RefValue::Integer(i) => RefValue::Integer(*i),
                 RefValue::Float(f) => RefValue::Float(*f),
-                RefValue::Text(text_ref) => {
-                    // let's update pointer
-                    let ptr_start = self.payload.as_ptr() as usize;
-                    let ptr_end = text_ref.value.data as usize;
-                    let len = ptr_end - ptr_start;
-                    let new_ptr = unsafe { new_payload.as_ptr().add(len) };
-                    RefValue::Text(TextRef {
-                        value: RawSlice::new(new_ptr, text_ref.value.len),
-                        subtype: text_ref.subtype.clone(),
-                    })
-                }
-                RefValue::Blob(raw_slice) => {
-                    let ptr_start = self.payload.as_ptr() as usize;
-                    let ptr_end = raw_slice.data as usize;
-                    let len = ptr_end - ptr_start;
-                    let new_ptr = unsafe { new_payload.as_ptr().add(len) };
-                    RefValue::Blob(RawSlice::new(new_ptr, raw_slice.len))
-                }
+                RefValue::Text(text_ref) => RefValue::Text(TextRef {
+                    value: rebase(&text_ref.value),
+                    subtype: text_ref.subtype.clone(),
+                }),
+                RefValue::Blob(raw_slice) => RefValue::Blob(rebase(raw_slice)),
             };
             new_values.push(value);
         }
@@ -1063,7 +1060,7 @@ impl IndexKeySortOrder {
 
     pub fn from_index(index: &Index) -> Self {
         let mut spec = 0;
-        for (i, column) in index.columns.iter().enumerate() {
+        for (i, column) in index.key_columns().enumerate() {
             spec |= ((column.order == SortOrder::Desc) as u64) << i;
         }
         IndexKeySortOrder(spec)
@@ -1080,15 +1077,19 @@ impl Default for IndexKeySortOrder {
     }
 }
 
+/// Compare two index keys. Columns without an entry in `collations`, such as the trailing rowid,
+/// are compared using the BINARY collating sequence.
 pub fn compare_immutable(
     l: &[RefValue],
     r: &[RefValue],
     index_key_sort_order: IndexKeySortOrder,
+    collations: &[CollationSeq],
 ) -> std::cmp::Ordering {
     assert_eq!(l.len(), r.len());
     for (i, (l, r)) in l.iter().zip(r).enumerate() {
         let column_order = index_key_sort_order.get_sort_order_for_col(i);
-        let cmp = l.partial_cmp(r).unwrap();
+        let collation = collations.get(i).copied().unwrap_or_default();
+        let cmp = collation.compare_ref_values(l, r);
         if !cmp.is_eq() {
             return match column_order {
                 SortOrder::Asc => cmp,
@@ -1229,6 +1230,7 @@ pub enum Cursor {
     Pseudo(PseudoCursor),
     Sorter(Sorter),
     Virtual(VTabOpaqueCursor),
+    HashTable(HashTable),
 }
 
 impl Cursor {
@@ -1271,6 +1273,13 @@ impl Cursor {
             _ => panic!("Cursor is not a virtual cursor"),
         }
     }
+
+    pub fn as_hash_table_mut(&mut self) -> &mut HashTable {
+        match self {
+            Self::HashTable(cursor) => cursor,
+            _ => panic!("Cursor is not a hash table cursor"),
+        }
+    }
 }
 
 #[derive(Debug)]
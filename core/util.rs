@@ -1,5 +1,7 @@
 use limbo_sqlite3_parser::ast::{self, CreateTableBody, Expr, FunctionTail, Literal};
-use std::{rc::Rc, sync::Arc};
+use limbo_sqlite3_parser::dialect::TokenType;
+use limbo_sqlite3_parser::lexer::{sql::Tokenizer, Scanner};
+use std::{ops::Range, rc::Rc, sync::Arc};
 
 use crate::{
     function::Func,
@@ -19,6 +21,18 @@ impl RoundToPrecision for f64 {
     }
 }
 
+/// The path of a new temporary file, e.g. the copy of a database built by VACUUM. It is opened
+/// through `io` like the database, so an IO that does not write files keeps it in memory.
+pub(crate) fn temp_file_path(io: &Arc<dyn IO>, purpose: &str) -> String {
+    let name = format!("limbo-{}-{:016x}", purpose, io.generate_random_number());
+    #[cfg(feature = "fs")]
+    let name = std::env::temp_dir()
+        .join(name)
+        .to_string_lossy()
+        .into_owned();
+    name
+}
+
 // https://sqlite.org/lang_keywords.html
 const QUOTE_PAIRS: &[(char, char)] = &[('"', '"'), ('[', ']'), ('`', '`')];
 
@@ -67,7 +81,7 @@ pub fn parse_schema_rows(
                 StepResult::Row => {
                     let row = rows.row().unwrap();
                     let ty = row.get::<&str>(0)?;
-                    if !["table", "index"].contains(&ty) {
+                    if !["table", "index", "view", "trigger"].contains(&ty) {
                         continue;
                     }
                     match ty {
@@ -111,6 +125,16 @@ pub fn parse_schema_rows(
                                 schema.add_btree_table(Rc::new(table));
                             }
                         }
+                        "view" => {
+                            let sql: &str = row.get::<&str>(4)?;
+                            let view = schema::View::from_sql(sql)?;
+                            schema.add_view(Rc::new(view));
+                        }
+                        "trigger" => {
+                            let sql: &str = row.get::<&str>(4)?;
+                            let trigger = schema::Trigger::from_sql(sql)?;
+                            schema.add_trigger(trigger);
+                        }
                         "index" => {
                             let root_page: i64 = row.get::<i64>(3)?;
                             match row.get::<&str>(4) {
@@ -122,7 +146,7 @@ pub fn parse_schema_rows(
                                     });
                                 }
                                 _ => {
-                                    // Automatic index on primary key, e.g.
+                                    // Automatic index on a primary key or unique constraint, e.g.
                                     // table|foo|foo|2|CREATE TABLE foo (a text PRIMARY KEY, b)
                                     // index|sqlite_autoindex_foo_1|foo|3|
                                     let index_name = row.get::<&str>(1)?.to_string();
@@ -166,11 +190,19 @@ pub fn parse_schema_rows(
                     root_page,
                 } => {
                     let table = schema.get_btree_table(&table_name).unwrap();
-                    let index = schema::Index::automatic_from_primary_key(
-                        table.as_ref(),
-                        &name,
-                        root_page as usize,
-                    )?;
+                    let index = match table.unique_set_of_index(&name) {
+                        Some(unique_set) => schema::Index::automatic_from_unique(
+                            table.as_ref(),
+                            &name,
+                            root_page,
+                            &unique_set.columns,
+                        )?,
+                        None => schema::Index::automatic_from_primary_key(
+                            table.as_ref(),
+                            &name,
+                            root_page,
+                        )?,
+                    };
                     schema.add_index(Arc::new(index));
                 }
             }
@@ -179,6 +211,41 @@ pub fn parse_schema_rows(
     Ok(())
 }
 
+/// The table in which ANALYZE stores the statistics of the tables and their indexes.
+pub const STAT1_TABLE: &str = "sqlite_stat1";
+
+/// Replaces the statistics of the schema with the rows of sqlite_stat1 returned by `rows`.
+pub fn parse_stat1_rows(
+    rows: Option<Statement>,
+    schema: &mut Schema,
+    io: Arc<dyn IO>,
+) -> Result<()> {
+    schema.table_stats.clear();
+    let Some(mut rows) = rows else {
+        return Ok(());
+    };
+    loop {
+        match rows.step()? {
+            StepResult::Row => {
+                let row = rows.row().unwrap();
+                let (OwnedValue::Text(tbl), OwnedValue::Text(stat)) =
+                    (row.get_value(0), row.get_value(2))
+                else {
+                    continue;
+                };
+                let idx = match row.get_value(1) {
+                    OwnedValue::Text(idx) => Some(idx.as_str()),
+                    _ => None,
+                };
+                schema.add_stat1_row(tbl.as_str(), idx, stat.as_str());
+            }
+            StepResult::IO => io.run_once()?,
+            StepResult::Interrupt | StepResult::Done | StepResult::Busy => break,
+        }
+    }
+    Ok(())
+}
+
 fn cmp_numeric_strings(num_str: &str, other: &str) -> bool {
     match (num_str.parse::<f64>(), other.parse::<f64>()) {
         (Ok(num), Ok(other)) => num == other,
@@ -198,6 +265,19 @@ pub fn check_ident_equivalency(ident1: &str, ident2: &str) -> bool {
     strip_quotes(ident1).eq_ignore_ascii_case(strip_quotes(ident2))
 }
 
+/// The tokens of the first statement in `sql`, with their byte ranges in it.
+pub fn statement_tokens(sql: &str) -> Vec<(TokenType, Range<usize>)> {
+    let mut scanner = Scanner::new(Tokenizer::new());
+    let mut tokens = vec![];
+    while let Ok((start, Some((_, token_type)), end)) = scanner.scan(sql.as_bytes()) {
+        if token_type == TokenType::TK_SEMI {
+            break;
+        }
+        tokens.push((token_type, start..end));
+    }
+    tokens
+}
+
 fn module_name_from_sql(sql: &str) -> Result<&str> {
     if let Some(start) = sql.find("USING") {
         let start = start + 6;
@@ -294,7 +374,7 @@ fn module_args_from_sql(sql: &str) -> Result<Vec<limbo_ext::Value>> {
 pub fn check_literal_equivalency(lhs: &Literal, rhs: &Literal) -> bool {
     match (lhs, rhs) {
         (Literal::Numeric(n1), Literal::Numeric(n2)) => cmp_numeric_strings(n1, n2),
-        (Literal::String(s1), Literal::String(s2)) => check_ident_equivalency(s1, s2),
+        (Literal::String(s1), Literal::String(s2)) => s1 == s2,
         (Literal::Blob(b1), Literal::Blob(b2)) => b1 == b2,
         (Literal::Keyword(k1), Literal::Keyword(k2)) => check_ident_equivalency(k1, k2),
         (Literal::Null, Literal::Null) => true,
@@ -420,13 +500,14 @@ pub fn exprs_are_equivalent(expr1: &Expr, expr2: &Expr) -> bool {
                             filter_clause: fc2,
                             over_clause: oc2,
                         }),
-                    ) => match ((fc1, fc2), (oc1, oc2)) {
-                        ((Some(fc1), Some(fc2)), (Some(oc1), Some(oc2))) => {
-                            exprs_are_equivalent(fc1, fc2) && oc1 == oc2
-                        }
-                        ((Some(fc1), Some(fc2)), _) => exprs_are_equivalent(fc1, fc2),
-                        _ => false,
-                    },
+                    ) => {
+                        let filters_match = match (fc1, fc2) {
+                            (Some(fc1), Some(fc2)) => exprs_are_equivalent(fc1, fc2),
+                            (None, None) => true,
+                            _ => false,
+                        };
+                        filters_match && oc1 == oc2
+                    }
                     _ => false,
                 }
         }
@@ -542,6 +623,15 @@ pub fn columns_from_create_table_body(body: &ast::CreateTableBody) -> crate::Res
                         }
                         _ => None,
                     }),
+                collation: column_def
+                    .constraints
+                    .iter()
+                    .find_map(|c| match &c.constraint {
+                        limbo_sqlite3_parser::ast::ColumnConstraint::Collate { collation_name } => {
+                            Some(normalize_ident(&collation_name.0))
+                        }
+                        _ => None,
+                    }),
                 notnull: column_def.constraints.iter().any(|c| {
                     matches!(
                         c.constraint,
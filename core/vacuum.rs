@@ -0,0 +1,374 @@
+//! VACUUM rebuilds a database into a compact copy: every b-tree is copied in order into a new
+//! temporary database, so the copy has no free pages and its pages are filled up. The copy then
+//! either replaces the pages of the database in the write transaction of the VACUUM statement, so
+//! that readers see the whole change at once when it commits to the WAL, or, with VACUUM INTO, is
+//! written out to a new file.
+
+use crate::collation::CollationSeq;
+use crate::schema::{Index, Schema, Table};
+use crate::storage::auto_vacuum::AutoVacuumMode;
+use crate::storage::btree::{BTreeCursor, BTreeKey};
+use crate::storage::database::FileMemoryStorage;
+use crate::storage::pager::CreateBTreeFlags;
+use crate::storage::sqlite3_ondisk::PageType;
+use crate::storage::wal::WalFileShared;
+use crate::types::{CursorResult, ImmutableRecord};
+use crate::util::{parse_schema_rows, temp_file_path};
+use crate::vdbe::Register;
+use crate::{
+    init_database_file, CheckpointStatus, Connection, Database, LimboError, OpenFlags, OwnedValue,
+    Pager, Result, IO,
+};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// The column of sqlite_schema that holds the root page of a b-tree.
+const ROOTPAGE_COLUMN: usize = 3;
+
+impl Connection {
+    /// Rebuilds the database at `database`, see [Schema::database], into a compact copy. Without
+    /// `into`, the copy replaces the database in the write transaction of the running statement.
+    /// With `into`, the copy is written to a new database file at that path instead.
+    pub(crate) fn vacuum(&self, database: usize, into: Option<&str>) -> Result<()> {
+        if !self.auto_commit.get() {
+            return Err(LimboError::TxError(
+                "cannot VACUUM from within a transaction".to_string(),
+            ));
+        }
+        if self._db.mv_store.is_some() {
+            return Err(LimboError::ParseError(
+                "VACUUM is not supported with MVCC".to_string(),
+            ));
+        }
+        let pager = if database == 0 {
+            self.pager.clone()
+        } else {
+            self.attached_pager(database)?
+        };
+        let indexes = self.vacuum_index_keys(database)?;
+        let path = temp_file_path(&pager.io, "vacuum");
+        let result = self.vacuum_through_copy(database, &pager, &indexes, &path, into);
+        remove_temp_files(&path);
+        result
+    }
+
+    /// Builds the compact copy of the database of `pager` in a temporary database at `path`, and
+    /// uses it like [Self::vacuum] does.
+    fn vacuum_through_copy(
+        &self,
+        database: usize,
+        pager: &Rc<Pager>,
+        indexes: &IndexKeys,
+        path: &str,
+        into: Option<&str>,
+    ) -> Result<()> {
+        let (copy_db, copy) = compact(pager, indexes, path)?;
+        copy.begin_read_tx()?;
+        let result = match into {
+            Some(path) => write_copy(&copy, path),
+            None => {
+                replace_pages(pager, &copy)?;
+                self.install_vacuumed_schema(database, &copy_db)
+            }
+        };
+        copy.end_read_tx()?;
+        result
+    }
+
+    /// Replaces the schema of the vacuumed database with the one of its compact copy, whose
+    /// b-trees have new root pages.
+    fn install_vacuumed_schema(&self, database: usize, copy_db: &Arc<Database>) -> Result<()> {
+        let mut schema = Schema::new();
+        schema.database = database;
+        let conn = copy_db.connect()?;
+        let rows = conn.query("SELECT * FROM sqlite_schema")?;
+        parse_schema_rows(
+            rows,
+            &mut schema,
+            copy_db.io.clone(),
+            &self.syms.borrow(),
+            None,
+        )?;
+        let database_schema = self.database_schema(database)?;
+        // The statistics are keyed by name, so they still hold for the new root pages
+        schema.table_stats = database_schema.read().table_stats.clone();
+        *database_schema.write() = schema;
+        if database == 0 {
+            self.schema_changed.set(true);
+        }
+        Ok(())
+    }
+
+    /// The b-trees of the database at `database` that are keyed by index keys, by root page,
+    /// with the collating sequences of their columns. Their entries are compared by these when
+    /// they are inserted into the copy.
+    fn vacuum_index_keys(&self, database: usize) -> Result<IndexKeys> {
+        let schema = self.database_schema(database)?;
+        let schema = schema.read();
+        let without_rowid_tables =
+            schema
+                .tables
+                .values()
+                .filter_map(|table| match table.as_ref() {
+                    Table::BTree(table) => table.primary_key_index().map(Arc::new),
+                    _ => None,
+                });
+        let syms = self.syms.borrow();
+        schema
+            .indexes
+            .values()
+            .flatten()
+            .cloned()
+            .chain(without_rowid_tables)
+            .map(|index| {
+                let collations = index
+                    .columns
+                    .iter()
+                    .map(|c| match &c.collation {
+                        None => Ok(CollationSeq::Binary),
+                        Some(name) => syms.resolve_collation(name).ok_or_else(|| {
+                            LimboError::ParseError(format!("no such collation sequence: {}", name))
+                        }),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok((index.root_page, (index, collations)))
+            })
+            .collect()
+    }
+}
+
+/// Index b-trees by root page, with the collating sequences of their columns.
+type IndexKeys = HashMap<usize, (Arc<Index>, Vec<CollationSeq>)>;
+
+/// Removes the temporary database at `path` and its WAL. An IO that does not write files, like
+/// the one of an in-memory database, left nothing to remove.
+fn remove_temp_files(path: &str) {
+    #[cfg(feature = "fs")]
+    for path in [path.to_string(), format!("{}-wal", path)] {
+        let _ = std::fs::remove_file(path);
+    }
+    #[cfg(not(feature = "fs"))]
+    let _ = path;
+}
+
+/// Copies the b-trees of the database of `source` into a new database at `path`, and commits it.
+fn compact(
+    source: &Rc<Pager>,
+    indexes: &IndexKeys,
+    path: &str,
+) -> Result<(Arc<Database>, Rc<Pager>)> {
+    let mut header = source.db_header.lock().clone();
+    header.database_size = 1;
+    header.freelist_trunk_page = 0;
+    header.freelist_pages = 0;
+    // The auto-vacuum mode can only be changed by rebuilding the database.
+    let auto_vacuum = source
+        .auto_vacuum_request
+        .get()
+        .unwrap_or_else(|| AutoVacuumMode::from_header(&header));
+    header.vacuum_mode_largest_root_page = (auto_vacuum != AutoVacuumMode::None) as u32;
+    header.incremental_vacuum_enabled = (auto_vacuum == AutoVacuumMode::Incremental) as u32;
+    let io = source.io.clone();
+    let file = io.open_file(path, OpenFlags::Create, false)?;
+    init_database_file(&file, &io, &header)?;
+    let wal = WalFileShared::open_shared(&io, &format!("{}-wal", path), header.page_size)?;
+    let db = Database::open(io, Arc::new(FileMemoryStorage::new(file)), wal, false)?;
+    let copy = db.new_pager()?;
+    copy.begin_read_tx()?;
+    copy.begin_write_tx()?;
+    if let Err(e) = copy_btrees(source, &copy, indexes) {
+        copy.rollback_tx()?;
+        return Err(e);
+    }
+    loop {
+        match copy.end_tx()? {
+            CheckpointStatus::Done(_) => break,
+            CheckpointStatus::IO => copy.io.run_once()?,
+        }
+    }
+    Ok((db, copy))
+}
+
+/// Copies every b-tree listed in sqlite_schema of `source` into `target`, and the rows of
+/// sqlite_schema itself with the root pages of the copies.
+fn copy_btrees(source: &Rc<Pager>, target: &Rc<Pager>, indexes: &IndexKeys) -> Result<()> {
+    let mut schema_cursor = BTreeCursor::new(None, source.clone(), 1);
+    let mut target_schema_cursor = BTreeCursor::new(None, target.clone(), 1);
+    run_until_done(|| schema_cursor.rewind(), source)?;
+    while !schema_cursor.is_empty() {
+        let rowid = schema_cursor
+            .rowid()?
+            .expect("sqlite_schema row without rowid");
+        let mut record = schema_cursor.record().as_ref().unwrap().clone();
+        let root_page = match record.get_value_opt(ROOTPAGE_COLUMN).map(|v| v.to_owned()) {
+            Some(OwnedValue::Integer(root_page)) if root_page > 0 => root_page as usize,
+            _ => 0,
+        };
+        if root_page > 0 {
+            let is_table = matches!(
+                source.read_page_sync(root_page)?.get_contents().page_type(),
+                PageType::TableLeaf | PageType::TableInterior
+            );
+            let flags = if is_table {
+                CreateBTreeFlags::new_table()
+            } else {
+                CreateBTreeFlags::new_index()
+            };
+            let new_root_page = target.btree_create(&flags)? as usize;
+            let mut target_cursor = match indexes.get(&root_page) {
+                Some((index, collations)) => BTreeCursor::new_index(
+                    None,
+                    target.clone(),
+                    new_root_page,
+                    index,
+                    collations.clone(),
+                ),
+                None => BTreeCursor::new(None, target.clone(), new_root_page),
+            };
+            copy_btree(source, root_page, target, &mut target_cursor, is_table)?;
+            let values = record
+                .get_values()
+                .iter()
+                .enumerate()
+                .map(|(i, value)| match i {
+                    ROOTPAGE_COLUMN => {
+                        Register::OwnedValue(OwnedValue::Integer(new_root_page as i64))
+                    }
+                    _ => Register::OwnedValue(value.to_owned()),
+                })
+                .collect::<Vec<_>>();
+            record = ImmutableRecord::from_registers(&values);
+        }
+        run_until_done(|| target_schema_cursor.seek_end(), target)?;
+        let key = BTreeKey::new_table_rowid(rowid, Some(&record));
+        run_until_done(|| target_schema_cursor.insert(&key, true), target)?;
+        run_until_done(|| schema_cursor.next(), source)?;
+    }
+    Ok(())
+}
+
+/// Appends the entries of the b-tree at `root_page` of `source`, in order, to the empty b-tree of
+/// `target_cursor` in `target`. The cursor is moved to the end before each insert, so that the b-tree does
+/// not have to be searched.
+fn copy_btree(
+    source: &Rc<Pager>,
+    root_page: usize,
+    target: &Rc<Pager>,
+    target_cursor: &mut BTreeCursor,
+    is_table: bool,
+) -> Result<()> {
+    let mut cursor = BTreeCursor::new(None, source.clone(), root_page);
+    run_until_done(|| cursor.rewind(), source)?;
+    while !cursor.is_empty() {
+        let record = cursor.record().as_ref().unwrap().clone();
+        let key = if is_table {
+            let rowid = cursor.rowid()?.expect("table entry without rowid");
+            BTreeKey::new_table_rowid(rowid, Some(&record))
+        } else {
+            BTreeKey::new_index_key(&record)
+        };
+        run_until_done(|| target_cursor.seek_end(), target)?;
+        run_until_done(|| target_cursor.insert(&key, true), target)?;
+        run_until_done(|| cursor.next(), source)?;
+    }
+    Ok(())
+}
+
+/// Overwrites the pages of the database of `pager` with the pages of its compact `copy`, in the
+/// write transaction of `pager`. The pages past the end of the copy are left as they are, and
+/// the database file is truncated once they are checkpointed.
+fn replace_pages(pager: &Rc<Pager>, copy: &Rc<Pager>) -> Result<()> {
+    let header = copy.db_header.lock().clone();
+    let database_size = pager.db_header.lock().database_size as usize;
+    for page_id in 1..=header.database_size as usize {
+        let copy_page = copy.read_page_sync(page_id)?;
+        let page = if page_id <= database_size {
+            pager.read_page_sync(page_id)?
+        } else {
+            let page = pager.allocate_page_buffer(page_id);
+            pager.put_loaded_page(page_id, page.clone());
+            page
+        };
+        page.get_contents()
+            .as_ptr()
+            .copy_from_slice(copy_page.get_contents().as_ptr());
+        page.set_dirty();
+        pager.add_dirty(page_id);
+    }
+    *pager.db_header.lock() = header.clone();
+    pager
+        .read_page_sync(1)?
+        .get_contents()
+        .write_database_header(&header);
+    Ok(())
+}
+
+/// Writes the pages of the compact `copy` to a new database file at `path`.
+#[cfg(feature = "fs")]
+fn write_copy(copy: &Rc<Pager>, path: &str) -> Result<()> {
+    use crate::io::{SyncCompletion, WriteCompletion};
+    use crate::storage::database::DatabaseFile;
+    use crate::{Completion, DatabaseStorage, PlatformIO};
+    use std::cell::Cell;
+
+    let io: Arc<dyn IO> = Arc::new(PlatformIO::new()?);
+    let file = io.open_file(path, OpenFlags::Create, false)?;
+    if file.size()? > 0 {
+        return Err(LimboError::ParseError(format!(
+            "output file already exists: {}",
+            path
+        )));
+    }
+    let storage = DatabaseFile::new(file);
+    let header = copy.db_header.lock().clone();
+    let pending_writes = Rc::new(Cell::new(0));
+    for page_id in 1..=header.database_size as usize {
+        let page = copy.read_page_sync(page_id)?;
+        if page_id == 1 {
+            page.get_contents().write_database_header(&header);
+        }
+        pending_writes.set(pending_writes.get() + 1);
+        let completion = {
+            let pending_writes = pending_writes.clone();
+            Completion::Write(WriteCompletion::new(Box::new(move |_| {
+                pending_writes.set(pending_writes.get() - 1);
+            })))
+        };
+        storage.write_page(page_id, page.get_contents().buffer.clone(), completion)?;
+    }
+    while pending_writes.get() > 0 {
+        io.run_once()?;
+    }
+    let synced = Rc::new(Cell::new(false));
+    let completion = {
+        let synced = synced.clone();
+        Completion::Sync(SyncCompletion {
+            complete: Box::new(move |_| synced.set(true)),
+        })
+    };
+    storage.sync(completion)?;
+    while !synced.get() {
+        io.run_once()?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "fs"))]
+fn write_copy(_copy: &Rc<Pager>, _path: &str) -> Result<()> {
+    Err(LimboError::InvalidArgument(
+        "VACUUM INTO is not supported without the fs feature".to_string(),
+    ))
+}
+
+fn run_until_done<T>(
+    mut action: impl FnMut() -> Result<CursorResult<T>>,
+    pager: &Pager,
+) -> Result<T> {
+    loop {
+        match action()? {
+            CursorResult::Ok(res) => return Ok(res),
+            CursorResult::IO => pager.io.run_once()?,
+        }
+    }
+}
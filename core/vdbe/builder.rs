@@ -37,6 +37,8 @@ pub struct ProgramBuilder {
     pub parameters: Parameters,
     pub result_columns: Vec<ResultSetColumn>,
     pub table_references: Vec<TableReference>,
+    /// Trigger programs run by [Insn::Program], indexed by [super::insn::Subprogram::Index].
+    subprograms: Vec<ProgramBuilder>,
 }
 
 #[derive(Debug, Clone)]
@@ -46,6 +48,8 @@ pub enum CursorType {
     Pseudo(Rc<PseudoTable>),
     Sorter,
     VirtualTable(Rc<VirtualTable>),
+    /// The hash table of a hash join, whose entries are laid out like the ones of the index.
+    HashTable(Arc<Index>),
 }
 
 impl CursorType {
@@ -95,9 +99,16 @@ impl ProgramBuilder {
             parameters: Parameters::new(),
             result_columns: Vec::new(),
             table_references: Vec::new(),
+            subprograms: Vec::new(),
         }
     }
 
+    /// Add a sub-program to be run by [Insn::Program] and return its index.
+    pub fn add_subprogram(&mut self, subprogram: ProgramBuilder) -> usize {
+        self.subprograms.push(subprogram);
+        self.subprograms.len() - 1
+    }
+
     pub fn alloc_register(&mut self) -> usize {
         let reg = self.next_free_register;
         self.next_free_register += 1;
@@ -170,17 +181,20 @@ impl ProgramBuilder {
         self.emit_insn(Insn::Halt {
             err_code: 0,
             description: String::new(),
+            on_error: ast::ResolveType::Abort,
         });
     }
 
-    // no users yet, but I want to avoid someone else in the future
-    // just adding parameters to emit_halt! If you use this, remove the
-    // clippy warning please.
-    #[allow(dead_code)]
-    pub fn emit_halt_err(&mut self, err_code: usize, description: String) {
+    pub fn emit_halt_err(
+        &mut self,
+        err_code: usize,
+        on_error: ast::ResolveType,
+        description: String,
+    ) {
         self.emit_insn(Insn::Halt {
             err_code,
             description,
+            on_error,
         });
     }
 
@@ -369,6 +383,12 @@ impl ProgramBuilder {
                 } => {
                     resolve(target_pc_when_reentered, "Once");
                 }
+                Insn::HashProbe { target_pc, .. } => {
+                    resolve(target_pc, "HashProbe");
+                }
+                Insn::HashNext { pc_if_next, .. } => {
+                    resolve(pc_if_next, "HashNext");
+                }
                 Insn::Prev { pc_if_prev, .. } => {
                     resolve(pc_if_prev, "Prev");
                 }
@@ -386,6 +406,9 @@ impl ProgramBuilder {
                 } => {
                     resolve(target_pc, "NotExists");
                 }
+                Insn::NoConflict { target_pc, .. } => {
+                    resolve(target_pc, "NoConflict");
+                }
                 Insn::Yield {
                     yield_reg: _,
                     end_offset,
@@ -440,6 +463,24 @@ impl ProgramBuilder {
                 Insn::VFilter { pc_if_empty, .. } => {
                     resolve(pc_if_empty, "VFilter");
                 }
+                Insn::NotFound { target_pc, .. } => {
+                    resolve(target_pc, "NotFound");
+                }
+                Insn::Program { ignore_target, .. } => {
+                    resolve(ignore_target, "Program");
+                }
+                Insn::MustBeInt {
+                    target_pc: Some(target_pc),
+                    ..
+                } => {
+                    resolve(target_pc, "MustBeInt");
+                }
+                Insn::FkIfZero { target_pc, .. } => {
+                    resolve(target_pc, "FkIfZero");
+                }
+                Insn::IncrVacuum { target_pc, .. } => {
+                    resolve(target_pc, "IncrVacuum");
+                }
                 _ => {}
             }
         }
@@ -447,8 +488,10 @@ impl ProgramBuilder {
     }
 
     // translate table to cursor id
+    // the most recently allocated cursor wins, so that e.g. the members of a compound SELECT
+    // that read from the same table each resolve to their own cursor
     pub fn resolve_cursor_id_safe(&self, table_identifier: &str) -> Option<CursorID> {
-        self.cursor_ref.iter().position(|(t_ident, _)| {
+        self.cursor_ref.iter().rposition(|(t_ident, _)| {
             t_ident
                 .as_ref()
                 .is_some_and(|ident| ident == table_identifier)
@@ -473,6 +516,17 @@ impl ProgramBuilder {
         );
 
         self.parameters.list.dedup();
+        let subprograms = self
+            .subprograms
+            .into_iter()
+            .map(|subprogram| {
+                Rc::new(subprogram.build(
+                    database_header.clone(),
+                    connection.clone(),
+                    change_cnt_on,
+                ))
+            })
+            .collect();
         Program {
             max_registers: self.next_free_register,
             insns: self.insns,
@@ -485,6 +539,7 @@ impl ProgramBuilder {
             change_cnt_on,
             result_columns: self.result_columns,
             table_references: self.table_references,
+            subprograms,
         }
     }
 }
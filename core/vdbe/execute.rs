@@ -1,10 +1,16 @@
 #![allow(unused_variables)]
 use crate::numeric::{NullableInteger, Numeric};
+use crate::storage::auto_vacuum::AutoVacuumMode;
 use crate::storage::database::FileMemoryStorage;
 use crate::storage::page_cache::DumbLruPageCache;
 use crate::storage::pager::CreateBTreeFlags;
 use crate::{
-    error::{LimboError, SQLITE_CONSTRAINT, SQLITE_CONSTRAINT_PRIMARYKEY},
+    collation::CollationSeq,
+    error::{
+        LimboError, SQLITE_CONSTRAINT, SQLITE_CONSTRAINT_CHECK, SQLITE_CONSTRAINT_NOTNULL,
+        SQLITE_CONSTRAINT_PRIMARYKEY, SQLITE_CONSTRAINT_TRIGGER, SQLITE_CONSTRAINT_UNIQUE,
+        SQLITE_IGNORE,
+    },
     ext::ExtValue,
     function::{AggFunc, ExtFunc, MathFunc, MathFuncArity, ScalarFunc, VectorFunc},
     functions::{
@@ -20,15 +26,16 @@ use std::{borrow::BorrowMut, rc::Rc, sync::Arc};
 use crate::{pseudo::PseudoCursor, result::LimboResult};
 
 use crate::{
-    schema::{affinity, Affinity},
+    schema::{affinity, literal_value, Affinity, Index},
     storage::btree::{BTreeCursor, BTreeKey},
+    translate::alter::exec_alter_table_func,
 };
 
 use crate::{
     storage::wal::CheckpointResult,
     types::{
-        AggContext, Cursor, CursorResult, ExternalAggState, OwnedValue, OwnedValueType, SeekKey,
-        SeekOp,
+        AggContext, Cursor, CursorResult, ExternalAggState, ImmutableRecord, IndexKeySortOrder,
+        OwnedValue, OwnedValueType, SeekKey, SeekOp,
     },
     util::{
         cast_real_to_integer, cast_text_to_integer, cast_text_to_numeric, cast_text_to_real,
@@ -47,13 +54,14 @@ use crate::{
 };
 
 use super::{
-    insn::{Cookie, RegisterOrLiteral},
+    insn::{Cookie, RegisterOrLiteral, SavepointOp, Subprogram},
     HaltState,
 };
 use parking_lot::RwLock;
 use rand::thread_rng;
 
 use super::{
+    hash_table::HashTable,
     likeop::{construct_like_escape_arg, exec_glob, exec_like_with_escape},
     sorter::Sorter,
 };
@@ -135,6 +143,26 @@ pub fn op_add(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_add_imm(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::AddImm { register, value } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let current = match exec_cast(state.registers[*register].get_owned_value(), "INTEGER") {
+        OwnedValue::Integer(i) => i,
+        _ => 0,
+    };
+    state.registers[*register] =
+        Register::OwnedValue(OwnedValue::Integer(current.wrapping_add(*value)));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_subtract(
     program: &Program,
     state: &mut ProgramState,
@@ -267,14 +295,14 @@ pub fn op_checkpoint(
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
     let Insn::Checkpoint {
-        database: _,
+        database,
         checkpoint_mode: _,
         dest,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let result = program.connection.upgrade().unwrap().checkpoint();
+    let result = database_pager(program, pager, *database).map(|pager| pager.clear_page_cache());
     match result {
         Ok(CheckpointResult {
             num_wal_frames: num_wal_pages,
@@ -328,6 +356,15 @@ pub fn op_null_row(
     let Insn::NullRow { cursor_id } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    state.cancel_deferred_seek(*cursor_id);
+    if let (_, CursorType::HashTable(_)) = &program.cursor_ref[*cursor_id] {
+        state
+            .get_cursor(*cursor_id)
+            .as_hash_table_mut()
+            .set_null_flag(true);
+        state.pc += 1;
+        return Ok(InsnFunctionStepResult::Step);
+    }
     {
         let mut cursor = must_be_btree_cursor!(*cursor_id, program.cursor_ref, state, "NullRow");
         let cursor = cursor.as_btree_mut();
@@ -348,6 +385,7 @@ pub fn op_compare(
         start_reg_a,
         start_reg_b,
         count,
+        collations,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
@@ -363,10 +401,10 @@ pub fn op_compare(
     }
 
     let mut cmp = None;
-    for i in 0..count {
+    for (i, collation) in collations.iter().enumerate().take(count) {
         let a = state.registers[start_reg_a + i].get_owned_value();
         let b = state.registers[start_reg_b + i].get_owned_value();
-        cmp = Some(a.cmp(b));
+        cmp = Some(collation.compare_values(a, b));
         if cmp != Some(std::cmp::Ordering::Equal) {
             break;
         }
@@ -532,7 +570,13 @@ pub fn op_eq(
             }
         }
         _ => {
-            if *state.registers[lhs].get_owned_value() == *state.registers[rhs].get_owned_value() {
+            if compare_operands(
+                state.registers[lhs].get_owned_value(),
+                state.registers[rhs].get_owned_value(),
+                flags.collation(),
+            )
+            .is_some_and(|ord| ord.is_eq())
+            {
                 state.pc = target_pc.to_offset_int();
             } else {
                 state.pc += 1;
@@ -577,7 +621,13 @@ pub fn op_ne(
             }
         }
         _ => {
-            if *state.registers[lhs].get_owned_value() != *state.registers[rhs].get_owned_value() {
+            if !compare_operands(
+                state.registers[lhs].get_owned_value(),
+                state.registers[rhs].get_owned_value(),
+                flags.collation(),
+            )
+            .is_some_and(|ord| ord.is_eq())
+            {
                 state.pc = target_pc.to_offset_int();
             } else {
                 state.pc += 1;
@@ -587,6 +637,20 @@ pub fn op_ne(
     Ok(InsnFunctionStepResult::Step)
 }
 
+/// Compare the non-NULL operands of a comparison instruction, using `collation` for TEXT values.
+fn compare_operands(
+    lhs: &OwnedValue,
+    rhs: &OwnedValue,
+    collation: CollationSeq,
+) -> Option<std::cmp::Ordering> {
+    match (lhs, rhs) {
+        (OwnedValue::Text(lhs), OwnedValue::Text(rhs)) => {
+            Some(collation.compare_strings(&lhs.value, &rhs.value))
+        }
+        _ => lhs.partial_cmp(rhs),
+    }
+}
+
 pub fn op_lt(
     program: &Program,
     state: &mut ProgramState,
@@ -620,7 +684,13 @@ pub fn op_lt(
             }
         }
         _ => {
-            if *state.registers[lhs].get_owned_value() < *state.registers[rhs].get_owned_value() {
+            if compare_operands(
+                state.registers[lhs].get_owned_value(),
+                state.registers[rhs].get_owned_value(),
+                flags.collation(),
+            )
+            .is_some_and(|ord| ord.is_lt())
+            {
                 state.pc = target_pc.to_offset_int();
             } else {
                 state.pc += 1;
@@ -663,7 +733,13 @@ pub fn op_le(
             }
         }
         _ => {
-            if *state.registers[lhs].get_owned_value() <= *state.registers[rhs].get_owned_value() {
+            if compare_operands(
+                state.registers[lhs].get_owned_value(),
+                state.registers[rhs].get_owned_value(),
+                flags.collation(),
+            )
+            .is_some_and(|ord| ord.is_le())
+            {
                 state.pc = target_pc.to_offset_int();
             } else {
                 state.pc += 1;
@@ -706,7 +782,13 @@ pub fn op_gt(
             }
         }
         _ => {
-            if *state.registers[lhs].get_owned_value() > *state.registers[rhs].get_owned_value() {
+            if compare_operands(
+                state.registers[lhs].get_owned_value(),
+                state.registers[rhs].get_owned_value(),
+                flags.collation(),
+            )
+            .is_some_and(|ord| ord.is_gt())
+            {
                 state.pc = target_pc.to_offset_int();
             } else {
                 state.pc += 1;
@@ -749,7 +831,13 @@ pub fn op_ge(
             }
         }
         _ => {
-            if *state.registers[lhs].get_owned_value() >= *state.registers[rhs].get_owned_value() {
+            if compare_operands(
+                state.registers[lhs].get_owned_value(),
+                state.registers[rhs].get_owned_value(),
+                flags.collation(),
+            )
+            .is_some_and(|ord| ord.is_ge())
+            {
                 state.pc = target_pc.to_offset_int();
             } else {
                 state.pc += 1;
@@ -825,10 +913,12 @@ pub fn op_open_read(
     let Insn::OpenRead {
         cursor_id,
         root_page,
+        db,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    let pager = &database_pager(program, pager, *db)?;
     let (_, cursor_type) = program.cursor_ref.get(*cursor_id).unwrap();
     let mv_cursor = match state.mv_tx_id {
         Some(tx_id) => {
@@ -843,16 +933,28 @@ pub fn op_open_read(
     };
     let mut cursors = state.cursors.borrow_mut();
     match cursor_type {
-        CursorType::BTreeTable(_) => {
-            let cursor = BTreeCursor::new(mv_cursor, pager.clone(), *root_page);
+        CursorType::BTreeTable(table) => {
+            let cursor = match table.primary_key_index() {
+                Some(index) => {
+                    let collations = index_key_collations(program, &index)?;
+                    BTreeCursor::new_index(mv_cursor, pager.clone(), *root_page, &index, collations)
+                }
+                None => BTreeCursor::new(mv_cursor, pager.clone(), *root_page),
+            };
             cursors
                 .get_mut(*cursor_id)
                 .unwrap()
                 .replace(Cursor::new_btree(cursor));
         }
         CursorType::BTreeIndex(index) => {
-            let cursor =
-                BTreeCursor::new_index(mv_cursor, pager.clone(), *root_page, index.as_ref());
+            let collations = index_key_collations(program, index)?;
+            let cursor = BTreeCursor::new_index(
+                mv_cursor,
+                pager.clone(),
+                *root_page,
+                index.as_ref(),
+                collations,
+            );
             cursors
                 .get_mut(*cursor_id)
                 .unwrap()
@@ -864,6 +966,9 @@ pub fn op_open_read(
         CursorType::Sorter => {
             panic!("OpenRead on sorter cursor");
         }
+        CursorType::HashTable(_) => {
+            panic!("OpenRead on hash table cursor");
+        }
         CursorType::VirtualTable(_) => {
             panic!("OpenRead on virtual table cursor, use Insn:VOpen instead");
         }
@@ -872,6 +977,32 @@ pub fn op_open_read(
     Ok(InsnFunctionStepResult::Step)
 }
 
+/// The pager of the database `db` of the connection, which is `pager` for the main database.
+fn database_pager(program: &Program, pager: &Rc<Pager>, db: usize) -> Result<Rc<Pager>> {
+    if db == 0 {
+        return Ok(pager.clone());
+    }
+    program.connection.upgrade().unwrap().attached_pager(db)
+}
+
+/// Resolve the collating sequences of the columns of an index.
+fn index_key_collations(program: &Program, index: &Index) -> Result<Vec<CollationSeq>> {
+    if index.key_columns().all(|c| c.collation.is_none()) {
+        return Ok(Vec::new());
+    }
+    let conn = program.connection.upgrade().unwrap();
+    let syms = conn.syms.borrow();
+    index
+        .key_columns()
+        .map(|c| match &c.collation {
+            None => Ok(CollationSeq::Binary),
+            Some(name) => syms.resolve_collation(name).ok_or_else(|| {
+                LimboError::ParseError(format!("no such collation sequence: {}", name))
+            }),
+        })
+        .collect()
+}
+
 pub fn op_vopen(
     program: &Program,
     state: &mut ProgramState,
@@ -1199,9 +1330,11 @@ pub fn op_rewind(
         unreachable!("unexpected Insn {:?}", insn)
     };
     assert!(pc_if_empty.is_offset());
+    state.cancel_deferred_seek(*cursor_id);
     let is_empty = {
         let mut cursor = must_be_btree_cursor!(*cursor_id, program.cursor_ref, state, "Rewind");
         let cursor = cursor.as_btree_mut();
+        cursor.set_null_flag(false);
         return_if_io!(cursor.rewind());
         cursor.is_empty()
     };
@@ -1228,9 +1361,11 @@ pub fn op_last(
         unreachable!("unexpected Insn {:?}", insn)
     };
     assert!(pc_if_empty.is_offset());
+    state.cancel_deferred_seek(*cursor_id);
     let is_empty = {
         let mut cursor = must_be_btree_cursor!(*cursor_id, program.cursor_ref, state, "Last");
         let cursor = cursor.as_btree_mut();
+        cursor.set_null_flag(false);
         return_if_io!(cursor.last());
         cursor.is_empty()
     };
@@ -1257,7 +1392,12 @@ pub fn op_column(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if let Some((index_cursor_id, table_cursor_id)) = state.deferred_seek.take() {
+    // A pending seek only concerns its own table cursor; it may be left over from a loop that
+    // never read from it, e.g. because of an OFFSET.
+    if let Some((index_cursor_id, table_cursor_id)) = state
+        .deferred_seek
+        .take_if(|(_, table_cursor_id)| table_cursor_id == cursor_id)
+    {
         let deferred_seek = {
             let rowid = {
                 let mut index_cursor = state.get_cursor(index_cursor_id);
@@ -1279,6 +1419,11 @@ pub fn op_column(
     let (_, cursor_type) = program.cursor_ref.get(*cursor_id).unwrap();
     match cursor_type {
         CursorType::BTreeTable(_) | CursorType::BTreeIndex(_) => {
+            let storage_position = match cursor_type {
+                CursorType::BTreeTable(table) => table.column_storage_position(*column),
+                _ => *column,
+            };
+            let mut is_missing = false;
             let value = {
                 let mut cursor =
                     must_be_btree_cursor!(*cursor_id, program.cursor_ref, state, "Column");
@@ -1288,9 +1433,12 @@ pub fn op_column(
                     if cursor.get_null_flag() {
                         RefValue::Null
                     } else {
-                        match record.get_value_opt(*column) {
+                        match record.get_value_opt(storage_position) {
                             Some(val) => val.clone(),
-                            None => RefValue::Null,
+                            None => {
+                                is_missing = true;
+                                RefValue::Null
+                            }
                         }
                     }
                 } else {
@@ -1298,19 +1446,38 @@ pub fn op_column(
                 };
                 value
             };
-            // If we are copying a text/blob, let's try to simply update size of text if we need to allocate more and reuse.
-            match (&value, &mut state.registers[*dest]) {
-                (RefValue::Text(text_ref), Register::OwnedValue(OwnedValue::Text(text_reg))) => {
-                    text_reg.value.clear();
-                    text_reg.value.extend_from_slice(text_ref.value.to_slice());
-                }
-                (RefValue::Blob(raw_slice), Register::OwnedValue(OwnedValue::Blob(blob_reg))) => {
-                    blob_reg.clear();
-                    blob_reg.extend_from_slice(raw_slice.to_slice());
-                }
-                _ => {
-                    let reg = &mut state.registers[*dest];
-                    *reg = Register::OwnedValue(value.to_owned());
+            // A row stored before the column was added by ALTER TABLE reads its default.
+            let default = match cursor_type {
+                CursorType::BTreeTable(table) if is_missing => table
+                    .columns
+                    .get(*column)
+                    .and_then(|column| column.default.as_ref())
+                    .and_then(literal_value),
+                _ => None,
+            };
+            if let Some(default) = default {
+                state.registers[*dest] = Register::OwnedValue(default);
+            } else {
+                // If we are copying a text/blob, let's try to simply update size of text if we need to allocate more and reuse.
+                match (&value, &mut state.registers[*dest]) {
+                    (
+                        RefValue::Text(text_ref),
+                        Register::OwnedValue(OwnedValue::Text(text_reg)),
+                    ) => {
+                        text_reg.value.clear();
+                        text_reg.value.extend_from_slice(text_ref.value.to_slice());
+                    }
+                    (
+                        RefValue::Blob(raw_slice),
+                        Register::OwnedValue(OwnedValue::Blob(blob_reg)),
+                    ) => {
+                        blob_reg.clear();
+                        blob_reg.extend_from_slice(raw_slice.to_slice());
+                    }
+                    _ => {
+                        let reg = &mut state.registers[*dest];
+                        *reg = Register::OwnedValue(value.to_owned());
+                    }
                 }
             }
         }
@@ -1330,6 +1497,17 @@ pub fn op_column(
                 state.registers[*dest] = Register::OwnedValue(OwnedValue::Null);
             }
         }
+        CursorType::HashTable(_) => {
+            let value = {
+                let mut cursor = state.get_cursor(*cursor_id);
+                let cursor = cursor.as_hash_table_mut();
+                cursor
+                    .record()
+                    .and_then(|record| record.get_value_opt(*column))
+                    .map_or(OwnedValue::Null, |value| value.to_owned())
+            };
+            state.registers[*dest] = Register::OwnedValue(value);
+        }
         CursorType::Pseudo(_) => {
             let value = {
                 let mut cursor = state.get_cursor(*cursor_id);
@@ -1444,6 +1622,11 @@ pub fn op_result_row(
     let Insn::ResultRow { start_reg, count } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    // The rows of a SELECT in the body of a trigger are discarded.
+    if !state.frames.is_empty() {
+        state.pc += 1;
+        return Ok(InsnFunctionStepResult::Step);
+    }
     let row = Row {
         values: &state.registers[*start_reg] as *const Register,
         count: *count,
@@ -1526,18 +1709,58 @@ pub fn op_halt(
     let Insn::Halt {
         err_code,
         description,
+        ..
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    if matches!(*err_code, SQLITE_IGNORE | SQLITE_CONSTRAINT_TRIGGER) && state.frames.is_empty() {
+        return Err(LimboError::ParseError(
+            "RAISE() may only be used within a trigger-program".to_string(),
+        ));
+    }
     match *err_code {
+        0 if !state.frames.is_empty() => {
+            state.pop_frame(false);
+            return Ok(InsnFunctionStepResult::Step);
+        }
+        0 if state.halt_state.is_none() => {
+            if state.fk_violations > 0 {
+                return Err(LimboError::Constraint(
+                    "FOREIGN KEY constraint failed (19)".to_string(),
+                ));
+            }
+            let connection = program.connection.upgrade().unwrap();
+            if connection.auto_commit.get() {
+                connection.check_deferred_foreign_keys()?;
+            }
+        }
         0 => {}
-        SQLITE_CONSTRAINT_PRIMARYKEY => {
+        SQLITE_IGNORE => {
+            state.pop_frame(true);
+            return Ok(InsnFunctionStepResult::Step);
+        }
+        SQLITE_CONSTRAINT_TRIGGER => {
+            return Err(LimboError::Constraint(format!("{} (19)", description)));
+        }
+        SQLITE_CONSTRAINT_PRIMARYKEY | SQLITE_CONSTRAINT_UNIQUE => {
             return Err(LimboError::Constraint(format!(
                 "UNIQUE constraint failed: {} (19)",
                 description
             )));
         }
+        SQLITE_CONSTRAINT_NOTNULL => {
+            return Err(LimboError::Constraint(format!(
+                "NOT NULL constraint failed: {} (19)",
+                description
+            )));
+        }
+        SQLITE_CONSTRAINT_CHECK => {
+            return Err(LimboError::Constraint(format!(
+                "CHECK constraint failed: {} (19)",
+                description
+            )));
+        }
         _ => {
             return Err(LimboError::Constraint(format!(
                 "undocumented halt error code {}",
@@ -1564,6 +1787,11 @@ pub fn op_transaction(
     let Insn::Transaction { write } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    // A trigger sub-program runs in the transaction of the statement that fired it.
+    if !state.frames.is_empty() {
+        state.pc += 1;
+        return Ok(InsnFunctionStepResult::Step);
+    }
     if let Some(mv_store) = &mv_store {
         if state.mv_tx_id.is_none() {
             let tx_id = mv_store.begin_tx();
@@ -1588,21 +1816,36 @@ pub fn op_transaction(
             (TransactionState::None, false) => (TransactionState::Read, true),
         };
 
+        // The transaction spans the attached databases too.
+        let pagers: Vec<_> = std::iter::once(pager.clone())
+            .chain(connection.attached_pagers())
+            .collect();
         if updated && matches!(current_state, TransactionState::None) {
-            if let LimboResult::Busy = pager.begin_read_tx()? {
-                return Ok(InsnFunctionStepResult::Busy);
+            for pager in &pagers {
+                if let LimboResult::Busy = pager.begin_read_tx()? {
+                    return Ok(InsnFunctionStepResult::Busy);
+                }
             }
         }
 
         if updated && matches!(new_transaction_state, TransactionState::Write) {
-            if let LimboResult::Busy = pager.begin_write_tx()? {
-                tracing::trace!("begin_write_tx busy");
-                return Ok(InsnFunctionStepResult::Busy);
+            for pager in &pagers {
+                if let LimboResult::Busy = pager.begin_write_tx()? {
+                    tracing::trace!("begin_write_tx busy");
+                    return Ok(InsnFunctionStepResult::Busy);
+                }
             }
         }
         if updated {
             connection.transaction_state.replace(new_transaction_state);
         }
+        if *write {
+            for pager in &pagers {
+                pager.begin_statement();
+            }
+            state.in_statement = true;
+            state.stmt_deferred_fk_violations = connection.deferred_fk_violations.get();
+        }
     }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
@@ -1635,9 +1878,13 @@ pub fn op_auto_commit(
 
     if *auto_commit != conn.auto_commit.get() {
         if *rollback {
-            todo!("Rollback is not implemented");
+            conn.rollback()?;
         } else {
+            if *auto_commit {
+                conn.check_deferred_foreign_keys()?;
+            }
             conn.auto_commit.replace(*auto_commit);
+            conn.clear_savepoints();
         }
     } else if !*auto_commit {
         return Err(LimboError::TxError(
@@ -1661,6 +1908,31 @@ pub fn op_auto_commit(
     };
 }
 
+pub fn op_savepoint(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    _pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Savepoint { op, name } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    if mv_store.is_some() {
+        return Err(LimboError::TxError(
+            "savepoints are not supported with MVCC".to_string(),
+        ));
+    }
+    let conn = program.connection.upgrade().unwrap();
+    match op {
+        SavepointOp::Begin => conn.open_savepoint(name),
+        SavepointOp::Release => conn.release_savepoint(name)?,
+        SavepointOp::Rollback => conn.rollback_to_savepoint(name)?,
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_goto(
     program: &Program,
     state: &mut ProgramState,
@@ -1806,7 +2078,10 @@ pub fn op_row_id(
     let Insn::RowId { cursor_id, dest } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if let Some((index_cursor_id, table_cursor_id)) = state.deferred_seek.take() {
+    if let Some((index_cursor_id, table_cursor_id)) = state
+        .deferred_seek
+        .take_if(|(_, table_cursor_id)| table_cursor_id == cursor_id)
+    {
         let deferred_seek = {
             let rowid = {
                 let mut index_cursor = state.get_cursor(index_cursor_id);
@@ -1827,7 +2102,9 @@ pub fn op_row_id(
     }
     let mut cursors = state.cursors.borrow_mut();
     if let Some(Cursor::BTree(btree_cursor)) = cursors.get_mut(*cursor_id).unwrap() {
-        if let Some(ref rowid) = btree_cursor.rowid()? {
+        if btree_cursor.get_null_flag() {
+            state.registers[*dest] = Register::OwnedValue(OwnedValue::Null);
+        } else if let Some(ref rowid) = btree_cursor.rowid()? {
             state.registers[*dest] = Register::OwnedValue(OwnedValue::Integer(*rowid as i64));
         } else {
             state.registers[*dest] = Register::OwnedValue(OwnedValue::Null);
@@ -1864,8 +2141,21 @@ pub fn op_idx_row_id(
     };
     let mut cursors = state.cursors.borrow_mut();
     let cursor = cursors.get_mut(*cursor_id).unwrap().as_mut().unwrap();
-    let cursor = cursor.as_btree_mut();
-    let rowid = cursor.rowid()?;
+    let rowid = match cursor {
+        // The entries of a hash table end with the rowid, like the ones of an index.
+        Cursor::HashTable(hash_table) => match hash_table.record().and_then(|r| r.last_value()) {
+            Some(RefValue::Integer(rowid)) => Some(*rowid as u64),
+            _ => None,
+        },
+        _ => {
+            let cursor = cursor.as_btree_mut();
+            if cursor.get_null_flag() {
+                None
+            } else {
+                cursor.rowid()?
+            }
+        }
+    };
     state.registers[*dest] = match rowid {
         Some(rowid) => Register::OwnedValue(OwnedValue::Integer(rowid as i64)),
         None => Register::OwnedValue(OwnedValue::Null),
@@ -1890,6 +2180,7 @@ pub fn op_seek_rowid(
         unreachable!("unexpected Insn {:?}", insn)
     };
     assert!(target_pc.is_offset());
+    state.cancel_deferred_seek(*cursor_id);
     let pc = {
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_btree_mut();
@@ -2071,7 +2362,12 @@ pub fn op_idx_ge(
             let idx_values = &idx_values[..record_from_regs.len()];
             let record_values = record_from_regs.get_values();
             let record_values = &record_values[..idx_values.len()];
-            let ord = compare_immutable(&idx_values, &record_values, cursor.index_key_sort_order);
+            let ord = compare_immutable(
+                idx_values,
+                record_values,
+                cursor.index_key_sort_order,
+                &cursor.index_key_collations,
+            );
             if ord.is_ge() {
                 target_pc.to_offset_int()
             } else {
@@ -2130,7 +2426,12 @@ pub fn op_idx_le(
             let idx_values = idx_record.get_values();
             let idx_values = &idx_values[..record_from_regs.len()];
             let record_values = record_from_regs.get_values();
-            let ord = compare_immutable(&idx_values, &record_values, cursor.index_key_sort_order);
+            let ord = compare_immutable(
+                idx_values,
+                record_values,
+                cursor.index_key_sort_order,
+                &cursor.index_key_collations,
+            );
             if ord.is_le() {
                 target_pc.to_offset_int()
             } else {
@@ -2171,7 +2472,12 @@ pub fn op_idx_gt(
             let idx_values = idx_record.get_values();
             let idx_values = &idx_values[..record_from_regs.len()];
             let record_values = record_from_regs.get_values();
-            let ord = compare_immutable(&idx_values, &record_values, cursor.index_key_sort_order);
+            let ord = compare_immutable(
+                idx_values,
+                record_values,
+                cursor.index_key_sort_order,
+                &cursor.index_key_collations,
+            );
             if ord.is_gt() {
                 target_pc.to_offset_int()
             } else {
@@ -2212,7 +2518,12 @@ pub fn op_idx_lt(
             let idx_values = idx_record.get_values();
             let idx_values = &idx_values[..record_from_regs.len()];
             let record_values = record_from_regs.get_values();
-            let ord = compare_immutable(&idx_values, &record_values, cursor.index_key_sort_order);
+            let ord = compare_immutable(
+                idx_values,
+                record_values,
+                cursor.index_key_sort_order,
+                &cursor.index_key_collations,
+            );
             if ord.is_lt() {
                 target_pc.to_offset_int()
             } else {
@@ -2253,6 +2564,21 @@ pub fn op_decr_jump_zero(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_coll_seq(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::CollSeq { collation } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    state.collation = Some(*collation);
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_agg_step(
     program: &Program,
     state: &mut ProgramState,
@@ -2285,28 +2611,8 @@ pub fn op_agg_step(
             AggFunc::Count | AggFunc::Count0 => {
                 Register::Aggregate(AggContext::Count(OwnedValue::Integer(0)))
             }
-            AggFunc::Max => {
-                let col = state.registers[*col].get_owned_value();
-                match col {
-                    OwnedValue::Integer(_) => Register::Aggregate(AggContext::Max(None)),
-                    OwnedValue::Float(_) => Register::Aggregate(AggContext::Max(None)),
-                    OwnedValue::Text(_) => Register::Aggregate(AggContext::Max(None)),
-                    _ => {
-                        unreachable!();
-                    }
-                }
-            }
-            AggFunc::Min => {
-                let col = state.registers[*col].get_owned_value();
-                match col {
-                    OwnedValue::Integer(_) => Register::Aggregate(AggContext::Min(None)),
-                    OwnedValue::Float(_) => Register::Aggregate(AggContext::Min(None)),
-                    OwnedValue::Text(_) => Register::Aggregate(AggContext::Min(None)),
-                    _ => {
-                        unreachable!();
-                    }
-                }
-            }
+            AggFunc::Max => Register::Aggregate(AggContext::Max(None)),
+            AggFunc::Min => Register::Aggregate(AggContext::Min(None)),
             AggFunc::GroupConcat | AggFunc::StringAgg => {
                 Register::Aggregate(AggContext::GroupConcat(OwnedValue::build_text("")))
             }
@@ -2383,6 +2689,7 @@ pub fn op_agg_step(
             };
         }
         AggFunc::Max => {
+            let collation = state.collation.take().unwrap_or_default();
             let col = state.registers[*col].clone();
             let Register::Aggregate(agg) = state.registers[*acc_reg].borrow_mut() else {
                 unreachable!();
@@ -2392,6 +2699,8 @@ pub fn op_agg_step(
             };
 
             match (acc.as_mut(), col.get_owned_value()) {
+                // NULLs are ignored by max()
+                (_, OwnedValue::Null) => {}
                 (None, value) => {
                     *acc = Some(value.clone());
                 }
@@ -2406,7 +2715,10 @@ pub fn op_agg_step(
                     }
                 }
                 (Some(OwnedValue::Text(ref mut current_max)), OwnedValue::Text(value)) => {
-                    if value.value > current_max.value {
+                    if collation
+                        .compare_strings(&value.value, &current_max.value)
+                        .is_gt()
+                    {
                         *current_max = value.clone();
                     }
                 }
@@ -2416,6 +2728,7 @@ pub fn op_agg_step(
             }
         }
         AggFunc::Min => {
+            let collation = state.collation.take().unwrap_or_default();
             let col = state.registers[*col].clone();
             let Register::Aggregate(agg) = state.registers[*acc_reg].borrow_mut() else {
                 unreachable!();
@@ -2425,6 +2738,8 @@ pub fn op_agg_step(
             };
 
             match (acc.as_mut(), col.get_owned_value()) {
+                // NULLs are ignored by min()
+                (_, OwnedValue::Null) => {}
                 (None, value) => {
                     *acc.borrow_mut() = Some(value.clone());
                 }
@@ -2439,7 +2754,10 @@ pub fn op_agg_step(
                     }
                 }
                 (Some(OwnedValue::Text(ref mut current_min)), OwnedValue::Text(text)) => {
-                    if text.value < current_min.value {
+                    if collation
+                        .compare_strings(&text.value, &current_min.value)
+                        .is_lt()
+                    {
                         *current_min = text.clone();
                     }
                 }
@@ -2689,6 +3007,7 @@ pub fn op_sorter_open(
         cursor_id,
         columns: _,
         order,
+        collations,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
@@ -2701,7 +3020,7 @@ pub fn op_sorter_open(
             _ => unreachable!(),
         })
         .collect();
-    let cursor = Sorter::new(order);
+    let cursor = Sorter::new(order, collations.clone());
     let mut cursors = state.cursors.borrow_mut();
     cursors
         .get_mut(*cursor_id)
@@ -3348,12 +3667,14 @@ pub fn op_function(
                 state.registers[*dest] = Register::OwnedValue(result);
             }
             ScalarFunc::Min => {
+                let collation = state.collation.take().unwrap_or_default();
                 let reg_values = &state.registers[*start_reg..*start_reg + arg_count];
-                state.registers[*dest] = Register::OwnedValue(exec_min(reg_values));
+                state.registers[*dest] = Register::OwnedValue(exec_min(reg_values, collation));
             }
             ScalarFunc::Max => {
+                let collation = state.collation.take().unwrap_or_default();
                 let reg_values = &state.registers[*start_reg..*start_reg + arg_count];
-                state.registers[*dest] = Register::OwnedValue(exec_max(reg_values));
+                state.registers[*dest] = Register::OwnedValue(exec_max(reg_values, collation));
             }
             ScalarFunc::Nullif => {
                 let first_value = &state.registers[*start_reg];
@@ -3601,6 +3922,20 @@ pub fn op_function(
         crate::function::Func::Agg(_) => {
             unreachable!("Aggregate functions should not be handled here")
         }
+        crate::function::Func::AlterTable(alter_func) => {
+            let result = match state.registers[*start_reg].get_owned_value() {
+                OwnedValue::Text(sql) => {
+                    let args = state.registers[*start_reg + 1..*start_reg + arg_count]
+                        .iter()
+                        .map(|arg| arg.get_owned_value().to_string())
+                        .collect::<Vec<_>>();
+                    let sql = exec_alter_table_func(alter_func, sql.as_str(), &args)?;
+                    OwnedValue::build_text(&sql)
+                }
+                _ => OwnedValue::Null,
+            };
+            state.registers[*dest] = Register::OwnedValue(result);
+        }
     }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
@@ -3746,7 +4081,11 @@ pub fn op_delete(
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::Delete { cursor_id } = insn else {
+    let Insn::Delete {
+        cursor_id,
+        count_change,
+    } = insn
+    else {
         unreachable!("unexpected Insn {:?}", insn)
     };
     {
@@ -3754,8 +4093,10 @@ pub fn op_delete(
         let cursor = cursor.as_btree_mut();
         return_if_io!(cursor.delete());
     }
-    let prev_changes = program.n_change.get();
-    program.n_change.set(prev_changes + 1);
+    if *count_change {
+        let prev_changes = program.n_change.get();
+        program.n_change.set(prev_changes + 1);
+    }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -3775,8 +4116,13 @@ pub fn op_idx_insert(
     } = *insn
     {
         let (_, cursor_type) = program.cursor_ref.get(cursor_id).unwrap();
-        let CursorType::BTreeIndex(index_meta) = cursor_type else {
-            panic!("IdxInsert: not a BTree index cursor");
+        let index_meta = match cursor_type {
+            CursorType::BTreeIndex(index) => index.clone(),
+            CursorType::BTreeTable(table) => match table.primary_key_index() {
+                Some(index) => Arc::new(index),
+                None => panic!("IdxInsert: not a BTree index cursor"),
+            },
+            _ => panic!("IdxInsert: not a BTree index cursor"),
         };
         {
             let mut cursor = state.get_cursor(cursor_id);
@@ -3792,7 +4138,7 @@ pub fn op_idx_insert(
             } else {
                 if index_meta.unique {
                     // check for uniqueness violation
-                    match cursor.key_exists_in_index(record)? {
+                    match cursor.key_exists_in_index(record, index_meta.columns.len())? {
                         CursorResult::Ok(true) => {
                             return Err(LimboError::Constraint(
                                 "UNIQUE constraint failed: duplicate key".into(),
@@ -3812,7 +4158,11 @@ pub fn op_idx_insert(
             // because it could trigger a movement to child page after a balance root which will leave the current page as the root page.
             return_if_io!(cursor.insert(&BTreeKey::new_index_key(record), moved_before));
         }
-        // TODO: flag optimizations, update n_change if OPFLAG_NCHANGE
+        // The rows of a WITHOUT ROWID table are written with IdxInsert
+        if flags.has(IdxInsertFlags::NCHANGE) {
+            let prev_changes = program.n_change.get();
+            program.n_change.set(prev_changes + 1);
+        }
         state.pc += 1;
     }
     Ok(InsnFunctionStepResult::Step)
@@ -3850,33 +4200,29 @@ pub fn op_must_be_int(
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::MustBeInt { reg } = insn else {
+    let Insn::MustBeInt { reg, target_pc } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    match &state.registers[*reg].get_owned_value() {
-        OwnedValue::Integer(_) => {}
-        OwnedValue::Float(f) => match cast_real_to_integer(*f) {
-            Ok(i) => state.registers[*reg] = Register::OwnedValue(OwnedValue::Integer(i)),
-            Err(_) => crate::bail_parse_error!(
-                "MustBeInt: the value in register cannot be cast to integer"
-            ),
-        },
+    let value = match &state.registers[*reg].get_owned_value() {
+        OwnedValue::Integer(i) => Some(*i),
+        OwnedValue::Float(f) => cast_real_to_integer(*f).ok(),
         OwnedValue::Text(text) => match checked_cast_text_to_numeric(text.as_str()) {
-            Ok(OwnedValue::Integer(i)) => {
-                state.registers[*reg] = Register::OwnedValue(OwnedValue::Integer(i))
-            }
-            Ok(OwnedValue::Float(f)) => {
-                state.registers[*reg] = Register::OwnedValue(OwnedValue::Integer(f as i64))
-            }
-            _ => crate::bail_parse_error!(
-                "MustBeInt: the value in register cannot be cast to integer"
-            ),
+            Ok(OwnedValue::Integer(i)) => Some(i),
+            Ok(OwnedValue::Float(f)) => Some(f as i64),
+            _ => None,
         },
-        _ => {
+        _ => None,
+    };
+    match (value, target_pc) {
+        (Some(i), _) => {
+            state.registers[*reg] = Register::OwnedValue(OwnedValue::Integer(i));
+            state.pc += 1;
+        }
+        (None, Some(target_pc)) => state.pc = target_pc.to_offset_int(),
+        (None, None) => {
             crate::bail_parse_error!("MustBeInt: the value in register cannot be cast to integer");
         }
-    };
-    state.pc += 1;
+    }
     Ok(InsnFunctionStepResult::Step)
 }
 
@@ -3980,11 +4326,12 @@ pub fn op_open_write(
     let Insn::OpenWrite {
         cursor_id,
         root_page,
-        ..
+        db,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    let pager = &database_pager(program, pager, *db)?;
     let root_page = match root_page {
         RegisterOrLiteral::Literal(lit) => *lit as u64,
         RegisterOrLiteral::Register(reg) => match &state.registers[*reg].get_owned_value() {
@@ -3998,8 +4345,10 @@ pub fn op_open_write(
     };
     let (_, cursor_type) = program.cursor_ref.get(*cursor_id).unwrap();
     let mut cursors = state.cursors.borrow_mut();
+    // A WITHOUT ROWID table is written like its PRIMARY KEY index
     let maybe_index = match cursor_type {
-        CursorType::BTreeIndex(index) => Some(index),
+        CursorType::BTreeIndex(index) => Some(index.clone()),
+        CursorType::BTreeTable(table) => table.primary_key_index().map(Arc::new),
         _ => None,
     };
     let mv_cursor = match state.mv_tx_id {
@@ -4014,8 +4363,14 @@ pub fn op_open_write(
         None => None,
     };
     if let Some(index) = maybe_index {
-        let cursor =
-            BTreeCursor::new_index(mv_cursor, pager.clone(), root_page as usize, index.as_ref());
+        let collations = index_key_collations(program, &index)?;
+        let cursor = BTreeCursor::new_index(
+            mv_cursor,
+            pager.clone(),
+            root_page as usize,
+            index.as_ref(),
+            collations,
+        );
         cursors
             .get_mut(*cursor_id)
             .unwrap()
@@ -4063,11 +4418,7 @@ pub fn op_create_btree(
     let Insn::CreateBtree { db, root, flags } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *db > 0 {
-        // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
-    }
-    let root_page = pager.btree_create(flags);
+    let root_page = database_pager(program, pager, *db)?.btree_create(flags)?;
     state.registers[*root] = Register::OwnedValue(OwnedValue::Integer(root_page as i64));
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
@@ -4082,17 +4433,42 @@ pub fn op_destroy(
 ) -> Result<InsnFunctionStepResult> {
     let Insn::Destroy {
         root,
-        former_root_reg: _,
-        is_temp,
+        former_root_reg,
+        db,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *is_temp == 1 {
-        todo!("temp databases not implemented yet.");
-    }
+    let pager = &database_pager(program, pager, *db)?;
     let mut cursor = BTreeCursor::new(None, pager.clone(), *root);
-    cursor.btree_destroy()?;
+    let mut moved_from = 0;
+    if pager.auto_vacuum_mode() == AutoVacuumMode::None {
+        // The cursor is not kept across steps, so the pages it reads are waited for here.
+        loop {
+            match cursor.btree_destroy()? {
+                CursorResult::Ok(()) => break,
+                CursorResult::IO => pager.io.run_once()?,
+            }
+        }
+    } else {
+        // The root page is freed last, as the largest root page may have to be moved into it.
+        loop {
+            match cursor.btree_clear()? {
+                CursorResult::Ok(()) => break,
+                CursorResult::IO => pager.io.run_once()?,
+            }
+        }
+        moved_from = pager.drop_root_page(*root)?;
+        if moved_from != 0 {
+            let conn = program.connection.upgrade().unwrap();
+            conn.database_schema(*db)?
+                .write()
+                .root_page_moved(moved_from, *root);
+            conn.schema_changed.set(true);
+        }
+    }
+    state.registers[*former_root_reg] =
+        Register::OwnedValue(OwnedValue::Integer(moved_from as i64));
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -4113,153 +4489,344 @@ pub fn op_drop_table(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *db > 0 {
-        todo!("temp databases not implemented yet");
-    }
     if let Some(conn) = program.connection.upgrade() {
-        let mut schema = conn.schema.write();
+        conn.schema_changed.set(true);
+        let schema = conn.database_schema(*db)?;
+        let mut schema = schema.write();
         schema.remove_indices_for_table(table_name);
+        schema.remove_triggers_for_table(table_name);
         schema.remove_table(table_name);
     }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
 
-pub fn op_close(
+pub fn op_drop_trigger(
     program: &Program,
     state: &mut ProgramState,
     insn: &Insn,
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::Close { cursor_id } = insn else {
+    let Insn::DropTrigger { db, trigger_name } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let mut cursors = state.cursors.borrow_mut();
-    cursors.get_mut(*cursor_id).unwrap().take();
+    if let Some(conn) = program.connection.upgrade() {
+        conn.schema_changed.set(true);
+        conn.database_schema(*db)?
+            .write()
+            .remove_trigger(trigger_name);
+    }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
 
-pub fn op_is_null(
+pub fn op_drop_index(
     program: &Program,
     state: &mut ProgramState,
     insn: &Insn,
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::IsNull { reg, target_pc } = insn else {
+    let Insn::DropIndex { db, index_name } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if matches!(
-        state.registers[*reg],
-        Register::OwnedValue(OwnedValue::Null)
-    ) {
-        state.pc = target_pc.to_offset_int();
-    } else {
-        state.pc += 1;
+    if let Some(conn) = program.connection.upgrade() {
+        conn.schema_changed.set(true);
+        conn.database_schema(*db)?.write().remove_index(index_name);
     }
+    state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
 
-pub fn op_page_count(
+pub fn op_clear(
     program: &Program,
     state: &mut ProgramState,
     insn: &Insn,
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::PageCount { db, dest } = insn else {
+    let Insn::Clear { root, db } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *db > 0 {
-        // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
-    }
-    // SQLite returns "0" on an empty database, and 2 on the first insertion,
-    // so we'll mimic that behavior.
-    let mut pages = pager.db_header.lock().database_size.into();
-    if pages == 1 {
-        pages = 0;
+    let pager = &database_pager(program, pager, *db)?;
+    let mut cursor = BTreeCursor::new(None, pager.clone(), *root);
+    loop {
+        match cursor.btree_clear()? {
+            CursorResult::Ok(()) => break,
+            CursorResult::IO => pager.io.run_once()?,
+        }
     }
-    state.registers[*dest] = Register::OwnedValue(OwnedValue::Integer(pages));
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
 
-pub fn op_parse_schema(
+/// Like SQLITE_MAX_TRIGGER_DEPTH, the most triggers that can run inside each other. Each trigger
+/// takes two frames: one for its program, and one for the command of its body that is running.
+const MAX_TRIGGER_DEPTH: usize = 1000;
+
+pub fn op_program(
     program: &Program,
     state: &mut ProgramState,
     insn: &Insn,
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::ParseSchema {
-        db: _,
-        where_clause,
+    let Insn::Program {
+        subprogram,
+        params_start,
+        ignore_target,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let conn = program.connection.upgrade();
-    let conn = conn.as_ref().unwrap();
-    let stmt = conn.prepare(format!(
-        "SELECT * FROM sqlite_schema WHERE {}",
-        where_clause
-    ))?;
-    let mut schema = conn.schema.write();
-    // TODO: This function below is synchronous, make it async
-    {
-        parse_schema_rows(
-            Some(stmt),
-            &mut schema,
-            conn.pager.io.clone(),
-            &conn.syms.borrow(),
-            state.mv_tx_id,
-        )?;
+    if state.frames.len() >= 2 * MAX_TRIGGER_DEPTH {
+        return Err(LimboError::ParseError(
+            "too many levels of trigger recursion".to_string(),
+        ));
     }
-    state.pc += 1;
+    let subprogram = match subprogram {
+        Subprogram::Index(index) => program.subprograms[*index].clone(),
+        Subprogram::Frame(frames_up) => state.frames[state.frames.len() - frames_up - 1]
+            .program
+            .clone(),
+    };
+    state.push_frame(
+        subprogram,
+        state.pc + 1,
+        ignore_target.to_offset_int(),
+        *params_start,
+    );
     Ok(InsnFunctionStepResult::Step)
 }
 
-pub fn op_read_cookie(
+pub fn op_fk_counter(
     program: &Program,
     state: &mut ProgramState,
     insn: &Insn,
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::ReadCookie { db, dest, cookie } = insn else {
+    let Insn::FkCounter {
+        deferred,
+        increment,
+    } = insn
+    else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *db > 0 {
-        // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
+    if *deferred {
+        let connection = program.connection.upgrade().unwrap();
+        let violations = &connection.deferred_fk_violations;
+        violations.set(violations.get() + increment);
+    } else {
+        state.fk_violations += increment;
     }
-    let cookie_value = match cookie {
-        Cookie::UserVersion => pager.db_header.lock().user_version.into(),
-        cookie => todo!("{cookie:?} is not yet implement for ReadCookie"),
-    };
-    state.registers[*dest] = Register::OwnedValue(OwnedValue::Integer(cookie_value));
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
 
-pub fn op_shift_right(
+pub fn op_fk_if_zero(
     program: &Program,
     state: &mut ProgramState,
     insn: &Insn,
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::ShiftRight { lhs, rhs, dest } = insn else {
+    let Insn::FkIfZero {
+        deferred,
+        target_pc,
+    } = insn
+    else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    state.registers[*dest] = Register::OwnedValue(exec_shift_right(
-        state.registers[*lhs].get_owned_value(),
-        state.registers[*rhs].get_owned_value(),
-    ));
-    state.pc += 1;
+    let violations = if *deferred {
+        let connection = program.connection.upgrade().unwrap();
+        connection.deferred_fk_violations.get()
+    } else {
+        state.fk_violations
+    };
+    if violations == 0 {
+        state.pc = target_pc.to_offset_int();
+    } else {
+        state.pc += 1;
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_param(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Param { offset, dest } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let frame = state
+        .frames
+        .last()
+        .expect("Param used outside of a trigger sub-program");
+    state.registers[*dest] = frame.registers[frame.params_start + *offset].clone();
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_close(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Close { cursor_id } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let mut cursors = state.cursors.borrow_mut();
+    cursors.get_mut(*cursor_id).unwrap().take();
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_is_null(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::IsNull { reg, target_pc } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    if matches!(
+        state.registers[*reg],
+        Register::OwnedValue(OwnedValue::Null)
+    ) {
+        state.pc = target_pc.to_offset_int();
+    } else {
+        state.pc += 1;
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_page_count(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::PageCount { db, dest } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let pager = database_pager(program, pager, *db)?;
+    // SQLite returns "0" on an empty database, and 2 on the first insertion,
+    // so we'll mimic that behavior.
+    let mut pages = pager.db_header.lock().database_size.into();
+    if pages == 1 {
+        pages = 0;
+    }
+    state.registers[*dest] = Register::OwnedValue(OwnedValue::Integer(pages));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_parse_schema(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::ParseSchema { db, where_clause } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let conn = program.connection.upgrade();
+    let conn = conn.as_ref().unwrap();
+    let stmt = conn.prepare(format!(
+        "SELECT * FROM {} WHERE {}",
+        conn.schema_table_name(*db),
+        where_clause
+    ))?;
+    let schema = conn.database_schema(*db)?;
+    let mut schema = schema.write();
+    // TODO: This function below is synchronous, make it async
+    {
+        parse_schema_rows(
+            Some(stmt),
+            &mut schema,
+            conn.pager.io.clone(),
+            &conn.syms.borrow(),
+            state.mv_tx_id,
+        )?;
+    }
+    conn.schema_changed.set(true);
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_load_analysis(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::LoadAnalysis { db } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    if *db > 0 {
+        return Err(LimboError::InternalError(
+            "loading the statistics of an attached database is not supported".to_string(),
+        ));
+    }
+    if let Some(conn) = program.connection.upgrade() {
+        conn.load_analysis()?;
+        conn.schema_changed.set(true);
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_read_cookie(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::ReadCookie { db, dest, cookie } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let pager = database_pager(program, pager, *db)?;
+    let cookie_value = match cookie {
+        Cookie::UserVersion => pager.db_header.lock().user_version.into(),
+        Cookie::LargestRootPageNumber => {
+            pager.db_header.lock().vacuum_mode_largest_root_page.into()
+        }
+        Cookie::IncrementalVacuum => pager.db_header.lock().incremental_vacuum_enabled.into(),
+        cookie => todo!("{cookie:?} is not yet implement for ReadCookie"),
+    };
+    state.registers[*dest] = Register::OwnedValue(OwnedValue::Integer(cookie_value));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_shift_right(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::ShiftRight { lhs, rhs, dest } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    state.registers[*dest] = Register::OwnedValue(exec_shift_right(
+        state.registers[*lhs].get_owned_value(),
+        state.registers[*rhs].get_owned_value(),
+    ));
+    state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
 
@@ -4442,7 +5009,7 @@ pub fn op_open_ephemeral(
         &CreateBTreeFlags::new_index()
     };
 
-    let root_page = pager.btree_create(flag);
+    let root_page = pager.btree_create(flag)?;
 
     let (_, cursor_type) = program.cursor_ref.get(cursor_id).unwrap();
     let mv_cursor = match state.mv_tx_id {
@@ -4469,7 +5036,9 @@ pub fn op_open_ephemeral(
                 .unwrap()
                 .replace(Cursor::new_btree(cursor));
         }
-        CursorType::BTreeIndex(_) => {
+        CursorType::BTreeIndex(index) => {
+            cursor.index_key_sort_order = IndexKeySortOrder::from_index(index);
+            cursor.index_key_collations = index_key_collations(program, index)?;
             cursors
                 .get_mut(cursor_id)
                 .unwrap()
@@ -4481,6 +5050,9 @@ pub fn op_open_ephemeral(
         CursorType::Sorter => {
             panic!("OpenEphemeral on sorter cursor");
         }
+        CursorType::HashTable(_) => {
+            panic!("OpenEphemeral on hash table cursor");
+        }
         CursorType::VirtualTable(_) => {
             panic!("OpenEphemeral on virtual table cursor, use Insn::VOpen instead");
         }
@@ -4490,6 +5062,132 @@ pub fn op_open_ephemeral(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_hash_open(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::HashOpen {
+        cursor_id,
+        key_count,
+        collations,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    // Like the sorter of SQLite, a hash table may hold as much memory as the page cache.
+    let memory_budget = {
+        let header = program.database_header.lock();
+        match header.default_page_cache_size {
+            kib if kib < 0 => kib.unsigned_abs() as usize * 1024,
+            pages => pages as usize * header.page_size as usize,
+        }
+    };
+    let hash_table = HashTable::new(
+        pager.io.clone(),
+        *key_count,
+        collations.clone(),
+        memory_budget,
+    );
+    state
+        .cursors
+        .borrow_mut()
+        .get_mut(*cursor_id)
+        .unwrap()
+        .replace(Cursor::HashTable(hash_table));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_hash_insert(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::HashInsert {
+        cursor_id,
+        record_reg,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    {
+        let mut cursor = state.get_cursor(*cursor_id);
+        let cursor = cursor.as_hash_table_mut();
+        let record = match &state.registers[*record_reg] {
+            Register::Record(record) => record,
+            _ => unreachable!("HashInsert on non-record register"),
+        };
+        cursor.insert(record)?;
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_hash_probe(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::HashProbe {
+        cursor_id,
+        key_reg,
+        target_pc,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    assert!(target_pc.is_offset());
+    let found = {
+        let mut cursor = state.get_cursor(*cursor_id);
+        let cursor = cursor.as_hash_table_mut();
+        let key = ImmutableRecord::from_registers(
+            &state.registers[*key_reg..*key_reg + cursor.key_count()],
+        );
+        cursor.probe(key)?
+    };
+    if found {
+        state.pc += 1;
+    } else {
+        state.pc = target_pc.to_offset_int();
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_hash_next(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::HashNext {
+        cursor_id,
+        pc_if_next,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    assert!(pc_if_next.is_offset());
+    let has_more = {
+        let mut cursor = state.get_cursor(*cursor_id);
+        let cursor = cursor.as_hash_table_mut();
+        cursor.next()?
+    };
+    if has_more {
+        state.pc = pc_if_next.to_offset_int();
+    } else {
+        state.pc += 1;
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
 /// Execute the [Insn::Once] instruction.
 ///
 /// This instruction is used to execute a block of code only once.
@@ -4565,6 +5263,75 @@ pub fn op_not_found(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_no_conflict(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::NoConflict {
+        cursor_id,
+        target_pc,
+        record_reg,
+        num_regs,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let key = &state.registers[*record_reg..*record_reg + *num_regs];
+    // NULLs are distinct from each other, so a key containing one never conflicts
+    if key
+        .iter()
+        .any(|reg| matches!(reg.get_owned_value(), OwnedValue::Null))
+    {
+        state.pc = target_pc.to_offset_int();
+        return Ok(InsnFunctionStepResult::Step);
+    }
+    let found = {
+        let record = make_record(&state.registers, record_reg, num_regs);
+        let mut cursor = state.get_cursor(*cursor_id);
+        let cursor = cursor.as_btree_mut();
+        return_if_io!(cursor.seek(SeekKey::IndexKey(&record), SeekOp::EQ))
+    };
+    if found {
+        state.pc += 1;
+    } else {
+        state.pc = target_pc.to_offset_int();
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_idx_delete(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::IdxDelete {
+        cursor_id,
+        start_reg,
+        num_regs,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let record = make_record(&state.registers, start_reg, num_regs);
+    {
+        let mut cursor = state.get_cursor(*cursor_id);
+        let cursor = cursor.as_btree_mut();
+        // A deletion that is waiting on I/O has already found its entry
+        let found = cursor.is_delete_in_progress()
+            || return_if_io!(cursor.seek(SeekKey::IndexKey(&record), SeekOp::EQ));
+        if found {
+            return_if_io!(cursor.delete());
+        }
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_affinity(
     program: &Program,
     state: &mut ProgramState,
@@ -4599,6 +5366,110 @@ pub fn op_affinity(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_attach(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Attach { path_reg, name_reg } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let path = state.registers[*path_reg].get_owned_value().to_string();
+    let name = state.registers[*name_reg].get_owned_value().to_string();
+    program.connection.upgrade().unwrap().attach(&path, &name)?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_detach(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Detach { name_reg } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let name = state.registers[*name_reg].get_owned_value().to_string();
+    program.connection.upgrade().unwrap().detach(&name)?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_vacuum(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Vacuum { db, into_reg } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let into = match into_reg {
+        Some(into_reg) => match state.registers[*into_reg].get_owned_value() {
+            OwnedValue::Text(path) => Some(path.as_str().to_string()),
+            _ => {
+                return Err(LimboError::ParseError(
+                    "non-text filename in VACUUM INTO".to_string(),
+                ))
+            }
+        },
+        None => None,
+    };
+    program
+        .connection
+        .upgrade()
+        .unwrap()
+        .vacuum(*db, into.as_deref())?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_set_cookie(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::SetCookie { db, cookie, value } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let pager = database_pager(program, pager, *db)?;
+    let mut header = pager.db_header.lock();
+    match cookie {
+        Cookie::LargestRootPageNumber => header.vacuum_mode_largest_root_page = *value,
+        Cookie::IncrementalVacuum => header.incremental_vacuum_enabled = *value,
+        cookie => todo!("{cookie:?} is not yet implement for SetCookie"),
+    }
+    pager.write_header_page(&header)?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_incr_vacuum(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::IncrVacuum { db, target_pc } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let pager = database_pager(program, pager, *db)?;
+    if pager.auto_vacuum_mode() != AutoVacuumMode::None && pager.incremental_vacuum_step()? {
+        state.pc += 1;
+    } else {
+        state.pc = target_pc.to_offset_int();
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
 fn exec_lower(reg: &OwnedValue) -> Option<OwnedValue> {
     match reg {
         OwnedValue::Text(t) => Some(OwnedValue::build_text(&t.as_str().to_lowercase())),
@@ -4932,18 +5803,18 @@ fn exec_like(regex_cache: Option<&mut HashMap<String, Regex>>, pattern: &str, te
     }
 }
 
-fn exec_min(regs: &[Register]) -> OwnedValue {
+fn exec_min(regs: &[Register], collation: CollationSeq) -> OwnedValue {
     regs.iter()
         .map(|v| v.get_owned_value())
-        .min()
+        .min_by(|a, b| collation.compare_values(a, b))
         .map(|v| v.to_owned())
         .unwrap_or(OwnedValue::Null)
 }
 
-fn exec_max(regs: &[Register]) -> OwnedValue {
+fn exec_max(regs: &[Register], collation: CollationSeq) -> OwnedValue {
     regs.iter()
         .map(|v| v.get_owned_value())
-        .max()
+        .max_by(|a, b| collation.compare_values(a, b))
         .map(|v| v.to_owned())
         .unwrap_or(OwnedValue::Null)
 }
@@ -6063,6 +6934,7 @@ mod tests {
         }
     }
 
+    use crate::collation::CollationSeq;
     use crate::vdbe::{
         execute::{exec_likelihood, exec_likely, exec_replace},
         Bitfield, Register,
@@ -6173,25 +7045,49 @@ mod tests {
             Register::OwnedValue(OwnedValue::Integer(-1)),
             Register::OwnedValue(OwnedValue::Integer(10)),
         ];
-        assert_eq!(exec_min(&input_int_vec), OwnedValue::Integer(-1));
-        assert_eq!(exec_max(&input_int_vec), OwnedValue::Integer(10));
+        assert_eq!(
+            exec_min(&input_int_vec, CollationSeq::Binary),
+            OwnedValue::Integer(-1)
+        );
+        assert_eq!(
+            exec_max(&input_int_vec, CollationSeq::Binary),
+            OwnedValue::Integer(10)
+        );
 
         let str1 = Register::OwnedValue(OwnedValue::build_text("A"));
         let str2 = Register::OwnedValue(OwnedValue::build_text("z"));
         let input_str_vec = vec![str2, str1.clone()];
-        assert_eq!(exec_min(&input_str_vec), OwnedValue::build_text("A"));
-        assert_eq!(exec_max(&input_str_vec), OwnedValue::build_text("z"));
+        assert_eq!(
+            exec_min(&input_str_vec, CollationSeq::Binary),
+            OwnedValue::build_text("A")
+        );
+        assert_eq!(
+            exec_max(&input_str_vec, CollationSeq::Binary),
+            OwnedValue::build_text("z")
+        );
 
         let input_null_vec = vec![
             Register::OwnedValue(OwnedValue::Null),
             Register::OwnedValue(OwnedValue::Null),
         ];
-        assert_eq!(exec_min(&input_null_vec), OwnedValue::Null);
-        assert_eq!(exec_max(&input_null_vec), OwnedValue::Null);
+        assert_eq!(
+            exec_min(&input_null_vec, CollationSeq::Binary),
+            OwnedValue::Null
+        );
+        assert_eq!(
+            exec_max(&input_null_vec, CollationSeq::Binary),
+            OwnedValue::Null
+        );
 
         let input_mixed_vec = vec![Register::OwnedValue(OwnedValue::Integer(10)), str1];
-        assert_eq!(exec_min(&input_mixed_vec), OwnedValue::Integer(10));
-        assert_eq!(exec_max(&input_mixed_vec), OwnedValue::build_text("A"));
+        assert_eq!(
+            exec_min(&input_mixed_vec, CollationSeq::Binary),
+            OwnedValue::Integer(10)
+        );
+        assert_eq!(
+            exec_max(&input_mixed_vec, CollationSeq::Binary),
+            OwnedValue::build_text("A")
+        );
     }
 
     #[test]
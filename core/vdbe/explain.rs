@@ -1,7 +1,11 @@
-use crate::vdbe::{builder::CursorType, insn::RegisterOrLiteral};
+use crate::vdbe::{
+    builder::CursorType,
+    insn::{RegisterOrLiteral, Subprogram},
+};
 
 use super::{Insn, InsnReference, OwnedValue, Program};
 use crate::function::{Func, ScalarFunc};
+use limbo_sqlite3_parser::ast::ResolveType;
 use std::rc::Rc;
 
 pub fn insn_to_str(
@@ -31,6 +35,15 @@ pub fn insn_to_str(
                 0,
                 format!("r[{}]=r[{}]+r[{}]", dest, lhs, rhs),
             ),
+            Insn::AddImm { register, value } => (
+                "AddImm",
+                *register as i32,
+                *value as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!("r[{}]=r[{}]+{}", register, register, value),
+            ),
             Insn::Subtract { lhs, rhs, dest } => (
                 "Subtract",
                 *lhs as i32,
@@ -140,6 +153,7 @@ pub fn insn_to_str(
                 start_reg_a,
                 start_reg_b,
                 count,
+                ..
             } => (
                 "Compare",
                 *start_reg_a as i32,
@@ -339,11 +353,12 @@ pub fn insn_to_str(
             Insn::OpenRead {
                 cursor_id,
                 root_page,
+                db,
             } => (
                 "OpenRead",
                 *cursor_id as i32,
                 *root_page as i32,
-                0,
+                *db as i32,
                 OwnedValue::build_text(""),
                 0,
                 format!(
@@ -482,15 +497,19 @@ pub fn insn_to_str(
                         let name = table.columns.get(*column).unwrap().name.as_ref();
                         name
                     }
+                    // The entries of an index on a WITHOUT ROWID table end with PRIMARY KEY
+                    // columns that are not in the index
                     CursorType::BTreeIndex(index) => {
-                        let name = &index.columns.get(*column).unwrap().name;
-                        Some(name)
+                        index.columns.get(*column).map(|column| &column.name)
                     }
                     CursorType::Pseudo(pseudo_table) => {
                         let name = pseudo_table.columns.get(*column).unwrap().name.as_ref();
                         name
                     }
                     CursorType::Sorter => None,
+                    CursorType::HashTable(index) => {
+                        index.columns.get(*column).map(|column| &column.name)
+                    }
                     CursorType::VirtualTable(v) => v.columns.get(*column).unwrap().name.as_ref(),
                 };
                 (
@@ -570,10 +589,17 @@ pub fn insn_to_str(
             Insn::Halt {
                 err_code,
                 description: _,
+                on_error,
             } => (
                 "Halt",
                 *err_code as i32,
-                0,
+                match on_error {
+                    ResolveType::Rollback => 1,
+                    ResolveType::Abort => 2,
+                    ResolveType::Fail => 3,
+                    ResolveType::Ignore => 4,
+                    ResolveType::Replace => 5,
+                },
                 0,
                 OwnedValue::build_text(""),
                 0,
@@ -846,6 +872,15 @@ pub fn insn_to_str(
                 0,
                 format!("if (--r[{}]==0) goto {}", reg, target_pc.to_debug_int()),
             ),
+            Insn::CollSeq { collation } => (
+                "CollSeq",
+                0,
+                0,
+                0,
+                OwnedValue::build_text(&format!("({})", collation.explain_name())),
+                0,
+                String::new(),
+            ),
             Insn::AggStep {
                 func,
                 acc_reg,
@@ -873,6 +908,7 @@ pub fn insn_to_str(
                 cursor_id,
                 columns,
                 order,
+                ..
             } => {
                 let _p4 = String::new();
                 let to_print: Vec<String> = order
@@ -1028,10 +1064,13 @@ pub fn insn_to_str(
                 *flag as u16,
                 "".to_string(),
             ),
-            Insn::Delete { cursor_id } => (
+            Insn::Delete {
+                cursor_id,
+                count_change,
+            } => (
                 "Delete",
                 *cursor_id as i32,
-                0,
+                *count_change as i32,
                 0,
                 OwnedValue::build_text(""),
                 0,
@@ -1050,10 +1089,10 @@ pub fn insn_to_str(
                 0,
                 "".to_string(),
             ),
-            Insn::MustBeInt { reg } => (
+            Insn::MustBeInt { reg, target_pc } => (
                 "MustBeInt",
                 *reg as i32,
-                0,
+                target_pc.map_or(0, |target_pc| target_pc.to_debug_int()),
                 0,
                 OwnedValue::build_text(""),
                 0,
@@ -1100,7 +1139,7 @@ pub fn insn_to_str(
             Insn::OpenWrite {
                 cursor_id,
                 root_page,
-                ..
+                db,
             } => (
                 "OpenWrite",
                 *cursor_id as i32,
@@ -1108,7 +1147,7 @@ pub fn insn_to_str(
                     RegisterOrLiteral::Literal(i) => *i as _,
                     RegisterOrLiteral::Register(i) => *i as _,
                 },
-                0,
+                *db as i32,
                 OwnedValue::build_text(""),
                 0,
                 "".to_string(),
@@ -1138,18 +1177,15 @@ pub fn insn_to_str(
             Insn::Destroy {
                 root,
                 former_root_reg,
-                is_temp,
+                db,
             } => (
                 "Destroy",
                 *root as i32,
                 *former_root_reg as i32,
-                *is_temp as i32,
+                *db as i32,
                 OwnedValue::build_text(&Rc::new("".to_string())),
                 0,
-                format!(
-                    "root iDb={} former_root={} is_temp={}",
-                    root, former_root_reg, is_temp
-                ),
+                format!("root={} former_root={} iDb={}", root, former_root_reg, db),
             ),
             Insn::DropTable {
                 db,
@@ -1195,6 +1231,90 @@ pub fn insn_to_str(
                 0,
                 format!("if (r[{}]==NULL) goto {}", reg, target_pc.to_debug_int()),
             ),
+            Insn::DropTrigger { db, trigger_name } => (
+                "DropTrigger",
+                *db as i32,
+                0,
+                0,
+                OwnedValue::build_text(trigger_name),
+                0,
+                format!("DROP TRIGGER {}", trigger_name),
+            ),
+            Insn::DropIndex { db, index_name } => (
+                "DropIndex",
+                *db as i32,
+                0,
+                0,
+                OwnedValue::build_text(index_name),
+                0,
+                format!("DROP INDEX {}", index_name),
+            ),
+            Insn::Clear { root, db } => (
+                "Clear",
+                *root as i32,
+                *db as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!("root={} db={}", root, db),
+            ),
+            Insn::Program {
+                subprogram,
+                params_start,
+                ignore_target,
+            } => (
+                "Program",
+                *params_start as i32,
+                ignore_target.to_debug_int(),
+                0,
+                OwnedValue::build_text(""),
+                0,
+                match subprogram {
+                    Subprogram::Index(index) => format!("subprogram {}", index),
+                    Subprogram::Frame(frames_up) => format!("program {} frames up", frames_up),
+                },
+            ),
+            Insn::FkCounter {
+                deferred,
+                increment,
+            } => (
+                "FkCounter",
+                *deferred as i32,
+                *increment as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!(
+                    "{}fkctr+={}",
+                    if *deferred { "deferred " } else { "" },
+                    increment
+                ),
+            ),
+            Insn::FkIfZero {
+                deferred,
+                target_pc,
+            } => (
+                "FkIfZero",
+                *deferred as i32,
+                target_pc.to_debug_int(),
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!(
+                    "if {}fkctr==0 goto {}",
+                    if *deferred { "deferred " } else { "" },
+                    target_pc.to_debug_int()
+                ),
+            ),
+            Insn::Param { offset, dest } => (
+                "Param",
+                *offset as i32,
+                *dest as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!("r[{}]=param({})", dest, offset),
+            ),
             Insn::ParseSchema { db, where_clause } => (
                 "ParseSchema",
                 *db as i32,
@@ -1204,6 +1324,15 @@ pub fn insn_to_str(
                 0,
                 where_clause.clone(),
             ),
+            Insn::LoadAnalysis { db } => (
+                "LoadAnalysis",
+                *db as i32,
+                0,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                "".to_string(),
+            ),
             Insn::Prev {
                 cursor_id,
                 pc_if_prev,
@@ -1330,6 +1459,15 @@ pub fn insn_to_str(
                 0,
                 format!("auto_commit={}, rollback={}", auto_commit, rollback),
             ),
+            Insn::Savepoint { op, name } => (
+                "Savepoint",
+                *op as i32,
+                0,
+                0,
+                OwnedValue::build_text(name),
+                0,
+                format!("{:?} {}", op, name),
+            ),
             Insn::OpenEphemeral {
                 cursor_id,
                 is_table,
@@ -1355,6 +1493,64 @@ pub fn insn_to_str(
                 0,
                 format!("cursor={}", cursor_id),
             ),
+            Insn::HashOpen {
+                cursor_id,
+                key_count,
+                collations,
+            } => (
+                "HashOpen",
+                *cursor_id as i32,
+                *key_count as i32,
+                0,
+                OwnedValue::build_text(&format!(
+                    "k({},{})",
+                    key_count,
+                    collations
+                        .iter()
+                        .map(|c| c.explain_name())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                )),
+                0,
+                format!("cursor={}", cursor_id),
+            ),
+            Insn::HashInsert {
+                cursor_id,
+                record_reg,
+            } => (
+                "HashInsert",
+                *cursor_id as i32,
+                *record_reg as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!("key=r[{}]", record_reg),
+            ),
+            Insn::HashProbe {
+                cursor_id,
+                key_reg,
+                target_pc,
+            } => (
+                "HashProbe",
+                *cursor_id as i32,
+                target_pc.to_debug_int(),
+                *key_reg as i32,
+                OwnedValue::build_text(""),
+                0,
+                format!("key=r[{}..]", key_reg),
+            ),
+            Insn::HashNext {
+                cursor_id,
+                pc_if_next,
+            } => (
+                "HashNext",
+                *cursor_id as i32,
+                pc_if_next.to_debug_int(),
+                0,
+                OwnedValue::build_text(""),
+                0,
+                "".to_string(),
+            ),
             Insn::Once {
                 target_pc_when_reentered,
             } => (
@@ -1395,6 +1591,41 @@ pub fn insn_to_str(
                     target_pc.to_debug_int()
                 ),
             ),
+            Insn::NoConflict {
+                cursor_id,
+                target_pc,
+                record_reg,
+                num_regs,
+            } => (
+                "NoConflict",
+                *cursor_id as i32,
+                target_pc.to_debug_int(),
+                *record_reg as i32,
+                OwnedValue::build_text(&format!("{}", num_regs)),
+                0,
+                format!(
+                    "key=r[{}..{}]",
+                    record_reg,
+                    record_reg + num_regs.saturating_sub(1)
+                ),
+            ),
+            Insn::IdxDelete {
+                cursor_id,
+                start_reg,
+                num_regs,
+            } => (
+                "IdxDelete",
+                *cursor_id as i32,
+                *start_reg as i32,
+                *num_regs as i32,
+                OwnedValue::build_text(""),
+                0,
+                format!(
+                    "key=r[{}..{}]",
+                    start_reg,
+                    start_reg + num_regs.saturating_sub(1)
+                ),
+            ),
             Insn::Affinity {
                 start_reg,
                 count,
@@ -1417,6 +1648,54 @@ pub fn insn_to_str(
                         .join(", ")
                 ),
             ),
+            Insn::Attach { path_reg, name_reg } => (
+                "Attach",
+                *path_reg as i32,
+                *name_reg as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!("attach r[{}] as r[{}]", path_reg, name_reg),
+            ),
+            Insn::Detach { name_reg } => (
+                "Detach",
+                *name_reg as i32,
+                0,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                format!("detach r[{}]", name_reg),
+            ),
+            Insn::Vacuum { db, into_reg } => (
+                "Vacuum",
+                *db as i32,
+                into_reg.unwrap_or(0) as i32,
+                0,
+                OwnedValue::build_text(""),
+                0,
+                match into_reg {
+                    Some(into_reg) => format!("vacuum db={} into r[{}]", db, into_reg),
+                    None => format!("vacuum db={}", db),
+                },
+            ),
+            Insn::SetCookie { db, cookie, value } => (
+                "SetCookie",
+                *db as i32,
+                *cookie as i32,
+                *value as i32,
+                OwnedValue::build_text(""),
+                0,
+                "".to_string(),
+            ),
+            Insn::IncrVacuum { db, target_pc } => (
+                "IncrVacuum",
+                *db as i32,
+                target_pc.to_debug_int(),
+                0,
+                OwnedValue::build_text(""),
+                0,
+                "".to_string(),
+            ),
         };
     format!(
         "{:<4}  {:<17}  {:<4}  {:<4}  {:<4}  {:<13}  {:<2}  {}",
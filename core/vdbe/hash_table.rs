@@ -0,0 +1,318 @@
+use crate::{
+    collation::CollationSeq,
+    io::{Buffer, Completion, ReadCompletion, WriteCompletion},
+    storage::sqlite3_ondisk::read_record,
+    types::{ImmutableRecord, RefValue},
+    util::temp_file_path,
+    File, OpenFlags, Result, IO,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    rc::Rc,
+    sync::Arc,
+};
+
+/// How many bytes of spilled entries are gathered before they are written to the spill file.
+const SPILL_WRITE_SIZE: usize = 64 * 1024;
+
+/// The table of a hash join, built from the rows of the inner table of the join and probed with
+/// the key of each row of the outer tables.
+///
+/// The entries are records whose first `key_count` values are the key. Once the entries held in
+/// memory exceed the memory budget, the next ones are written to a temporary file and only their
+/// position in it stays in memory; a probe reads back the ones of the bucket of its key. The file
+/// is opened through the IO of the database, so an IO that does not write files, like the one of
+/// an in-memory database, keeps it in memory.
+pub struct HashTable {
+    io: Arc<dyn IO>,
+    key_count: usize,
+    collations: Vec<CollationSeq>,
+    buckets: HashMap<u64, Vec<Entry>>,
+    memory_budget: usize,
+    memory_used: usize,
+    spill: Spill,
+    /// The key of the last probe, its bucket and the position in it of the next entry to check.
+    probe: Option<(ImmutableRecord, u64, usize)>,
+    current: Option<ImmutableRecord>,
+    null_flag: bool,
+}
+
+enum Entry {
+    Memory(ImmutableRecord),
+    /// The payload of a record at `offset` in the spill file.
+    Spilled {
+        offset: u64,
+        len: usize,
+    },
+}
+
+enum Spill {
+    /// Nothing was spilled yet.
+    None,
+    File(Box<SpillFile>),
+    /// A temporary file could not be created, so all the entries are kept in memory.
+    Unavailable,
+}
+
+struct SpillFile {
+    io: Arc<dyn IO>,
+    file: Arc<dyn File>,
+    /// The length of the file.
+    len: u64,
+    /// The entries that follow the end of the file, which are not written to it yet.
+    pending: Vec<u8>,
+    /// Declared after `file`, so that the file is closed before it is removed.
+    _path: SpillPath,
+}
+
+/// The path of a spill file, which is removed when it is dropped.
+struct SpillPath(String);
+
+impl HashTable {
+    pub fn new(
+        io: Arc<dyn IO>,
+        key_count: usize,
+        collations: Vec<CollationSeq>,
+        memory_budget: usize,
+    ) -> Self {
+        assert_eq!(key_count, collations.len());
+        Self {
+            io,
+            key_count,
+            collations,
+            buckets: HashMap::new(),
+            memory_budget,
+            memory_used: 0,
+            spill: Spill::None,
+            probe: None,
+            current: None,
+            null_flag: false,
+        }
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.key_count
+    }
+
+    /// Add an entry. An entry with a NULL in its key is left out, since it never matches a probe.
+    pub fn insert(&mut self, record: &ImmutableRecord) -> Result<()> {
+        let Some(hash) = self.hash_key(record) else {
+            return Ok(());
+        };
+        let size = record.get_payload().len() + record.len() * std::mem::size_of::<RefValue>();
+        let entry = if self.memory_used + size > self.memory_budget {
+            self.spill_entry(record.get_payload())?
+        } else {
+            None
+        };
+        let entry = entry.unwrap_or_else(|| {
+            self.memory_used += size;
+            Entry::Memory(record.clone())
+        });
+        self.buckets.entry(hash).or_default().push(entry);
+        Ok(())
+    }
+
+    /// Position the table on the first entry whose key is equal to `key`.
+    /// Returns false if there is none.
+    pub fn probe(&mut self, key: ImmutableRecord) -> Result<bool> {
+        self.null_flag = false;
+        self.probe = self.hash_key(&key).map(|hash| (key, hash, 0));
+        self.next()
+    }
+
+    /// Move to the next entry whose key is equal to the key of the last probe.
+    /// Returns false if there is none.
+    pub fn next(&mut self) -> Result<bool> {
+        self.current = None;
+        let Some((key, hash, position)) = self.probe.take() else {
+            return Ok(false);
+        };
+        let entries = self.buckets.get(&hash).map_or(&[][..], |e| e.as_slice());
+        for (i, entry) in entries.iter().enumerate().skip(position) {
+            let record = match entry {
+                Entry::Memory(record) => record.clone(),
+                Entry::Spilled { offset, len } => {
+                    let Spill::File(spill) = &mut self.spill else {
+                        unreachable!("spilled entry without a spill file");
+                    };
+                    spill.read(*offset, *len)?
+                }
+            };
+            let matches = self
+                .collations
+                .iter()
+                .enumerate()
+                .all(|(column, collation)| {
+                    collation.compare_ref_values(record.get_value(column), key.get_value(column))
+                        == std::cmp::Ordering::Equal
+                });
+            if matches {
+                self.current = Some(record);
+                self.probe = Some((key, hash, i + 1));
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn record(&self) -> Option<&ImmutableRecord> {
+        if self.null_flag {
+            return None;
+        }
+        self.current.as_ref()
+    }
+
+    pub fn set_null_flag(&mut self, flag: bool) {
+        self.null_flag = flag;
+    }
+
+    /// The hash of the key of `record`, or None if a value of the key is NULL.
+    fn hash_key(&self, record: &ImmutableRecord) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        for (value, collation) in record.get_values()[..self.key_count]
+            .iter()
+            .zip(self.collations.iter())
+        {
+            match value {
+                RefValue::Null => return None,
+                // Integers and reals that compare equal must hash the same.
+                RefValue::Integer(i) => hash_number(*i as f64, &mut hasher),
+                RefValue::Float(f) => hash_number(*f, &mut hasher),
+                RefValue::Text(text) => {
+                    2u8.hash(&mut hasher);
+                    let text = text.value.to_slice();
+                    match collation {
+                        CollationSeq::Binary => text.hash(&mut hasher),
+                        CollationSeq::NoCase => {
+                            for b in text {
+                                b.to_ascii_lowercase().hash(&mut hasher);
+                            }
+                        }
+                        CollationSeq::Rtrim => {
+                            let len = text.iter().rposition(|b| *b != b' ').map_or(0, |i| i + 1);
+                            text[..len].hash(&mut hasher);
+                        }
+                        // The texts that a collating sequence of an extension finds equal are
+                        // unknown, so the planner does not hash them.
+                        CollationSeq::External(_) => {
+                            unreachable!("a hash table key cannot use the collating sequence of an extension")
+                        }
+                    }
+                }
+                RefValue::Blob(blob) => {
+                    3u8.hash(&mut hasher);
+                    blob.to_slice().hash(&mut hasher);
+                }
+            }
+        }
+        Some(hasher.finish())
+    }
+
+    /// Write `payload` to the spill file, or return None if there cannot be one.
+    fn spill_entry(&mut self, payload: &[u8]) -> Result<Option<Entry>> {
+        if let Spill::None = self.spill {
+            let path = temp_file_path(&self.io, "hash");
+            self.spill = match self.io.open_file(&path, OpenFlags::Create, false) {
+                Ok(file) => Spill::File(Box::new(SpillFile {
+                    io: self.io.clone(),
+                    file,
+                    len: 0,
+                    pending: Vec::new(),
+                    _path: SpillPath(path),
+                })),
+                Err(e) => {
+                    tracing::debug!("hash table kept in memory, no temporary file: {}", e);
+                    Spill::Unavailable
+                }
+            };
+        }
+        let Spill::File(spill) = &mut self.spill else {
+            return Ok(None);
+        };
+        let offset = spill.write(payload)?;
+        Ok(Some(Entry::Spilled {
+            offset,
+            len: payload.len(),
+        }))
+    }
+}
+
+fn hash_number(value: f64, hasher: &mut DefaultHasher) {
+    1u8.hash(hasher);
+    // 0.0 and -0.0 are equal
+    let value = if value == 0.0 { 0.0 } else { value };
+    value.to_bits().hash(hasher);
+}
+
+impl SpillFile {
+    /// Append `payload` and return its offset.
+    fn write(&mut self, payload: &[u8]) -> Result<u64> {
+        let offset = self.len + self.pending.len() as u64;
+        self.pending.extend_from_slice(payload);
+        if self.pending.len() >= SPILL_WRITE_SIZE {
+            self.flush()?;
+        }
+        Ok(offset)
+    }
+
+    fn read(&mut self, offset: u64, len: usize) -> Result<ImmutableRecord> {
+        let mut record = ImmutableRecord::new(len, 0);
+        // The pending entries are written together, so an entry is either all in the file or
+        // all pending
+        if offset >= self.len {
+            let start = (offset - self.len) as usize;
+            read_record(&self.pending[start..start + len], &mut record)?;
+            return Ok(record);
+        }
+        let buf = spill_buffer(vec![0; len]);
+        let done = Rc::new(Cell::new(false));
+        let completion = {
+            let done = done.clone();
+            Completion::Read(ReadCompletion::new(
+                buf.clone(),
+                Box::new(move |_| done.set(true)),
+            ))
+        };
+        self.file.pread(offset as usize, completion)?;
+        while !done.get() {
+            self.io.run_once()?;
+        }
+        read_record(buf.borrow().as_slice(), &mut record)?;
+        Ok(record)
+    }
+
+    /// Write the pending entries at the end of the file.
+    fn flush(&mut self) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        let len = pending.len() as u64;
+        let buf = spill_buffer(pending);
+        let done = Rc::new(Cell::new(false));
+        let completion = {
+            let done = done.clone();
+            Completion::Write(WriteCompletion::new(Box::new(move |_| done.set(true))))
+        };
+        self.file.pwrite(self.len as usize, buf, completion)?;
+        while !done.get() {
+            self.io.run_once()?;
+        }
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// A buffer for reading or writing `data` in a spill file.
+#[allow(clippy::arc_with_non_send_sync)]
+fn spill_buffer(data: Vec<u8>) -> Arc<RefCell<Buffer>> {
+    let drop_fn = Rc::new(|_| {});
+    Arc::new(RefCell::new(Buffer::new(std::pin::Pin::new(data), drop_fn)))
+}
+
+impl Drop for SpillPath {
+    fn drop(&mut self) {
+        #[cfg(feature = "fs")]
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
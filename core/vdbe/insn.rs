@@ -5,31 +5,42 @@ use std::{
 
 use super::{execute, AggFunc, BranchOffset, CursorID, FuncCtx, InsnFunction, PageIdx};
 use crate::{
+    collation::CollationSeq,
     schema::BTreeTable,
     storage::{pager::CreateBTreeFlags, wal::CheckpointMode},
     types::Record,
 };
 use limbo_macros::Description;
+use limbo_sqlite3_parser::ast::ResolveType;
 
-/// Flags provided to comparison instructions (e.g. Eq, Ne) which determine behavior related to NULL values.
+/// Flags provided to comparison instructions (e.g. Eq, Ne) which determine behavior related to NULL values,
+/// along with the collating sequence used to compare TEXT values.
 #[derive(Clone, Copy, Debug, Default)]
-pub struct CmpInsFlags(usize);
+pub struct CmpInsFlags {
+    flags: usize,
+    collation: CollationSeq,
+}
 
 impl CmpInsFlags {
     const NULL_EQ: usize = 0x80;
     const JUMP_IF_NULL: usize = 0x10;
 
     fn has(&self, flag: usize) -> bool {
-        (self.0 & flag) != 0
+        (self.flags & flag) != 0
     }
 
     pub fn null_eq(mut self) -> Self {
-        self.0 |= CmpInsFlags::NULL_EQ;
+        self.flags |= CmpInsFlags::NULL_EQ;
         self
     }
 
     pub fn jump_if_null(mut self) -> Self {
-        self.0 |= CmpInsFlags::JUMP_IF_NULL;
+        self.flags |= CmpInsFlags::JUMP_IF_NULL;
+        self
+    }
+
+    pub fn with_collation(mut self, collation: CollationSeq) -> Self {
+        self.collation = collation;
         self
     }
 
@@ -40,6 +51,10 @@ impl CmpInsFlags {
     pub fn has_nulleq(&self) -> bool {
         self.has(CmpInsFlags::NULL_EQ)
     }
+
+    pub fn collation(&self) -> CollationSeq {
+        self.collation
+    }
 }
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -92,6 +107,24 @@ impl From<PageIdx> for RegisterOrLiteral<PageIdx> {
     }
 }
 
+/// What the Savepoint instruction does with its savepoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SavepointOp {
+    Begin,
+    Release,
+    Rollback,
+}
+
+/// The trigger program run by [Insn::Program].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subprogram {
+    /// A sub-program of the program that contains the instruction, by index.
+    Index(usize),
+    /// The program that runs the given number of frames up from the running one, which runs
+    /// itself again recursively.
+    Frame(usize),
+}
+
 #[derive(Description, Debug)]
 pub enum Insn {
     /// Initialize the program state and jump to the given PC.
@@ -119,6 +152,11 @@ pub enum Insn {
         rhs: usize,
         dest: usize,
     },
+    /// Add the constant P2 to the value in register P1. The result is always an integer.
+    AddImm {
+        register: usize,
+        value: i64,
+    },
     /// Subtract rhs from lhs and store in dest
     Subtract {
         lhs: usize,
@@ -138,10 +176,12 @@ pub enum Insn {
         dest: usize,
     },
     /// Compare two vectors of registers in reg(P1)..reg(P1+P3-1) (call this vector "A") and in reg(P2)..reg(P2+P3-1) ("B"). Save the result of the comparison for use by the next Jump instruct.
+    /// P4 holds the collating sequence used to compare each pair of registers.
     Compare {
         start_reg_a: usize,
         start_reg_b: usize,
         count: usize,
+        collations: Vec<CollationSeq>,
     },
     /// Place the result of rhs bitwise AND lhs in third register.
     BitAnd {
@@ -269,6 +309,9 @@ pub enum Insn {
     OpenRead {
         cursor_id: CursorID,
         root_page: PageIdx,
+        /// The database of the b-tree: 0 is the main database and 2 and up are the attached
+        /// databases (P3).
+        db: usize,
     },
 
     /// Open a cursor for a virtual table.
@@ -382,10 +425,13 @@ pub enum Insn {
         pc_if_prev: BranchOffset,
     },
 
-    /// Halt the program.
+    /// Halt the program. If `err_code` is not 0, the statement fails, and `on_error` says what
+    /// happens to the changes it made: ABORT undoes them, FAIL keeps them and ROLLBACK undoes
+    /// the whole transaction.
     Halt {
         err_code: usize,
         description: String,
+        on_error: ResolveType,
     },
 
     /// Start a transaction.
@@ -399,6 +445,12 @@ pub enum Insn {
         rollback: bool,
     },
 
+    /// Open a savepoint, release it or roll back to it, depending on `op`.
+    Savepoint {
+        op: SavepointOp,
+        name: String,
+    },
+
     /// Branch to the given PC.
     Goto {
         target_pc: BranchOffset,
@@ -572,6 +624,11 @@ pub enum Insn {
         target_pc: BranchOffset,
     },
 
+    /// Set the collating sequence used by the next min() or max() call in a Function or AggStep instruction.
+    CollSeq {
+        collation: CollationSeq,
+    },
+
     AggStep {
         acc_reg: usize,
         col: usize,
@@ -589,6 +646,7 @@ pub enum Insn {
         cursor_id: CursorID, // P1
         columns: usize,      // P2
         order: Record,       // P4. 0 if ASC and 1 if DESC
+        collations: Vec<CollationSeq>,
     },
 
     /// Insert a row into the sorter.
@@ -648,6 +706,8 @@ pub enum Insn {
 
     Delete {
         cursor_id: CursorID,
+        /// Whether the deleted row counts towards `changes()`.
+        count_change: bool,
     },
 
     NewRowid {
@@ -656,8 +716,11 @@ pub enum Insn {
         prev_largest_reg: usize, // P3 Previous largest rowid in the table (Not used for now)
     },
 
+    /// Convert the value in `reg` to an integer. If it cannot be converted, jump to `target_pc`,
+    /// or fail if there is no target.
     MustBeInt {
         reg: usize,
+        target_pc: Option<BranchOffset>,
     },
 
     SoftNull {
@@ -679,6 +742,8 @@ pub enum Insn {
     OpenWrite {
         cursor_id: CursorID,
         root_page: RegisterOrLiteral<PageIdx>,
+        /// The database of the b-tree, see [Insn::OpenRead] (P3).
+        db: usize,
     },
 
     Copy {
@@ -689,7 +754,7 @@ pub enum Insn {
 
     /// Allocate a new b-tree.
     CreateBtree {
-        /// Allocate b-tree in main database if zero or in temp database if non-zero (P1).
+        /// The database to allocate the b-tree in (P1), see [crate::schema::Schema::database].
         db: usize,
         /// The root page of the new b-tree (P2).
         root: usize,
@@ -703,8 +768,8 @@ pub enum Insn {
         root: usize,
         /// Register to store the former value of any moved root page (for AUTOVACUUM)
         former_root_reg: usize,
-        /// Whether this is a temporary table (1) or main database table (0)
-        is_temp: usize,
+        /// The database of the b-tree (P3).
+        db: usize,
     },
 
     ///  Drop a table
@@ -719,6 +784,29 @@ pub enum Insn {
         table_name: String,
     },
 
+    /// Remove a trigger from the in-memory schema.
+    DropTrigger {
+        ///  The database that contains the trigger (P1).
+        db: usize,
+        trigger_name: String,
+    },
+
+    /// Remove an index from the in-memory schema.
+    DropIndex {
+        ///  The database that contains the index (P1).
+        db: usize,
+        index_name: String,
+    },
+
+    /// Delete all the entries of the table or index whose root page is given by P1. Unlike
+    /// Destroy, the b-tree itself is kept.
+    Clear {
+        /// The root page of the table/index to clear (P1).
+        root: usize,
+        ///  The database that contains the b-tree (P2).
+        db: usize,
+    },
+
     /// Close a cursor.
     Close {
         cursor_id: CursorID,
@@ -733,10 +821,45 @@ pub enum Insn {
         target_pc: BranchOffset,
     },
     ParseSchema {
+        /// The database whose schema table the entries are read from (P1).
         db: usize,
         where_clause: String,
     },
 
+    /// Read the sqlite_stat1 table of the database P1 into the statistics of its schema.
+    LoadAnalysis {
+        db: usize,
+    },
+
+    /// Run a trigger sub-program in a new frame, with its own registers and cursors.
+    /// The sub-program reads the registers of this program starting at `params_start`
+    /// with [Insn::Param]. If the sub-program halts with `RAISE(IGNORE)`, jump to `ignore_target`.
+    Program {
+        subprogram: Subprogram,
+        params_start: usize,
+        ignore_target: BranchOffset,
+    },
+
+    /// Add `increment` to the number of violations of immediate foreign keys made by the statement,
+    /// or to the number of violations of deferred foreign keys made by the transaction.
+    FkCounter {
+        deferred: bool,
+        increment: i64,
+    },
+
+    /// Jump to `target_pc` if the number of violations of immediate, or deferred, foreign keys
+    /// is zero.
+    FkIfZero {
+        deferred: bool,
+        target_pc: BranchOffset,
+    },
+
+    /// Copy register `offset` of the parameters passed by the calling program to `dest`.
+    Param {
+        offset: usize,
+        dest: usize,
+    },
+
     /// Place the result of lhs >> rhs in dest register.
     ShiftRight {
         lhs: usize,
@@ -808,6 +931,30 @@ pub enum Insn {
     OpenAutoindex {
         cursor_id: usize,
     },
+    /// Open the hash table of a hash join on cursor P1, whose entries have a key of P2 values.
+    HashOpen {
+        cursor_id: CursorID,
+        key_count: usize,
+        collations: Vec<CollationSeq>,
+    },
+    /// Add the record in register P2 to the hash table of cursor P1.
+    HashInsert {
+        cursor_id: CursorID,
+        record_reg: usize,
+    },
+    /// Position the hash table of cursor P1 on its first entry whose key is equal to the values
+    /// of the registers starting at P3, or jump to P2 if there is none.
+    HashProbe {
+        cursor_id: CursorID,
+        key_reg: usize,
+        target_pc: BranchOffset,
+    },
+    /// Move the hash table of cursor P1 to its next entry with the key of the last probe, and jump
+    /// to P2 if there is one.
+    HashNext {
+        cursor_id: CursorID,
+        pc_if_next: BranchOffset,
+    },
     /// Fall through to the next instruction on the first invocation, otherwise jump to target_pc
     Once {
         target_pc_when_reentered: BranchOffset,
@@ -825,12 +972,56 @@ pub enum Insn {
         record_reg: usize,
         num_regs: usize,
     },
+    /// Search the UNIQUE index for an entry whose key starts with the num_regs registers at
+    /// record_reg. If there is none, or one of the registers is NULL, go to target_pc;
+    /// otherwise the cursor points at the conflicting entry.
+    NoConflict {
+        cursor_id: CursorID,
+        target_pc: BranchOffset,
+        record_reg: usize,
+        num_regs: usize,
+    },
+    /// Delete the index entry whose key is made of the num_regs registers at start_reg, if
+    /// there is one.
+    IdxDelete {
+        cursor_id: CursorID,
+        start_reg: usize,
+        num_regs: usize,
+    },
     /// Apply affinities to a range of registers. Affinities must have the same size of count
     Affinity {
         start_reg: usize,
         count: NonZeroUsize,
         affinities: String,
     },
+    /// Attach the database file named in r\[path_reg\] to the connection under the name in
+    /// r\[name_reg\].
+    Attach {
+        path_reg: usize,
+        name_reg: usize,
+    },
+    /// Detach the database attached under the name in r\[name_reg\].
+    Detach {
+        name_reg: usize,
+    },
+    /// Rebuild database number db into a compact copy. If into_reg is set, write the copy to the
+    /// file named in r\[into_reg\] instead of replacing the database with it.
+    Vacuum {
+        db: usize,
+        into_reg: Option<usize>,
+    },
+    /// Write the value into the cookie of database db.
+    SetCookie {
+        db: usize,
+        cookie: Cookie,
+        value: u32,
+    },
+    /// Give back one free page of the auto-vacuum database db, or jump to target_pc if it has
+    /// none left.
+    IncrVacuum {
+        db: usize,
+        target_pc: BranchOffset,
+    },
 }
 
 impl Insn {
@@ -841,6 +1032,7 @@ impl Insn {
             Insn::BeginSubrtn { .. } => execute::op_null,
             Insn::NullRow { .. } => execute::op_null_row,
             Insn::Add { .. } => execute::op_add,
+            Insn::AddImm { .. } => execute::op_add_imm,
             Insn::Subtract { .. } => execute::op_subtract,
             Insn::Multiply { .. } => execute::op_multiply,
             Insn::Divide { .. } => execute::op_divide,
@@ -883,6 +1075,7 @@ impl Insn {
             Insn::Halt { .. } => execute::op_halt,
             Insn::Transaction { .. } => execute::op_transaction,
             Insn::AutoCommit { .. } => execute::op_auto_commit,
+            Insn::Savepoint { .. } => execute::op_savepoint,
             Insn::Goto { .. } => execute::op_goto,
             Insn::Gosub { .. } => execute::op_gosub,
             Insn::Return { .. } => execute::op_return,
@@ -905,6 +1098,7 @@ impl Insn {
             Insn::IdxLE { .. } => execute::op_idx_le,
             Insn::IdxLT { .. } => execute::op_idx_lt,
             Insn::DecrJumpZero { .. } => execute::op_decr_jump_zero,
+            Insn::CollSeq { .. } => execute::op_coll_seq,
             Insn::AggStep { .. } => execute::op_agg_step,
             Insn::AggFinal { .. } => execute::op_agg_final,
             Insn::SorterOpen { .. } => execute::op_sorter_open,
@@ -933,6 +1127,14 @@ impl Insn {
             Insn::Close { .. } => execute::op_close,
             Insn::IsNull { .. } => execute::op_is_null,
             Insn::ParseSchema { .. } => execute::op_parse_schema,
+            Insn::LoadAnalysis { .. } => execute::op_load_analysis,
+            Insn::Program { .. } => execute::op_program,
+            Insn::FkCounter { .. } => execute::op_fk_counter,
+            Insn::FkIfZero { .. } => execute::op_fk_if_zero,
+            Insn::Param { .. } => execute::op_param,
+            Insn::DropTrigger { .. } => execute::op_drop_trigger,
+            Insn::DropIndex { .. } => execute::op_drop_index,
+            Insn::Clear { .. } => execute::op_clear,
             Insn::ShiftRight { .. } => execute::op_shift_right,
             Insn::ShiftLeft { .. } => execute::op_shift_left,
             Insn::Variable { .. } => execute::op_variable,
@@ -945,9 +1147,20 @@ impl Insn {
             Insn::PageCount { .. } => execute::op_page_count,
             Insn::ReadCookie { .. } => execute::op_read_cookie,
             Insn::OpenEphemeral { .. } | Insn::OpenAutoindex { .. } => execute::op_open_ephemeral,
+            Insn::HashOpen { .. } => execute::op_hash_open,
+            Insn::HashInsert { .. } => execute::op_hash_insert,
+            Insn::HashProbe { .. } => execute::op_hash_probe,
+            Insn::HashNext { .. } => execute::op_hash_next,
             Insn::Once { .. } => execute::op_once,
             Insn::NotFound { .. } => execute::op_not_found,
+            Insn::NoConflict { .. } => execute::op_no_conflict,
+            Insn::IdxDelete { .. } => execute::op_idx_delete,
             Insn::Affinity { .. } => execute::op_affinity,
+            Insn::Attach { .. } => execute::op_attach,
+            Insn::Detach { .. } => execute::op_detach,
+            Insn::Vacuum { .. } => execute::op_vacuum,
+            Insn::SetCookie { .. } => execute::op_set_cookie,
+            Insn::IncrVacuum { .. } => execute::op_incr_vacuum,
         }
     }
 }
@@ -967,4 +1180,6 @@ pub enum Cookie {
     DatabaseTextEncoding = 5,
     /// The "user version" as read and set by the user_version pragma.
     UserVersion = 6,
+    /// True (non-zero) for incremental-vacuum mode. False (zero) otherwise.
+    IncrementalVacuum = 7,
 }
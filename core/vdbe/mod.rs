@@ -20,11 +20,13 @@
 pub mod builder;
 pub mod execute;
 pub mod explain;
+pub mod hash_table;
 pub mod insn;
 pub mod likeop;
 pub mod sorter;
 
 use crate::{
+    collation::CollationSeq,
     error::LimboError,
     fast_lock::SpinLock,
     function::{AggFunc, FuncCtx},
@@ -44,6 +46,7 @@ use crate::CheckpointStatus;
 use crate::json::JsonCacheCell;
 use crate::{Connection, MvStore, Result, TransactionState};
 use execute::{InsnFunction, InsnFunctionStepResult};
+use limbo_sqlite3_parser::ast::ResolveType;
 
 use rand::{
     distributions::{Distribution, Uniform},
@@ -224,6 +227,24 @@ pub struct Row {
     count: usize,
 }
 
+/// The state of a program that is suspended while a trigger sub-program runs, see [Insn::Program].
+struct Frame {
+    /// The sub-program that runs in this frame.
+    program: Rc<Program>,
+    /// Where the calling program continues when the sub-program halts.
+    return_pc: InsnReference,
+    /// Where the calling program continues when the sub-program halts with `RAISE(IGNORE)`.
+    ignore_pc: InsnReference,
+    /// The first register of the calling program that the sub-program reads with [Insn::Param].
+    params_start: usize,
+    registers: Vec<Register>,
+    cursors: Vec<Option<Cursor>>,
+    once: SmallVec<u32, 4>,
+    ended_coroutine: Bitfield<4>,
+    deferred_seek: Option<(CursorID, CursorID)>,
+    last_compare: Option<std::cmp::Ordering>,
+}
+
 /// The program state describes the environment in which the program executes.
 pub struct ProgramState {
     pub pc: InsnReference,
@@ -231,6 +252,8 @@ pub struct ProgramState {
     registers: Vec<Register>,
     pub(crate) result_row: Option<Row>,
     last_compare: Option<std::cmp::Ordering>,
+    /// The collating sequence set by the last [Insn::CollSeq], used by the next min() or max() call.
+    collation: Option<CollationSeq>,
     deferred_seek: Option<(CursorID, CursorID)>,
     ended_coroutine: Bitfield<4>, // flag to indicate that a coroutine has ended (key is the yield register. currently we assume that the yield register is always between 0-255, YOLO)
     /// Indicate whether an [Insn::Once] instruction at a given program counter position has already been executed, well, once.
@@ -240,6 +263,17 @@ pub struct ProgramState {
     interrupted: bool,
     parameters: HashMap<NonZero<usize>, OwnedValue>,
     halt_state: Option<HaltState>,
+    /// Whether the program began a statement of a write transaction, whose changes are undone
+    /// on their own if the program fails.
+    in_statement: bool,
+    /// The number of violations of immediate foreign keys made by the statement, see
+    /// [Insn::FkCounter].
+    fk_violations: i64,
+    /// The number of violations of deferred foreign keys in the transaction when the statement
+    /// began, which is restored if the statement fails.
+    stmt_deferred_fk_violations: i64,
+    /// The calling programs of the trigger sub-program that is running, innermost last.
+    frames: Vec<Frame>,
     #[cfg(feature = "json")]
     json_cache: JsonCacheCell,
 }
@@ -255,6 +289,7 @@ impl ProgramState {
             registers,
             result_row: None,
             last_compare: None,
+            collation: None,
             deferred_seek: None,
             ended_coroutine: Bitfield::new(),
             once: SmallVec::<u32, 4>::new(),
@@ -263,6 +298,10 @@ impl ProgramState {
             interrupted: false,
             parameters: HashMap::new(),
             halt_state: None,
+            in_statement: false,
+            fk_violations: 0,
+            stmt_deferred_fk_violations: 0,
+            frames: Vec::new(),
             #[cfg(feature = "json")]
             json_cache: JsonCacheCell::new(),
         }
@@ -295,22 +334,89 @@ impl ProgramState {
             .unwrap_or(OwnedValue::Null)
     }
 
+    /// Drops the pending deferred seek of a table cursor that is being moved or set to a NULL row,
+    /// since the index cursor it was deferred to no longer points to the row it is on.
+    pub fn cancel_deferred_seek(&mut self, cursor_id: CursorID) {
+        self.deferred_seek
+            .take_if(|(_, table_cursor_id)| *table_cursor_id == cursor_id);
+    }
+
     pub fn reset(&mut self) {
+        if let Some(frame) = self.frames.drain(..).next() {
+            self.registers = frame.registers;
+            *self.cursors.get_mut() = frame.cursors;
+            self.once = frame.once;
+        }
         self.pc = 0;
         self.cursors.borrow_mut().iter_mut().for_each(|c| *c = None);
         self.registers
             .iter_mut()
             .for_each(|r| *r = Register::OwnedValue(OwnedValue::Null));
         self.last_compare = None;
+        self.collation = None;
         self.deferred_seek = None;
         self.ended_coroutine.0 = [0; 4];
         self.regex_cache.like.clear();
         self.interrupted = false;
+        self.in_statement = false;
+        self.fk_violations = 0;
         self.parameters.clear();
         #[cfg(feature = "json")]
         self.json_cache.clear()
     }
 
+    /// Suspend the running program and start running the trigger sub-program `program`.
+    fn push_frame(
+        &mut self,
+        program: Rc<Program>,
+        return_pc: InsnReference,
+        ignore_pc: InsnReference,
+        params_start: usize,
+    ) {
+        let registers = std::mem::replace(
+            &mut self.registers,
+            vec![Register::OwnedValue(OwnedValue::Null); program.max_registers],
+        );
+        let cursors = std::mem::replace(
+            self.cursors.get_mut(),
+            (0..program.cursor_ref.len()).map(|_| None).collect(),
+        );
+        let frame = Frame {
+            program,
+            return_pc,
+            ignore_pc,
+            params_start,
+            registers,
+            cursors,
+            once: std::mem::replace(&mut self.once, SmallVec::new()),
+            ended_coroutine: Bitfield(std::mem::take(&mut self.ended_coroutine.0)),
+            deferred_seek: self.deferred_seek.take(),
+            last_compare: self.last_compare.take(),
+        };
+        self.frames.push(frame);
+        self.pc = 0;
+    }
+
+    /// Return to the program that called the running trigger sub-program.
+    /// If `ignore` is set, continue at the target of `RAISE(IGNORE)` instead of the next instruction.
+    fn pop_frame(&mut self, ignore: bool) {
+        let frame = self
+            .frames
+            .pop()
+            .expect("no trigger sub-program is running");
+        self.registers = frame.registers;
+        *self.cursors.get_mut() = frame.cursors;
+        self.once = frame.once;
+        self.ended_coroutine = frame.ended_coroutine;
+        self.deferred_seek = frame.deferred_seek;
+        self.last_compare = frame.last_compare;
+        self.pc = if ignore {
+            frame.ignore_pc
+        } else {
+            frame.return_pc
+        };
+    }
+
     pub fn get_cursor<'a>(&'a self, cursor_id: CursorID) -> std::cell::RefMut<'a, Cursor> {
         let cursors = self.cursors.borrow_mut();
         std::cell::RefMut::map(cursors, |c| {
@@ -340,6 +446,7 @@ macro_rules! must_be_btree_cursor {
             CursorType::BTreeIndex(_) => $state.get_cursor($cursor_id),
             CursorType::Pseudo(_) => panic!("{} on pseudo cursor", $insn_name),
             CursorType::Sorter => panic!("{} on sorter cursor", $insn_name),
+            CursorType::HashTable(_) => panic!("{} on hash table cursor", $insn_name),
             CursorType::VirtualTable(_) => panic!("{} on virtual table cursor", $insn_name),
         };
         cursor
@@ -359,6 +466,8 @@ pub struct Program {
     pub change_cnt_on: bool,
     pub result_columns: Vec<ResultSetColumn>,
     pub table_references: Vec<TableReference>,
+    /// Trigger programs run by [Insn::Program].
+    pub subprograms: Vec<Rc<Program>>,
 }
 
 impl Program {
@@ -374,9 +483,22 @@ impl Program {
             }
             // invalidate row
             let _ = state.result_row.take();
-            let (insn, insn_function) = &self.insns[state.pc as usize];
-            trace_insn(self, state.pc as InsnReference, insn);
-            let res = insn_function(self, state, insn, &pager, mv_store.as_ref())?;
+            // While a trigger sub-program runs, execute its instructions instead of ours.
+            let frame_program = state.frames.last().map(|frame| frame.program.clone());
+            let program = frame_program.as_deref().unwrap_or(self);
+            let (insn, insn_function) = &program.insns[state.pc as usize];
+            trace_insn(program, state.pc as InsnReference, insn);
+            let res = match insn_function(program, state, insn, &pager, mv_store.as_ref()) {
+                Ok(res) => res,
+                Err(err) => {
+                    let on_error = match insn {
+                        Insn::Halt { on_error, .. } => *on_error,
+                        _ => ResolveType::Abort,
+                    };
+                    self.abort(&pager, state, mv_store.as_ref(), on_error)?;
+                    return Err(err);
+                }
+            };
             match res {
                 InsnFunctionStepResult::Step => {}
                 InsnFunctionStepResult::Done => return Ok(StepResult::Done),
@@ -388,6 +510,44 @@ impl Program {
         }
     }
 
+    /// Deals with the changes made by the program after it failed: ABORT undoes the changes of
+    /// the statement, FAIL keeps them and ROLLBACK undoes the whole transaction. In autocommit
+    /// mode, the statement is the transaction.
+    fn abort(
+        &self,
+        pager: &Rc<Pager>,
+        state: &mut ProgramState,
+        mv_store: Option<&Rc<MvStore>>,
+        on_error: ResolveType,
+    ) -> Result<()> {
+        let connection = self
+            .connection
+            .upgrade()
+            .expect("only weak ref to connection?");
+        let in_statement = std::mem::take(&mut state.in_statement);
+        state.halt_state = None;
+        match on_error {
+            ResolveType::Fail => {
+                pager.end_statement();
+                if connection.auto_commit.get() {
+                    while let StepResult::IO = self.halt(pager.clone(), state, mv_store)? {
+                        pager.io.run_once()?;
+                    }
+                }
+                Ok(())
+            }
+            ResolveType::Rollback => connection.rollback(),
+            _ if connection.auto_commit.get() => connection.rollback(),
+            _ if in_statement => {
+                connection
+                    .deferred_fk_violations
+                    .set(state.stmt_deferred_fk_violations);
+                connection.rollback_statement()
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn halt(
         &self,
         pager: Rc<Pager>,
@@ -412,6 +572,12 @@ impl Program {
                 .expect("only weak ref to connection?");
             let auto_commit = connection.auto_commit.get();
             tracing::trace!("Halt auto_commit {}", auto_commit);
+            if std::mem::take(&mut program_state.in_statement) {
+                pager.end_statement();
+                for pager in connection.attached_pagers() {
+                    pager.end_statement();
+                }
+            }
             assert!(
                 program_state.halt_state.is_none()
                     || (matches!(program_state.halt_state.unwrap(), HaltState::Checkpointing))
@@ -421,14 +587,20 @@ impl Program {
             } else if auto_commit {
                 let current_state = connection.transaction_state.get();
                 match current_state {
-                    TransactionState::Write => self.step_end_write_txn(
-                        &pager,
-                        &mut program_state.halt_state,
-                        connection.deref(),
-                    ),
+                    TransactionState::Write => {
+                        connection.commit_attached()?;
+                        self.step_end_write_txn(
+                            &pager,
+                            &mut program_state.halt_state,
+                            connection.deref(),
+                        )
+                    }
                     TransactionState::Read => {
                         connection.transaction_state.replace(TransactionState::None);
                         pager.end_read_tx()?;
+                        for pager in connection.attached_pagers() {
+                            pager.end_read_tx()?;
+                        }
                         Ok(StepResult::Done)
                     }
                     TransactionState::None => Ok(StepResult::Done),
@@ -459,6 +631,7 @@ impl Program {
                     }
                 }
                 connection.transaction_state.replace(TransactionState::None);
+                connection.schema_changed.set(false);
                 let _ = halt_state.take();
             }
             CheckpointStatus::IO => {
@@ -570,6 +743,7 @@ fn get_indent_count(indent_count: usize, curr_insn: &Insn, prev_insn: Option<&In
             Insn::Rewind { .. }
             | Insn::Last { .. }
             | Insn::SorterSort { .. }
+            | Insn::HashProbe { .. }
             | Insn::SeekGE { .. }
             | Insn::SeekGT { .. }
             | Insn::SeekLE { .. }
@@ -582,7 +756,9 @@ fn get_indent_count(indent_count: usize, curr_insn: &Insn, prev_insn: Option<&In
     };
 
     match curr_insn {
-        Insn::Next { .. } | Insn::SorterNext { .. } | Insn::Prev { .. } => indent_count - 1,
+        Insn::Next { .. } | Insn::SorterNext { .. } | Insn::HashNext { .. } | Insn::Prev { .. } => {
+            indent_count - 1
+        }
         _ => indent_count,
     }
 }
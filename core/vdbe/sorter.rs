@@ -1,18 +1,20 @@
-use crate::types::ImmutableRecord;
+use crate::{collation::CollationSeq, types::ImmutableRecord};
 use std::cmp::Ordering;
 
 pub struct Sorter {
     records: Vec<ImmutableRecord>,
     current: Option<ImmutableRecord>,
     order: Vec<bool>,
+    collations: Vec<CollationSeq>,
 }
 
 impl Sorter {
-    pub fn new(order: Vec<bool>) -> Self {
+    pub fn new(order: Vec<bool>, collations: Vec<CollationSeq>) -> Self {
         Self {
             records: Vec::new(),
             current: None,
             order,
+            collations,
         }
     }
     pub fn is_empty(&self) -> bool {
@@ -29,10 +31,11 @@ impl Sorter {
             let cmp_by_idx = |idx: usize, ascending: bool| {
                 let a = &a.get_value(idx);
                 let b = &b.get_value(idx);
+                let collation = self.collations[idx];
                 if ascending {
-                    a.cmp(b)
+                    collation.compare_ref_values(a, b)
                 } else {
-                    b.cmp(a)
+                    collation.compare_ref_values(b, a)
                 }
             };
 
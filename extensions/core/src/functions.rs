@@ -18,6 +18,17 @@ pub type RegisterAggFn = unsafe extern "C" fn(
     finalize: FinalizeFunction,
 ) -> ResultCode;
 
+/// Compares two UTF-8 strings, returning a negative, zero or positive value if `lhs` is
+/// respectively less than, equal to or greater than `rhs`.
+pub type CollationFunction =
+    unsafe extern "C" fn(lhs: *const u8, lhs_len: usize, rhs: *const u8, rhs_len: usize) -> i32;
+
+pub type RegisterCollationFn = unsafe extern "C" fn(
+    ctx: *mut c_void,
+    name: *const c_char,
+    func: CollationFunction,
+) -> ResultCode;
+
 pub type InitAggFunction = unsafe extern "C" fn() -> *mut AggCtx;
 pub type StepFunction = unsafe extern "C" fn(ctx: *mut AggCtx, argc: i32, argv: *const Value);
 pub type FinalizeFunction = unsafe extern "C" fn(ctx: *mut AggCtx) -> Value;
@@ -4,12 +4,13 @@ mod types;
 mod vfs_modules;
 mod vtabs;
 pub use functions::{
-    AggCtx, AggFunc, FinalizeFunction, InitAggFunction, ScalarFunction, StepFunction,
+    AggCtx, AggFunc, CollationFunction, FinalizeFunction, InitAggFunction, ScalarFunction,
+    StepFunction,
 };
-use functions::{RegisterAggFn, RegisterScalarFn};
+use functions::{RegisterAggFn, RegisterCollationFn, RegisterScalarFn};
 #[cfg(feature = "vfs")]
 pub use limbo_macros::VfsDerive;
-pub use limbo_macros::{register_extension, scalar, AggregateDerive, VTabModuleDerive};
+pub use limbo_macros::{collation, register_extension, scalar, AggregateDerive, VTabModuleDerive};
 use std::os::raw::c_void;
 pub use types::{ResultCode, Value, ValueType};
 #[cfg(feature = "vfs")]
@@ -30,6 +31,7 @@ pub struct ExtensionApi {
     pub register_scalar_function: RegisterScalarFn,
     pub register_aggregate_function: RegisterAggFn,
     pub register_vtab_module: RegisterModuleFn,
+    pub register_collation: RegisterCollationFn,
     #[cfg(feature = "vfs")]
     pub vfs_interface: VfsInterface,
 }
@@ -1,7 +1,8 @@
 use lazy_static::lazy_static;
 use limbo_ext::{
-    register_extension, scalar, ConstraintInfo, ConstraintOp, ConstraintUsage, ExtResult,
-    IndexInfo, OrderByInfo, ResultCode, VTabCursor, VTabKind, VTabModule, VTabModuleDerive, Value,
+    collation, register_extension, scalar, ConstraintInfo, ConstraintOp, ConstraintUsage,
+    ExtResult, IndexInfo, OrderByInfo, ResultCode, VTabCursor, VTabKind, VTabModule,
+    VTabModuleDerive, Value,
 };
 #[cfg(not(target_family = "wasm"))]
 use limbo_ext::{VfsDerive, VfsExtension, VfsFile};
@@ -14,6 +15,7 @@ register_extension! {
     vtabs: { KVStoreVTab },
     scalars: { test_scalar },
     vfs: { TestFS },
+    collations: { test_reverse },
 }
 
 lazy_static! {
@@ -252,6 +254,11 @@ fn test_scalar(_args: limbo_ext::Value) -> limbo_ext::Value {
     limbo_ext::Value::from_integer(42)
 }
 
+#[collation(name = "reverse")]
+fn test_reverse(lhs: &str, rhs: &str) -> std::cmp::Ordering {
+    rhs.cmp(lhs)
+}
+
 #[cfg(not(target_family = "wasm"))]
 impl VfsExtension for TestFS {
     const NAME: &'static str = "testvfs";
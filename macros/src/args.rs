@@ -8,6 +8,7 @@ pub(crate) struct RegisterExtensionInput {
     pub scalars: Vec<Ident>,
     pub vtabs: Vec<Ident>,
     pub vfs: Vec<Ident>,
+    pub collations: Vec<Ident>,
 }
 
 impl syn::parse::Parse for RegisterExtensionInput {
@@ -16,11 +17,12 @@ impl syn::parse::Parse for RegisterExtensionInput {
         let mut scalars = Vec::new();
         let mut vtabs = Vec::new();
         let mut vfs = Vec::new();
+        let mut collations = Vec::new();
         while !input.is_empty() {
             if input.peek(syn::Ident) && input.peek2(Token![:]) {
                 let section_name: Ident = input.parse()?;
                 input.parse::<Token![:]>()?;
-                let names = ["aggregates", "scalars", "vtabs", "vfs"];
+                let names = ["aggregates", "scalars", "vtabs", "vfs", "collations"];
                 if names.contains(&section_name.to_string().as_str()) {
                     let content;
                     syn::braced!(content in input);
@@ -33,6 +35,7 @@ impl syn::parse::Parse for RegisterExtensionInput {
                         "scalars" => scalars = parsed_items,
                         "vtabs" => vtabs = parsed_items,
                         "vfs" => vfs = parsed_items,
+                        "collations" => collations = parsed_items,
                         _ => unreachable!(),
                     };
 
@@ -52,6 +55,7 @@ impl syn::parse::Parse for RegisterExtensionInput {
             scalars,
             vtabs,
             vfs,
+            collations,
         })
     }
 }
@@ -230,6 +230,86 @@ pub fn scalar(attr: TokenStream, input: TokenStream) -> TokenStream {
     TokenStream::from(expanded)
 }
 
+/// Declare a collating sequence for your extension. This requires the name:
+/// #[collation(name = "example")] that the collation is referred to by in COLLATE clauses.
+/// The function compares two strings and returns their ordering.
+/// ```ignore
+/// use limbo_ext::collation;
+/// #[collation(name = "length")] // you can provide an <optional> alias
+/// fn compare_length(lhs: &str, rhs: &str) -> std::cmp::Ordering {
+///     lhs.len().cmp(&rhs.len())
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn collation(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as ItemFn);
+    let fn_name = &ast.sig.ident;
+    let collation_info = parse_macro_input!(attr as ScalarInfo);
+    let name = &collation_info.name;
+    let register_fn_name = format_ident!("register_{}", fn_name);
+    let mut compare_fn = ast.clone();
+    compare_fn.sig.ident = format_ident!("compare");
+    let alias_check = if let Some(alias) = &collation_info.alias {
+        quote! {
+            let Ok(alias_c_name) = ::std::ffi::CString::new(#alias) else {
+                return ::limbo_ext::ResultCode::Error;
+            };
+            (api.register_collation)(
+                api.ctx,
+                alias_c_name.as_ptr(),
+                #fn_name,
+            );
+        }
+    } else {
+        quote! {}
+    };
+
+    let expanded = quote! {
+        #[no_mangle]
+        pub unsafe extern "C" fn #register_fn_name(
+            api: *const ::limbo_ext::ExtensionApi
+        ) -> ::limbo_ext::ResultCode {
+            if api.is_null() {
+                return ::limbo_ext::ResultCode::Error;
+            }
+            let api = unsafe { &*api };
+            let Ok(c_name) = ::std::ffi::CString::new(#name) else {
+                return ::limbo_ext::ResultCode::Error;
+            };
+            let result = (api.register_collation)(
+                api.ctx,
+                c_name.as_ptr(),
+                #fn_name,
+            );
+            if !result.is_ok() {
+                return result;
+            }
+            #alias_check
+            ::limbo_ext::ResultCode::OK
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn #fn_name(
+            lhs: *const u8,
+            lhs_len: usize,
+            rhs: *const u8,
+            rhs_len: usize,
+        ) -> i32 {
+            #compare_fn
+            let as_str = |ptr: *const u8, len: usize| {
+                if ptr.is_null() || len == 0 {
+                    ::std::borrow::Cow::Borrowed("")
+                } else {
+                    String::from_utf8_lossy(unsafe { ::std::slice::from_raw_parts(ptr, len) })
+                }
+            };
+            compare(&as_str(lhs, lhs_len), &as_str(rhs, rhs_len)) as i32
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
 /// Define an aggregate function for your extension by deriving
 /// AggregateDerive on a struct that implements the AggFunc trait.
 /// ```ignore
@@ -922,6 +1002,7 @@ pub fn register_extension(input: TokenStream) -> TokenStream {
         scalars,
         vtabs,
         vfs,
+        collations,
     } = input_ast;
 
     let scalar_calls = scalars.iter().map(|scalar_ident| {
@@ -948,6 +1029,20 @@ pub fn register_extension(input: TokenStream) -> TokenStream {
             }
         }
     });
+    let collation_calls = collations.iter().map(|collation_ident| {
+        let register_fn = syn::Ident::new(
+            &format!("register_{}", collation_ident),
+            collation_ident.span(),
+        );
+        quote! {
+            {
+                let result = unsafe { #register_fn(api)};
+                if !result.is_ok() {
+                    return result;
+                }
+            }
+        }
+    });
     let vtab_calls = vtabs.iter().map(|vtab_ident| {
         let register_fn = syn::Ident::new(&format!("register_{}", vtab_ident), vtab_ident.span());
         quote! {
@@ -985,6 +1080,7 @@ pub fn register_extension(input: TokenStream) -> TokenStream {
     let static_aggregates = aggregate_calls.clone();
     let static_scalars = scalar_calls.clone();
     let static_vtabs = vtab_calls.clone();
+    let static_collations = collation_calls.clone();
 
     let expanded = quote! {
     #[cfg(not(target_family = "wasm"))]
@@ -1000,6 +1096,8 @@ pub fn register_extension(input: TokenStream) -> TokenStream {
 
                 #(#static_vtabs)*
 
+                #(#static_collations)*
+
                 #[cfg(not(target_family = "wasm"))]
                 #(#static_vfs)*
 
@@ -1015,6 +1113,8 @@ pub fn register_extension(input: TokenStream) -> TokenStream {
 
                 #(#vtab_calls)*
 
+                #(#collation_calls)*
+
                 #(#vfs_calls)*
 
                 ::limbo_ext::ResultCode::OK
@@ -112,6 +112,10 @@ impl File for SimulatorFile {
     fn size(&self) -> Result<u64> {
         self.inner.size()
     }
+
+    fn truncate(&self, len: usize, c: limbo_core::Completion) -> Result<()> {
+        self.inner.truncate(len, c)
+    }
 }
 
 impl Drop for SimulatorFile {
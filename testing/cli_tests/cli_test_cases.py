@@ -301,6 +301,29 @@ def test_insert_default_values():
     limbo.quit()
 
 
+def test_views_from_sqlite_db():
+    db_path = "testing/views.db"
+    with TestLimboShell(init_commands="", exec_name="sqlite3", flags=db_path) as sqlite:
+        sqlite.execute_dot(
+            "CREATE TABLE t (a INTEGER, b TEXT);"
+            "INSERT INTO t VALUES (1, 'one'), (2, 'two'), (3, 'three');"
+            "CREATE VIEW big AS SELECT * FROM t WHERE a > 1;"
+            'CREATE VIEW "named view"(x) AS SELECT upper(b) FROM big;'
+        )
+    limbo = TestLimboShell("")
+    limbo.execute_dot(f".open {db_path}")
+    limbo.run_test("view-from-sqlite-db", "SELECT b FROM big ORDER BY a;", "two\nthree")
+    limbo.run_test(
+        "view-with-column-names-from-sqlite-db",
+        'SELECT x FROM "named view" ORDER BY x;',
+        "THREE\nTWO",
+    )
+    limbo.quit()
+    for path in [db_path, f"{db_path}-wal", f"{db_path}-shm"]:
+        if os.path.exists(path):
+            os.remove(path)
+
+
 def main():
     console.info("Running all Limbo CLI tests...")
     test_basic_queries()
@@ -320,6 +343,7 @@ def main():
     test_table_patterns()
     test_update_with_limit()
     test_update_with_limit_and_offset()
+    test_views_from_sqlite_db()
     console.info("All tests have passed")
 
 
@@ -166,6 +166,21 @@ def test_aggregates():
     limbo.run_test_fn(
         "SELECT percentile_disc(value, 0.55) from test;", validate_percentile_disc
     )
+    limbo.run_test_fn(
+        "select median(value) filter (where value > 2.0) from numbers;",
+        lambda res: res == "5.5",
+        "median agg function works with a FILTER clause",
+    )
+    limbo.run_test_fn(
+        "select value > 4.0, median(value) filter (where value <> 8.0) from numbers group by value > 4.0;",
+        lambda res: res == "0|2.5\n1|6.0",
+        "median agg function works with GROUP BY and a FILTER clause",
+    )
+    limbo.run_test_fn(
+        "select value, median(value) filter (where value <> 8.0) over (order by value rows between 1 preceding and 1 following) from numbers where value > 5.0;",
+        lambda res: res == "6.0|6.5\n7.0|6.5\n8.0|7.0",
+        "median agg function works as a window function with a FILTER clause",
+    )
     limbo.quit()
 
 
@@ -552,6 +567,52 @@ def test_drop_virtual_table():
     limbo.quit()
 
 
+def test_collation():
+    ext_path = "target/debug/liblimbo_ext_tests"
+    limbo = TestLimboShell()
+    limbo.run_test_fn(
+        "select 'a' < 'b' collate reverse;",
+        lambda res: "no such collation sequence: reverse" in res,
+        "collation is unavailable before the extension is loaded",
+    )
+    limbo.execute_dot(f".load {ext_path}")
+    limbo.run_test_fn(
+        "select 'a' < 'b' collate reverse;",
+        false,
+        "can compare with an extension collation",
+    )
+    limbo.execute_dot("create table t (x text collate reverse);")
+    limbo.execute_dot("insert into t values ('b'), ('c'), ('a');")
+    limbo.run_test_fn(
+        "select group_concat(x, '') from (select x from t order by x);",
+        lambda res: "cba" == res,
+        "can order by a column with an extension collation",
+    )
+    limbo.run_test_fn(
+        "select max(x) from t;",
+        lambda res: "a" == res,
+        "max() uses the column's extension collation",
+    )
+    limbo.run_test_fn(
+        "select x from t where x > 'b';",
+        lambda res: "a" == res,
+        "can filter with an extension collation",
+    )
+    limbo.execute_dot("create table u (y text);")
+    limbo.execute_dot("insert into u values ('a'), ('c'), ('d');")
+    limbo.run_test_fn(
+        "explain query plan select u.y from u join t on t.x = u.y;",
+        lambda res: "HASH TABLE" not in res,
+        "does not hash the values of a column with an extension collation",
+    )
+    limbo.run_test_fn(
+        "select group_concat(u.y, '') from u join t on t.x = u.y;",
+        lambda res: "ac" == res,
+        "can join on a column with an extension collation",
+    )
+    limbo.quit()
+
+
 def test_sqlite_vfs_compat():
     sqlite = TestLimboShell(
         init_commands="",
@@ -600,6 +661,7 @@ def main():
         test_sqlite_vfs_compat()
         test_kv()
         test_drop_virtual_table()
+        test_collation()
     except Exception as e:
         console.error(f"Test FAILED: {e}")
         cleanup()
@@ -61,6 +61,17 @@ proc do_execsql_test_regex {test_name sql_statements expected_regex} {
     }
 }
 
+proc do_execsql_test_regex_on_specific_db {db_name test_name sql_statements expected_regex} {
+    test_put "Running test" $db_name $test_name
+    set combined_sql [string trim $sql_statements]
+    set actual_output [evaluate_sql $::sqlite_exec $db_name $combined_sql]
+    if {![regexp $expected_regex $actual_output]} {
+        error_put $sql_statements
+        puts "returned '$actual_output'"
+        puts "expected to match regex '$expected_regex'"
+        exit 1
+    }
+}
 
 proc do_execsql_test_on_specific_db {db_name test_name sql_statements expected_outputs} {
     test_put "Running test" $db_name $test_name
@@ -69,6 +80,19 @@ proc do_execsql_test_on_specific_db {db_name test_name sql_statements expected_o
     run_test $::sqlite_exec $db_name $combined_sql $combined_expected_output
 }
 
+# Runs the statements on a new database file and checks the file with the integrity_check pragma
+# of SQLite, which finds the pages that no b-tree or freelist refers to.
+proc do_execsql_test_integrity_check {test_name sql_statements expected_outputs} {
+    set db_name "testing/integrity_check.db"
+    file delete -force $db_name $db_name-wal
+    test_put "Running integrity check test" $db_name $test_name
+    set combined_sql [string trim $sql_statements]
+    set combined_expected_output [join $expected_outputs "\n"]
+    run_test $::sqlite_exec $db_name $combined_sql $combined_expected_output
+    run_test sqlite3 $db_name "pragma integrity_check" "ok"
+    file delete -force $db_name $db_name-wal
+}
+
 proc within_tolerance {actual expected tolerance} {
     expr {abs($actual - $expected) <= $tolerance}
 }
@@ -226,3 +250,13 @@ proc do_execsql_test_in_memory_any_error {test_name sql_statements} {
     set combined_sql [string trim $sql_statements]
     run_test_expecting_any_error $::sqlite_exec $db_name $combined_sql
 }
+
+proc do_execsql_test_in_memory_error_content {test_name sql_statements expected_error_text} {
+    test_put "Running error content test" in-memory $test_name
+
+    # Use ":memory:" special filename for in-memory database
+    set db_name ":memory:"
+
+    set combined_sql [string trim $sql_statements]
+    run_test_expecting_error_content $::sqlite_exec $db_name $combined_sql $expected_error_text
+}
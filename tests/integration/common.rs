@@ -143,6 +143,18 @@ mod tests {
         let columns = stmt.num_columns();
         assert_eq!(columns, 0);
 
+        let stmt = conn.prepare("insert into test (foo) values (1) returning *, bar as b")?;
+        let columns = stmt.num_columns();
+        assert_eq!(columns, 4);
+        assert_eq!(stmt.get_column_name(0), "foo");
+        assert_eq!(stmt.get_column_name(2), "baz");
+        assert_eq!(stmt.get_column_name(3), "b");
+
+        let stmt = conn.prepare("delete from test returning rowid")?;
+        let columns = stmt.num_columns();
+        assert_eq!(columns, 1);
+        assert_eq!(stmt.get_column_name(0), "rowid");
+
         Ok(())
     }
 }
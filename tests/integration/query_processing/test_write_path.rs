@@ -1,6 +1,6 @@
 use crate::common::{self, maybe_setup_tracing};
 use crate::common::{compare_string, do_flush, TempDatabase};
-use limbo_core::{Connection, OwnedValue, StepResult};
+use limbo_core::{Connection, LimboError, OwnedValue, StepResult};
 use log::debug;
 use std::rc::Rc;
 
@@ -461,3 +461,243 @@ fn test_insert_after_big_blob() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+fn query_i64(conn: &Rc<Connection>, tmp_db: &TempDatabase, sql: &str) -> anyhow::Result<i64> {
+    let mut rows = conn.query(sql)?.unwrap();
+    let mut value = None;
+    loop {
+        match rows.step()? {
+            StepResult::Row => value = Some(rows.row().unwrap().get::<i64>(0)?),
+            StepResult::IO => tmp_db.io.run_once()?,
+            StepResult::Done => break,
+            StepResult::Interrupt | StepResult::Busy => panic!("unexpected step result"),
+        }
+    }
+    Ok(value.unwrap())
+}
+
+#[test]
+fn test_failed_statement_rolls_back_its_changes() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, a TEXT UNIQUE);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("INSERT INTO t VALUES (1, 'x')")?;
+
+    // The rows inserted before the conflict are rolled back.
+    assert!(conn
+        .execute("INSERT INTO t VALUES (2, 'y'), (3, 'z'), (4, 'x')")
+        .is_err());
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT count(*) FROM t")?, 1);
+
+    // In a transaction, only the changes of the failed statement are rolled back.
+    conn.execute("BEGIN")?;
+    conn.execute("INSERT INTO t VALUES (5, 'w')")?;
+    assert!(conn
+        .execute("INSERT INTO t VALUES (6, 'v'), (7, 'x')")
+        .is_err());
+    assert!(!conn.get_auto_commit());
+    conn.execute("COMMIT")?;
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT sum(id) FROM t")?, 6);
+
+    // With FAIL, the rows inserted before the conflict are kept.
+    assert!(conn
+        .execute("INSERT OR FAIL INTO t VALUES (8, 'u'), (9, 'x')")
+        .is_err());
+    // With ROLLBACK, the whole transaction is rolled back.
+    conn.execute("BEGIN")?;
+    conn.execute("INSERT INTO t VALUES (10, 't')")?;
+    assert!(conn
+        .execute("INSERT OR ROLLBACK INTO t VALUES (11, 'x')")
+        .is_err());
+    assert!(conn.get_auto_commit());
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT sum(id) FROM t")?, 14);
+    conn.close()?;
+
+    let conn = tmp_db.connect_limbo();
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT sum(id) FROM t")?, 14);
+    conn.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_rollback() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, a TEXT);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("INSERT INTO t VALUES (1, 'x')")?;
+
+    conn.execute("BEGIN")?;
+    // Enough rows to split pages
+    for i in 2..500 {
+        conn.execute(format!(
+            "INSERT INTO t VALUES ({}, '{}')",
+            i,
+            "y".repeat(100)
+        ))?;
+    }
+    conn.execute("CREATE TABLE u (b)")?;
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT count(*) FROM t")?, 499);
+    conn.execute("ROLLBACK")?;
+
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT count(*) FROM t")?, 1);
+    assert!(conn.query("SELECT * FROM u").is_err());
+    conn.execute("INSERT INTO t VALUES (2, 'z')")?;
+    conn.close()?;
+
+    let conn = tmp_db.connect_limbo();
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT count(*) FROM t")?, 2);
+    conn.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_savepoints() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, a TEXT);");
+    let conn = tmp_db.connect_limbo();
+    let insert_rows = |range: std::ops::Range<i64>| -> anyhow::Result<()> {
+        for i in range {
+            conn.execute(format!(
+                "INSERT INTO t VALUES ({}, '{}')",
+                i,
+                "y".repeat(100)
+            ))?;
+        }
+        Ok(())
+    };
+
+    // Enough rows to split pages between each savepoint
+    conn.execute("SAVEPOINT outer")?;
+    assert!(!conn.get_auto_commit());
+    insert_rows(0..200)?;
+    conn.execute("SAVEPOINT inner")?;
+    insert_rows(200..400)?;
+    conn.execute("ROLLBACK TO inner")?;
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT count(*) FROM t")?, 200);
+    insert_rows(400..600)?;
+    conn.execute("RELEASE inner")?;
+    assert!(!conn.get_auto_commit());
+    conn.execute("SAVEPOINT inner")?;
+    insert_rows(600..800)?;
+    conn.execute("RELEASE outer")?;
+    assert!(conn.get_auto_commit());
+    conn.close()?;
+
+    let conn = tmp_db.connect_limbo();
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT count(*) FROM t")?, 600);
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT max(id) FROM t")?, 799);
+    conn.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_deferred_foreign_keys() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE p (id INTEGER PRIMARY KEY);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute(
+        "CREATE TABLE c (pid REFERENCES p DEFERRABLE INITIALLY DEFERRED, q REFERENCES p)",
+    )?;
+    conn.execute("PRAGMA foreign_keys = ON")?;
+
+    // The transaction cannot commit until it inserts the missing parent row.
+    conn.execute("BEGIN")?;
+    conn.execute("INSERT INTO c VALUES (1, NULL)")?;
+    assert!(conn.execute("COMMIT").is_err());
+    assert!(!conn.get_auto_commit());
+
+    // A failed statement takes back the violations of deferred constraints it made.
+    conn.execute("INSERT INTO p VALUES (1)")?;
+    assert!(conn
+        .execute("INSERT INTO c VALUES (2, NULL), (3, 4)")
+        .is_err());
+    conn.execute("COMMIT")?;
+    assert!(conn.get_auto_commit());
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT count(*) FROM c")?, 1);
+
+    // So does ROLLBACK TO.
+    conn.execute("SAVEPOINT s")?;
+    conn.execute("INSERT INTO c VALUES (5, NULL)")?;
+    conn.execute("ROLLBACK TO s")?;
+    conn.execute("RELEASE s")?;
+    assert!(conn.get_auto_commit());
+
+    // In autocommit mode, the statement is the transaction.
+    assert!(conn.execute("INSERT INTO c VALUES (6, NULL)").is_err());
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT count(*) FROM c")?, 1);
+    conn.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_check_constraints() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "CREATE TABLE t (a CHECK (a > 0), b, CONSTRAINT a_lt_b CHECK (a < b));",
+    );
+    let conn = tmp_db.connect_limbo();
+
+    // The error names the constraint, or gives its expression if it has no name.
+    let err = conn.execute("INSERT INTO t VALUES (-1, 1)").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Runtime error: CHECK constraint failed: a > 0 (19)"
+    );
+    conn.execute("INSERT INTO t VALUES (1, 2)")?;
+    let err = conn.execute("UPDATE t SET b = 0").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Runtime error: CHECK constraint failed: a_lt_b (19)"
+    );
+
+    // The setting applies to the statements prepared after it.
+    conn.execute("PRAGMA ignore_check_constraints = ON")?;
+    conn.execute("UPDATE t SET b = 0")?;
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT b FROM t")?, 0);
+    conn.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_trigger_raise_is_constraint_error() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (a, b);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute(
+        "CREATE TRIGGER tr BEFORE INSERT ON t WHEN new.b BETWEEN 1 AND 5
+         BEGIN SELECT RAISE(ABORT, 'b out of range'); END",
+    )?;
+
+    conn.execute("INSERT INTO t VALUES (1, 6)")?;
+    let err = conn.execute("INSERT INTO t VALUES (2, 3)").unwrap_err();
+    assert!(matches!(err, LimboError::Constraint(_)));
+    assert_eq!(err.to_string(), "Runtime error: b out of range (19)");
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT count(*) FROM t")?, 1);
+    conn.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_foreign_key_restrict_is_constraint_error() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE p (k UNIQUE);");
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE c (pk REFERENCES p(k) ON DELETE RESTRICT)")?;
+    conn.execute("PRAGMA foreign_keys = ON")?;
+    conn.execute("INSERT INTO p VALUES (1), (2)")?;
+    conn.execute("INSERT INTO c VALUES (1)")?;
+
+    let err = conn.execute("DELETE FROM p").unwrap_err();
+    assert!(matches!(err, LimboError::Constraint(_)));
+    assert_eq!(
+        err.to_string(),
+        "Runtime error: FOREIGN KEY constraint failed (19)"
+    );
+    assert_eq!(query_i64(&conn, &tmp_db, "SELECT count(*) FROM p")?, 2);
+    conn.execute("DELETE FROM p WHERE k = 2")?;
+    conn.close()?;
+    Ok(())
+}
@@ -740,6 +740,10 @@ impl ToTokens for Expr {
                 query.to_tokens(s)?;
                 s.append(TK_RP, None)
             }
+            Self::SubqueryResult { .. } => Ok(()),
+            Self::OuterRef(_) => Ok(()),
+            Self::TriggerParam(_) => Ok(()),
+            Self::Register(_) => Ok(()),
             Self::Unary(op, sub_expr) => {
                 op.to_tokens(s)?;
                 sub_expr.to_tokens(s)
@@ -423,8 +423,29 @@ pub enum Expr {
     Qualified(Name, Name),
     /// `RAISE` function call
     Raise(ResolveType, Option<Box<Expr>>),
+    /// A value that the program holds in a register, such as a column of the `excluded` row
+    /// of an upsert.
+    /// number of the register
+    Register(usize),
     /// Subquery expression
     Subquery(Box<Select>),
+    /// `EXISTS`, `IN` or scalar subquery, after the subquery has been planned
+    SubqueryResult {
+        /// index of the subquery in the plan of the query that contains it
+        subquery_id: usize,
+        /// the expression on the left of `IN`
+        lhs: Option<Box<Expr>>,
+        /// `NOT IN`
+        not: bool,
+        /// the values of the query that contains the subquery that the subquery refers to
+        outer_refs: Vec<Expr>,
+    },
+    /// A value of an outer query that a subquery refers to.
+    /// index of the value in the `outer_refs` of the `SubqueryResult`
+    OuterRef(usize),
+    /// A column of the `NEW` or `OLD` row in the body of a trigger.
+    /// index of the value in the parameters passed to the trigger program
+    TriggerParam(usize),
     /// Unary expression
     Unary(UnaryOperator, Box<Expr>),
     /// Parameters
@@ -824,6 +845,15 @@ pub struct FromClause {
     op: Option<JoinOperator>, // FIXME transient
 }
 impl FromClause {
+    /// Constructor
+    pub fn new(select: Box<SelectTable>, joins: Option<Vec<JoinedSelectTable>>) -> Self {
+        Self {
+            select: Some(select),
+            joins,
+            op: None,
+        }
+    }
+
     pub(crate) fn empty() -> Self {
         Self {
             select: None,
@@ -1614,12 +1644,30 @@ pub type PragmaValue = Expr; // TODO
 #[derive(Clone, Debug, PartialEq, Eq, EnumIter, EnumString, strum::Display)]
 #[strum(serialize_all = "snake_case")]
 pub enum PragmaName {
+    /// sets or returns the number of index entries examined by ANALYZE
+    AnalysisLimit,
+    /// sets or returns the auto-vacuum mode of the database
+    AutoVacuum,
     /// `cache_size` pragma
     CacheSize,
+    /// returns the databases of the connection
+    DatabaseList,
+    /// checks the FOREIGN KEY constraints of a table, or of all the tables
+    ForeignKeyCheck,
+    /// returns the FOREIGN KEY constraints of a table
+    ForeignKeyList,
+    /// enables or disables the enforcement of FOREIGN KEY constraints
+    ForeignKeys,
+    /// enables or disables the enforcement of CHECK constraints
+    IgnoreCheckConstraints,
+    /// gives back free pages of an incremental auto-vacuum database to the file system
+    IncrementalVacuum,
     /// `journal_mode` pragma
     JournalMode,
     /// Noop as per SQLite docs
     LegacyFileFormat,
+    /// analyzes the tables whose statistics are missing or out of date
+    Optimize,
     /// Return the total number of pages in the database file.
     PageCount,
     /// returns information about the columns of a table